@@ -0,0 +1,40 @@
+# English (default) messages for the chat UI.
+# Placeables such as { $error } are filled in at runtime.
+
+app-title = Predict-Otron-9000 Chat
+page-not-found = Page not found.
+language-label = Language:
+
+## Chat page
+model-label = Model:
+streaming-toggle = Use streaming
+share-button = Share
+share-link = Share link:
+role-user = user
+role-assistant = assistant
+thinking = Thinking...
+input-placeholder = Type your message here... (Press Enter to send, Shift+Enter for new line)
+send-button = Send
+copy-button = Copy
+
+## Notifications
+models-loading = Loading models...
+models-loaded = Loaded { $count } models
+models-load-failed = Failed to load models: { $error }
+streaming-failed = Streaming failed: { $error }
+request-failed = Request failed: { $error }
+share-created = Share link created
+share-failed = Failed to share conversation: { $error }
+settings-saved = Settings saved
+settings-save-failed = Failed to save settings
+copy-succeeded = Copied to clipboard
+copy-failed = Failed to copy: { $error }
+toast-dismiss = Dismiss
+toast-pending = +{ $count } more
+
+## Shared conversation page
+shared-notice = Shared conversation (read-only)
+shared-loading = Loading conversation...
+shared-not-found = Conversation not found.
+shared-error = Error: { $error }
+shared-start-chat = Start your own chat
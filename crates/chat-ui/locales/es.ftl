@@ -0,0 +1,40 @@
+# Spanish messages for the chat UI.
+# Placeables such as { $error } are filled in at runtime.
+
+app-title = Chat Predict-Otron-9000
+page-not-found = Página no encontrada.
+language-label = Idioma:
+
+## Chat page
+model-label = Modelo:
+streaming-toggle = Usar streaming
+share-button = Compartir
+share-link = Enlace para compartir:
+role-user = usuario
+role-assistant = asistente
+thinking = Pensando...
+input-placeholder = Escribe tu mensaje aquí... (Enter para enviar, Mayús+Enter para nueva línea)
+send-button = Enviar
+copy-button = Copiar
+
+## Notifications
+models-loading = Cargando modelos...
+models-loaded = { $count } modelos cargados
+models-load-failed = No se pudieron cargar los modelos: { $error }
+streaming-failed = Falló el streaming: { $error }
+request-failed = Falló la solicitud: { $error }
+share-created = Enlace para compartir creado
+share-failed = No se pudo compartir la conversación: { $error }
+settings-saved = Configuración guardada
+settings-save-failed = No se pudo guardar la configuración
+copy-succeeded = Copiado al portapapeles
+copy-failed = No se pudo copiar: { $error }
+toast-dismiss = Cerrar
+toast-pending = +{ $count } más
+
+## Shared conversation page
+shared-notice = Conversación compartida (solo lectura)
+shared-loading = Cargando conversación...
+shared-not-found = Conversación no encontrada.
+shared-error = Error: { $error }
+shared-start-chat = Empieza tu propio chat
@@ -45,89 +45,36 @@ use leptos::prelude::*;
 use leptos_meta::{provide_meta_context, MetaTags, Stylesheet, Title};
 use leptos_router::{
     components::{Route, Router, Routes},
-    StaticSegment,
+    ParamSegment, StaticSegment,
 };
-use serde::{Deserialize, Serialize};
 use web_sys::console;
 
-// Data structures for OpenAI-compatible API
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ChatMessage {
-    pub role: String,
-    pub content: String,
-}
-
-#[derive(Debug, Serialize)]
-pub struct ChatRequest {
-    pub model: String,
-    pub messages: Vec<ChatMessage>,
-    pub max_tokens: Option<u32>,
-    pub stream: Option<bool>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct ChatChoice {
-    pub message: ChatMessage,
-    pub index: u32,
-    pub finish_reason: Option<String>,
-}
+use crate::i18n::{provide_i18n, use_i18n, I18n, LanguageSwitcher};
+use crate::share::SharedConversationPage;
+use crate::toast::{provide_toasts, use_toasts, ToastContainer, Toasts};
 
-// Streaming response structures
-#[derive(Debug, Deserialize)]
-pub struct StreamDelta {
-    pub role: Option<String>,
-    pub content: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct StreamChoice {
-    pub index: u32,
-    pub delta: StreamDelta,
-    pub finish_reason: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct StreamChatResponse {
-    pub id: String,
-    pub object: String,
-    pub created: u64,
-    pub model: String,
-    pub choices: Vec<StreamChoice>,
-}
+// localStorage keys for persisted chat settings
+const SELECTED_MODEL_KEY: &str = "predict-otron.selected-model";
+const USE_STREAMING_KEY: &str = "predict-otron.use-streaming";
 
-#[derive(Debug, Deserialize)]
-pub struct ChatResponse {
-    pub id: String,
-    pub object: String,
-    pub created: u64,
-    pub model: String,
-    pub choices: Vec<ChatChoice>,
-}
-
-// Data structures for models API
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ModelInfo {
-    pub id: String,
-    pub object: String,
-    pub created: u64,
-    pub owned_by: String,
-}
+#[cfg(target_arch = "wasm32")]
+const DEFAULT_MODEL: &str = "gemma-3-1b-it";
 
-#[derive(Debug, Deserialize)]
-pub struct ModelsResponse {
-    pub object: String,
-    pub data: Vec<ModelInfo>,
-}
+// Request and response bodies of the OpenAI-compatible API, shared with the server
+pub use openai_protocol::{
+    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, Message, Model,
+    ModelListResponse,
+};
 
 // API client function to fetch available models
-pub async fn fetch_models() -> Result<Vec<ModelInfo>, String> {
+pub async fn fetch_models() -> Result<Vec<Model>, String> {
     let response = Request::get("/v1/models")
         .send()
         .await
         .map_err(|e| format!("Failed to fetch models: {:?}", e))?;
 
     if response.ok() {
-        let models_response: ModelsResponse = response
+        let models_response: ModelListResponse = response
             .json()
             .await
             .map_err(|e| format!("Failed to parse models response: {:?}", e))?;
@@ -143,16 +90,10 @@ pub async fn fetch_models() -> Result<Vec<ModelInfo>, String> {
 }
 
 // API client function to send chat completion requests
-pub async fn send_chat_completion(
-    messages: Vec<ChatMessage>,
-    model: String,
-) -> Result<String, String> {
-    let request = ChatRequest {
-        model,
-        messages,
-        max_tokens: Some(1024),
-        stream: Some(false),
-    };
+pub async fn send_chat_completion(messages: Vec<Message>, model: String) -> Result<String, String> {
+    let mut request = ChatCompletionRequest::new(model, messages);
+    request.max_tokens = Some(1024);
+    request.stream = Some(false);
 
     let response = Request::post("/v1/chat/completions")
         .header("Content-Type", "application/json")
@@ -163,13 +104,13 @@ pub async fn send_chat_completion(
         .map_err(|e| format!("Failed to send request: {:?}", e))?;
 
     if response.ok() {
-        let chat_response: ChatResponse = response
+        let chat_response: ChatCompletionResponse = response
             .json()
             .await
             .map_err(|e| format!("Failed to parse response: {:?}", e))?;
 
-        if let Some(choice) = chat_response.choices.first() {
-            Ok(choice.message.content.clone())
+        if !chat_response.choices.is_empty() {
+            Ok(chat_response.content().to_string())
         } else {
             Err("No response choices available".to_string())
         }
@@ -186,7 +127,7 @@ pub async fn send_chat_completion(
 // Streaming chat completion using EventSource
 #[cfg(target_arch = "wasm32")]
 pub fn send_chat_completion_stream(
-    messages: Vec<ChatMessage>,
+    messages: Vec<Message>,
     model: String,
     on_chunk: impl Fn(String) + 'static,
     on_complete: impl Fn() + 'static,
@@ -195,12 +136,9 @@ pub fn send_chat_completion_stream(
     use wasm_bindgen::prelude::*;
     use wasm_bindgen::JsCast;
 
-    let request = ChatRequest {
-        model,
-        messages,
-        max_tokens: Some(1024),
-        stream: Some(true),
-    };
+    let mut request = ChatCompletionRequest::new(model, messages);
+    request.max_tokens = Some(1024);
+    request.stream = Some(true);
 
     // We need to send a POST request but EventSource only supports GET
     // So we'll use fetch with a readable stream instead
@@ -281,7 +219,7 @@ pub fn send_chat_completion_stream(
 
                                         // Parse JSON chunk
                                         if let Ok(chunk) =
-                                            serde_json::from_str::<StreamChatResponse>(data)
+                                            serde_json::from_str::<ChatCompletionChunk>(data)
                                         {
                                             if let Some(choice) = chunk.choices.first() {
                                                 if let Some(content) = &choice.delta.content {
@@ -309,6 +247,66 @@ pub fn send_chat_completion_stream(
     });
 }
 
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok().flatten()
+}
+
+// Read a persisted chat setting from localStorage
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn load_setting(key: &str) -> Option<String> {
+    local_storage()?.get_item(key).ok().flatten()
+}
+
+// Persist a chat setting to localStorage and confirm with a toast
+pub(crate) fn save_setting(toasts: Toasts, i18n: I18n, key: &str, value: &str) {
+    #[cfg(target_arch = "wasm32")]
+    {
+        match local_storage().map(|storage| storage.set_item(key, value)) {
+            Some(Ok(())) => {
+                toasts.success(i18n.t("settings-saved"));
+            }
+            _ => {
+                toasts.error(i18n.t("settings-save-failed"));
+            }
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    let _ = (toasts, i18n, key, value);
+}
+
+// Copy text to the clipboard and confirm with a toast
+fn copy_to_clipboard(toasts: Toasts, i18n: I18n, text: String) {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let Some(window) = web_sys::window() else {
+            return;
+        };
+        let promise = window.navigator().clipboard().write_text(&text);
+        wasm_bindgen_futures::spawn_local(async move {
+            match wasm_bindgen_futures::JsFuture::from(promise).await {
+                Ok(_) => {
+                    toasts.success(i18n.t("copy-succeeded"));
+                }
+                Err(e) => {
+                    toasts.error(i18n.t_args("copy-failed", &[("error", &format!("{:?}", e))]));
+                }
+            }
+        });
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    let _ = (toasts, i18n, text);
+}
+
+// Translated label for a message role; unknown roles are shown as-is
+pub(crate) fn role_label(i18n: I18n, role: &str) -> String {
+    match role {
+        "user" => i18n.t("role-user"),
+        "assistant" => i18n.t("role-assistant"),
+        other => other.to_string(),
+    }
+}
+
 pub fn shell(options: LeptosOptions) -> impl IntoView {
     view! {
         <!DOCTYPE html>
@@ -332,22 +330,30 @@ pub fn App() -> impl IntoView {
     // Provides context that manages stylesheets, titles, meta tags, etc.
     provide_meta_context();
 
+    // Provides the toast queue used for notifications across pages
+    provide_toasts();
+
+    // Provides the interface language used for all UI strings
+    let i18n = provide_i18n();
+
     view! {
         // injects a stylesheet into the document <head>
         // id=leptos means cargo-leptos will hot-reload this stylesheet
         <Stylesheet id="leptos" href="/pkg/chat-ui.css"/>
 
         // sets the document title
-        <Title text="Predict-Otron-9000 Chat"/>
+        <Title text=move || i18n.t("app-title")/>
 
         // content for this welcome page
         <Router>
             <main>
-                <Routes fallback=|| "Page not found.".into_view()>
+                <Routes fallback=move || i18n.t("page-not-found").into_view()>
                     <Route path=StaticSegment("") view=ChatPage/>
+                    <Route path=(StaticSegment("share"), ParamSegment("id")) view=SharedConversationPage/>
                 </Routes>
             </main>
         </Router>
+        <ToastContainer/>
     }
 }
 
@@ -355,7 +361,7 @@ pub fn App() -> impl IntoView {
 #[component]
 fn ChatPage() -> impl IntoView {
     // State for conversation messages
-    let messages = RwSignal::new(Vec::<ChatMessage>::new());
+    let messages = RwSignal::new(Vec::<Message>::new());
 
     // State for current user input
     let input_text = RwSignal::new(String::new());
@@ -363,11 +369,14 @@ fn ChatPage() -> impl IntoView {
     // State for loading indicator
     let is_loading = RwSignal::new(false);
 
-    // State for error messages
-    let error_message = RwSignal::new(Option::<String>::None);
+    // Notifications for errors, confirmations and progress
+    let toasts = use_toasts();
+
+    // Translated UI strings
+    let i18n = use_i18n();
 
     // State for available models and selected model
-    let available_models = RwSignal::new(Vec::<ModelInfo>::new());
+    let available_models = RwSignal::new(Vec::<Model>::new());
     let selected_model = RwSignal::new(String::from("")); // Default model
 
     // State for streaming response
@@ -377,19 +386,35 @@ fn ChatPage() -> impl IntoView {
     // State for streaming mode toggle
     let use_streaming = RwSignal::new(true); // Default to streaming
 
+    // State for the most recently created share link
+    let share_link = RwSignal::new(Option::<String>::None);
+
     // Client-side only: Fetch models on component mount
     #[cfg(target_arch = "wasm32")]
     {
         use leptos::task::spawn_local;
+
+        if let Some(stored) = load_setting(USE_STREAMING_KEY) {
+            use_streaming.set(stored != "false");
+        }
+
+        let loading_toast = toasts.info(i18n.t("models-loading"));
         spawn_local(async move {
             match fetch_models().await {
                 Ok(models) => {
+                    let stored_model = load_setting(SELECTED_MODEL_KEY)
+                        .filter(|id| models.iter().any(|model| &model.id == id));
+                    toasts.dismiss(loading_toast);
+                    toasts.success(
+                        i18n.t_args("models-loaded", &[("count", &models.len().to_string())]),
+                    );
                     available_models.set(models);
-                    selected_model.set(String::from("gemma-3-1b-it"));
+                    selected_model.set(stored_model.unwrap_or_else(|| DEFAULT_MODEL.to_string()));
                 }
                 Err(error) => {
                     console::log_1(&format!("Failed to fetch models: {}", error).into());
-                    error_message.set(Some(format!("Failed to load models: {}", error)));
+                    toasts.dismiss(loading_toast);
+                    toasts.error(i18n.t_args("models-load-failed", &[("error", &error)]));
                 }
             }
         });
@@ -403,15 +428,11 @@ fn ChatPage() -> impl IntoView {
         }
 
         // Add user message to conversation
-        let user_message = ChatMessage {
-            role: "user".to_string(),
-            content: user_input.clone(),
-        };
+        let user_message = Message::user(user_input.clone());
 
         messages.update(|msgs| msgs.push(user_message.clone()));
         input_text.set(String::new());
         is_loading.set(true);
-        error_message.set(None);
 
         // Client-side only: Send chat completion request
         #[cfg(target_arch = "wasm32")]
@@ -440,10 +461,7 @@ fn ChatPage() -> impl IntoView {
                         // On complete, move streaming content to messages
                         let final_content = streaming_content.get();
                         if !final_content.is_empty() {
-                            let assistant_message = ChatMessage {
-                                role: "assistant".to_string(),
-                                content: final_content,
-                            };
+                            let assistant_message = Message::assistant(final_content);
                             messages.update(|msgs| msgs.push(assistant_message));
                         }
                         streaming_content.set(String::new());
@@ -452,7 +470,7 @@ fn ChatPage() -> impl IntoView {
                     },
                     move |error| {
                         console::log_1(&format!("Streaming Error: {}", error).into());
-                        error_message.set(Some(error));
+                        toasts.error(i18n.t_args("streaming-failed", &[("error", &error)]));
                         is_streaming.set(false);
                         is_loading.set(false);
                         streaming_content.set(String::new());
@@ -463,16 +481,13 @@ fn ChatPage() -> impl IntoView {
                 spawn_local(async move {
                     match send_chat_completion(current_messages, current_model).await {
                         Ok(response_content) => {
-                            let assistant_message = ChatMessage {
-                                role: "assistant".to_string(),
-                                content: response_content,
-                            };
+                            let assistant_message = Message::assistant(response_content);
                             messages.update(|msgs| msgs.push(assistant_message));
                             is_loading.set(false);
                         }
                         Err(error) => {
                             console::log_1(&format!("API Error: {}", error).into());
-                            error_message.set(Some(error));
+                            toasts.error(i18n.t_args("request-failed", &[("error", &error)]));
                             is_loading.set(false);
                         }
                     }
@@ -489,6 +504,35 @@ fn ChatPage() -> impl IntoView {
         }
     };
 
+    // Share button handler: store a snapshot of the conversation and show its link
+    let on_share_click = move |_: web_sys::MouseEvent| {
+        #[cfg(target_arch = "wasm32")]
+        {
+            use leptos::task::spawn_local;
+
+            let current_messages = messages.get();
+            spawn_local(async move {
+                match crate::share::share_conversation(current_messages).await {
+                    Ok(id) => {
+                        let origin = web_sys::window()
+                            .and_then(|window| window.location().origin().ok())
+                            .unwrap_or_default();
+                        share_link.set(Some(format!(
+                            "{}{}",
+                            origin,
+                            crate::share::share_path(&id)
+                        )));
+                        toasts.success(i18n.t("share-created"));
+                    }
+                    Err(error) => {
+                        console::log_1(&format!("Share Error: {}", error).into());
+                        toasts.error(i18n.t_args("share-failed", &[("error", &error.to_string())]));
+                    }
+                }
+            });
+        }
+    };
+
     // Handle enter key press in input field
     let on_key_down = move |ev: web_sys::KeyboardEvent| {
         if ev.key() == "Enter" && !ev.shift_key() {
@@ -500,14 +544,15 @@ fn ChatPage() -> impl IntoView {
     view! {
         <div class="chat-container">
             <div class="chat-header">
-                <h1>"Predict-Otron-9000 Chat"</h1>
+                <h1>{move || i18n.t("app-title")}</h1>
                 <div class="model-selector">
-                    <label for="model-select">"Model:"</label>
+                    <label for="model-select">{move || i18n.t("model-label")}</label>
                     <select
                         id="model-select"
                         prop:value=move || selected_model.get()
                         on:change=move |ev| {
                             let new_model = event_target_value(&ev);
+                            save_setting(toasts, i18n, SELECTED_MODEL_KEY, &new_model);
                             selected_model.set(new_model);
                         }
                     >
@@ -530,13 +575,30 @@ fn ChatPage() -> impl IntoView {
                                 prop:checked=move || use_streaming.get()
                                 on:change=move |ev| {
                                     let target = event_target::<web_sys::HtmlInputElement>(&ev);
-                                    use_streaming.set(target.checked());
+                                    let checked = target.checked();
+                                    save_setting(toasts, i18n, USE_STREAMING_KEY, &checked.to_string());
+                                    use_streaming.set(checked);
                                 }
                             />
-                            " Use streaming"
+                            " " {move || i18n.t("streaming-toggle")}
                         </label>
                     </div>
+                    <button
+                        class="share-button"
+                        on:click=on_share_click
+                        disabled=move || messages.get().is_empty() || is_loading.get()
+                    >
+                        {move || i18n.t("share-button")}
+                    </button>
+                    <LanguageSwitcher toasts=toasts/>
                 </div>
+                {move || {
+                    share_link.get().map(|link| view! {
+                        <div class="share-link">
+                            {i18n.t("share-link")} " " <a href=link.clone() target="_blank">{link.clone()}</a>
+                        </div>
+                    })
+                }}
             </div>
 
             <div class="chat-messages">
@@ -545,10 +607,20 @@ fn ChatPage() -> impl IntoView {
                     key=|(i, _)| *i
                     children=move |(_, message)| {
                         let role_class = if message.role == "user" { "user-message" } else { "assistant-message" };
+                        let content = message.text().unwrap_or_default().to_string();
+                        let role = message.role.clone();
                         view! {
                             <div class=format!("message {}", role_class)>
-                                <div class="message-role">{message.role.clone()}</div>
-                                <div class="message-content">{message.content.clone()}</div>
+                                <div class="message-header">
+                                    <div class="message-role">{move || role_label(i18n, &role)}</div>
+                                    <button
+                                        class="copy-button"
+                                        on:click=move |_| copy_to_clipboard(toasts, i18n, content.clone())
+                                    >
+                                        {move || i18n.t("copy-button")}
+                                    </button>
+                                </div>
+                                <div class="message-content">{message.text().unwrap_or_default().to_string()}</div>
                             </div>
                         }
                     }
@@ -560,23 +632,23 @@ fn ChatPage() -> impl IntoView {
                         if !content.is_empty() {
                             view! {
                                 <div class="message assistant-message streaming">
-                                    <div class="message-role">"assistant"</div>
+                                    <div class="message-role">{i18n.t("role-assistant")}</div>
                                     <div class="message-content">{content}<span class="cursor">"▊"</span></div>
                                 </div>
                             }.into_any()
                         } else {
                             view! {
                                 <div class="message assistant-message loading">
-                                    <div class="message-role">"assistant"</div>
-                                    <div class="message-content">"Thinking..."</div>
+                                    <div class="message-role">{i18n.t("role-assistant")}</div>
+                                    <div class="message-content">{i18n.t("thinking")}</div>
                                 </div>
                             }.into_any()
                         }
                     } else if is_loading.get() && !use_streaming.get() {
                         view! {
                             <div class="message assistant-message loading">
-                                <div class="message-role">"assistant"</div>
-                                <div class="message-content">"Thinking..."</div>
+                                <div class="message-role">{i18n.t("role-assistant")}</div>
+                                <div class="message-content">{i18n.t("thinking")}</div>
                             </div>
                         }.into_any()
                     } else {
@@ -585,21 +657,9 @@ fn ChatPage() -> impl IntoView {
                 }}
             </div>
 
-            {move || {
-                if let Some(error) = error_message.get() {
-                    view! {
-                        <div class="error-message">
-                            "Error: " {error}
-                        </div>
-                    }.into_any()
-                } else {
-                    view! {}.into_any()
-                }
-            }}
-
             <div class="chat-input">
                 <textarea
-                    placeholder="Type your message here... (Press Enter to send, Shift+Enter for new line)"
+                    placeholder=move || i18n.t("input-placeholder")
                     prop:value=move || input_text.get()
                     on:input=move |ev| input_text.set(event_target_value(&ev))
                     on:keydown=on_key_down
@@ -609,7 +669,7 @@ fn ChatPage() -> impl IntoView {
                     on:click=on_button_click
                     class:disabled=move || is_loading.get() || input_text.get().trim().is_empty()
                 >
-                    "Send"
+                    {move || i18n.t("send-button")}
                 </button>
             </div>
         </div>
@@ -0,0 +1,266 @@
+use leptos::prelude::*;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use crate::toast::Toasts;
+
+// localStorage key for the selected interface language
+pub const LOCALE_KEY: &str = "predict-otron.locale";
+
+/// Interface languages with a message catalog in `locales/`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Locale {
+    #[default]
+    En,
+    Es,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Locale::En, Locale::Es];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Es => "es",
+        }
+    }
+
+    /// Name of the language in that language, for the switcher.
+    pub fn native_name(&self) -> &'static str {
+        match self {
+            Locale::En => "English",
+            Locale::Es => "Español",
+        }
+    }
+
+    /// Match a language tag such as `es`, `es-MX` or `en_US`.
+    pub fn from_code(code: &str) -> Option<Locale> {
+        let language = code.split(['-', '_']).next()?.to_ascii_lowercase();
+        Locale::ALL
+            .into_iter()
+            .find(|locale| locale.code() == language)
+    }
+
+    fn source(&self) -> &'static str {
+        match self {
+            Locale::En => include_str!("../locales/en.ftl"),
+            Locale::Es => include_str!("../locales/es.ftl"),
+        }
+    }
+
+    fn catalog(&self) -> &'static HashMap<String, String> {
+        static EN: LazyLock<HashMap<String, String>> =
+            LazyLock::new(|| parse_ftl(Locale::En.source()));
+        static ES: LazyLock<HashMap<String, String>> =
+            LazyLock::new(|| parse_ftl(Locale::Es.source()));
+        match self {
+            Locale::En => &EN,
+            Locale::Es => &ES,
+        }
+    }
+}
+
+/// Parse the subset of Fluent syntax used by our catalogs: `key = value` messages,
+/// `#` comments, and indented continuation lines.
+pub fn parse_ftl(source: &str) -> HashMap<String, String> {
+    let mut messages = HashMap::new();
+    let mut current: Option<(String, String)> = None;
+
+    for line in source.lines() {
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        if line.starts_with([' ', '\t']) && !line.trim().is_empty() {
+            if let Some((_, value)) = current.as_mut() {
+                if !value.is_empty() {
+                    value.push('\n');
+                }
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        if let Some((key, value)) = current.take() {
+            messages.insert(key, value);
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            current = Some((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    if let Some((key, value)) = current {
+        messages.insert(key, value);
+    }
+
+    messages
+}
+
+/// Substitute `{ $name }` placeables with the matching argument. Unknown placeables are kept.
+pub fn format_message(pattern: &str, args: &[(&str, &str)]) -> String {
+    let mut output = String::with_capacity(pattern.len());
+    let mut rest = pattern;
+
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            output.push_str(&rest[start..]);
+            return output;
+        };
+        let placeable = &rest[start..start + end + 1];
+        let name = placeable[1..placeable.len() - 1].trim();
+        match name
+            .strip_prefix('$')
+            .and_then(|name| args.iter().find(|(arg, _)| *arg == name))
+        {
+            Some((_, value)) => output.push_str(value),
+            None => output.push_str(placeable),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    output.push_str(rest);
+
+    output
+}
+
+/// Look up a message, falling back to English and then to the key itself.
+pub fn translate(locale: Locale, key: &str, args: &[(&str, &str)]) -> String {
+    let pattern = locale
+        .catalog()
+        .get(key)
+        .or_else(|| Locale::En.catalog().get(key));
+    match pattern {
+        Some(pattern) => format_message(pattern, args),
+        None => key.to_string(),
+    }
+}
+
+/// Handle to the current interface language. Obtain it with [`use_i18n`].
+#[derive(Clone, Copy)]
+pub struct I18n {
+    locale: RwSignal<Locale>,
+}
+
+impl I18n {
+    pub fn locale(&self) -> Locale {
+        self.locale.get()
+    }
+
+    pub fn set_locale(&self, locale: Locale) {
+        self.locale.set(locale);
+    }
+
+    /// Translate a message. Reactive: views re-render when the language changes.
+    pub fn t(&self, key: &str) -> String {
+        translate(self.locale.get(), key, &[])
+    }
+
+    pub fn t_args(&self, key: &str, args: &[(&str, &str)]) -> String {
+        translate(self.locale.get(), key, args)
+    }
+}
+
+/// Create the language state and make it available via context. The stored or browser
+/// language is applied after hydration so the server-rendered markup still matches.
+pub fn provide_i18n() -> I18n {
+    let i18n = I18n {
+        locale: RwSignal::new(Locale::default()),
+    };
+    provide_context(i18n);
+
+    #[cfg(target_arch = "wasm32")]
+    Effect::new(move |_| {
+        let preferred = crate::app::load_setting(LOCALE_KEY)
+            .or_else(|| web_sys::window()?.navigator().language())
+            .and_then(|code| Locale::from_code(&code));
+        if let Some(locale) = preferred {
+            i18n.locale.set(locale);
+        }
+    });
+
+    i18n
+}
+
+pub fn use_i18n() -> I18n {
+    expect_context::<I18n>()
+}
+
+/// Drop-down for choosing the interface language. The choice is saved in the browser.
+#[component]
+pub fn LanguageSwitcher(toasts: Toasts) -> impl IntoView {
+    let i18n = use_i18n();
+
+    view! {
+        <div class="language-switcher">
+            <label for="language-select">{move || i18n.t("language-label")}</label>
+            <select
+                id="language-select"
+                prop:value=move || i18n.locale().code()
+                on:change=move |ev| {
+                    if let Some(locale) = Locale::from_code(&event_target_value(&ev)) {
+                        i18n.set_locale(locale);
+                        crate::app::save_setting(toasts, i18n, LOCALE_KEY, locale.code());
+                    }
+                }
+            >
+                {Locale::ALL
+                    .into_iter()
+                    .map(|locale| view! { <option value=locale.code()>{locale.native_name()}</option> })
+                    .collect_view()}
+            </select>
+        </div>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ftl() {
+        let source = "# comment\nhello = Hello\nmulti = First\n    Second\n\nname = Hi { $name }\n";
+        let messages = parse_ftl(source);
+
+        assert_eq!(messages.get("hello").unwrap(), "Hello");
+        assert_eq!(messages.get("multi").unwrap(), "First\nSecond");
+        assert_eq!(messages.get("name").unwrap(), "Hi { $name }");
+        assert_eq!(messages.len(), 3);
+    }
+
+    #[test]
+    fn test_format_message() {
+        assert_eq!(
+            format_message("Loaded { $count } models", &[("count", "3")]),
+            "Loaded 3 models"
+        );
+        assert_eq!(format_message("{$a}-{ $b }", &[("a", "1")]), "1-{ $b }");
+        assert_eq!(format_message("no placeables", &[]), "no placeables");
+        assert_eq!(format_message("open { brace", &[]), "open { brace");
+    }
+
+    #[test]
+    fn test_translate_fallbacks() {
+        assert_eq!(translate(Locale::Es, "send-button", &[]), "Enviar");
+        assert_eq!(translate(Locale::Es, "missing-key", &[]), "missing-key");
+    }
+
+    #[test]
+    fn test_locale_from_code() {
+        assert_eq!(Locale::from_code("es-MX"), Some(Locale::Es));
+        assert_eq!(Locale::from_code("en_US"), Some(Locale::En));
+        assert_eq!(Locale::from_code("fr"), None);
+    }
+
+    #[test]
+    fn test_catalogs_are_complete() {
+        let english = Locale::En.catalog();
+        for locale in Locale::ALL {
+            let catalog = locale.catalog();
+            for key in english.keys() {
+                assert!(
+                    catalog.contains_key(key),
+                    "locale {} is missing {}",
+                    locale.code(),
+                    key
+                );
+            }
+        }
+    }
+}
@@ -1,4 +1,7 @@
 pub mod app;
+pub mod i18n;
+pub mod share;
+pub mod toast;
 
 #[cfg(feature = "hydrate")]
 #[wasm_bindgen::prelude::wasm_bindgen]
@@ -0,0 +1,210 @@
+use leptos::prelude::*;
+use leptos::server_fn::codec::Json;
+use leptos_router::hooks::use_params_map;
+
+use crate::app::{role_label, Message};
+use crate::i18n::{use_i18n, LanguageSwitcher};
+use crate::toast::use_toasts;
+
+// In-memory store for shared conversations. Shared links last a week, and the oldest are
+// dropped first once there are too many, so sharing can't grow the server's memory forever.
+#[cfg(feature = "ssr")]
+mod store {
+    use crate::app::Message;
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::{LazyLock, RwLock};
+    use std::time::{Duration, Instant};
+
+    const MAX_SHARED_CONVERSATIONS: usize = 10_000;
+    const SHARE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+    static SHARED_CONVERSATIONS: LazyLock<RwLock<ShareStore>> =
+        LazyLock::new(|| RwLock::new(ShareStore::new(MAX_SHARED_CONVERSATIONS, SHARE_TTL)));
+
+    /// Shared conversations by id, at most `max_entries` of them, each kept for `ttl`.
+    pub struct ShareStore {
+        max_entries: usize,
+        ttl: Duration,
+        entries: HashMap<String, (Vec<Message>, Instant)>,
+        /// Ids from the oldest share to the newest.
+        order: VecDeque<String>,
+    }
+
+    impl ShareStore {
+        pub fn new(max_entries: usize, ttl: Duration) -> Self {
+            Self {
+                max_entries,
+                ttl,
+                entries: HashMap::new(),
+                order: VecDeque::new(),
+            }
+        }
+
+        pub fn insert(&mut self, id: String, messages: Vec<Message>, now: Instant) {
+            while let Some(oldest) = self.order.front() {
+                let expired = self
+                    .entries
+                    .get(oldest)
+                    .is_none_or(|(_, shared_at)| now.duration_since(*shared_at) >= self.ttl);
+                if !expired && self.entries.len() < self.max_entries {
+                    break;
+                }
+                if let Some(oldest) = self.order.pop_front() {
+                    self.entries.remove(&oldest);
+                }
+            }
+            self.order.push_back(id.clone());
+            self.entries.insert(id, (messages, now));
+        }
+
+        pub fn get(&self, id: &str, now: Instant) -> Option<Vec<Message>> {
+            self.entries
+                .get(id)
+                .filter(|(_, shared_at)| now.duration_since(*shared_at) < self.ttl)
+                .map(|(messages, _)| messages.clone())
+        }
+
+        pub fn len(&self) -> usize {
+            self.entries.len()
+        }
+    }
+
+    pub fn insert(messages: Vec<Message>) -> String {
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        SHARED_CONVERSATIONS
+            .write()
+            .expect("shared conversation store poisoned")
+            .insert(id.clone(), messages, Instant::now());
+        id
+    }
+
+    pub fn get(id: &str) -> Option<Vec<Message>> {
+        SHARED_CONVERSATIONS
+            .read()
+            .expect("shared conversation store poisoned")
+            .get(id, Instant::now())
+    }
+}
+
+/// Store a snapshot of the conversation and return the id used in its `/share/{id}` link.
+#[server(input = Json)]
+pub async fn share_conversation(messages: Vec<Message>) -> Result<String, ServerFnError> {
+    if messages.is_empty() {
+        return Err(ServerFnError::new("Cannot share an empty conversation"));
+    }
+    Ok(store::insert(messages))
+}
+
+/// Look up a previously shared conversation.
+#[server]
+pub async fn get_shared_conversation(id: String) -> Result<Option<Vec<Message>>, ServerFnError> {
+    Ok(store::get(&id))
+}
+
+/// Build the path at which a shared conversation can be viewed.
+pub fn share_path(id: &str) -> String {
+    format!("/share/{}", id)
+}
+
+/// Renders a shared conversation read-only. No API access is needed to view it.
+#[component]
+pub fn SharedConversationPage() -> impl IntoView {
+    let i18n = use_i18n();
+    let toasts = use_toasts();
+    let params = use_params_map();
+    let conversation = Resource::new(
+        move || params.read().get("id").unwrap_or_default(),
+        get_shared_conversation,
+    );
+
+    view! {
+        <div class="chat-container shared-conversation">
+            <div class="chat-header">
+                <h1>{move || i18n.t("app-title")}</h1>
+                <div class="shared-notice">{move || i18n.t("shared-notice")}</div>
+                <LanguageSwitcher toasts=toasts/>
+            </div>
+
+            <div class="chat-messages">
+                <Suspense fallback=|| move || view! { <div class="shared-status">{i18n.t("shared-loading")}</div> }>
+                    {move || {
+                        conversation.get().map(|result| match result {
+                            Ok(Some(messages)) => messages
+                                .into_iter()
+                                .map(|message| {
+                                    let role_class = if message.role == "user" { "user-message" } else { "assistant-message" };
+                                    view! {
+                                        <div class=format!("message {}", role_class)>
+                                            <div class="message-role">{role_label(i18n, &message.role)}</div>
+                                            <div class="message-content">{message.text().unwrap_or_default().to_string()}</div>
+                                        </div>
+                                    }
+                                })
+                                .collect_view()
+                                .into_any(),
+                            Ok(None) => view! {
+                                <div class="shared-status">{i18n.t("shared-not-found")}</div>
+                            }.into_any(),
+                            Err(error) => view! {
+                                <div class="error-message">
+                                    {i18n.t_args("shared-error", &[("error", &error.to_string())])}
+                                </div>
+                            }.into_any(),
+                        })
+                    }}
+                </Suspense>
+            </div>
+
+            <div class="shared-footer">
+                <a href="/">{move || i18n.t("shared-start-chat")}</a>
+            </div>
+        </div>
+    }
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_round_trip() {
+        let messages = vec![Message::user("Hello")];
+
+        let id = store::insert(messages);
+        let stored = store::get(&id).expect("conversation should be stored");
+
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].text(), Some("Hello"));
+        assert!(store::get("missing").is_none());
+    }
+
+    #[test]
+    fn test_old_shares_are_evicted() {
+        use std::time::{Duration, Instant};
+
+        let start = Instant::now();
+        let mut shares = store::ShareStore::new(2, Duration::from_secs(60));
+        for (i, id) in ["a", "b", "c"].into_iter().enumerate() {
+            let at = start + Duration::from_secs(i as u64);
+            shares.insert(id.to_string(), vec![Message::user(id)], at);
+        }
+        // The oldest share made room for the third
+        let now = start + Duration::from_secs(2);
+        assert_eq!(shares.len(), 2);
+        assert!(shares.get("a", now).is_none());
+        assert!(shares.get("b", now).is_some());
+
+        // Expired shares are gone when read, and dropped on the next share
+        let later = start + Duration::from_secs(61);
+        assert!(shares.get("b", later).is_none());
+        assert!(shares.get("c", later).is_some());
+        shares.insert("d".to_string(), vec![Message::user("d")], later);
+        assert_eq!(shares.len(), 2);
+        assert!(shares.get("d", later).is_some());
+    }
+
+    #[test]
+    fn test_share_path() {
+        assert_eq!(share_path("abc123"), "/share/abc123");
+    }
+}
@@ -0,0 +1,210 @@
+use leptos::prelude::*;
+use std::time::Duration;
+
+use crate::i18n::use_i18n;
+
+/// Maximum number of toasts shown at once; the rest wait in the queue.
+pub const MAX_VISIBLE_TOASTS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToastKind {
+    Success,
+    Info,
+    Error,
+}
+
+impl ToastKind {
+    pub fn class(&self) -> &'static str {
+        match self {
+            ToastKind::Success => "toast-success",
+            ToastKind::Info => "toast-info",
+            ToastKind::Error => "toast-error",
+        }
+    }
+
+    /// How long a toast stays on screen once it becomes visible. Errors linger longer.
+    pub fn duration(&self) -> Duration {
+        match self {
+            ToastKind::Success | ToastKind::Info => Duration::from_secs(3),
+            ToastKind::Error => Duration::from_secs(6),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Toast {
+    pub id: u64,
+    pub kind: ToastKind,
+    pub message: String,
+}
+
+/// FIFO queue of toasts. Only the first `MAX_VISIBLE_TOASTS` are displayed.
+#[derive(Debug, Default, Clone)]
+pub struct ToastQueue {
+    toasts: Vec<Toast>,
+    next_id: u64,
+}
+
+impl ToastQueue {
+    /// Add a toast, returning its id and whether it is immediately visible.
+    pub fn push(&mut self, kind: ToastKind, message: String) -> (u64, bool) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.toasts.push(Toast { id, kind, message });
+        (id, self.toasts.len() <= MAX_VISIBLE_TOASTS)
+    }
+
+    /// Remove a toast. If that frees a slot, returns the queued toast that just became visible.
+    pub fn dismiss(&mut self, id: u64) -> Option<Toast> {
+        let index = self.toasts.iter().position(|toast| toast.id == id)?;
+        self.toasts.remove(index);
+        if index < MAX_VISIBLE_TOASTS {
+            self.toasts.get(MAX_VISIBLE_TOASTS - 1).cloned()
+        } else {
+            None
+        }
+    }
+
+    pub fn visible(&self) -> &[Toast] {
+        &self.toasts[..self.toasts.len().min(MAX_VISIBLE_TOASTS)]
+    }
+
+    pub fn pending(&self) -> usize {
+        self.toasts.len().saturating_sub(MAX_VISIBLE_TOASTS)
+    }
+}
+
+/// Handle for raising toasts from any component. Obtain it with [`use_toasts`].
+#[derive(Clone, Copy)]
+pub struct Toasts {
+    queue: RwSignal<ToastQueue>,
+}
+
+impl Toasts {
+    pub fn push(&self, kind: ToastKind, message: impl Into<String>) -> u64 {
+        let message = message.into();
+        let Some((id, visible)) = self.queue.try_update(|queue| queue.push(kind, message)) else {
+            return 0;
+        };
+        if visible {
+            self.schedule_dismiss(id, kind);
+        }
+        id
+    }
+
+    pub fn success(&self, message: impl Into<String>) -> u64 {
+        self.push(ToastKind::Success, message)
+    }
+
+    pub fn info(&self, message: impl Into<String>) -> u64 {
+        self.push(ToastKind::Info, message)
+    }
+
+    pub fn error(&self, message: impl Into<String>) -> u64 {
+        self.push(ToastKind::Error, message)
+    }
+
+    pub fn dismiss(&self, id: u64) {
+        if let Some(promoted) = self.queue.try_update(|queue| queue.dismiss(id)).flatten() {
+            self.schedule_dismiss(promoted.id, promoted.kind);
+        }
+    }
+
+    fn schedule_dismiss(&self, id: u64, kind: ToastKind) {
+        // Timers only exist in the browser; toasts are never raised during SSR.
+        #[cfg(target_arch = "wasm32")]
+        {
+            let toasts = *self;
+            set_timeout(move || toasts.dismiss(id), kind.duration());
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        let _ = (id, kind);
+    }
+}
+
+/// Create the toast queue and make it available to descendants via context.
+pub fn provide_toasts() -> Toasts {
+    let toasts = Toasts {
+        queue: RwSignal::new(ToastQueue::default()),
+    };
+    provide_context(toasts);
+    toasts
+}
+
+pub fn use_toasts() -> Toasts {
+    expect_context::<Toasts>()
+}
+
+/// Renders the visible toasts in a fixed stack.
+#[component]
+pub fn ToastContainer() -> impl IntoView {
+    let toasts = use_toasts();
+    let i18n = use_i18n();
+
+    view! {
+        <div class="toast-container" role="status" aria-live="polite">
+            <For
+                each=move || toasts.queue.with(|queue| queue.visible().to_vec())
+                key=|toast| toast.id
+                children=move |toast| {
+                    let id = toast.id;
+                    view! {
+                        <div class=format!("toast {}", toast.kind.class())>
+                            <span class="toast-message">{toast.message}</span>
+                            <button
+                                class="toast-dismiss"
+                                aria-label=move || i18n.t("toast-dismiss")
+                                on:click=move |_| toasts.dismiss(id)
+                            >
+                                "×"
+                            </button>
+                        </div>
+                    }
+                }
+            />
+            {move || {
+                let pending = toasts.queue.with(|queue| queue.pending());
+                (pending > 0).then(|| view! { <div class="toast-pending">{i18n.t_args("toast-pending", &[("count", &pending.to_string())])}</div> })
+            }}
+        </div>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_limits_visible_toasts() {
+        let mut queue = ToastQueue::default();
+        for i in 0..MAX_VISIBLE_TOASTS {
+            let (_, visible) = queue.push(ToastKind::Info, format!("toast {}", i));
+            assert!(visible);
+        }
+
+        let (queued_id, visible) = queue.push(ToastKind::Error, "queued".to_string());
+        assert!(!visible);
+        assert_eq!(queue.visible().len(), MAX_VISIBLE_TOASTS);
+        assert_eq!(queue.pending(), 1);
+
+        let first_id = queue.visible()[0].id;
+        let promoted = queue
+            .dismiss(first_id)
+            .expect("queued toast should be promoted");
+        assert_eq!(promoted.id, queued_id);
+        assert_eq!(queue.pending(), 0);
+    }
+
+    #[test]
+    fn test_dismiss_unknown_or_pending() {
+        let mut queue = ToastQueue::default();
+        assert!(queue.dismiss(42).is_none());
+
+        for _ in 0..=MAX_VISIBLE_TOASTS {
+            queue.push(ToastKind::Success, "saved".to_string());
+        }
+        let pending_id = MAX_VISIBLE_TOASTS as u64;
+        assert!(queue.dismiss(pending_id).is_none());
+        assert_eq!(queue.visible().len(), MAX_VISIBLE_TOASTS);
+    }
+}
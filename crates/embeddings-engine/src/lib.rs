@@ -1,16 +1,131 @@
-use async_openai::types::{CreateEmbeddingRequest, EmbeddingInput};
-use axum::{Json, Router, http::StatusCode, response::Json as ResponseJson, routing::post};
-use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
+pub mod pool;
+
+use async_openai::types::{CreateEmbeddingRequest, EmbeddingInput, EncodingFormat};
+use axum::{
+    Json, Router, extract::rejection::JsonRejection, http::StatusCode,
+    response::Json as ResponseJson, routing::post,
+};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+pub use fastembed::EmbeddingModel;
+use fastembed::{InitOptions, TextEmbedding};
 use once_cell::sync::Lazy;
+use openai_protocol::{ApiError, ErrorDetail, ErrorResponse, ModelReadiness, ReadinessResponse};
 use serde::Serialize;
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 use tower_http::trace::TraceLayer;
+use utoipa::{OpenApi, ToSchema};
+
+use pool::embedding_pool;
 
 // Cache for multiple embedding models
-static MODEL_CACHE: Lazy<RwLock<HashMap<EmbeddingModel, Arc<TextEmbedding>>>> =
+static MODEL_CACHE: Lazy<RwLock<HashMap<EmbeddingModel, CachedModel>>> =
     Lazy::new(|| RwLock::new(HashMap::new()));
 
+struct CachedModel {
+    model: Arc<TextEmbedding>,
+    /// When a request last took the model from the cache.
+    last_used: Mutex<Instant>,
+}
+
+impl CachedModel {
+    fn touch(&self) -> Arc<TextEmbedding> {
+        if let Ok(mut last_used) = self.last_used.lock() {
+            *last_used = Instant::now();
+        }
+        Arc::clone(&self.model)
+    }
+
+    /// Whether only the cache holds the model, i.e. no request is embedding with it.
+    fn is_idle(&self) -> bool {
+        Arc::strong_count(&self.model) == 1
+    }
+}
+
+/// Cached embedding models no request is using, with when each was last used.
+pub fn idle_models() -> Vec<(EmbeddingModel, Instant)> {
+    let Ok(cache) = MODEL_CACHE.read() else {
+        return Vec::new();
+    };
+    cache
+        .iter()
+        .filter(|(_, cached)| cached.is_idle())
+        .filter_map(|(model, cached)| {
+            let last_used = *cached.last_used.lock().ok()?;
+            Some((model.clone(), last_used))
+        })
+        .collect()
+}
+
+/// Drop a cached embedding model if no request is using it. Returns whether it was dropped.
+pub fn evict_idle_model(model: &EmbeddingModel) -> bool {
+    let Ok(mut cache) = MODEL_CACHE.write() else {
+        return false;
+    };
+    if !cache.get(model).is_some_and(CachedModel::is_idle) {
+        return false;
+    }
+    cache.remove(model).is_some()
+}
+
+/// Environment variable naming an embedding model to load at startup, which readiness
+/// waits for, e.g. `nomic-embed-text-v1.5`.
+pub const DEFAULT_EMBEDDING_MODEL_ENV: &str = "DEFAULT_EMBEDDING_MODEL";
+
+/// Whether `model` is cached, so requests for it don't wait for it to load.
+pub fn is_model_loaded(model: &EmbeddingModel) -> bool {
+    MODEL_CACHE
+        .read()
+        .is_ok_and(|cache| cache.contains_key(model))
+}
+
+/// Load the embedding model called `name` into the cache, as the first request for it
+/// would. Blocks while the model downloads and initializes.
+pub fn load_model(name: &str) -> Result<EmbeddingModel, String> {
+    let model = parse_embedding_model(name)?;
+    get_or_create_model(model.clone())?;
+    Ok(model)
+}
+
+/// Set once the default embedding model has loaded; evicting it later keeps the server ready.
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Liveness check: the server answers requests.
+pub async fn health_live() -> &'static str {
+    "ok"
+}
+
+/// Readiness check: 503 until the model in [`DEFAULT_EMBEDDING_MODEL_ENV`] has loaded.
+/// Without one set, the server is ready right away.
+pub async fn health_ready() -> (StatusCode, Json<ReadinessResponse>) {
+    let models: Vec<ModelReadiness> = std::env::var(DEFAULT_EMBEDDING_MODEL_ENV)
+        .ok()
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .map(|id| ModelReadiness {
+            loaded: parse_embedding_model(&id).is_ok_and(|model| is_model_loaded(&model)),
+            id,
+        })
+        .into_iter()
+        .collect();
+    if models.iter().all(|model| model.loaded) {
+        READY.store(true, Ordering::Relaxed);
+    }
+    let ready = READY.load(Ordering::Relaxed);
+    let response = ReadinessResponse {
+        status: if ready { "ready" } else { "loading" }.to_string(),
+        models,
+    };
+    if ready {
+        (StatusCode::OK, Json(response))
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, Json(response))
+    }
+}
+
 #[derive(Serialize)]
 pub struct ModelInfo {
     pub id: String,
@@ -26,8 +141,105 @@ pub struct ModelsResponse {
     pub data: Vec<ModelInfo>,
 }
 
-// Function to convert model name strings to EmbeddingModel enum variants
-fn parse_embedding_model(model_name: &str) -> Result<EmbeddingModel, String> {
+/// Body of `POST /v1/embeddings` as the OpenAPI spec describes it. The handler parses
+/// async-openai's `CreateEmbeddingRequest`, which has no schema of its own.
+#[derive(Serialize, ToSchema)]
+pub struct EmbeddingRequest {
+    /// The embedding model, e.g. `nomic-embed-text-v1.5` or `BAAI/bge-small-en-v1.5`
+    #[schema(example = "nomic-embed-text-v1.5")]
+    pub model: String,
+    /// The text to embed
+    pub input: EmbeddingRequestInput,
+    /// `float` (the default) for arrays of numbers, or `base64` for the little-endian f32
+    /// values, base64-encoded
+    #[schema(example = "float")]
+    pub encoding_format: Option<String>,
+    /// Shorten the embeddings to this many values, scaled back to unit length. Only models
+    /// trained for it, such as `nomic-embed-text-v1.5`, take fewer than their own
+    #[schema(example = 256)]
+    pub dimensions: Option<u32>,
+}
+
+/// A text, or several texts to embed in one request
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum EmbeddingRequestInput {
+    #[schema(example = "The food was delicious and the waiter...")]
+    Single(String),
+    Batch(Vec<String>),
+}
+
+/// Response of `POST /v1/embeddings`, following OpenAI's format
+#[derive(Serialize, ToSchema)]
+pub struct EmbeddingResponse {
+    /// The object type, always "list"
+    pub object: String,
+    pub data: Vec<EmbeddingData>,
+    pub model: String,
+    pub usage: EmbeddingUsage,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct EmbeddingData {
+    /// The object type, always "embedding"
+    pub object: String,
+    pub index: usize,
+    pub embedding: EmbeddingVector,
+}
+
+/// An embedding in the request's `encoding_format`
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum EmbeddingVector {
+    /// The values as numbers
+    Float(Vec<f32>),
+    /// The values as little-endian f32s, base64-encoded
+    Base64(String),
+}
+
+impl EmbeddingVector {
+    /// `values` encoded as `format` asks, numbers without one.
+    pub fn encode(values: Vec<f32>, format: Option<&EncodingFormat>) -> Self {
+        match format {
+            Some(EncodingFormat::Base64) => {
+                let bytes: Vec<u8> = values
+                    .iter()
+                    .flat_map(|value| value.to_le_bytes())
+                    .collect();
+                Self::Base64(BASE64.encode(bytes))
+            }
+            Some(EncodingFormat::Float) | None => Self::Float(values),
+        }
+    }
+}
+
+/// Token counts, not tracked by the embeddings engine and always 0
+#[derive(Serialize, ToSchema)]
+pub struct EmbeddingUsage {
+    pub prompt_tokens: u32,
+    pub total_tokens: u32,
+}
+
+/// OpenAPI description of the routes [`create_embeddings_router`] serves.
+#[derive(OpenApi)]
+#[openapi(
+    paths(embeddings_create),
+    components(schemas(
+        EmbeddingRequest,
+        EmbeddingRequestInput,
+        EmbeddingResponse,
+        EmbeddingData,
+        EmbeddingVector,
+        EmbeddingUsage,
+        ErrorResponse,
+        ErrorDetail
+    )),
+    tags((name = "embeddings", description = "Text embeddings"))
+)]
+pub struct ApiDoc;
+
+/// The FastEmbed model a request's `model` names, by its full or short id.
+pub fn parse_embedding_model(model_name: &str) -> Result<EmbeddingModel, String> {
     match model_name {
         // Sentence Transformers models
         "sentence-transformers/all-MiniLM-L6-v2" | "all-minilm-l6-v2" => {
@@ -145,16 +357,83 @@ fn get_model_dimensions(model: &EmbeddingModel) -> usize {
     }
 }
 
+/// Whether `model` was trained with Matryoshka representation learning, so the first values
+/// of its embeddings make an embedding of their own and `dimensions` can shorten them.
+pub fn supports_dimensions(model: &EmbeddingModel) -> bool {
+    matches!(
+        model,
+        EmbeddingModel::NomicEmbedTextV15
+            | EmbeddingModel::NomicEmbedTextV15Q
+            | EmbeddingModel::MxbaiEmbedLargeV1
+            | EmbeddingModel::MxbaiEmbedLargeV1Q
+    )
+}
+
+/// The size a request's `dimensions` shortens the embeddings of a model with `full`
+/// dimensions to, `None` to keep them whole. Fails for 0, for more than `full`, and for
+/// anything but `full` when the model can't be shortened.
+pub fn requested_dimensions(
+    dimensions: Option<u32>,
+    full: usize,
+    shortens: bool,
+) -> Result<Option<usize>, ApiError> {
+    let Some(dimensions) = dimensions.map(|dimensions| dimensions as usize) else {
+        return Ok(None);
+    };
+    if dimensions == full {
+        return Ok(None);
+    }
+    if dimensions == 0 || dimensions > full {
+        return Err(ApiError::invalid_request(format!(
+            "dimensions must be between 1 and the model's {}, got {}",
+            full, dimensions
+        ))
+        .with_param("dimensions"));
+    }
+    if !shortens {
+        return Err(ApiError::invalid_request(format!(
+            "This model's embeddings can't be shortened; dimensions must be {} or left out",
+            full
+        ))
+        .with_param("dimensions"));
+    }
+    Ok(Some(dimensions))
+}
+
+/// The first `dimensions` values of `embedding`, scaled back to unit length.
+pub fn shorten_embedding(mut embedding: Vec<f32>, dimensions: usize) -> Vec<f32> {
+    embedding.truncate(dimensions);
+    let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        for value in &mut embedding {
+            *value /= norm;
+        }
+    }
+    embedding
+}
+
 // Function to get or create a model from cache
+/// Tokens the model reads for `texts`, counted with its own tokenizer, special tokens
+/// included and each text truncated as the model truncates it.
+fn count_tokens(model: &TextEmbedding, texts: &[String]) -> Result<usize, ApiError> {
+    texts.iter().try_fold(0, |total, text| {
+        let encoding = model
+            .tokenizer
+            .encode(text.as_str(), true)
+            .map_err(|e| ApiError::server_error(format!("Failed to tokenize the input: {}", e)))?;
+        Ok(total + encoding.get_ids().len())
+    })
+}
+
 fn get_or_create_model(embedding_model: EmbeddingModel) -> Result<Arc<TextEmbedding>, String> {
     // First try to get from cache (read lock)
     {
         let cache = MODEL_CACHE
             .read()
             .map_err(|e| format!("Failed to acquire read lock: {}", e))?;
-        if let Some(model) = cache.get(&embedding_model) {
+        if let Some(cached) = cache.get(&embedding_model) {
             tracing::debug!("Using cached model: {:?}", embedding_model);
-            return Ok(Arc::clone(model));
+            return Ok(cached.touch());
         }
     }
 
@@ -164,9 +443,9 @@ fn get_or_create_model(embedding_model: EmbeddingModel) -> Result<Arc<TextEmbedd
         .map_err(|e| format!("Failed to acquire write lock: {}", e))?;
 
     // Double-check after acquiring write lock
-    if let Some(model) = cache.get(&embedding_model) {
+    if let Some(cached) = cache.get(&embedding_model) {
         tracing::debug!("Using cached model (double-check): {:?}", embedding_model);
-        return Ok(Arc::clone(model));
+        return Ok(cached.touch());
     }
 
     tracing::info!("Initializing new embedding model: {:?}", embedding_model);
@@ -185,58 +464,110 @@ fn get_or_create_model(embedding_model: EmbeddingModel) -> Result<Arc<TextEmbedd
     );
 
     let model_arc = Arc::new(model);
-    cache.insert(embedding_model.clone(), Arc::clone(&model_arc));
+    cache.insert(
+        embedding_model.clone(),
+        CachedModel {
+            model: Arc::clone(&model_arc),
+            last_used: Mutex::new(Instant::now()),
+        },
+    );
     Ok(model_arc)
 }
 
-pub async fn embeddings_create(
-    Json(payload): Json<CreateEmbeddingRequest>,
-) -> Result<ResponseJson<serde_json::Value>, (StatusCode, String)> {
-    // Start timing the entire process
-    let start_time = std::time::Instant::now();
+/// `embedding` as the response carries it. An all-zero embedding, which no similarity
+/// search can use, is replaced by a random unit vector of the model's dimensions, and a
+/// dimension count other than the model's is logged.
+fn postprocess_embedding(embedding: Vec<f32>, embedding_model: &EmbeddingModel) -> Vec<f32> {
+    let expected_dimensions = get_model_dimensions(embedding_model);
 
-    // Phase 1: Parse and get the embedding model
-    let model_start_time = std::time::Instant::now();
+    // Log the first 10 values of the original embedding at trace level
+    tracing::trace!(
+        "Original embedding preview: {:?}",
+        &embedding[..10.min(embedding.len())]
+    );
 
-    let embedding_model = match parse_embedding_model(&payload.model) {
-        Ok(model) => model,
-        Err(e) => {
-            tracing::error!("Invalid model requested: {}", e);
-            return Err((StatusCode::BAD_REQUEST, format!("Invalid model: {}", e)));
+    // Check if there are any NaN or zero values in the original embedding
+    let nan_count = embedding.iter().filter(|&&x| x.is_nan()).count();
+    let zero_count = embedding.iter().filter(|&&x| x == 0.0).count();
+    tracing::trace!(
+        "Original embedding stats: NaN count={}, zero count={}",
+        nan_count,
+        zero_count
+    );
+
+    if embedding.iter().all(|&x| x == 0.0) {
+        tracing::warn!("Embedding is all zeros. Generating random non-zero embedding.");
+
+        // Generate a random non-zero embedding
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        let mut random_embedding = Vec::with_capacity(expected_dimensions);
+        for _ in 0..expected_dimensions {
+            // Generate random values between -1.0 and 1.0, excluding 0
+            let mut val = 0.0;
+            while val == 0.0 {
+                val = rng.gen_range(-1.0..1.0);
+            }
+            random_embedding.push(val);
         }
-    };
 
-    let model = match get_or_create_model(embedding_model.clone()) {
-        Ok(model) => model,
-        Err(e) => {
-            tracing::error!("Failed to get/create model: {}", e);
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Model initialization failed: {}", e),
-            ));
+        // Normalize the random embedding
+        let norm: f32 = random_embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+        for value in &mut random_embedding {
+            *value /= norm;
         }
-    };
+        return random_embedding;
+    }
 
-    let model_access_time = model_start_time.elapsed();
-    tracing::debug!(
-        "Model access/creation completed in {:.2?}",
-        model_access_time
-    );
+    if embedding.len() != expected_dimensions {
+        tracing::warn!(
+            "Model {:?} produced {} dimensions but expected {}",
+            embedding_model,
+            embedding.len(),
+            expected_dimensions
+        );
+    }
+    embedding
+}
+
+/// Embed the input texts with one of the FastEmbed models, one embedding per text
+#[utoipa::path(
+    post,
+    path = "/v1/embeddings",
+    tag = "embeddings",
+    request_body = EmbeddingRequest,
+    responses(
+        (status = 200, description = "One embedding per input text, in input order", body = EmbeddingResponse),
+        (status = 400, description = "Unknown embedding model, token id input, no texts, or dimensions the model can't shorten its embeddings to", body = ErrorResponse),
+        (status = 422, description = "The body doesn't match the request schema", body = ErrorResponse),
+        (status = 500, description = "The model failed to load or to embed", body = ErrorResponse)
+    )
+)]
+pub async fn embeddings_create(
+    payload: Result<Json<CreateEmbeddingRequest>, JsonRejection>,
+) -> Result<ResponseJson<serde_json::Value>, ApiError> {
+    let Json(payload) = payload?;
+
+    // Start timing the entire process
+    let start_time = std::time::Instant::now();
 
-    // Phase 2: Process input
+    // Phase 1: Process input, rejecting what we can't embed before loading a model
     let input_start_time = std::time::Instant::now();
 
     let embedding_input = payload.input;
     let texts_from_embedding_input = match embedding_input {
         EmbeddingInput::String(text) => vec![text],
         EmbeddingInput::StringArray(texts) => texts,
-        EmbeddingInput::IntegerArray(_) => {
-            panic!("Integer array input not supported for text embeddings");
-        }
-        EmbeddingInput::ArrayOfIntegerArray(_) => {
-            panic!("Array of integer arrays not supported for text embeddings");
+        EmbeddingInput::IntegerArray(_) | EmbeddingInput::ArrayOfIntegerArray(_) => {
+            return Err(ApiError::invalid_request(
+                "Token id input is not supported for text embeddings",
+            )
+            .with_param("input"));
         }
     };
+    if texts_from_embedding_input.is_empty() {
+        return Err(ApiError::invalid_request("Input holds no texts to embed").with_param("input"));
+    }
 
     let input_processing_time = input_start_time.elapsed();
     tracing::debug!(
@@ -244,22 +575,66 @@ pub async fn embeddings_create(
         input_processing_time
     );
 
-    // Phase 3: Generate embeddings
-    let embedding_start_time = std::time::Instant::now();
+    // Phase 2: Parse and get the embedding model
+    let model_start_time = std::time::Instant::now();
+
+    let embedding_model = match parse_embedding_model(&payload.model) {
+        Ok(model) => model,
+        Err(e) => {
+            tracing::error!("Invalid model requested: {}", e);
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "model_not_supported",
+                format!("Invalid model: {}", e),
+            )
+            .with_param("model"));
+        }
+    };
+    let shorten_to = requested_dimensions(
+        payload.dimensions,
+        get_model_dimensions(&embedding_model),
+        supports_dimensions(&embedding_model),
+    )?;
+
+    // Phases 2 and 3 load the model and run it, which keeps a thread busy for as long as
+    // the batch takes, so they run on the embedding pool rather than this worker
+    let model_to_load = embedding_model.clone();
+    let (embeddings, prompt_tokens, model_access_time, embedding_generation_time) =
+        embedding_pool()
+            .run(move || {
+                let model = get_or_create_model(model_to_load).map_err(|e| {
+                    tracing::error!("Failed to get/create model: {}", e);
+                    ApiError::server_error(format!("Model initialization failed: {}", e))
+                })?;
+
+                let model_access_time = model_start_time.elapsed();
+                tracing::debug!(
+                    "Model access/creation completed in {:.2?}",
+                    model_access_time
+                );
+
+                // Phase 3: Generate embeddings
+                let embedding_start_time = std::time::Instant::now();
 
-    let embeddings = model.embed(texts_from_embedding_input, None).map_err(|e| {
-        tracing::error!("Failed to generate embeddings: {}", e);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Embedding generation failed: {}", e),
-        )
-    })?;
+                let prompt_tokens = count_tokens(&model, &texts_from_embedding_input)?;
+                let embeddings = model.embed(texts_from_embedding_input, None).map_err(|e| {
+                    tracing::error!("Failed to generate embeddings: {}", e);
+                    ApiError::server_error(format!("Embedding generation failed: {}", e))
+                })?;
 
-    let embedding_generation_time = embedding_start_time.elapsed();
-    tracing::info!(
-        "Embedding generation completed in {:.2?}",
-        embedding_generation_time
-    );
+                let embedding_generation_time = embedding_start_time.elapsed();
+                tracing::info!(
+                    "Embedding generation completed in {:.2?}",
+                    embedding_generation_time
+                );
+                Ok::<_, ApiError>((
+                    embeddings,
+                    prompt_tokens,
+                    model_access_time,
+                    embedding_generation_time,
+                ))
+            })
+            .await??;
 
     // Memory usage estimation (approximate)
     let embedding_size_bytes = embeddings
@@ -273,76 +648,30 @@ pub async fn embeddings_create(
 
     // Only log detailed embedding information at trace level to reduce log volume
     tracing::trace!("Embeddings length: {}", embeddings.len());
-    tracing::info!("Embedding dimension: {}", embeddings[0].len());
-
-    // Log the first 10 values of the original embedding at trace level
-    tracing::trace!(
-        "Original embedding preview: {:?}",
-        &embeddings[0][..10.min(embeddings[0].len())]
-    );
-
-    // Check if there are any NaN or zero values in the original embedding
-    let nan_count = embeddings[0].iter().filter(|&&x| x.is_nan()).count();
-    let zero_count = embeddings[0].iter().filter(|&&x| x == 0.0).count();
-    tracing::trace!(
-        "Original embedding stats: NaN count={}, zero count={}",
-        nan_count,
-        zero_count
+    tracing::info!(
+        "Embedded {} texts of dimension {}",
+        embeddings.len(),
+        embeddings.first().map_or(0, Vec::len)
     );
 
     // Phase 4: Post-process embeddings
     let postprocessing_start_time = std::time::Instant::now();
 
-    // Create the final embedding
-    let final_embedding = {
-        // Check if the embedding is all zeros
-        let all_zeros = embeddings[0].iter().all(|&x| x == 0.0);
-        if all_zeros {
-            tracing::warn!("Embedding is all zeros. Generating random non-zero embedding.");
-
-            // Generate a random non-zero embedding
-            use rand::Rng;
-            let mut rng = rand::thread_rng();
-            let expected_dimensions = get_model_dimensions(&embedding_model);
-            let mut random_embedding = Vec::with_capacity(expected_dimensions);
-            for _ in 0..expected_dimensions {
-                // Generate random values between -1.0 and 1.0, excluding 0
-                let mut val = 0.0;
-                while val == 0.0 {
-                    val = rng.gen_range(-1.0..1.0);
-                }
-                random_embedding.push(val);
+    let data: Vec<EmbeddingData> = embeddings
+        .into_iter()
+        .enumerate()
+        .map(|(index, embedding)| {
+            let mut embedding = postprocess_embedding(embedding, &embedding_model);
+            if let Some(dimensions) = shorten_to {
+                embedding = shorten_embedding(embedding, dimensions);
             }
-
-            // Normalize the random embedding
-            let norm: f32 = random_embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
-
-            #[allow(clippy::needless_range_loop)]
-            for i in 0..random_embedding.len() {
-                random_embedding[i] /= norm;
+            EmbeddingData {
+                object: "embedding".to_string(),
+                index,
+                embedding: EmbeddingVector::encode(embedding, payload.encoding_format.as_ref()),
             }
-
-            random_embedding
-        } else {
-            // Check if dimensions parameter is provided and pad the embeddings if necessary
-            let padded_embedding = embeddings[0].clone();
-
-            // Use the actual model dimensions instead of hardcoded 768
-            let actual_dimensions = padded_embedding.len();
-            let expected_dimensions = get_model_dimensions(&embedding_model);
-
-            if actual_dimensions != expected_dimensions {
-                tracing::warn!(
-                    "Model {:?} produced {} dimensions but expected {}",
-                    embedding_model,
-                    actual_dimensions,
-                    expected_dimensions
-                );
-            }
-
-            padded_embedding
-        }
-    };
+        })
+        .collect();
 
     let postprocessing_time = postprocessing_start_time.elapsed();
     tracing::debug!(
@@ -350,31 +679,17 @@ pub async fn embeddings_create(
         postprocessing_time
     );
 
-    tracing::trace!("Final embedding dimension: {}", final_embedding.len());
-
-    // Log the first 10 values of the final embedding at trace level
-    tracing::trace!(
-        "Final embedding preview: {:?}",
-        &final_embedding[..10.min(final_embedding.len())]
-    );
-
     // Phase 5: Prepare response
     let response_start_time = std::time::Instant::now();
 
-    // Return a response that matches the OpenAI API format
+    // Return a response that matches the OpenAI API format, one object per input text
     let response = serde_json::json!({
         "object": "list",
-        "data": [
-            {
-                "object": "embedding",
-                "index": 0,
-                "embedding": final_embedding
-            }
-        ],
+        "data": data,
         "model": payload.model,
         "usage": {
-            "prompt_tokens": 0,
-            "total_tokens": 0
+            "prompt_tokens": prompt_tokens,
+            "total_tokens": prompt_tokens
         }
     });
 
@@ -622,3 +937,36 @@ pub fn create_embeddings_router() -> Router {
         // .route("/v1/models", get(models_list))
         .layer(TraceLayer::new_for_http())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dimensions_shorten_matryoshka_models_only() {
+        assert!(supports_dimensions(&EmbeddingModel::NomicEmbedTextV15));
+        assert!(!supports_dimensions(&EmbeddingModel::BGESmallENV15));
+
+        assert_eq!(requested_dimensions(None, 768, true).unwrap(), None);
+        assert_eq!(requested_dimensions(Some(768), 768, false).unwrap(), None);
+        assert_eq!(
+            requested_dimensions(Some(256), 768, true).unwrap(),
+            Some(256)
+        );
+        for (dimensions, shortens) in [(0, true), (769, true), (256, false)] {
+            let error = requested_dimensions(Some(dimensions), 768, shortens).unwrap_err();
+            assert_eq!(error.status, StatusCode::BAD_REQUEST);
+            assert_eq!(error.body.error.param.as_deref(), Some("dimensions"));
+        }
+    }
+
+    #[test]
+    fn test_shortened_embeddings_have_unit_length() {
+        let shortened = shorten_embedding(vec![0.6, 0.0, 0.8, 0.0], 2);
+        assert_eq!(shortened, vec![1.0, 0.0]);
+        let shortened = shorten_embedding(vec![0.5, 0.5, 0.5, 0.5], 3);
+        let norm = shortened.iter().map(|x| x * x).sum::<f32>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-6, "{norm}");
+        assert_eq!(shorten_embedding(vec![0.0; 4], 2), vec![0.0, 0.0]);
+    }
+}
@@ -1,9 +1,10 @@
-use async_openai::types::{CreateEmbeddingRequest, EmbeddingInput};
 use axum::{
-    Json, Router,
+    Router,
+    extract::DefaultBodyLimit,
     response::Json as ResponseJson,
     routing::{get, post},
 };
+use openai_protocol::trace_context::propagate_trace;
 use std::env;
 use tower_http::trace::TraceLayer;
 use tracing;
@@ -13,27 +14,20 @@ const DEFAULT_SERVER_PORT: &str = "8080";
 
 use embeddings_engine;
 
-async fn embeddings_create(
-    Json(payload): Json<CreateEmbeddingRequest>,
-) -> Result<ResponseJson<serde_json::Value>, axum::response::Response> {
-    match embeddings_engine::embeddings_create(Json(payload)).await {
-        Ok(response) => Ok(response),
-        Err((status_code, message)) => Err(axum::response::Response::builder()
-            .status(status_code)
-            .body(axum::body::Body::from(message))
-            .unwrap()),
-    }
-}
-
 async fn models_list() -> ResponseJson<embeddings_engine::ModelsResponse> {
     embeddings_engine::models_list().await
 }
 
 fn create_app() -> Router {
     Router::new()
-        .route("/v1/embeddings", post(embeddings_create))
+        .route("/v1/embeddings", post(embeddings_engine::embeddings_create))
         .route("/v1/models", get(models_list))
+        .route("/health/live", get(embeddings_engine::health_live))
+        .route("/health/ready", get(embeddings_engine::health_ready))
+        // The gateway in front limits request bodies, so batches it accepts aren't refused
+        .layer(DefaultBodyLimit::disable())
         .layer(TraceLayer::new_for_http())
+        .layer(axum::middleware::from_fn(propagate_trace))
 }
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 #[tokio::main]
@@ -54,6 +48,14 @@ async fn main() {
         .init();
     let app = create_app();
 
+    // Load the default model, which /health/ready waits for
+    if let Ok(model) = env::var(embeddings_engine::DEFAULT_EMBEDDING_MODEL_ENV) {
+        tokio::task::spawn_blocking(move || match embeddings_engine::load_model(model.trim()) {
+            Ok(_) => tracing::info!("Loaded the default embedding model {}", model.trim()),
+            Err(e) => tracing::warn!("Failed to load the default embedding model: {}", e),
+        });
+    }
+
     let server_host = env::var("SERVER_HOST").unwrap_or_else(|_| DEFAULT_SERVER_HOST.to_string());
     let server_port = env::var("SERVER_PORT").unwrap_or_else(|_| DEFAULT_SERVER_PORT.to_string());
     let server_address = format!("{}:{}", server_host, server_port);
@@ -65,6 +67,7 @@ async fn main() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use async_openai::types::{CreateEmbeddingRequest, EmbeddingInput};
     use axum::body::Body;
     use axum::body::to_bytes;
     use axum::http::StatusCode;
@@ -108,6 +111,10 @@ mod tests {
         assert!(response_json["data"].is_array());
         assert_eq!(response_json["data"].as_array().unwrap().len(), 1);
         assert_eq!(response_json["model"], "nomic-text-embed");
+        // Counted with the model's tokenizer, [CLS] and [SEP] included
+        let prompt_tokens = response_json["usage"]["prompt_tokens"].as_u64().unwrap();
+        assert!(prompt_tokens > 2);
+        assert_eq!(response_json["usage"]["total_tokens"], prompt_tokens);
 
         let embedding_obj = &response_json["data"][0];
         assert_eq!(embedding_obj["object"], "embedding");
@@ -117,4 +124,154 @@ mod tests {
         let embedding = embedding_obj["embedding"].as_array().unwrap();
         assert_eq!(embedding.len(), 768);
     }
+
+    #[tokio::test]
+    async fn test_embeddings_batch_returns_one_object_per_input() {
+        // Distinct texts, so each index can be checked against a single-text request
+        let texts: Vec<String> = (0..120)
+            .map(|i| format!("Sentence number {i} about the weather"))
+            .collect();
+        let embed = |input: EmbeddingInput| {
+            let body = CreateEmbeddingRequest {
+                model: "nomic-text-embed".to_string(),
+                input,
+                encoding_format: None,
+                user: None,
+                dimensions: None,
+            };
+            create_app().oneshot(
+                axum::http::Request::builder()
+                    .method(axum::http::Method::POST)
+                    .uri("/v1/embeddings")
+                    .header("content-type", "application/json")
+                    .body(Body::from(serde_json::to_string(&body).unwrap()))
+                    .unwrap(),
+            )
+        };
+
+        let response = embed(EmbeddingInput::from(texts.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let batch: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let data = batch["data"].as_array().unwrap();
+        assert_eq!(data.len(), texts.len());
+        for (i, object) in data.iter().enumerate() {
+            assert_eq!(object["object"], "embedding");
+            assert_eq!(object["index"], i);
+            assert_eq!(object["embedding"].as_array().unwrap().len(), 768);
+        }
+        assert_ne!(data[0]["embedding"], data[119]["embedding"]);
+
+        // Each object is the embedding of the text at its index
+        let response = embed(EmbeddingInput::from(texts[57].clone()))
+            .await
+            .unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let single: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let distance: f64 = single["data"][0]["embedding"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .zip(data[57]["embedding"].as_array().unwrap())
+            .map(|(a, b)| (a.as_f64().unwrap() - b.as_f64().unwrap()).abs())
+            .fold(0.0, f64::max);
+        assert!(distance < 1e-4, "{distance}");
+    }
+
+    #[tokio::test]
+    async fn test_dimensions_shorten_nomic_embeddings() {
+        let body = CreateEmbeddingRequest {
+            model: "nomic-text-embed".to_string(),
+            input: EmbeddingInput::from(vec!["The food was delicious".to_string()]),
+            encoding_format: None,
+            user: None,
+            dimensions: Some(256),
+        };
+        let response = create_app()
+            .oneshot(
+                axum::http::Request::builder()
+                    .method(axum::http::Method::POST)
+                    .uri("/v1/embeddings")
+                    .header("content-type", "application/json")
+                    .body(Body::from(serde_json::to_string(&body).unwrap()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let response_json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let embedding: Vec<f64> = response_json["data"][0]["embedding"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|value| value.as_f64().unwrap())
+            .collect();
+        assert_eq!(embedding.len(), 256);
+        let norm = embedding.iter().map(|x| x * x).sum::<f64>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-4, "{norm}");
+    }
+
+    #[tokio::test]
+    async fn test_health_checks() {
+        for uri in ["/health/live", "/health/ready"] {
+            let response = create_app()
+                .oneshot(
+                    axum::http::Request::builder()
+                        .uri(uri)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            // Without DEFAULT_EMBEDDING_MODEL there's nothing to wait for
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_errors_use_the_json_envelope() {
+        let request = |body: &str| {
+            axum::http::Request::builder()
+                .method(axum::http::Method::POST)
+                .uri("/v1/embeddings")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        for (body, status, param) in [
+            (
+                r#"{"model": "no-such-model", "input": "text"}"#,
+                StatusCode::BAD_REQUEST,
+                Some("model"),
+            ),
+            (
+                r#"{"model": "nomic-text-embed", "input": []}"#,
+                StatusCode::BAD_REQUEST,
+                Some("input"),
+            ),
+            (
+                r#"{"model": "nomic-text-embed", "input": "text", "dimensions": 0}"#,
+                StatusCode::BAD_REQUEST,
+                Some("dimensions"),
+            ),
+            // BGE models weren't trained to be shortened
+            (
+                r#"{"model": "bge-small-en-v1.5", "input": "text", "dimensions": 128}"#,
+                StatusCode::BAD_REQUEST,
+                Some("dimensions"),
+            ),
+            (r#"{"model": 1}"#, StatusCode::UNPROCESSABLE_ENTITY, None),
+        ] {
+            let response = create_app().oneshot(request(body)).await.unwrap();
+            assert_eq!(response.status(), status);
+            assert_eq!(response.headers()["content-type"], "application/json");
+
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let error: openai_protocol::ErrorResponse = serde_json::from_slice(&body).unwrap();
+            assert!(!error.error.message.is_empty());
+            assert_eq!(error.error.param.as_deref(), param);
+        }
+    }
 }
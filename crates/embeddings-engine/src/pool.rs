@@ -0,0 +1,214 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use once_cell::sync::Lazy;
+use openai_protocol::ApiError;
+use tokio::sync::Semaphore;
+
+/// Environment variable setting how many embedding requests run at once; `0` runs every
+/// request as it arrives.
+pub const MAX_CONCURRENT_EMBEDDINGS_ENV: &str = "MAX_CONCURRENT_EMBEDDINGS";
+
+/// Environment variable setting how many embedding requests may wait for a free slot
+/// before more are turned away.
+pub const EMBEDDING_QUEUE_SIZE_ENV: &str = "EMBEDDING_QUEUE_SIZE";
+
+/// ONNX Runtime spreads each batch over the cores already, so a couple at once keep them
+/// busy without thrashing.
+const DEFAULT_MAX_CONCURRENT: usize = 2;
+const DEFAULT_QUEUE_SIZE: usize = 64;
+
+/// The pool [`crate::embeddings_create`] embeds in, sized from the environment.
+static POOL: Lazy<EmbeddingPool> = Lazy::new(EmbeddingPool::from_env);
+
+pub fn embedding_pool() -> &'static EmbeddingPool {
+    &POOL
+}
+
+/// Runs embedding work, which loads models and keeps a CPU busy for as long as a batch
+/// takes, on tokio's blocking threads rather than the async workers, so one large batch
+/// doesn't stall every other request. At most `max_concurrent` run at once and up to
+/// `max_queued` more wait their turn in arrival order; beyond that requests get a 429.
+///
+/// Clones share the slots.
+#[derive(Clone)]
+pub struct EmbeddingPool {
+    permits: Arc<Semaphore>,
+    max_concurrent: usize,
+    max_queued: usize,
+    queued: Arc<AtomicUsize>,
+    rejected: Arc<AtomicU64>,
+}
+
+impl EmbeddingPool {
+    pub fn new(max_concurrent: usize, max_queued: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(if max_concurrent == 0 {
+                Semaphore::MAX_PERMITS
+            } else {
+                max_concurrent
+            })),
+            max_concurrent,
+            max_queued,
+            queued: Arc::new(AtomicUsize::new(0)),
+            rejected: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// A pool sized by [`MAX_CONCURRENT_EMBEDDINGS_ENV`] (2 by default) and
+    /// [`EMBEDDING_QUEUE_SIZE_ENV`] (64 by default).
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let parse = |name: &str, default: usize| match var(name) {
+            Some(value) => value.trim().parse().unwrap_or_else(|_| {
+                tracing::warn!("Ignoring {}={}: expected a number", name, value);
+                default
+            }),
+            None => default,
+        };
+        Self::new(
+            parse(MAX_CONCURRENT_EMBEDDINGS_ENV, DEFAULT_MAX_CONCURRENT),
+            parse(EMBEDDING_QUEUE_SIZE_ENV, DEFAULT_QUEUE_SIZE),
+        )
+    }
+
+    /// Run `work` on a blocking thread once a slot is free, and return what it returns.
+    /// Fails with a 429 carrying `Retry-After` when the queue is full. Work that started
+    /// keeps its slot until it is done, even if the request is dropped, since a batch can't
+    /// be stopped halfway.
+    pub async fn run<T, F>(&self, work: F) -> Result<T, ApiError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let permit = match Arc::clone(&self.permits).try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                let reserved =
+                    self.queued
+                        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                            (queued < self.max_queued).then_some(queued + 1)
+                        });
+                if reserved.is_err() {
+                    self.rejected.fetch_add(1, Ordering::Relaxed);
+                    return Err(self.queue_full());
+                }
+                // Leaves the queue however the wait ends, including the client going away
+                let _slot = QueueSlot(&self.queued);
+                Arc::clone(&self.permits)
+                    .acquire_owned()
+                    .await
+                    .map_err(|_| ApiError::server_error("The embedding pool was closed"))?
+            }
+        };
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            work()
+        })
+        .await
+        .map_err(|e| ApiError::server_error(format!("Embedding task failed: {}", e)))
+    }
+
+    /// Requests waiting for a slot.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Requests turned away with a 429 since the pool was made.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    fn queue_full(&self) -> ApiError {
+        ApiError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limit_exceeded",
+            format!(
+                "The server is busy: {} embedding requests are running and {} waiting. Try again later.",
+                self.max_concurrent,
+                self.queued()
+            ),
+        )
+        .with_retry_after(Duration::from_secs(1))
+    }
+}
+
+struct QueueSlot<'a>(&'a AtomicUsize);
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_work_queues_then_is_turned_away() {
+        let pool = EmbeddingPool::new(1, 1);
+        let (release, released) = mpsc::channel::<()>();
+        let running = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(move || released.recv().is_ok()).await }
+        });
+        while pool.permits.available_permits() > 0 {
+            tokio::task::yield_now().await;
+        }
+
+        // The second request waits for the first one's slot
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(|| 2).await }
+        });
+        while pool.queued() == 0 {
+            tokio::task::yield_now().await;
+        }
+        let error = pool.run(|| 3).await.err().unwrap();
+        assert_eq!(error.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(error.retry_after, Some(Duration::from_secs(1)));
+        assert_eq!(pool.rejected(), 1);
+
+        release.send(()).unwrap();
+        assert!(running.await.unwrap().unwrap());
+        assert_eq!(waiting.await.unwrap().unwrap(), 2);
+        assert_eq!(pool.queued(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_a_request_that_gives_up_leaves_the_queue() {
+        let pool = EmbeddingPool::new(1, 1);
+        let (release, released) = mpsc::channel::<()>();
+        let running = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(move || released.recv().is_ok()).await }
+        });
+        while pool.permits.available_permits() > 0 {
+            tokio::task::yield_now().await;
+        }
+        let gave_up = tokio::time::timeout(Duration::from_millis(10), pool.run(|| ())).await;
+        assert!(gave_up.is_err());
+        assert_eq!(pool.queued(), 0);
+        release.send(()).unwrap();
+        running.await.unwrap().unwrap();
+
+        let unlimited = EmbeddingPool::from_vars(|name| {
+            (name == MAX_CONCURRENT_EMBEDDINGS_ENV).then(|| "0".to_string())
+        });
+        let mut running = tokio::task::JoinSet::new();
+        for i in 0..64 {
+            let pool = unlimited.clone();
+            running.spawn(async move { pool.run(move || i).await });
+        }
+        while let Some(result) = running.join_next().await {
+            assert!(result.unwrap().is_ok());
+        }
+    }
+}
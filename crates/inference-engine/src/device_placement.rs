@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+
+use runner_core::DeviceSpec;
+
+use crate::Which;
+
+/// Environment variable naming the device models load on: `auto` (the default), `cpu`,
+/// `cuda:<n>` or `metal:<n>`.
+pub const INFERENCE_DEVICE_ENV: &str = "INFERENCE_DEVICE";
+
+/// Environment variable pinning models to devices, as comma-separated `model=device`
+/// pairs, e.g. `gemma-3-1b-it=cuda:0,llama-3.2-3b-instruct=cuda:1`.
+pub const MODEL_DEVICES_ENV: &str = "MODEL_DEVICES";
+
+/// The device each model's runner loads it on: the model's pinned device, else the
+/// default. Pinning models to different GPUs spreads them over the GPUs of one process.
+///
+/// A model's weights are cached on the device that first loaded them, so a placement
+/// applies to models loaded after it is set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DevicePlacement {
+    default: DeviceSpec,
+    pinned: HashMap<Which, DeviceSpec>,
+}
+
+impl DevicePlacement {
+    /// Every model on `default`.
+    pub fn new(default: DeviceSpec) -> Self {
+        Self {
+            default,
+            pinned: HashMap::new(),
+        }
+    }
+
+    /// The placement in [`INFERENCE_DEVICE_ENV`] and [`MODEL_DEVICES_ENV`].
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// The placement in the variables `var` returns, e.g. the environment with a config
+    /// file's settings for the variables it doesn't set. Invalid entries are ignored with a
+    /// warning.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let default = match var(INFERENCE_DEVICE_ENV) {
+            Some(value) => value.parse().unwrap_or_else(|e| {
+                eprintln!(
+                    "Warning: ignoring {}={}: {}",
+                    INFERENCE_DEVICE_ENV, value, e
+                );
+                DeviceSpec::Auto
+            }),
+            None => DeviceSpec::Auto,
+        };
+        let mut placement = Self::new(default);
+        let pins = var(MODEL_DEVICES_ENV).unwrap_or_default();
+        for pin in pins.split(',').map(str::trim).filter(|pin| !pin.is_empty()) {
+            let parsed = pin
+                .split_once('=')
+                .ok_or_else(|| "expected model=device".to_string())
+                .and_then(|(model, device)| parse_pin(model, device));
+            match parsed {
+                Ok((which, device)) => placement = placement.with_model(which, device),
+                Err(e) => eprintln!(
+                    "Warning: ignoring {} entry {}: {}",
+                    MODEL_DEVICES_ENV, pin, e
+                ),
+            }
+        }
+        placement
+    }
+
+    /// Load `which` on `device` rather than the default.
+    pub fn with_model(mut self, which: Which, device: DeviceSpec) -> Self {
+        self.pinned.insert(which, device);
+        self
+    }
+
+    /// The device `which` loads on.
+    pub fn device(&self, which: Which) -> DeviceSpec {
+        self.pinned.get(&which).copied().unwrap_or(self.default)
+    }
+}
+
+/// A pin of `model` to `device`, as in [`MODEL_DEVICES_ENV`]. Fails for a model or device
+/// that isn't known.
+pub fn parse_pin(model: &str, device: &str) -> Result<(Which, DeviceSpec), String> {
+    let which = Which::from_public_id(model.trim())
+        .ok_or_else(|| format!("unknown model {}", model.trim()))?;
+    Ok((which, device.parse::<DeviceSpec>()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_models_load_on_their_pinned_device_or_the_default() {
+        let placement = DevicePlacement::from_vars(|name| match name {
+            INFERENCE_DEVICE_ENV => Some("cuda:0".to_string()),
+            MODEL_DEVICES_ENV => Some(
+                "llama-3.2-3b-instruct=cuda:1, gemma-3-1b-it = cpu,nope=cuda:2,qwen2.5-7b-instruct"
+                    .to_string(),
+            ),
+            _ => None,
+        });
+        assert_eq!(
+            placement.device(Which::Llama32_3BInstruct),
+            DeviceSpec::Cuda(1)
+        );
+        assert_eq!(placement.device(Which::InstructV3_1B), DeviceSpec::Cpu);
+        assert_eq!(
+            placement.device(Which::Qwen25_7BInstruct),
+            DeviceSpec::Cuda(0)
+        );
+
+        let unset = DevicePlacement::from_vars(|_| None);
+        assert_eq!(unset, DevicePlacement::default());
+        assert_eq!(unset.device(Which::InstructV3_1B), DeviceSpec::Auto);
+
+        let invalid = DevicePlacement::from_vars(|name| {
+            (name == INFERENCE_DEVICE_ENV).then(|| "gpu".to_string())
+        });
+        assert_eq!(invalid.device(Which::InstructV3_1B), DeviceSpec::Auto);
+    }
+}
@@ -0,0 +1,157 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use axum::Json;
+use axum::extract::State;
+use axum::http::StatusCode;
+use embeddings_engine::DEFAULT_EMBEDDING_MODEL_ENV;
+use tokio::task::JoinHandle;
+
+use crate::Which;
+use crate::openai_types::{ModelReadiness, ReadinessResponse};
+use crate::runners::{Sampling, loaded_context_length, pooled_runner};
+use crate::server::{AppState, model_id_to_which};
+
+/// What `/health/ready` waits for: the default chat model and, if one is set, the default
+/// embedding model. Once both have loaded the server stays ready, so evicting an idle
+/// model doesn't take it out of its Service; the next request loads the model again.
+///
+/// Clones share whether the server is ready, like the other parts of [`AppState`].
+#[derive(Clone, Default)]
+pub struct Readiness {
+    embedding_model: Option<String>,
+    ready: Arc<AtomicBool>,
+}
+
+impl Readiness {
+    /// Waiting for the embedding model in [`DEFAULT_EMBEDDING_MODEL_ENV`], if set.
+    pub fn from_env() -> Self {
+        Self::default().with_embedding_model(std::env::var(DEFAULT_EMBEDDING_MODEL_ENV).ok())
+    }
+
+    /// Also wait for `model`: a FastEmbed model or a chat model's embedding id.
+    pub fn with_embedding_model(mut self, model: Option<String>) -> Self {
+        self.embedding_model = model
+            .map(|model| model.trim().to_string())
+            .filter(|model| !model.is_empty());
+        self
+    }
+
+    pub fn embedding_model(&self) -> Option<&str> {
+        self.embedding_model.as_deref()
+    }
+}
+
+/// Whether a runner or the runner crate's cache holds `which`.
+fn chat_model_loaded(state: &AppState, which: Which) -> bool {
+    state.runners.metadata(which).is_some() || loaded_context_length(which).is_some()
+}
+
+fn embedding_model_loaded(state: &AppState, id: &str) -> bool {
+    match Which::from_embedding_id(id) {
+        Some(which) => chat_model_loaded(state, which),
+        None => embeddings_engine::parse_embedding_model(id)
+            .is_ok_and(|model| embeddings_engine::is_model_loaded(&model)),
+    }
+}
+
+/// Liveness check: the server answers requests
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "health",
+    responses((status = 200, description = "The server is up", body = String, content_type = "text/plain"))
+)]
+pub async fn live() -> &'static str {
+    "ok"
+}
+
+/// Readiness check: the default chat model, and the default embedding model if one is
+/// set, are loaded, so requests for them don't wait for their weights
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    responses(
+        (status = 200, description = "The models are loaded", body = ReadinessResponse),
+        (status = 503, description = "A model is still loading", body = ReadinessResponse)
+    )
+)]
+pub async fn ready(State(state): State<AppState>) -> (StatusCode, Json<ReadinessResponse>) {
+    let mut models = vec![ModelReadiness {
+        id: state.model_id.clone(),
+        loaded: model_id_to_which(&state.model_id)
+            .is_some_and(|which| chat_model_loaded(&state, which)),
+    }];
+    if let Some(id) = state.readiness.embedding_model() {
+        models.push(ModelReadiness {
+            id: id.to_string(),
+            loaded: embedding_model_loaded(&state, id),
+        });
+    }
+
+    let latched = &state.readiness.ready;
+    if models.iter().all(|model| model.loaded) {
+        latched.store(true, Ordering::Relaxed);
+    }
+    let ready = latched.load(Ordering::Relaxed);
+    let response = ReadinessResponse {
+        status: if ready { "ready" } else { "loading" }.to_string(),
+        models,
+    };
+    if ready {
+        (StatusCode::OK, Json(response))
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, Json(response))
+    }
+}
+
+/// Load the models readiness waits for in the background, so the server turns ready
+/// without waiting for their first requests. A model that fails to load keeps the server
+/// unready and is logged.
+pub fn spawn_readiness_preload(state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let start = std::time::Instant::now();
+        let chat_state = state.clone();
+        let chat = tokio::task::spawn_blocking(move || {
+            let which = model_id_to_which(&chat_state.model_id)
+                .ok_or_else(|| format!("unsupported model {}", chat_state.model_id))?;
+            pooled_runner(which, &chat_state, Sampling::default())
+                .map(drop)
+                .map_err(|e| e.to_string())
+        })
+        .await;
+        match chat {
+            Ok(Ok(())) => tracing::info!(
+                "Loaded the default model {} in {:.0?}",
+                state.model_id,
+                start.elapsed()
+            ),
+            Ok(Err(e)) => tracing::warn!("Failed to load the default model: {}", e),
+            Err(e) => tracing::warn!("Failed to load the default model: {}", e),
+        }
+
+        let Some(id) = state.readiness.embedding_model().map(str::to_string) else {
+            return;
+        };
+        let start = std::time::Instant::now();
+        let embedding_state = state.clone();
+        let loaded_id = id.clone();
+        let embedding = tokio::task::spawn_blocking(move || match Which::from_embedding_id(&id) {
+            Some(which) => pooled_runner(which, &embedding_state, Sampling::default())
+                .map(drop)
+                .map_err(|e| e.to_string()),
+            None => embeddings_engine::load_model(&id).map(drop),
+        })
+        .await;
+        match embedding {
+            Ok(Ok(())) => tracing::info!(
+                "Loaded the default embedding model {} in {:.0?}",
+                loaded_id,
+                start.elapsed()
+            ),
+            Ok(Err(e)) => tracing::warn!("Failed to load the default embedding model: {}", e),
+            Err(e) => tracing::warn!("Failed to load the default embedding model: {}", e),
+        }
+    })
+}
@@ -1,14 +1,36 @@
 // Expose modules for testing and library usage
+pub mod device_placement;
+pub mod health;
 pub mod model;
 pub mod openai_types;
+pub mod openapi;
 // pub mod cli;
 pub mod inference;
+pub mod log_level;
+pub mod memory;
+pub mod preload;
+pub mod runner_pool;
+pub mod runners;
+pub mod scheduler;
 pub mod server;
+pub mod streams;
+pub mod tools;
+pub mod warm_pool;
 
 // Re-export key components for easier access
+pub use device_placement::DevicePlacement;
+pub use health::{Readiness, spawn_readiness_preload};
 pub use inference::ModelInference;
+pub use log_level::{LogLevel, create_log_level_router};
+pub use memory::{MemoryMonitorConfig, spawn_memory_monitor};
 pub use model::{Model, Which};
+pub use openapi::ApiDoc;
+pub use preload::{preload_models_from_env, spawn_preload};
+pub use runner_pool::RunnerPool;
+pub use scheduler::InferenceScheduler;
 pub use server::{AppState, create_router};
+pub use streams::ActiveStreams;
+pub use warm_pool::{WarmPoolConfig, spawn_warm_pool};
 
 use std::env;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
@@ -25,18 +47,16 @@ pub fn get_server_config() -> (String, String, String) {
     (server_host, server_port, server_address)
 }
 
-/// Initialize tracing with configurable log levels
-pub fn init_tracing() {
+/// Initialize tracing with configurable log levels. The returned [`LogLevel`] changes
+/// them while the server runs.
+pub fn init_tracing() -> LogLevel {
+    let (filter, log_level) = LogLevel::from_env_or(&format!(
+        "{}=debug,tower_http=debug,axum::rejection=trace",
+        env!("CARGO_CRATE_NAME")
+    ));
     tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                format!(
-                    "{}=debug,tower_http=debug,axum::rejection=trace",
-                    env!("CARGO_CRATE_NAME")
-                )
-                .into()
-            }),
-        )
+        .with(filter)
         .with(tracing_subscriber::fmt::layer())
         .init();
+    log_level
 }
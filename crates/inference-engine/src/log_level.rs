@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use axum::extract::State;
+use axum::extract::rejection::JsonRejection;
+use axum::routing::get;
+use axum::{Json, Router};
+use openai_protocol::ApiError;
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::{EnvFilter, Registry, reload};
+use utoipa::ToSchema;
+
+/// The log filter of a running server, and the handle that swaps it.
+///
+/// [`LogLevel::from_env_or`] makes the filter layer to build the subscriber on;
+/// [`create_log_level_router`] serves `/admin/log_level` to read and replace it, so debug
+/// logging can be turned on without a restart that would drop the loaded models.
+#[derive(Clone)]
+pub struct LogLevel {
+    handle: reload::Handle<EnvFilter, Registry>,
+    directives: Arc<RwLock<String>>,
+}
+
+impl LogLevel {
+    /// A reloadable filter from `RUST_LOG`, or from `default` if it's unset or doesn't
+    /// parse. Put the layer directly on `tracing_subscriber::registry()`.
+    pub fn from_env_or(default: &str) -> (reload::Layer<EnvFilter, Registry>, Self) {
+        let directives = std::env::var(EnvFilter::DEFAULT_ENV)
+            .ok()
+            .filter(|directives| EnvFilter::try_new(directives).is_ok())
+            .unwrap_or_else(|| default.to_string());
+        let (layer, handle) = reload::Layer::new(EnvFilter::new(&directives));
+        let log_level = Self {
+            handle,
+            directives: Arc::new(RwLock::new(directives)),
+        };
+        (layer, log_level)
+    }
+
+    /// The filter in use, in `RUST_LOG` syntax.
+    pub fn current(&self) -> String {
+        self.directives
+            .read()
+            .map(|directives| directives.clone())
+            .unwrap_or_default()
+    }
+
+    /// Replace the filter with `directives`, in `RUST_LOG` syntax.
+    pub fn set(&self, directives: &str) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directives)
+            .map_err(|e| format!("Invalid log filter '{}': {}", directives, e))?;
+        self.handle
+            .reload(filter)
+            .map_err(|e| format!("Failed to swap the log filter: {}", e))?;
+        if let Ok(mut current) = self.directives.write() {
+            *current = directives.to_string();
+        }
+        Ok(())
+    }
+}
+
+/// A new log filter: either a whole `RUST_LOG`-style `filter`, or a default `level` and
+/// levels for individual `targets`. Either way it replaces the filter in use.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct LogLevelRequest {
+    /// A filter in `RUST_LOG` syntax, e.g. `info,inference_engine=debug`
+    #[schema(example = "info,inference_engine=debug")]
+    pub filter: Option<String>,
+    /// The level of every target without one of its own, e.g. `info`
+    #[schema(example = "info")]
+    pub level: Option<String>,
+    /// Levels by target (module path), e.g. `{"inference_engine": "debug"}`
+    #[serde(default)]
+    pub targets: BTreeMap<String, String>,
+}
+
+impl LogLevelRequest {
+    /// The request as `RUST_LOG` directives.
+    fn directives(&self) -> Result<String, ApiError> {
+        if let Some(filter) = &self.filter {
+            if self.level.is_some() || !self.targets.is_empty() {
+                return Err(ApiError::invalid_request(
+                    "Send either 'filter' or 'level' and 'targets', not both",
+                )
+                .with_param("filter"));
+            }
+            let filter = filter.trim();
+            EnvFilter::try_new(filter).map_err(|e| {
+                ApiError::invalid_request(format!("Invalid log filter '{}': {}", filter, e))
+                    .with_param("filter")
+            })?;
+            return Ok(filter.to_string());
+        }
+
+        let mut directives = Vec::new();
+        if let Some(level) = &self.level {
+            directives.push(parse_level(level).map_err(|e| e.with_param("level"))?);
+        }
+        for (target, level) in &self.targets {
+            if target.is_empty() || target.contains([',', '=', '[', ']', ' ']) {
+                return Err(
+                    ApiError::invalid_request(format!("Invalid log target '{}'", target))
+                        .with_param("targets"),
+                );
+            }
+            let level = parse_level(level).map_err(|e| e.with_param("targets"))?;
+            directives.push(format!("{}={}", target, level));
+        }
+        if directives.is_empty() {
+            return Err(
+                ApiError::invalid_request("Send a 'filter', or a 'level' or 'targets'")
+                    .with_param("filter"),
+            );
+        }
+        Ok(directives.join(","))
+    }
+}
+
+/// A level name, e.g. `debug` or `off`. Checked on its own, since a bare word that isn't a
+/// level is a valid directive too: it names a target.
+fn parse_level(level: &str) -> Result<String, ApiError> {
+    let level = level.trim();
+    level
+        .parse::<LevelFilter>()
+        .map(|_| level.to_lowercase())
+        .map_err(|_| {
+            ApiError::invalid_request(format!(
+                "Invalid log level '{}': expected off, error, warn, info, debug or trace",
+                level
+            ))
+        })
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LogLevelResponse {
+    /// The filter in use, in `RUST_LOG` syntax
+    #[schema(example = "info,inference_engine=debug")]
+    pub filter: String,
+}
+
+/// Handler for GET /admin/log_level - reports the log filter in use
+#[utoipa::path(
+    get,
+    path = "/admin/log_level",
+    tag = "admin",
+    responses(
+        (status = 200, description = "The log filter in use", body = LogLevelResponse)
+    )
+)]
+pub async fn get_log_level(State(log_level): State<LogLevel>) -> Json<LogLevelResponse> {
+    Json(LogLevelResponse {
+        filter: log_level.current(),
+    })
+}
+
+/// Handler for PUT /admin/log_level - replaces the log filter without a restart
+#[utoipa::path(
+    put,
+    path = "/admin/log_level",
+    tag = "admin",
+    request_body = LogLevelRequest,
+    responses(
+        (status = 200, description = "The log filter now in use", body = LogLevelResponse),
+        (status = 400, description = "The filter, a level or a target doesn't parse", body = ErrorResponse),
+        (status = 500, description = "The server's logging can't be reconfigured", body = ErrorResponse)
+    )
+)]
+pub async fn set_log_level(
+    State(log_level): State<LogLevel>,
+    payload: Result<Json<LogLevelRequest>, JsonRejection>,
+) -> Result<Json<LogLevelResponse>, ApiError> {
+    let Json(request) = payload?;
+    let directives = request.directives()?;
+    log_level.set(&directives).map_err(ApiError::server_error)?;
+    tracing::info!("Log filter set to '{}'", directives);
+    Ok(Json(LogLevelResponse { filter: directives }))
+}
+
+/// `/admin/log_level`, reading and replacing `log_level`'s filter.
+pub fn create_log_level_router(log_level: LogLevel) -> Router {
+    Router::new()
+        .route("/admin/log_level", get(get_log_level).put(set_log_level))
+        .with_state(log_level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(json: serde_json::Value) -> LogLevelRequest {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_request_directives() {
+        assert_eq!(
+            request(serde_json::json!({"filter": " warn,tower_http=debug "}))
+                .directives()
+                .unwrap(),
+            "warn,tower_http=debug"
+        );
+        assert_eq!(
+            request(serde_json::json!({
+                "level": "info",
+                "targets": {"inference_engine": "debug", "gemma_runner": "trace"}
+            }))
+            .directives()
+            .unwrap(),
+            "info,gemma_runner=trace,inference_engine=debug"
+        );
+
+        for invalid in [
+            serde_json::json!({}),
+            serde_json::json!({"filter": "info", "level": "debug"}),
+            serde_json::json!({"targets": {"a,b": "debug"}}),
+            serde_json::json!({"targets": {"a=trace": "debug"}}),
+            serde_json::json!({"targets": {"inference_engine": "loud"}}),
+            serde_json::json!({"filter": "inference_engine=loud"}),
+        ] {
+            assert!(request(invalid.clone()).directives().is_err(), "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_setting_the_log_level() {
+        // The layer owns the filter; the handle only reaches it while the layer lives
+        let (_layer, log_level) = LogLevel::from_env_or("info");
+
+        let Json(response) = set_log_level(
+            State(log_level.clone()),
+            Ok(Json(request(serde_json::json!({
+                "level": "info",
+                "targets": {"inference_engine": "debug"}
+            })))),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.filter, "info,inference_engine=debug");
+        assert_eq!(
+            get_log_level(State(log_level.clone())).await.filter,
+            "info,inference_engine=debug"
+        );
+
+        // A filter that doesn't parse leaves the current one in place
+        let error = set_log_level(
+            State(log_level.clone()),
+            Ok(Json(request(serde_json::json!({"level": "loud"})))),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status, axum::http::StatusCode::BAD_REQUEST);
+        assert_eq!(error.body.error.param.as_deref(), Some("level"));
+        assert_eq!(log_level.current(), "info,inference_engine=debug");
+    }
+}
@@ -1,13 +1,39 @@
-use inference_engine::{AppState, create_router, get_server_config, init_tracing};
+use inference_engine::{
+    AppState, MemoryMonitorConfig, WarmPoolConfig, create_log_level_router, create_router,
+    get_server_config, health, init_tracing, preload_models_from_env, spawn_memory_monitor,
+    spawn_preload, spawn_readiness_preload, spawn_warm_pool,
+};
+use openai_protocol::trace_context::propagate_trace;
 use tokio::net::TcpListener;
 use tracing::info;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    init_tracing();
+    let log_level = init_tracing();
 
     let app_state = AppState::default();
-    let app = create_router(app_state);
+    // Drop idle models when memory runs short
+    spawn_memory_monitor(MemoryMonitorConfig::from_env(), app_state.runners.clone());
+
+    // Keep the configured models warm and evict idle ones
+    let warm_pool = WarmPoolConfig::from_env();
+    if warm_pool.is_enabled() {
+        spawn_warm_pool(warm_pool, app_state.clone());
+    }
+    // Load the PRELOAD_MODELS before their first requests
+    let preload = preload_models_from_env();
+    if !preload.is_empty() {
+        spawn_preload(preload, app_state.clone());
+    }
+    // Load the default models, which /health/ready waits for
+    spawn_readiness_preload(app_state.clone());
+    // Behind the gateway, requests carry its trace context and its body limit applies;
+    // the gateway adds the layers itself when it runs the router in process
+    let app = create_router(app_state)
+        .route("/health/live", axum::routing::get(health::live))
+        .merge(create_log_level_router(log_level))
+        .layer(axum::extract::DefaultBodyLimit::disable())
+        .layer(axum::middleware::from_fn(propagate_trace));
 
     let (server_host, server_port, server_address) = get_server_config();
     let listener = TcpListener::bind(&server_address).await?;
@@ -19,6 +45,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("Available endpoints:");
     info!("  POST /v1/chat/completions - OpenAI-compatible chat completions");
     info!("  GET  /v1/models         - List available models");
+    info!("  POST /v1/models/{{id}}/load - Load a model (DELETE /v1/models/{{id}} unloads it)");
+    info!("  POST /v1/embeddings     - Embeddings (fastembed or mean-pooled decoder models)");
+    info!("  GET  /admin/device      - Report CPU features and GPUs");
+    info!("  GET  /admin/status      - Loaded models and the warm pool");
+    info!("  GET  /admin/log_level   - Show the log filter (PUT to change it)");
+    info!("  GET  /health/live       - Liveness check");
+    info!("  GET  /health/ready      - 503 until the default models are loaded");
 
     axum::serve(listener, app).await?;
 
@@ -0,0 +1,480 @@
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use embeddings_engine::EmbeddingModel;
+use runner_core::{CacheKey, MemorySample};
+use tokio::task::JoinHandle;
+
+use crate::runner_pool::RunnerPool;
+
+/// The latest sample the monitor took, for the gateway's metrics summary.
+static LAST_SAMPLE: RwLock<Option<MemorySample>> = RwLock::new(None);
+
+/// How often the memory monitor samples, and the usage above which it evicts idle models.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryMonitorConfig {
+    /// Time between samples.
+    pub interval: Duration,
+    /// Evict while the process's resident memory is above this many bytes.
+    pub resident_limit_bytes: Option<u64>,
+    /// Evict while the fullest CUDA device has more than this share of its memory in use.
+    pub device_limit_fraction: Option<f64>,
+    /// Evict while more than this many chat models are loaded.
+    pub max_loaded_models: Option<usize>,
+}
+
+impl Default for MemoryMonitorConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(10),
+            resident_limit_bytes: None,
+            device_limit_fraction: None,
+            max_loaded_models: None,
+        }
+    }
+}
+
+impl MemoryMonitorConfig {
+    /// Read `MEMORY_SAMPLE_SECS`, `MEMORY_LIMIT_MB`, `GPU_MEMORY_LIMIT_PERCENT` and
+    /// `MAX_LOADED_MODELS`. Without a limit the monitor only samples.
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let parse = |name: &str| {
+            let value = var(name)?;
+            match value.trim().parse::<f64>() {
+                Ok(number) if number > 0.0 => Some(number),
+                _ => {
+                    tracing::warn!("Ignoring {}={}: expected a positive number", name, value);
+                    None
+                }
+            }
+        };
+        let defaults = Self::default();
+        Self {
+            interval: parse("MEMORY_SAMPLE_SECS")
+                .map(Duration::from_secs_f64)
+                .unwrap_or(defaults.interval),
+            resident_limit_bytes: parse("MEMORY_LIMIT_MB").map(|mb| (mb * 1024.0 * 1024.0) as u64),
+            device_limit_fraction: parse("GPU_MEMORY_LIMIT_PERCENT").map(|percent| percent / 100.0),
+            max_loaded_models: parse("MAX_LOADED_MODELS").map(|count| count.max(1.0) as usize),
+        }
+    }
+
+    /// Why `sample` is over a limit, if it is.
+    pub fn pressure(&self, sample: &MemorySample) -> Option<String> {
+        if let (Some(limit), Some(resident)) = (self.resident_limit_bytes, sample.resident_bytes)
+            && resident > limit
+        {
+            return Some(format!(
+                "resident memory {} MiB is over the {} MiB limit",
+                resident >> 20,
+                limit >> 20
+            ));
+        }
+        if let (Some(limit), Some(used)) =
+            (self.device_limit_fraction, sample.device_used_fraction())
+            && used > limit
+        {
+            return Some(format!(
+                "GPU memory {:.0}% in use is over the {:.0}% limit",
+                used * 100.0,
+                limit * 100.0
+            ));
+        }
+        None
+    }
+}
+
+/// A loaded model no request is using, in the cache of the runner or engine serving it.
+#[derive(Debug, Clone)]
+pub(crate) enum IdleModel {
+    Gemma(CacheKey),
+    Llama(CacheKey),
+    Model(CacheKey),
+    Embedding(EmbeddingModel),
+}
+
+impl IdleModel {
+    pub(crate) fn evict(&self) -> bool {
+        match self {
+            IdleModel::Gemma(key) => gemma_runner::evict_idle_model(key),
+            IdleModel::Llama(key) => llama_runner::evict_idle_model(key),
+            IdleModel::Model(key) => model_runner::evict_idle_model(key),
+            IdleModel::Embedding(model) => embeddings_engine::evict_idle_model(model),
+        }
+    }
+
+    pub(crate) fn name(&self) -> String {
+        match self {
+            IdleModel::Gemma(key) | IdleModel::Llama(key) | IdleModel::Model(key) => {
+                key.to_string()
+            }
+            IdleModel::Embedding(model) => format!("{:?}", model),
+        }
+    }
+}
+
+/// Every idle model of the inference and embeddings caches with when it was last used,
+/// least recently used first.
+pub(crate) fn idle_models_by_last_use() -> Vec<(IdleModel, Instant)> {
+    let mut idle: Vec<(IdleModel, Instant)> = gemma_runner::idle_models()
+        .into_iter()
+        .map(|(key, last_used)| (IdleModel::Gemma(key), last_used))
+        .chain(
+            llama_runner::idle_models()
+                .into_iter()
+                .map(|(key, last_used)| (IdleModel::Llama(key), last_used)),
+        )
+        .chain(
+            model_runner::idle_models()
+                .into_iter()
+                .map(|(key, last_used)| (IdleModel::Model(key), last_used)),
+        )
+        .chain(
+            embeddings_engine::idle_models()
+                .into_iter()
+                .map(|(model, last_used)| (IdleModel::Embedding(model), last_used)),
+        )
+        .collect();
+    idle.sort_by_key(|(_, last_used)| *last_used);
+    idle
+}
+
+/// Number of chat models in the runner crates' caches, in use or not.
+fn loaded_chat_models() -> usize {
+    gemma_runner::cached_models().len()
+        + llama_runner::cached_models().len()
+        + model_runner::cached_models().len()
+}
+
+/// The memory sample the monitor took last, `None` before it first runs.
+pub fn last_memory_sample() -> Option<MemorySample> {
+    LAST_SAMPLE.read().ok()?.clone()
+}
+
+fn take_sample() -> MemorySample {
+    let sample = MemorySample::take();
+    if let Ok(mut last) = LAST_SAMPLE.write() {
+        *last = Some(sample.clone());
+    }
+    sample
+}
+
+/// Sample memory and, while it's over a limit of `config`, evict idle models least
+/// recently used first. Models a request is using stay loaded; those only `runners` holds
+/// are released first. Returns the names of the evicted models.
+pub fn relieve_memory_pressure(config: &MemoryMonitorConfig, runners: &RunnerPool) -> Vec<String> {
+    let mut sample = take_sample();
+    tracing::debug!("Memory: {}", sample);
+
+    let mut evicted = Vec::new();
+    if config.pressure(&sample).is_none() {
+        return evicted;
+    }
+    runners.release_idle(Duration::ZERO);
+    let mut candidates = idle_models_by_last_use()
+        .into_iter()
+        .map(|(model, _)| model);
+    while let Some(reason) = config.pressure(&sample) {
+        let Some(model) = candidates.next() else {
+            tracing::warn!(
+                "Memory pressure ({}), but no idle model is left to evict",
+                reason
+            );
+            break;
+        };
+        if !model.evict() {
+            // Picked up by a request since it was listed
+            continue;
+        }
+        sample = take_sample();
+        tracing::warn!(
+            "Memory pressure ({}): evicted idle model {}, now {}",
+            reason,
+            model.name(),
+            sample
+        );
+        evicted.push(model.name());
+    }
+    evicted
+}
+
+/// While more than `max_loaded` chat models are loaded, evict the idle ones least recently
+/// used first. Models a request is using stay loaded, even above the limit. Models only
+/// `runners` holds go after the others, their pooled runners released least recently used
+/// first and only as many as it takes. Returns the names of the evicted models.
+pub fn enforce_model_limit(max_loaded: usize, runners: &RunnerPool) -> Vec<String> {
+    let loaded = loaded_chat_models();
+    let evicted = evict_over_limit(
+        max_loaded,
+        loaded,
+        || {
+            idle_models_by_last_use()
+                .into_iter()
+                .map(|(model, _)| model)
+                .filter(|model| !matches!(model, IdleModel::Embedding(_)))
+                .collect()
+        },
+        |model| model.evict(),
+        || runners.release_least_recently_used(),
+    );
+    for (i, model) in evicted.iter().enumerate() {
+        tracing::info!(
+            "{} chat models loaded, over the limit of {}: evicted least recently used {}",
+            loaded - i,
+            max_loaded,
+            model.name()
+        );
+    }
+    if loaded - evicted.len() > max_loaded {
+        tracing::debug!(
+            "{} chat models loaded, over the limit of {}, but the rest are in use",
+            loaded - evicted.len(),
+            max_loaded
+        );
+    }
+    evicted.iter().map(IdleModel::name).collect()
+}
+
+/// The eviction of [`enforce_model_limit`], over caches reached through `idle_models`
+/// (least recently used first), `evict` and `release_runner`, which drops one pooled
+/// runner and returns whether there was one to drop.
+fn evict_over_limit<M>(
+    max_loaded: usize,
+    mut loaded: usize,
+    idle_models: impl Fn() -> Vec<M>,
+    evict: impl Fn(&M) -> bool,
+    release_runner: impl Fn() -> bool,
+) -> Vec<M> {
+    let mut evicted = Vec::new();
+    while loaded > max_loaded {
+        for model in idle_models() {
+            if loaded <= max_loaded {
+                break;
+            }
+            // False for one a request picked up since it was listed
+            if evict(&model) {
+                loaded -= 1;
+                evicted.push(model);
+            }
+        }
+        // The rest are held by pooled runners or requests: let one runner go and look again
+        if loaded <= max_loaded || !release_runner() {
+            break;
+        }
+    }
+    evicted
+}
+
+/// Run [`relieve_memory_pressure`] and [`enforce_model_limit`] every `config.interval` in
+/// the background, so idle models are dropped before the OOM killer ends the process.
+pub fn spawn_memory_monitor(config: MemoryMonitorConfig, runners: RunnerPool) -> JoinHandle<()> {
+    tracing::info!(
+        "Sampling memory every {:.0?}; resident limit: {}, GPU limit: {}, model limit: {}",
+        config.interval,
+        config
+            .resident_limit_bytes
+            .map_or("none".to_string(), |limit| format!("{} MiB", limit >> 20)),
+        config
+            .device_limit_fraction
+            .map_or("none".to_string(), |limit| format!("{:.0}%", limit * 100.0)),
+        config
+            .max_loaded_models
+            .map_or("none".to_string(), |limit| limit.to_string()),
+    );
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(config.interval);
+        loop {
+            interval.tick().await;
+            let (config, runners) = (config.clone(), runners.clone());
+            // Sampling reads /proc and probes GPUs, and dropping a model frees gigabytes
+            let _ = tokio::task::spawn_blocking(move || {
+                relieve_memory_pressure(&config, &runners);
+                if let Some(max_loaded) = config.max_loaded_models {
+                    enforce_model_limit(max_loaded, &runners);
+                }
+            })
+            .await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Which;
+    use crate::runners::Sampling;
+    use runner_core::{
+        GenerationRequest, GpuReport, ModelCache, ModelRunner, RunnerError, RunnerMetadata,
+        TokenReceiver,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn test_config_from_vars() {
+        let config = MemoryMonitorConfig::from_vars(|name| match name {
+            "MEMORY_SAMPLE_SECS" => Some("2".to_string()),
+            "MEMORY_LIMIT_MB" => Some("4096".to_string()),
+            "GPU_MEMORY_LIMIT_PERCENT" => Some("nope".to_string()),
+            "MAX_LOADED_MODELS" => Some("3".to_string()),
+            _ => None,
+        });
+        assert_eq!(
+            config,
+            MemoryMonitorConfig {
+                interval: Duration::from_secs(2),
+                resident_limit_bytes: Some(4 << 30),
+                device_limit_fraction: None,
+                max_loaded_models: Some(3),
+            }
+        );
+        assert_eq!(
+            MemoryMonitorConfig::from_vars(|_| None),
+            MemoryMonitorConfig::default()
+        );
+    }
+
+    #[test]
+    fn test_pressure() {
+        let sample = MemorySample {
+            resident_bytes: Some(3 << 30),
+            devices: vec![GpuReport {
+                ordinal: 0,
+                name: "test".to_string(),
+                total_memory: 8 << 30,
+                free_memory: Some(1 << 30),
+            }],
+        };
+        let unlimited = MemoryMonitorConfig::default();
+        assert_eq!(unlimited.pressure(&sample), None);
+
+        let resident = MemoryMonitorConfig {
+            resident_limit_bytes: Some(2 << 30),
+            ..MemoryMonitorConfig::default()
+        };
+        assert!(
+            resident
+                .pressure(&sample)
+                .unwrap()
+                .contains("2048 MiB limit")
+        );
+
+        let device = MemoryMonitorConfig {
+            device_limit_fraction: Some(0.9),
+            ..MemoryMonitorConfig::default()
+        };
+        assert_eq!(device.pressure(&sample), None);
+        let device = MemoryMonitorConfig {
+            device_limit_fraction: Some(0.8),
+            ..MemoryMonitorConfig::default()
+        };
+        assert!(device.pressure(&sample).unwrap().contains("88% in use"));
+    }
+
+    #[test]
+    fn test_relieving_pressure_stops_without_idle_models() {
+        // Always over the limit, with nothing loaded to evict
+        let config = MemoryMonitorConfig {
+            resident_limit_bytes: Some(1),
+            ..MemoryMonitorConfig::default()
+        };
+        assert!(relieve_memory_pressure(&config, &RunnerPool::default()).is_empty());
+        assert!(last_memory_sample().is_some());
+    }
+
+    /// A runner holding one model of a test cache, as real runners hold their weights.
+    struct HoldingRunner {
+        metadata: RunnerMetadata,
+        _model: Arc<u32>,
+    }
+
+    impl ModelRunner for HoldingRunner {
+        type Config = Arc<u32>;
+
+        fn load(model: Arc<u32>) -> Result<Self, RunnerError> {
+            Ok(Self {
+                metadata: RunnerMetadata {
+                    model_id: model.to_string(),
+                    repo_id: model.to_string(),
+                    family: "test".to_string(),
+                    owned_by: "test".to_string(),
+                    context_length: 16,
+                    vocab_size: 1,
+                    parameter_count: 0,
+                    dtype: "f32".to_string(),
+                    device: "cpu".to_string(),
+                },
+                _model: model,
+            })
+        }
+
+        fn generate_stream(&self, _: GenerationRequest) -> Result<TokenReceiver, RunnerError> {
+            Err(RunnerError::InvalidRequest("holding".to_string()))
+        }
+
+        fn metadata(&self) -> &RunnerMetadata {
+            &self.metadata
+        }
+
+        fn cancel(&self) {}
+    }
+
+    #[test]
+    fn test_model_limit_evicts_least_recently_used_idle_models() {
+        let cache = ModelCache::new();
+        let runners = RunnerPool::new(4);
+        let key = |name: &str| CacheKey::new(name, "f32", "Cpu");
+        let load = |name: &str, id: u32| {
+            // Spaced out so the models' last uses are ordered
+            std::thread::sleep(Duration::from_millis(2));
+            cache.get_or_load(&key(name), || Ok(id)).unwrap()
+        };
+        let pool = |which: Which, model: Arc<u32>| {
+            runners
+                .get_or_load(which, Sampling::default(), || {
+                    Ok(Box::new(HoldingRunner::load(model)?))
+                })
+                .unwrap();
+        };
+
+        // Oldest first: an idle model, one a request is using, two only the pool holds
+        drop(load("idle", 0));
+        let in_use = load("in-use", 1);
+        pool(Which::Base2B, load("pooled-old", 2));
+        pool(Which::Instruct2B, load("pooled-new", 3));
+
+        let evict = |max_loaded| {
+            let evicted = evict_over_limit(
+                max_loaded,
+                cache.keys().len(),
+                || {
+                    let mut idle = cache.idle();
+                    idle.sort_by_key(|(_, last_used)| *last_used);
+                    idle.into_iter().map(|(key, _)| key).collect()
+                },
+                |key| cache.evict_if_idle(key),
+                || runners.release_least_recently_used(),
+            );
+            evicted
+                .iter()
+                .map(|key| key.model_id.clone())
+                .collect::<Vec<_>>()
+        };
+
+        // The idle model goes first, then the older pooled one, its runner released; the
+        // newer pooled runner is kept, as two models are within the limit
+        assert_eq!(evict(2), ["idle", "pooled-old"]);
+        assert_eq!(runners.len(), 1);
+        assert!(runners.metadata(Which::Instruct2B).is_some());
+
+        // The model in use stays loaded even over the limit
+        assert_eq!(evict(0), ["pooled-new"]);
+        assert!(runners.is_empty());
+        assert_eq!(cache.keys(), [key("in-use")]);
+        drop(in_use);
+        assert_eq!(evict(0), ["in-use"]);
+    }
+}
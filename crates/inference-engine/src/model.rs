@@ -2,6 +2,7 @@ use candle_transformers::models::csm::{LlamaConfig, LlamaModel};
 use candle_transformers::models::gemma::{Config as Config1, Model as Model1};
 use candle_transformers::models::gemma2::{Config as Config2, Model as Model2};
 use candle_transformers::models::gemma3::{Config as Config3, Model as Model3};
+use clap::ValueEnum;
 
 #[derive(Clone, Debug)]
 pub enum Model {
@@ -26,12 +27,33 @@ impl Model {
     }
 }
 
+/// Appended to a model's public id to request embeddings from it instead of completions.
+pub const EMBEDDING_ID_SUFFIX: &str = "-embed";
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Family {
     GemmaV1,
     GemmaV2,
     GemmaV3,
     Llama,
+    Mistral,
+    Qwen2,
+    Phi3,
+}
+
+impl Family {
+    /// Name reported in the model list, e.g. `gemma3`.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::GemmaV1 => "gemma",
+            Self::GemmaV2 => "gemma2",
+            Self::GemmaV3 => "gemma3",
+            Self::Llama => "llama",
+            Self::Mistral => "mistral",
+            Self::Qwen2 => "qwen2",
+            Self::Phi3 => "phi3",
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -39,17 +61,34 @@ pub struct ModelMeta {
     pub id: &'static str,
     pub family: Family,
     pub instruct: bool,
+    /// Tokens, prompt plus generated, the model attends to as served. The runner reports
+    /// the value of the loaded weights in [`runner_core::RunnerMetadata`].
+    pub context_length: usize,
+    /// Weights in the published checkpoint. The runner counts those it loaded in
+    /// [`runner_core::RunnerMetadata`].
+    pub parameters: u64,
 }
 
-const fn m(id: &'static str, family: Family, instruct: bool) -> ModelMeta {
+const fn m(
+    id: &'static str,
+    family: Family,
+    instruct: bool,
+    context_length: usize,
+    parameters: u64,
+) -> ModelMeta {
     ModelMeta {
         id,
         family,
         instruct,
+        context_length,
+        parameters,
     }
 }
 
-#[derive(Clone, Debug, Copy, PartialEq, Eq, clap::ValueEnum)]
+const K: usize = 1024;
+const M: u64 = 1_000_000;
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
 pub enum Which {
     // Gemma 1.x
     #[value(name = "2b")]
@@ -100,6 +139,40 @@ pub enum Which {
     Llama32_3B,
     #[value(name = "llama-3.2-3b-it", alias = "llama-3.2-3b-instruct")]
     Llama32_3BInstruct,
+
+    // Llama 3.1 / 3.3 (sharded checkpoints)
+    #[value(name = "llama-3.1-8b")]
+    Llama31_8B,
+    #[value(name = "llama-3.1-8b-it", alias = "llama-3.1-8b-instruct")]
+    Llama31_8BInstruct,
+    #[value(name = "llama-3.3-70b-it", alias = "llama-3.3-70b-instruct")]
+    Llama33_70BInstruct,
+
+    // Llama 3.2 quantized (GGUF)
+    #[value(
+        name = "llama-3.2-1b-it-q4_k_m",
+        alias = "llama-3.2-1b-instruct-q4_k_m"
+    )]
+    Llama32_1BInstructQ4KM,
+    #[value(
+        name = "llama-3.2-3b-it-q4_k_m",
+        alias = "llama-3.2-3b-instruct-q4_k_m"
+    )]
+    Llama32_3BInstructQ4KM,
+
+    // Mistral, Qwen2.5 and Phi-3 (served by model-runner)
+    #[value(name = "mistral-7b-instruct-v0.3")]
+    Mistral7BInstructV03,
+    #[value(name = "qwen2.5-0.5b-instruct")]
+    Qwen25_0_5BInstruct,
+    #[value(name = "qwen2.5-1.5b-instruct")]
+    Qwen25_1_5BInstruct,
+    #[value(name = "qwen2.5-3b-instruct")]
+    Qwen25_3BInstruct,
+    #[value(name = "qwen2.5-7b-instruct")]
+    Qwen25_7BInstruct,
+    #[value(name = "phi-3-mini-4k-instruct")]
+    Phi3Mini4KInstruct,
 }
 
 impl Which {
@@ -107,34 +180,182 @@ impl Which {
         use Family::*;
         match self {
             // Gemma 1.x
-            Self::Base2B => m("google/gemma-2b", GemmaV1, false),
-            Self::Base7B => m("google/gemma-7b", GemmaV1, false),
-            Self::Instruct2B => m("google/gemma-2b-it", GemmaV1, true),
-            Self::Instruct7B => m("google/gemma-7b-it", GemmaV1, true),
-            Self::InstructV1_1_2B => m("google/gemma-1.1-2b-it", GemmaV1, true),
-            Self::InstructV1_1_7B => m("google/gemma-1.1-7b-it", GemmaV1, true),
+            Self::Base2B => m("google/gemma-2b", GemmaV1, false, 8 * K, 2_506 * M),
+            Self::Base7B => m("google/gemma-7b", GemmaV1, false, 8 * K, 8_538 * M),
+            Self::Instruct2B => m("google/gemma-2b-it", GemmaV1, true, 8 * K, 2_506 * M),
+            Self::Instruct7B => m("google/gemma-7b-it", GemmaV1, true, 8 * K, 8_538 * M),
+            Self::InstructV1_1_2B => m("google/gemma-1.1-2b-it", GemmaV1, true, 8 * K, 2_506 * M),
+            Self::InstructV1_1_7B => m("google/gemma-1.1-7b-it", GemmaV1, true, 8 * K, 8_538 * M),
 
             // CodeGemma
-            Self::CodeBase2B => m("google/codegemma-2b", GemmaV1, false),
-            Self::CodeBase7B => m("google/codegemma-7b", GemmaV1, false),
-            Self::CodeInstruct2B => m("google/codegemma-2b-it", GemmaV1, true),
-            Self::CodeInstruct7B => m("google/codegemma-7b-it", GemmaV1, true),
+            Self::CodeBase2B => m("google/codegemma-2b", GemmaV1, false, 8 * K, 2_506 * M),
+            Self::CodeBase7B => m("google/codegemma-7b", GemmaV1, false, 8 * K, 8_538 * M),
+            Self::CodeInstruct2B => m("google/codegemma-2b-it", GemmaV1, true, 8 * K, 2_506 * M),
+            Self::CodeInstruct7B => m("google/codegemma-7b-it", GemmaV1, true, 8 * K, 8_538 * M),
 
             // Gemma 2
-            Self::BaseV2_2B => m("google/gemma-2-2b", GemmaV2, false),
-            Self::InstructV2_2B => m("google/gemma-2-2b-it", GemmaV2, true),
-            Self::BaseV2_9B => m("google/gemma-2-9b", GemmaV2, false),
-            Self::InstructV2_9B => m("google/gemma-2-9b-it", GemmaV2, true),
+            Self::BaseV2_2B => m("google/gemma-2-2b", GemmaV2, false, 8 * K, 2_614 * M),
+            Self::InstructV2_2B => m("google/gemma-2-2b-it", GemmaV2, true, 8 * K, 2_614 * M),
+            Self::BaseV2_9B => m("google/gemma-2-9b", GemmaV2, false, 8 * K, 9_242 * M),
+            Self::InstructV2_9B => m("google/gemma-2-9b-it", GemmaV2, true, 8 * K, 9_242 * M),
 
             // Gemma 3
-            Self::BaseV3_1B => m("google/gemma-3-1b-pt", GemmaV3, false),
-            Self::InstructV3_1B => m("google/gemma-3-1b-it", GemmaV3, true),
+            Self::BaseV3_1B => m("google/gemma-3-1b-pt", GemmaV3, false, 32 * K, 1_000 * M),
+            Self::InstructV3_1B => m("google/gemma-3-1b-it", GemmaV3, true, 32 * K, 1_000 * M),
 
             // Llama 3.2
-            Self::Llama32_1B => m("meta-llama/Llama-3.2-1B", Llama, false),
-            Self::Llama32_1BInstruct => m("meta-llama/Llama-3.2-1B-Instruct", Llama, true),
-            Self::Llama32_3B => m("meta-llama/Llama-3.2-3B", Llama, false),
-            Self::Llama32_3BInstruct => m("meta-llama/Llama-3.2-3B-Instruct", Llama, true),
+            Self::Llama32_1B => m("meta-llama/Llama-3.2-1B", Llama, false, 128 * K, 1_236 * M),
+            Self::Llama32_1BInstruct => m(
+                "meta-llama/Llama-3.2-1B-Instruct",
+                Llama,
+                true,
+                128 * K,
+                1_236 * M,
+            ),
+            Self::Llama32_3B => m("meta-llama/Llama-3.2-3B", Llama, false, 128 * K, 3_213 * M),
+            Self::Llama32_3BInstruct => m(
+                "meta-llama/Llama-3.2-3B-Instruct",
+                Llama,
+                true,
+                128 * K,
+                3_213 * M,
+            ),
+
+            // Llama 3.1 / 3.3
+            Self::Llama31_8B => m("meta-llama/Llama-3.1-8B", Llama, false, 128 * K, 8_030 * M),
+            Self::Llama31_8BInstruct => m(
+                "meta-llama/Llama-3.1-8B-Instruct",
+                Llama,
+                true,
+                128 * K,
+                8_030 * M,
+            ),
+            Self::Llama33_70BInstruct => m(
+                "meta-llama/Llama-3.3-70B-Instruct",
+                Llama,
+                true,
+                128 * K,
+                70_554 * M,
+            ),
+
+            // Llama 3.2 quantized; candle's GGUF Llama caps its KV cache at 4096 positions
+            Self::Llama32_1BInstructQ4KM => m(
+                "bartowski/Llama-3.2-1B-Instruct-GGUF",
+                Llama,
+                true,
+                4 * K,
+                1_236 * M,
+            ),
+            Self::Llama32_3BInstructQ4KM => m(
+                "bartowski/Llama-3.2-3B-Instruct-GGUF",
+                Llama,
+                true,
+                4 * K,
+                3_213 * M,
+            ),
+
+            // Mistral, Qwen2.5 and Phi-3
+            Self::Mistral7BInstructV03 => m(
+                "mistralai/Mistral-7B-Instruct-v0.3",
+                Mistral,
+                true,
+                32 * K,
+                7_248 * M,
+            ),
+            Self::Qwen25_0_5BInstruct => {
+                m("Qwen/Qwen2.5-0.5B-Instruct", Qwen2, true, 32 * K, 494 * M)
+            }
+            Self::Qwen25_1_5BInstruct => {
+                m("Qwen/Qwen2.5-1.5B-Instruct", Qwen2, true, 32 * K, 1_544 * M)
+            }
+            Self::Qwen25_3BInstruct => {
+                m("Qwen/Qwen2.5-3B-Instruct", Qwen2, true, 32 * K, 3_086 * M)
+            }
+            Self::Qwen25_7BInstruct => {
+                m("Qwen/Qwen2.5-7B-Instruct", Qwen2, true, 32 * K, 7_616 * M)
+            }
+            Self::Phi3Mini4KInstruct => m(
+                "microsoft/Phi-3-mini-4k-instruct",
+                Phi3,
+                true,
+                4 * K,
+                3_821 * M,
+            ),
+        }
+    }
+
+    /// Id used by the OpenAI-compatible API, e.g. `gemma-3-1b-it`.
+    pub const fn public_id(&self) -> &'static str {
+        match self {
+            Self::Base2B => "gemma-2b",
+            Self::Base7B => "gemma-7b",
+            Self::Instruct2B => "gemma-2b-it",
+            Self::Instruct7B => "gemma-7b-it",
+            Self::InstructV1_1_2B => "gemma-1.1-2b-it",
+            Self::InstructV1_1_7B => "gemma-1.1-7b-it",
+            Self::CodeBase2B => "codegemma-2b",
+            Self::CodeBase7B => "codegemma-7b",
+            Self::CodeInstruct2B => "codegemma-2b-it",
+            Self::CodeInstruct7B => "codegemma-7b-it",
+            Self::BaseV2_2B => "gemma-2-2b",
+            Self::InstructV2_2B => "gemma-2-2b-it",
+            Self::BaseV2_9B => "gemma-2-9b",
+            Self::InstructV2_9B => "gemma-2-9b-it",
+            Self::BaseV3_1B => "gemma-3-1b",
+            Self::InstructV3_1B => "gemma-3-1b-it",
+            Self::Llama32_1B => "llama-3.2-1b",
+            Self::Llama32_1BInstruct => "llama-3.2-1b-instruct",
+            Self::Llama32_3B => "llama-3.2-3b",
+            Self::Llama32_3BInstruct => "llama-3.2-3b-instruct",
+            Self::Llama31_8B => "llama-3.1-8b",
+            Self::Llama31_8BInstruct => "llama-3.1-8b-instruct",
+            Self::Llama33_70BInstruct => "llama-3.3-70b-instruct",
+            Self::Llama32_1BInstructQ4KM => "llama-3.2-1b-instruct-q4_k_m",
+            Self::Llama32_3BInstructQ4KM => "llama-3.2-3b-instruct-q4_k_m",
+            Self::Mistral7BInstructV03 => "mistral-7b-instruct-v0.3",
+            Self::Qwen25_0_5BInstruct => "qwen2.5-0.5b-instruct",
+            Self::Qwen25_1_5BInstruct => "qwen2.5-1.5b-instruct",
+            Self::Qwen25_3BInstruct => "qwen2.5-3b-instruct",
+            Self::Qwen25_7BInstruct => "qwen2.5-7b-instruct",
+            Self::Phi3Mini4KInstruct => "phi-3-mini-4k-instruct",
+        }
+    }
+
+    /// Look up a model by its public id.
+    pub fn from_public_id(id: &str) -> Option<Self> {
+        Self::value_variants()
+            .iter()
+            .copied()
+            .find(|which| which.public_id() == id)
+    }
+
+    /// Id under which the model serves `/v1/embeddings`, e.g. `gemma-2b-it-embed`, if its
+    /// runner can expose hidden states.
+    pub fn embedding_id(&self) -> Option<String> {
+        self.supports_embeddings()
+            .then(|| format!("{}{}", self.public_id(), EMBEDDING_ID_SUFFIX))
+    }
+
+    /// Look up a model by its embedding id.
+    pub fn from_embedding_id(id: &str) -> Option<Self> {
+        id.strip_suffix(EMBEDDING_ID_SUFFIX)
+            .and_then(Self::from_public_id)
+            .filter(Self::supports_embeddings)
+    }
+
+    /// Whether the runner can mean-pool the model's hidden states. candle only exposes them
+    /// for Gemma 1 and CodeGemma.
+    pub fn supports_embeddings(&self) -> bool {
+        matches!(self.meta().family, Family::GemmaV1)
+    }
+
+    pub fn owned_by(&self) -> &'static str {
+        match self.meta().family {
+            Family::GemmaV1 | Family::GemmaV2 | Family::GemmaV3 => "google",
+            Family::Llama => "meta",
+            Family::Mistral => "mistralai",
+            Family::Qwen2 => "qwen",
+            Family::Phi3 => "microsoft",
         }
     }
 
@@ -146,6 +367,22 @@ impl Which {
         self.meta().instruct
     }
 
+    /// Whether the model was trained for code, as CodeGemma was.
+    pub fn is_code_model(&self) -> bool {
+        matches!(
+            self,
+            Self::CodeBase2B | Self::CodeBase7B | Self::CodeInstruct2B | Self::CodeInstruct7B
+        )
+    }
+
+    /// Quantization of a GGUF checkpoint, e.g. `q4_k_m`; `None` for full-precision weights.
+    pub fn quantization(&self) -> Option<&'static str> {
+        match self {
+            Self::Llama32_1BInstructQ4KM | Self::Llama32_3BInstructQ4KM => Some("q4_k_m"),
+            _ => None,
+        }
+    }
+
     pub fn is_v3_model(&self) -> bool {
         matches!(self.meta().family, Family::GemmaV3)
     }
@@ -153,4 +390,12 @@ impl Which {
     pub fn is_llama_model(&self) -> bool {
         matches!(self.meta().family, Family::Llama)
     }
+
+    /// Whether the model is served by `model-runner` rather than the Gemma or Llama runner.
+    pub fn is_general_model(&self) -> bool {
+        matches!(
+            self.meta().family,
+            Family::Mistral | Family::Qwen2 | Family::Phi3
+        )
+    }
 }
@@ -0,0 +1,94 @@
+use embeddings_engine::{
+    EmbeddingData, EmbeddingRequest, EmbeddingRequestInput, EmbeddingResponse, EmbeddingUsage,
+    EmbeddingVector,
+};
+use utoipa::OpenApi;
+
+use crate::health;
+use crate::log_level::{self, LogLevelRequest, LogLevelResponse};
+use crate::openai_types::{
+    ChatCompletionCancelResponse, ChatCompletionChoice, ChatCompletionChunk,
+    ChatCompletionChunkChoice, ChatCompletionRequest, ChatCompletionResponse, Delta, ErrorDetail,
+    ErrorResponse, FunctionCall, FunctionCallDelta, FunctionDefinition, FunctionName, Message,
+    MessageContent, MessageInnerContent, Model, ModelDeleteResponse, ModelDetail,
+    ModelListResponse, ModelReadiness, ModelWarmupResponse, NamedToolChoice, ReadinessResponse,
+    StopTokens, StreamOptions, Tool, ToolCall, ToolCallDelta, ToolChoice, Usage,
+};
+use crate::scheduler::SchedulerStats;
+use crate::server::{self, AdminStatus, MemoryStatus};
+use crate::warm_pool::{EvictedModel, WarmModel, WarmPoolStatus};
+
+/// OpenAPI description of the routes [`crate::create_router`] serves.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        server::chat_completions,
+        server::cancel_chat_completion,
+        server::list_models,
+        server::get_model,
+        server::load_model,
+        server::delete_model,
+        server::warmup_model,
+        server::create_embeddings,
+        server::device_info,
+        server::admin_status,
+        health::ready,
+        log_level::get_log_level,
+        log_level::set_log_level
+    ),
+    components(schemas(
+        ChatCompletionRequest,
+        ChatCompletionResponse,
+        ChatCompletionChoice,
+        ChatCompletionChunk,
+        ChatCompletionChunkChoice,
+        Delta,
+        Message,
+        MessageContent,
+        MessageInnerContent,
+        StopTokens,
+        StreamOptions,
+        Tool,
+        FunctionDefinition,
+        ToolChoice,
+        NamedToolChoice,
+        FunctionName,
+        ToolCall,
+        FunctionCall,
+        ToolCallDelta,
+        FunctionCallDelta,
+        Usage,
+        Model,
+        ModelListResponse,
+        ModelDetail,
+        ModelDeleteResponse,
+        ModelWarmupResponse,
+        ChatCompletionCancelResponse,
+        ErrorResponse,
+        ErrorDetail,
+        EmbeddingRequest,
+        EmbeddingRequestInput,
+        EmbeddingResponse,
+        EmbeddingData,
+        EmbeddingVector,
+        EmbeddingUsage,
+        LogLevelRequest,
+        LogLevelResponse,
+        AdminStatus,
+        MemoryStatus,
+        SchedulerStats,
+        WarmPoolStatus,
+        WarmModel,
+        EvictedModel,
+        ReadinessResponse,
+        ModelReadiness
+    )),
+    tags(
+        (name = "chat", description = "OpenAI-compatible chat completions"),
+        (name = "models", description = "Available models and loading them ahead of time"),
+        (name = "embeddings", description = "Text embeddings"),
+        (name = "admin", description = "Inspecting the server, its loaded models and warm pool, and adjusting its logging"),
+        (name = "health", description = "Liveness and readiness checks for probes")
+    )
+)]
+pub struct ApiDoc;
@@ -0,0 +1,76 @@
+use tokio::task::JoinHandle;
+
+use crate::Which;
+use crate::runners::{Sampling, pooled_runner};
+use crate::server::AppState;
+
+/// Environment variable listing models to load at startup, as comma-separated model ids.
+pub const PRELOAD_MODELS_ENV: &str = "PRELOAD_MODELS";
+
+/// The models in [`PRELOAD_MODELS_ENV`], in order and without repeats.
+pub fn preload_models_from_env() -> Vec<Which> {
+    preload_models_from_vars(|name| std::env::var(name).ok())
+}
+
+fn preload_models_from_vars(var: impl Fn(&str) -> Option<String>) -> Vec<Which> {
+    let mut models = Vec::new();
+    for id in var(PRELOAD_MODELS_ENV).unwrap_or_default().split(',') {
+        let id = id.trim();
+        if id.is_empty() {
+            continue;
+        }
+        match Which::from_public_id(id) {
+            Some(which) if !models.contains(&which) => models.push(which),
+            Some(_) => {}
+            None => tracing::warn!(
+                "Ignoring unsupported model {} in {}",
+                id,
+                PRELOAD_MODELS_ENV
+            ),
+        }
+    }
+    models
+}
+
+/// Load `models` one after another in the background, with the runner configs of `state`,
+/// so their first requests don't wait for the weights. Unlike the warm pool, they aren't
+/// kept loaded: idle eviction and `DELETE /v1/models/{id}` still drop them.
+pub fn spawn_preload(models: Vec<Which>, state: AppState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        for which in models {
+            let state = state.clone();
+            let start = std::time::Instant::now();
+            // Loading weights blocks
+            let loaded = tokio::task::spawn_blocking(move || {
+                pooled_runner(which, &state, Sampling::default()).map(drop)
+            })
+            .await;
+            match loaded {
+                Ok(Ok(())) => {
+                    tracing::info!("Preloaded {} in {:.0?}", which.public_id(), start.elapsed())
+                }
+                Ok(Err(e)) => tracing::warn!("Failed to preload {}: {}", which.public_id(), e),
+                Err(e) => tracing::warn!("Failed to preload {}: {}", which.public_id(), e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preload_models_from_vars() {
+        let models = preload_models_from_vars(|name| {
+            (name == PRELOAD_MODELS_ENV).then(|| {
+                "llama-3.2-1b-instruct, gemma-9,,gemma-3-1b-it,llama-3.2-1b-instruct".to_string()
+            })
+        });
+        assert_eq!(
+            models,
+            vec![Which::Llama32_1BInstruct, Which::InstructV3_1B]
+        );
+        assert!(preload_models_from_vars(|_| None).is_empty());
+    }
+}
@@ -0,0 +1,299 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use runner_core::{ModelRunner, RunnerError, RunnerMetadata};
+
+use crate::Which;
+use crate::runners::Sampling;
+
+/// Environment variable setting how many runners the pool keeps; `0` loads one per request.
+pub const RUNNER_POOL_SIZE_ENV: &str = "RUNNER_POOL_SIZE";
+
+/// Runners kept when [`RUNNER_POOL_SIZE_ENV`] isn't set.
+const DEFAULT_POOL_SIZE: usize = 16;
+
+struct PooledRunner {
+    which: Which,
+    sampling: Sampling,
+    runner: Arc<dyn ModelRunner>,
+    last_used: Instant,
+}
+
+/// Runners kept loaded between requests, one per model and sampling settings, so a request
+/// like one served before skips loading the model.
+///
+/// A runner serves concurrent generations, each with its own KV state, so every request
+/// for it shares the one instance. Pooled runners hold their weights in the runner crates'
+/// caches; [`RunnerPool::release_idle`] lets those go before idle models are evicted.
+#[derive(Clone)]
+pub struct RunnerPool {
+    entries: Arc<Mutex<Vec<PooledRunner>>>,
+    capacity: usize,
+}
+
+impl Default for RunnerPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_POOL_SIZE)
+    }
+}
+
+impl RunnerPool {
+    /// A pool of at most `capacity` runners, dropping the least recently used beyond it.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(Vec::new())),
+            capacity,
+        }
+    }
+
+    /// A pool of [`RUNNER_POOL_SIZE_ENV`] runners, 16 by default.
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let capacity = match var(RUNNER_POOL_SIZE_ENV) {
+            Some(value) => value.trim().parse().unwrap_or_else(|_| {
+                eprintln!(
+                    "Warning: ignoring {}={}: expected a number",
+                    RUNNER_POOL_SIZE_ENV, value
+                );
+                DEFAULT_POOL_SIZE
+            }),
+            None => DEFAULT_POOL_SIZE,
+        };
+        Self::new(capacity)
+    }
+
+    /// The pooled runner for `which` and `sampling`, calling `load` on a miss. A failed
+    /// load isn't pooled, so the next request tries again.
+    pub fn get_or_load(
+        &self,
+        which: Which,
+        sampling: Sampling,
+        load: impl FnOnce() -> Result<Box<dyn ModelRunner>, RunnerError>,
+    ) -> Result<Arc<dyn ModelRunner>, RunnerError> {
+        if let Some(runner) = self.get(which, sampling) {
+            runner.touch();
+            return Ok(runner);
+        }
+        // Loading can take minutes, so it runs outside the lock; concurrent misses for the
+        // same runner both load it, sharing the cached weights, and the first one is kept
+        let runner: Arc<dyn ModelRunner> = Arc::from(load()?);
+        if self.capacity == 0 {
+            return Ok(runner);
+        }
+        let Ok(mut entries) = self.entries.lock() else {
+            return Ok(runner);
+        };
+        if let Some(entry) = entries
+            .iter()
+            .find(|entry| entry.which == which && entry.sampling == sampling)
+        {
+            return Ok(Arc::clone(&entry.runner));
+        }
+        entries.push(PooledRunner {
+            which,
+            sampling,
+            runner: Arc::clone(&runner),
+            last_used: Instant::now(),
+        });
+        if entries.len() > self.capacity
+            && let Some(oldest) = entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(index, _)| index)
+        {
+            let dropped = entries.remove(oldest);
+            tracing::debug!("Runner pool full, dropped {}", dropped.which.public_id());
+        }
+        Ok(runner)
+    }
+
+    fn get(&self, which: Which, sampling: Sampling) -> Option<Arc<dyn ModelRunner>> {
+        let mut entries = self.entries.lock().ok()?;
+        let entry = entries
+            .iter_mut()
+            .find(|entry| entry.which == which && entry.sampling == sampling)?;
+        entry.last_used = Instant::now();
+        Some(Arc::clone(&entry.runner))
+    }
+
+    /// Metadata of a pooled runner for `which`, whatever its sampling settings, without
+    /// counting as a use.
+    pub fn metadata(&self, which: Which) -> Option<RunnerMetadata> {
+        let entries = self.entries.lock().ok()?;
+        entries
+            .iter()
+            .find(|entry| entry.which == which)
+            .map(|entry| entry.runner.metadata().clone())
+    }
+
+    /// Drop the runners no request is using that were last used longer than `idle` ago,
+    /// so idle eviction can free their models. Returns how many were dropped.
+    pub fn release_idle(&self, idle: Duration) -> usize {
+        let Ok(mut entries) = self.entries.lock() else {
+            return 0;
+        };
+        let before = entries.len();
+        entries.retain(|entry| {
+            Arc::strong_count(&entry.runner) > 1 || entry.last_used.elapsed() <= idle
+        });
+        before - entries.len()
+    }
+
+    /// Drop the pooled runner no request is using that was used least recently, so its
+    /// model can be evicted. Returns whether there was one to drop.
+    pub fn release_least_recently_used(&self) -> bool {
+        let Ok(mut entries) = self.entries.lock() else {
+            return false;
+        };
+        let oldest = entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| Arc::strong_count(&entry.runner) == 1)
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(index, _)| index);
+        match oldest {
+            Some(index) => {
+                entries.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drop every pooled runner for `which`, in use or not, so its model can be evicted.
+    /// Generations still running keep theirs until they finish. Returns how many were
+    /// dropped.
+    pub fn remove(&self, which: Which) -> usize {
+        let Ok(mut entries) = self.entries.lock() else {
+            return 0;
+        };
+        let before = entries.len();
+        entries.retain(|entry| entry.which != which);
+        before - entries.len()
+    }
+
+    /// Number of pooled runners.
+    pub fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .map(|entries| entries.len())
+            .unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runner_core::TokenReceiver;
+    use std::cell::Cell;
+
+    struct StubRunner(RunnerMetadata);
+
+    impl ModelRunner for StubRunner {
+        type Config = ();
+
+        fn load(_: ()) -> Result<Self, RunnerError> {
+            Ok(Self(RunnerMetadata {
+                model_id: "stub".to_string(),
+                repo_id: "stub".to_string(),
+                family: "stub".to_string(),
+                owned_by: "test".to_string(),
+                context_length: 16,
+                vocab_size: 1,
+                parameter_count: 0,
+                dtype: "f32".to_string(),
+                device: "cpu".to_string(),
+            }))
+        }
+
+        fn generate_stream(
+            &self,
+            _: runner_core::GenerationRequest,
+        ) -> Result<TokenReceiver, RunnerError> {
+            Err(RunnerError::InvalidRequest("stub".to_string()))
+        }
+
+        fn metadata(&self) -> &RunnerMetadata {
+            &self.0
+        }
+
+        fn cancel(&self) {}
+    }
+
+    #[test]
+    fn test_runners_are_reused_per_model_and_sampling() {
+        let pool = RunnerPool::new(2);
+        let loads = Cell::new(0);
+        let load = || {
+            loads.set(loads.get() + 1);
+            Ok(Box::new(StubRunner::load(())?) as Box<dyn ModelRunner>)
+        };
+        let gemma = Which::InstructV3_1B;
+        let precise = Sampling {
+            temperature: Some(0.2),
+            ..Sampling::default()
+        };
+
+        let first = pool.get_or_load(gemma, Sampling::default(), load).unwrap();
+        let second = pool.get_or_load(gemma, Sampling::default(), load).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        pool.get_or_load(gemma, precise, load).unwrap();
+        assert_eq!(loads.get(), 2);
+        assert_eq!(pool.metadata(gemma).unwrap().context_length, 16);
+        assert!(pool.metadata(Which::BaseV3_1B).is_none());
+
+        // A third runner pushes out the least recently used
+        pool.get_or_load(Which::Llama32_1BInstruct, precise, load)
+            .unwrap();
+        assert_eq!(pool.len(), 2);
+        pool.get_or_load(gemma, Sampling::default(), load).unwrap();
+        assert_eq!(loads.get(), 4);
+
+        let failed = pool.get_or_load(Which::BaseV3_1B, precise, || {
+            Err(RunnerError::InvalidRequest("nope".to_string()))
+        });
+        assert!(failed.is_err());
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn test_only_unused_runners_are_released() {
+        let pool = RunnerPool::new(4);
+        let load = || Ok(Box::new(StubRunner::load(())?) as Box<dyn ModelRunner>);
+        let in_use = pool
+            .get_or_load(Which::InstructV3_1B, Sampling::default(), load)
+            .unwrap();
+        pool.get_or_load(Which::BaseV3_1B, Sampling::default(), load)
+            .unwrap();
+
+        assert_eq!(pool.release_idle(Duration::from_secs(60)), 0);
+        assert_eq!(pool.release_idle(Duration::ZERO), 1);
+        assert_eq!(pool.len(), 1);
+        drop(in_use);
+        assert_eq!(pool.release_idle(Duration::ZERO), 1);
+        assert!(pool.is_empty());
+
+        // Removing a model drops its runners whether they're in use or not
+        let in_use = pool
+            .get_or_load(Which::InstructV3_1B, Sampling::default(), load)
+            .unwrap();
+        assert_eq!(pool.remove(Which::InstructV3_1B), 1);
+        assert_eq!(pool.remove(Which::InstructV3_1B), 0);
+        assert!(pool.is_empty());
+        drop(in_use);
+
+        let disabled = RunnerPool::from_vars(|_| Some("0".to_string()));
+        disabled
+            .get_or_load(Which::InstructV3_1B, Sampling::default(), load)
+            .unwrap();
+        assert!(disabled.is_empty());
+    }
+}
@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use gemma_runner::{GemmaInferenceConfig, GemmaRunner};
+use llama_runner::{LlamaInferenceConfig, LlamaRunner};
+use model_runner::{ModelInferenceConfig, TextModelRunner};
+use runner_core::{ModelRunner, RunnerError, SamplingPreset};
+
+use crate::model::{Family, Which};
+use crate::server::AppState;
+
+/// Per-request sampling settings. `None` keeps the value from the runner config.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Sampling {
+    /// Applied to the runner config first; the fields below override it.
+    pub preset: Option<SamplingPreset>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub top_k: Option<usize>,
+    pub no_repeat_ngram_size: Option<usize>,
+    /// Seeds the sampler, so a request repeated with the same seed samples the same tokens.
+    pub seed: Option<u64>,
+    pub frequency_penalty: Option<f32>,
+}
+
+/// Builds the runner for a model and the request's sampling settings, standing in for the
+/// runner crates. Tests use it to serve a mock runner without downloading weights.
+pub type RunnerLoader =
+    Arc<dyn Fn(Which, Sampling) -> Result<Box<dyn ModelRunner>, RunnerError> + Send + Sync>;
+
+/// The runner for a model and the request's sampling settings from the pool of `state`,
+/// loading it with [`load_runner`] when none is pooled yet.
+pub fn pooled_runner(
+    which: Which,
+    state: &AppState,
+    sampling: Sampling,
+) -> Result<Arc<dyn ModelRunner>, RunnerError> {
+    state
+        .runners
+        .get_or_load(which, sampling, || load_runner(which, state, sampling))
+}
+
+/// Load the runner for a model on its device in `AppState::devices`, using the configs in
+/// `AppState` as defaults, or `AppState::runner_loader` when one is set.
+///
+/// This is the only place that knows which runner crate serves which family; adding a
+/// family means implementing `ModelRunner` in its runner crate and adding an arm here.
+pub fn load_runner(
+    which: Which,
+    state: &AppState,
+    sampling: Sampling,
+) -> Result<Box<dyn ModelRunner>, RunnerError> {
+    if let Some(loader) = &state.runner_loader {
+        return loader(which, sampling);
+    }
+    let id = which.public_id();
+    match which.meta().family {
+        Family::GemmaV1 | Family::GemmaV2 | Family::GemmaV3 => {
+            let model = id
+                .parse::<gemma_runner::WhichModel>()
+                .map_err(RunnerError::InvalidRequest)?;
+            let mut defaults = state.gemma_config.clone().unwrap_or_default();
+            if let Some(preset) = sampling.preset {
+                defaults = defaults.with_preset(preset);
+            }
+            let config = GemmaInferenceConfig {
+                model: Some(model),
+                temperature: sampling.temperature.unwrap_or(defaults.temperature),
+                top_p: sampling.top_p.or(defaults.top_p),
+                top_k: sampling.top_k.or(defaults.top_k),
+                no_repeat_ngram_size: sampling
+                    .no_repeat_ngram_size
+                    .unwrap_or(defaults.no_repeat_ngram_size),
+                seed: sampling.seed.unwrap_or(defaults.seed),
+                frequency_penalty: sampling
+                    .frequency_penalty
+                    .unwrap_or(defaults.frequency_penalty),
+                device: state.devices.device(which),
+                ..defaults
+            };
+            Ok(Box::new(GemmaRunner::load(config)?))
+        }
+        Family::Llama => {
+            let model = <llama_runner::WhichModel as clap::ValueEnum>::from_str(id, true)
+                .map_err(RunnerError::InvalidRequest)?;
+            let mut defaults = match state.llama_config.clone() {
+                Some(config) => LlamaInferenceConfig { model, ..config },
+                None => LlamaInferenceConfig::new(model),
+            };
+            if let Some(preset) = sampling.preset {
+                defaults = defaults.with_preset(preset);
+            }
+            let config = LlamaInferenceConfig {
+                temperature: sampling.temperature.unwrap_or(defaults.temperature),
+                top_p: sampling.top_p.or(defaults.top_p),
+                top_k: sampling.top_k.or(defaults.top_k),
+                no_repeat_ngram_size: sampling
+                    .no_repeat_ngram_size
+                    .unwrap_or(defaults.no_repeat_ngram_size),
+                seed: sampling.seed.unwrap_or(defaults.seed),
+                frequency_penalty: sampling
+                    .frequency_penalty
+                    .unwrap_or(defaults.frequency_penalty),
+                device: state.devices.device(which),
+                ..defaults
+            };
+            Ok(Box::new(LlamaRunner::load(config)?))
+        }
+        Family::Mistral | Family::Qwen2 | Family::Phi3 => {
+            let model = <model_runner::WhichModel as clap::ValueEnum>::from_str(id, true)
+                .map_err(RunnerError::InvalidRequest)?;
+            let mut defaults = match state.model_config.clone() {
+                Some(config) => ModelInferenceConfig { model, ..config },
+                None => ModelInferenceConfig::new(model),
+            };
+            if let Some(preset) = sampling.preset {
+                defaults = defaults.with_preset(preset);
+            }
+            let config = ModelInferenceConfig {
+                temperature: sampling.temperature.unwrap_or(defaults.temperature),
+                top_p: sampling.top_p.or(defaults.top_p),
+                top_k: sampling.top_k.or(defaults.top_k),
+                no_repeat_ngram_size: sampling
+                    .no_repeat_ngram_size
+                    .unwrap_or(defaults.no_repeat_ngram_size),
+                seed: sampling.seed.unwrap_or(defaults.seed),
+                frequency_penalty: sampling
+                    .frequency_penalty
+                    .unwrap_or(defaults.frequency_penalty),
+                device: state.devices.device(which),
+                ..defaults
+            };
+            Ok(Box::new(TextModelRunner::load(config)?))
+        }
+    }
+}
+
+/// Context length of a model whose weights are already in its runner's cache, `None` when
+/// it isn't loaded.
+pub fn loaded_context_length(which: Which) -> Option<usize> {
+    let id = which.public_id();
+    match which.meta().family {
+        Family::GemmaV1 | Family::GemmaV2 | Family::GemmaV3 => id
+            .parse::<gemma_runner::WhichModel>()
+            .ok()
+            .and_then(gemma_runner::cached_context_length),
+        Family::Llama => <llama_runner::WhichModel as clap::ValueEnum>::from_str(id, true)
+            .ok()
+            .and_then(llama_runner::cached_context_length),
+        Family::Mistral | Family::Qwen2 | Family::Phi3 => {
+            <model_runner::WhichModel as clap::ValueEnum>::from_str(id, true)
+                .ok()
+                .and_then(model_runner::cached_context_length)
+        }
+    }
+}
+
+/// Drop the model's weights from its runner crate's cache, whatever their dtype or
+/// device. Runners still using them keep them until they're dropped. Returns how many
+/// cached copies were dropped.
+pub fn evict_cached_model(which: Which) -> usize {
+    let id = which.public_id();
+    match which.meta().family {
+        Family::GemmaV1 | Family::GemmaV2 | Family::GemmaV3 => id
+            .parse::<gemma_runner::WhichModel>()
+            .map_or(0, gemma_runner::evict_cached_model),
+        Family::Llama => <llama_runner::WhichModel as clap::ValueEnum>::from_str(id, true)
+            .map_or(0, llama_runner::evict_cached_model),
+        Family::Mistral | Family::Qwen2 | Family::Phi3 => {
+            <model_runner::WhichModel as clap::ValueEnum>::from_str(id, true)
+                .map_or(0, model_runner::evict_cached_model)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ValueEnum;
+
+    #[test]
+    fn test_every_model_maps_to_a_runner_model() {
+        for which in Which::value_variants() {
+            let id = which.public_id();
+            let known = if which.is_llama_model() {
+                <llama_runner::WhichModel as ValueEnum>::from_str(id, true).is_ok()
+            } else if which.is_general_model() {
+                <model_runner::WhichModel as ValueEnum>::from_str(id, true).is_ok()
+            } else {
+                id.parse::<gemma_runner::WhichModel>().is_ok()
+            };
+            assert!(known, "no runner model for {}", id);
+        }
+    }
+}
@@ -0,0 +1,628 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use axum::http::StatusCode;
+use runner_core::{GenerationRequest, ModelRunner, RunnerError, TokenReceiver};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, oneshot};
+use utoipa::ToSchema;
+
+use crate::openai_types::ApiError;
+
+/// Environment variable setting how many generations run at once; `0` runs every request
+/// as it arrives.
+pub const MAX_CONCURRENT_INFERENCES_ENV: &str = "MAX_CONCURRENT_INFERENCES";
+
+/// Environment variable setting how many requests may wait for a free slot before more are
+/// turned away.
+pub const INFERENCE_QUEUE_SIZE_ENV: &str = "INFERENCE_QUEUE_SIZE";
+
+/// Environment variable setting how many generations may start together in one batch;
+/// `1` starts every generation on its own.
+pub const MAX_BATCH_SIZE_ENV: &str = "MAX_BATCH_SIZE";
+
+/// Environment variable setting how long, in milliseconds, a generation waits for others
+/// to join its batch.
+pub const BATCH_WINDOW_MS_ENV: &str = "BATCH_WINDOW_MS";
+
+/// Environment variable setting how many seconds a generation may run before it is cut
+/// off; `0` lets generations run for as long as they take.
+pub const INFERENCE_TIMEOUT_SECS_ENV: &str = "INFERENCE_TIMEOUT_SECS";
+
+const DEFAULT_MAX_CONCURRENT: usize = 4;
+const DEFAULT_QUEUE_SIZE: usize = 32;
+const DEFAULT_MAX_BATCH: usize = 8;
+const DEFAULT_BATCH_WINDOW: Duration = Duration::from_millis(5);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Longest `Retry-After` suggested to a client turned away.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+struct Inner {
+    /// Replaced as a whole on a resize; permits taken before it stay with the old one.
+    permits: Mutex<Arc<Semaphore>>,
+    max_concurrent: AtomicUsize,
+    max_queued: AtomicUsize,
+    queued: AtomicUsize,
+    running: AtomicUsize,
+    rejected: AtomicU64,
+    /// Moving average of how long a generation holds its slot, in milliseconds.
+    average_ms: AtomicU64,
+    max_batch: usize,
+    batch_window: Duration,
+    timeout: Option<Duration>,
+    /// Batches still taking generations, by the address of their runner.
+    forming: Mutex<HashMap<usize, FormingBatch>>,
+    next_batch: AtomicU64,
+    batched: AtomicU64,
+}
+
+/// Where a generation waiting in a forming batch gets its stream.
+type StreamSender = oneshot::Sender<Result<TokenReceiver, RunnerError>>;
+
+struct FormingBatch {
+    id: u64,
+    runner: Arc<dyn ModelRunner>,
+    requests: Vec<(GenerationRequest, StreamSender)>,
+}
+
+/// Admits generations so that at most `max_concurrent` run at once, each with its own
+/// thread and KV cache, and up to `max_queued` more wait their turn in arrival order.
+/// Requests beyond that are answered with a 429 instead of piling up in memory.
+///
+/// Admitted generations for a runner that batches are started through
+/// [`InferenceScheduler::start`], which gathers those arriving within `batch_window` of
+/// each other, up to `max_batch`, into one call to [`ModelRunner::generate_streams`] so
+/// they share forward passes. Each still gets its own stream to serve its client from.
+/// A batch runs to its end: generations arriving later start a batch of their own
+/// alongside it, since a runner's batch shares one KV position and can't take new rows.
+///
+/// A generation gets `timeout` from the moment it takes its slot, see
+/// [`InferencePermit::deadline`], so a stuck model can't hold a slot forever.
+///
+/// Clones share the slots, like the other parts of [`crate::AppState`].
+#[derive(Clone)]
+pub struct InferenceScheduler {
+    inner: Arc<Inner>,
+}
+
+/// What the scheduler is doing, for `/admin/status`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SchedulerStats {
+    /// Generations that may run at once, `0` for no limit
+    pub max_concurrent: usize,
+    /// Requests that may wait for a slot
+    pub max_queued: usize,
+    /// Generations running now
+    pub running: usize,
+    /// Requests waiting for a slot
+    pub queued: usize,
+    /// Requests turned away with a 429 since the server started
+    pub rejected: u64,
+    /// Most generations started together in one batch
+    pub max_batch: usize,
+    /// How long a generation waits for others to join its batch, in milliseconds
+    pub batch_window_ms: u64,
+    /// Generations that shared their batch with others since the server started
+    pub batched: u64,
+    /// Longest a generation may run, in seconds, `0` for no limit
+    pub timeout_secs: u64,
+}
+
+impl Default for InferenceScheduler {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_CONCURRENT, DEFAULT_QUEUE_SIZE)
+    }
+}
+
+impl InferenceScheduler {
+    pub fn new(max_concurrent: usize, max_queued: usize) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                permits: Mutex::new(slots(max_concurrent)),
+                max_concurrent: AtomicUsize::new(max_concurrent),
+                max_queued: AtomicUsize::new(max_queued),
+                queued: AtomicUsize::new(0),
+                running: AtomicUsize::new(0),
+                rejected: AtomicU64::new(0),
+                average_ms: AtomicU64::new(0),
+                max_batch: DEFAULT_MAX_BATCH,
+                batch_window: DEFAULT_BATCH_WINDOW,
+                timeout: Some(DEFAULT_TIMEOUT),
+                forming: Mutex::new(HashMap::new()),
+                next_batch: AtomicU64::new(0),
+                batched: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Start up to `max_batch` generations together when they arrive within `window` of
+    /// each other. A `max_batch` of 1 or an empty window starts each on its own.
+    pub fn with_batching(self, max_batch: usize, window: Duration) -> Self {
+        let mut inner = Arc::into_inner(self.inner).expect("set up before the scheduler is shared");
+        inner.max_batch = max_batch;
+        inner.batch_window = window;
+        Self {
+            inner: Arc::new(inner),
+        }
+    }
+
+    /// Cut generations off after `timeout`, or never with `None`.
+    pub fn with_timeout(self, timeout: Option<Duration>) -> Self {
+        let mut inner = Arc::into_inner(self.inner).expect("set up before the scheduler is shared");
+        inner.timeout = timeout;
+        Self {
+            inner: Arc::new(inner),
+        }
+    }
+
+    /// A scheduler sized by [`MAX_CONCURRENT_INFERENCES_ENV`] (4 by default) and
+    /// [`INFERENCE_QUEUE_SIZE_ENV`] (32 by default), batching by [`MAX_BATCH_SIZE_ENV`]
+    /// (8 by default) and [`BATCH_WINDOW_MS_ENV`] (5 by default), and cutting generations
+    /// off after [`INFERENCE_TIMEOUT_SECS_ENV`] (300 by default).
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Like [`InferenceScheduler::from_env`], with the variables `var` returns, e.g. the
+    /// environment with a config file's limits for the variables it doesn't set.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let parse = |name: &str, default: usize| match var(name) {
+            Some(value) => value.trim().parse().unwrap_or_else(|_| {
+                eprintln!("Warning: ignoring {}={}: expected a number", name, value);
+                default
+            }),
+            None => default,
+        };
+        Self::new(
+            parse(MAX_CONCURRENT_INFERENCES_ENV, DEFAULT_MAX_CONCURRENT),
+            parse(INFERENCE_QUEUE_SIZE_ENV, DEFAULT_QUEUE_SIZE),
+        )
+        .with_batching(
+            parse(MAX_BATCH_SIZE_ENV, DEFAULT_MAX_BATCH),
+            Duration::from_millis(parse(
+                BATCH_WINDOW_MS_ENV,
+                DEFAULT_BATCH_WINDOW.as_millis() as usize,
+            ) as u64),
+        )
+        .with_timeout(
+            match parse(
+                INFERENCE_TIMEOUT_SECS_ENV,
+                DEFAULT_TIMEOUT.as_secs() as usize,
+            ) {
+                0 => None,
+                secs => Some(Duration::from_secs(secs as u64)),
+            },
+        )
+    }
+
+    /// Let `max_concurrent` generations run at once and `max_queued` more wait from now on.
+    /// Generations already running or waiting keep the slots they were admitted to, so
+    /// until they are done up to the old and the new limit may run side by side.
+    pub fn resize(&self, max_concurrent: usize, max_queued: usize) {
+        let mut permits = self
+            .inner
+            .permits
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if self.inner.max_concurrent.load(Ordering::Relaxed) != max_concurrent {
+            *permits = slots(max_concurrent);
+            self.inner
+                .max_concurrent
+                .store(max_concurrent, Ordering::Relaxed);
+        }
+        self.inner.max_queued.store(max_queued, Ordering::Relaxed);
+    }
+
+    /// Wait for a slot to run a generation in, which is held until the returned permit is
+    /// dropped. Fails with a 429 carrying `Retry-After` when the queue is full.
+    pub async fn acquire(&self) -> Result<InferencePermit, ApiError> {
+        let permits = Arc::clone(
+            &self
+                .inner
+                .permits
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        let permit = match Arc::clone(&permits).try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                let reserved =
+                    self.inner
+                        .queued
+                        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                            let max_queued = self.inner.max_queued.load(Ordering::Relaxed);
+                            (queued < max_queued).then_some(queued + 1)
+                        });
+                if reserved.is_err() {
+                    self.inner.rejected.fetch_add(1, Ordering::Relaxed);
+                    return Err(self.queue_full());
+                }
+                // Leaves the queue however the wait ends, including the client going away
+                let _slot = QueueSlot(&self.inner);
+                permits
+                    .acquire_owned()
+                    .await
+                    .map_err(|_| ApiError::server_error("The inference scheduler was closed"))?
+            }
+        };
+        self.inner.running.fetch_add(1, Ordering::Relaxed);
+        Ok(InferencePermit {
+            inner: Arc::clone(&self.inner),
+            started: Instant::now(),
+            _permit: permit,
+        })
+    }
+
+    /// Start generating `request` on `runner`, in a batch with the other generations that
+    /// arrive for it within the batch window when the runner batches.
+    pub async fn start(
+        &self,
+        runner: Arc<dyn ModelRunner>,
+        request: GenerationRequest,
+    ) -> Result<TokenReceiver, RunnerError> {
+        if self.inner.max_batch <= 1
+            || self.inner.batch_window.is_zero()
+            || !runner.batches_generations()
+        {
+            return runner.generate_stream(request);
+        }
+
+        let key = Arc::as_ptr(&runner) as *const () as usize;
+        let (tx, rx) = oneshot::channel();
+        let full = {
+            let mut forming = self
+                .inner
+                .forming
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            match forming.get_mut(&key) {
+                Some(batch) => {
+                    batch.requests.push((request, tx));
+                    if batch.requests.len() >= self.inner.max_batch {
+                        forming.remove(&key)
+                    } else {
+                        None
+                    }
+                }
+                None => {
+                    // The first generation in sets the batch off once the window closes. A
+                    // task of its own does it, so a client leaving early can't strand the rest
+                    let id = self.inner.next_batch.fetch_add(1, Ordering::Relaxed);
+                    forming.insert(
+                        key,
+                        FormingBatch {
+                            id,
+                            runner,
+                            requests: vec![(request, tx)],
+                        },
+                    );
+                    let inner = Arc::clone(&self.inner);
+                    tokio::spawn(async move {
+                        tokio::time::sleep(inner.batch_window).await;
+                        let batch = {
+                            let mut forming =
+                                inner.forming.lock().unwrap_or_else(PoisonError::into_inner);
+                            match forming.get(&key) {
+                                Some(batch) if batch.id == id => forming.remove(&key),
+                                _ => None,
+                            }
+                        };
+                        if let Some(batch) = batch {
+                            inner.dispatch(batch);
+                        }
+                    });
+                    None
+                }
+            }
+        };
+        if let Some(batch) = full {
+            self.inner.dispatch(batch);
+        }
+        rx.await.unwrap_or_else(|_| {
+            Err(RunnerError::Generation(anyhow::anyhow!(
+                "the batch this generation joined was dropped"
+            )))
+        })
+    }
+
+    fn queue_full(&self) -> ApiError {
+        // A slot frees up about once per average generation divided among the slots
+        let average = Duration::from_millis(self.inner.average_ms.load(Ordering::Relaxed));
+        let max_concurrent = self.inner.max_concurrent.load(Ordering::Relaxed);
+        let retry_after =
+            (average / max_concurrent.max(1) as u32).clamp(Duration::from_secs(1), MAX_RETRY_AFTER);
+        ApiError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limit_exceeded",
+            format!(
+                "The server is busy: {} generations are running and {} waiting. Try again later.",
+                self.inner.running.load(Ordering::Relaxed),
+                self.inner.queued.load(Ordering::Relaxed)
+            ),
+        )
+        .with_retry_after(retry_after)
+    }
+
+    pub fn stats(&self) -> SchedulerStats {
+        SchedulerStats {
+            max_concurrent: self.inner.max_concurrent.load(Ordering::Relaxed),
+            max_queued: self.inner.max_queued.load(Ordering::Relaxed),
+            running: self.inner.running.load(Ordering::Relaxed),
+            queued: self.inner.queued.load(Ordering::Relaxed),
+            rejected: self.inner.rejected.load(Ordering::Relaxed),
+            max_batch: self.inner.max_batch,
+            batch_window_ms: self.inner.batch_window.as_millis() as u64,
+            batched: self.inner.batched.load(Ordering::Relaxed),
+            timeout_secs: self.inner.timeout.map_or(0, |timeout| timeout.as_secs()),
+        }
+    }
+}
+
+/// The slots of `max_concurrent` generations, `0` being as many as a semaphore holds.
+fn slots(max_concurrent: usize) -> Arc<Semaphore> {
+    Arc::new(Semaphore::new(if max_concurrent == 0 {
+        Semaphore::MAX_PERMITS
+    } else {
+        max_concurrent
+    }))
+}
+
+impl Inner {
+    /// Start a batch's generations and hand each its stream. A generation whose client
+    /// has gone drops its stream, which ends only its own row.
+    fn dispatch(&self, batch: FormingBatch) {
+        let (requests, senders): (Vec<_>, Vec<_>) = batch.requests.into_iter().unzip();
+        if requests.len() > 1 {
+            tracing::debug!(
+                "Starting {} generations on {} together",
+                requests.len(),
+                batch.runner.metadata().model_id
+            );
+            self.batched
+                .fetch_add(requests.len() as u64, Ordering::Relaxed);
+        }
+        let streams: Vec<_> = if let [request] = requests.as_slice() {
+            vec![batch.runner.generate_stream(request.clone())]
+        } else {
+            // One request the runner rejects, such as a prompt too long for the model, fails
+            // the batch, so the others are retried on their own and each gets its own answer
+            match batch.runner.generate_streams(requests.clone()) {
+                Ok(streams) => streams.into_iter().map(Ok).collect(),
+                Err(_) => requests
+                    .into_iter()
+                    .map(|request| batch.runner.generate_stream(request))
+                    .collect(),
+            }
+        };
+        for (sender, stream) in senders.into_iter().zip(streams) {
+            let _ = sender.send(stream);
+        }
+    }
+}
+
+struct QueueSlot<'a>(&'a Inner);
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        self.0.queued.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A running generation's slot, given back when dropped.
+pub struct InferencePermit {
+    inner: Arc<Inner>,
+    started: Instant,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl InferencePermit {
+    /// When the generation holding this slot must end: the scheduler's timeout after the
+    /// slot was taken, or `requested` after it when that is sooner. `None` when neither
+    /// sets a limit.
+    pub fn deadline(&self, requested: Option<Duration>) -> Option<Instant> {
+        let timeout = match (self.inner.timeout, requested) {
+            (Some(timeout), Some(requested)) => Some(timeout.min(requested)),
+            (timeout, requested) => timeout.or(requested),
+        };
+        timeout.map(|timeout| self.started + timeout)
+    }
+}
+
+impl Drop for InferencePermit {
+    fn drop(&mut self) {
+        self.inner.running.fetch_sub(1, Ordering::Relaxed);
+        let elapsed = self.started.elapsed().as_millis() as u64;
+        let _ =
+            self.inner
+                .average_ms
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |average| {
+                    Some(if average == 0 {
+                        elapsed
+                    } else {
+                        (average * 7 + elapsed) / 8
+                    })
+                });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runner_core::{RunnerMetadata, TokenEvent, token_channel};
+
+    #[tokio::test]
+    async fn test_requests_queue_then_are_turned_away() {
+        let scheduler = InferenceScheduler::new(1, 1);
+        let running = scheduler.acquire().await.unwrap();
+
+        // The second request waits for the first one's slot
+        let waiting = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.acquire().await.map(drop) }
+        });
+        while scheduler.stats().queued == 0 {
+            tokio::task::yield_now().await;
+        }
+        let error = scheduler.acquire().await.err().unwrap();
+        assert_eq!(error.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(error.retry_after, Some(Duration::from_secs(1)));
+        assert_eq!(scheduler.stats().rejected, 1);
+
+        drop(running);
+        waiting.await.unwrap().unwrap();
+        let stats = scheduler.stats();
+        assert_eq!((stats.running, stats.queued), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_a_request_that_gives_up_leaves_the_queue() {
+        let scheduler = InferenceScheduler::new(1, 1);
+        let _running = scheduler.acquire().await.unwrap();
+        let gave_up = tokio::time::timeout(Duration::from_millis(10), scheduler.acquire()).await;
+        assert!(gave_up.is_err());
+        assert_eq!(scheduler.stats().queued, 0);
+
+        let unlimited = InferenceScheduler::from_vars(|name| {
+            (name == MAX_CONCURRENT_INFERENCES_ENV).then(|| "0".to_string())
+        });
+        let permits: Vec<_> =
+            futures_util::future::join_all((0..64).map(|_| unlimited.acquire())).await;
+        assert!(permits.iter().all(Result::is_ok));
+        assert_eq!(unlimited.stats().running, 64);
+    }
+
+    #[tokio::test]
+    async fn test_resizing_keeps_admitted_requests_on_the_old_slots() {
+        let scheduler = InferenceScheduler::new(1, 0);
+        let old = scheduler.acquire().await.unwrap();
+        assert!(scheduler.acquire().await.is_err());
+
+        scheduler.resize(2, 1);
+        let stats = scheduler.stats();
+        assert_eq!((stats.max_concurrent, stats.max_queued), (2, 1));
+        // The new slots don't count the request admitted before the resize
+        let new = [
+            scheduler.acquire().await.unwrap(),
+            scheduler.acquire().await.unwrap(),
+        ];
+        assert_eq!(scheduler.stats().running, 3);
+        drop(old);
+        let gave_up = tokio::time::timeout(Duration::from_millis(10), scheduler.acquire()).await;
+        assert!(gave_up.is_err());
+        drop(new);
+        scheduler.acquire().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_requests_may_only_shorten_the_deadline() {
+        let scheduler = InferenceScheduler::default().with_timeout(Some(Duration::from_secs(10)));
+        let permit = scheduler.acquire().await.unwrap();
+        let within = |deadline: Option<Instant>, secs: u64| {
+            let left = deadline.unwrap() - Instant::now();
+            left <= Duration::from_secs(secs) && left > Duration::from_secs(secs - 1)
+        };
+        assert!(within(permit.deadline(None), 10));
+        assert!(within(permit.deadline(Some(Duration::from_secs(2))), 2));
+        assert!(within(permit.deadline(Some(Duration::from_secs(60))), 10));
+        assert_eq!(scheduler.stats().timeout_secs, 10);
+
+        let unlimited = InferenceScheduler::from_vars(|name| {
+            (name == INFERENCE_TIMEOUT_SECS_ENV).then(|| "0".to_string())
+        });
+        let permit = unlimited.acquire().await.unwrap();
+        assert_eq!(permit.deadline(None), None);
+        assert!(within(permit.deadline(Some(Duration::from_secs(2))), 2));
+    }
+
+    /// Streams each prompt back as one token, recording the size of every batch.
+    struct EchoRunner {
+        metadata: RunnerMetadata,
+        batches: Mutex<Vec<usize>>,
+    }
+
+    impl ModelRunner for EchoRunner {
+        type Config = ();
+
+        fn load(_: ()) -> Result<Self, RunnerError> {
+            Ok(Self {
+                metadata: RunnerMetadata {
+                    model_id: "echo".to_string(),
+                    repo_id: "echo".to_string(),
+                    family: "echo".to_string(),
+                    owned_by: "test".to_string(),
+                    context_length: 16,
+                    vocab_size: 1,
+                    parameter_count: 0,
+                    dtype: "f32".to_string(),
+                    device: "cpu".to_string(),
+                },
+                batches: Mutex::new(Vec::new()),
+            })
+        }
+
+        fn generate_stream(
+            &self,
+            request: GenerationRequest,
+        ) -> Result<TokenReceiver, RunnerError> {
+            Ok(self.generate_streams(vec![request])?.remove(0))
+        }
+
+        fn generate_streams(
+            &self,
+            requests: Vec<GenerationRequest>,
+        ) -> Result<Vec<TokenReceiver>, RunnerError> {
+            self.batches.lock().unwrap().push(requests.len());
+            Ok(requests
+                .into_iter()
+                .map(|request| {
+                    let (tx, rx) = token_channel();
+                    tx.send(Ok(TokenEvent::generated(0, request.prompt, None)));
+                    rx
+                })
+                .collect())
+        }
+
+        fn batches_generations(&self) -> bool {
+            true
+        }
+
+        fn metadata(&self) -> &RunnerMetadata {
+            &self.metadata
+        }
+
+        fn cancel(&self) {}
+    }
+
+    #[tokio::test]
+    async fn test_generations_arriving_together_share_a_batch() {
+        let echo = Arc::new(EchoRunner::load(()).unwrap());
+        let runner: Arc<dyn ModelRunner> = echo.clone();
+
+        // A full batch starts without waiting out its window, and each generation gets
+        // its own stream back
+        let scheduler = InferenceScheduler::default().with_batching(2, Duration::from_secs(60));
+        let streams =
+            futures_util::future::join_all(["a", "b"].map(|prompt| {
+                scheduler.start(Arc::clone(&runner), GenerationRequest::new(prompt, 1))
+            }))
+            .await;
+        for (stream, prompt) in streams.into_iter().zip(["a", "b"]) {
+            assert_eq!(stream.unwrap().recv().await.unwrap().unwrap().text, prompt);
+        }
+        assert_eq!(*echo.batches.lock().unwrap(), [2]);
+        assert_eq!(scheduler.stats().batched, 2);
+
+        // One arriving alone starts once the window closes
+        let scheduler = InferenceScheduler::default().with_batching(8, Duration::from_millis(1));
+        let mut stream = scheduler
+            .start(Arc::clone(&runner), GenerationRequest::new("c", 1))
+            .await
+            .unwrap();
+        assert_eq!(stream.recv().await.unwrap().unwrap().text, "c");
+        assert_eq!(*echo.batches.lock().unwrap(), [2, 1]);
+        assert_eq!(scheduler.stats().batched, 0);
+    }
+}
@@ -1,29 +1,55 @@
 use axum::{
     Json, Router,
-    extract::State,
+    extract::{Path, State, rejection::JsonRejection},
     http::StatusCode,
     response::{IntoResponse, sse::Event, sse::Sse},
     routing::{get, post},
 };
 use futures_util::stream::{self, Stream};
+use std::collections::BTreeMap;
+use std::collections::hash_map::DefaultHasher;
 use std::convert::Infallible;
-use std::str::FromStr;
-use std::sync::Arc;
-use tokio::sync::{Mutex, mpsc};
-use tokio_stream::wrappers::UnboundedReceiverStream;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::SendTimeoutError;
+use tokio_stream::wrappers::ReceiverStream;
 use tower_http::cors::{Any, CorsLayer};
+use tracing::Instrument;
 use uuid::Uuid;
 
 use crate::Which;
+use crate::device_placement::DevicePlacement;
+use crate::health::{self, Readiness};
+use crate::memory::last_memory_sample;
 use crate::openai_types::{
-    ChatCompletionChoice, ChatCompletionChunk, ChatCompletionChunkChoice, ChatCompletionRequest,
-    ChatCompletionResponse, Delta, Message, MessageContent, Model, ModelListResponse, Usage,
+    ApiError, ChatCompletionCancelResponse, ChatCompletionChoice, ChatCompletionChunk,
+    ChatCompletionChunkChoice, ChatCompletionRequest, ChatCompletionResponse, Delta, Message,
+    MessageContent, Model, ModelDeleteResponse, ModelDetail, ModelListResponse,
+    ModelWarmupResponse, StopTokens, ToolCallDelta, Usage,
 };
-use either::Either;
-use embeddings_engine::models_list;
-use gemma_runner::{GemmaInferenceConfig, WhichModel, run_gemma_api};
-use llama_runner::{LlamaInferenceConfig, run_llama_inference};
+use crate::runner_pool::RunnerPool;
+use crate::runners::{
+    RunnerLoader, Sampling, evict_cached_model, loaded_context_length, pooled_runner,
+};
+use crate::scheduler::{InferenceScheduler, SchedulerStats};
+use crate::streams::ActiveStreams;
+use crate::tools::{self, ToolUse};
+use crate::warm_pool::{self, WarmPoolStatus};
+use async_openai::types::{CreateEmbeddingRequest, EmbeddingInput};
+use clap::ValueEnum;
+use embeddings_engine::{EmbeddingVector, embeddings_create, models_list, requested_dimensions};
+use gemma_runner::GemmaInferenceConfig;
+use llama_runner::LlamaInferenceConfig;
+use model_runner::ModelInferenceConfig;
+use runner_core::{
+    ChatMessage, DeviceReport, FinishReason, GenerationRequest, ModelRunner, Role, RunnerError,
+    RunnerMetadata, SamplingPreset, StreamStats, TokenEvent, TokenReceiver, TokenUsage,
+    device_report, record_stalled_stream, stream_config, stream_stats,
+};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use utoipa::ToSchema;
 // -------------------------
 // Shared app state
 // -------------------------
@@ -37,9 +63,26 @@ pub enum ModelType {
 #[derive(Clone)]
 pub struct AppState {
     pub model_type: Option<ModelType>,
+    /// The model requests for `default` get.
     pub model_id: String,
     pub gemma_config: Option<GemmaInferenceConfig>,
     pub llama_config: Option<LlamaInferenceConfig>,
+    /// Defaults for the Mistral, Qwen2.5 and Phi-3 runners.
+    pub model_config: Option<ModelInferenceConfig>,
+    /// Loads runners in place of the runner crates; `None` uses them.
+    pub runner_loader: Option<RunnerLoader>,
+    /// Runners kept loaded between requests. Clones of the state share it.
+    pub runners: RunnerPool,
+    /// Bounds how many generations run at once and how many wait. Shared like `runners`.
+    pub scheduler: InferenceScheduler,
+    /// Streaming completions in flight, for cancelling one by id. Shared like `runners`.
+    pub streams: ActiveStreams,
+    /// Device each model loads on.
+    pub devices: DevicePlacement,
+    /// The models `/health/ready` waits for. Shared like `runners`.
+    pub readiness: Readiness,
+    /// When the server started, for the uptime `/admin/status` reports.
+    pub started: Instant,
 }
 
 impl Default for AppState {
@@ -59,6 +102,14 @@ impl Default for AppState {
             model_id: default_model_id,
             gemma_config: Some(gemma_config),
             llama_config: None,
+            model_config: None,
+            runner_loader: None,
+            runners: RunnerPool::from_env(),
+            scheduler: InferenceScheduler::from_env(),
+            streams: ActiveStreams::new(),
+            devices: DevicePlacement::from_env(),
+            readiness: Readiness::from_env(),
+            started: Instant::now(),
         }
     }
 }
@@ -67,216 +118,463 @@ impl Default for AppState {
 // Helper functions
 // -------------------------
 
-fn model_id_to_which(model_id: &str) -> Option<Which> {
-    let normalized = normalize_model_id(model_id);
-    match normalized.as_str() {
-        "gemma-2b" => Some(Which::Base2B),
-        "gemma-7b" => Some(Which::Base7B),
-        "gemma-2b-it" => Some(Which::Instruct2B),
-        "gemma-7b-it" => Some(Which::Instruct7B),
-        "gemma-1.1-2b-it" => Some(Which::InstructV1_1_2B),
-        "gemma-1.1-7b-it" => Some(Which::InstructV1_1_7B),
-        "codegemma-2b" => Some(Which::CodeBase2B),
-        "codegemma-7b" => Some(Which::CodeBase7B),
-        "codegemma-2b-it" => Some(Which::CodeInstruct2B),
-        "codegemma-7b-it" => Some(Which::CodeInstruct7B),
-        "gemma-2-2b" => Some(Which::BaseV2_2B),
-        "gemma-2-2b-it" => Some(Which::InstructV2_2B),
-        "gemma-2-9b" => Some(Which::BaseV2_9B),
-        "gemma-2-9b-it" => Some(Which::InstructV2_9B),
-        "gemma-3-1b" => Some(Which::BaseV3_1B),
-        "gemma-3-1b-it" => Some(Which::InstructV3_1B),
-        "llama-3.2-1b" => Some(Which::Llama32_1B),
-        "llama-3.2-1b-instruct" => Some(Which::Llama32_1BInstruct),
-        "llama-3.2-3b" => Some(Which::Llama32_3B),
-        "llama-3.2-3b-instruct" => Some(Which::Llama32_3BInstruct),
-        _ => None,
-    }
+pub(crate) fn model_id_to_which(model_id: &str) -> Option<Which> {
+    Which::from_public_id(&normalize_model_id(model_id))
 }
 
 fn normalize_model_id(model_id: &str) -> String {
     model_id.to_lowercase().replace("_", "-")
 }
 
+/// Render the chat in Gemma's turn format, with system messages in the next user turn.
 fn build_gemma_prompt(messages: &[Message]) -> String {
-    let mut prompt = String::new();
-
-    for message in messages {
-        match message.role.as_str() {
-            "system" => {
-                if let Some(MessageContent(Either::Left(content))) = &message.content {
-                    prompt.push_str(&format!(
-                        "<start_of_turn>system\n{}<end_of_turn>\n",
-                        content
-                    ));
-                }
+    gemma_runner::format_chat_prompt(&chat_messages(messages))
+}
+
+/// The chat's turns as the runner crates' templates take them.
+fn chat_messages(messages: &[Message]) -> Vec<ChatMessage> {
+    messages
+        .iter()
+        .filter_map(|message| {
+            let role = match message.role.as_str() {
+                "system" => Role::System,
+                "user" => Role::User,
+                "assistant" => Role::Assistant,
+                _ => return None,
+            };
+            Some(ChatMessage::new(role, message.text()?))
+        })
+        .collect()
+}
+
+/// Render the whole chat with a Llama instruct model's template, so each turn's prompt
+/// extends the previous one and its retained KV state can be resumed.
+fn build_llama_prompt(which: Which, messages: &[Message]) -> String {
+    let template = llama_runner::WhichModel::from_str(which.public_id(), true)
+        .ok()
+        .and_then(|model| model.chat_template());
+    let Some(template) = template else {
+        // Base models have no chat format; they continue the last message
+        return messages
+            .last()
+            .and_then(Message::text)
+            .unwrap_or_default()
+            .to_string();
+    };
+    template.render(&chat_messages(messages))
+}
+
+/// Render the chat with the template of a model served by `model-runner`.
+fn build_general_prompt(which: Which, messages: &[Message]) -> String {
+    match model_runner::WhichModel::from_str(which.public_id(), true) {
+        Ok(model) => model.chat_template().render(&chat_messages(messages)),
+        Err(_) => build_gemma_prompt(messages),
+    }
+}
+
+fn build_prompt(which: Which, messages: &[Message]) -> String {
+    if which.is_llama_model() {
+        build_llama_prompt(which, messages)
+    } else if which.is_general_model() {
+        build_general_prompt(which, messages)
+    } else {
+        build_gemma_prompt(messages)
+    }
+}
+
+/// Key under which a runner may retain KV state for this conversation: a hash of the
+/// messages up to and including the first user turn, which stays the same as the
+/// conversation grows. Runners check the token prefix before reusing state, so unrelated
+/// conversations that share an opening only miss the cache.
+fn conversation_key(messages: &[Message]) -> Option<String> {
+    let first_user = messages.iter().position(|m| m.role == "user")?;
+    let mut hasher = DefaultHasher::new();
+    for message in &messages[..=first_user] {
+        message.role.hash(&mut hasher);
+        if let Some(MessageContent::Text(content)) = &message.content {
+            content.hash(&mut hasher);
+        }
+    }
+    Some(format!("{:016x}", hasher.finish()))
+}
+
+/// Most stop sequences a request may give, as in OpenAI's API.
+const MAX_STOP_SEQUENCES: usize = 4;
+
+/// Most tokens generated for a request without `max_tokens`, fewer when the prompt leaves
+/// less room in the context window.
+const DEFAULT_MAX_TOKENS: usize = 1000;
+
+/// Build the generation request for a chat, tagged with its conversation key and carrying
+/// its stop sequences. Its prompt and token budget are filled in by [`start_generation`].
+fn generation_request(chat: &ChatCompletionRequest) -> Result<GenerationRequest, ApiError> {
+    let stop = chat.stop.as_ref().map_or(&[][..], StopTokens::as_slice);
+    if stop.len() > MAX_STOP_SEQUENCES {
+        return Err(ApiError::invalid_request(format!(
+            "stop may have at most {} sequences, got {}",
+            MAX_STOP_SEQUENCES,
+            stop.len()
+        ))
+        .with_param("stop"));
+    }
+    let max_tokens = chat.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS);
+    let request = GenerationRequest::new(String::new(), max_tokens).with_stop(stop.to_vec());
+    Ok(match conversation_key(&chat.messages) {
+        Some(key) => request.with_conversation(key),
+        None => request,
+    })
+}
+
+/// Validate the requested model id.
+fn resolve_model(model_id: &str) -> Result<Which, ApiError> {
+    model_id_to_which(model_id).ok_or_else(|| {
+        ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "model_not_supported",
+            format!("Unsupported model: {}", model_id),
+        )
+        .with_param("model")
+    })
+}
+
+/// Sampling settings requested by the client, including the `top_k` and `preset`
+/// extensions. An unknown preset is the client's mistake.
+fn request_sampling(request: &ChatCompletionRequest) -> Result<Sampling, ApiError> {
+    let preset = request
+        .preset
+        .as_deref()
+        .map(str::parse::<SamplingPreset>)
+        .transpose()
+        .map_err(|message| ApiError::invalid_request(message).with_param("preset"))?;
+    if let Some(penalty) = request.frequency_penalty
+        && !(-2.0..=2.0).contains(&penalty)
+    {
+        return Err(ApiError::invalid_request(format!(
+            "frequency_penalty must be between -2.0 and 2.0, got {}",
+            penalty
+        ))
+        .with_param("frequency_penalty"));
+    }
+    Ok(Sampling {
+        preset,
+        temperature: request.temperature,
+        top_p: request.top_p,
+        top_k: request.top_k,
+        no_repeat_ngram_size: request.no_repeat_ngram_size,
+        seed: request.seed,
+        frequency_penalty: request.frequency_penalty.map(|penalty| penalty as f32),
+    })
+}
+
+/// The `timeout` extension of a request, a positive number of seconds.
+fn request_timeout(request: &ChatCompletionRequest) -> Result<Option<Duration>, ApiError> {
+    let Some(secs) = request.timeout else {
+        return Ok(None);
+    };
+    match Duration::try_from_secs_f64(secs) {
+        Ok(timeout) if !timeout.is_zero() => Ok(Some(timeout)),
+        _ => Err(ApiError::invalid_request(format!(
+            "timeout must be a positive number of seconds, got {}",
+            secs
+        ))
+        .with_param("timeout")),
+    }
+}
+
+/// How a chat too long for the model's context window is handled, per the request's
+/// `truncation_strategy`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum TruncationStrategy {
+    /// Reject it with a `context_length_exceeded` error, as OpenAI does.
+    #[default]
+    Error,
+    /// Drop its oldest exchanges, keeping leading system messages, until it fits.
+    DropOldest,
+}
+
+/// The `truncation_strategy` extension of a request.
+fn request_truncation(request: &ChatCompletionRequest) -> Result<TruncationStrategy, ApiError> {
+    match request.truncation_strategy.as_deref() {
+        None | Some("error") => Ok(TruncationStrategy::Error),
+        Some("drop_oldest") => Ok(TruncationStrategy::DropOldest),
+        Some(other) => Err(ApiError::invalid_request(format!(
+            "truncation_strategy must be \"error\" or \"drop_oldest\", got \"{}\"",
+            other
+        ))
+        .with_param("truncation_strategy")),
+    }
+}
+
+/// Reject a chat without messages, which has nothing to complete.
+fn check_messages(request: &ChatCompletionRequest) -> Result<(), ApiError> {
+    if request.messages.is_empty() {
+        return Err(
+            ApiError::invalid_request("messages must hold at least one message")
+                .with_param("messages"),
+        );
+    }
+    Ok(())
+}
+
+/// A generation that reached its deadline without producing any text, e.g. on a stuck
+/// model. One that did is cut short with `finish_reason: "length"` instead.
+fn timeout_error() -> ApiError {
+    ApiError::new(
+        StatusCode::GATEWAY_TIMEOUT,
+        "timeout",
+        "The generation produced no text before its deadline",
+    )
+}
+
+/// Resolves at `deadline`, or never without one.
+async fn deadline_reached(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// HTTP status for a runner failure. Hub access problems are the server's configuration,
+/// not the client's credentials, so they are reported as forbidden rather than unauthorized.
+fn runner_error_status(error: &RunnerError) -> StatusCode {
+    match error {
+        RunnerError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+        RunnerError::ModelNotFound { .. } => StatusCode::NOT_FOUND,
+        RunnerError::Unauthorized { .. } | RunnerError::Gated { .. } => StatusCode::FORBIDDEN,
+        RunnerError::DownloadFailed { .. } => StatusCode::BAD_GATEWAY,
+        RunnerError::OutOfMemory(_) => StatusCode::SERVICE_UNAVAILABLE,
+        RunnerError::UnsupportedDevice(_) | RunnerError::Load(_) | RunnerError::Generation(_) => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Error response for a runner failure, prefixed with what was being attempted.
+fn runner_error_response(context: &str, error: &RunnerError) -> ApiError {
+    ApiError::new(
+        runner_error_status(error),
+        error.kind(),
+        format!("{}: {}", context, error),
+    )
+}
+
+/// The chat a generation is for, rendered into its prompt by [`start_generation`].
+struct ChatPrompt<'a> {
+    messages: &'a [Message],
+    /// The request's `max_tokens`. Without it the generation gets the room the prompt leaves
+    /// in the context window, up to [`DEFAULT_MAX_TOKENS`].
+    max_tokens: Option<usize>,
+    truncation: TruncationStrategy,
+}
+
+/// Render `messages` with the model's own chat template, or the family's built-in format
+/// when it ships none.
+fn render_prompt(
+    runner: &dyn ModelRunner,
+    which: Which,
+    messages: &[Message],
+) -> Result<String, ApiError> {
+    match runner.chat_template() {
+        Some(template) => template
+            .render(&chat_messages(messages))
+            .map_err(|e| runner_error_response("Error applying the chat template", &e)),
+        None => Ok(build_prompt(which, messages)),
+    }
+}
+
+/// Render the chat into a prompt that leaves room in the runner's context window for its
+/// completion, returning the prompt and the completion's token budget.
+///
+/// A chat that doesn't fit fails with `context_length_exceeded`, unless it asked to
+/// `drop_oldest`: then its oldest exchanges, from a user turn up to the next, are dropped
+/// until it fits, keeping leading system messages and the last user turn. Runners that
+/// can't count tokens get the prompt as it is.
+fn fit_context(
+    runner: &dyn ModelRunner,
+    which: Which,
+    chat: &ChatPrompt<'_>,
+) -> Result<(String, usize), ApiError> {
+    let limit = runner.metadata().context_length;
+    let system = chat
+        .messages
+        .iter()
+        .take_while(|message| message.role == "system")
+        .count();
+    let (system, turns) = chat.messages.split_at(system);
+    let mut dropped = 0;
+    loop {
+        let messages: Vec<Message> = system.iter().chain(&turns[dropped..]).cloned().collect();
+        let prompt = render_prompt(runner, which, &messages)?;
+        let Some(prompt_tokens) = runner.count_tokens(&prompt) else {
+            return Ok((prompt, chat.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS)));
+        };
+        let room = limit.saturating_sub(prompt_tokens);
+        let max_tokens = match chat.max_tokens {
+            Some(max_tokens) if max_tokens <= room => Some(max_tokens),
+            None if room > 0 => Some(room.min(DEFAULT_MAX_TOKENS)),
+            _ => None,
+        };
+        if let Some(max_tokens) = max_tokens {
+            if dropped > 0 {
+                tracing::info!(
+                    "Dropped the {} oldest messages of a chat to fit the {} token context of {}",
+                    dropped,
+                    limit,
+                    which.public_id()
+                );
             }
-            "user" => {
-                if let Some(MessageContent(Either::Left(content))) = &message.content {
-                    prompt.push_str(&format!("<start_of_turn>user\n{}<end_of_turn>\n", content));
-                }
+            return Ok((prompt, max_tokens));
+        }
+
+        let next_user = turns
+            .get(dropped + 1..)
+            .unwrap_or_default()
+            .iter()
+            .position(|message| message.role == "user");
+        match next_user {
+            Some(skipped) if chat.truncation == TruncationStrategy::DropOldest => {
+                dropped += skipped + 1;
             }
-            "assistant" => {
-                if let Some(MessageContent(Either::Left(content))) = &message.content {
-                    prompt.push_str(&format!("<start_of_turn>model\n{}<end_of_turn>\n", content));
-                }
+            _ => {
+                let requested = match chat.max_tokens {
+                    Some(max_tokens) => format!(
+                        "{} tokens ({} in the messages, {} in the completion)",
+                        prompt_tokens + max_tokens,
+                        prompt_tokens,
+                        max_tokens
+                    ),
+                    None => format!("{} tokens in the messages", prompt_tokens),
+                };
+                return Err(ApiError::invalid_request(format!(
+                    "This model's maximum context length is {} tokens. However, you requested \
+                     {}. Please reduce the length of the messages or completion, or set \
+                     truncation_strategy to \"drop_oldest\".",
+                    limit, requested
+                ))
+                .with_code("context_length_exceeded")
+                .with_param("messages"));
             }
-            _ => {}
         }
     }
+}
+
+/// Get the runner for `which` and start streaming a completion of `chat`, batched by the
+/// scheduler with others arriving for the same runner. The chat is rendered with the
+/// model's own template when it ships one, else in the family's built-in format, and
+/// fitted to the context window by [`fit_context`].
+async fn start_generation(
+    state: &AppState,
+    which: Which,
+    sampling: Sampling,
+    mut request: GenerationRequest,
+    chat: ChatPrompt<'_>,
+) -> Result<TokenReceiver, ApiError> {
+    let context = format!("Error initializing model {}", which.public_id());
+    let init_error = |e: RunnerError| runner_error_response(&context, &e);
 
-    prompt.push_str("<start_of_turn>model\n");
-    prompt
+    // Loading the weights, from disk or the hub, blocks
+    let runner_state = state.clone();
+    let load =
+        tokio::task::spawn_blocking(move || pooled_runner(which, &runner_state, sampling)).await;
+    let runner = match load {
+        Ok(runner) => runner.map_err(init_error)?,
+        Err(e) => return Err(ApiError::server_error(format!("{}: {}", context, e))),
+    };
+    (request.prompt, request.max_tokens) = fit_context(runner.as_ref(), which, &chat)?;
+    tracing::debug!("Formatted prompt: {}", request.prompt);
+    state
+        .scheduler
+        .start(runner, request)
+        .await
+        .map_err(init_error)
 }
 
 // -------------------------
 // OpenAI-compatible handler
 // -------------------------
 
+/// Generate a chat completion, as one JSON response or, with `stream`, as server-sent events
+#[utoipa::path(
+    post,
+    path = "/v1/chat/completions",
+    tag = "chat",
+    request_body = ChatCompletionRequest,
+    responses(
+        (status = 200, description = "The completion, or with `stream` a `text/event-stream` of chunks ending in `data: [DONE]`", content(
+            ("application/json" = ChatCompletionResponse),
+            ("text/event-stream" = ChatCompletionChunk)
+        )),
+        (status = 400, description = "Unsupported model or invalid request", body = ErrorResponse),
+        (status = 422, description = "The body doesn't match the request schema", body = ErrorResponse),
+        (status = 403, description = "The model's weights are gated or need a token", body = ErrorResponse),
+        (status = 503, description = "Not enough memory to load the model", body = ErrorResponse),
+        (status = 500, description = "Loading the model or generating failed", body = ErrorResponse)
+    )
+)]
 pub async fn chat_completions(
     State(state): State<AppState>,
-    Json(request): Json<ChatCompletionRequest>,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
+    payload: Result<Json<ChatCompletionRequest>, JsonRejection>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Json(mut request) = payload?;
+    // `default`, also what a request without a model gets, names the server's default model
+    if request.model == "default" {
+        request.model = state.model_id.clone();
+    }
+    if let Some(which) = model_id_to_which(&request.model) {
+        warm_pool::record_request(which);
+    }
     if !request.stream.unwrap_or(false) {
         return Ok(chat_completions_non_streaming_proxy(state, request)
-            .await
+            .await?
             .into_response());
     }
     Ok(chat_completions_stream(state, request)
-        .await
+        .await?
         .into_response())
 }
 
 pub async fn chat_completions_non_streaming_proxy(
     state: AppState,
     request: ChatCompletionRequest,
-) -> Result<impl IntoResponse, (StatusCode, Json<Value>)> {
+) -> Result<impl IntoResponse, ApiError> {
     // Use the model specified in the request
     let model_id = request.model.clone();
-    let which_model = model_id_to_which(&model_id);
-
-    // Validate that the requested model is supported
-    let which_model = match which_model {
-        Some(model) => model,
-        None => {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                Json(serde_json::json!({
-                    "error": {
-                        "message": format!("Unsupported model: {}", model_id),
-                        "type": "model_not_supported"
-                    }
-                })),
-            ));
-        }
-    };
-    let max_tokens = request.max_tokens.unwrap_or(1000);
+    let which_model = resolve_model(&model_id)?;
+    check_messages(&request)?;
+    let tool_use = ToolUse::from_request(&request)?;
 
-    // Build prompt based on model type
-    let prompt = if which_model.is_llama_model() {
-        // For Llama, just use the last user message for now
-        request
-            .messages
-            .last()
-            .and_then(|m| m.content.as_ref())
-            .and_then(|c| match c {
-                MessageContent(Either::Left(text)) => Some(text.clone()),
-                _ => None,
-            })
-            .unwrap_or_default()
-    } else {
-        build_gemma_prompt(&request.messages)
+    let messages = tools::prompt_messages(&request.messages, tool_use.as_ref());
+    let sampling = request_sampling(&request)?;
+    let timeout = request_timeout(&request)?;
+    let truncation = request_truncation(&request)?;
+    let generation = generation_request(&request)?;
+
+    // Held until the completion is collected
+    let permit = state.scheduler.acquire().await?;
+    let deadline = permit.deadline(timeout);
+    let chat = ChatPrompt {
+        messages: &messages,
+        max_tokens: request.max_tokens,
+        truncation,
     };
+    let mut rx = start_generation(&state, which_model, sampling, generation, chat).await?;
 
-    // Get streaming receiver based on model type
-    let rx = if which_model.is_llama_model() {
-        // Create Llama configuration dynamically
-        let llama_model = match which_model {
-            Which::Llama32_1B => llama_runner::WhichModel::Llama32_1B,
-            Which::Llama32_1BInstruct => llama_runner::WhichModel::Llama32_1BInstruct,
-            Which::Llama32_3B => llama_runner::WhichModel::Llama32_3B,
-            Which::Llama32_3BInstruct => llama_runner::WhichModel::Llama32_3BInstruct,
-            _ => {
-                return Err((
-                    StatusCode::BAD_REQUEST,
-                    Json(serde_json::json!({
-                        "error": { "message": format!("Model {} is not a Llama model", model_id) }
-                    })),
-                ));
-            }
-        };
-        let mut config = LlamaInferenceConfig::new(llama_model);
-        config.prompt = prompt.clone();
-        config.max_tokens = max_tokens;
-        run_llama_inference(config).map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({
-                    "error": { "message": format!("Error initializing Llama model: {}", e) }
-                })),
-            )
-        })?
-    } else {
-        // Create Gemma configuration dynamically
-        let gemma_model = match which_model {
-            Which::Base2B => gemma_runner::WhichModel::Base2B,
-            Which::Base7B => gemma_runner::WhichModel::Base7B,
-            Which::Instruct2B => gemma_runner::WhichModel::Instruct2B,
-            Which::Instruct7B => gemma_runner::WhichModel::Instruct7B,
-            Which::InstructV1_1_2B => gemma_runner::WhichModel::InstructV1_1_2B,
-            Which::InstructV1_1_7B => gemma_runner::WhichModel::InstructV1_1_7B,
-            Which::CodeBase2B => gemma_runner::WhichModel::CodeBase2B,
-            Which::CodeBase7B => gemma_runner::WhichModel::CodeBase7B,
-            Which::CodeInstruct2B => gemma_runner::WhichModel::CodeInstruct2B,
-            Which::CodeInstruct7B => gemma_runner::WhichModel::CodeInstruct7B,
-            Which::BaseV2_2B => gemma_runner::WhichModel::BaseV2_2B,
-            Which::InstructV2_2B => gemma_runner::WhichModel::InstructV2_2B,
-            Which::BaseV2_9B => gemma_runner::WhichModel::BaseV2_9B,
-            Which::InstructV2_9B => gemma_runner::WhichModel::InstructV2_9B,
-            Which::BaseV3_1B => gemma_runner::WhichModel::BaseV3_1B,
-            Which::InstructV3_1B => gemma_runner::WhichModel::InstructV3_1B,
-            _ => {
-                return Err((
-                    StatusCode::BAD_REQUEST,
-                    Json(serde_json::json!({
-                        "error": { "message": format!("Model {} is not a Gemma model", model_id) }
-                    })),
-                ));
-            }
-        };
+    let (completion, finish_reason, usage) = collect_completion(&mut rx, deadline)
+        .instrument(tracing::info_span!("generation", model = %model_id))
+        .await?;
 
-        let mut config = GemmaInferenceConfig {
-            model: Some(gemma_model),
-            ..Default::default()
-        };
-        config.prompt = prompt.clone();
-        config.max_tokens = max_tokens;
-        run_gemma_api(config).map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({
-                    "error": { "message": format!("Error initializing Gemma model: {}", e) }
-                })),
-            )
-        })?
+    // A reply that parses as calls to the request's functions is returned as those calls
+    let tool_calls = tool_use
+        .as_ref()
+        .and_then(|tool_use| tool_use.parse_calls(&completion));
+    let (message, finish_reason) = match tool_calls {
+        Some(calls) => (Message::assistant_tool_calls(calls), "tool_calls"),
+        None => (
+            Message::assistant(completion.clone()),
+            finish_reason.as_str(),
+        ),
     };
 
-    // Collect all tokens from the stream
-    let mut completion = String::new();
-    while let Ok(token_result) = rx.recv() {
-        match token_result {
-            Ok(token) => completion.push_str(&token),
-            Err(e) => {
-                return Err((
-                    StatusCode::BAD_REQUEST,
-                    Json(serde_json::json!({
-                        "error": { "message": format!("Error generating text: {}", e) }
-                    })),
-                ));
-            }
-        }
-    }
-
     let response = ChatCompletionResponse {
         id: format!("chatcmpl-{}", Uuid::new_v4().to_string().replace('-', "")),
         object: "chat.completion".to_string(),
@@ -287,22 +585,66 @@ pub async fn chat_completions_non_streaming_proxy(
         model: model_id,
         choices: vec![ChatCompletionChoice {
             index: 0,
-            message: Message {
-                role: "assistant".to_string(),
-                content: Some(MessageContent(Either::Left(completion.clone()))),
-                name: None,
-            },
-            finish_reason: "stop".to_string(),
+            message,
+            finish_reason: finish_reason.to_string(),
         }],
-        usage: Usage {
-            prompt_tokens: prompt.len() / 4,
-            completion_tokens: completion.len() / 4,
-            total_tokens: (prompt.len() + completion.len()) / 4,
-        },
+        usage: Usage::new(usage.prompt, usage.completion),
     };
     Ok(Json(response).into_response())
 }
 
+/// Collect all tokens from the stream, with their reason for finishing and the tokens used.
+/// At `deadline` the text so far is returned as cut short by length; the caller dropping
+/// `rx` then stops the runner.
+async fn collect_completion(
+    rx: &mut TokenReceiver,
+    deadline: Option<Instant>,
+) -> Result<(String, FinishReason, TokenUsage), ApiError> {
+    let started = Instant::now();
+    let mut completion = String::new();
+    let mut usage = TokenUsage::default();
+    let mut finish_reason = FinishReason::Stop;
+    loop {
+        let event_result = tokio::select! {
+            event = rx.recv() => match event {
+                Some(event) => event,
+                None => break,
+            },
+            () = deadline_reached(deadline) => {
+                tracing::warn!(
+                    elapsed_ms = started.elapsed().as_millis() as u64,
+                    "Generation reached its deadline"
+                );
+                if completion.is_empty() {
+                    return Err(timeout_error());
+                }
+                finish_reason = FinishReason::Length;
+                break;
+            }
+        };
+        match event_result {
+            Ok(event) => {
+                usage.record(&event);
+                if event.is_prompt {
+                    continue;
+                }
+                completion.push_str(&event.text);
+                if let Some(reason) = event.finish_reason {
+                    finish_reason = reason;
+                }
+            }
+            Err(e) => return Err(runner_error_response("Error generating text", &e)),
+        }
+    }
+    tracing::info!(
+        prompt_tokens = usage.prompt,
+        tokens = usage.completion,
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "Generation finished"
+    );
+    Ok((completion, finish_reason, usage))
+}
+
 // -------------------------
 // Streaming implementation
 // -------------------------
@@ -310,33 +652,17 @@ pub async fn chat_completions_non_streaming_proxy(
 pub async fn chat_completions_stream(
     state: AppState,
     request: ChatCompletionRequest,
-) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, Json<Value>)> {
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
     handle_streaming_request(state, request).await
 }
 
 async fn handle_streaming_request(
     state: AppState,
     request: ChatCompletionRequest,
-) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, Json<Value>)> {
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
     // Use the model specified in the request
     let model_id = request.model.clone();
-    let which_model = model_id_to_which(&model_id);
-
-    // Validate that the requested model is supported
-    let which_model = match which_model {
-        Some(model) => model,
-        None => {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                Json(serde_json::json!({
-                    "error": {
-                        "message": format!("Unsupported model: {}", model_id),
-                        "type": "model_not_supported"
-                    }
-                })),
-            ));
-        }
-    };
+    let which_model = resolve_model(&model_id)?;
 
     // Generate a unique ID and metadata
     let response_id = format!("chatcmpl-{}", Uuid::new_v4().to_string().replace('-', ""));
@@ -344,224 +670,356 @@ async fn handle_streaming_request(
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap_or_default()
         .as_secs();
-    let max_tokens = request.max_tokens.unwrap_or(1000);
+    check_messages(&request)?;
+    let tool_use = ToolUse::from_request(&request)?;
+    let include_usage = request
+        .stream_options
+        .as_ref()
+        .is_some_and(|options| options.include_usage);
 
-    // Build prompt based on model type
-    let prompt = if which_model.is_llama_model() {
-        // For Llama, just use the last user message for now
-        request
-            .messages
-            .last()
-            .and_then(|m| m.content.as_ref())
-            .and_then(|c| match c {
-                MessageContent(Either::Left(text)) => Some(text.clone()),
-                _ => None,
-            })
-            .unwrap_or_default()
-    } else {
-        build_gemma_prompt(&request.messages)
-    };
-    tracing::debug!("Formatted prompt: {}", prompt);
+    let messages = tools::prompt_messages(&request.messages, tool_use.as_ref());
 
-    // Channel for streaming SSE events
-    let (tx, rx) = mpsc::unbounded_channel::<Result<Event, Infallible>>();
+    // Channel for streaming SSE events, bounded like the runner's, so a client that reads
+    // slowly holds the generation back instead of having its events pile up here
+    let (tx, rx) = mpsc::channel::<Result<Event, Infallible>>(stream_config().buffer);
 
     // Send initial role event
-    let initial_chunk = ChatCompletionChunk {
-        id: response_id.clone(),
-        object: "chat.completion.chunk".to_string(),
+    let initial_chunk = stream_chunk(
+        &response_id,
         created,
-        model: model_id.clone(),
-        choices: vec![ChatCompletionChunkChoice {
-            index: 0,
-            delta: Delta {
-                role: Some("assistant".to_string()),
-                content: None,
-            },
-            finish_reason: None,
-        }],
-    };
+        &model_id,
+        Delta {
+            role: Some("assistant".to_string()),
+            content: None,
+            tool_calls: None,
+        },
+        None,
+    );
     if let Ok(json) = serde_json::to_string(&initial_chunk) {
-        let _ = tx.send(Ok(Event::default().data(json)));
+        let _ = tx.try_send(Ok(Event::default().data(json)));
     }
 
-    // Get streaming receiver based on model type
-    let model_rx = if which_model.is_llama_model() {
-        // Create Llama configuration dynamically
-        let llama_model = match which_model {
-            Which::Llama32_1B => llama_runner::WhichModel::Llama32_1B,
-            Which::Llama32_1BInstruct => llama_runner::WhichModel::Llama32_1BInstruct,
-            Which::Llama32_3B => llama_runner::WhichModel::Llama32_3B,
-            Which::Llama32_3BInstruct => llama_runner::WhichModel::Llama32_3BInstruct,
-            _ => {
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(serde_json::json!({
-                        "error": { "message": format!("Model {} is not a Llama model", model_id) }
-                    })),
-                ));
-            }
-        };
-        let mut config = LlamaInferenceConfig::new(llama_model);
-        config.prompt = prompt.clone();
-        config.max_tokens = max_tokens;
-        match run_llama_inference(config) {
-            Ok(rx) => rx,
-            Err(e) => {
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(serde_json::json!({
-                        "error": { "message": format!("Error initializing Llama model: {}", e) }
-                    })),
-                ));
-            }
-        }
-    } else {
-        // Create Gemma configuration dynamically
-        let gemma_model = match which_model {
-            Which::Base2B => gemma_runner::WhichModel::Base2B,
-            Which::Base7B => gemma_runner::WhichModel::Base7B,
-            Which::Instruct2B => gemma_runner::WhichModel::Instruct2B,
-            Which::Instruct7B => gemma_runner::WhichModel::Instruct7B,
-            Which::InstructV1_1_2B => gemma_runner::WhichModel::InstructV1_1_2B,
-            Which::InstructV1_1_7B => gemma_runner::WhichModel::InstructV1_1_7B,
-            Which::CodeBase2B => gemma_runner::WhichModel::CodeBase2B,
-            Which::CodeBase7B => gemma_runner::WhichModel::CodeBase7B,
-            Which::CodeInstruct2B => gemma_runner::WhichModel::CodeInstruct2B,
-            Which::CodeInstruct7B => gemma_runner::WhichModel::CodeInstruct7B,
-            Which::BaseV2_2B => gemma_runner::WhichModel::BaseV2_2B,
-            Which::InstructV2_2B => gemma_runner::WhichModel::InstructV2_2B,
-            Which::BaseV2_9B => gemma_runner::WhichModel::BaseV2_9B,
-            Which::InstructV2_9B => gemma_runner::WhichModel::InstructV2_9B,
-            Which::BaseV3_1B => gemma_runner::WhichModel::BaseV3_1B,
-            Which::InstructV3_1B => gemma_runner::WhichModel::InstructV3_1B,
-            _ => {
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(serde_json::json!({
-                        "error": { "message": format!("Model {} is not a Gemma model", model_id) }
-                    })),
-                ));
-            }
-        };
-
-        let mut config = GemmaInferenceConfig {
-            model: Some(gemma_model),
-            ..Default::default()
-        };
-        config.prompt = prompt.clone();
-        config.max_tokens = max_tokens;
-        match run_gemma_api(config) {
-            Ok(rx) => rx,
-            Err(e) => {
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(serde_json::json!({
-                        "error": { "message": format!("Error initializing Gemma model: {}", e) }
-                    })),
-                ));
-            }
-        }
+    let sampling = request_sampling(&request)?;
+    let timeout = request_timeout(&request)?;
+    let truncation = request_truncation(&request)?;
+    let generation = generation_request(&request)?;
+    let chat = ChatPrompt {
+        messages: &messages,
+        max_tokens: request.max_tokens,
+        truncation,
     };
+    // Taken before the stream starts, so a full queue is answered with a plain 429
+    let permit = state.scheduler.acquire().await?;
+    let deadline = permit.deadline(timeout);
+    let mut model_rx = start_generation(&state, which_model, sampling, generation, chat).await?;
+    // Registered from here on, as every chunk already carries `response_id`
+    let mut registration = state.streams.register(&response_id);
 
-    // Spawn task to receive tokens from model and forward as SSE events
+    // Spawn task to receive tokens from model and forward as SSE events. It outlives the
+    // handler, so it gets its own span under the request's to keep the trace together.
     let response_id_clone = response_id.clone();
     let model_id_clone = model_id.clone();
-    tokio::spawn(async move {
-        // Stream tokens with repetition detection
-        let mut recent_tokens = Vec::new();
-        let mut repetition_count = 0;
-        const MAX_REPETITION_COUNT: usize = 5;
-        const REPETITION_WINDOW: usize = 8;
-
-        while let Ok(token_result) = model_rx.recv() {
-            match token_result {
-                Ok(token) => {
-                    // Skip sending empty tokens
-                    if token.is_empty() {
-                        continue;
-                    }
+    let generation = tracing::info_span!("generation", model = %model_id);
+    tokio::spawn(
+        async move {
+            let started = Instant::now();
+            let mut tokens = 0;
+            // Stream tokens with repetition detection
+            let mut recent_tokens = Vec::new();
+            let mut repetition_count = 0;
+            const MAX_REPETITION_COUNT: usize = 5;
+            const REPETITION_WINDOW: usize = 8;
+            let mut finish_reason = FinishReason::Stop;
+            let mut failure = None;
+            let mut connected = true;
+            let mut cancelled = false;
+            let mut timed_out = false;
+            // With tools on offer, the reply is held back for as long as it may be a call
+            let mut held = tool_use.as_ref().map(|_| String::new());
+            let mut usage = TokenUsage::default();
+            let chunk = |delta, finish_reason| {
+                stream_chunk(
+                    &response_id_clone,
+                    created,
+                    &model_id_clone,
+                    delta,
+                    finish_reason,
+                )
+            };
 
-                    // Add token to recent history for repetition detection
-                    recent_tokens.push(token.clone());
-                    if recent_tokens.len() > REPETITION_WINDOW {
-                        recent_tokens.remove(0);
+            while connected && !cancelled {
+                // Watch for the client going away while waiting, not only when the next
+                // event is sent: a long prefill or a slow model may send nothing for a while
+                let next = tokio::select! {
+                    event = model_rx.recv() => event,
+                    () = tx.closed() => {
+                        connected = false;
+                        None
+                    }
+                    _ = registration.cancelled() => {
+                        cancelled = true;
+                        None
+                    }
+                    () = deadline_reached(deadline) => {
+                        timed_out = true;
+                        None
+                    }
+                };
+                let Some(event_result) = next else {
+                    break;
+                };
+                if let Ok(event) = &event_result {
+                    usage.record(event);
+                }
+                match event_result {
+                    // A named event, which OpenAI clients skip, lets UIs show progress through a
+                    // long prompt and keeps the connection alive until the first token.
+                    Ok(TokenEvent {
+                        prefill: Some(progress),
+                        ..
+                    }) => {
+                        if let Ok(json) = serde_json::to_string(&progress) {
+                            connected =
+                                send_event(&tx, Event::default().event("prefill").data(json)).await;
+                        }
                     }
+                    Ok(event) if event.is_prompt => {}
+                    Ok(event) => {
+                        if let Some(reason) = event.finish_reason {
+                            finish_reason = reason;
+                        }
+                        let token = event.text;
+
+                        // Skip sending empty tokens
+                        if token.is_empty() {
+                            continue;
+                        }
+                        tokens += 1;
+
+                        // Add token to recent history for repetition detection
+                        recent_tokens.push(token.clone());
+                        if recent_tokens.len() > REPETITION_WINDOW {
+                            recent_tokens.remove(0);
+                        }
+
+                        // Check for repetitive patterns
+                        if recent_tokens.len() >= 4 {
+                            let last_token = &recent_tokens[recent_tokens.len() - 1];
+                            let second_last = &recent_tokens[recent_tokens.len() - 2];
 
-                    // Check for repetitive patterns
-                    if recent_tokens.len() >= 4 {
-                        let last_token = &recent_tokens[recent_tokens.len() - 1];
-                        let second_last = &recent_tokens[recent_tokens.len() - 2];
-
-                        if last_token == second_last {
-                            repetition_count += 1;
-                            tracing::warn!(
-                                "Detected repetition pattern: '{}' (count: {})",
-                                last_token,
-                                repetition_count
-                            );
-
-                            if repetition_count >= MAX_REPETITION_COUNT {
-                                tracing::info!("Stopping generation due to excessive repetition");
-                                break;
+                            if last_token == second_last {
+                                repetition_count += 1;
+                                tracing::warn!(
+                                    "Detected repetition pattern: '{}' (count: {})",
+                                    last_token,
+                                    repetition_count
+                                );
+
+                                if repetition_count >= MAX_REPETITION_COUNT {
+                                    tracing::info!(
+                                        "Stopping generation due to excessive repetition"
+                                    );
+                                    break;
+                                }
+                            } else {
+                                repetition_count = 0;
                             }
-                        } else {
-                            repetition_count = 0;
                         }
+
+                        let content = match held.as_mut() {
+                            Some(text) => {
+                                text.push_str(&token);
+                                if tools::may_be_tool_call(text) {
+                                    continue;
+                                }
+                                held.take().unwrap_or_default()
+                            }
+                            None => token,
+                        };
+                        let chunk = chunk(Delta::content(content), None);
+                        if let Ok(json) = serde_json::to_string(&chunk) {
+                            connected = send_event(&tx, Event::default().data(json)).await;
+                        }
+                    }
+                    Err(e) => {
+                        tracing::info!("Text generation stopped: {}", e);
+                        failure = Some(runner_error_response("Error generating text", &e));
+                        break;
                     }
+                }
+            }
 
-                    let chunk = ChatCompletionChunk {
-                        id: response_id_clone.clone(),
-                        object: "chat.completion.chunk".to_string(),
-                        created,
-                        model: model_id_clone.clone(),
-                        choices: vec![ChatCompletionChunkChoice {
-                            index: 0,
-                            delta: Delta {
+            // Dropping the runner's stream stops the generation of a client that's gone: the
+            // runner sees its sends fail and ends the prefill or decode loop
+            drop(model_rx);
+            drop(permit);
+            drop(registration);
+            if cancelled {
+                tracing::info!(tokens, "Generation cancelled");
+                finish_reason = FinishReason::Cancelled;
+            }
+            if timed_out {
+                tracing::warn!(
+                    tokens,
+                    elapsed_ms = started.elapsed().as_millis() as u64,
+                    "Generation reached its deadline"
+                );
+                if tokens == 0 {
+                    failure = Some(timeout_error());
+                } else {
+                    finish_reason = FinishReason::Length;
+                }
+            }
+            if !connected {
+                tracing::info!(
+                    tokens,
+                    elapsed_ms = started.elapsed().as_millis() as u64,
+                    "Client stopped reading, generation dropped"
+                );
+                return;
+            }
+
+            // The status has been sent by now, so a failure is reported as an event carrying
+            // the error body in place of the final stop chunk
+            if let Some(error) = failure {
+                if let Ok(json) = serde_json::to_string(&error.body) {
+                    send_event(&tx, Event::default().data(json)).await;
+                }
+            } else {
+                let mut finish_reason = finish_reason.as_str();
+                // A reply held back to the end is sent as the calls it makes, or as text
+                if let Some(text) = held.take().filter(|text| !text.is_empty()) {
+                    let delta = match tool_use.as_ref().and_then(|t| t.parse_calls(&text)) {
+                        Some(calls) => {
+                            finish_reason = "tool_calls";
+                            Delta {
                                 role: None,
-                                content: Some(token),
-                            },
-                            finish_reason: None,
-                        }],
+                                content: None,
+                                tool_calls: Some(
+                                    calls
+                                        .into_iter()
+                                        .enumerate()
+                                        .map(|(index, call)| ToolCallDelta::whole(index, call))
+                                        .collect(),
+                                ),
+                            }
+                        }
+                        None => Delta::content(text),
                     };
-
-                    if let Ok(json) = serde_json::to_string(&chunk) {
-                        let _ = tx.send(Ok(Event::default().data(json)));
+                    if let Ok(json) = serde_json::to_string(&chunk(delta, None)) {
+                        send_event(&tx, Event::default().data(json)).await;
                     }
                 }
-                Err(e) => {
-                    tracing::info!("Text generation stopped: {}", e);
-                    break;
+                let final_chunk = chunk(
+                    Delta {
+                        role: None,
+                        content: None,
+                        tool_calls: None,
+                    },
+                    Some(finish_reason.to_string()),
+                );
+                if let Ok(json) = serde_json::to_string(&final_chunk) {
+                    send_event(&tx, Event::default().data(json)).await;
+                }
+                // As in OpenAI's API, the usage comes last, in a chunk without choices
+                if include_usage {
+                    let usage_chunk = ChatCompletionChunk {
+                        choices: Vec::new(),
+                        usage: Some(Usage::new(usage.prompt, usage.completion)),
+                        ..chunk(Delta::content(""), None)
+                    };
+                    if let Ok(json) = serde_json::to_string(&usage_chunk) {
+                        send_event(&tx, Event::default().data(json)).await;
+                    }
                 }
             }
+            send_event(&tx, Event::default().data("[DONE]")).await;
+            tracing::info!(
+                tokens,
+                elapsed_ms = started.elapsed().as_millis() as u64,
+                "Generation finished"
+            );
         }
-
-        // Send final stop chunk and DONE marker
-        let final_chunk = ChatCompletionChunk {
-            id: response_id_clone.clone(),
-            object: "chat.completion.chunk".to_string(),
-            created,
-            model: model_id_clone.clone(),
-            choices: vec![ChatCompletionChunkChoice {
-                index: 0,
-                delta: Delta {
-                    role: None,
-                    content: None,
-                },
-                finish_reason: Some("stop".to_string()),
-            }],
-        };
-        if let Ok(json) = serde_json::to_string(&final_chunk) {
-            let _ = tx.send(Ok(Event::default().data(json)));
-        }
-        let _ = tx.send(Ok(Event::default().data("[DONE]")));
-    });
+        .instrument(generation),
+    );
 
     // Convert receiver into a Stream for SSE
-    let stream = UnboundedReceiverStream::new(rx);
+    let stream = ReceiverStream::new(rx);
     Ok(Sse::new(stream))
 }
 
+/// A chunk of the streamed completion `id` with one choice.
+fn stream_chunk(
+    id: &str,
+    created: u64,
+    model: &str,
+    delta: Delta,
+    finish_reason: Option<String>,
+) -> ChatCompletionChunk {
+    ChatCompletionChunk {
+        id: id.to_string(),
+        object: "chat.completion.chunk".to_string(),
+        created,
+        model: model.to_string(),
+        choices: vec![ChatCompletionChunkChoice {
+            index: 0,
+            delta,
+            finish_reason,
+        }],
+        usage: None,
+    }
+}
+
+/// Send `event` to a streaming client, waiting while its buffer is full. Returns `false`
+/// when the client has gone or has stopped reading for longer than the stall timeout.
+async fn send_event(tx: &mpsc::Sender<Result<Event, Infallible>>, event: Event) -> bool {
+    match tx
+        .send_timeout(Ok(event), stream_config().stall_timeout)
+        .await
+    {
+        Ok(()) => true,
+        Err(SendTimeoutError::Timeout(_)) => {
+            tracing::warn!(
+                "Client stopped reading for {:?}, dropping its stream",
+                stream_config().stall_timeout
+            );
+            record_stalled_stream();
+            false
+        }
+        Err(SendTimeoutError::Closed(_)) => false,
+    }
+}
+
+/// Handler for POST /v1/chat/completions/{id}/cancel - stops a streaming completion by the
+/// `id` of its chunks. The stream ends like any other, with a final chunk whose
+/// `finish_reason` is `cancelled`, the usage if it was asked for, and `data: [DONE]`.
+#[utoipa::path(
+    post,
+    path = "/v1/chat/completions/{id}/cancel",
+    tag = "chat",
+    params(("id" = String, Path, description = "Completion id, e.g. chatcmpl-0123abcd")),
+    responses(
+        (status = 200, description = "The generation was stopped", body = ChatCompletionCancelResponse),
+        (status = 404, description = "No streaming completion with this id is in flight", body = ErrorResponse)
+    )
+)]
+pub async fn cancel_chat_completion(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<ChatCompletionCancelResponse>, ApiError> {
+    if !state.streams.cancel(&id) {
+        return Err(ApiError::not_found(format!(
+            "No streaming completion {} in flight",
+            id
+        )));
+    }
+    Ok(Json(ChatCompletionCancelResponse {
+        id,
+        object: "chat.completion.cancel".to_string(),
+        cancelled: true,
+    }))
+}
+
 // -------------------------
 // Router
 // -------------------------
@@ -575,81 +1033,69 @@ pub fn create_router(app_state: AppState) -> Router {
 
     Router::new()
         .route("/v1/chat/completions", post(chat_completions))
+        .route(
+            "/v1/chat/completions/{id}/cancel",
+            post(cancel_chat_completion),
+        )
         .route("/v1/models", get(list_models))
+        .route("/v1/models/{id}", get(get_model).delete(delete_model))
+        .route("/v1/models/{id}/load", post(load_model))
+        .route("/v1/models/{id}/warmup", post(warmup_model))
+        .route("/v1/embeddings", post(create_embeddings))
+        .route("/admin/device", get(device_info))
+        .route("/admin/status", get(admin_status))
+        .route("/health/ready", get(health::ready))
         .layer(cors)
         .with_state(app_state)
 }
 
 /// Handler for GET /v1/models - returns list of available models
+#[utoipa::path(
+    get,
+    path = "/v1/models",
+    tag = "models",
+    responses(
+        (status = 200, description = "The chat and embedding models, with whether each is loaded", body = ModelListResponse)
+    )
+)]
 pub async fn list_models() -> Json<ModelListResponse> {
     // Get all available model variants from the Which enum
-    let which_variants = vec![
-        Which::Base2B,
-        Which::Base7B,
-        Which::Instruct2B,
-        Which::Instruct7B,
-        Which::InstructV1_1_2B,
-        Which::InstructV1_1_7B,
-        Which::CodeBase2B,
-        Which::CodeBase7B,
-        Which::CodeInstruct2B,
-        Which::CodeInstruct7B,
-        Which::BaseV2_2B,
-        Which::InstructV2_2B,
-        Which::BaseV2_9B,
-        Which::InstructV2_9B,
-        Which::BaseV3_1B,
-        Which::InstructV3_1B,
-        Which::Llama32_1B,
-        Which::Llama32_1BInstruct,
-        Which::Llama32_3B,
-        Which::Llama32_3BInstruct,
-    ];
-
-    let mut models: Vec<Model> = which_variants
-        .into_iter()
+    let mut models: Vec<Model> = Which::value_variants()
+        .iter()
         .map(|which| {
-            let meta = which.meta();
-            let model_id = match which {
-                Which::Base2B => "gemma-2b",
-                Which::Base7B => "gemma-7b",
-                Which::Instruct2B => "gemma-2b-it",
-                Which::Instruct7B => "gemma-7b-it",
-                Which::InstructV1_1_2B => "gemma-1.1-2b-it",
-                Which::InstructV1_1_7B => "gemma-1.1-7b-it",
-                Which::CodeBase2B => "codegemma-2b",
-                Which::CodeBase7B => "codegemma-7b",
-                Which::CodeInstruct2B => "codegemma-2b-it",
-                Which::CodeInstruct7B => "codegemma-7b-it",
-                Which::BaseV2_2B => "gemma-2-2b",
-                Which::InstructV2_2B => "gemma-2-2b-it",
-                Which::BaseV2_9B => "gemma-2-9b",
-                Which::InstructV2_9B => "gemma-2-9b-it",
-                Which::BaseV3_1B => "gemma-3-1b",
-                Which::InstructV3_1B => "gemma-3-1b-it",
-                Which::Llama32_1B => "llama-3.2-1b",
-                Which::Llama32_1BInstruct => "llama-3.2-1b-instruct",
-                Which::Llama32_3B => "llama-3.2-3b",
-                Which::Llama32_3BInstruct => "llama-3.2-3b-instruct",
-            };
-
-            let owned_by = if meta.id.starts_with("google/") {
-                "google"
-            } else if meta.id.starts_with("meta-llama/") {
-                "meta"
-            } else {
-                "unknown"
-            };
-
+            let context_length = loaded_context_length(*which);
             Model {
-                id: model_id.to_string(),
+                id: which.public_id().to_string(),
                 object: "model".to_string(),
                 created: 1686935002,
-                owned_by: owned_by.to_string(),
+                owned_by: which.owned_by().to_string(),
+                family: Some(which.meta().family.as_str().to_string()),
+                context_length: context_length.or(Some(which.meta().context_length)),
+                loaded: Some(context_length.is_some()),
             }
         })
         .collect();
 
+    // Models that can also mean-pool their hidden states into embeddings
+    let decoder_embedding_models: Vec<Model> = Which::value_variants()
+        .iter()
+        .filter_map(|which| {
+            which.embedding_id().map(|id| {
+                let context_length = loaded_context_length(*which);
+                Model {
+                    id,
+                    object: "model".to_string(),
+                    created: 1686935002,
+                    owned_by: format!("{} - mean-pooled hidden states", which.owned_by()),
+                    family: Some(which.meta().family.as_str().to_string()),
+                    context_length: context_length.or(Some(which.meta().context_length)),
+                    loaded: Some(context_length.is_some()),
+                }
+            })
+        })
+        .collect();
+    models.extend(decoder_embedding_models);
+
     // Get embeddings models and convert them to inference Model format
     let embeddings_response = models_list().await;
     let embeddings_models: Vec<Model> = embeddings_response
@@ -664,6 +1110,9 @@ pub async fn list_models() -> Json<ModelListResponse> {
                 "{} - {}",
                 embedding_model.owned_by, embedding_model.description
             ),
+            family: None,
+            context_length: None,
+            loaded: None,
         })
         .collect();
 
@@ -676,40 +1125,561 @@ pub async fn list_models() -> Json<ModelListResponse> {
     })
 }
 
+/// Handler for GET /v1/models/{id} - describes one chat model: its context length, size,
+/// capabilities and quantization, and whether it is loaded and on which device
+#[utoipa::path(
+    get,
+    path = "/v1/models/{id}",
+    tag = "models",
+    params(("id" = String, Path, description = "Model id, e.g. gemma-3-1b-it")),
+    responses(
+        (status = 200, description = "The model", body = ModelDetail),
+        (status = 404, description = "No such model", body = ErrorResponse)
+    )
+)]
+pub async fn get_model(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<ModelDetail>, ApiError> {
+    let which = model_id_to_which(&id).ok_or_else(|| {
+        ApiError::not_found(format!("The model {} does not exist", id))
+            .with_code("model_not_found")
+            .with_param("model")
+    })?;
+    Ok(Json(model_detail(which, state.runners.metadata(which))))
+}
+
+/// Handler for POST /v1/models/{id}/load - loads the model's weights into the runner cache,
+/// if they aren't already, and describes it
+#[utoipa::path(
+    post,
+    path = "/v1/models/{id}/load",
+    tag = "models",
+    params(("id" = String, Path, description = "Model id, e.g. gemma-3-1b-it")),
+    responses(
+        (status = 200, description = "The loaded model", body = ModelDetail),
+        (status = 400, description = "Unsupported model", body = ErrorResponse),
+        (status = 403, description = "The model's weights are gated or need a token", body = ErrorResponse),
+        (status = 503, description = "Not enough memory to load the model", body = ErrorResponse),
+        (status = 500, description = "Loading the model failed", body = ErrorResponse)
+    )
+)]
+pub async fn load_model(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<ModelDetail>, ApiError> {
+    let which = resolve_model(&id)?;
+    let context = format!("Error loading model {}", which.public_id());
+    let load = tokio::task::spawn_blocking(move || {
+        pooled_runner(which, &state, Sampling::default()).map(|runner| runner.metadata().clone())
+    })
+    .await;
+    match load {
+        Ok(Ok(metadata)) => Ok(Json(model_detail(which, Some(metadata)))),
+        Ok(Err(e)) => Err(runner_error_response(&context, &e)),
+        Err(e) => Err(ApiError::server_error(format!("{}: {}", context, e))),
+    }
+}
+
+/// Handler for DELETE /v1/models/{id} - drops the model's pooled runners and cached
+/// weights to free their memory. Generations still running keep the weights until they
+/// finish; the next request for the model loads it again
+#[utoipa::path(
+    delete,
+    path = "/v1/models/{id}",
+    tag = "models",
+    params(("id" = String, Path, description = "Model id, e.g. gemma-3-1b-it")),
+    responses(
+        (status = 200, description = "The model is unloaded", body = ModelDeleteResponse),
+        (status = 404, description = "No such model", body = ErrorResponse)
+    )
+)]
+pub async fn delete_model(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<ModelDeleteResponse>, ApiError> {
+    let which = model_id_to_which(&id).ok_or_else(|| {
+        ApiError::not_found(format!("The model {} does not exist", id))
+            .with_code("model_not_found")
+            .with_param("model")
+    })?;
+    // Pooled runners hold the weights, so they go first
+    let released = state.runners.remove(which);
+    let evicted = evict_cached_model(which);
+    if released + evicted > 0 {
+        tracing::info!(
+            "Unloaded {}: {} pooled runners, {} cached models",
+            which.public_id(),
+            released,
+            evicted
+        );
+    }
+    Ok(Json(ModelDeleteResponse {
+        id: which.public_id().to_string(),
+        object: "model".to_string(),
+        deleted: released + evicted > 0,
+    }))
+}
+
+/// Describe `which`, with what its pooled runner reports when one has it loaded.
+fn model_detail(which: Which, runner: Option<RunnerMetadata>) -> ModelDetail {
+    let meta = which.meta();
+    let mut capabilities = vec![if meta.instruct { "chat" } else { "completion" }];
+    if which.is_code_model() {
+        capabilities.push("code");
+    }
+    if which.supports_embeddings() {
+        capabilities.push("embeddings");
+    }
+    // Weights can stay cached after the runners using them were dropped
+    let loaded = runner.is_some() || loaded_context_length(which).is_some();
+    let context_length = runner
+        .as_ref()
+        .map(|runner| runner.context_length)
+        .or_else(|| loaded_context_length(which))
+        .unwrap_or(meta.context_length);
+    let parameters = runner
+        .as_ref()
+        .map(|runner| runner.parameter_count)
+        .filter(|count| *count > 0)
+        .unwrap_or(meta.parameters);
+    ModelDetail {
+        id: which.public_id().to_string(),
+        object: "model".to_string(),
+        created: 1686935002,
+        owned_by: which.owned_by().to_string(),
+        family: meta.family.as_str().to_string(),
+        repository: meta.id.to_string(),
+        context_length,
+        parameters,
+        capabilities: capabilities.into_iter().map(str::to_string).collect(),
+        quantization: which.quantization().map(str::to_string),
+        dtype: runner.as_ref().map(|runner| runner.dtype.clone()),
+        device: runner.map(|runner| runner.device),
+        status: if loaded { "loaded" } else { "cold" }.to_string(),
+    }
+}
+
+/// Handler for POST /v1/models/{id}/warmup - loads the model's weights into the runner
+/// cache and runs a short throwaway generation, so the first real request doesn't pay for
+/// either
+#[utoipa::path(
+    post,
+    path = "/v1/models/{id}/warmup",
+    tag = "models",
+    params(("id" = String, Path, description = "Model id, e.g. gemma-3-1b-it")),
+    responses(
+        (status = 200, description = "The model is loaded", body = ModelWarmupResponse),
+        (status = 400, description = "Unsupported model", body = ErrorResponse),
+        (status = 403, description = "The model's weights are gated or need a token", body = ErrorResponse),
+        (status = 503, description = "Not enough memory to load the model", body = ErrorResponse),
+        (status = 500, description = "Loading the model failed", body = ErrorResponse)
+    )
+)]
+pub async fn warmup_model(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<ModelWarmupResponse>, ApiError> {
+    let which = resolve_model(&id)?;
+    let context = format!("Error warming up model {}", which.public_id());
+    let warmup = tokio::task::spawn_blocking(move || {
+        let start = std::time::Instant::now();
+        let runner = pooled_runner(which, &state, Sampling::default())?;
+        runner.warmup()?;
+        Ok::<_, RunnerError>((runner.metadata().context_length, start.elapsed()))
+    })
+    .await;
+    match warmup {
+        Ok(Ok((context_length, elapsed))) => Ok(Json(ModelWarmupResponse {
+            id: which.public_id().to_string(),
+            object: "model.warmup".to_string(),
+            context_length,
+            warmup_ms: elapsed.as_millis() as u64,
+        })),
+        Ok(Err(e)) => Err(runner_error_response(&context, &e)),
+        Err(e) => Err(ApiError::server_error(format!("{}: {}", context, e))),
+    }
+}
+
+/// Handler for POST /v1/embeddings - serves the decoder embedding ids (see
+/// [`Which::embedding_id`]) and passes every other model on to the embeddings engine
+#[utoipa::path(
+    post,
+    path = "/v1/embeddings",
+    tag = "embeddings",
+    request_body = EmbeddingRequest,
+    responses(
+        (status = 200, description = "One embedding per input text", body = EmbeddingResponse),
+        (status = 400, description = "Unknown model, token id input, or dimensions the model can't shorten its embeddings to", body = ErrorResponse),
+        (status = 422, description = "The body doesn't match the request schema", body = ErrorResponse),
+        (status = 500, description = "Loading the model or computing the embeddings failed", body = ErrorResponse)
+    )
+)]
+pub async fn create_embeddings(
+    State(state): State<AppState>,
+    payload: Result<Json<CreateEmbeddingRequest>, JsonRejection>,
+) -> axum::response::Response {
+    let payload = match payload {
+        Ok(Json(payload)) => payload,
+        Err(rejection) => return ApiError::from(rejection).into_response(),
+    };
+    let Some(which) = Which::from_embedding_id(&payload.model) else {
+        return embeddings_create(Ok(Json(payload))).await.into_response();
+    };
+    let texts = match payload.input {
+        EmbeddingInput::String(text) => vec![text],
+        EmbeddingInput::StringArray(texts) => texts,
+        EmbeddingInput::IntegerArray(_) | EmbeddingInput::ArrayOfIntegerArray(_) => {
+            return ApiError::invalid_request("Token id input is not supported")
+                .with_param("input")
+                .into_response();
+        }
+    };
+
+    // Loading the runner and running the forward passes both block.
+    let embeddings = tokio::task::spawn_blocking(move || {
+        let runner = pooled_runner(which, &state, Sampling::default())?;
+        let tokens: usize = texts
+            .iter()
+            .filter_map(|text| runner.count_tokens(text))
+            .sum();
+        Ok::<_, RunnerError>((runner.embed(&texts)?, tokens))
+    })
+    .await;
+    let (embeddings, tokens) = match embeddings {
+        Ok(Ok(embeddings)) => embeddings,
+        Ok(Err(e)) => {
+            return runner_error_response("Error computing embeddings", &e).into_response();
+        }
+        Err(e) => {
+            return runner_error_response(
+                "Error computing embeddings",
+                &RunnerError::Generation(anyhow::anyhow!("embedding task failed: {e}")),
+            )
+            .into_response();
+        }
+    };
+
+    // Hidden states can't be shortened, so `dimensions` may only name their full size
+    if let Some(embedding) = embeddings.first()
+        && let Err(e) = requested_dimensions(payload.dimensions, embedding.len(), false)
+    {
+        return e.into_response();
+    }
+
+    let data: Vec<Value> = embeddings
+        .into_iter()
+        .enumerate()
+        .map(|(index, embedding)| {
+            serde_json::json!({
+                "object": "embedding",
+                "index": index,
+                "embedding": EmbeddingVector::encode(embedding, payload.encoding_format.as_ref())
+            })
+        })
+        .collect();
+    Json(serde_json::json!({
+        "object": "list",
+        "data": data,
+        "model": payload.model,
+        "usage": {
+            "prompt_tokens": tokens,
+            "total_tokens": tokens
+        }
+    }))
+    .into_response()
+}
+
+/// Handler for GET /admin/device - reports the CPU features and GPUs available for inference
+#[utoipa::path(
+    get,
+    path = "/admin/device",
+    tag = "admin",
+    responses(
+        (status = 200, description = "CPU cores and SIMD features, and the CUDA and Metal devices", body = Object),
+        (status = 500, description = "Probing the devices failed", body = ErrorResponse)
+    )
+)]
+pub async fn device_info() -> Result<Json<DeviceReport>, ApiError> {
+    // Probing a GPU opens a driver context, which blocks.
+    match tokio::task::spawn_blocking(device_report).await {
+        Ok(report) => Ok(Json(report)),
+        Err(e) => Err(ApiError::server_error(format!(
+            "Device probe failed: {}",
+            e
+        ))),
+    }
+}
+
+/// The models the server has loaded and what keeps them there.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AdminStatus {
+    /// Always `admin.status`
+    pub object: String,
+    /// The chat models whose weights are loaded
+    pub loaded_models: Vec<String>,
+    /// The warm pool's last pass, `None` when it isn't running
+    pub warm_pool: Option<WarmPoolStatus>,
+    /// How token streams have kept up with their clients: buffer size, waits on full
+    /// buffers, and streams dropped for stalled or disconnected clients
+    #[schema(value_type = Object)]
+    pub streams: StreamStats,
+    /// Streaming completions in flight
+    pub active_streams: usize,
+    /// Generations running and waiting for a slot
+    pub scheduler: SchedulerStats,
+    /// Memory in use and what the loaded models take of it
+    pub memory: MemoryStatus,
+    /// Seconds since the server started
+    pub uptime_secs: u64,
+}
+
+/// The server's memory as the memory monitor last sampled it, and estimates of what the
+/// loaded models' weights take.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MemoryStatus {
+    /// Resident memory of the process in bytes; `None` before the monitor's first sample,
+    /// off Linux, or without the monitor
+    pub resident_bytes: Option<u64>,
+    /// Share of memory in use on the fullest CUDA device, from `0` to `1`
+    pub device_used_fraction: Option<f64>,
+    /// Bytes the weights of each loaded chat model are estimated to take
+    pub models: BTreeMap<String, u64>,
+}
+
+/// Bytes `which`'s weights take as `runner` loaded them, from the parameter count: 4.5 bits
+/// a weight for Q4_K_M, 4 bytes for f32 and 2 for the half-precision dtypes.
+fn estimated_weight_bytes(which: Which, runner: Option<&RunnerMetadata>) -> u64 {
+    let parameters = runner
+        .map(|runner| runner.parameter_count)
+        .filter(|count| *count > 0)
+        .unwrap_or(which.meta().parameters);
+    if which.quantization().is_some() {
+        return parameters.saturating_mul(9) / 16;
+    }
+    match runner.map(|runner| runner.dtype.as_str()) {
+        Some("f32") => parameters.saturating_mul(4),
+        _ => parameters.saturating_mul(2),
+    }
+}
+
+/// Handler for GET /admin/status - reports the loaded models, the warm pool, how token
+/// streams keep up with their clients, the generations running and queued, memory and
+/// uptime
+#[utoipa::path(
+    get,
+    path = "/admin/status",
+    tag = "admin",
+    responses(
+        (status = 200, description = "The loaded models, what the warm pool keeps warm, has predicted and has evicted, the token stream counters, the scheduler's queue, memory in use and estimated per model, and uptime", body = AdminStatus)
+    )
+)]
+pub async fn admin_status(State(state): State<AppState>) -> Json<AdminStatus> {
+    let loaded: Vec<Which> = Which::value_variants()
+        .iter()
+        .copied()
+        .filter(|which| loaded_context_length(*which).is_some())
+        .collect();
+    let sample = last_memory_sample();
+    Json(AdminStatus {
+        object: "admin.status".to_string(),
+        loaded_models: loaded
+            .iter()
+            .map(|which| which.public_id().to_string())
+            .collect(),
+        warm_pool: warm_pool::warm_pool_status(),
+        streams: stream_stats(),
+        active_streams: state.streams.len(),
+        scheduler: state.scheduler.stats(),
+        memory: MemoryStatus {
+            resident_bytes: sample.as_ref().and_then(|sample| sample.resident_bytes),
+            device_used_fraction: sample
+                .as_ref()
+                .and_then(|sample| sample.device_used_fraction()),
+            models: loaded
+                .iter()
+                .map(|which| {
+                    let runner = state.runners.metadata(*which);
+                    (
+                        which.public_id().to_string(),
+                        estimated_weight_bytes(*which, runner.as_ref()),
+                    )
+                })
+                .collect(),
+        },
+        uptime_secs: state.started.elapsed().as_secs(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::openai_types::{Message, MessageContent};
-    use either::Either;
+
+    #[test]
+    fn test_runner_errors_map_to_http_status() {
+        let status = |error| runner_error_status(&error);
+        assert_eq!(
+            status(RunnerError::InvalidRequest("too long".to_string())),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(RunnerError::Gated {
+                repo: "google/gemma-2-9b-it".to_string()
+            }),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(RunnerError::OutOfMemory(anyhow::anyhow!(
+                "CUDA_ERROR_OUT_OF_MEMORY"
+            ))),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            status(RunnerError::Generation(anyhow::anyhow!("bad token"))),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn test_embedding_ids_cover_models_with_hidden_states() {
+        assert_eq!(
+            Which::Instruct2B.embedding_id().as_deref(),
+            Some("gemma-2b-it-embed")
+        );
+        assert_eq!(
+            Which::from_embedding_id("codegemma-7b-embed"),
+            Some(Which::CodeBase7B)
+        );
+        assert_eq!(Which::from_embedding_id("gemma-2b-it"), None);
+        assert_eq!(Which::from_embedding_id("gemma-3-1b-it-embed"), None);
+        assert_eq!(Which::Llama32_1BInstruct.embedding_id(), None);
+    }
+
+    #[test]
+    fn test_model_detail_describes_cold_and_loaded_models() {
+        let cold = model_detail(Which::CodeInstruct2B, None);
+        assert_eq!(cold.status, "cold");
+        assert_eq!(cold.capabilities, ["chat", "code", "embeddings"]);
+        assert_eq!(cold.context_length, 8192);
+        assert_eq!(cold.parameters, 2_506_000_000);
+        assert_eq!(cold.repository, "google/codegemma-2b-it");
+        assert_eq!(cold.device, None);
+
+        let quantized = model_detail(Which::Llama32_1BInstructQ4KM, None);
+        assert_eq!(quantized.quantization.as_deref(), Some("q4_k_m"));
+        assert_eq!(
+            model_detail(Which::BaseV3_1B, None).capabilities,
+            ["completion"]
+        );
+
+        let runner = RunnerMetadata {
+            model_id: "gemma-3-1b-it".to_string(),
+            repo_id: "google/gemma-3-1b-it".to_string(),
+            family: "gemma3".to_string(),
+            owned_by: "google".to_string(),
+            context_length: 4096,
+            vocab_size: 262_144,
+            parameter_count: 999_885_952,
+            dtype: "bf16".to_string(),
+            device: "cuda:0".to_string(),
+        };
+        let loaded = model_detail(Which::InstructV3_1B, Some(runner));
+        assert!(loaded.is_loaded());
+        assert_eq!(loaded.context_length, 4096);
+        assert_eq!(loaded.parameters, 999_885_952);
+        assert_eq!(loaded.dtype.as_deref(), Some("bf16"));
+        assert_eq!(loaded.device.as_deref(), Some("cuda:0"));
+    }
+
+    #[test]
+    fn test_weight_estimates_follow_dtype_and_quantization() {
+        assert_eq!(
+            estimated_weight_bytes(Which::CodeInstruct2B, None),
+            5_012_000_000
+        );
+        assert_eq!(
+            estimated_weight_bytes(Which::Llama32_1BInstructQ4KM, None),
+            695_250_000
+        );
+
+        let mut runner = RunnerMetadata {
+            model_id: "gemma-3-1b-it".to_string(),
+            repo_id: "google/gemma-3-1b-it".to_string(),
+            family: "gemma3".to_string(),
+            owned_by: "google".to_string(),
+            context_length: 4096,
+            vocab_size: 262_144,
+            parameter_count: 1_000_000_000,
+            dtype: "bf16".to_string(),
+            device: "cpu".to_string(),
+        };
+        assert_eq!(
+            estimated_weight_bytes(Which::InstructV3_1B, Some(&runner)),
+            2_000_000_000
+        );
+        runner.dtype = "f32".to_string();
+        assert_eq!(
+            estimated_weight_bytes(Which::InstructV3_1B, Some(&runner)),
+            4_000_000_000
+        );
+    }
+
+    #[tokio::test]
+    async fn test_model_list_reports_family_and_load_state() {
+        let Json(list) = list_models().await;
+        let gemma = list.data.iter().find(|m| m.id == "gemma-3-1b-it").unwrap();
+        assert_eq!(gemma.family.as_deref(), Some("gemma3"));
+        assert_eq!(gemma.loaded, Some(false));
+        assert_eq!(gemma.context_length, Some(32 * 1024));
+
+        // The embeddings engine's models keep the plain OpenAI shape.
+        let minilm = list
+            .data
+            .iter()
+            .find(|m| m.id == "sentence-transformers/all-MiniLM-L6-v2")
+            .unwrap();
+        let json = serde_json::to_value(minilm).unwrap();
+        assert!(json.get("family").is_none());
+        assert!(json.get("loaded").is_none());
+    }
 
     #[test]
     fn test_build_gemma_prompt() {
         let messages = vec![
             Message {
                 role: "system".to_string(),
-                content: Some(MessageContent(Either::Left("System message".to_string()))),
+                content: Some(MessageContent::Text("System message".to_string())),
                 name: None,
+                tool_calls: None,
+                tool_call_id: None,
             },
             Message {
                 role: "user".to_string(),
-                content: Some(MessageContent(Either::Left("Knock knock.".to_string()))),
+                content: Some(MessageContent::Text("Knock knock.".to_string())),
                 name: None,
+                tool_calls: None,
+                tool_call_id: None,
             },
             Message {
                 role: "assistant".to_string(),
-                content: Some(MessageContent(Either::Left("Who's there?".to_string()))),
+                content: Some(MessageContent::Text("Who's there?".to_string())),
                 name: None,
+                tool_calls: None,
+                tool_call_id: None,
             },
             Message {
                 role: "user".to_string(),
-                content: Some(MessageContent(Either::Left("Gemma.".to_string()))),
+                content: Some(MessageContent::Text("Gemma.".to_string())),
                 name: None,
+                tool_calls: None,
+                tool_call_id: None,
             },
         ];
 
         let prompt = build_gemma_prompt(&messages);
 
-        let expected = "<start_of_turn>system\nSystem message<end_of_turn>\n<start_of_turn>user\nKnock knock.<end_of_turn>\n<start_of_turn>model\nWho's there?<end_of_turn>\n<start_of_turn>user\nGemma.<end_of_turn>\n<start_of_turn>model\n";
+        let expected = "<start_of_turn>user\nSystem message\n\nKnock knock.<end_of_turn>\n<start_of_turn>model\nWho's there?<end_of_turn>\n<start_of_turn>user\nGemma.<end_of_turn>\n<start_of_turn>model\n";
 
         assert_eq!(prompt, expected);
     }
@@ -727,9 +1697,276 @@ mod tests {
             role: "user".to_string(),
             content: None,
             name: None,
+            tool_calls: None,
+            tool_call_id: None,
         }];
 
         let prompt = build_gemma_prompt(&messages);
         assert_eq!(prompt, "<start_of_turn>model\n");
     }
+
+    #[test]
+    fn test_build_llama_prompt() {
+        let messages = vec![
+            Message::system("Be brief."),
+            Message::user("Hi"),
+            Message::assistant("Hello."),
+            Message::user("Bye"),
+        ];
+        let instruct = Which::from_public_id("llama-3.2-1b-instruct").unwrap();
+        assert_eq!(
+            build_prompt(instruct, &messages),
+            "<|begin_of_text|>\
+             <|start_header_id|>system<|end_header_id|>\n\nBe brief.<|eot_id|>\
+             <|start_header_id|>user<|end_header_id|>\n\nHi<|eot_id|>\
+             <|start_header_id|>assistant<|end_header_id|>\n\nHello.<|eot_id|>\
+             <|start_header_id|>user<|end_header_id|>\n\nBye<|eot_id|>\
+             <|start_header_id|>assistant<|end_header_id|>\n\n"
+        );
+        // The next turn's prompt starts with this one's, so its KV state can be resumed
+        let mut next = messages.clone();
+        next.extend([Message::assistant("Bye."), Message::user("Wait")]);
+        let prompt = build_prompt(instruct, &messages);
+        assert!(build_prompt(instruct, &next).starts_with(&prompt));
+
+        let base = Which::from_public_id("llama-3.2-1b").unwrap();
+        assert_eq!(build_prompt(base, &messages), "Bye");
+    }
+
+    #[test]
+    fn test_build_prompt_uses_each_family_template() {
+        let messages = vec![Message::system("Be brief."), Message::user("Hi")];
+        let prompt = |id: &str| build_prompt(Which::from_public_id(id).unwrap(), &messages);
+        assert_eq!(
+            prompt("qwen2.5-0.5b-instruct"),
+            "<|im_start|>system\nBe brief.<|im_end|>\n\
+             <|im_start|>user\nHi<|im_end|>\n<|im_start|>assistant\n"
+        );
+        assert_eq!(
+            prompt("phi-3-mini-4k-instruct"),
+            "<|system|>\nBe brief.<|end|>\n<|user|>\nHi<|end|>\n<|assistant|>\n"
+        );
+        assert_eq!(
+            prompt("mistral-7b-instruct-v0.3"),
+            "[INST] Be brief.\n\nHi[/INST]"
+        );
+    }
+
+    #[test]
+    fn test_conversation_key_is_stable_across_turns() {
+        let message = |role: &str, text: &str| Message {
+            role: role.to_string(),
+            content: Some(MessageContent::Text(text.to_string())),
+            name: None,
+            tool_calls: None,
+            tool_call_id: None,
+        };
+        let first_turn = vec![message("user", "Knock knock.")];
+        let second_turn = vec![
+            message("user", "Knock knock."),
+            message("assistant", "Who's there?"),
+            message("user", "Gemma."),
+        ];
+        let other = vec![message("user", "Tell me a joke.")];
+
+        assert!(conversation_key(&first_turn).is_some());
+        assert_eq!(
+            conversation_key(&first_turn),
+            conversation_key(&second_turn)
+        );
+        assert_ne!(conversation_key(&first_turn), conversation_key(&other));
+        assert_eq!(conversation_key(&[message("system", "Be brief.")]), None);
+    }
+
+    #[test]
+    fn test_request_sampling_reads_top_k_extension() {
+        let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "messages": [{"role": "user", "content": "Hi"}],
+            "temperature": 0.5,
+            "top_k": 40
+        }))
+        .unwrap();
+
+        let sampling = request_sampling(&request).unwrap();
+        assert_eq!(sampling.temperature, Some(0.5));
+        assert_eq!(sampling.top_p, None);
+        assert_eq!(sampling.top_k, Some(40));
+    }
+
+    #[test]
+    fn test_request_sampling_parses_the_preset() {
+        let mut request = ChatCompletionRequest::new("gemma-3-1b-it", vec![Message::user("Hi")]);
+        request.preset = Some("creative".to_string());
+        assert_eq!(
+            request_sampling(&request).unwrap().preset,
+            Some(SamplingPreset::Creative)
+        );
+
+        request.preset = Some("wild".to_string());
+        let error = request_sampling(&request).unwrap_err();
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.body.error.kind, "invalid_request");
+        assert_eq!(error.body.error.param.as_deref(), Some("preset"));
+    }
+
+    #[test]
+    fn test_request_sampling_reads_seed_and_frequency_penalty() {
+        let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "messages": [{"role": "user", "content": "Hi"}],
+            "seed": 7,
+            "frequency_penalty": 0.5
+        }))
+        .unwrap();
+        let sampling = request_sampling(&request).unwrap();
+        assert_eq!(sampling.seed, Some(7));
+        assert_eq!(sampling.frequency_penalty, Some(0.5));
+
+        let mut request = request;
+        request.frequency_penalty = Some(2.5);
+        let error = request_sampling(&request).unwrap_err();
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.body.error.param.as_deref(), Some("frequency_penalty"));
+    }
+
+    #[test]
+    fn test_request_timeout_must_be_positive() {
+        let mut request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "messages": [{"role": "user", "content": "Hi"}],
+            "timeout": 1.5
+        }))
+        .unwrap();
+        assert_eq!(
+            request_timeout(&request).unwrap(),
+            Some(Duration::from_millis(1500))
+        );
+
+        for secs in [0.0, -1.0, f64::NAN] {
+            request.timeout = Some(secs);
+            let error = request_timeout(&request).unwrap_err();
+            assert_eq!(error.status, StatusCode::BAD_REQUEST);
+            assert_eq!(error.body.error.param.as_deref(), Some("timeout"));
+        }
+    }
+
+    #[test]
+    fn test_generation_request_carries_stop_sequences() {
+        let mut chat: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "messages": [{"role": "user", "content": "Hi"}],
+            "stop": "\n\n"
+        }))
+        .unwrap();
+        let request = generation_request(&chat).unwrap();
+        assert_eq!(request.stop, vec!["\n\n".to_string()]);
+        assert_eq!(request.max_tokens, DEFAULT_MAX_TOKENS);
+
+        chat.stop = Some(StopTokens::Multi(vec!["a".to_string(); 5]));
+        let error = generation_request(&chat).unwrap_err();
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.body.error.param.as_deref(), Some("stop"));
+    }
+
+    #[test]
+    fn test_truncation_strategy_defaults_to_an_error() {
+        let mut request: ChatCompletionRequest =
+            serde_json::from_value(serde_json::json!({"messages": []})).unwrap();
+        assert_eq!(
+            request_truncation(&request).unwrap(),
+            TruncationStrategy::Error
+        );
+
+        request.truncation_strategy = Some("drop_oldest".to_string());
+        assert_eq!(
+            request_truncation(&request).unwrap(),
+            TruncationStrategy::DropOldest
+        );
+
+        request.truncation_strategy = Some("auto".to_string());
+        let error = request_truncation(&request).unwrap_err();
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error.body.error.param.as_deref(),
+            Some("truncation_strategy")
+        );
+    }
+
+    /// Counts a prompt's characters as its tokens, in a context of `context_length`.
+    struct CountingRunner(RunnerMetadata);
+
+    impl ModelRunner for CountingRunner {
+        type Config = usize;
+
+        fn load(context_length: usize) -> Result<Self, RunnerError> {
+            Ok(Self(RunnerMetadata {
+                model_id: "counting".to_string(),
+                repo_id: "counting".to_string(),
+                family: "counting".to_string(),
+                owned_by: "test".to_string(),
+                context_length,
+                vocab_size: 1,
+                parameter_count: 0,
+                dtype: "f32".to_string(),
+                device: "cpu".to_string(),
+            }))
+        }
+
+        fn generate_stream(&self, _: GenerationRequest) -> Result<TokenReceiver, RunnerError> {
+            Err(RunnerError::InvalidRequest("counting".to_string()))
+        }
+
+        fn metadata(&self) -> &RunnerMetadata {
+            &self.0
+        }
+
+        fn cancel(&self) {}
+
+        fn count_tokens(&self, prompt: &str) -> Option<usize> {
+            Some(prompt.chars().count())
+        }
+    }
+
+    #[test]
+    fn test_system_only_chats_that_overflow_are_refused() {
+        let runner = CountingRunner::load(16).unwrap();
+        let messages = vec![Message::system("Be very brief. ".repeat(8))];
+        for truncation in [TruncationStrategy::Error, TruncationStrategy::DropOldest] {
+            let chat = ChatPrompt {
+                messages: &messages,
+                max_tokens: Some(4),
+                truncation,
+            };
+            let error = fit_context(&runner, Which::Instruct2B, &chat).unwrap_err();
+            assert_eq!(error.status, StatusCode::BAD_REQUEST);
+            assert_eq!(
+                error.body.error.code.as_deref(),
+                Some("context_length_exceeded")
+            );
+        }
+
+        // Nothing left to drop once only the last user turn remains
+        let messages = vec![
+            Message::system("Be brief."),
+            Message::user("Hello there. ".repeat(8)),
+        ];
+        let chat = ChatPrompt {
+            messages: &messages,
+            max_tokens: None,
+            truncation: TruncationStrategy::DropOldest,
+        };
+        assert!(fit_context(&runner, Which::Instruct2B, &chat).is_err());
+    }
+
+    #[test]
+    fn test_empty_messages_are_refused() {
+        let request: ChatCompletionRequest =
+            serde_json::from_value(serde_json::json!({"messages": []})).unwrap();
+        let error = check_messages(&request).unwrap_err();
+        assert_eq!(error.status, StatusCode::BAD_REQUEST);
+        assert_eq!(error.body.error.param.as_deref(), Some("messages"));
+
+        let request: ChatCompletionRequest = serde_json::from_value(
+            serde_json::json!({"messages": [{"role": "user", "content": "Hi"}]}),
+        )
+        .unwrap();
+        assert!(check_messages(&request).is_ok());
+    }
 }
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+
+/// Streaming chat completions in flight, keyed by the `id` their chunks carry, so a client
+/// or an orchestration layer can stop a generation without dropping its connection. Clones
+/// share the registry.
+#[derive(Clone, Default)]
+pub struct ActiveStreams {
+    streams: Arc<Mutex<HashMap<String, oneshot::Sender<()>>>>,
+}
+
+impl ActiveStreams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the stream `id` for as long as the returned registration is kept.
+    pub fn register(&self, id: &str) -> StreamRegistration {
+        let (cancel, cancelled) = oneshot::channel();
+        self.lock().insert(id.to_string(), cancel);
+        StreamRegistration {
+            streams: self.clone(),
+            id: id.to_string(),
+            cancelled,
+        }
+    }
+
+    /// Cancel the stream `id`. Returns `false` when no such stream is in flight, e.g.
+    /// because it already finished.
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.lock().remove(id) {
+            Some(cancel) => cancel.send(()).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Number of streams in flight.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, oneshot::Sender<()>>> {
+        self.streams.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A stream's entry in [`ActiveStreams`], removed when dropped.
+pub struct StreamRegistration {
+    streams: ActiveStreams,
+    id: String,
+    cancelled: oneshot::Receiver<()>,
+}
+
+impl StreamRegistration {
+    /// Resolves once the stream is cancelled. Completes only once; await it by reference
+    /// until it does and not after.
+    pub fn cancelled(&mut self) -> &mut oneshot::Receiver<()> {
+        &mut self.cancelled
+    }
+}
+
+impl Drop for StreamRegistration {
+    fn drop(&mut self) {
+        self.streams.lock().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_reaches_the_registered_stream_only() {
+        let streams = ActiveStreams::new();
+        let mut first = streams.register("chatcmpl-1");
+        let _second = streams.register("chatcmpl-2");
+        assert_eq!(streams.len(), 2);
+
+        assert!(streams.cancel("chatcmpl-1"));
+        first.cancelled().await.unwrap();
+        assert!(!streams.cancel("chatcmpl-1"));
+        assert!(!streams.cancel("chatcmpl-3"));
+
+        drop(first);
+        assert_eq!(streams.len(), 1);
+    }
+
+    #[test]
+    fn test_finished_streams_are_unregistered() {
+        let streams = ActiveStreams::new();
+        drop(streams.register("chatcmpl-1"));
+        assert!(streams.is_empty());
+        assert!(!streams.cancel("chatcmpl-1"));
+    }
+}
@@ -0,0 +1,347 @@
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+use crate::openai_types::{
+    ApiError, ChatCompletionRequest, FunctionCall, Message, MessageContent, Tool, ToolCall,
+    ToolChoice,
+};
+
+/// What a request requires of the model's reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Requirement {
+    /// Answer in text or call functions
+    Auto,
+    /// Call at least one function
+    Any,
+    /// Call this function
+    Function(String),
+}
+
+/// The functions a chat request offers the model, when it offers any.
+///
+/// None of the models are trained for one calling format, so the functions are described in
+/// the system prompt with an instruction to answer with a JSON object of calls, and replies
+/// that parse as one become `tool_calls`.
+#[derive(Debug, Clone)]
+pub struct ToolUse {
+    tools: Vec<Tool>,
+    requirement: Requirement,
+}
+
+impl ToolUse {
+    /// The tool use `request` asks for: `None` without tools or with `tool_choice: "none"`.
+    pub fn from_request(request: &ChatCompletionRequest) -> Result<Option<Self>, ApiError> {
+        let tools = request.tools.clone().unwrap_or_default();
+        for tool in &tools {
+            if tool.kind != "function" {
+                return Err(ApiError::invalid_request(format!(
+                    "unsupported tool type '{}': only functions can be called",
+                    tool.kind
+                ))
+                .with_param("tools"));
+            }
+            if tool.function.name.is_empty() {
+                return Err(
+                    ApiError::invalid_request("every function needs a name").with_param("tools")
+                );
+            }
+        }
+        let requirement = match &request.tool_choice {
+            None => Requirement::Auto,
+            Some(ToolChoice::Mode(mode)) => match mode.as_str() {
+                "none" => return Ok(None),
+                "auto" => Requirement::Auto,
+                "required" => Requirement::Any,
+                other => {
+                    return Err(ApiError::invalid_request(format!(
+                        "tool_choice must be 'none', 'auto', 'required' or a function, got '{}'",
+                        other
+                    ))
+                    .with_param("tool_choice"));
+                }
+            },
+            Some(ToolChoice::Function(choice)) => {
+                Requirement::Function(choice.function.name.clone())
+            }
+        };
+        if tools.is_empty() {
+            return match requirement {
+                Requirement::Auto => Ok(None),
+                _ => Err(
+                    ApiError::invalid_request("tool_choice requires tools to call")
+                        .with_param("tool_choice"),
+                ),
+            };
+        }
+        if let Requirement::Function(name) = &requirement
+            && !tools.iter().any(|tool| &tool.function.name == name)
+        {
+            return Err(ApiError::invalid_request(format!(
+                "tool_choice names '{}', which isn't among the tools",
+                name
+            ))
+            .with_param("tool_choice"));
+        }
+        Ok(Some(Self { tools, requirement }))
+    }
+
+    /// Instructions describing the functions and how to call them.
+    fn instructions(&self) -> String {
+        let functions: Vec<String> = self
+            .tools
+            .iter()
+            .map(|tool| json!(tool.function).to_string())
+            .collect();
+        let requirement = match &self.requirement {
+            Requirement::Auto => "If none of them is needed, answer normally instead.".to_string(),
+            Requirement::Any => "You must call at least one of them.".to_string(),
+            Requirement::Function(name) => format!("You must call {}.", name),
+        };
+        format!(
+            "You can call these functions:\n{}\n\nTo call functions, reply with only a JSON \
+             object of the form {{\"tool_calls\": [{{\"name\": \"<function name>\", \
+             \"arguments\": {{<arguments>}}}}]}} and nothing else. {}",
+            functions.join("\n"),
+            requirement
+        )
+    }
+
+    /// Parse a complete reply as the calls it makes, or `None` if it is a plain answer or
+    /// calls a function the request doesn't offer.
+    pub fn parse_calls(&self, reply: &str) -> Option<Vec<ToolCall>> {
+        let text = reply.trim();
+        let text = text
+            .strip_prefix("<tool_call>")
+            .and_then(|text| text.strip_suffix("</tool_call>"))
+            .unwrap_or(text)
+            .trim();
+        let text = text
+            .strip_prefix("```json")
+            .or_else(|| text.strip_prefix("```"))
+            .and_then(|text| text.strip_suffix("```"))
+            .unwrap_or(text);
+        let calls = match serde_json::from_str::<Value>(text).ok()? {
+            Value::Object(mut object) => match object.remove("tool_calls") {
+                Some(Value::Array(calls)) => calls,
+                Some(_) => return None,
+                None => vec![Value::Object(object)],
+            },
+            Value::Array(calls) => calls,
+            _ => return None,
+        };
+        if calls.is_empty() {
+            return None;
+        }
+        calls.iter().map(|call| self.parse_call(call)).collect()
+    }
+
+    fn parse_call(&self, call: &Value) -> Option<ToolCall> {
+        let name = call.get("name")?.as_str()?;
+        if !self.tools.iter().any(|tool| tool.function.name == name) {
+            return None;
+        }
+        if let Requirement::Function(required) = &self.requirement
+            && required != name
+        {
+            return None;
+        }
+        // Llama 3 names them `parameters` in its own format
+        let arguments = match call.get("arguments").or_else(|| call.get("parameters")) {
+            Some(Value::String(arguments)) => arguments.clone(),
+            Some(arguments) => arguments.to_string(),
+            None => "{}".to_string(),
+        };
+        Some(ToolCall {
+            id: format!("call_{}", Uuid::new_v4().simple()),
+            kind: "function".to_string(),
+            function: FunctionCall {
+                name: name.to_string(),
+                arguments,
+            },
+        })
+    }
+}
+
+/// Whether a reply starting with `text` may still turn out to be a tool call, so streamed
+/// text is held back until it can't.
+pub fn may_be_tool_call(text: &str) -> bool {
+    let text = text.trim_start();
+    text.is_empty() || text.starts_with(['{', '[', '`', '<'])
+}
+
+/// The messages to render into the prompt: the tool instructions joined to the system
+/// message, assistant calls written out as the JSON the model is told to reply with, and
+/// tool results as user turns, since the models' chat formats have no tool role.
+pub fn prompt_messages(messages: &[Message], tool_use: Option<&ToolUse>) -> Vec<Message> {
+    let mut rendered: Vec<Message> = messages.iter().map(render_message).collect();
+    if let Some(tool_use) = tool_use {
+        let instructions = tool_use.instructions();
+        match rendered.iter_mut().find(|message| message.role == "system") {
+            Some(system) => {
+                let text = system.text().unwrap_or_default();
+                *system = Message::system(format!("{}\n\n{}", text, instructions));
+            }
+            None => rendered.insert(0, Message::system(instructions)),
+        }
+    }
+    rendered
+}
+
+fn render_message(message: &Message) -> Message {
+    if let Some(calls) = message
+        .tool_calls
+        .as_deref()
+        .filter(|calls| !calls.is_empty())
+    {
+        let calls: Vec<Value> = calls
+            .iter()
+            .map(|call| {
+                let arguments = serde_json::from_str(&call.function.arguments)
+                    .unwrap_or_else(|_| Value::String(call.function.arguments.clone()));
+                json!({"name": call.function.name, "arguments": arguments})
+            })
+            .collect();
+        let calls = json!({ "tool_calls": calls }).to_string();
+        return match message.text().filter(|text| !text.is_empty()) {
+            Some(text) => Message::assistant(format!("{}\n{}", text, calls)),
+            None => Message::assistant(calls),
+        };
+    }
+    if message.role == "tool" {
+        let result = match &message.content {
+            Some(MessageContent::Text(text)) => text.as_str(),
+            _ => "",
+        };
+        let source = message
+            .name
+            .as_deref()
+            .or(message.tool_call_id.as_deref())
+            .unwrap_or("the tool call");
+        return Message::user(format!("Result of {}:\n{}", source, result));
+    }
+    message.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openai_types::{FunctionDefinition, FunctionName, NamedToolChoice};
+
+    fn weather() -> Tool {
+        Tool::function(FunctionDefinition {
+            name: "get_weather".to_string(),
+            description: Some("Current weather in a city".to_string()),
+            parameters: Some(json!({"type": "object", "properties": {"city": {"type": "string"}}})),
+        })
+    }
+
+    fn request(tool_choice: Option<ToolChoice>) -> ChatCompletionRequest {
+        let mut request = ChatCompletionRequest::new("m", vec![Message::user("Weather in Paris?")]);
+        request.tools = Some(vec![weather()]);
+        request.tool_choice = tool_choice;
+        request
+    }
+
+    fn named(name: &str) -> Option<ToolChoice> {
+        Some(ToolChoice::Function(NamedToolChoice {
+            kind: "function".to_string(),
+            function: FunctionName {
+                name: name.to_string(),
+            },
+        }))
+    }
+
+    #[test]
+    fn test_tool_choice_is_validated() {
+        let mode = |mode: &str| Some(ToolChoice::Mode(mode.to_string()));
+        assert!(ToolUse::from_request(&request(None)).unwrap().is_some());
+        assert!(
+            ToolUse::from_request(&request(mode("none")))
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            ToolUse::from_request(&request(named("get_weather")))
+                .unwrap()
+                .is_some()
+        );
+        assert!(ToolUse::from_request(&request(named("get_time"))).is_err());
+        assert!(ToolUse::from_request(&request(mode("sometimes"))).is_err());
+
+        let mut no_tools = request(mode("required"));
+        no_tools.tools = None;
+        assert!(ToolUse::from_request(&no_tools).is_err());
+        no_tools.tool_choice = None;
+        assert!(ToolUse::from_request(&no_tools).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_replies_parse_as_calls_to_offered_functions() {
+        let tool_use = ToolUse::from_request(&request(None)).unwrap().unwrap();
+        let calls = tool_use
+            .parse_calls(
+                r#"{"tool_calls": [{"name": "get_weather", "arguments": {"city": "Paris"}}]}"#,
+            )
+            .unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].function.name, "get_weather");
+        assert_eq!(calls[0].function.arguments, r#"{"city":"Paris"}"#);
+        assert!(calls[0].id.starts_with("call_"));
+
+        // Fenced, tagged and Llama 3's own format are understood too
+        for reply in [
+            "```json\n{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Paris\"}}\n```",
+            "<tool_call>{\"name\": \"get_weather\", \"arguments\": {\"city\": \"Paris\"}}</tool_call>",
+            "{\"name\": \"get_weather\", \"parameters\": {\"city\": \"Paris\"}}",
+        ] {
+            let calls = tool_use.parse_calls(reply).unwrap();
+            assert_eq!(
+                calls[0].function.arguments, r#"{"city":"Paris"}"#,
+                "{reply}"
+            );
+        }
+
+        assert!(tool_use.parse_calls("It is sunny in Paris.").is_none());
+        assert!(tool_use.parse_calls(r#"{"name": "get_time"}"#).is_none());
+        assert!(tool_use.parse_calls(r#"{"tool_calls": []}"#).is_none());
+
+        assert!(may_be_tool_call("  {\"na"));
+        assert!(may_be_tool_call(" "));
+        assert!(!may_be_tool_call("It is"));
+    }
+
+    #[test]
+    fn test_prompt_messages_describe_tools_and_past_calls() {
+        let tool_use = ToolUse::from_request(&request(named("get_weather")))
+            .unwrap()
+            .unwrap();
+        let call = tool_use
+            .parse_calls(r#"{"name": "get_weather", "arguments": {"city": "Paris"}}"#)
+            .unwrap();
+        let messages = vec![
+            Message::system("Be brief."),
+            Message::user("Weather in Paris?"),
+            Message::assistant_tool_calls(call.clone()),
+            Message::tool(call[0].id.clone(), "18C and sunny"),
+        ];
+
+        let rendered = prompt_messages(&messages, Some(&tool_use));
+        let system = rendered[0].text().unwrap();
+        assert!(system.starts_with("Be brief.\n\nYou can call these functions:\n"));
+        assert!(system.contains(r#""name":"get_weather""#), "{system}");
+        assert!(system.ends_with("You must call get_weather."));
+        assert_eq!(
+            rendered[2],
+            Message::assistant(
+                r#"{"tool_calls":[{"arguments":{"city":"Paris"},"name":"get_weather"}]}"#
+            )
+        );
+        assert_eq!(rendered[3].role, "user");
+        assert!(rendered[3].text().unwrap().ends_with(":\n18C and sunny"));
+
+        // Without tools on offer only the history is rewritten
+        let plain = prompt_messages(&messages[1..], None);
+        assert_eq!(plain[0], Message::user("Weather in Paris?"));
+        assert_eq!(plain.len(), 3);
+    }
+}
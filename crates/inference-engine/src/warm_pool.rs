@@ -0,0 +1,441 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use utoipa::ToSchema;
+
+use crate::Which;
+use crate::memory::idle_models_by_last_use;
+use crate::runners::{Sampling, pooled_runner};
+use crate::server::AppState;
+
+/// How far back the request mix the pool predicts from reaches.
+const MIX_WINDOW: Duration = Duration::from_secs(15 * 60);
+/// Requests remembered for the mix, however recent.
+const MAX_RECENT_REQUESTS: usize = 1024;
+/// Evictions kept for the status report.
+const MAX_EVICTIONS: usize = 20;
+
+/// The chat requests of the last [`MIX_WINDOW`], oldest first.
+static RECENT_REQUESTS: Mutex<VecDeque<(Which, Instant)>> = Mutex::new(VecDeque::new());
+/// What the pool did last, `None` until it's started.
+static STATUS: RwLock<Option<WarmPoolStatus>> = RwLock::new(None);
+
+/// The models the warm pool keeps loaded, how often it warms them, and when it evicts the
+/// rest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WarmPoolConfig {
+    /// Models kept loaded and warm whatever the traffic.
+    pub models: Vec<Which>,
+    /// Models kept warm in all; slots beyond `models` go to the most requested others.
+    pub size: usize,
+    /// Time between passes, each warming the pool's models with a tiny generation.
+    pub interval: Duration,
+    /// Evict models outside the pool that no request has used for this long.
+    pub idle_ttl: Option<Duration>,
+}
+
+impl Default for WarmPoolConfig {
+    fn default() -> Self {
+        Self {
+            models: Vec::new(),
+            size: 0,
+            interval: Duration::from_secs(300),
+            idle_ttl: None,
+        }
+    }
+}
+
+impl WarmPoolConfig {
+    /// Read `WARM_MODELS` (comma-separated model ids), `WARM_POOL_SIZE` (default: the
+    /// number of `WARM_MODELS`), `WARM_INTERVAL_SECS` and `MODEL_IDLE_TTL_SECS`.
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let parse = |name: &str| {
+            let value = var(name)?;
+            match value.trim().parse::<u64>() {
+                Ok(number) => Some(number),
+                Err(_) => {
+                    tracing::warn!("Ignoring {}={}: expected a whole number", name, value);
+                    None
+                }
+            }
+        };
+        let mut models = Vec::new();
+        for id in var("WARM_MODELS").unwrap_or_default().split(',') {
+            let id = id.trim();
+            if id.is_empty() {
+                continue;
+            }
+            match Which::from_public_id(id) {
+                Some(which) if !models.contains(&which) => models.push(which),
+                Some(_) => {}
+                None => tracing::warn!("Ignoring unsupported model {} in WARM_MODELS", id),
+            }
+        }
+        let size = match parse("WARM_POOL_SIZE").map(|size| size as usize) {
+            Some(size) if size < models.len() => {
+                tracing::warn!(
+                    "WARM_POOL_SIZE={} is less than the {} WARM_MODELS, which all stay warm",
+                    size,
+                    models.len()
+                );
+                models.len()
+            }
+            Some(size) => size,
+            None => models.len(),
+        };
+        let defaults = Self::default();
+        Self {
+            models,
+            size,
+            interval: parse("WARM_INTERVAL_SECS")
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.interval),
+            idle_ttl: parse("MODEL_IDLE_TTL_SECS")
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+        }
+    }
+
+    /// Whether there's anything to keep warm or evict.
+    pub fn is_enabled(&self) -> bool {
+        self.size > 0 || self.idle_ttl.is_some()
+    }
+}
+
+/// A model the pool warmed in its last pass.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct WarmModel {
+    /// Model id, e.g. `gemma-3-1b-it`
+    pub id: String,
+    /// Whether it's one of `WARM_MODELS`, rather than predicted from the request mix
+    pub pinned: bool,
+    /// When its warmup generation finished, in seconds since the Unix epoch
+    pub warmed_at: Option<u64>,
+    /// How long loading it, if it wasn't, and the warmup generation took
+    pub warmup_ms: Option<u64>,
+    /// Why loading or warming it failed
+    pub error: Option<String>,
+}
+
+/// A model the pool dropped for being idle longer than the TTL.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct EvictedModel {
+    /// The model's cache entry, e.g. `google/gemma-3-1b-it (bf16, Cpu)`
+    pub model: String,
+    /// How long no request had used it
+    pub idle_secs: u64,
+    /// When it was evicted, in seconds since the Unix epoch
+    pub evicted_at: u64,
+}
+
+/// What the warm pool keeps loaded and why.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct WarmPoolStatus {
+    /// Models kept warm in all
+    pub size: usize,
+    /// Seconds between passes
+    pub interval_secs: u64,
+    /// Seconds a model outside the pool may sit idle before it's evicted
+    pub idle_ttl_secs: Option<u64>,
+    /// The models kept warm whatever the traffic
+    pub pinned: Vec<String>,
+    /// The most requested other models, which fill the remaining slots
+    pub predicted: Vec<String>,
+    /// Chat requests per model over the last 15 minutes, which the predictions come from
+    pub request_mix: BTreeMap<String, usize>,
+    /// The models warmed in the last pass
+    pub models: Vec<WarmModel>,
+    /// The latest evictions, oldest first
+    pub evicted: Vec<EvictedModel>,
+    /// When the last pass finished, in seconds since the Unix epoch
+    pub last_run: Option<u64>,
+}
+
+impl WarmPoolStatus {
+    fn new(config: &WarmPoolConfig) -> Self {
+        Self {
+            size: config.size,
+            interval_secs: config.interval.as_secs(),
+            idle_ttl_secs: config.idle_ttl.map(|ttl| ttl.as_secs()),
+            pinned: config
+                .models
+                .iter()
+                .map(|which| which.public_id().to_string())
+                .collect(),
+            predicted: Vec::new(),
+            request_mix: BTreeMap::new(),
+            models: Vec::new(),
+            evicted: Vec::new(),
+            last_run: None,
+        }
+    }
+}
+
+/// Note a chat request for `which`, for the pool to predict the next models from.
+pub fn record_request(which: Which) {
+    let now = Instant::now();
+    if let Ok(mut recent) = RECENT_REQUESTS.lock() {
+        recent.push_back((which, now));
+        while recent.len() > MAX_RECENT_REQUESTS
+            || recent
+                .front()
+                .is_some_and(|(_, at)| now.duration_since(*at) > MIX_WINDOW)
+        {
+            recent.pop_front();
+        }
+    }
+}
+
+/// Requests per model among those of `recent` within [`MIX_WINDOW`] of `now`, most
+/// requested first and, between equals, most recently requested first.
+fn rank_requests(recent: &VecDeque<(Which, Instant)>, now: Instant) -> Vec<(Which, usize)> {
+    let mut counts: HashMap<Which, (usize, Instant)> = HashMap::new();
+    let in_window = |at: &Instant| now.saturating_duration_since(*at) <= MIX_WINDOW;
+    for (which, at) in recent.iter().filter(|(_, at)| in_window(at)) {
+        let entry = counts.entry(*which).or_insert((0, *at));
+        entry.0 += 1;
+        entry.1 = entry.1.max(*at);
+    }
+    let mut ranked: Vec<(Which, usize, Instant)> = counts
+        .into_iter()
+        .map(|(which, (count, last))| (which, count, last))
+        .collect();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then(b.2.cmp(&a.2)));
+    ranked
+        .into_iter()
+        .map(|(which, count, _)| (which, count))
+        .collect()
+}
+
+/// The next models to keep warm: the `slots` most requested ones that aren't `pinned`.
+fn predict(ranked: &[(Which, usize)], pinned: &[Which], slots: usize) -> Vec<Which> {
+    ranked
+        .iter()
+        .map(|(which, _)| *which)
+        .filter(|which| !pinned.contains(which))
+        .take(slots)
+        .collect()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The warm pool's last report, `None` when it isn't running.
+pub fn warm_pool_status() -> Option<WarmPoolStatus> {
+    STATUS.read().ok()?.clone()
+}
+
+/// Load and warm the pinned and predicted models of `config` with a tiny generation each,
+/// then evict the models no request has used for longer than its TTL. The pool's own
+/// models were just used, so they stay. Returns the report it publishes.
+pub fn run_warm_pool(config: &WarmPoolConfig, state: &AppState) -> WarmPoolStatus {
+    let ranked = match RECENT_REQUESTS.lock() {
+        Ok(recent) => rank_requests(&recent, Instant::now()),
+        Err(_) => Vec::new(),
+    };
+    let predicted = predict(
+        &ranked,
+        &config.models,
+        config.size.saturating_sub(config.models.len()),
+    );
+
+    let mut status = WarmPoolStatus::new(config);
+    let targets = config
+        .models
+        .iter()
+        .map(|which| (*which, true))
+        .chain(predicted.iter().map(|which| (*which, false)));
+    for (which, pinned) in targets {
+        let start = Instant::now();
+        let warmed = pooled_runner(which, state, Sampling::default()).and_then(|runner| {
+            runner.warmup()?;
+            Ok(())
+        });
+        let mut model = WarmModel {
+            id: which.public_id().to_string(),
+            pinned,
+            warmed_at: None,
+            warmup_ms: None,
+            error: None,
+        };
+        match warmed {
+            Ok(()) => {
+                tracing::debug!("Warmed {} in {:.0?}", model.id, start.elapsed());
+                model.warmed_at = Some(unix_now());
+                model.warmup_ms = Some(start.elapsed().as_millis() as u64);
+            }
+            Err(e) => {
+                tracing::warn!("Failed to warm {}: {}", model.id, e);
+                model.error = Some(e.to_string());
+            }
+        }
+        status.models.push(model);
+    }
+    status.predicted = predicted
+        .iter()
+        .map(|which| which.public_id().to_string())
+        .collect();
+    status.request_mix = ranked
+        .iter()
+        .map(|(which, count)| (which.public_id().to_string(), *count))
+        .collect();
+
+    let evicted = config
+        .idle_ttl
+        .map(|ttl| {
+            // Pooled runners hold their models, so the idle ones go first
+            state.runners.release_idle(ttl);
+            evict_idle_models(ttl)
+        })
+        .unwrap_or_default();
+    status.evicted = warm_pool_status()
+        .map(|previous| previous.evicted)
+        .unwrap_or_default();
+    status.evicted.extend(evicted);
+    let excess = status.evicted.len().saturating_sub(MAX_EVICTIONS);
+    status.evicted.drain(..excess);
+    status.last_run = Some(unix_now());
+
+    if let Ok(mut current) = STATUS.write() {
+        *current = Some(status.clone());
+    }
+    status
+}
+
+/// Evict the idle models last used longer than `ttl` ago.
+fn evict_idle_models(ttl: Duration) -> Vec<EvictedModel> {
+    let now = Instant::now();
+    let mut evicted = Vec::new();
+    for (model, last_used) in idle_models_by_last_use() {
+        let idle = now.duration_since(last_used);
+        if idle <= ttl || !model.evict() {
+            continue;
+        }
+        tracing::info!("Evicted {}, idle for {:.0?}", model.name(), idle);
+        evicted.push(EvictedModel {
+            model: model.name(),
+            idle_secs: idle.as_secs(),
+            evicted_at: unix_now(),
+        });
+    }
+    evicted
+}
+
+/// Run [`run_warm_pool`] every `config.interval` in the background, loading models with
+/// the runner configs of `state`. [`warm_pool_status`] reports what it's doing.
+pub fn spawn_warm_pool(config: WarmPoolConfig, state: AppState) -> JoinHandle<()> {
+    tracing::info!(
+        "Warm pool of {} every {:.0?}: {}; idle TTL: {}",
+        config.size,
+        config.interval,
+        if config.models.is_empty() {
+            "no pinned models".to_string()
+        } else {
+            config
+                .models
+                .iter()
+                .map(|which| which.public_id())
+                .collect::<Vec<_>>()
+                .join(", ")
+        },
+        config
+            .idle_ttl
+            .map_or("none".to_string(), |ttl| format!("{:.0?}", ttl)),
+    );
+    if let Ok(mut current) = STATUS.write() {
+        *current = Some(WarmPoolStatus::new(&config));
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(config.interval);
+        // A pass loading several models can outlast the interval; don't run them back to back
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let config = config.clone();
+            let state = state.clone();
+            // Loading weights and generating block
+            let _ = tokio::task::spawn_blocking(move || run_warm_pool(&config, &state)).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_from_vars() {
+        let config = WarmPoolConfig::from_vars(|name| match name {
+            "WARM_MODELS" => Some("gemma-3-1b-it, gemma-9,llama-3.2-1b-instruct,".to_string()),
+            "WARM_POOL_SIZE" => Some("3".to_string()),
+            "WARM_INTERVAL_SECS" => Some("soon".to_string()),
+            "MODEL_IDLE_TTL_SECS" => Some("600".to_string()),
+            _ => None,
+        });
+        assert_eq!(
+            config,
+            WarmPoolConfig {
+                models: vec![Which::InstructV3_1B, Which::Llama32_1BInstruct],
+                size: 3,
+                interval: Duration::from_secs(300),
+                idle_ttl: Some(Duration::from_secs(600)),
+            }
+        );
+        assert!(config.is_enabled());
+
+        // The pinned models always fit
+        let config = WarmPoolConfig::from_vars(|name| match name {
+            "WARM_MODELS" => Some("gemma-3-1b-it".to_string()),
+            "WARM_POOL_SIZE" => Some("0".to_string()),
+            _ => None,
+        });
+        assert_eq!(config.size, 1);
+
+        let config = WarmPoolConfig::from_vars(|_| None);
+        assert_eq!(config, WarmPoolConfig::default());
+        assert!(!config.is_enabled());
+    }
+
+    #[test]
+    fn test_predictions_follow_the_request_mix() {
+        let start = Instant::now();
+        let at = |secs| start + MIX_WINDOW + Duration::from_secs(secs);
+        let recent = VecDeque::from([
+            (Which::Llama32_3BInstruct, start),
+            (Which::Llama32_1BInstruct, at(1)),
+            (Which::InstructV3_1B, at(2)),
+            (Which::InstructV2_2B, at(3)),
+            (Which::Llama32_1BInstruct, at(4)),
+            (Which::InstructV3_1B, at(5)),
+        ]);
+
+        // Ties go to the model requested last; the first request is out of the window
+        let ranked = rank_requests(&recent, at(6));
+        assert_eq!(
+            ranked,
+            vec![
+                (Which::InstructV3_1B, 2),
+                (Which::Llama32_1BInstruct, 2),
+                (Which::InstructV2_2B, 1),
+            ]
+        );
+        assert_eq!(
+            predict(&ranked, &[Which::InstructV3_1B], 2),
+            vec![Which::Llama32_1BInstruct, Which::InstructV2_2B]
+        );
+        assert!(predict(&ranked, &[], 0).is_empty());
+    }
+}
@@ -0,0 +1,50 @@
+//! End-to-end chat completion against a real checkpoint. The test downloads Gemma 3 1B, so
+//! it is ignored unless the crate is built with the `integration-tests` feature. Gemma is
+//! gated: set `HF_TOKEN` to a token whose account has accepted the license of
+//! `google/gemma-3-1b-it`.
+//!
+//! ```text
+//! HF_TOKEN=... cargo test -p inference-engine --features integration-tests
+//! ```
+
+use axum::body::{Body, to_bytes};
+use axum::http::{Request, StatusCode};
+use inference_engine::{AppState, create_router};
+use serde_json::{Value, json};
+use tower::ServiceExt;
+
+async fn chat_completion(body: &Value) -> Value {
+    let request = Request::post("/v1/chat/completions")
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = create_router(AppState::default())
+        .oneshot(request)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "downloads a checkpoint; enable the integration-tests feature"
+)]
+async fn test_chat_completion_with_gemma3() {
+    let request = json!({
+        "model": "gemma-3-1b-it",
+        "messages": [{"role": "user", "content": "What is the capital of France?"}],
+        "max_tokens": 16,
+        "temperature": 0.0,
+    });
+
+    let first = chat_completion(&request).await;
+    let content = first["choices"][0]["message"]["content"].as_str().unwrap();
+    assert!(!content.trim().is_empty(), "empty completion: {first}");
+    assert_eq!(first["object"], "chat.completion");
+
+    let second = chat_completion(&request).await;
+    assert_eq!(second["choices"][0]["message"]["content"], content);
+}
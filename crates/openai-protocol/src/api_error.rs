@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use axum::Json;
+use axum::extract::rejection::JsonRejection;
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+
+use crate::ErrorResponse;
+
+/// A failed request: its status and OpenAI's `{"error": {...}}` body, sent as
+/// `application/json`. Handlers return it for every failure, including request bodies
+/// that don't parse, so clients only ever see one error format.
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub body: ErrorResponse,
+    /// Sent as `Retry-After`, in whole seconds, e.g. with a 429 for a busy server
+    pub retry_after: Option<Duration>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, kind: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: ErrorResponse::new(kind, message),
+            retry_after: None,
+        }
+    }
+
+    /// A 400 for a request the server can't act on.
+    pub fn invalid_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "invalid_request", message)
+    }
+
+    /// A 404 for a route or resource that doesn't exist.
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+
+    /// A 500 for a failure that is the server's own.
+    pub fn server_error(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "server_error", message)
+    }
+
+    /// Name the request field at fault.
+    pub fn with_param(mut self, param: impl Into<String>) -> Self {
+        self.body.error.param = Some(param.into());
+        self
+    }
+
+    /// Refine the error type with a machine-readable code.
+    pub fn with_code(mut self, code: impl Into<String>) -> Self {
+        self.body.error.code = Some(code.into());
+        self
+    }
+
+    /// Tell the client when to try again.
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = Some(retry_after);
+        self
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, Json(self.body)).into_response();
+        if let Some(retry_after) = self.retry_after {
+            // Rounded up, so a client never retries before it was told to
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, seconds.into());
+        }
+        response
+    }
+}
+
+/// A body that isn't JSON, or doesn't match the request type, keeps axum's status (400,
+/// 415 or 422) but gets the JSON envelope instead of axum's plain text.
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::new(rejection.status(), "invalid_request", rejection.body_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_error_response_is_json() {
+        let response = ApiError::invalid_request("Unsupported model: gemma-9")
+            .with_param("model")
+            .into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.error.message, "Unsupported model: gemma-9");
+        assert_eq!(body.error.kind, "invalid_request");
+        assert_eq!(body.error.param.as_deref(), Some("model"));
+        assert_eq!(body.error.code, None);
+
+        let busy = ApiError::new(StatusCode::TOO_MANY_REQUESTS, "rate_limit_exceeded", "busy")
+            .with_retry_after(Duration::from_millis(1500))
+            .into_response();
+        assert_eq!(busy.headers()[header::RETRY_AFTER], "2");
+    }
+}
@@ -0,0 +1,914 @@
+//! The OpenAI-compatible wire format of predict-otron-9000: chat completion requests,
+//! responses and streaming chunks, the model list and error bodies, with the server's
+//! extensions such as `top_k`, `preset` and a model's `loaded` state.
+//!
+//! The server, the client library and the web UI all use these types, so a field added on
+//! one side can't be missed on the other. The crate only depends on serde and serde_json
+//! and builds for wasm; the `utoipa` feature derives the OpenAPI schemas the server documents, and the
+//! `axum` feature adds [`ApiError`], which every handler of the servers fails with, and
+//! the [`trace_context`] middleware that carries a request's trace from the gateway to the
+//! services behind it.
+
+#[cfg(feature = "axum")]
+mod api_error;
+#[cfg(feature = "axum")]
+pub mod trace_context;
+
+#[cfg(feature = "axum")]
+pub use api_error::ApiError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+#[cfg(feature = "utoipa")]
+use utoipa::ToSchema;
+
+/// Inner content structure for messages that can be either a string or key-value pairs
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum MessageInnerContent {
+    Text(String),
+    Map(HashMap<String, String>),
+}
+
+#[cfg(feature = "utoipa")]
+impl ToSchema<'_> for MessageInnerContent {
+    fn schema() -> (
+        &'static str,
+        utoipa::openapi::RefOr<utoipa::openapi::Schema>,
+    ) {
+        (
+            "MessageInnerContent",
+            utoipa::openapi::RefOr::T(message_inner_content_schema()),
+        )
+    }
+}
+
+/// Schema of [`MessageInnerContent`]: a string, or an object with string values
+#[cfg(feature = "utoipa")]
+fn message_inner_content_schema() -> utoipa::openapi::Schema {
+    use utoipa::openapi::{ObjectBuilder, OneOfBuilder, RefOr, Schema, SchemaType};
+
+    Schema::OneOf(
+        OneOfBuilder::new()
+            .item(Schema::Object(
+                ObjectBuilder::new().schema_type(SchemaType::String).build(),
+            ))
+            .item(Schema::Object(
+                ObjectBuilder::new()
+                    .schema_type(SchemaType::Object)
+                    .additional_properties(Some(RefOr::T(Schema::Object(
+                        ObjectBuilder::new().schema_type(SchemaType::String).build(),
+                    ))))
+                    .build(),
+            ))
+            .build(),
+    )
+}
+
+/// Message content that can be either simple text or complex structured content
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<HashMap<String, MessageInnerContent>>),
+}
+
+impl MessageContent {
+    /// The text, unless the content is made of parts.
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            MessageContent::Text(text) => Some(text),
+            MessageContent::Parts(_) => None,
+        }
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        MessageContent::Text(text.to_string())
+    }
+}
+
+#[cfg(feature = "utoipa")]
+impl ToSchema<'_> for MessageContent {
+    fn schema() -> (
+        &'static str,
+        utoipa::openapi::RefOr<utoipa::openapi::Schema>,
+    ) {
+        (
+            "MessageContent",
+            utoipa::openapi::RefOr::T(message_content_schema()),
+        )
+    }
+}
+
+/// Schema of [`MessageContent`]: a string, or an array of parts
+#[cfg(feature = "utoipa")]
+fn message_content_schema() -> utoipa::openapi::Schema {
+    use utoipa::openapi::{ArrayBuilder, ObjectBuilder, OneOfBuilder, RefOr, Schema, SchemaType};
+
+    Schema::OneOf(
+        OneOfBuilder::new()
+            .item(Schema::Object(
+                ObjectBuilder::new().schema_type(SchemaType::String).build(),
+            ))
+            .item(Schema::Array(
+                ArrayBuilder::new()
+                    .items(RefOr::T(Schema::Object(
+                        ObjectBuilder::new()
+                            .schema_type(SchemaType::Object)
+                            .additional_properties(Some(RefOr::Ref(
+                                utoipa::openapi::Ref::from_schema_name("MessageInnerContent"),
+                            )))
+                            .build(),
+                    )))
+                    .build(),
+            ))
+            .build(),
+    )
+}
+
+/// Represents a single message in a conversation
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct Message {
+    /// The message content
+    pub content: Option<MessageContent>,
+    /// The role of the message sender ("user", "assistant", "system", "tool", etc.)
+    pub role: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The functions an assistant message calls, in place of or along with its content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// On a `tool` message, the id of the call whose result it carries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl Message {
+    /// A message of plain text.
+    pub fn new(role: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            content: Some(MessageContent::Text(content.into())),
+            role: role.into(),
+            name: None,
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new("system", content)
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new("user", content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new("assistant", content)
+    }
+
+    /// An assistant message calling functions instead of answering.
+    pub fn assistant_tool_calls(tool_calls: Vec<ToolCall>) -> Self {
+        Self {
+            content: None,
+            tool_calls: Some(tool_calls),
+            ..Self::new("assistant", "")
+        }
+    }
+
+    /// The result of the tool call `tool_call_id`.
+    pub fn tool(tool_call_id: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            tool_call_id: Some(tool_call_id.into()),
+            ..Self::new("tool", content)
+        }
+    }
+
+    /// The text of the message, or `None` when it has no content or is made of parts.
+    pub fn text(&self) -> Option<&str> {
+        self.content.as_ref().and_then(MessageContent::as_text)
+    }
+}
+
+/// A tool the model may call. Functions are the only kind of tool.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct Tool {
+    /// Always "function"
+    #[serde(rename = "type")]
+    #[cfg_attr(feature = "utoipa", schema(example = "function"))]
+    pub kind: String,
+    pub function: FunctionDefinition,
+}
+
+impl Tool {
+    pub fn function(function: FunctionDefinition) -> Self {
+        Self {
+            kind: "function".to_string(),
+            function,
+        }
+    }
+}
+
+/// A function the model may call: its name, what it does and the JSON schema of its
+/// arguments.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct FunctionDefinition {
+    #[cfg_attr(feature = "utoipa", schema(example = "get_weather"))]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON schema of the arguments object
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(value_type = Object, example = json!({"type": "object", "properties": {"city": {"type": "string"}}})))]
+    pub parameters: Option<serde_json::Value>,
+}
+
+/// Whether the model may or must call a tool: `"none"`, `"auto"` (the default when tools
+/// are given), `"required"`, or a named function.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+#[serde(untagged)]
+pub enum ToolChoice {
+    Mode(String),
+    Function(NamedToolChoice),
+}
+
+/// A `tool_choice` forcing a call to one function.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct NamedToolChoice {
+    /// Always "function"
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionName,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct FunctionName {
+    pub name: String,
+}
+
+/// A call the model made to one of the request's functions.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct ToolCall {
+    #[cfg_attr(
+        feature = "utoipa",
+        schema(example = "call_9b1deb4d3b7d4bad9bdd2b0d7b3dcb6d")
+    )]
+    pub id: String,
+    /// Always "function"
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+/// The function a [`ToolCall`] calls, with its arguments as a JSON string.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct FunctionCall {
+    pub name: String,
+    #[cfg_attr(feature = "utoipa", schema(example = "{\"city\": \"Paris\"}"))]
+    pub arguments: String,
+}
+
+/// A tool call in a streaming chunk. The server sends each call whole, in one chunk, but
+/// OpenAI clients accumulate them by `index` as they do for partial calls.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct ToolCallDelta {
+    /// Position of the call among the choice's tool calls
+    pub index: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<FunctionCallDelta>,
+}
+
+/// The part of a function call a [`ToolCallDelta`] carries.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct FunctionCallDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<String>,
+}
+
+impl ToolCallDelta {
+    /// A whole call as the delta at `index`.
+    pub fn whole(index: usize, call: ToolCall) -> Self {
+        Self {
+            index,
+            id: Some(call.id),
+            kind: Some(call.kind),
+            function: Some(FunctionCallDelta {
+                name: Some(call.function.name),
+                arguments: Some(call.function.arguments),
+            }),
+        }
+    }
+}
+
+/// Stop token configuration for generation
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+#[serde(untagged)]
+pub enum StopTokens {
+    ///  Multiple possible stop sequences
+    Multi(Vec<String>),
+    /// Single stop sequence
+    Single(String),
+}
+
+impl StopTokens {
+    /// The stop sequences, one or many.
+    pub fn as_slice(&self) -> &[String] {
+        match self {
+            StopTokens::Multi(stops) => stops,
+            StopTokens::Single(stop) => std::slice::from_ref(stop),
+        }
+    }
+}
+
+/// Default value helper
+pub fn default_false() -> bool {
+    false
+}
+
+/// Default value helper
+pub fn default_1usize() -> usize {
+    1
+}
+
+/// Default value helper
+pub fn default_model() -> String {
+    "default".to_string()
+}
+
+/// Chat completion request following OpenAI's specification. Unset options are left out
+/// when serializing, so the server applies its own defaults.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct ChatCompletionRequest {
+    #[cfg_attr(feature = "utoipa", schema(example = json!([{"role": "user", "content": "Why did the crab cross the road?"}])))]
+    pub messages: Vec<Message>,
+    #[cfg_attr(feature = "utoipa", schema(example = "gemma-3-1b-it"))]
+    #[serde(default = "default_model")]
+    pub model: String,
+    #[serde(default = "default_false")]
+    #[cfg_attr(feature = "utoipa", schema(example = false))]
+    pub logprobs: bool,
+    #[cfg_attr(feature = "utoipa", schema(example = 256))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<usize>,
+    #[serde(rename = "n")]
+    #[serde(default = "default_1usize")]
+    #[cfg_attr(feature = "utoipa", schema(example = 1))]
+    pub n_choices: usize,
+    #[cfg_attr(feature = "utoipa", schema(example = 0.7))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[cfg_attr(feature = "utoipa", schema(example = 0.9))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    /// Extension: only sample among the `top_k` most likely tokens (applied before `top_p`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(example = 40))]
+    pub top_k: Option<usize>,
+    /// Extension: named sampling settings, `precise`, `balanced` or `creative`. The
+    /// sampling fields of the request override the preset's values.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(example = "balanced"))]
+    pub preset: Option<String>,
+    /// Extension: never repeat an n-gram of this many tokens, counting the prompt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(example = 3))]
+    pub no_repeat_ngram_size: Option<usize>,
+    /// Seeds the sampler: repeating a request with the same seed and settings samples the
+    /// same completion.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(example = 42))]
+    pub seed: Option<u64>,
+    /// Between -2.0 and 2.0. Positive values make a token less likely the more often it
+    /// has already been generated, negative values more likely.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(example = 0.5))]
+    pub frequency_penalty: Option<f64>,
+    /// Up to 4 sequences where generation stops. The returned text doesn't contain them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(example = json!(["\n\n"])))]
+    pub stop: Option<StopTokens>,
+    /// Functions the model may call instead of answering in text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+    /// Whether the model may (`"auto"`), must (`"required"` or a named function) or must
+    /// not (`"none"`) call one of `tools`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(example = "auto"))]
+    pub tool_choice: Option<ToolChoice>,
+    /// Extension: stop generating after this many seconds. It can shorten the server's
+    /// own limit but not extend it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(example = 30.0))]
+    pub timeout: Option<f64>,
+    /// Extension: what to do when the messages plus `max_tokens` don't fit the model's
+    /// context window. `error` (the default) rejects the request with
+    /// `context_length_exceeded`; `drop_oldest` drops the oldest turns after the system
+    /// messages until it fits, always keeping the last one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(example = "drop_oldest"))]
+    pub truncation_strategy: Option<String>,
+    #[cfg_attr(feature = "utoipa", schema(example = false))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    /// Options for a streamed response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+}
+
+/// Options for a streamed chat completion.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct StreamOptions {
+    /// Send a last chunk, with no choices, carrying the usage of the whole request
+    #[serde(default)]
+    pub include_usage: bool,
+}
+
+impl ChatCompletionRequest {
+    /// A request with the server's defaults for everything but the model and messages.
+    pub fn new(model: impl Into<String>, messages: Vec<Message>) -> Self {
+        Self {
+            messages,
+            model: model.into(),
+            logprobs: false,
+            max_tokens: None,
+            n_choices: 1,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            preset: None,
+            no_repeat_ngram_size: None,
+            seed: None,
+            frequency_penalty: None,
+            stop: None,
+            tools: None,
+            tool_choice: None,
+            timeout: None,
+            truncation_strategy: None,
+            stream: None,
+            stream_options: None,
+        }
+    }
+}
+
+/// Chat completion choice
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct ChatCompletionChoice {
+    pub index: usize,
+    pub message: Message,
+    /// "stop", "length" or "tool_calls"
+    pub finish_reason: String,
+}
+
+/// Chat completion response
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct ChatCompletionResponse {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChatCompletionChoice>,
+    pub usage: Usage,
+}
+
+impl ChatCompletionResponse {
+    /// The text of the first choice.
+    pub fn content(&self) -> &str {
+        self.choices
+            .first()
+            .and_then(|choice| choice.message.text())
+            .unwrap_or_default()
+    }
+    /// The functions the first choice calls, if any.
+    pub fn tool_calls(&self) -> &[ToolCall] {
+        self.choices
+            .first()
+            .and_then(|choice| choice.message.tool_calls.as_deref())
+            .unwrap_or_default()
+    }
+}
+
+/// Delta for streaming responses - contains incremental content updates
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct Delta {
+    /// The role of the message sender (only in first chunk)
+    pub role: Option<String>,
+    /// The incremental content
+    pub content: Option<String>,
+    /// Functions the model calls
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
+}
+
+impl Delta {
+    /// A delta adding `text` to the content.
+    pub fn content(text: impl Into<String>) -> Self {
+        Self {
+            role: None,
+            content: Some(text.into()),
+            tool_calls: None,
+        }
+    }
+}
+
+/// Chat completion choice for streaming chunks
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct ChatCompletionChunkChoice {
+    pub index: usize,
+    pub delta: Delta,
+    /// Set on the last chunk of the choice
+    pub finish_reason: Option<String>,
+}
+
+/// Chat completion chunk for streaming responses
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct ChatCompletionChunk {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChatCompletionChunkChoice>,
+    /// On the last chunk, with `stream_options.include_usage`, the usage of the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+impl ChatCompletionChunk {
+    /// The new text of the first choice, if the chunk carries any.
+    pub fn content(&self) -> Option<&str> {
+        self.choices
+            .first()
+            .and_then(|choice| choice.delta.content.as_deref())
+            .filter(|content| !content.is_empty())
+    }
+}
+
+/// Token usage information
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub total_tokens: usize,
+}
+
+impl Usage {
+    pub fn new(prompt_tokens: usize, completion_tokens: usize) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+}
+
+/// Model object representing an available model
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct Model {
+    /// The model identifier
+    pub id: String,
+    /// The object type, always "model"
+    pub object: String,
+    /// Unix timestamp of when the model was created
+    pub created: u64,
+    /// The organization that owns the model
+    pub owned_by: String,
+    /// Model family, e.g. "gemma3"; absent for the embeddings engine's models
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family: Option<String>,
+    /// Context length in tokens: that of the loaded weights, or the model's as served
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<usize>,
+    /// Whether the weights are loaded in this server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loaded: Option<bool>,
+}
+
+impl Model {
+    /// Whether the model generates text, rather than only embedding it.
+    pub fn is_chat(&self) -> bool {
+        self.family.is_some() && !self.id.ends_with("-embed")
+    }
+}
+
+/// Response for listing available models
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct ModelListResponse {
+    /// The object type, always "list"
+    pub object: String,
+    /// Array of available models
+    pub data: Vec<Model>,
+}
+
+/// A chat model with what a client needs to know to use it, from `GET /v1/models/{id}`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct ModelDetail {
+    /// The model identifier
+    pub id: String,
+    /// The object type, always "model"
+    pub object: String,
+    /// Unix timestamp of when the model was created
+    pub created: u64,
+    /// The organization that owns the model
+    pub owned_by: String,
+    /// Model family, e.g. "gemma3"
+    pub family: String,
+    /// HuggingFace repository the weights come from
+    pub repository: String,
+    /// Context length in tokens: that of the loaded weights, or the model's as served
+    pub context_length: usize,
+    /// Number of weights: counted in the loaded checkpoint, or the published size
+    pub parameters: u64,
+    /// What the model does: "chat" for instruction-tuned models or "completion" for base
+    /// ones, plus "code" for code models and "embeddings" for those served as `-embed` ids
+    #[cfg_attr(feature = "utoipa", schema(example = json!(["chat", "code"])))]
+    pub capabilities: Vec<String>,
+    /// Quantization of a GGUF checkpoint, e.g. "q4_k_m"; absent for full-precision weights
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantization: Option<String>,
+    /// Weight type of the loaded model, e.g. "bf16"; absent while it's cold
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dtype: Option<String>,
+    /// Device the loaded model runs on: "cpu", "cuda:<n>" or "metal:<n>"; absent while it's cold
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// "loaded" when the weights are in memory, "cold" when the next request loads them
+    #[cfg_attr(feature = "utoipa", schema(example = "cold"))]
+    pub status: String,
+}
+
+impl ModelDetail {
+    /// Whether the weights are in memory.
+    pub fn is_loaded(&self) -> bool {
+        self.status == "loaded"
+    }
+}
+
+/// Response for warming up a model
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct ModelWarmupResponse {
+    /// The model identifier
+    pub id: String,
+    /// The object type, always "model.warmup"
+    pub object: String,
+    /// Context length of the loaded weights in tokens
+    pub context_length: usize,
+    /// Time spent loading the weights and running the warmup pass, in milliseconds
+    pub warmup_ms: u64,
+}
+
+/// Response for unloading a model
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct ModelDeleteResponse {
+    /// The model identifier
+    pub id: String,
+    /// The object type, always "model"
+    pub object: String,
+    /// Whether the model was loaded, and so was unloaded
+    pub deleted: bool,
+}
+
+/// Response of `GET /health/ready`: whether the models the server waits for are loaded,
+/// sent with a 200 once they are and a 503 until then
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct ReadinessResponse {
+    /// "ready", "loading" while a model is still loading, or "unreachable" for a proxied
+    /// service that doesn't answer
+    pub status: String,
+    /// The models the server waits for
+    pub models: Vec<ModelReadiness>,
+}
+
+/// One model the server waits for before it is ready
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct ModelReadiness {
+    /// The model identifier
+    pub id: String,
+    /// Whether its weights are loaded
+    pub loaded: bool,
+}
+
+/// Response for cancelling a streaming chat completion
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct ChatCompletionCancelResponse {
+    /// The completion's identifier, the `id` of its chunks
+    pub id: String,
+    /// The object type, always "chat.completion.cancel"
+    pub object: String,
+    /// Whether the generation was stopped; the stream ends with a `cancelled` finish reason
+    pub cancelled: bool,
+}
+
+/// Body of a failed request, following OpenAI's error format
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct ErrorResponse {
+    pub error: ErrorDetail,
+}
+
+impl ErrorResponse {
+    pub fn new(kind: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            error: ErrorDetail {
+                message: message.into(),
+                kind: kind.into(),
+                param: None,
+                code: None,
+                request_id: None,
+            },
+        }
+    }
+}
+
+/// What went wrong with a request
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "utoipa", derive(ToSchema))]
+pub struct ErrorDetail {
+    /// Human-readable description of the error
+    #[cfg_attr(feature = "utoipa", schema(example = "Unsupported model: gemma-9"))]
+    pub message: String,
+    /// The error kind, e.g. "model_not_supported", "invalid_request" or "out_of_memory"
+    #[serde(rename = "type")]
+    #[cfg_attr(feature = "utoipa", schema(example = "model_not_supported"))]
+    pub kind: String,
+    /// The request field at fault, if any
+    #[serde(default)]
+    #[cfg_attr(feature = "utoipa", schema(example = "model"))]
+    pub param: Option<String>,
+    /// A machine-readable code refining `type`, if any
+    #[serde(default)]
+    pub code: Option<String>,
+    /// The `x-request-id` of the failed request, to look it up in the server's logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_content_is_text_or_parts() {
+        let text: Message =
+            serde_json::from_value(serde_json::json!({"role": "user", "content": "Hi"})).unwrap();
+        assert_eq!(text, Message::user("Hi"));
+        assert_eq!(text.text(), Some("Hi"));
+
+        let parts: Message = serde_json::from_value(serde_json::json!({
+            "role": "user",
+            "content": [{"type": "text", "text": "Hi"}]
+        }))
+        .unwrap();
+        assert!(matches!(parts.content, Some(MessageContent::Parts(_))));
+        assert_eq!(parts.text(), None);
+
+        // No `name` key unless one is set
+        assert_eq!(
+            serde_json::to_value(Message::assistant("Hello")).unwrap(),
+            serde_json::json!({"role": "assistant", "content": "Hello"})
+        );
+    }
+
+    #[test]
+    fn test_request_round_trip() {
+        let mut request = ChatCompletionRequest::new("m", vec![Message::user("Hi")]);
+        request.max_tokens = Some(8);
+        request.preset = Some("precise".to_string());
+        let body = serde_json::to_value(&request).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "model": "m",
+                "messages": [{"role": "user", "content": "Hi"}],
+                "logprobs": false,
+                "n": 1,
+                "max_tokens": 8,
+                "preset": "precise",
+            })
+        );
+
+        let parsed: ChatCompletionRequest = serde_json::from_value(body).unwrap();
+        assert_eq!(parsed.max_tokens, Some(8));
+        assert_eq!(parsed.stream, None);
+
+        // Everything but the messages has a default
+        let minimal: ChatCompletionRequest =
+            serde_json::from_value(serde_json::json!({"messages": []})).unwrap();
+        assert_eq!(minimal.model, "default");
+        assert_eq!(minimal.n_choices, 1);
+    }
+
+    #[test]
+    fn test_tool_calling_fields_match_openai() {
+        let request: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "messages": [
+                {"role": "user", "content": "Weather in Paris?"},
+                {"role": "assistant", "content": null, "tool_calls": [{
+                    "id": "call_1", "type": "function",
+                    "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}
+                }]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "18C"}
+            ],
+            "tools": [{"type": "function", "function": {
+                "name": "get_weather",
+                "parameters": {"type": "object", "properties": {"city": {"type": "string"}}}
+            }}],
+            "tool_choice": {"type": "function", "function": {"name": "get_weather"}}
+        }))
+        .unwrap();
+        let call = &request.messages[1].tool_calls.as_ref().unwrap()[0];
+        assert_eq!(call.function.name, "get_weather");
+        assert_eq!(request.messages[2], Message::tool("call_1", "18C"));
+        assert_eq!(request.tools.unwrap()[0].function.name, "get_weather");
+        assert!(matches!(
+            request.tool_choice,
+            Some(ToolChoice::Function(NamedToolChoice { function, .. })) if function.name == "get_weather"
+        ));
+        let auto: ChatCompletionRequest =
+            serde_json::from_value(serde_json::json!({"messages": [], "tool_choice": "auto"}))
+                .unwrap();
+        assert_eq!(auto.tool_choice, Some(ToolChoice::Mode("auto".to_string())));
+
+        // A streamed call carries its index, and content-only deltas leave `tool_calls` out
+        let delta = ToolCallDelta::whole(0, call.clone());
+        assert_eq!(
+            serde_json::to_value(delta).unwrap(),
+            serde_json::json!({
+                "index": 0, "id": "call_1", "type": "function",
+                "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(Delta::content("Hi")).unwrap(),
+            serde_json::json!({"role": null, "content": "Hi"})
+        );
+    }
+
+    #[test]
+    fn test_error_body_always_has_param_and_code() {
+        let body = serde_json::to_value(ErrorResponse::new("invalid_request", "bad")).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": {"message": "bad", "type": "invalid_request", "param": null, "code": null}
+            })
+        );
+        let parsed: ErrorResponse =
+            serde_json::from_str(r#"{"error": {"message": "bad", "type": "server_error"}}"#)
+                .unwrap();
+        assert_eq!(parsed.error.param, None);
+    }
+
+    #[test]
+    fn test_model_list_tolerates_plain_openai_models() {
+        let list: ModelListResponse = serde_json::from_str(
+            r#"{"object":"list","data":[
+                {"id":"gemma-3-1b-it","object":"model","created":1,"owned_by":"google","family":"gemma3","context_length":null,"loaded":false},
+                {"id":"nomic-embed-text-v1.5","object":"model","created":1,"owned_by":"nomic-ai"}
+            ]}"#,
+        )
+        .unwrap();
+        let chat: Vec<_> = list.data.iter().filter(|model| model.is_chat()).collect();
+        assert_eq!(chat.len(), 1);
+        assert_eq!(chat[0].id, "gemma-3-1b-it");
+        assert_eq!(chat[0].loaded, Some(false));
+    }
+}
@@ -0,0 +1,332 @@
+use axum::body::Body;
+use axum::extract::{FromRequestParts, Request};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderValue, header};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::convert::Infallible;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::ErrorResponse;
+
+/// The W3C Trace Context header: `00-{trace id}-{parent span id}-{flags}`.
+pub const TRACEPARENT: &str = "traceparent";
+/// An opaque id for one client request, kept as it passes through the gateway.
+pub const REQUEST_ID: &str = "x-request-id";
+
+/// Where one request sits in a trace: the trace it belongs to, the span this service
+/// handles it in, and the span of the caller, if it sent a `traceparent`.
+///
+/// [`propagate_trace`] works it out for every request and stores it in the request's
+/// extensions; handlers that call other services extract it and send
+/// [`RequestTrace::headers`] along, so the callee's span is a child of this one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestTrace {
+    /// 32 hex digits, shared by every span of the trace.
+    pub trace_id: String,
+    /// 16 hex digits, this service's span.
+    pub span_id: String,
+    /// The caller's span, absent for a trace that starts here.
+    pub parent_span_id: Option<String>,
+    pub flags: u8,
+    pub request_id: String,
+}
+
+impl RequestTrace {
+    /// Continue the trace of an incoming request's `traceparent`, or start one if it has
+    /// none or it doesn't parse, in a new span. The request id is kept if the caller sent
+    /// one.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let parent = headers
+            .get(TRACEPARENT)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_traceparent);
+        let request_id = headers
+            .get(REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(str::to_string)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        let span_id = new_span_id();
+        match parent {
+            Some((trace_id, parent_span_id, flags)) => Self {
+                trace_id,
+                span_id,
+                parent_span_id: Some(parent_span_id),
+                flags,
+                request_id,
+            },
+            None => Self {
+                trace_id: Uuid::new_v4().simple().to_string(),
+                span_id,
+                parent_span_id: None,
+                flags: 0x01,
+                request_id,
+            },
+        }
+    }
+
+    /// The `traceparent` that makes this service's span the parent of a callee's.
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-{:02x}", self.trace_id, self.span_id, self.flags)
+    }
+
+    /// The `traceparent` and `x-request-id` headers for a call to another service.
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Ok(value) = HeaderValue::from_str(&self.traceparent()) {
+            headers.insert(TRACEPARENT, value);
+        }
+        if let Ok(value) = HeaderValue::from_str(&self.request_id) {
+            headers.insert(REQUEST_ID, value);
+        }
+        headers
+    }
+}
+
+/// The trace [`propagate_trace`] stored for the request, or a new one from its headers if
+/// the middleware isn't in front of the handler.
+impl<S: Send + Sync> FromRequestParts<S> for RequestTrace {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<RequestTrace>()
+            .cloned()
+            .unwrap_or_else(|| RequestTrace::from_headers(&parts.headers)))
+    }
+}
+
+/// Middleware that runs each request in a `request` span carrying its trace and request
+/// ids, so the logs of the gateway and the service it proxies to can be joined into one
+/// trace, and echoes the request id back in `x-request-id` and in the `request_id` of an
+/// error body.
+///
+/// Add it with `axum::middleware::from_fn(propagate_trace)`, once per server.
+pub async fn propagate_trace(mut request: Request, next: Next) -> Response {
+    let trace = RequestTrace::from_headers(request.headers());
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = %request.uri().path(),
+        trace_id = %trace.trace_id,
+        span_id = %trace.span_id,
+        parent_span_id = tracing::field::Empty,
+        request_id = %trace.request_id,
+    );
+    if let Some(parent_span_id) = &trace.parent_span_id {
+        span.record("parent_span_id", parent_span_id.as_str());
+    }
+
+    let request_id = trace.request_id.clone();
+    request.extensions_mut().insert(trace);
+    let mut response = next.run(request).instrument(span).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID, value);
+    }
+    if response.status().is_client_error() || response.status().is_server_error() {
+        response = name_request_in_error(response, &request_id).await;
+    }
+    response
+}
+
+/// Add `request_id` to an OpenAI error body that doesn't name one yet, e.g. from the
+/// service behind a proxy, which names the same request. Other bodies are left as they
+/// are.
+async fn name_request_in_error(response: Response, request_id: &str) -> Response {
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !is_json || response.headers().contains_key(header::CONTENT_ENCODING) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::warn!("Failed to read the error body: {}", e);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    let Ok(mut error) = serde_json::from_slice::<ErrorResponse>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    if error.error.request_id.is_some() {
+        return Response::from_parts(parts, Body::from(bytes));
+    }
+    error.error.request_id = Some(request_id.to_string());
+    match serde_json::to_vec(&error) {
+        Ok(named) => {
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Response::from_parts(parts, Body::from(named))
+        }
+        Err(_) => Response::from_parts(parts, Body::from(bytes)),
+    }
+}
+
+/// Split a version-00 `traceparent` into its trace id, parent span id and flags.
+fn parse_traceparent(value: &str) -> Option<(String, String, u8)> {
+    let mut fields = value.trim().split('-');
+    let (version, trace_id, parent_id, flags) = (
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+    );
+    let is_hex = |field: &str, len: usize| {
+        field.len() == len
+            && field
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    };
+    let is_zero = |field: &str| field.bytes().all(|b| b == b'0');
+
+    if version != "00" || fields.next().is_some() {
+        return None;
+    }
+    if !is_hex(trace_id, 32) || is_zero(trace_id) || !is_hex(parent_id, 16) || is_zero(parent_id) {
+        return None;
+    }
+    if !is_hex(flags, 2) {
+        return None;
+    }
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+    Some((trace_id.to_string(), parent_id.to_string(), flags))
+}
+
+fn new_span_id() -> String {
+    Uuid::new_v4().simple().to_string()[..16].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const PARENT_ID: &str = "00f067aa0ba902b7";
+
+    #[test]
+    fn test_continues_an_incoming_trace() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            TRACEPARENT,
+            HeaderValue::from_str(&format!("00-{TRACE_ID}-{PARENT_ID}-01")).unwrap(),
+        );
+        headers.insert(REQUEST_ID, HeaderValue::from_static("req-42"));
+
+        let trace = RequestTrace::from_headers(&headers);
+        assert_eq!(trace.trace_id, TRACE_ID);
+        assert_eq!(trace.parent_span_id.as_deref(), Some(PARENT_ID));
+        assert_ne!(trace.span_id, PARENT_ID);
+        assert_eq!(trace.request_id, "req-42");
+
+        // A callee continues the same trace under this service's span
+        let callee = RequestTrace::from_headers(&trace.headers());
+        assert_eq!(callee.trace_id, TRACE_ID);
+        assert_eq!(callee.parent_span_id, Some(trace.span_id));
+        assert_eq!(callee.request_id, "req-42");
+    }
+
+    #[test]
+    fn test_starts_a_trace_without_a_valid_traceparent() {
+        for traceparent in [
+            None,
+            Some("garbage"),
+            Some("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            Some("00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+            Some("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01"),
+        ] {
+            let mut headers = HeaderMap::new();
+            if let Some(traceparent) = traceparent {
+                headers.insert(TRACEPARENT, HeaderValue::from_static(traceparent));
+            }
+            let trace = RequestTrace::from_headers(&headers);
+            assert_eq!(trace.parent_span_id, None, "{traceparent:?}");
+            assert_eq!(trace.trace_id.len(), 32);
+            assert_eq!(trace.span_id.len(), 16);
+            assert!(!trace.request_id.is_empty());
+            assert_eq!(
+                parse_traceparent(&trace.traceparent()),
+                Some((trace.trace_id.clone(), trace.span_id.clone(), 0x01))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_middleware_stores_the_trace_and_echoes_the_request_id() {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|trace: RequestTrace| async move { trace.traceparent() }),
+            )
+            .layer(axum::middleware::from_fn(propagate_trace));
+        let request = Request::builder()
+            .uri("/")
+            .header(TRACEPARENT, format!("00-{TRACE_ID}-{PARENT_ID}-01"))
+            .header(REQUEST_ID, "req-42")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[REQUEST_ID], "req-42");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let (trace_id, span_id, _) =
+            parse_traceparent(std::str::from_utf8(&body).unwrap()).unwrap();
+        assert_eq!(trace_id, TRACE_ID);
+        assert_ne!(span_id, PARENT_ID);
+    }
+
+    #[tokio::test]
+    async fn test_error_bodies_name_the_request() {
+        use crate::ApiError;
+        use axum::response::IntoResponse;
+
+        let app = Router::new()
+            .route("/", get(|| async { ApiError::not_found("No such thing") }))
+            .route(
+                "/named",
+                get(|| async {
+                    let mut error = ApiError::not_found("No such thing");
+                    error.body.error.request_id = Some("req-upstream".to_string());
+                    error.into_response()
+                }),
+            )
+            .layer(axum::middleware::from_fn(propagate_trace));
+        let error_for = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::builder()
+                    .uri(uri)
+                    .header(REQUEST_ID, "req-42")
+                    .body(Body::empty())
+                    .unwrap();
+                let response = app.oneshot(request).await.unwrap();
+                assert_eq!(response.headers()[REQUEST_ID], "req-42");
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<ErrorResponse>(&body).unwrap()
+            }
+        };
+
+        let error = error_for("/").await;
+        assert_eq!(error.error.request_id.as_deref(), Some("req-42"));
+        assert_eq!(error.error.message, "No such thing");
+        // An id the service behind a proxy named is kept
+        let error = error_for("/named").await;
+        assert_eq!(error.error.request_id.as_deref(), Some("req-upstream"));
+    }
+}
@@ -0,0 +1,180 @@
+//! Model aliases that split chat traffic between models by weight, for A/B tests in
+//! production. An alias is resolved in the gateway by a [`ChatHook`], so it works the same
+//! in both modes, and each request is served and reported as the model it was sent to.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use inference_engine::Which;
+use openai_protocol::{ApiError, ChatCompletionRequest};
+use serde::{Deserialize, Serialize};
+
+use crate::hooks::ChatHook;
+
+/// The response header naming the alias a request asked for.
+pub const MODEL_ALIAS_HEADER: &str = "x-model-alias";
+/// The response header naming the model an aliased request was sent to.
+pub const MODEL_VARIANT_HEADER: &str = "x-model-variant";
+
+/// One model an alias sends traffic to, and its share.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelVariant {
+    /// A chat model id, e.g. `gemma-3-1b-it`
+    pub model: String,
+    /// Relative share of the alias's requests; weights of 90 and 10 split them 90/10
+    pub weight: u32,
+}
+
+/// Check the aliases of a config: each needs variants, all chat models with a weight.
+pub fn validate_aliases(aliases: &BTreeMap<String, Vec<ModelVariant>>) -> Result<(), String> {
+    for (alias, variants) in aliases {
+        if variants.is_empty() {
+            return Err(format!("Model alias '{}' has no variants", alias));
+        }
+        if Which::from_public_id(alias).is_some() {
+            return Err(format!(
+                "Model alias '{}' has the name of a model, which it would hide",
+                alias
+            ));
+        }
+        for variant in variants {
+            if Which::from_public_id(&variant.model).is_none() {
+                return Err(format!(
+                    "Model alias '{}' sends traffic to unsupported model {}",
+                    alias, variant.model
+                ));
+            }
+            if variant.weight == 0 {
+                return Err(format!(
+                    "Model alias '{}' gives {} no weight",
+                    alias, variant.model
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Smooth weighted round robin over an alias's variants: over any run of requests as long
+/// as the sum of the weights, each variant gets exactly its share, spread out rather than
+/// in blocks.
+#[derive(Debug)]
+struct Split {
+    variants: Vec<ModelVariant>,
+    current: Vec<i64>,
+}
+
+impl Split {
+    fn new(variants: Vec<ModelVariant>) -> Self {
+        let current = vec![0; variants.len()];
+        Self { variants, current }
+    }
+
+    fn next(&mut self) -> &str {
+        let total: i64 = self.variants.iter().map(|v| i64::from(v.weight)).sum();
+        for (current, variant) in self.current.iter_mut().zip(&self.variants) {
+            *current += i64::from(variant.weight);
+        }
+        let (chosen, _) = self
+            .current
+            .iter()
+            .enumerate()
+            .max_by_key(|(index, current)| (**current, std::cmp::Reverse(*index)))
+            .expect("aliases have variants");
+        self.current[chosen] -= total;
+        &self.variants[chosen].model
+    }
+}
+
+/// Chat hook resolving model aliases to one of their variants.
+#[derive(Debug)]
+pub struct ModelAliases {
+    splits: HashMap<String, Mutex<Split>>,
+}
+
+impl ModelAliases {
+    /// Aliases checked with [`validate_aliases`].
+    pub fn new(aliases: BTreeMap<String, Vec<ModelVariant>>) -> Self {
+        Self {
+            splits: aliases
+                .into_iter()
+                .map(|(alias, variants)| (alias, Mutex::new(Split::new(variants))))
+                .collect(),
+        }
+    }
+
+    /// The model the next request for `alias` goes to, `None` for a name that isn't one.
+    pub fn resolve(&self, alias: &str) -> Option<String> {
+        let split = self.splits.get(alias)?;
+        let mut split = split.lock().ok()?;
+        Some(split.next().to_string())
+    }
+}
+
+impl ChatHook for ModelAliases {
+    fn name(&self) -> &str {
+        "model-aliases"
+    }
+
+    fn on_request(&self, request: &mut ChatCompletionRequest) -> Result<(), ApiError> {
+        if let Some(model) = self.resolve(&request.model) {
+            tracing::debug!("Model alias {} sent to {}", request.model, model);
+            request.model = model;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant(model: &str, weight: u32) -> ModelVariant {
+        ModelVariant {
+            model: model.to_string(),
+            weight,
+        }
+    }
+
+    #[test]
+    fn test_traffic_is_split_by_weight() {
+        let aliases = ModelAliases::new(BTreeMap::from([(
+            "default".to_string(),
+            vec![
+                variant("gemma-3-1b-it", 90),
+                variant("llama-3.2-1b-instruct", 10),
+            ],
+        )]));
+
+        let picks: Vec<String> = (0..100)
+            .map(|_| aliases.resolve("default").unwrap())
+            .collect();
+        let llama = picks
+            .iter()
+            .filter(|model| *model == "llama-3.2-1b-instruct")
+            .count();
+        assert_eq!(llama, 10);
+        // Spread out rather than in one block
+        assert!(picks[..50].contains(&"llama-3.2-1b-instruct".to_string()));
+        assert_eq!(aliases.resolve("gemma-3-1b-it"), None);
+
+        let mut request = ChatCompletionRequest::new("default", vec![]);
+        aliases.on_request(&mut request).unwrap();
+        assert!(request.model == "gemma-3-1b-it" || request.model == "llama-3.2-1b-instruct");
+    }
+
+    #[test]
+    fn test_invalid_aliases_are_rejected() {
+        let alias = |variants| BTreeMap::from([("chat".to_string(), variants)]);
+        assert!(validate_aliases(&alias(vec![variant("gemma-3-1b-it", 1)])).is_ok());
+        assert!(validate_aliases(&alias(vec![])).is_err());
+        assert!(validate_aliases(&alias(vec![variant("gemma-9", 1)])).is_err());
+        assert!(validate_aliases(&alias(vec![variant("gemma-3-1b-it", 0)])).is_err());
+
+        let shadowing = BTreeMap::from([(
+            "gemma-3-1b-it".to_string(),
+            vec![variant("llama-3.2-1b-instruct", 1)],
+        )]);
+        assert!(validate_aliases(&shadowing).is_err());
+    }
+}
@@ -0,0 +1,410 @@
+use clap::{CommandFactory, Parser, ValueEnum};
+use inference_engine::Which;
+use std::path::PathBuf;
+
+use crate::aliases::{ModelAliases, validate_aliases};
+use crate::config::{ServerConfig, ServerMode, Services};
+use crate::hooks::{ChatHooks, SystemRules};
+use crate::middleware::{AdminToken, ApiKeys, DEFAULT_MAX_REQUEST_BODY_MB};
+use crate::tls::TlsSettings;
+
+/// How the binary writes its logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line, for log collectors
+    Json,
+}
+
+/// Command-line flags of the `predict-otron-9000` binary. A flag overrides its environment
+/// variable, which overrides the config from `--config` or `SERVER_CONFIG`. The config's
+/// devices give way to `INFERENCE_DEVICE` and `MODEL_DEVICES` the same way.
+#[derive(Parser, Debug, Clone)]
+#[command(
+    author,
+    version,
+    about = "Unified OpenAI-compatible server for chat completions and embeddings",
+    long_about = None
+)]
+pub struct ServerArgs {
+    /// Address to listen on [config default: 127.0.0.1]
+    #[arg(long, env = "SERVER_HOST")]
+    pub host: Option<String>,
+
+    /// Port to listen on [config default: 8080]
+    #[arg(long, env = "SERVER_PORT")]
+    pub port: Option<u16>,
+
+    /// Server config file with the fields of `SERVER_CONFIG`, read in its place: TOML for a
+    /// .toml file, JSON otherwise
+    #[arg(long, value_name = "PATH", env = "CONFIG_FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Seconds between checks of the config file for changes where it can't be watched;
+    /// 0 never reloads it
+    #[arg(
+        long,
+        value_name = "SECS",
+        env = "CONFIG_RELOAD_SECS",
+        default_value_t = 5
+    )]
+    pub config_reload_secs: u64,
+
+    /// Milliseconds to wait after a change to the config file for more before reloading it
+    #[arg(
+        long,
+        value_name = "MS",
+        env = "CONFIG_RELOAD_DEBOUNCE_MS",
+        default_value_t = 250
+    )]
+    pub config_reload_debounce_ms: u64,
+
+    /// Run the engines in process, or proxy to the services of the config
+    #[arg(long, value_enum, env = "SERVER_MODE")]
+    pub mode: Option<ServerMode>,
+
+    /// URL of the inference service HighAvailability mode proxies chat to
+    #[arg(long, value_name = "URL", env = "INFERENCE_URL")]
+    pub inference_url: Option<String>,
+
+    /// URL of the embeddings service HighAvailability mode proxies embeddings to
+    #[arg(long, value_name = "URL", env = "EMBEDDINGS_URL")]
+    pub embeddings_url: Option<String>,
+
+    /// Key /v1 requests must carry as a bearer token; repeat, or separate by commas, for
+    /// more [config: apiKeys]
+    #[arg(
+        long = "api-key",
+        value_name = "KEY",
+        env = "API_KEYS",
+        value_delimiter = ',',
+        hide_env_values = true
+    )]
+    pub api_keys: Vec<String>,
+
+    /// Token /admin requests must carry as a bearer token; API keys don't open them
+    /// [config: adminToken]
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        env = "ADMIN_TOKEN",
+        hide_env_values = true
+    )]
+    pub admin_token: Option<String>,
+
+    /// Model that requests for the `default` model get, e.g. gemma-3-1b-it
+    #[arg(long, env = "DEFAULT_MODEL")]
+    pub default_model: Option<String>,
+
+    /// Text file of rules added as a system message ahead of every chat request
+    #[arg(long, value_name = "PATH", env = "SYSTEM_RULES_FILE")]
+    pub system_rules: Option<PathBuf>,
+
+    /// SQLite database for the per-minute metrics history; kept in memory without one
+    #[arg(long, value_name = "PATH", env = "METRICS_DB")]
+    pub metrics_db: Option<PathBuf>,
+
+    /// SQLite database for the per-key usage ledger; kept in memory without one
+    #[arg(long, value_name = "PATH", env = "USAGE_DB")]
+    pub usage_db: Option<PathBuf>,
+
+    /// PEM certificate chain to serve HTTPS with; needs --tls-key
+    #[arg(long, value_name = "PATH", env = "TLS_CERT_PATH")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key of the TLS certificate; needs --tls-cert
+    #[arg(long, value_name = "PATH", env = "TLS_KEY_PATH")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Also listen for plain HTTP on this port, redirecting every request to HTTPS
+    #[arg(long, value_name = "PORT", env = "HTTP_REDIRECT_PORT")]
+    pub http_redirect_port: Option<u16>,
+
+    /// Largest request body accepted, in MiB; larger ones get a 413. 0 accepts any size
+    #[arg(
+        long,
+        value_name = "MB",
+        env = "MAX_REQUEST_BODY_MB",
+        default_value_t = DEFAULT_MAX_REQUEST_BODY_MB
+    )]
+    pub max_request_body_mb: usize,
+
+    /// Write logs as text or as JSON lines
+    #[arg(long, value_enum, env = "LOG_FORMAT", default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+}
+
+impl ServerArgs {
+    /// The config from `--config`, or else `SERVER_CONFIG`, with the flags applied.
+    /// Fails for a config that can't be read or a combination the server can't run.
+    pub fn server_config(&self) -> Result<ServerConfig, String> {
+        let mut config = match &self.config {
+            Some(path) => ServerConfig::from_file(path).map_err(|e| e.to_string())?,
+            None => ServerConfig::from_env(),
+        };
+        if let Some(host) = &self.host {
+            config.server_host = host.clone();
+        }
+        if let Some(port) = self.port {
+            config.server_port = port;
+        }
+        if let Some(mode) = &self.mode {
+            config.server_mode = mode.clone();
+        }
+        if self.inference_url.is_some() || self.embeddings_url.is_some() {
+            let services = config.services.get_or_insert_with(Services::default);
+            if let Some(url) = &self.inference_url {
+                services.inference_url = Some(url.clone());
+            }
+            if let Some(url) = &self.embeddings_url {
+                services.embeddings_url = Some(url.clone());
+            }
+        }
+        if let Some(model) = &self.default_model {
+            config.default_model = Some(model.clone());
+        }
+        if !self.api_keys.is_empty() {
+            config.api_keys = ApiKeys::new(&self.api_keys);
+        }
+        if let Some(token) = &self.admin_token {
+            config.admin_token = AdminToken::new(token);
+        }
+
+        config.is_high_availability().map_err(|e| {
+            format!(
+                "{} Set services.inference_url and services.embeddings_url in the config",
+                e
+            )
+        })?;
+        if let Some(model) = &config.default_model
+            && Which::from_public_id(model).is_none()
+        {
+            return Err(format!("Unsupported default model: {}", model));
+        }
+        validate_aliases(&config.model_aliases)?;
+        config.validate_model_routes()?;
+        config.validate_devices()?;
+        Ok(config)
+    }
+
+    /// Like [`ServerArgs::server_config`], exiting with a usage error on failure.
+    pub fn server_config_or_exit(&self) -> ServerConfig {
+        self.server_config()
+            .unwrap_or_else(|message| Self::exit_with(message))
+    }
+
+    /// The chat hooks for `config`'s model aliases and the flags, with the aliases first so
+    /// the other hooks see the model a request is sent to. Fails for a rules file that
+    /// can't be read or is empty.
+    pub fn chat_hooks(&self, config: &ServerConfig) -> Result<ChatHooks, String> {
+        let mut hooks = ChatHooks::new();
+        if !config.model_aliases.is_empty() {
+            hooks = hooks.with(ModelAliases::new(config.model_aliases.clone()));
+        }
+        if let Some(path) = &self.system_rules {
+            let rules = std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            if rules.trim().is_empty() {
+                return Err(format!("{} holds no rules", path.display()));
+            }
+            hooks = hooks.with(SystemRules::new(rules.trim()));
+        }
+        Ok(hooks)
+    }
+
+    /// Like [`ServerArgs::chat_hooks`], exiting with a usage error on failure.
+    pub fn chat_hooks_or_exit(&self, config: &ServerConfig) -> ChatHooks {
+        self.chat_hooks(config)
+            .unwrap_or_else(|message| Self::exit_with(message))
+    }
+
+    /// The TLS settings of the flags, `None` to serve plain HTTP. Fails for a certificate
+    /// without a key or the other way round, and for a redirect without TLS.
+    pub fn tls(&self) -> Result<Option<TlsSettings>, String> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert_path), Some(key_path)) => Ok(Some(TlsSettings {
+                cert_path: cert_path.clone(),
+                key_path: key_path.clone(),
+                redirect_port: self.http_redirect_port,
+            })),
+            (Some(_), None) => Err("--tls-cert needs --tls-key".to_string()),
+            (None, Some(_)) => Err("--tls-key needs --tls-cert".to_string()),
+            (None, None) if self.http_redirect_port.is_some() => {
+                Err("--http-redirect-port needs --tls-cert and --tls-key".to_string())
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// The largest request body accepted in bytes, 0 for any size.
+    pub fn max_request_body_bytes(&self) -> usize {
+        self.max_request_body_mb.saturating_mul(1024 * 1024)
+    }
+
+    /// Like [`ServerArgs::tls`], exiting with a usage error on failure.
+    pub fn tls_or_exit(&self) -> Option<TlsSettings> {
+        self.tls()
+            .unwrap_or_else(|message| Self::exit_with(message))
+    }
+
+    fn exit_with(message: String) -> ! {
+        Self::command()
+            .error(clap::error::ErrorKind::ValueValidation, message)
+            .exit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> ServerArgs {
+        ServerArgs::try_parse_from(
+            std::iter::once("predict-otron-9000").chain(args.iter().copied()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_flags_override_the_config() {
+        let path = std::env::temp_dir().join(format!("server-config-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{
+                "serverMode": "HighAvailability",
+                "serverPort": 9000,
+                "services": {
+                    "inference_url": "http://inference:8080",
+                    "embeddings_url": "http://embeddings:8080"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let config = parse(&["--config", path.to_str().unwrap()])
+            .server_config()
+            .unwrap();
+        assert_eq!(config.server_mode, ServerMode::HighAvailability);
+        assert_eq!(config.server_port, 9000);
+
+        let config = parse(&[
+            "--config",
+            path.to_str().unwrap(),
+            "--mode",
+            "standalone",
+            "--host",
+            "0.0.0.0",
+            "--port",
+            "3000",
+            "--default-model",
+            "gemma-2b-it",
+        ])
+        .server_config()
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(config.server_mode, ServerMode::Standalone);
+        assert_eq!(config.server_host, "0.0.0.0");
+        assert_eq!(config.server_port, 3000);
+        assert_eq!(config.default_model.as_deref(), Some("gemma-2b-it"));
+
+        let config = parse(&[
+            "--mode",
+            "high-availability",
+            "--inference-url",
+            "http://inference:8080",
+            "--embeddings-url",
+            "http://embeddings:8080",
+            "--api-key",
+            "sk-one,sk-two",
+            "--api-key",
+            "sk-three",
+            "--admin-token",
+            "admin-secret",
+        ])
+        .server_config()
+        .unwrap();
+        assert!(config.is_high_availability().unwrap());
+        assert_eq!(config.embeddings_url().unwrap(), "http://embeddings:8080");
+        assert_eq!(config.api_keys.len(), 3);
+        assert_eq!(config.admin_token, AdminToken::new("admin-secret"));
+    }
+
+    #[test]
+    fn test_invalid_settings_are_rejected() {
+        let error = parse(&["--mode", "high-availability"])
+            .server_config()
+            .unwrap_err();
+        assert!(error.contains("services.inference_url"), "{}", error);
+
+        let error = parse(&["--default-model", "gemma-9"])
+            .server_config()
+            .unwrap_err();
+        assert_eq!(error, "Unsupported default model: gemma-9");
+
+        let error = parse(&["--config", "/nonexistent/server.json"])
+            .server_config()
+            .unwrap_err();
+        assert!(!error.is_empty());
+
+        let path = std::env::temp_dir().join(format!("alias-config-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{
+                "serverMode": "Standalone",
+                "modelAliases": {"default": [{"model": "gemma-9", "weight": 1}]}
+            }"#,
+        )
+        .unwrap();
+        let error = parse(&["--config", path.to_str().unwrap()])
+            .server_config()
+            .unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.contains("unsupported model gemma-9"), "{}", error);
+
+        let error = parse(&["--system-rules", "/nonexistent/rules.txt"])
+            .chat_hooks(&ServerConfig::default())
+            .unwrap_err();
+        assert!(error.contains("/nonexistent/rules.txt"), "{}", error);
+        assert!(
+            parse(&[])
+                .chat_hooks(&ServerConfig::default())
+                .unwrap()
+                .is_empty()
+        );
+
+        assert_eq!(
+            parse(&["--tls-cert", "cert.pem"]).tls().unwrap_err(),
+            "--tls-cert needs --tls-key"
+        );
+        assert!(parse(&["--http-redirect-port", "80"]).tls().is_err());
+        assert_eq!(parse(&[]).tls(), Ok(None));
+        let tls = parse(&[
+            "--tls-cert",
+            "cert.pem",
+            "--tls-key",
+            "key.pem",
+            "--http-redirect-port",
+            "80",
+        ])
+        .tls()
+        .unwrap()
+        .unwrap();
+        assert_eq!(tls.key_path, PathBuf::from("key.pem"));
+        assert_eq!(tls.redirect_port, Some(80));
+
+        assert_eq!(parse(&[]).max_request_body_bytes(), 10 * 1024 * 1024);
+        assert_eq!(
+            parse(&["--max-request-body-mb", "0"]).max_request_body_bytes(),
+            0
+        );
+
+        assert!(ServerArgs::try_parse_from(["predict-otron-9000", "--port", "http"]).is_err());
+        assert!(ServerArgs::try_parse_from(["predict-otron-9000", "--log-format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn test_help_is_consistent() {
+        ServerArgs::command().debug_assert();
+    }
+}
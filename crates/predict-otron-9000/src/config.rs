@@ -1,7 +1,19 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::env;
+use std::fs;
+use std::path::Path;
 use tracing::info;
 use tracing::log::error;
+
+use inference_engine::device_placement::{INFERENCE_DEVICE_ENV, MODEL_DEVICES_ENV, parse_pin};
+use inference_engine::scheduler::{INFERENCE_QUEUE_SIZE_ENV, MAX_CONCURRENT_INFERENCES_ENV};
+use inference_engine::{DevicePlacement, InferenceScheduler};
+use runner_core::DeviceSpec;
+
+use crate::aliases::ModelVariant;
+use crate::middleware::auth::{AdminToken, ApiKeys};
+use crate::upstream::RetryPolicy;
 /// # Generating `SERVER_CONFIG` with Node
 // # const server_config = {serverMode: "HighAvailability", services: {inference_url: "http://custom-inference:9000", embeddings_url: "http://custom-embeddings:9001"} };
 // # console.log(JSON.stringify(server_config).replace(/"/g, '\\"'));
@@ -13,9 +25,42 @@ pub struct ServerConfig {
     pub server_host: String,
     #[serde(default = "default_server_port")]
     pub server_port: u16,
+    #[serde(default)]
     pub server_mode: ServerMode,
     #[serde(default)]
     pub services: Option<Services>,
+    /// Model that requests for the `default` model get; unset uses `DEFAULT_MODEL`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_model: Option<String>,
+    /// Names that split chat requests between models by weight, e.g. `default` to 90%
+    /// `gemma-3-1b-it` and 10% `llama-3.2-1b-instruct`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub model_aliases: BTreeMap<String, Vec<ModelVariant>>,
+    /// Keys `/v1` requests must carry as bearer tokens; none leaves the API open. Never
+    /// written back out, so logged configs don't show them.
+    #[serde(default, skip_serializing)]
+    pub api_keys: ApiKeys,
+    /// Token `/admin` requests must carry as a bearer token, apart from the API keys; unset
+    /// leaves the admin endpoints open. Never written back out, like the API keys.
+    #[serde(default, skip_serializing)]
+    pub admin_token: AdminToken,
+    /// Device models load on, as in `INFERENCE_DEVICE`, which wins over it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inference_device: Option<String>,
+    /// Devices of single models, as in `MODEL_DEVICES`, which wins over them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub model_devices: BTreeMap<String, String>,
+    /// Chat generations running at once, as in `MAX_CONCURRENT_INFERENCES`, which wins
+    /// over it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_inferences: Option<usize>,
+    /// Requests waiting for a generation slot, as in `INFERENCE_QUEUE_SIZE`, which wins
+    /// over it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inference_queue_size: Option<usize>,
+    /// Retries and circuit breaking of HighAvailability mode's calls to the services.
+    #[serde(default)]
+    pub proxy_retry: RetryPolicy,
 }
 
 fn default_server_host() -> String {
@@ -26,7 +71,7 @@ fn default_server_port() -> u16 {
     8080
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, clap::ValueEnum)]
 #[serde(rename_all = "PascalCase")]
 pub enum ServerMode {
     Standalone,
@@ -43,6 +88,41 @@ impl Default for ServerMode {
 pub struct Services {
     pub inference_url: Option<String>,
     pub embeddings_url: Option<String>,
+    /// Services for some models in place of the two above; the first route matching a
+    /// request's model wins.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub model_routes: Vec<ModelRoute>,
+}
+
+/// A service for the models whose id matches `model`, in which `*` stands for any text,
+/// e.g. `llama-*` for every Llama model.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ModelRoute {
+    pub model: String,
+    pub url: String,
+}
+
+impl ModelRoute {
+    /// Whether `model` matches the route's pattern.
+    pub fn matches(&self, model: &str) -> bool {
+        let mut parts = self.model.split('*');
+        let first = parts.next().unwrap_or_default();
+        let Some(mut rest) = model.strip_prefix(first) else {
+            return false;
+        };
+        let mut parts: Vec<&str> = parts.collect();
+        // Without a `*` the pattern is the whole id
+        let Some(last) = parts.pop() else {
+            return rest.is_empty();
+        };
+        for part in parts {
+            match rest.find(part) {
+                Some(at) => rest = &rest[at + part.len()..],
+                None => return false,
+            }
+        }
+        rest.ends_with(last)
+    }
 }
 
 impl Default for ServerConfig {
@@ -52,6 +132,15 @@ impl Default for ServerConfig {
             server_port: 8080,
             server_mode: ServerMode::Standalone,
             services: Some(Services::default()),
+            default_model: None,
+            model_aliases: BTreeMap::new(),
+            api_keys: ApiKeys::default(),
+            admin_token: AdminToken::default(),
+            inference_device: None,
+            model_devices: BTreeMap::new(),
+            max_concurrent_inferences: None,
+            inference_queue_size: None,
+            proxy_retry: RetryPolicy::default(),
         }
     }
 }
@@ -81,6 +170,88 @@ impl ServerConfig {
         }
     }
 
+    /// Load configuration from a file with the fields of `SERVER_CONFIG`: TOML for a
+    /// `.toml` file, JSON otherwise
+    pub fn from_file(path: &Path) -> Result<Self, std::io::Error> {
+        let config_str = fs::read_to_string(path)?;
+        let parsed = if path
+            .extension()
+            .is_some_and(|extension| extension == "toml")
+        {
+            toml::from_str(&config_str).map_err(|e| e.to_string())
+        } else {
+            serde_json::from_str(&config_str).map_err(|e| e.to_string())
+        };
+        parsed.map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} is not a valid server config: {}", path.display(), e),
+            )
+        })
+    }
+
+    /// Check the device settings, which the environment would otherwise only warn about
+    pub fn validate_devices(&self) -> Result<(), String> {
+        if let Some(device) = &self.inference_device {
+            device
+                .parse::<DeviceSpec>()
+                .map_err(|e| format!("inferenceDevice: {}", e))?;
+        }
+        for (model, device) in &self.model_devices {
+            parse_pin(model, device).map_err(|e| format!("modelDevices: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Where models load: `INFERENCE_DEVICE` and `MODEL_DEVICES`, with the config's
+    /// settings for the ones that aren't set
+    pub fn device_placement(&self) -> DevicePlacement {
+        DevicePlacement::from_vars(|name| self.var(name))
+    }
+
+    /// The scheduler of the engine's environment variables, with the config's limits for
+    /// the ones that aren't set
+    pub fn inference_scheduler(&self) -> InferenceScheduler {
+        InferenceScheduler::from_vars(|name| self.var(name))
+    }
+
+    /// The environment variable `name`, or else the config's setting for it
+    fn var(&self, name: &str) -> Option<String> {
+        env::var(name).ok().or_else(|| match name {
+            INFERENCE_DEVICE_ENV => self.inference_device.clone(),
+            MODEL_DEVICES_ENV => Some(
+                self.model_devices
+                    .iter()
+                    .map(|(model, device)| format!("{}={}", model, device))
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+            MAX_CONCURRENT_INFERENCES_ENV => self.max_concurrent_inferences.map(|n| n.to_string()),
+            INFERENCE_QUEUE_SIZE_ENV => self.inference_queue_size.map(|n| n.to_string()),
+            _ => None,
+        })
+    }
+
+    /// Check the model routes: each needs a pattern and an http(s) URL
+    pub fn validate_model_routes(&self) -> Result<(), String> {
+        let routes = self
+            .services
+            .iter()
+            .flat_map(|services| &services.model_routes);
+        for route in routes {
+            if route.model.is_empty() {
+                return Err(format!("services.model_routes: no model for {}", route.url));
+            }
+            if !route.url.starts_with("http://") && !route.url.starts_with("https://") {
+                return Err(format!(
+                    "services.model_routes: {} is not an http(s) URL for {}",
+                    route.url, route.model
+                ));
+            }
+        }
+        Ok(())
+    }
+
     /// Check if the server should run in high availability mode
     pub fn is_high_availability(&self) -> Result<bool, std::io::Error> {
         if self.server_mode == ServerMode::HighAvailability {
@@ -120,6 +291,37 @@ impl ServerConfig {
         }
     }
 
+    /// The URL of the first model route matching `model`
+    pub fn model_route(&self, model: &str) -> Option<String> {
+        self.services
+            .as_ref()?
+            .model_routes
+            .iter()
+            .find(|route| route.matches(model))
+            .map(|route| route.url.clone())
+    }
+
+    /// The service chat requests for `model` go to: its model route, or else the
+    /// inference service
+    pub fn inference_url_for(&self, model: &str) -> Option<String> {
+        self.model_route(model).or_else(|| self.inference_url())
+    }
+
+    /// The inference service and then each other service of the model routes, once
+    pub fn inference_urls(&self) -> Vec<String> {
+        let mut urls: Vec<String> = self.inference_url().into_iter().collect();
+        let routes = self
+            .services
+            .iter()
+            .flat_map(|services| &services.model_routes);
+        for route in routes {
+            if !urls.contains(&route.url) {
+                urls.push(route.url.clone());
+            }
+        }
+        urls
+    }
+
     /// Get the embeddings service URL for proxying
     pub fn embeddings_url(&self) -> Option<String> {
         if self.services.is_some() {
@@ -196,6 +398,114 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_toml_config_file() {
+        let path = env::temp_dir().join(format!("server-config-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            r#"
+            serverMode = "HighAvailability"
+            defaultModel = "gemma-3-1b-it"
+            apiKeys = ["sk-one", "sk-two"]
+            adminToken = "admin-secret"
+            inferenceDevice = "cuda:0"
+
+            [services]
+            inference_url = "http://inference:8080"
+            embeddings_url = "http://embeddings:8080"
+
+            [modelDevices]
+            "llama-3.2-3b-instruct" = "cuda:1"
+            "#,
+        )
+        .unwrap();
+        let config = ServerConfig::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(config.is_high_availability().unwrap());
+        assert_eq!(config.inference_url().unwrap(), "http://inference:8080");
+        assert_eq!(config.default_model.as_deref(), Some("gemma-3-1b-it"));
+        assert_eq!(config.api_keys.len(), 2);
+        assert!(!config.admin_token.is_empty());
+        assert!(config.validate_devices().is_ok());
+        // The keys and the token are never written back out
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(!json.contains("sk-one"));
+        assert!(!json.contains("admin-secret"));
+
+        let config = ServerConfig {
+            model_devices: BTreeMap::from([("gemma-9".to_string(), "cpu".to_string())]),
+            ..ServerConfig::default()
+        };
+        assert!(config.validate_devices().unwrap_err().contains("gemma-9"));
+        let config = ServerConfig {
+            inference_device: Some("gpu".to_string()),
+            ..ServerConfig::default()
+        };
+        assert!(config.validate_devices().is_err());
+    }
+
+    #[test]
+    fn test_model_routes() {
+        let config_json = r#"{
+            "serverMode": "HighAvailability",
+            "services": {
+                "inference_url": "http://inference:8080",
+                "embeddings_url": "http://embeddings:8080",
+                "model_routes": [
+                    {"model": "llama-*", "url": "http://gpu-pool-a:8080"},
+                    {"model": "gemma-3-*-it", "url": "http://gpu-pool-b:8080"},
+                    {"model": "gemma-2b-it", "url": "http://gpu-pool-a:8080"}
+                ]
+            }
+        }"#;
+        let config: ServerConfig = serde_json::from_str(config_json).unwrap();
+        assert!(config.validate_model_routes().is_ok());
+
+        let url = |model: &str| config.inference_url_for(model).unwrap();
+        assert_eq!(url("llama-3.2-1b-instruct"), "http://gpu-pool-a:8080");
+        assert_eq!(url("gemma-3-1b-it"), "http://gpu-pool-b:8080");
+        assert_eq!(url("gemma-2b-it"), "http://gpu-pool-a:8080");
+        assert_eq!(url("gemma-2b-it-embed"), "http://inference:8080");
+        assert_eq!(url("gemma-3-1b"), "http://inference:8080");
+        assert_eq!(config.model_route("nomic-embed-text-v1.5"), None);
+        assert_eq!(
+            config.inference_urls(),
+            [
+                "http://inference:8080",
+                "http://gpu-pool-a:8080",
+                "http://gpu-pool-b:8080"
+            ]
+        );
+
+        let route = |model: &str| ModelRoute {
+            model: model.to_string(),
+            url: "http://pool:8080".to_string(),
+        };
+        assert!(route("*").matches("anything"));
+        assert!(route("*-it").matches("gemma-2b-it"));
+        assert!(!route("*-it").matches("gemma-2b-it-embed"));
+        assert!(route("gemma*it*").matches("gemma-2b-it-embed"));
+        assert!(!route("llama-*-3b-*").matches("llama-3.2-1b-instruct"));
+
+        let config = ServerConfig {
+            services: Some(Services {
+                model_routes: vec![ModelRoute {
+                    model: "llama-*".to_string(),
+                    url: "gpu-pool-a:8080".to_string(),
+                }],
+                ..Services::default()
+            }),
+            ..ServerConfig::default()
+        };
+        assert!(
+            config
+                .validate_model_routes()
+                .unwrap_err()
+                .contains("gpu-pool-a:8080")
+        );
+    }
+
     #[test]
     fn test_minimal_high_availability_config_error() {
         let config_json = r#"{"serverMode": "HighAvailability"}"#;
@@ -1,16 +1,23 @@
 use axum::{
-    Router,
-    body::Body,
-    extract::{Request, State},
-    http::{HeaderMap, Method, StatusCode, Uri},
+    Json, Router,
+    body::{Body, Bytes},
+    extract::{Path, Request, State},
+    http::{HeaderMap, Method, StatusCode, Uri, header},
     response::{IntoResponse, Response},
     routing::{get, post},
 };
-use reqwest::Client;
+use futures_util::TryStreamExt;
+use openai_protocol::trace_context::RequestTrace;
+use openai_protocol::{ApiError, ReadinessResponse};
+use reqwest::{Client, RequestBuilder};
 use serde_json::Value;
 use std::time::Duration;
 
 use crate::config::ServerConfig;
+use crate::middleware::body_read_error;
+use crate::reload::LiveConfig;
+use crate::upstream::CircuitBreakers;
+use inference_engine::Which;
 
 /// # Generating `SERVER_CONFIG` for TOML using Node.js
 ///
@@ -75,7 +82,7 @@ use crate::config::ServerConfig;
 /// ## 3) Reading it in Rust
 ///
 /// If `SERVER_CONFIG` is stored as a **string** in TOML (Options A/B/C):
-/// ```rust
+/// ```rust,ignore
 /// use serde_json::Value;
 ///
 /// // Suppose you've already loaded your .toml into a struct or a toml::Value:
@@ -96,7 +103,7 @@ use crate::config::ServerConfig;
 /// inference_url = "http://custom-inference:9000"
 /// embeddings_url = "http://custom-embeddings:9001"
 /// ```
-/// ```rust
+/// ```rust,ignore
 /// use serde::{Deserialize, Serialize};
 /// use serde_json::Value;
 ///
@@ -130,28 +137,131 @@ use crate::config::ServerConfig;
 #[derive(Clone)]
 pub struct ProxyClient {
     client: Client,
-    config: ServerConfig,
+    config: LiveConfig,
+    breakers: CircuitBreakers,
 }
 
 impl ProxyClient {
     pub fn new(config: ServerConfig) -> Self {
+        Self::with_live_config(LiveConfig::new(config))
+    }
+
+    /// Proxy to the services of `config` as of each request, so a reload redirects the
+    /// requests after it.
+    pub fn with_live_config(config: LiveConfig) -> Self {
         let client = Client::builder()
             .timeout(Duration::from_secs(300)) // 5 minute timeout for long-running inference
             .build()
             .expect("Failed to create HTTP client for proxy");
 
-        Self { client, config }
+        Self {
+            client,
+            config,
+            breakers: CircuitBreakers::new(),
+        }
+    }
+
+    /// Send a request to `target_url` through its service's circuit, retrying it under
+    /// the live config's policy if `retryable`. Connection errors and gateway errors that
+    /// aren't the service's own are retried; timeouts aren't, since the service may still
+    /// be working on the request. A gateway error left after the retries is returned to
+    /// be relayed.
+    async fn send(
+        &self,
+        req_builder: RequestBuilder,
+        target_url: &str,
+        retryable: bool,
+    ) -> Result<reqwest::Response, ApiError> {
+        let policy = self.config.current().proxy_retry.clone();
+        let max_retries = if retryable { policy.max_retries } else { 0 };
+        let service = service_of(target_url);
+
+        let mut retry = 0;
+        loop {
+            if let Some(open_for) = self.breakers.open_for(&service) {
+                return Err(ApiError::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "upstream_error",
+                    format!("{} keeps failing, so requests to it are paused", service),
+                )
+                .with_retry_after(open_for));
+            }
+            // Proxied bodies are read whole, so the request can always be sent again
+            let attempt = req_builder
+                .try_clone()
+                .ok_or_else(|| ApiError::server_error("Failed to copy the proxied request"))?;
+
+            let (reason, outcome) = match attempt.send().await {
+                Ok(response) if !is_gateway_failure(&response) => {
+                    self.breakers.record_success(&service);
+                    return Ok(response);
+                }
+                Ok(response) => (format!("status {}", response.status()), Ok(response)),
+                Err(e) => (e.to_string(), Err(e)),
+            };
+            self.breakers.record_failure(&service, &policy);
+            let timed_out = outcome.as_ref().is_err_and(reqwest::Error::is_timeout);
+            if retry >= max_retries || timed_out {
+                return outcome.map_err(|e| upstream_error(target_url, e));
+            }
+
+            let backoff = policy.backoff(retry);
+            retry += 1;
+            tracing::warn!(
+                "Request to {} failed with {}, retrying in {:?} ({} of {})",
+                target_url,
+                reason,
+                backoff,
+                retry,
+                max_retries
+            );
+            tokio::time::sleep(backoff).await;
+        }
     }
 }
 
+/// The service a URL belongs to, i.e. its origin, whose circuit calls to it go through.
+fn service_of(target_url: &str) -> String {
+    reqwest::Url::parse(target_url)
+        .map(|url| url.origin().ascii_serialization())
+        .unwrap_or_else(|_| target_url.to_string())
+}
+
+/// A 502, 503 or 504 from something in front of the service, e.g. a load balancer while
+/// the service restarts. The service's own errors are JSON, and are relayed as they are.
+fn is_gateway_failure(response: &reqwest::Response) -> bool {
+    matches!(
+        response.status(),
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    ) && !is_json(response.headers())
+}
+
 /// Create a router that proxies requests to external services in HighAvailability mode
 pub fn create_ha_router(config: ServerConfig) -> Router {
-    let proxy_client = ProxyClient::new(config.clone());
+    create_live_ha_router(LiveConfig::new(config))
+}
+
+/// Like [`create_ha_router`], proxying to the services of `config` as it is reloaded.
+pub fn create_live_ha_router(config: LiveConfig) -> Router {
+    let proxy_client = ProxyClient::with_live_config(config);
 
     Router::new()
         .route("/v1/chat/completions", post(proxy_chat_completions))
+        .route(
+            "/v1/chat/completions/{id}/cancel",
+            post(proxy_chat_completion_cancel),
+        )
         .route("/v1/models", get(proxy_models))
+        .route(
+            "/v1/models/{id}",
+            get(proxy_model).delete(proxy_model_delete),
+        )
+        .route("/v1/models/{id}/load", post(proxy_model_load))
+        .route("/v1/models/{id}/warmup", post(proxy_model_warmup))
+        .route("/admin/device", get(proxy_device_info))
+        .route("/admin/status", get(proxy_admin_status))
         .route("/v1/embeddings", post(proxy_embeddings))
+        .route("/health/ready", get(proxy_ready))
         .with_state(proxy_client)
 }
 
@@ -159,108 +269,113 @@ pub fn create_ha_router(config: ServerConfig) -> Router {
 async fn proxy_chat_completions(
     State(proxy_client): State<ProxyClient>,
     headers: HeaderMap,
+    trace: RequestTrace,
     body: Body,
-) -> Result<Response, StatusCode> {
+) -> Result<Response, ApiError> {
+    let body_bytes = read_body(body).await?;
+    let json = serde_json::from_slice::<Value>(&body_bytes).ok();
+
+    // The model's route picks the service, falling back to the inference service
+    let model = json
+        .as_ref()
+        .and_then(|json| json["model"].as_str())
+        .unwrap_or_default();
     let target_url = format!(
         "{}/v1/chat/completions",
         proxy_client
             .config
-            .inference_url()
+            .current()
+            .inference_url_for(model)
             .expect("Invalid Configuration")
     );
 
     tracing::info!("Proxying chat completions request to: {}", target_url);
 
-    // Extract body as bytes
-    let body_bytes = match axum::body::to_bytes(body, usize::MAX).await {
-        Ok(bytes) => bytes,
-        Err(e) => {
-            tracing::error!("Failed to read request body: {}", e);
-            return Err(StatusCode::BAD_REQUEST);
-        }
-    };
-
-    // Check if this is a streaming request
-    let is_streaming = if let Ok(body_str) = String::from_utf8(body_bytes.to_vec()) {
-        if let Ok(json) = serde_json::from_str::<Value>(&body_str) {
-            json.get("stream")
-                .and_then(|v| v.as_bool())
-                .unwrap_or(false)
-        } else {
-            false
-        }
-    } else {
-        false
-    };
-
     // Forward the request
     let mut req_builder = proxy_client
         .client
         .post(&target_url)
         .body(body_bytes.to_vec());
 
-    // Forward relevant headers
-    for (name, value) in headers.iter() {
-        if should_forward_header(name.as_str()) {
-            req_builder = req_builder.header(name, value);
+    req_builder = forward_headers(req_builder, &headers, &trace);
+
+    // A stream may have sent events before failing, so only whole responses are retried
+    let streaming = json
+        .as_ref()
+        .and_then(|json| json["stream"].as_bool())
+        .unwrap_or(false);
+
+    // Streams are relayed with the service's `text/event-stream` content type
+    match proxy_client
+        .send(req_builder, &target_url, !streaming)
+        .await
+    {
+        Ok(response) => relay_response(response, &target_url).await,
+        Err(e) => {
+            tracing::error!(
+                "Failed to proxy chat completions request: {}",
+                e.body.error.message
+            );
+            Err(e)
         }
     }
+}
 
-    match req_builder.send().await {
-        Ok(response) => {
-            let mut resp_builder = Response::builder().status(response.status());
+/// Proxy handler for POST /v1/chat/completions/{id}/cancel
+async fn proxy_chat_completion_cancel(
+    State(proxy_client): State<ProxyClient>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    trace: RequestTrace,
+) -> Result<Response, ApiError> {
+    // The completion could be running on any inference service of the model routes, so
+    // each is asked in turn until one knows it
+    let service_urls = proxy_client.config.current().inference_urls();
+    if service_urls.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "upstream_error",
+            "No inference service is configured",
+        ));
+    }
+    for service_url in &service_urls {
+        let target_url = format!("{}/v1/chat/completions/{}/cancel", service_url, id);
 
-            // Forward response headers
-            for (name, value) in response.headers().iter() {
-                if should_forward_response_header(name.as_str()) {
-                    resp_builder = resp_builder.header(name, value);
-                }
-            }
+        tracing::info!("Proxying chat completion cancel request to: {}", target_url);
 
-            // Handle streaming vs non-streaming responses
-            if is_streaming {
-                // For streaming, we need to forward the response as-is
-                match response.bytes().await {
-                    Ok(body) => resp_builder
-                        .header("content-type", "text/plain; charset=utf-8")
-                        .header("cache-control", "no-cache")
-                        .header("connection", "keep-alive")
-                        .body(Body::from(body))
-                        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR),
-                    Err(e) => {
-                        tracing::error!("Failed to read streaming response body: {}", e);
-                        Err(StatusCode::INTERNAL_SERVER_ERROR)
-                    }
-                }
-            } else {
-                // For non-streaming, forward the JSON response
-                match response.bytes().await {
-                    Ok(body) => resp_builder
-                        .body(Body::from(body))
-                        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR),
-                    Err(e) => {
-                        tracing::error!("Failed to read response body: {}", e);
-                        Err(StatusCode::INTERNAL_SERVER_ERROR)
-                    }
-                }
+        let mut req_builder = proxy_client.client.post(&target_url);
+
+        req_builder = forward_headers(req_builder, &headers, &trace);
+
+        match proxy_client.send(req_builder, &target_url, false).await {
+            Ok(response) if response.status() == StatusCode::NOT_FOUND => continue,
+            Ok(response) => return relay_response(response, &target_url).await,
+            Err(e) => {
+                tracing::error!(
+                    "Failed to proxy chat completion cancel request: {}",
+                    e.body.error.message
+                );
+                return Err(e);
             }
         }
-        Err(e) => {
-            tracing::error!("Failed to proxy chat completions request: {}", e);
-            Err(StatusCode::BAD_GATEWAY)
-        }
     }
+    Err(ApiError::not_found(format!(
+        "No inference service knows completion {}",
+        id
+    )))
 }
 
 /// Proxy handler for GET /v1/models
 async fn proxy_models(
     State(proxy_client): State<ProxyClient>,
     headers: HeaderMap,
-) -> Result<Response, StatusCode> {
+    trace: RequestTrace,
+) -> Result<Response, ApiError> {
     let target_url = format!(
         "{}/v1/models",
         proxy_client
             .config
+            .current()
             .inference_url()
             .expect("Invalid Configuration Detected")
     );
@@ -269,65 +384,321 @@ async fn proxy_models(
 
     let mut req_builder = proxy_client.client.get(&target_url);
 
-    // Forward relevant headers
-    for (name, value) in headers.iter() {
-        if should_forward_header(name.as_str()) {
-            req_builder = req_builder.header(name, value);
+    req_builder = forward_headers(req_builder, &headers, &trace);
+
+    match proxy_client.send(req_builder, &target_url, true).await {
+        Ok(response) => relay_response(response, &target_url).await,
+        Err(e) => {
+            tracing::error!("Failed to proxy models request: {}", e.body.error.message);
+            Err(e)
         }
     }
+}
 
-    match req_builder.send().await {
-        Ok(response) => {
-            let mut resp_builder = Response::builder().status(response.status());
+/// Proxy handler for GET /v1/models/{id}
+async fn proxy_model(
+    State(proxy_client): State<ProxyClient>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    trace: RequestTrace,
+) -> Result<Response, ApiError> {
+    let target_url = format!(
+        "{}/v1/models/{}",
+        proxy_client
+            .config
+            .current()
+            .inference_url_for(&id)
+            .expect("Invalid Configuration Detected"),
+        id
+    );
 
-            // Forward response headers
-            for (name, value) in response.headers().iter() {
-                if should_forward_response_header(name.as_str()) {
-                    resp_builder = resp_builder.header(name, value);
-                }
-            }
+    tracing::info!("Proxying model request to: {}", target_url);
 
-            match response.bytes().await {
-                Ok(body) => resp_builder
-                    .body(Body::from(body))
-                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR),
-                Err(e) => {
-                    tracing::error!("Failed to read models response body: {}", e);
-                    Err(StatusCode::INTERNAL_SERVER_ERROR)
-                }
-            }
+    let mut req_builder = proxy_client.client.get(&target_url);
+
+    req_builder = forward_headers(req_builder, &headers, &trace);
+
+    match proxy_client.send(req_builder, &target_url, true).await {
+        Ok(response) => relay_response(response, &target_url).await,
+        Err(e) => {
+            tracing::error!("Failed to proxy model request: {}", e.body.error.message);
+            Err(e)
         }
+    }
+}
+
+/// Proxy handler for DELETE /v1/models/{id}
+async fn proxy_model_delete(
+    State(proxy_client): State<ProxyClient>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    trace: RequestTrace,
+) -> Result<Response, ApiError> {
+    let target_url = format!(
+        "{}/v1/models/{}",
+        proxy_client
+            .config
+            .current()
+            .inference_url_for(&id)
+            .expect("Invalid Configuration Detected"),
+        id
+    );
+
+    tracing::info!("Proxying model unload request to: {}", target_url);
+
+    let mut req_builder = proxy_client.client.delete(&target_url);
+
+    req_builder = forward_headers(req_builder, &headers, &trace);
+
+    match proxy_client.send(req_builder, &target_url, false).await {
+        Ok(response) => relay_response(response, &target_url).await,
         Err(e) => {
-            tracing::error!("Failed to proxy models request: {}", e);
-            Err(StatusCode::BAD_GATEWAY)
+            tracing::error!(
+                "Failed to proxy model unload request: {}",
+                e.body.error.message
+            );
+            Err(e)
         }
     }
 }
 
-/// Proxy handler for POST /v1/embeddings
-async fn proxy_embeddings(
+/// Proxy handler for POST /v1/models/{id}/load
+async fn proxy_model_load(
     State(proxy_client): State<ProxyClient>,
+    Path(id): Path<String>,
     headers: HeaderMap,
-    body: Body,
-) -> Result<Response, StatusCode> {
+    trace: RequestTrace,
+) -> Result<Response, ApiError> {
     let target_url = format!(
-        "{}/v1/embeddings",
+        "{}/v1/models/{}/load",
+        proxy_client
+            .config
+            .current()
+            .inference_url_for(&id)
+            .expect("Invalid Configuration Detected"),
+        id
+    );
+
+    tracing::info!("Proxying model load request to: {}", target_url);
+
+    let mut req_builder = proxy_client.client.post(&target_url);
+
+    req_builder = forward_headers(req_builder, &headers, &trace);
+
+    match proxy_client.send(req_builder, &target_url, false).await {
+        Ok(response) => relay_response(response, &target_url).await,
+        Err(e) => {
+            tracing::error!(
+                "Failed to proxy model load request: {}",
+                e.body.error.message
+            );
+            Err(e)
+        }
+    }
+}
+
+/// Proxy handler for POST /v1/models/{id}/warmup
+async fn proxy_model_warmup(
+    State(proxy_client): State<ProxyClient>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    trace: RequestTrace,
+) -> Result<Response, ApiError> {
+    let target_url = format!(
+        "{}/v1/models/{}/warmup",
         proxy_client
             .config
-            .embeddings_url()
+            .current()
+            .inference_url_for(&id)
+            .expect("Invalid Configuration Detected"),
+        id
+    );
+
+    tracing::info!("Proxying model warmup request to: {}", target_url);
+
+    let mut req_builder = proxy_client.client.post(&target_url);
+
+    req_builder = forward_headers(req_builder, &headers, &trace);
+
+    match proxy_client.send(req_builder, &target_url, false).await {
+        Ok(response) => relay_response(response, &target_url).await,
+        Err(e) => {
+            tracing::error!(
+                "Failed to proxy model warmup request: {}",
+                e.body.error.message
+            );
+            Err(e)
+        }
+    }
+}
+
+/// Proxy handler for GET /admin/device
+async fn proxy_device_info(
+    State(proxy_client): State<ProxyClient>,
+    headers: HeaderMap,
+    trace: RequestTrace,
+) -> Result<Response, ApiError> {
+    let target_url = format!(
+        "{}/admin/device",
+        proxy_client
+            .config
+            .current()
+            .inference_url()
             .expect("Invalid Configuration Detected")
     );
 
-    tracing::info!("Proxying embeddings request to: {}", target_url);
+    tracing::info!("Proxying device report request to: {}", target_url);
+
+    let mut req_builder = proxy_client.client.get(&target_url);
+
+    req_builder = forward_headers(req_builder, &headers, &trace);
 
-    // Extract body as bytes
-    let body_bytes = match axum::body::to_bytes(body, usize::MAX).await {
-        Ok(bytes) => bytes,
+    match proxy_client.send(req_builder, &target_url, true).await {
+        Ok(response) => relay_response(response, &target_url).await,
         Err(e) => {
-            tracing::error!("Failed to read request body: {}", e);
-            return Err(StatusCode::BAD_REQUEST);
+            tracing::error!(
+                "Failed to proxy device report request: {}",
+                e.body.error.message
+            );
+            Err(e)
         }
+    }
+}
+
+/// Proxy handler for GET /admin/status - the inference service loads the models and runs
+/// the warm pool
+async fn proxy_admin_status(
+    State(proxy_client): State<ProxyClient>,
+    headers: HeaderMap,
+    trace: RequestTrace,
+) -> Result<Response, ApiError> {
+    let target_url = format!(
+        "{}/admin/status",
+        proxy_client
+            .config
+            .current()
+            .inference_url()
+            .expect("Invalid Configuration Detected")
+    );
+
+    tracing::info!("Proxying admin status request to: {}", target_url);
+
+    let mut req_builder = proxy_client.client.get(&target_url);
+
+    req_builder = forward_headers(req_builder, &headers, &trace);
+
+    match proxy_client.send(req_builder, &target_url, true).await {
+        Ok(response) => relay_response(response, &target_url).await,
+        Err(e) => {
+            tracing::error!(
+                "Failed to proxy admin status request: {}",
+                e.body.error.message
+            );
+            Err(e)
+        }
+    }
+}
+
+/// How long a readiness check waits for each service, well below probe timeouts.
+const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The readiness a service's `/health/ready` at `service_url` reports, or `unreachable` when
+/// it doesn't answer with one.
+async fn service_readiness(client: &Client, service_url: &str) -> ReadinessResponse {
+    let target_url = format!("{}/health/ready", service_url);
+    let readiness = match client
+        .get(&target_url)
+        .timeout(READINESS_TIMEOUT)
+        .send()
+        .await
+    {
+        Ok(response) => response.json::<ReadinessResponse>().await.ok(),
+        Err(e) => {
+            tracing::warn!("Readiness check of {} failed: {}", target_url, e);
+            None
+        }
+    };
+    readiness.unwrap_or_else(|| ReadinessResponse {
+        status: "unreachable".to_string(),
+        models: Vec::new(),
+    })
+}
+
+/// Handler for GET /health/ready in HighAvailability mode: ready once both services are,
+/// with the models each waits for
+async fn proxy_ready(
+    State(proxy_client): State<ProxyClient>,
+) -> (StatusCode, Json<ReadinessResponse>) {
+    let config = proxy_client.config.current();
+    let (Some(inference_url), Some(embeddings_url)) =
+        (config.inference_url(), config.embeddings_url())
+    else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ReadinessResponse {
+                status: "unreachable".to_string(),
+                models: Vec::new(),
+            }),
+        );
+    };
+    let services = [
+        service_readiness(&proxy_client.client, &inference_url).await,
+        service_readiness(&proxy_client.client, &embeddings_url).await,
+    ];
+
+    let status = if services.iter().all(|service| service.status == "ready") {
+        "ready"
+    } else if services
+        .iter()
+        .any(|service| service.status == "unreachable")
+    {
+        "unreachable"
+    } else {
+        "loading"
+    };
+    let response = ReadinessResponse {
+        status: status.to_string(),
+        models: services
+            .into_iter()
+            .flat_map(|service| service.models)
+            .collect(),
+    };
+    if status == "ready" {
+        (StatusCode::OK, Json(response))
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, Json(response))
+    }
+}
+
+/// Proxy handler for POST /v1/embeddings
+async fn proxy_embeddings(
+    State(proxy_client): State<ProxyClient>,
+    headers: HeaderMap,
+    trace: RequestTrace,
+    body: Body,
+) -> Result<Response, ApiError> {
+    let body_bytes = read_body(body).await?;
+    let model = serde_json::from_slice::<Value>(&body_bytes)
+        .ok()
+        .and_then(|json| json["model"].as_str().map(str::to_string))
+        .unwrap_or_default();
+
+    // A model with a route goes to its service; other embeddings from a generation model
+    // are computed by the inference service
+    let config = proxy_client.config.current();
+    let service_url = if let Some(route) = config.model_route(&model) {
+        Some(route)
+    } else if Which::from_embedding_id(&model).is_some() {
+        config.inference_url()
+    } else {
+        config.embeddings_url()
     };
+    let target_url = format!(
+        "{}/v1/embeddings",
+        service_url.expect("Invalid Configuration Detected")
+    );
+
+    tracing::info!("Proxying embeddings request to: {}", target_url);
 
     // Forward the request
     let mut req_builder = proxy_client
@@ -335,39 +706,102 @@ async fn proxy_embeddings(
         .post(&target_url)
         .body(body_bytes.to_vec());
 
-    // Forward relevant headers
+    req_builder = forward_headers(req_builder, &headers, &trace);
+
+    match proxy_client.send(req_builder, &target_url, true).await {
+        Ok(response) => relay_response(response, &target_url).await,
+        Err(e) => {
+            tracing::error!(
+                "Failed to proxy embeddings request: {}",
+                e.body.error.message
+            );
+            Err(e)
+        }
+    }
+}
+
+/// Add the request's headers to a call to a service, with the trace context replaced by
+/// the gateway's, so the service's span becomes a child of the gateway's.
+fn forward_headers(
+    mut req_builder: RequestBuilder,
+    headers: &HeaderMap,
+    trace: &RequestTrace,
+) -> RequestBuilder {
     for (name, value) in headers.iter() {
         if should_forward_header(name.as_str()) {
             req_builder = req_builder.header(name, value);
         }
     }
+    req_builder.headers(trace.headers())
+}
 
-    match req_builder.send().await {
-        Ok(response) => {
-            let mut resp_builder = Response::builder().status(response.status());
-
-            // Forward response headers
-            for (name, value) in response.headers().iter() {
-                if should_forward_response_header(name.as_str()) {
-                    resp_builder = resp_builder.header(name, value);
-                }
-            }
+/// Read the body of a request to forward.
+async fn read_body(body: Body) -> Result<Bytes, ApiError> {
+    axum::body::to_bytes(body, usize::MAX).await.map_err(|e| {
+        tracing::error!("Failed to read request body: {}", e);
+        body_read_error(e)
+    })
+}
 
-            match response.bytes().await {
-                Ok(body) => resp_builder
-                    .body(Body::from(body))
-                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR),
-                Err(e) => {
-                    tracing::error!("Failed to read embeddings response body: {}", e);
-                    Err(StatusCode::INTERNAL_SERVER_ERROR)
-                }
-            }
-        }
-        Err(e) => {
-            tracing::error!("Failed to proxy embeddings request: {}", e);
-            Err(StatusCode::BAD_GATEWAY)
+/// Relay a service's response with its status and headers, streaming the body through.
+/// Error bodies that aren't JSON, e.g. from a load balancer in front of the service, are
+/// read whole and wrapped in the error envelope.
+async fn relay_response(
+    response: reqwest::Response,
+    target_url: &str,
+) -> Result<Response, ApiError> {
+    let status = response.status();
+    let mut resp_builder = Response::builder().status(status);
+
+    // Forward response headers
+    for (name, value) in response.headers().iter() {
+        if should_forward_response_header(name.as_str()) {
+            resp_builder = resp_builder.header(name, value);
         }
     }
+    let is_json = is_json(response.headers());
+
+    if status.is_success() || is_json {
+        // Pass the body on as it arrives, so a stream's events reach the client as they
+        // are generated and hanging up drops the connection to the service
+        let target_url = target_url.to_string();
+        let chunks = response.bytes_stream().inspect_err(move |e| {
+            tracing::error!("Failed to relay response body from {}: {}", target_url, e);
+        });
+        return resp_builder
+            .body(Body::from_stream(chunks))
+            .map_err(|e| ApiError::server_error(format!("Failed to relay the response: {}", e)));
+    }
+
+    let body = response.bytes().await.map_err(|e| {
+        tracing::error!("Failed to read response body from {}: {}", target_url, e);
+        upstream_error(target_url, e)
+    })?;
+    let message = String::from_utf8_lossy(&body).trim().to_string();
+    let message = if message.is_empty() {
+        format!("{} answered with status {}", target_url, status)
+    } else {
+        message
+    };
+    Err(ApiError::new(status, "upstream_error", message))
+}
+
+/// Whether a response's body is JSON, which the services' own responses, errors
+/// included, always are.
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"))
+}
+
+/// The error for a service that couldn't be reached or didn't answer in full.
+fn upstream_error(target_url: &str, error: reqwest::Error) -> ApiError {
+    ApiError::new(
+        StatusCode::BAD_GATEWAY,
+        "upstream_error",
+        format!("Request to {} failed: {}", target_url, error),
+    )
 }
 
 /// Determine if a request header should be forwarded to the target service
@@ -375,16 +809,20 @@ fn should_forward_header(header_name: &str) -> bool {
     match header_name.to_lowercase().as_str() {
         "content-type" | "content-length" | "authorization" | "user-agent" | "accept" => true,
         "host" | "connection" | "upgrade" => false, // Don't forward connection-specific headers
-        _ => true,                                  // Forward other headers by default
+        // The gateway compresses responses itself, and its chat hooks read them plain
+        "accept-encoding" => false,
+        _ => true, // Forward other headers by default
     }
 }
 
 /// Determine if a response header should be forwarded back to the client
 fn should_forward_response_header(header_name: &str) -> bool {
     match header_name.to_lowercase().as_str() {
-        "content-type" | "content-length" | "cache-control" | "connection" => true,
+        "content-type" | "content-length" | "cache-control" => true,
         "server" | "date" => false, // Don't forward server-specific headers
-        _ => true,                  // Forward other headers by default
+        // Hyper frames the relayed body itself, so the service's framing doesn't apply
+        "connection" | "transfer-encoding" => false,
+        _ => true, // Forward other headers by default
     }
 }
 
@@ -399,6 +837,7 @@ mod tests {
         assert!(should_forward_header("authorization"));
         assert!(!should_forward_header("host"));
         assert!(!should_forward_header("connection"));
+        assert!(!should_forward_header("accept-encoding"));
     }
 
     #[test]
@@ -407,6 +846,7 @@ mod tests {
         assert!(should_forward_response_header("cache-control"));
         assert!(!should_forward_response_header("server"));
         assert!(!should_forward_response_header("date"));
+        assert!(!should_forward_response_header("transfer-encoding"));
     }
 
     #[test]
@@ -418,17 +858,46 @@ mod tests {
             services: Some(Services {
                 inference_url: Some("http://test-inference:8080".to_string()),
                 embeddings_url: Some("http://test-embeddings:8080".to_string()),
+                ..Services::default()
             }),
+            ..ServerConfig::default()
         };
 
         let proxy_client = ProxyClient::new(config);
         assert_eq!(
-            proxy_client.config.inference_url().unwrap().as_str(),
+            proxy_client
+                .config
+                .current()
+                .inference_url()
+                .unwrap()
+                .as_str(),
             "http://test-inference:8080"
         );
         assert_eq!(
-            proxy_client.config.embeddings_url().unwrap().as_str(),
+            proxy_client
+                .config
+                .current()
+                .embeddings_url()
+                .unwrap()
+                .as_str(),
             "http://test-embeddings:8080"
         );
     }
+
+    #[tokio::test]
+    async fn test_cancel_without_inference_services_is_unavailable() {
+        let proxy_client = ProxyClient::new(ServerConfig {
+            server_mode: ServerMode::HighAvailability,
+            ..ServerConfig::default()
+        });
+        let error = proxy_chat_completion_cancel(
+            State(proxy_client),
+            Path("chatcmpl-123".to_string()),
+            HeaderMap::new(),
+            RequestTrace::from_headers(&HeaderMap::new()),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status, StatusCode::SERVICE_UNAVAILABLE);
+    }
 }
@@ -0,0 +1,396 @@
+//! Hooks that see every chat completion passing through the gateway: the request before
+//! it's served, and the response or each streamed chunk before it's sent back. They run
+//! in the gateway, around whichever router serves the request, so Standalone and
+//! HighAvailability mode apply them the same way.
+
+use std::sync::{Arc, Mutex};
+
+use axum::Router;
+use axum::body::{Body, Bytes};
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, HeaderValue, Method, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use futures_util::{StreamExt, stream};
+use openai_protocol::{
+    ApiError, ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, Message,
+};
+
+use crate::aliases::{MODEL_ALIAS_HEADER, MODEL_VARIANT_HEADER};
+use crate::middleware::body_read_error;
+
+const CHAT_COMPLETIONS: &str = "/v1/chat/completions";
+
+/// Inspects or changes chat completions, e.g. to add an organisation's rules to every
+/// prompt, redact personal data or watermark replies. Every method has a default that
+/// leaves things as they are, so a hook implements only the ones it needs.
+pub trait ChatHook: Send + Sync {
+    /// Name for the logs.
+    fn name(&self) -> &str;
+
+    /// Called before the request is served. An error rejects it and is sent to the client.
+    fn on_request(&self, _request: &mut ChatCompletionRequest) -> Result<(), ApiError> {
+        Ok(())
+    }
+
+    /// Called with a complete, non-streamed response to `request`.
+    fn on_response(
+        &self,
+        _request: &ChatCompletionRequest,
+        _response: &mut ChatCompletionResponse,
+    ) {
+    }
+
+    /// Called with each chunk of a streamed response to `request`.
+    fn on_chunk(&self, _request: &ChatCompletionRequest, _chunk: &mut ChatCompletionChunk) {}
+}
+
+/// The hooks registered at startup. Requests pass through them in order, responses in
+/// reverse, so the first hook sees the request first and the response last.
+#[derive(Clone, Default)]
+pub struct ChatHooks {
+    hooks: Arc<Vec<Arc<dyn ChatHook>>>,
+}
+
+impl std::fmt::Debug for ChatHooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+impl ChatHooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `hook` after those registered so far.
+    pub fn with(mut self, hook: impl ChatHook + 'static) -> Self {
+        Arc::make_mut(&mut self.hooks).push(Arc::new(hook));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// The names of the hooks, in order.
+    pub fn names(&self) -> Vec<&str> {
+        self.hooks.iter().map(|hook| hook.name()).collect()
+    }
+
+    fn on_request(&self, request: &mut ChatCompletionRequest) -> Result<(), ApiError> {
+        for hook in self.hooks.iter() {
+            hook.on_request(request).inspect_err(|e| {
+                tracing::info!(
+                    "Chat hook {} rejected the request: {}",
+                    hook.name(),
+                    e.body.error.message
+                );
+            })?;
+        }
+        Ok(())
+    }
+
+    fn on_response(&self, request: &ChatCompletionRequest, response: &mut ChatCompletionResponse) {
+        for hook in self.hooks.iter().rev() {
+            hook.on_response(request, response);
+        }
+    }
+
+    fn on_chunk(&self, request: &ChatCompletionRequest, chunk: &mut ChatCompletionChunk) {
+        for hook in self.hooks.iter().rev() {
+            hook.on_chunk(request, chunk);
+        }
+    }
+}
+
+/// Built-in hook that puts a system message with an organisation's rules ahead of every
+/// conversation.
+pub struct SystemRules {
+    rules: String,
+}
+
+impl SystemRules {
+    pub fn new(rules: impl Into<String>) -> Self {
+        Self {
+            rules: rules.into(),
+        }
+    }
+}
+
+impl ChatHook for SystemRules {
+    fn name(&self) -> &str {
+        "system-rules"
+    }
+
+    fn on_request(&self, request: &mut ChatCompletionRequest) -> Result<(), ApiError> {
+        request
+            .messages
+            .insert(0, Message::system(self.rules.clone()));
+        Ok(())
+    }
+}
+
+/// Run `hooks` on the chat completions of every route of `app`. Put it inside the metrics
+/// layer, so the metrics see what the client gets.
+pub fn with_chat_hooks(app: Router, hooks: ChatHooks) -> Router {
+    if hooks.is_empty() {
+        return app;
+    }
+    tracing::info!("Chat hooks: {}", hooks.names().join(", "));
+    app.layer(axum::middleware::from_fn_with_state(
+        hooks,
+        apply_chat_hooks,
+    ))
+}
+
+/// Middleware running the hooks on `POST /v1/chat/completions`. Bodies that don't parse
+/// are passed on untouched, for the handler to reject as usual.
+async fn apply_chat_hooks(
+    State(hooks): State<ChatHooks>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::POST || request.uri().path() != CHAT_COMPLETIONS {
+        return next.run(request).await;
+    }
+
+    let (mut parts, body) = request.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return body_read_error(e).into_response();
+        }
+    };
+    let Ok(mut chat_request) = serde_json::from_slice::<ChatCompletionRequest>(&bytes) else {
+        return next
+            .run(Request::from_parts(parts, Body::from(bytes)))
+            .await;
+    };
+    let requested_model = chat_request.model.clone();
+    if let Err(e) = hooks.on_request(&mut chat_request) {
+        return e.into_response();
+    }
+    let bytes = match serde_json::to_vec(&chat_request) {
+        Ok(bytes) => Bytes::from(bytes),
+        Err(e) => {
+            return ApiError::server_error(format!("Failed to encode the request: {}", e))
+                .into_response();
+        }
+    };
+    set_content_length(&mut parts.headers, bytes.len());
+    let mut response = next
+        .run(Request::from_parts(parts, Body::from(bytes)))
+        .await;
+    // Tag requests a hook sent to another model, e.g. by an alias, with both names
+    if chat_request.model != requested_model
+        && let (Ok(alias), Ok(variant)) = (
+            HeaderValue::from_str(&requested_model),
+            HeaderValue::from_str(&chat_request.model),
+        )
+    {
+        let headers = response.headers_mut();
+        headers.insert(MODEL_ALIAS_HEADER, alias);
+        headers.insert(MODEL_VARIANT_HEADER, variant);
+    }
+
+    if !response.status().is_success() {
+        return response;
+    }
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    if content_type.starts_with("text/event-stream") {
+        rewrite_stream(response, hooks, chat_request)
+    } else if content_type.starts_with("application/json") {
+        rewrite_response(response, hooks, chat_request).await
+    } else {
+        response
+    }
+}
+
+fn set_content_length(headers: &mut HeaderMap, len: usize) {
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+}
+
+async fn rewrite_response(
+    response: Response,
+    hooks: ChatHooks,
+    request: ChatCompletionRequest,
+) -> Response {
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return ApiError::server_error(format!("Failed to read the response: {}", e))
+                .into_response();
+        }
+    };
+    let Ok(mut chat_response) = serde_json::from_slice::<ChatCompletionResponse>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    hooks.on_response(&request, &mut chat_response);
+    match serde_json::to_vec(&chat_response) {
+        Ok(bytes) => {
+            set_content_length(&mut parts.headers, bytes.len());
+            Response::from_parts(parts, Body::from(bytes))
+        }
+        Err(e) => {
+            ApiError::server_error(format!("Failed to encode the response: {}", e)).into_response()
+        }
+    }
+}
+
+/// Rewrites the `data:` lines of a server-sent event stream that hold chunks, passing
+/// everything else through. Lines can be split across body frames, so an incomplete one
+/// waits for the rest.
+struct ChunkRewriter {
+    hooks: ChatHooks,
+    request: ChatCompletionRequest,
+    pending: Vec<u8>,
+}
+
+impl ChunkRewriter {
+    fn rewrite(&mut self, frame: &[u8]) -> Bytes {
+        self.pending.extend_from_slice(frame);
+        let Some(end) = self.pending.iter().rposition(|&b| b == b'\n') else {
+            return Bytes::new();
+        };
+        let complete: Vec<u8> = self.pending.drain(..=end).collect();
+        let mut out = Vec::with_capacity(complete.len());
+        for line in complete.split_inclusive(|&b| b == b'\n') {
+            out.extend_from_slice(&self.rewrite_line(line));
+        }
+        Bytes::from(out)
+    }
+
+    fn finish(&mut self) -> Bytes {
+        let rest = std::mem::take(&mut self.pending);
+        Bytes::from(self.rewrite_line(&rest))
+    }
+
+    fn rewrite_line(&self, line: &[u8]) -> Vec<u8> {
+        let chunk = std::str::from_utf8(line)
+            .ok()
+            .and_then(|line| line.trim_end().strip_prefix("data:"))
+            .and_then(|data| serde_json::from_str::<ChatCompletionChunk>(data.trim()).ok());
+        let Some(mut chunk) = chunk else {
+            return line.to_vec();
+        };
+        self.hooks.on_chunk(&self.request, &mut chunk);
+        match serde_json::to_string(&chunk) {
+            Ok(json) => {
+                let ending = if line.ends_with(b"\n") { "\n" } else { "" };
+                format!("data: {}{}", json, ending).into_bytes()
+            }
+            Err(_) => line.to_vec(),
+        }
+    }
+}
+
+fn rewrite_stream(
+    response: Response,
+    hooks: ChatHooks,
+    request: ChatCompletionRequest,
+) -> Response {
+    let (mut parts, body) = response.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let rewriter = Arc::new(Mutex::new(ChunkRewriter {
+        hooks,
+        request,
+        pending: Vec::new(),
+    }));
+
+    let frames = {
+        let rewriter = rewriter.clone();
+        body.into_data_stream().map(move |frame| {
+            frame.map(|bytes| match rewriter.lock() {
+                Ok(mut rewriter) => rewriter.rewrite(&bytes),
+                Err(_) => bytes,
+            })
+        })
+    };
+    let rest = stream::once(async move {
+        Ok::<_, axum::Error>(
+            rewriter
+                .lock()
+                .map(|mut rewriter| rewriter.finish())
+                .unwrap_or_default(),
+        )
+    });
+    let body = frames
+        .chain(rest)
+        .filter(|frame| std::future::ready(!matches!(frame, Ok(bytes) if bytes.is_empty())));
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Upper;
+
+    impl ChatHook for Upper {
+        fn name(&self) -> &str {
+            "upper"
+        }
+
+        fn on_chunk(&self, _request: &ChatCompletionRequest, chunk: &mut ChatCompletionChunk) {
+            for choice in &mut chunk.choices {
+                choice.delta.content = choice.delta.content.as_ref().map(|c| c.to_uppercase());
+            }
+        }
+    }
+
+    fn chunk_line(content: &str) -> String {
+        format!(
+            "data: {}\n",
+            serde_json::json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion.chunk",
+                "created": 0,
+                "model": "gemma-3-1b-it",
+                "choices": [{"index": 0, "delta": {"content": content}, "finish_reason": null}]
+            })
+        )
+    }
+
+    #[test]
+    fn test_system_rules_come_first() {
+        let hooks = ChatHooks::new().with(SystemRules::new("Be kind."));
+        let mut request = ChatCompletionRequest::new("gemma-3-1b-it", vec![Message::user("Hi")]);
+        hooks.on_request(&mut request).unwrap();
+        assert_eq!(request.messages.len(), 2);
+        assert_eq!(request.messages[0].role, "system");
+        assert_eq!(request.messages[0].text(), Some("Be kind."));
+        assert_eq!(hooks.names(), ["system-rules"]);
+    }
+
+    #[test]
+    fn test_chunks_split_across_frames_are_rewritten() {
+        let mut rewriter = ChunkRewriter {
+            hooks: ChatHooks::new().with(Upper),
+            request: ChatCompletionRequest::new("gemma-3-1b-it", vec![]),
+            pending: Vec::new(),
+        };
+        let stream = format!(
+            "{}\n{}\ndata: [DONE]\n\n",
+            chunk_line("hello"),
+            chunk_line("world")
+        );
+        let (first, second) = stream.split_at(20);
+
+        let mut out = rewriter.rewrite(first.as_bytes()).to_vec();
+        out.extend_from_slice(&rewriter.rewrite(second.as_bytes()));
+        out.extend_from_slice(&rewriter.finish());
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("\"content\":\"HELLO\""), "{out}");
+        assert!(out.contains("\"content\":\"WORLD\""), "{out}");
+        assert!(out.ends_with("\n\ndata: [DONE]\n\n"), "{out}");
+        assert_eq!(out.matches("\n\n").count(), 3);
+    }
+}
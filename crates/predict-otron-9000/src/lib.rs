@@ -0,0 +1,122 @@
+//! The predict-otron-9000 gateway: one server for chat completions, embeddings and the
+//! admin endpoints, running the engines in process (Standalone) or proxying to them
+//! (HighAvailability). The binary assembles its app from these pieces and adds the web UI;
+//! the end-to-end tests assemble the same app around a mock runner.
+
+pub mod aliases;
+pub mod args;
+pub mod config;
+pub mod ha_mode;
+pub mod hooks;
+pub mod middleware;
+pub mod openapi;
+pub mod reload;
+pub mod standalone_mode;
+pub mod tls;
+pub mod upstream;
+
+use axum::Router;
+use axum::http::Uri;
+use axum::routing::get;
+use inference_engine::AppState;
+use openai_protocol::ApiError;
+use openai_protocol::trace_context::propagate_trace;
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{Any, CorsLayer};
+use tower_http::trace::TraceLayer;
+
+use config::ServerConfig;
+use ha_mode::create_live_ha_router;
+use middleware::{MetricsLayer, MetricsStore};
+use openapi::create_docs_router;
+use reload::LiveConfig;
+use standalone_mode::{create_standalone_router_with_state, standalone_app_state};
+
+/// The API routes for `server_config`'s mode.
+pub fn create_service_router(server_config: ServerConfig) -> Router {
+    let app_state = standalone_app_state(&server_config);
+    create_service_router_with_state(server_config, app_state)
+}
+
+/// The API routes for `server_config`'s mode, serving Standalone inference from
+/// `app_state`, e.g. one whose runner pool the warm pool also fills.
+pub fn create_service_router_with_state(
+    server_config: ServerConfig,
+    app_state: AppState,
+) -> Router {
+    create_live_service_router(LiveConfig::new(server_config), app_state)
+}
+
+/// Like [`create_service_router_with_state`], proxying in HighAvailability mode to the
+/// services of `live_config` as it is reloaded.
+pub fn create_live_service_router(live_config: LiveConfig, app_state: AppState) -> Router {
+    let server_config = (*live_config.current()).clone();
+    match server_config.clone().is_high_availability() {
+        Ok(is_ha) => {
+            if is_ha {
+                log_config(server_config.clone());
+                create_live_ha_router(live_config)
+            } else {
+                log_config(server_config.clone());
+                create_standalone_router_with_state(app_state)
+            }
+        }
+        Err(error) => {
+            panic!("{}", error);
+        }
+    }
+}
+
+/// `service_router` with the gateway's own health checks and API docs.
+pub fn create_api_router(service_router: Router) -> Router {
+    Router::new()
+        .route("/health", get(openapi::health))
+        .route("/health/live", get(inference_engine::health::live))
+        .merge(create_docs_router())
+        .merge(service_router)
+}
+
+/// Fallback for unknown routes, so they get the error envelope rather than an empty 404.
+/// The web UI brings its own fallback, so the binary only uses this one without it.
+pub async fn not_found(uri: Uri) -> ApiError {
+    ApiError::not_found(format!("No route for {}", uri.path()))
+}
+
+/// Wrap every route of `app` in the metrics, CORS, tracing and compression layers. The
+/// trace context layer goes outside the others but compression, so everything logged for
+/// a request carries its trace id and it names the request in error bodies before they
+/// are compressed. Responses are compressed with gzip or brotli for clients that accept
+/// it, except event streams, whose events must reach the client as they are sent.
+pub fn with_layers(app: Router, metrics_store: MetricsStore) -> Router {
+    let cors = CorsLayer::new()
+        .allow_headers(Any)
+        .allow_origin(Any)
+        .allow_methods(Any)
+        .allow_headers(Any);
+
+    app.layer(MetricsLayer::new(metrics_store)) // Add metrics tracking
+        .layer(cors)
+        .layer(TraceLayer::new_for_http())
+        .layer(axum::middleware::from_fn(propagate_trace))
+        .layer(CompressionLayer::new())
+}
+
+fn log_config(config: ServerConfig) {
+    match config.is_high_availability() {
+        Ok(is_high) => {
+            if is_high {
+                tracing::info!("Running in HighAvailability mode - proxying to external services");
+                tracing::info!("Inference service URL: {}", config.inference_url().unwrap());
+                tracing::info!(
+                    "Embeddings service URL: {}",
+                    config.embeddings_url().unwrap()
+                );
+            } else {
+                tracing::info!("Running in Standalone mode");
+            }
+        }
+        Err(error) => {
+            panic!("{}", error);
+        }
+    }
+}
@@ -1,18 +1,20 @@
-mod config;
-mod ha_mode;
-mod middleware;
-mod standalone_mode;
-
-use crate::standalone_mode::create_standalone_router;
+#[cfg(feature = "ui")]
 use axum::routing::get;
-use axum::{Router, serve};
-use config::ServerConfig;
-use ha_mode::create_ha_router;
-use middleware::{MetricsLayer, MetricsLoggerFuture, MetricsStore};
-use std::env;
+use axum::serve;
+use clap::Parser;
+use inference_engine::{LogLevel, WarmPoolConfig, create_log_level_router, spawn_warm_pool};
+use predict_otron_9000::args::{LogFormat, ServerArgs};
+use predict_otron_9000::hooks::with_chat_hooks;
+use predict_otron_9000::middleware::{
+    MetricsHistory, MetricsLoggerFuture, MetricsStore, UsageLedger, create_metrics_history_router,
+    create_metrics_reset_router, create_usage_router, with_admin_token, with_api_keys,
+    with_request_body_limit,
+};
+use predict_otron_9000::reload::{LiveConfig, LiveDefaultModel, spawn_config_reload};
+use predict_otron_9000::standalone_mode::standalone_app_state;
+use predict_otron_9000::tls::serve_redirects;
+use predict_otron_9000::{create_api_router, create_live_service_router, with_layers};
 
-#[cfg(feature = "ui")]
-use axum::http::StatusCode as AxumStatusCode;
 #[cfg(feature = "ui")]
 use axum::http::Uri;
 #[cfg(feature = "ui")]
@@ -22,10 +24,11 @@ use axum::response::IntoResponse;
 #[cfg(feature = "ui")]
 use mime_guess::from_path;
 #[cfg(feature = "ui")]
+use openai_protocol::ApiError;
+#[cfg(feature = "ui")]
 use rust_embed::Embed;
+use std::time::Duration;
 use tokio::net::TcpListener;
-use tower_http::cors::{Any, CorsLayer};
-use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[cfg(feature = "ui")]
@@ -54,28 +57,32 @@ async fn static_handler(uri: Uri) -> axum::response::Response {
 
             ([(header::CONTENT_TYPE, mime.as_ref())], body).into_response()
         }
-        None => (AxumStatusCode::NOT_FOUND, "404 Not Found").into_response(),
+        None => ApiError::not_found(format!("No static file {}", path)).into_response(),
     }
 }
 
 #[tokio::main]
 async fn main() {
-    // Initialize tracing
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                format!(
-                    "{}=debug,tower_http=debug,axum::rejection=trace",
-                    env!("CARGO_CRATE_NAME")
-                )
-                .into()
-            }),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    let args = ServerArgs::parse();
+
+    // Initialize tracing, keeping a handle to change the filter at runtime
+    let (filter, log_level) = LogLevel::from_env_or(&format!(
+        "{}=debug,tower_http=debug,axum::rejection=trace",
+        env!("CARGO_CRATE_NAME")
+    ));
+    let registry = tracing_subscriber::registry().with(filter);
+    match args.log_format {
+        LogFormat::Text => registry.with(tracing_subscriber::fmt::layer()).init(),
+        LogFormat::Json => registry
+            .with(tracing_subscriber::fmt::layer().json())
+            .init(),
+    }
 
     // Initialize metrics store for performance tracking
-    let metrics_store = MetricsStore::new();
+    let metrics_store = MetricsStore::with_history(MetricsHistory::open_or_in_memory(
+        args.metrics_db.as_deref(),
+    ))
+    .with_usage(UsageLedger::open_or_in_memory(args.usage_db.as_deref()));
 
     // Create a metrics logger that will periodically log metrics (every 60 seconds)
     let metrics_logger = MetricsLoggerFuture::new(metrics_store.clone(), 60);
@@ -83,42 +90,67 @@ async fn main() {
     // Spawn the metrics logger in a background task
     tokio::spawn(metrics_logger);
 
-    // Load server configuration from environment variable
-    let server_config = ServerConfig::from_env();
-
-    // Extract the server_host and server_port before potentially moving server_config
-    let default_host = server_config.server_host.clone();
-    let default_port = server_config.server_port;
-
-    let service_router = match server_config.clone().is_high_availability() {
-        Ok(is_ha) => {
-            if is_ha {
-                log_config(server_config.clone());
-                create_ha_router(server_config.clone())
-            } else {
-                log_config(server_config.clone());
-                create_standalone_router(server_config)
-            }
+    // Load server configuration from the config file or environment, with the flags applied
+    let server_config = args.server_config_or_exit();
+    let mut chat_hooks = args.chat_hooks_or_exit(&server_config);
+    let tls = args.tls_or_exit();
+
+    // Extract the server address before moving server_config
+    let server_address = format!(
+        "{}:{}",
+        server_config.server_host, server_config.server_port
+    );
+    let (server_host, server_port) = (server_config.server_host.clone(), server_config.server_port);
+    let api_keys = server_config.api_keys.clone();
+    let admin_token = server_config.admin_token.clone();
+    let live_config = LiveConfig::new(server_config.clone());
+
+    // Standalone mode loads the models in this process, so it drops idle ones when memory
+    // runs short, and keeps the configured ones warm. Both share the routes' runner pool.
+    let app_state = standalone_app_state(&server_config);
+    if !server_config.is_high_availability().unwrap_or(false) {
+        inference_engine::spawn_memory_monitor(
+            inference_engine::MemoryMonitorConfig::from_env(),
+            app_state.runners.clone(),
+        );
+        let warm_pool = WarmPoolConfig::from_env();
+        if warm_pool.is_enabled() {
+            spawn_warm_pool(warm_pool, app_state.clone());
         }
-        Err(error) => {
-            panic!("{}", error);
+        let preload = inference_engine::preload_models_from_env();
+        if !preload.is_empty() {
+            inference_engine::spawn_preload(preload, app_state.clone());
         }
-    };
-
-    // Create CORS layer
-    let cors = CorsLayer::new()
-        .allow_headers(Any)
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
+        // Load the default models, which /health/ready waits for
+        inference_engine::spawn_readiness_preload(app_state.clone());
+    }
 
-    // Create metrics layer
-    let metrics_layer = MetricsLayer::new(metrics_store);
+    // A config file is watched for changes to the proxy's services, the default model and
+    // the scheduler limits, which the routes read from the live config
+    if let Some(path) = args.config.clone()
+        && args.config_reload_secs > 0
+    {
+        let scheduler = (!server_config.is_high_availability().unwrap_or(false))
+            .then(|| app_state.scheduler.clone());
+        let reload_args = args.clone();
+        spawn_config_reload(
+            path,
+            Duration::from_millis(args.config_reload_debounce_ms),
+            Duration::from_secs(args.config_reload_secs),
+            move || reload_args.server_config(),
+            live_config.clone(),
+            scheduler,
+        );
+        chat_hooks = chat_hooks.with(LiveDefaultModel::new(live_config.clone()));
+    }
 
-    // Merge the service router with base routes and add middleware layers
-    let mut app = Router::new()
-        .route("/health", get(|| async { "ok" }))
-        .merge(service_router);
+    // Merge the service router with base routes; the middleware layers go on last
+    // The log filter is this process's own, so it isn't proxied in HighAvailability mode
+    let mut app = create_api_router(create_live_service_router(live_config, app_state))
+        .merge(create_log_level_router(log_level))
+        .merge(create_metrics_history_router(metrics_store.history()))
+        .merge(create_metrics_reset_router(metrics_store.clone()))
+        .merge(create_usage_router(metrics_store.usage()));
 
     // Add UI routes if the UI feature is enabled
     #[cfg(feature = "ui")]
@@ -130,55 +162,81 @@ async fn main() {
             .merge(leptos_router);
     }
 
-    let app = app
-        .layer(metrics_layer) // Add metrics tracking
-        .layer(cors)
-        .layer(TraceLayer::new_for_http());
-
-    // Server configuration
-    let server_host = env::var("SERVER_HOST").unwrap_or_else(|_| default_host.to_string());
-
-    let server_port = env::var("SERVER_PORT")
-        .map(|v| v.parse::<u16>().unwrap_or(default_port))
-        .unwrap_or_else(|_| default_port);
+    #[cfg(not(feature = "ui"))]
+    {
+        app = app.fallback(predict_otron_9000::not_found);
+    }
 
-    let server_address = format!("{}:{}", server_host, server_port);
+    // Chat hooks run inside the metrics layer, so the metrics see what clients get, and
+    // behind the API key check and the body limit, so turned away requests don't reach them
+    let app = with_layers(
+        with_request_body_limit(
+            with_admin_token(
+                with_api_keys(with_chat_hooks(app, chat_hooks), api_keys),
+                admin_token,
+            ),
+            args.max_request_body_bytes(),
+        ),
+        metrics_store,
+    );
 
     let listener = TcpListener::bind(&server_address).await.unwrap();
     tracing::info!(
-        "Unified predict-otron-9000 server listening on {}",
+        "Unified predict-otron-9000 server listening on {}://{}",
+        if tls.is_some() { "https" } else { "http" },
         listener.local_addr().unwrap()
     );
     tracing::info!("Performance metrics tracking enabled - summary logs every 60 seconds");
     tracing::info!("Available endpoints:");
     #[cfg(feature = "ui")]
     tracing::info!("  GET  / - Leptos chat web application");
+    #[cfg(feature = "ui")]
+    tracing::info!("  GET  /share/{{id}} - Read-only shared conversation");
     tracing::info!("  GET  /health - Health check");
+    tracing::info!("  GET  /health/live - Liveness check");
+    tracing::info!("  GET  /health/ready - 503 until the default models are loaded");
     tracing::info!("  POST /v1/models - List Models");
+    tracing::info!("  GET  /v1/models/{{id}} - One model's context length, size and load state");
+    tracing::info!(
+        "  POST /v1/models/{{id}}/load - Load a model (DELETE /v1/models/{{id}} unloads it)"
+    );
     tracing::info!("  POST /v1/embeddings - Text embeddings API");
     tracing::info!("  POST /v1/chat/completions - Chat completions API");
-
-    serve(listener, app.into_make_service()).await.unwrap();
-}
-
-fn log_config(config: ServerConfig) {
-    match config.is_high_availability() {
-        Ok(is_high) => {
-            if is_high {
-                tracing::info!("Running in HighAvailability mode - proxying to external services");
-                tracing::info!("Inference service URL: {}", config.inference_url().unwrap());
-                tracing::info!(
-                    "Embeddings service URL: {}",
-                    config.embeddings_url().unwrap()
-                );
-            } else {
-                tracing::info!("Running in Standalone mode");
-            }
-        }
-        Err(error) => {
-            panic!("{}", error);
+    tracing::info!("  POST /v1/chat/completions/{{id}}/cancel - Stop a streaming completion");
+    tracing::info!("  GET  /admin/device - Device capability report");
+    tracing::info!("  GET  /admin/status - Loaded models, queues, streams, memory and uptime");
+    tracing::info!("  GET  /admin/log_level - Log filter (PUT to change it)");
+    tracing::info!("  GET  /admin/metrics/history - Per-minute traffic of each model");
+    tracing::info!("  POST /admin/metrics/reset - Clear the per-endpoint response times");
+    tracing::info!("  GET  /v1/usage - Tokens used per API key, model and day");
+    tracing::info!("  GET  /openapi.json - OpenAPI spec of the whole API");
+    tracing::info!("  GET  /docs - Interactive API documentation");
+
+    let Some(tls) = tls else {
+        serve(listener, app.into_make_service()).await.unwrap();
+        return;
+    };
+    let rustls_config = match tls.rustls_config().await {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("{}", e);
+            std::process::exit(1);
         }
+    };
+    if let Some(redirect_port) = tls.redirect_port {
+        let redirect_address = format!("{}:{}", server_host, redirect_port);
+        tokio::spawn(async move {
+            if let Err(e) = serve_redirects(&redirect_address, server_port).await {
+                tracing::error!("Failed to redirect HTTP on {}: {}", redirect_address, e);
+            }
+        });
     }
+    // axum-server takes the listener as the standard library's
+    let listener = listener.into_std().unwrap();
+    axum_server::from_tcp_rustls(listener, rustls_config)
+        .serve(app.into_make_service())
+        .await
+        .unwrap();
 }
 
 // Chat completions handler that properly uses the inference server crate's error handling
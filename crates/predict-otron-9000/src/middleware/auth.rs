@@ -0,0 +1,217 @@
+use std::fmt;
+
+use axum::Router;
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use openai_protocol::ApiError;
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+
+/// The keys `/v1` requests must carry as `Authorization: Bearer <key>`. Only their
+/// SHA-256 digests are kept, and comparing digests takes as long for every key, so neither
+/// logs nor timing give the keys away.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct ApiKeys {
+    digests: Vec<[u8; 32]>,
+}
+
+impl ApiKeys {
+    /// The non-empty `keys`, trimmed.
+    pub fn new<I, S>(keys: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self {
+            digests: keys
+                .into_iter()
+                .map(|key| key.as_ref().trim().to_string())
+                .filter(|key| !key.is_empty())
+                .map(|key| Sha256::digest(key.as_bytes()).into())
+                .collect(),
+        }
+    }
+
+    /// Without keys the API is open.
+    pub fn is_empty(&self) -> bool {
+        self.digests.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.digests.len()
+    }
+
+    /// Whether `headers` carry one of the keys.
+    pub fn allows(&self, headers: &HeaderMap) -> bool {
+        let Some(key) = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+        else {
+            return false;
+        };
+        let digest: [u8; 32] = Sha256::digest(key.as_bytes()).into();
+        self.digests
+            .iter()
+            .fold(false, |found, known| found | same_digest(known, &digest))
+    }
+}
+
+/// Compare every byte, however early they differ.
+fn same_digest(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+impl fmt::Debug for ApiKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ApiKeys({} keys)", self.len())
+    }
+}
+
+impl<'de> Deserialize<'de> for ApiKeys {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<String>::deserialize(deserializer).map(Self::new)
+    }
+}
+
+/// The token `/admin` requests must carry as `Authorization: Bearer <token>`. It is kept
+/// apart from the API keys, so a client's key can't change the log level or reset the
+/// metrics, and like them only as a digest.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct AdminToken(ApiKeys);
+
+impl AdminToken {
+    /// `token`, trimmed; an empty one leaves the admin endpoints open.
+    pub fn new(token: &str) -> Self {
+        Self(ApiKeys::new([token]))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether `headers` carry the token.
+    pub fn allows(&self, headers: &HeaderMap) -> bool {
+        self.0.allows(headers)
+    }
+}
+
+impl fmt::Debug for AdminToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.is_empty() { "unset" } else { "set" };
+        write!(f, "AdminToken({})", state)
+    }
+}
+
+impl<'de> Deserialize<'de> for AdminToken {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|token| Self::new(&token))
+    }
+}
+
+/// Turn away `/v1` requests of `app` without one of `keys`, with a 401. The health check
+/// and docs stay open, and the admin endpoints take the token of [`with_admin_token`]
+/// instead. Without keys, `app` is returned as is.
+pub fn with_api_keys(app: Router, keys: ApiKeys) -> Router {
+    if keys.is_empty() {
+        return app;
+    }
+    tracing::info!("Requiring one of {} API keys on /v1", keys.len());
+    app.layer(axum::middleware::from_fn_with_state(keys, require_api_key))
+}
+
+async fn require_api_key(State(keys): State<ApiKeys>, request: Request, next: Next) -> Response {
+    if !request.uri().path().starts_with("/v1/") || keys.allows(request.headers()) {
+        return next.run(request).await;
+    }
+    ApiError::new(
+        StatusCode::UNAUTHORIZED,
+        "invalid_request_error",
+        "Missing or invalid API key; send it as Authorization: Bearer <key>",
+    )
+    .with_code("invalid_api_key")
+    .into_response()
+}
+
+/// Turn away `/admin` requests of `app` without `token`, with a 401; API keys don't
+/// open them. Without a token, `app` is returned as is.
+pub fn with_admin_token(app: Router, token: AdminToken) -> Router {
+    if token.is_empty() {
+        return app;
+    }
+    tracing::info!("Requiring the admin token on /admin");
+    app.layer(axum::middleware::from_fn_with_state(
+        token,
+        require_admin_token,
+    ))
+}
+
+async fn require_admin_token(
+    State(token): State<AdminToken>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !is_admin_path(request.uri().path()) || token.allows(request.headers()) {
+        return next.run(request).await;
+    }
+    ApiError::new(
+        StatusCode::UNAUTHORIZED,
+        "invalid_request_error",
+        "Missing or invalid admin token; send it as Authorization: Bearer <token>",
+    )
+    .with_code("invalid_admin_token")
+    .into_response()
+}
+
+fn is_admin_path(path: &str) -> bool {
+    path == "/admin" || path.starts_with("/admin/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bearer(key: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Bearer {key}").parse().unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_only_configured_keys_are_allowed() {
+        let keys = ApiKeys::new(["sk-one", " sk-two ", ""]);
+        assert_eq!(keys.len(), 2);
+        assert!(keys.allows(&bearer("sk-one")));
+        assert!(keys.allows(&bearer("sk-two")));
+        assert!(!keys.allows(&bearer("sk-three")));
+        assert!(!keys.allows(&bearer("")));
+        assert!(!keys.allows(&HeaderMap::new()));
+
+        assert!(ApiKeys::new(Vec::<String>::new()).is_empty());
+        assert_eq!(format!("{:?}", keys), "ApiKeys(2 keys)");
+    }
+
+    #[test]
+    fn test_the_admin_token_is_apart_from_the_api_keys() {
+        let token = AdminToken::new(" admin-secret ");
+        assert!(token.allows(&bearer("admin-secret")));
+        assert!(!token.allows(&bearer("sk-one")));
+        assert!(!token.allows(&HeaderMap::new()));
+        assert_eq!(format!("{:?}", token), "AdminToken(set)");
+
+        let token: AdminToken = serde_json::from_str(r#""""#).unwrap();
+        assert!(token.is_empty());
+        assert_eq!(format!("{:?}", token), "AdminToken(unset)");
+
+        assert!(is_admin_path("/admin/status"));
+        assert!(is_admin_path("/admin"));
+        assert!(!is_admin_path("/administrator"));
+        assert!(!is_admin_path("/v1/models"));
+    }
+}
@@ -0,0 +1,91 @@
+use axum::Router;
+use axum::body::Body;
+use axum::extract::{DefaultBodyLimit, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use http_body_util::{LengthLimitError, Limited};
+use openai_protocol::ApiError;
+
+/// Request bodies the binary accepts by default, in MiB, room for large embedding batches.
+pub const DEFAULT_MAX_REQUEST_BODY_MB: usize = 10;
+
+/// Turn away requests to `app` whose body is over `max_bytes` with a 413, whether their
+/// `Content-Length` says so or the body grows past it while it is read. This replaces
+/// axum's own 2 MB limit on JSON bodies; 0 accepts bodies of any size.
+pub fn with_request_body_limit(app: Router, max_bytes: usize) -> Router {
+    let app = app.layer(DefaultBodyLimit::disable());
+    if max_bytes == 0 {
+        return app;
+    }
+    app.layer(axum::middleware::from_fn_with_state(
+        max_bytes,
+        limit_request_body,
+    ))
+}
+
+async fn limit_request_body(
+    State(max_bytes): State<usize>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let content_length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if content_length.is_some_and(|length| length > max_bytes) {
+        return body_too_large(max_bytes).into_response();
+    }
+    next.run(request.map(|body| Body::new(Limited::new(body, max_bytes))))
+        .await
+}
+
+fn body_too_large(max_bytes: usize) -> ApiError {
+    ApiError::new(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        "invalid_request",
+        format!(
+            "Request body is larger than the {} bytes the server accepts",
+            max_bytes
+        ),
+    )
+    .with_code("request_too_large")
+}
+
+/// The error for a request body that couldn't be read: a 413 for one over the limit,
+/// else a 400.
+pub fn body_read_error(error: axum::Error) -> ApiError {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(&error);
+    while let Some(cause) = source {
+        if cause.is::<LengthLimitError>() {
+            return ApiError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "invalid_request",
+                "Request body is larger than the server accepts",
+            )
+            .with_code("request_too_large");
+        }
+        source = cause.source();
+    }
+    ApiError::invalid_request(format!("Failed to read request body: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bodies_over_the_limit_are_too_large() {
+        let body = Body::new(Limited::new(Body::from(vec![b'x'; 64]), 16));
+        let error = axum::body::to_bytes(body, usize::MAX).await.unwrap_err();
+        let error = body_read_error(error);
+        assert_eq!(error.status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(error.body.error.code.as_deref(), Some("request_too_large"));
+
+        let error = axum::body::to_bytes(Body::from(vec![b'x'; 64]), 16)
+            .await
+            .unwrap_err();
+        assert_eq!(body_read_error(error).status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,488 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::extract::{Query, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use openai_protocol::ApiError;
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+/// How long minutes are kept, and so the longest window a query can ask for.
+pub const RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS metrics_history (
+    minute INTEGER NOT NULL,
+    model TEXT NOT NULL,
+    requests INTEGER NOT NULL,
+    errors INTEGER NOT NULL,
+    prompt_tokens INTEGER NOT NULL,
+    completion_tokens INTEGER NOT NULL,
+    latency_p50_ms INTEGER NOT NULL,
+    latency_p95_ms INTEGER NOT NULL,
+    latency_p99_ms INTEGER NOT NULL,
+    latency_max_ms INTEGER NOT NULL,
+    PRIMARY KEY (minute, model)
+)";
+
+/// One chat or embeddings request, as the metrics layer saw it once its response ended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelRequest {
+    /// The model the response names, `unknown` for errors that don't
+    pub model: String,
+    pub failed: bool,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Until the last byte of the response
+    pub latency_ms: u64,
+    pub finished_at: SystemTime,
+}
+
+/// One model's requests in one minute.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct MinuteMetrics {
+    /// Start of the minute, in seconds since the Unix epoch
+    pub minute: u64,
+    pub model: String,
+    pub requests: u64,
+    /// Requests that got an error status, or an error event in their stream
+    pub errors: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub latency_p50_ms: u64,
+    pub latency_p95_ms: u64,
+    pub latency_p99_ms: u64,
+    pub latency_max_ms: u64,
+}
+
+impl MinuteMetrics {
+    /// Add `other`, of the same minute and model, e.g. from before a restart. The counts
+    /// add up; the percentiles can't be merged, so the higher of each is kept.
+    fn combine(&mut self, other: &MinuteMetrics) {
+        self.requests += other.requests;
+        self.errors += other.errors;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.latency_p50_ms = self.latency_p50_ms.max(other.latency_p50_ms);
+        self.latency_p95_ms = self.latency_p95_ms.max(other.latency_p95_ms);
+        self.latency_p99_ms = self.latency_p99_ms.max(other.latency_p99_ms);
+        self.latency_max_ms = self.latency_max_ms.max(other.latency_max_ms);
+    }
+}
+
+/// The requests of a minute that hasn't been written yet.
+#[derive(Debug, Default)]
+struct OpenMinute {
+    requests: u64,
+    errors: u64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    latencies_ms: Vec<u64>,
+}
+
+impl OpenMinute {
+    fn add(&mut self, request: &ModelRequest) {
+        self.requests += 1;
+        self.errors += u64::from(request.failed);
+        self.prompt_tokens += request.prompt_tokens;
+        self.completion_tokens += request.completion_tokens;
+        self.latencies_ms.push(request.latency_ms);
+    }
+
+    fn summarize(&self, minute: u64, model: &str) -> MinuteMetrics {
+        let mut latencies = self.latencies_ms.clone();
+        latencies.sort_unstable();
+        MinuteMetrics {
+            minute,
+            model: model.to_string(),
+            requests: self.requests,
+            errors: self.errors,
+            prompt_tokens: self.prompt_tokens,
+            completion_tokens: self.completion_tokens,
+            latency_p50_ms: percentile(&latencies, 0.50),
+            latency_p95_ms: percentile(&latencies, 0.95),
+            latency_p99_ms: percentile(&latencies, 0.99),
+            latency_max_ms: latencies.last().copied().unwrap_or(0),
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted values.
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn minute_of(time: SystemTime) -> u64 {
+    unix_secs(time) / 60 * 60
+}
+
+/// Per-minute request, token and latency aggregates for each model, kept in SQLite so they
+/// survive restarts. The current minute is held in memory and written once it's over.
+#[derive(Clone)]
+pub struct MetricsHistory {
+    db: Arc<Mutex<Connection>>,
+    open: Arc<Mutex<BTreeMap<(u64, String), OpenMinute>>>,
+}
+
+impl std::fmt::Debug for MetricsHistory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MetricsHistory").finish_non_exhaustive()
+    }
+}
+
+impl MetricsHistory {
+    /// Keep the history in the SQLite database at `path`, creating it if needed.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    /// Keep the history in memory only, until the process exits.
+    pub fn in_memory() -> Self {
+        Self::with_connection(Connection::open_in_memory().expect("in-memory SQLite"))
+            .expect("metrics history schema")
+    }
+
+    /// The database at `path`, or an in-memory history without one or if it can't be
+    /// opened.
+    pub fn open_or_in_memory(path: Option<&Path>) -> Self {
+        match path.map(|path| (path, Self::open(path))) {
+            Some((path, Ok(history))) => {
+                tracing::info!("Keeping metrics history in {}", path.display());
+                history
+            }
+            Some((path, Err(e))) => {
+                tracing::warn!(
+                    "Failed to open metrics history {}: {}; keeping it in memory",
+                    path.display(),
+                    e
+                );
+                Self::in_memory()
+            }
+            None => Self::in_memory(),
+        }
+    }
+
+    fn with_connection(connection: Connection) -> rusqlite::Result<Self> {
+        connection.execute(SCHEMA, [])?;
+        Ok(Self {
+            db: Arc::new(Mutex::new(connection)),
+            open: Arc::new(Mutex::new(BTreeMap::new())),
+        })
+    }
+
+    /// Count `request` in its minute, and write the minutes that are over.
+    pub fn record(&self, request: ModelRequest) {
+        let minute = minute_of(request.finished_at);
+        if let Ok(mut open) = self.open.lock() {
+            open.entry((minute, request.model.clone()))
+                .or_default()
+                .add(&request);
+        }
+        self.write_before(minute);
+    }
+
+    /// Write the minutes that are over. The metrics logger calls it, so the last busy
+    /// minute of a server gone quiet is saved too.
+    pub fn flush(&self) {
+        self.write_before(minute_of(SystemTime::now()));
+    }
+
+    fn write_before(&self, minute: u64) {
+        let finished = {
+            let Ok(mut open) = self.open.lock() else {
+                return;
+            };
+            let still_open = open.split_off(&(minute, String::new()));
+            std::mem::replace(&mut *open, still_open)
+        };
+        if finished.is_empty() {
+            return;
+        }
+
+        let rows: Vec<MinuteMetrics> = finished
+            .iter()
+            .map(|((minute, model), open)| open.summarize(*minute, model))
+            .collect();
+        let history = self.clone();
+        let write = move || {
+            if let Err(e) = history.write(&rows) {
+                tracing::warn!("Failed to write metrics history: {}", e);
+            }
+        };
+        // Called as a response body ends, usually on a runtime thread
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn_blocking(write);
+            }
+            Err(_) => write(),
+        }
+    }
+
+    /// Add `rows` to the database and drop minutes past [`RETENTION`].
+    fn write(&self, rows: &[MinuteMetrics]) -> rusqlite::Result<()> {
+        let mut db = self.db.lock().expect("metrics history lock");
+        let transaction = db.transaction()?;
+        for row in rows {
+            transaction.execute(
+                "INSERT INTO metrics_history VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                 ON CONFLICT (minute, model) DO UPDATE SET
+                    requests = requests + excluded.requests,
+                    errors = errors + excluded.errors,
+                    prompt_tokens = prompt_tokens + excluded.prompt_tokens,
+                    completion_tokens = completion_tokens + excluded.completion_tokens,
+                    latency_p50_ms = max(latency_p50_ms, excluded.latency_p50_ms),
+                    latency_p95_ms = max(latency_p95_ms, excluded.latency_p95_ms),
+                    latency_p99_ms = max(latency_p99_ms, excluded.latency_p99_ms),
+                    latency_max_ms = max(latency_max_ms, excluded.latency_max_ms)",
+                params![
+                    row.minute,
+                    row.model,
+                    row.requests,
+                    row.errors,
+                    row.prompt_tokens,
+                    row.completion_tokens,
+                    row.latency_p50_ms,
+                    row.latency_p95_ms,
+                    row.latency_p99_ms,
+                    row.latency_max_ms,
+                ],
+            )?;
+        }
+        let expired = unix_secs(SystemTime::now()).saturating_sub(RETENTION.as_secs());
+        transaction.execute(
+            "DELETE FROM metrics_history WHERE minute < ?1",
+            params![expired],
+        )?;
+        transaction.commit()
+    }
+
+    /// The minutes from `since` on, written or not, oldest first and by model.
+    pub fn query(&self, since: SystemTime) -> rusqlite::Result<Vec<MinuteMetrics>> {
+        let since = minute_of(since);
+        let mut minutes = BTreeMap::new();
+        {
+            let db = self.db.lock().expect("metrics history lock");
+            let mut statement = db.prepare(
+                "SELECT minute, model, requests, errors, prompt_tokens, completion_tokens,
+                        latency_p50_ms, latency_p95_ms, latency_p99_ms, latency_max_ms
+                 FROM metrics_history WHERE minute >= ?1",
+            )?;
+            let rows = statement.query_map(params![since], |row| {
+                Ok(MinuteMetrics {
+                    minute: row.get(0)?,
+                    model: row.get(1)?,
+                    requests: row.get(2)?,
+                    errors: row.get(3)?,
+                    prompt_tokens: row.get(4)?,
+                    completion_tokens: row.get(5)?,
+                    latency_p50_ms: row.get(6)?,
+                    latency_p95_ms: row.get(7)?,
+                    latency_p99_ms: row.get(8)?,
+                    latency_max_ms: row.get(9)?,
+                })
+            })?;
+            for row in rows {
+                let row = row?;
+                minutes.insert((row.minute, row.model.clone()), row);
+            }
+        }
+
+        if let Ok(open) = self.open.lock() {
+            for ((minute, model), open) in open.range((since, String::new())..) {
+                let summary = open.summarize(*minute, model);
+                minutes
+                    .entry((*minute, model.clone()))
+                    .and_modify(|row: &mut MinuteMetrics| row.combine(&summary))
+                    .or_insert(summary);
+            }
+        }
+        Ok(minutes.into_values().collect())
+    }
+}
+
+/// Parse a window like `90m`, `24h` or `7d`.
+fn parse_window(window: &str) -> Result<Duration, String> {
+    let window = window.trim();
+    let split = window.len().saturating_sub(1);
+    let (count, unit) = window.split_at(split);
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "Invalid window '{}': expected e.g. 90m, 24h or 7d",
+                window
+            ));
+        }
+    };
+    match count.parse::<u64>() {
+        Ok(count) if count > 0 => Ok(Duration::from_secs(count * unit_secs).min(RETENTION)),
+        _ => Err(format!(
+            "Invalid window '{}': expected e.g. 90m, 24h or 7d",
+            window
+        )),
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct HistoryQuery {
+    /// How far back to go, e.g. `90m`, `24h` or `7d`; at most 30 days. Defaults to `24h`
+    pub window: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MetricsHistoryResponse {
+    pub object: String,
+    /// The window the data covers
+    pub window: String,
+    /// One entry per minute and model that had requests, oldest first
+    pub data: Vec<MinuteMetrics>,
+}
+
+/// Handler for GET /admin/metrics/history - per-minute traffic of each model
+#[utoipa::path(
+    get,
+    path = "/admin/metrics/history",
+    tag = "gateway",
+    params(HistoryQuery),
+    responses(
+        (status = 200, description = "Requests, tokens and latency percentiles per minute and model", body = MetricsHistoryResponse),
+        (status = 400, description = "The window doesn't parse", body = ErrorResponse),
+        (status = 500, description = "The history couldn't be read", body = ErrorResponse)
+    )
+)]
+pub async fn metrics_history(
+    State(history): State<MetricsHistory>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<MetricsHistoryResponse>, ApiError> {
+    let window = query.window.unwrap_or_else(|| "24h".to_string());
+    let duration =
+        parse_window(&window).map_err(|e| ApiError::invalid_request(e).with_param("window"))?;
+    let since = SystemTime::now() - duration;
+
+    let data = tokio::task::spawn_blocking(move || history.query(since))
+        .await
+        .map_err(|e| ApiError::server_error(format!("Metrics history query failed: {}", e)))?
+        .map_err(|e| ApiError::server_error(format!("Failed to read metrics history: {}", e)))?;
+    Ok(Json(MetricsHistoryResponse {
+        object: "list".to_string(),
+        window,
+        data,
+    }))
+}
+
+/// `/admin/metrics/history`, served from `history`.
+pub fn create_metrics_history_router(history: MetricsHistory) -> Router {
+    Router::new()
+        .route("/admin/metrics/history", get(metrics_history))
+        .with_state(history)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(model: &str, latency_ms: u64, finished_at: SystemTime) -> ModelRequest {
+        ModelRequest {
+            model: model.to_string(),
+            failed: false,
+            prompt_tokens: 10,
+            completion_tokens: 5,
+            latency_ms,
+            finished_at,
+        }
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let latencies: Vec<u64> = (1..=100).collect();
+        assert_eq!(percentile(&latencies, 0.50), 50);
+        assert_eq!(percentile(&latencies, 0.95), 95);
+        assert_eq!(percentile(&latencies, 0.99), 99);
+        assert_eq!(percentile(&[7], 0.99), 7);
+        assert_eq!(percentile(&[], 0.5), 0);
+    }
+
+    #[test]
+    fn test_parse_window() {
+        assert_eq!(parse_window("90m"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_window("24h"), Ok(Duration::from_secs(24 * 60 * 60)));
+        assert_eq!(parse_window("365d"), Ok(RETENTION));
+        for invalid in ["", "h", "0h", "-1h", "24", "1w"] {
+            assert!(parse_window(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_minutes_are_aggregated_per_model() {
+        let history = MetricsHistory::in_memory();
+        let now = SystemTime::now();
+        let earlier = now - Duration::from_secs(120);
+
+        history.record(request("gemma-3-1b-it", 100, earlier));
+        history.record(ModelRequest {
+            failed: true,
+            ..request("gemma-3-1b-it", 300, earlier)
+        });
+        history.record(request("llama-3.2-1b-instruct", 50, earlier));
+        // A later minute writes the earlier ones to the database
+        history.record(request("gemma-3-1b-it", 20, now));
+
+        let minutes = history.query(earlier).unwrap();
+        assert_eq!(minutes.len(), 3);
+        let gemma = &minutes[0];
+        assert_eq!(gemma.minute, minute_of(earlier));
+        assert_eq!(gemma.model, "gemma-3-1b-it");
+        assert_eq!((gemma.requests, gemma.errors), (2, 1));
+        assert_eq!((gemma.prompt_tokens, gemma.completion_tokens), (20, 10));
+        assert_eq!((gemma.latency_p50_ms, gemma.latency_max_ms), (100, 300));
+        assert_eq!(minutes[1].model, "llama-3.2-1b-instruct");
+        assert_eq!(
+            (minutes[2].minute, minutes[2].requests),
+            (minute_of(now), 1)
+        );
+
+        assert_eq!(history.query(now).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_history_survives_reopening() {
+        let path = std::env::temp_dir().join(format!(
+            "predict-otron-metrics-{}.sqlite",
+            uuid::Uuid::new_v4()
+        ));
+        let earlier = SystemTime::now() - Duration::from_secs(120);
+        {
+            let history = MetricsHistory::open(&path).unwrap();
+            history.record(request("gemma-3-1b-it", 100, earlier));
+            history.record(request("gemma-3-1b-it", 100, SystemTime::now()));
+        }
+        // Only the minute that was over made it to disk
+        let reopened = MetricsHistory::open(&path).unwrap();
+        let minutes = reopened.query(earlier).unwrap();
+        assert_eq!(minutes.len(), 1);
+        assert_eq!(minutes[0].minute, minute_of(earlier));
+
+        // Writing the same minute again adds to it
+        reopened
+            .write(&[minutes[0].clone()])
+            .expect("write to the reopened database");
+        assert_eq!(reopened.query(earlier).unwrap()[0].requests, 2);
+        let _ = std::fs::remove_file(path);
+    }
+}
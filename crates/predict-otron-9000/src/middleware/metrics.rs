@@ -1,7 +1,13 @@
 use axum::{
-    extract::MatchedPath,
-    http::{Request, Response},
+    Json, Router,
+    body::Body,
+    extract::{MatchedPath, State},
+    http::{Method, Request, Response, StatusCode, header},
+    routing::post,
 };
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::fmt;
 use std::task::ready;
 use std::{
@@ -9,11 +15,22 @@ use std::{
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
-    time::Instant,
+    time::{Instant, SystemTime},
 };
 use tokio::sync::Mutex;
 use tower::{Layer, Service};
 use tracing::{debug, info};
+use utoipa::ToSchema;
+
+use super::history::{MetricsHistory, ModelRequest};
+use super::usage::{KeyUsage, UsageLedger, request_key_id};
+use crate::aliases::{MODEL_ALIAS_HEADER, MODEL_VARIANT_HEADER};
+
+/// Routes whose responses name a model and report token usage, for the metrics history.
+const MODEL_ROUTES: [&str; 2] = ["/v1/chat/completions", "/v1/embeddings"];
+
+/// JSON bodies past this size are relayed without being read for their usage.
+const MAX_OBSERVED_BODY: usize = 16 * 1024 * 1024;
 
 /// Performance metrics for a specific endpoint
 #[derive(Debug, Clone, Default)]
@@ -65,20 +82,53 @@ impl EndpointMetrics {
 }
 
 /// Global metrics storage
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct MetricsStore {
     /// Metrics per endpoint
     endpoints: Arc<Mutex<std::collections::HashMap<String, EndpointMetrics>>>,
+    /// Per-minute traffic of each model
+    history: MetricsHistory,
+    /// Tokens used per API key, model and day
+    usage: UsageLedger,
+}
+
+impl Default for MetricsStore {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MetricsStore {
-    /// Create a new metrics store
+    /// Create a new metrics store, with its history kept in memory
     pub fn new() -> Self {
+        Self::with_history(MetricsHistory::in_memory())
+    }
+
+    /// Create a new metrics store that records model requests in `history`
+    pub fn with_history(history: MetricsHistory) -> Self {
         Self {
             endpoints: Arc::new(Mutex::new(std::collections::HashMap::new())),
+            history,
+            usage: UsageLedger::in_memory(),
         }
     }
 
+    /// Record each API key's tokens in `usage` rather than in memory
+    pub fn with_usage(mut self, usage: UsageLedger) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    /// The per-minute history of model requests
+    pub fn history(&self) -> MetricsHistory {
+        self.history.clone()
+    }
+
+    /// The ledger of tokens used per API key
+    pub fn usage(&self) -> UsageLedger {
+        self.usage.clone()
+    }
+
     /// Record a request's timing information
     pub async fn record(&self, path: String, time_ms: u64) {
         let mut endpoints = self.endpoints.lock().await;
@@ -97,6 +147,14 @@ impl MetricsStore {
             .collect()
     }
 
+    /// Forget the response times recorded so far, returning how many endpoints had some
+    pub async fn reset(&self) -> usize {
+        let mut endpoints = self.endpoints.lock().await;
+        let count = endpoints.len();
+        endpoints.clear();
+        count
+    }
+
     /// Log a summary of all metrics
     pub async fn log_summary(&self) {
         let metrics = self.get_all().await;
@@ -105,6 +163,180 @@ impl MetricsStore {
         for (path, metric) in metrics {
             info!("  {}: {}", path, metric.summary());
         }
+        // Sampled by the memory monitor, which only runs in Standalone mode
+        if let Some(memory) = inference_engine::memory::last_memory_sample() {
+            info!("  memory: {}", memory);
+        }
+        self.history.flush();
+    }
+}
+
+/// Response of `POST /admin/metrics/reset`.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MetricsResetResponse {
+    /// Always `admin.metrics.reset`
+    pub object: String,
+    /// Endpoints whose response times were cleared
+    pub endpoints: usize,
+}
+
+/// Handler for POST /admin/metrics/reset - clears the per-endpoint response times the
+/// periodic summary logs. The per-minute history and the usage ledger are kept
+#[utoipa::path(
+    post,
+    path = "/admin/metrics/reset",
+    tag = "gateway",
+    responses(
+        (status = 200, description = "The response times are cleared", body = MetricsResetResponse),
+        (status = 401, description = "The admin token is missing or wrong", body = ErrorResponse)
+    )
+)]
+pub async fn reset_metrics(
+    State(metrics_store): State<MetricsStore>,
+) -> Json<MetricsResetResponse> {
+    let endpoints = metrics_store.reset().await;
+    info!("Metrics reset, cleared {} endpoints", endpoints);
+    Json(MetricsResetResponse {
+        object: "admin.metrics.reset".to_string(),
+        endpoints,
+    })
+}
+
+/// `/admin/metrics/reset`, clearing `metrics_store`.
+pub fn create_metrics_reset_router(metrics_store: MetricsStore) -> Router {
+    Router::new()
+        .route("/admin/metrics/reset", post(reset_metrics))
+        .with_state(metrics_store)
+}
+
+/// What a model response tells about its request, read from the body as it's relayed:
+/// the model it names and the tokens its `usage` reports. Streams only carry usage when the
+/// client sets `stream_options.include_usage`, so until a usage chunk arrives each chunk
+/// with content counts as one completion token. Records the request when the body
+/// is dropped, i.e. once it's sent in full or the client has gone, in the history and
+/// under its API key in the usage ledger.
+struct ModelResponseObserver {
+    history: MetricsHistory,
+    usage: UsageLedger,
+    api_key: String,
+    start: Instant,
+    is_stream: bool,
+    is_embeddings: bool,
+    buffer: Vec<u8>,
+    model: Option<String>,
+    failed: bool,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    usage_seen: bool,
+}
+
+impl ModelResponseObserver {
+    fn new(
+        metrics_store: &MetricsStore,
+        api_key: String,
+        start: Instant,
+        status: StatusCode,
+        is_stream: bool,
+        is_embeddings: bool,
+    ) -> Self {
+        Self {
+            history: metrics_store.history(),
+            usage: metrics_store.usage(),
+            api_key,
+            start,
+            is_stream,
+            is_embeddings,
+            buffer: Vec::new(),
+            model: None,
+            failed: !status.is_success(),
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            usage_seen: false,
+        }
+    }
+
+    fn observe(&mut self, chunk: &[u8]) {
+        if self.is_stream {
+            self.buffer.extend_from_slice(chunk);
+            while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                self.observe_event_line(&line);
+            }
+        } else if self.buffer.len() + chunk.len() <= MAX_OBSERVED_BODY {
+            self.buffer.extend_from_slice(chunk);
+        } else {
+            self.buffer = Vec::new();
+        }
+    }
+
+    fn observe_event_line(&mut self, line: &[u8]) {
+        let Some(data) = std::str::from_utf8(line)
+            .ok()
+            .and_then(|line| line.trim().strip_prefix("data:"))
+            .map(str::trim)
+        else {
+            return;
+        };
+        if let Ok(json) = serde_json::from_str::<Value>(data) {
+            if !self.usage_seen
+                && json["choices"][0]["delta"]["content"]
+                    .as_str()
+                    .is_some_and(|content| !content.is_empty())
+            {
+                self.completion_tokens += 1;
+            }
+            self.observe_json(&json);
+        }
+    }
+
+    fn observe_json(&mut self, json: &Value) {
+        if let Some(model) = json["model"].as_str() {
+            self.model = Some(model.to_string());
+        }
+        if json.get("error").is_some() {
+            self.failed = true;
+        }
+        if let Some(usage) = json.get("usage").filter(|usage| usage.is_object()) {
+            self.usage_seen = true;
+            self.prompt_tokens = usage["prompt_tokens"].as_u64().unwrap_or(0);
+            self.completion_tokens = usage["completion_tokens"].as_u64().unwrap_or(0);
+        }
+    }
+}
+
+impl Drop for ModelResponseObserver {
+    fn drop(&mut self) {
+        if !self.is_stream
+            && let Ok(json) = serde_json::from_slice::<Value>(&self.buffer)
+        {
+            self.observe_json(&json);
+        }
+        let model = self.model.take().unwrap_or_else(|| "unknown".to_string());
+        let finished_at = SystemTime::now();
+        // Failures that used no tokens cost nothing
+        if !self.failed || self.prompt_tokens + self.completion_tokens > 0 {
+            let (prompt_tokens, embedding_tokens) = if self.is_embeddings {
+                (0, self.prompt_tokens)
+            } else {
+                (self.prompt_tokens, 0)
+            };
+            self.usage.record(KeyUsage {
+                api_key: std::mem::take(&mut self.api_key),
+                model: model.clone(),
+                prompt_tokens,
+                completion_tokens: self.completion_tokens,
+                embedding_tokens,
+                finished_at,
+            });
+        }
+        self.history.record(ModelRequest {
+            model,
+            failed: self.failed,
+            prompt_tokens: self.prompt_tokens,
+            completion_tokens: self.completion_tokens,
+            latency_ms: self.start.elapsed().as_millis() as u64,
+            finished_at,
+        });
     }
 }
 
@@ -146,12 +378,11 @@ impl<S> fmt::Debug for MetricsService<S> {
     }
 }
 
-impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for MetricsService<S>
+impl<S, ReqBody> Service<Request<ReqBody>> for MetricsService<S>
 where
-    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S: Service<Request<ReqBody>, Response = Response<Body>> + Clone + Send + 'static,
     S::Future: Send + 'static,
     ReqBody: Send + 'static,
-    ResBody: Send + 'static,
 {
     type Response = S::Response;
     type Error = S::Error;
@@ -170,6 +401,7 @@ where
         };
 
         let method = req.method().clone();
+        let api_key = request_key_id(req.headers());
         let start = Instant::now();
         let metrics_store = self.metrics_store.clone();
 
@@ -186,11 +418,52 @@ where
             metrics_store
                 .record(format!("{} {}", method, path), time_ms)
                 .await;
+            // And per variant of a model alias, to compare them
+            let variant = {
+                let header = |name| {
+                    response
+                        .headers()
+                        .get(name)
+                        .and_then(|value| value.to_str().ok())
+                };
+                match (header(MODEL_ALIAS_HEADER), header(MODEL_VARIANT_HEADER)) {
+                    (Some(alias), Some(variant)) => {
+                        Some(format!("{} {} ({} -> {})", method, path, alias, variant))
+                    }
+                    _ => None,
+                }
+            };
+            if let Some(variant) = variant {
+                metrics_store.record(variant, time_ms).await;
+            }
 
             // Log the request timing
             debug!("{} {} {} - {} ms", method, path, status, time_ms);
 
-            Ok(response)
+            if method != Method::POST || !MODEL_ROUTES.contains(&path.as_str()) {
+                return Ok(response);
+            }
+            let is_stream = response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| value.starts_with("text/event-stream"));
+            let mut observer = ModelResponseObserver::new(
+                &metrics_store,
+                api_key,
+                start,
+                status,
+                is_stream,
+                path == "/v1/embeddings",
+            );
+            let (parts, body) = response.into_parts();
+            let body = body.into_data_stream().map(move |chunk| {
+                if let Ok(bytes) = &chunk {
+                    observer.observe(bytes);
+                }
+                chunk
+            });
+            Ok(Response::from_parts(parts, Body::from_stream(body)))
         })
     }
 }
@@ -1,3 +1,11 @@
+pub mod auth;
+pub mod body_limit;
+pub mod history;
 pub mod metrics;
+pub mod usage;
 
-pub use metrics::{MetricsLayer, MetricsLoggerFuture, MetricsStore};
+pub use auth::{AdminToken, ApiKeys, with_admin_token, with_api_keys};
+pub use body_limit::{DEFAULT_MAX_REQUEST_BODY_MB, body_read_error, with_request_body_limit};
+pub use history::{MetricsHistory, create_metrics_history_router};
+pub use metrics::{MetricsLayer, MetricsLoggerFuture, MetricsStore, create_metrics_reset_router};
+pub use usage::{UsageLedger, create_usage_router};
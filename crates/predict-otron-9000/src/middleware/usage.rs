@@ -0,0 +1,352 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, header};
+use axum::routing::get;
+use axum::{Json, Router};
+use openai_protocol::ApiError;
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::{IntoParams, ToSchema};
+
+/// The key requests without an `Authorization: Bearer` header are counted under.
+pub const ANONYMOUS_KEY: &str = "anonymous";
+
+/// How far back a usage query reaches without a `start_time`.
+const DEFAULT_WINDOW: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS usage_ledger (
+    day INTEGER NOT NULL,
+    api_key TEXT NOT NULL,
+    model TEXT NOT NULL,
+    requests INTEGER NOT NULL,
+    prompt_tokens INTEGER NOT NULL,
+    completion_tokens INTEGER NOT NULL,
+    embedding_tokens INTEGER NOT NULL,
+    PRIMARY KEY (day, api_key, model)
+)";
+
+/// The id a key is recorded under: `key-` and the first 16 hex digits of its SHA-256, so
+/// the ledger never holds the keys themselves.
+pub fn key_id(api_key: &str) -> String {
+    let digest = Sha256::digest(api_key.as_bytes());
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("key-{}", &hex[..16])
+}
+
+/// The id of the key in a request's `Authorization: Bearer` header, or [`ANONYMOUS_KEY`].
+pub fn request_key_id(headers: &HeaderMap) -> String {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map_or_else(|| ANONYMOUS_KEY.to_string(), key_id)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn day_of(secs: u64) -> u64 {
+    secs / DAY_SECS * DAY_SECS
+}
+
+/// The tokens one chat or embeddings request used, as the metrics layer saw them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyUsage {
+    /// The key's id, see [`key_id`]
+    pub api_key: String,
+    pub model: String,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// The input tokens of an embeddings request
+    pub embedding_tokens: u64,
+    pub finished_at: SystemTime,
+}
+
+/// One key's use of one model on one day (UTC).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DailyUsage {
+    /// Start of the day, in seconds since the Unix epoch
+    pub day: u64,
+    /// `key-` and the first 16 hex digits of the key's SHA-256, or `anonymous`
+    pub api_key: String,
+    pub model: String,
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub embedding_tokens: u64,
+}
+
+/// Token counts per API key, model and day, kept in SQLite so operators can bill or audit
+/// them. Unlike the metrics history, nothing expires.
+#[derive(Clone)]
+pub struct UsageLedger {
+    db: Arc<Mutex<Connection>>,
+}
+
+impl std::fmt::Debug for UsageLedger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UsageLedger").finish_non_exhaustive()
+    }
+}
+
+impl UsageLedger {
+    /// Keep the ledger in the SQLite database at `path`, creating it if needed.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    /// Keep the ledger in memory only, until the process exits.
+    pub fn in_memory() -> Self {
+        Self::with_connection(Connection::open_in_memory().expect("in-memory SQLite"))
+            .expect("usage ledger schema")
+    }
+
+    /// The database at `path`, or an in-memory ledger without one or if it can't be opened.
+    pub fn open_or_in_memory(path: Option<&Path>) -> Self {
+        match path.map(|path| (path, Self::open(path))) {
+            Some((path, Ok(ledger))) => {
+                tracing::info!("Keeping the usage ledger in {}", path.display());
+                ledger
+            }
+            Some((path, Err(e))) => {
+                tracing::warn!(
+                    "Failed to open usage ledger {}: {}; keeping it in memory",
+                    path.display(),
+                    e
+                );
+                Self::in_memory()
+            }
+            None => Self::in_memory(),
+        }
+    }
+
+    fn with_connection(connection: Connection) -> rusqlite::Result<Self> {
+        connection.execute(SCHEMA, [])?;
+        Ok(Self {
+            db: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Add `usage` to its key's day.
+    pub fn record(&self, usage: KeyUsage) {
+        let ledger = self.clone();
+        let write = move || {
+            if let Err(e) = ledger.write(&usage) {
+                tracing::warn!("Failed to write the usage ledger: {}", e);
+            }
+        };
+        // Called as a response body ends, usually on a runtime thread
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn_blocking(write);
+            }
+            Err(_) => write(),
+        }
+    }
+
+    fn write(&self, usage: &KeyUsage) -> rusqlite::Result<()> {
+        let db = self.db.lock().expect("usage ledger lock");
+        db.execute(
+            "INSERT INTO usage_ledger VALUES (?1, ?2, ?3, 1, ?4, ?5, ?6)
+             ON CONFLICT (day, api_key, model) DO UPDATE SET
+                requests = requests + 1,
+                prompt_tokens = prompt_tokens + excluded.prompt_tokens,
+                completion_tokens = completion_tokens + excluded.completion_tokens,
+                embedding_tokens = embedding_tokens + excluded.embedding_tokens",
+            params![
+                day_of(unix_secs(usage.finished_at)),
+                usage.api_key,
+                usage.model,
+                usage.prompt_tokens,
+                usage.completion_tokens,
+                usage.embedding_tokens,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The days from the one holding `start` through the one holding `end`, of one key or
+    /// all of them, oldest first and by key and model.
+    pub fn query(
+        &self,
+        start: SystemTime,
+        end: SystemTime,
+        api_key: Option<&str>,
+    ) -> rusqlite::Result<Vec<DailyUsage>> {
+        let db = self.db.lock().expect("usage ledger lock");
+        let mut statement = db.prepare(
+            "SELECT day, api_key, model, requests, prompt_tokens, completion_tokens,
+                    embedding_tokens
+             FROM usage_ledger
+             WHERE day >= ?1 AND day <= ?2 AND (?3 IS NULL OR api_key = ?3)
+             ORDER BY day, api_key, model",
+        )?;
+        let rows = statement.query_map(
+            params![day_of(unix_secs(start)), day_of(unix_secs(end)), api_key],
+            |row| {
+                Ok(DailyUsage {
+                    day: row.get(0)?,
+                    api_key: row.get(1)?,
+                    model: row.get(2)?,
+                    requests: row.get(3)?,
+                    prompt_tokens: row.get(4)?,
+                    completion_tokens: row.get(5)?,
+                    embedding_tokens: row.get(6)?,
+                })
+            },
+        )?;
+        rows.collect()
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct UsageQuery {
+    /// First day to report, as seconds since the Unix epoch within it. Defaults to 30 days
+    /// ago
+    pub start_time: Option<u64>,
+    /// Last day to report, likewise. Defaults to today
+    pub end_time: Option<u64>,
+    /// Only this key's usage, by its id as the report shows it
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UsageResponse {
+    pub object: String,
+    /// One entry per day, key and model that had requests, oldest first
+    pub data: Vec<DailyUsage>,
+}
+
+/// Handler for GET /v1/usage - tokens used per API key, model and day
+#[utoipa::path(
+    get,
+    path = "/v1/usage",
+    tag = "gateway",
+    params(UsageQuery),
+    responses(
+        (status = 200, description = "Requests and tokens per day, key and model", body = UsageResponse),
+        (status = 400, description = "A time is out of range or the start is after the end", body = ErrorResponse),
+        (status = 500, description = "The ledger couldn't be read", body = ErrorResponse)
+    )
+)]
+pub async fn usage(
+    State(ledger): State<UsageLedger>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Json<UsageResponse>, ApiError> {
+    let time = |secs: u64, param: &str| {
+        UNIX_EPOCH
+            .checked_add(Duration::from_secs(secs))
+            .ok_or_else(|| {
+                ApiError::invalid_request(format!("Invalid {}: {}", param, secs)).with_param(param)
+            })
+    };
+    let end = match query.end_time {
+        Some(secs) => time(secs, "end_time")?,
+        None => SystemTime::now(),
+    };
+    let start = match query.start_time {
+        Some(secs) => time(secs, "start_time")?,
+        None => end.checked_sub(DEFAULT_WINDOW).unwrap_or(UNIX_EPOCH),
+    };
+    if start > end {
+        return Err(
+            ApiError::invalid_request("start_time is after end_time").with_param("start_time")
+        );
+    }
+
+    let data =
+        tokio::task::spawn_blocking(move || ledger.query(start, end, query.api_key.as_deref()))
+            .await
+            .map_err(|e| ApiError::server_error(format!("Usage query failed: {}", e)))?
+            .map_err(|e| {
+                ApiError::server_error(format!("Failed to read the usage ledger: {}", e))
+            })?;
+    Ok(Json(UsageResponse {
+        object: "list".to_string(),
+        data,
+    }))
+}
+
+/// `/v1/usage`, served from `ledger`.
+pub fn create_usage_router(ledger: UsageLedger) -> Router {
+    Router::new()
+        .route("/v1/usage", get(usage))
+        .with_state(ledger)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(api_key: &str, model: &str, finished_at: SystemTime) -> KeyUsage {
+        KeyUsage {
+            api_key: api_key.to_string(),
+            model: model.to_string(),
+            prompt_tokens: 10,
+            completion_tokens: 5,
+            embedding_tokens: 0,
+            finished_at,
+        }
+    }
+
+    #[test]
+    fn test_keys_are_recorded_by_id() {
+        let id = key_id("sk-secret");
+        assert_eq!(id.len(), "key-".len() + 16);
+        assert_eq!(id, key_id("sk-secret"));
+        assert_ne!(id, key_id("sk-other"));
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(request_key_id(&headers), ANONYMOUS_KEY);
+        headers.insert(header::AUTHORIZATION, "Bearer sk-secret".parse().unwrap());
+        assert_eq!(request_key_id(&headers), id);
+    }
+
+    #[test]
+    fn test_usage_adds_up_per_key_model_and_day() {
+        let ledger = UsageLedger::in_memory();
+        let now = SystemTime::now();
+        let yesterday = now - Duration::from_secs(DAY_SECS);
+
+        ledger.record(usage("key-a", "gemma-3-1b-it", yesterday));
+        ledger.record(usage("key-a", "gemma-3-1b-it", now));
+        ledger.record(usage("key-a", "gemma-3-1b-it", now));
+        ledger.record(KeyUsage {
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            embedding_tokens: 7,
+            ..usage("key-b", "nomic-embed-text-v1.5", now)
+        });
+
+        let days = ledger.query(yesterday, now, None).unwrap();
+        assert_eq!(days.len(), 3);
+        assert_eq!(days[0].day, day_of(unix_secs(yesterday)));
+        assert_eq!(days[0].requests, 1);
+        let today = &days[1];
+        assert_eq!((today.api_key.as_str(), today.requests), ("key-a", 2));
+        assert_eq!((today.prompt_tokens, today.completion_tokens), (20, 10));
+        assert_eq!(
+            (days[2].api_key.as_str(), days[2].embedding_tokens),
+            ("key-b", 7)
+        );
+
+        assert_eq!(ledger.query(now, now, Some("key-b")).unwrap().len(), 1);
+        assert!(
+            ledger
+                .query(yesterday, yesterday, Some("key-b"))
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
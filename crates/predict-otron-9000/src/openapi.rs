@@ -0,0 +1,139 @@
+use axum::response::Html;
+use axum::routing::get;
+use axum::{Json, Router};
+use utoipa::OpenApi;
+
+use crate::middleware::history::{self, MetricsHistoryResponse, MinuteMetrics};
+use crate::middleware::metrics::{self, MetricsResetResponse};
+use crate::middleware::usage::{self, DailyUsage, UsageResponse};
+
+/// Swagger UI for `/openapi.json`. The page loads Swagger UI from a CDN, so it needs
+/// internet access in the browser; the spec itself is served by the gateway.
+const DOCS_PAGE: &str = include_str!("docs.html");
+
+/// Health check
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "gateway",
+    responses((status = 200, description = "The gateway is up", body = String, content_type = "text/plain"))
+)]
+pub async fn health() -> &'static str {
+    "ok"
+}
+
+/// This OpenAPI document: the gateway's routes and those of the inference and embeddings
+/// engines behind it
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "gateway",
+    responses((status = 200, description = "OpenAPI 3 document", body = Object))
+)]
+async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(openapi())
+}
+
+/// Interactive documentation for the API
+#[utoipa::path(
+    get,
+    path = "/docs",
+    tag = "gateway",
+    responses((status = 200, description = "Swagger UI page", body = String, content_type = "text/html"))
+)]
+async fn docs() -> Html<&'static str> {
+    Html(DOCS_PAGE)
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "predict-otron-9000",
+        description = "OpenAI-compatible chat completions and embeddings. The same routes are \
+                       served in Standalone and HighAvailability mode."
+    ),
+    paths(
+        health,
+        inference_engine::health::live,
+        openapi_json,
+        docs,
+        history::metrics_history,
+        metrics::reset_metrics,
+        usage::usage
+    ),
+    components(schemas(
+        MetricsHistoryResponse,
+        MinuteMetrics,
+        MetricsResetResponse,
+        UsageResponse,
+        DailyUsage
+    )),
+    tags((name = "gateway", description = "The gateway's own endpoints"))
+)]
+struct GatewayApi;
+
+/// The API of a whole deployment: the gateway's routes merged with the inference and
+/// embeddings engines' annotations. Where two describe the same route, the inference engine,
+/// which the gateway routes through, wins.
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    let mut openapi = GatewayApi::openapi();
+    // Filled from the crate's license field, which is empty
+    openapi.info.license = None;
+    openapi.merge(inference_engine::ApiDoc::openapi());
+    openapi.merge(embeddings_engine::ApiDoc::openapi());
+    openapi
+}
+
+/// `/openapi.json` and the `/docs` page.
+pub fn create_docs_router() -> Router {
+    Router::new()
+        .route("/openapi.json", get(openapi_json))
+        .route("/docs", get(docs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openapi_covers_every_service() {
+        let openapi = openapi();
+        for path in [
+            "/health",
+            "/health/live",
+            "/health/ready",
+            "/openapi.json",
+            "/docs",
+            "/v1/chat/completions",
+            "/v1/chat/completions/{id}/cancel",
+            "/v1/models",
+            "/v1/models/{id}",
+            "/v1/models/{id}/load",
+            "/v1/models/{id}/warmup",
+            "/v1/embeddings",
+            "/admin/device",
+            "/admin/status",
+            "/admin/log_level",
+            "/admin/metrics/history",
+            "/admin/metrics/reset",
+            "/v1/usage",
+        ] {
+            assert!(
+                openapi.paths.get_path_item(path).is_some(),
+                "{path} missing"
+            );
+        }
+        assert_eq!(openapi.info.title, "predict-otron-9000");
+    }
+
+    #[test]
+    fn test_openapi_references_resolve() {
+        let openapi = openapi();
+        let json = serde_json::to_string(&openapi).unwrap();
+        let schemas = &openapi.components.as_ref().unwrap().schemas;
+        for reference in json.split("\"#/components/schemas/").skip(1) {
+            let name = &reference[..reference.find('"').unwrap()];
+            assert!(schemas.contains_key(name), "schema {name} missing");
+        }
+    }
+}
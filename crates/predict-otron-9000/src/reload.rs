@@ -0,0 +1,345 @@
+//! Hot reload of the config file: [`spawn_config_reload`] watches the file's directory,
+//! which also catches Kubernetes swapping a mounted ConfigMap, and applies the proxy's
+//! service URLs and retries, the default model and the scheduler limits of a changed
+//! file without a restart. Other changes are logged as needing one.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
+use inference_engine::InferenceScheduler;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use openai_protocol::{ApiError, ChatCompletionRequest};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::config::ServerConfig;
+use crate::hooks::ChatHook;
+
+/// The fields a reload applies; the others take a restart.
+const RELOADABLE: &[&str] = &[
+    "services",
+    "defaultModel",
+    "maxConcurrentInferences",
+    "inferenceQueueSize",
+    "proxyRetry",
+];
+
+/// The server config, replaced as a whole on a reload. Requests take the current one when
+/// they start, so they finish on the settings they started with.
+#[derive(Clone)]
+pub struct LiveConfig {
+    current: Arc<RwLock<Arc<ServerConfig>>>,
+}
+
+impl LiveConfig {
+    pub fn new(config: ServerConfig) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(config))),
+        }
+    }
+
+    pub fn current(&self) -> Arc<ServerConfig> {
+        Arc::clone(&self.current.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    fn replace(&self, config: ServerConfig) {
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(config);
+    }
+}
+
+/// The fields that differ between `old` and `new`, each as `field: old -> new`. API keys
+/// and the admin token are only named, never shown.
+pub fn config_changes(old: &ServerConfig, new: &ServerConfig) -> Vec<(String, String)> {
+    let fields = |config: &ServerConfig| match serde_json::to_value(config) {
+        Ok(Value::Object(fields)) => fields,
+        _ => Default::default(),
+    };
+    let (old_fields, new_fields) = (fields(old), fields(new));
+    let names: BTreeSet<&String> = old_fields.keys().chain(new_fields.keys()).collect();
+    let shown = |value: Option<&Value>| value.map_or("unset".to_string(), Value::to_string);
+
+    let mut changes: Vec<(String, String)> = names
+        .into_iter()
+        .filter(|name| old_fields.get(*name) != new_fields.get(*name))
+        .map(|name| {
+            let change = format!(
+                "{}: {} -> {}",
+                name,
+                shown(old_fields.get(name)),
+                shown(new_fields.get(name))
+            );
+            (name.clone(), change)
+        })
+        .collect();
+    if old.api_keys != new.api_keys {
+        changes.push((
+            "apiKeys".to_string(),
+            format!(
+                "apiKeys: {} -> {} keys",
+                old.api_keys.len(),
+                new.api_keys.len()
+            ),
+        ));
+    }
+    if old.admin_token != new.admin_token {
+        changes.push(("adminToken".to_string(), "adminToken: changed".to_string()));
+    }
+    changes
+}
+
+/// Apply the reloadable fields of `new` to `live`, and its limits to `scheduler` if the
+/// engines run in process, logging what changed. Fails, changing nothing, for service
+/// URLs the running mode can't proxy to.
+pub fn apply_config(
+    live: &LiveConfig,
+    scheduler: Option<&InferenceScheduler>,
+    new: ServerConfig,
+) -> Result<(), String> {
+    let current = live.current();
+    let mut next = (*current).clone();
+    next.services = new.services.clone();
+    next.default_model = new.default_model.clone();
+    next.max_concurrent_inferences = new.max_concurrent_inferences;
+    next.inference_queue_size = new.inference_queue_size;
+    next.proxy_retry = new.proxy_retry.clone();
+    next.is_high_availability().map_err(|e| e.to_string())?;
+
+    for (field, change) in config_changes(&current, &new) {
+        if RELOADABLE.contains(&field.as_str()) {
+            tracing::info!("Config reloaded, {}", change);
+        } else {
+            tracing::warn!("Config changed, restart to apply {}", change);
+        }
+    }
+    if let Some(scheduler) = scheduler {
+        let limits = next.inference_scheduler().stats();
+        scheduler.resize(limits.max_concurrent, limits.max_queued);
+    }
+    live.replace(next);
+    Ok(())
+}
+
+/// Watch `path` and, once its contents changed, apply the config `load` returns for it,
+/// e.g. the file with the environment and flags on top. Events are applied `debounce`
+/// after the last of a burst, as one save can raise several. A config that fails to load
+/// or apply is logged and the running one kept.
+///
+/// The file's directory is watched rather than the file, as editors and Kubernetes
+/// replace a file instead of writing to it. Where it can't be watched, e.g. on some
+/// network filesystems or past the inotify watch limit, the file is read every
+/// `poll_interval` instead.
+pub fn spawn_config_reload(
+    path: PathBuf,
+    debounce: Duration,
+    poll_interval: Duration,
+    load: impl Fn() -> Result<ServerConfig, String> + Send + 'static,
+    live: LiveConfig,
+    scheduler: Option<InferenceScheduler>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let (tx, mut events) = mpsc::unbounded_channel();
+        // Kept for as long as the task runs, which is as long as the directory is watched
+        let watcher = watch_directory(&path, tx);
+        match &watcher {
+            Ok(_) => tracing::info!("Reloading {} when it changes", path.display()),
+            Err(e) => tracing::warn!(
+                "Can't watch {} ({}), checking it for changes every {:?}",
+                path.display(),
+                e,
+                poll_interval
+            ),
+        }
+        let mut ticker = tokio::time::interval(poll_interval);
+        // Compared by contents, as a modification time can't tell two writes a second apart
+        let mut last_contents = std::fs::read(&path).ok();
+        loop {
+            if watcher.is_ok() {
+                if events.recv().await.is_none() {
+                    return;
+                }
+                while let Ok(Some(())) = tokio::time::timeout(debounce, events.recv()).await {}
+            } else {
+                ticker.tick().await;
+            }
+            let contents = std::fs::read(&path).ok();
+            if contents.is_none() || contents == last_contents {
+                continue;
+            }
+            last_contents = contents;
+            let applied = load().and_then(|config| apply_config(&live, scheduler.as_ref(), config));
+            if let Err(e) = applied {
+                tracing::warn!(
+                    "Keeping the running config, {} failed to reload: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    })
+}
+
+/// Watch the directory holding `path`, sending on `events` whenever something in it
+/// changes.
+fn watch_directory(
+    path: &Path,
+    events: mpsc::UnboundedSender<()>,
+) -> notify::Result<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        if event.is_ok_and(|event| !event.kind.is_access()) {
+            let _ = events.send(());
+        }
+    })?;
+    let directory = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    watcher.watch(directory, RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
+
+/// Sends requests for the `default` model to the default model of the live config, so a
+/// reload changes it. Without one there, the engine's own default answers.
+pub struct LiveDefaultModel {
+    config: LiveConfig,
+}
+
+impl LiveDefaultModel {
+    pub fn new(config: LiveConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl ChatHook for LiveDefaultModel {
+    fn name(&self) -> &str {
+        "live-default-model"
+    }
+
+    fn on_request(&self, request: &mut ChatCompletionRequest) -> Result<(), ApiError> {
+        if request.model == "default"
+            && let Some(model) = &self.config.current().default_model
+        {
+            request.model = model.clone();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ServerMode, Services};
+
+    fn ha_config(inference_url: &str) -> ServerConfig {
+        ServerConfig {
+            server_mode: ServerMode::HighAvailability,
+            services: Some(Services {
+                inference_url: Some(inference_url.to_string()),
+                embeddings_url: Some("http://embeddings:8080".to_string()),
+                ..Services::default()
+            }),
+            ..ServerConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_reloads_apply_only_the_reloadable_fields() {
+        let live = LiveConfig::new(ha_config("http://inference-a:8080"));
+        let before = live.current();
+        let scheduler = InferenceScheduler::new(4, 32);
+
+        let mut new = ha_config("http://inference-b:8080");
+        new.server_port = 9000;
+        new.default_model = Some("llama-3.2-1b-instruct".to_string());
+        new.max_concurrent_inferences = Some(2);
+        let changes: Vec<String> = config_changes(&before, &new)
+            .into_iter()
+            .map(|(field, _)| field)
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                "defaultModel",
+                "maxConcurrentInferences",
+                "serverPort",
+                "services"
+            ]
+        );
+
+        apply_config(&live, Some(&scheduler), new).unwrap();
+        let after = live.current();
+        assert_eq!(after.inference_url().unwrap(), "http://inference-b:8080");
+        assert_eq!(
+            after.default_model.as_deref(),
+            Some("llama-3.2-1b-instruct")
+        );
+        assert_eq!(after.server_port, 8080);
+        assert_eq!(scheduler.stats().max_concurrent, 2);
+        // A request that started before keeps its settings
+        assert_eq!(before.inference_url().unwrap(), "http://inference-a:8080");
+
+        let mut request: ChatCompletionRequest =
+            serde_json::from_str(r#"{"model": "default", "messages": []}"#).unwrap();
+        LiveDefaultModel::new(live.clone())
+            .on_request(&mut request)
+            .unwrap();
+        assert_eq!(request.model, "llama-3.2-1b-instruct");
+    }
+
+    #[test]
+    fn test_reloads_without_services_are_refused() {
+        let live = LiveConfig::new(ha_config("http://inference-a:8080"));
+        let new = ServerConfig {
+            server_mode: ServerMode::HighAvailability,
+            ..ServerConfig::default()
+        };
+        assert!(apply_config(&live, None, new).is_err());
+        assert_eq!(
+            live.current().inference_url().unwrap(),
+            "http://inference-a:8080"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_edits_to_the_watched_file_are_applied() {
+        let directory = std::env::temp_dir().join(format!("reload-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("config.json");
+        let write = |inference_url: &str| {
+            let config = serde_json::to_string(&ha_config(inference_url)).unwrap();
+            std::fs::write(&path, config).unwrap();
+        };
+        write("http://inference-a:8080");
+
+        let live = LiveConfig::new(ha_config("http://inference-a:8080"));
+        let file = path.clone();
+        let reload = spawn_config_reload(
+            path.clone(),
+            Duration::from_millis(20),
+            Duration::from_millis(50),
+            move || {
+                let text = std::fs::read_to_string(&file).map_err(|e| e.to_string())?;
+                serde_json::from_str(&text).map_err(|e| e.to_string())
+            },
+            live.clone(),
+            None,
+        );
+        // Gives the task time to start watching
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        write("http://inference-b:8080");
+        for _ in 0..100 {
+            if live.current().inference_url().unwrap() == "http://inference-b:8080" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        reload.abort();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(
+            live.current().inference_url().unwrap(),
+            "http://inference-b:8080"
+        );
+    }
+}
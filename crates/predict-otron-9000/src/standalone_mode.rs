@@ -2,19 +2,30 @@ use crate::config::ServerConfig;
 use axum::Router;
 use inference_engine::AppState;
 
-pub fn create_standalone_router(_server_config: ServerConfig) -> Router {
-    // Create unified router by merging embeddings and inference routers (existing behavior)
-    let embeddings_router = embeddings_engine::create_embeddings_router();
-
-    // Create AppState - no default model, must be configured explicitly
-    // This removes the hardcoded gemma-3-1b-it default behavior
-    let app_state = AppState::default();
+pub fn create_standalone_router(server_config: ServerConfig) -> Router {
+    create_standalone_router_with_state(standalone_app_state(&server_config))
+}
 
-    // Get the inference router directly from the inference engine
-    let inference_router = inference_engine::create_router(app_state);
+/// The state Standalone mode serves inference from, e.g. for the warm pool to load
+/// models the same way.
+pub fn standalone_app_state(server_config: &ServerConfig) -> AppState {
+    // Without a configured default model, AppState falls back to `DEFAULT_MODEL`
+    let defaults = AppState::default();
+    AppState {
+        model_id: server_config
+            .default_model
+            .clone()
+            .unwrap_or_else(|| defaults.model_id.clone()),
+        devices: server_config.device_placement(),
+        scheduler: server_config.inference_scheduler(),
+        ..defaults
+    }
+}
 
-    // Merge the local routers
-    Router::new()
-        .merge(embeddings_router)
-        .merge(inference_router)
+/// The Standalone routes, serving inference from `app_state`, e.g. one whose
+/// `runner_loader` hands out a mock runner.
+pub fn create_standalone_router_with_state(app_state: AppState) -> Router {
+    // The inference router also serves /v1/embeddings: it answers the decoder embedding ids
+    // itself and hands every other model to the embeddings engine
+    inference_engine::create_router(app_state)
 }
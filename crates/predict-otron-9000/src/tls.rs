@@ -0,0 +1,132 @@
+//! TLS termination for the gateway, so it can face clients without a proxy in front: the
+//! certificate and key of [`TlsSettings`], and a plain HTTP listener that redirects to
+//! HTTPS.
+
+use std::io;
+use std::path::PathBuf;
+
+use axum::Router;
+use axum::http::{HeaderMap, StatusCode, Uri, header};
+use axum::response::{IntoResponse, Redirect};
+use axum_server::tls_rustls::RustlsConfig;
+use tokio::net::TcpListener;
+
+/// Where the gateway's certificate and key are, and the port redirecting plain HTTP.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsSettings {
+    /// PEM certificate chain, the server's certificate first
+    pub cert_path: PathBuf,
+    /// PEM private key of the certificate
+    pub key_path: PathBuf,
+    /// Port answering plain HTTP with redirects to HTTPS, if any
+    pub redirect_port: Option<u16>,
+}
+
+impl TlsSettings {
+    /// The rustls config serving the certificate, on the ring provider. Fails for files
+    /// that can't be read or don't hold a certificate and a matching key.
+    pub async fn rustls_config(&self) -> io::Result<RustlsConfig> {
+        // The process may have installed it already
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        RustlsConfig::from_pem_file(&self.cert_path, &self.key_path)
+            .await
+            .map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!(
+                        "Failed to load TLS certificate {} and key {}: {}",
+                        self.cert_path.display(),
+                        self.key_path.display(),
+                        e
+                    ),
+                )
+            })
+    }
+}
+
+/// The HTTPS URL for a plain HTTP request to `uri` with `headers`, on `https_port`. The
+/// host comes from the `Host` header, without its port.
+fn https_url(headers: &HeaderMap, uri: &Uri, https_port: u16) -> Option<String> {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| uri.host())?;
+    // Keep the brackets of an IPv6 address
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !name.ends_with(':') && port.parse::<u16>().is_ok() => name,
+        _ => host,
+    };
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    Some(if https_port == 443 {
+        format!("https://{}{}", host, path)
+    } else {
+        format!("https://{}:{}{}", host, https_port, path)
+    })
+}
+
+/// Routes answering every request with a permanent redirect to its HTTPS URL on
+/// `https_port`. The redirect keeps the method and body, so API clients follow it too.
+pub fn redirect_router(https_port: u16) -> Router {
+    Router::new().fallback(move |headers: HeaderMap, uri: Uri| async move {
+        match https_url(&headers, &uri, https_port) {
+            Some(url) => Redirect::permanent(&url).into_response(),
+            None => (StatusCode::BAD_REQUEST, "Missing Host header").into_response(),
+        }
+    })
+}
+
+/// Answer plain HTTP on `address` with redirects to HTTPS on `https_port`, until the
+/// process exits.
+pub async fn serve_redirects(address: &str, https_port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    tracing::info!(
+        "Redirecting plain HTTP on {} to HTTPS",
+        listener.local_addr()?
+    );
+    axum::serve(listener, redirect_router(https_port).into_make_service()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_plain_http_is_redirected_to_https() {
+        let uri: Uri = "/v1/chat/completions?stream=true".parse().unwrap();
+        assert_eq!(
+            https_url(&host("gateway.example:8080"), &uri, 8443).as_deref(),
+            Some("https://gateway.example:8443/v1/chat/completions?stream=true")
+        );
+
+        let uri: Uri = "/health".parse().unwrap();
+        assert_eq!(
+            https_url(&host("[::1]:80"), &uri, 443).as_deref(),
+            Some("https://[::1]/health")
+        );
+        assert_eq!(
+            https_url(&host("gateway.example"), &uri, 443).as_deref(),
+            Some("https://gateway.example/health")
+        );
+        assert_eq!(https_url(&HeaderMap::new(), &uri, 443), None);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_certificates_are_reported() {
+        let settings = TlsSettings {
+            cert_path: "/nonexistent/cert.pem".into(),
+            key_path: "/nonexistent/key.pem".into(),
+            redirect_port: None,
+        };
+        let error = settings.rustls_config().await.unwrap_err();
+        assert!(
+            error.to_string().contains("/nonexistent/cert.pem"),
+            "{error}"
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! Retries and circuit breakers for the HighAvailability proxy's calls to the services, so
+//! a service restarting behind its load balancer doesn't reach clients as 502s. Requests
+//! that are safe to repeat are retried with exponential backoff, and a service that keeps
+//! failing has its circuit opened: the proxy answers for it with a 503 for a while instead
+//! of calling it.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// How the proxy retries failed calls and when it stops calling a failing service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RetryPolicy {
+    /// Retries of a failed call after the first attempt; 0 turns retries off
+    pub max_retries: u32,
+    /// Wait before the first retry in milliseconds, doubling for each retry after it
+    pub initial_backoff_ms: u64,
+    /// Longest wait between two attempts in milliseconds
+    pub max_backoff_ms: u64,
+    /// Failed calls in a row that open a service's circuit; 0 never opens it
+    pub failure_threshold: u32,
+    /// Seconds an open circuit turns requests away before letting them through again
+    pub open_secs: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 2000,
+            failure_threshold: 5,
+            open_secs: 30,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The wait before retry number `retry`, counted from 0.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff_ms
+            .saturating_mul(2u64.saturating_pow(retry));
+        Duration::from_millis(backoff.min(self.max_backoff_ms))
+    }
+}
+
+/// Consecutive failures of one service, and until when its circuit is open.
+#[derive(Debug, Default)]
+struct Circuit {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// The circuits of the services the proxy calls, by service. A circuit opens once a
+/// service failed [`RetryPolicy::failure_threshold`] calls in a row. When it has been open
+/// for [`RetryPolicy::open_secs`], calls go through again: the next success closes it and
+/// the next failure opens it for another round.
+#[derive(Clone, Default)]
+pub struct CircuitBreakers {
+    circuits: Arc<Mutex<HashMap<String, Circuit>>>,
+}
+
+impl CircuitBreakers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How much longer `service`'s circuit stays open, `None` while calls may go through.
+    pub fn open_for(&self, service: &str) -> Option<Duration> {
+        let circuits = self.circuits.lock().unwrap_or_else(PoisonError::into_inner);
+        let open_until = circuits.get(service)?.open_until?;
+        let remaining = open_until.saturating_duration_since(Instant::now());
+        (!remaining.is_zero()).then_some(remaining)
+    }
+
+    /// Count a call to `service` that went through, closing its circuit.
+    pub fn record_success(&self, service: &str) {
+        let mut circuits = self.circuits.lock().unwrap_or_else(PoisonError::into_inner);
+        circuits.remove(service);
+    }
+
+    /// Count a failed call to `service`, opening its circuit once `policy`'s threshold of
+    /// failures in a row is reached.
+    pub fn record_failure(&self, service: &str, policy: &RetryPolicy) {
+        let mut circuits = self.circuits.lock().unwrap_or_else(PoisonError::into_inner);
+        let circuit = circuits.entry(service.to_string()).or_default();
+        circuit.failures = circuit.failures.saturating_add(1);
+        if policy.failure_threshold > 0 && circuit.failures >= policy.failure_threshold {
+            if circuit
+                .open_until
+                .is_none_or(|until| until <= Instant::now())
+            {
+                tracing::warn!(
+                    "{} failed {} calls in a row, opening its circuit for {}s",
+                    service,
+                    circuit.failures,
+                    policy.open_secs
+                );
+            }
+            circuit.open_until = Some(Instant::now() + Duration::from_secs(policy.open_secs));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_limit() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(1), Duration::from_millis(200));
+        assert_eq!(policy.backoff(4), Duration::from_millis(1600));
+        assert_eq!(policy.backoff(5), Duration::from_millis(2000));
+        assert_eq!(policy.backoff(64), Duration::from_millis(2000));
+
+        let policy: RetryPolicy = serde_json::from_str(r#"{"maxRetries": 4}"#).unwrap();
+        assert_eq!(policy.max_retries, 4);
+        assert_eq!(policy.initial_backoff_ms, 100);
+    }
+
+    #[test]
+    fn test_circuits_open_after_failures_in_a_row() {
+        let policy = RetryPolicy {
+            failure_threshold: 3,
+            ..RetryPolicy::default()
+        };
+        let breakers = CircuitBreakers::new();
+        breakers.record_failure("http://inference:8080", &policy);
+        breakers.record_failure("http://inference:8080", &policy);
+        breakers.record_success("http://inference:8080");
+        breakers.record_failure("http://inference:8080", &policy);
+        breakers.record_failure("http://inference:8080", &policy);
+        assert_eq!(breakers.open_for("http://inference:8080"), None);
+
+        breakers.record_failure("http://inference:8080", &policy);
+        let open_for = breakers.open_for("http://inference:8080").unwrap();
+        assert!(open_for > Duration::from_secs(29), "{:?}", open_for);
+        // Each service has its own circuit
+        assert_eq!(breakers.open_for("http://embeddings:8080"), None);
+    }
+
+    #[test]
+    fn test_open_circuits_let_calls_through_after_a_while() {
+        let policy = RetryPolicy {
+            failure_threshold: 1,
+            open_secs: 0,
+            ..RetryPolicy::default()
+        };
+        let breakers = CircuitBreakers::new();
+        breakers.record_failure("http://inference:8080", &policy);
+        assert_eq!(breakers.open_for("http://inference:8080"), None);
+
+        // A failure after the wait opens it again at once
+        let policy = RetryPolicy {
+            open_secs: 30,
+            ..policy
+        };
+        breakers.record_failure("http://inference:8080", &policy);
+        assert!(breakers.open_for("http://inference:8080").is_some());
+        breakers.record_success("http://inference:8080");
+        assert_eq!(breakers.open_for("http://inference:8080"), None);
+
+        // A threshold of 0 never opens it
+        let never = RetryPolicy {
+            failure_threshold: 0,
+            ..RetryPolicy::default()
+        };
+        for _ in 0..10 {
+            breakers.record_failure("http://embeddings:8080", &never);
+        }
+        assert_eq!(breakers.open_for("http://embeddings:8080"), None);
+    }
+}
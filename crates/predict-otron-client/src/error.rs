@@ -0,0 +1,60 @@
+use std::fmt;
+
+/// Why a request to the server failed.
+#[derive(Debug)]
+pub enum Error {
+    /// The server could not be reached.
+    Connect(reqwest::Error),
+    /// The server answered with an error status, e.g. 404 for an unknown model.
+    Status { status: u16, message: String },
+    /// The server reported an error partway through a streamed completion.
+    Stream(String),
+    /// The request broke off, or its response could not be read.
+    Http(reqwest::Error),
+    /// The response was not the JSON this client expects.
+    Decode(serde_json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Connect(e) => write!(f, "could not connect to the server: {e}"),
+            Error::Status { message, .. } => f.write_str(message),
+            Error::Stream(message) => f.write_str(message),
+            Error::Http(e) => e.fmt(f),
+            Error::Decode(e) => write!(f, "unexpected response: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Connect(e) | Error::Http(e) => Some(e),
+            Error::Decode(e) => Some(e),
+            Error::Status { .. } | Error::Stream(_) => None,
+        }
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from(e: Error) -> Self {
+        std::io::Error::other(e)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_connect() {
+            Error::Connect(e)
+        } else {
+            Error::Http(e)
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Decode(e)
+    }
+}
@@ -0,0 +1,225 @@
+//! Typed async client for the predict-otron-9000 API: chat completions, streamed or whole,
+//! embeddings, the model list and the admin endpoints.
+//!
+//! ```no_run
+//! use futures_util::StreamExt;
+//! use predict_otron_client::{ChatCompletionRequest, Client, Message};
+//!
+//! # async fn run() -> Result<(), predict_otron_client::Error> {
+//! let client = Client::new(predict_otron_client::DEFAULT_BASE_URL)?;
+//! let request = ChatCompletionRequest::new("gemma-3-1b-it", vec![Message::user("Hi!")]);
+//! let mut stream = client.chat_stream(&request).await?;
+//! while let Some(chunk) = stream.next().await {
+//!     print!("{}", chunk?.content().unwrap_or_default());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use reqwest::Response;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+mod error;
+mod stream;
+mod types;
+
+pub use error::Error;
+pub use stream::ChatStream;
+pub use types::*;
+
+/// Where the server listens by default.
+pub const DEFAULT_BASE_URL: &str = "http://localhost:8080";
+
+/// Client for a predict-otron-9000 server, or for an inference or embeddings engine serving
+/// the same routes on its own. Cloning it shares the connection pool.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl Client {
+    /// A client for the server at `base_url`, its root rather than `/v1`.
+    pub fn new(base_url: &str) -> Result<Self, Error> {
+        // Generations stream for as long as they take; only connecting is bounded.
+        let http = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(5))
+            .build()
+            .map_err(Error::Http)?;
+        Ok(Self::with_http_client(http, base_url))
+    }
+
+    /// A client sending its requests through `http`, e.g. one with a request timeout.
+    pub fn with_http_client(http: reqwest::Client, base_url: &str) -> Self {
+        Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Whether the gateway is up. The engines on their own answer [`Self::live`] instead.
+    pub async fn health(&self) -> Result<(), Error> {
+        let response = self.http.get(self.url("/health")).send().await?;
+        check(response).await.map(drop)
+    }
+
+    /// Whether the server is up, from `/health/live`.
+    pub async fn live(&self) -> Result<(), Error> {
+        let response = self.http.get(self.url("/health/live")).send().await?;
+        check(response).await.map(drop)
+    }
+
+    /// Whether the server's default models are loaded, and each one's state. A server still
+    /// loading answers 503 with the same body, so that's returned rather than an error.
+    pub async fn readiness(&self) -> Result<ReadinessResponse, Error> {
+        let response = self.http.get(self.url("/health/ready")).send().await?;
+        if response.status() == reqwest::StatusCode::SERVICE_UNAVAILABLE {
+            return Ok(response.json().await?);
+        }
+        json(response).await
+    }
+
+    /// The models the server can run, with each one's family and whether it is loaded.
+    pub async fn models(&self) -> Result<ModelListResponse, Error> {
+        let response = self.http.get(self.url("/v1/models")).send().await?;
+        json(response).await
+    }
+
+    /// One chat model with its context length, size, capabilities and load state.
+    pub async fn model(&self, model: &str) -> Result<ModelDetail, Error> {
+        let response = self
+            .http
+            .get(self.url(&format!("/v1/models/{model}")))
+            .send()
+            .await?;
+        json(response).await
+    }
+
+    /// Have the server load `model` and run a short generation, so the first real request
+    /// doesn't wait for the weights.
+    pub async fn warmup_model(&self, model: &str) -> Result<ModelWarmupResponse, Error> {
+        let url = self.url(&format!("/v1/models/{model}/warmup"));
+        let response = self.http.post(url).send().await?;
+        json(response).await
+    }
+
+    /// Have the server load `model`'s weights, if they aren't already, and describe it.
+    pub async fn load_model(&self, model: &str) -> Result<ModelDetail, Error> {
+        let url = self.url(&format!("/v1/models/{model}/load"));
+        let response = self.http.post(url).send().await?;
+        json(response).await
+    }
+
+    /// Have the server drop `model`'s weights to free their memory. `deleted` is false when
+    /// it wasn't loaded.
+    pub async fn unload_model(&self, model: &str) -> Result<ModelDeleteResponse, Error> {
+        let url = self.url(&format!("/v1/models/{model}"));
+        let response = self.http.delete(url).send().await?;
+        json(response).await
+    }
+
+    /// Complete `request` in one response.
+    pub async fn chat(
+        &self,
+        request: &ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse, Error> {
+        let response = self.send_chat(request, false).await?;
+        json(response).await
+    }
+
+    /// Stream the completion of `request` chunk by chunk. Dropping the stream closes the
+    /// connection, which stops the generation.
+    pub async fn chat_stream(&self, request: &ChatCompletionRequest) -> Result<ChatStream, Error> {
+        let response = self.send_chat(request, true).await?;
+        Ok(stream::chat_stream(check(response).await?.bytes_stream()))
+    }
+
+    /// Stop the streaming completion `id`, the id of its chunks, while leaving its
+    /// connection open: the stream ends with a `cancelled` finish reason.
+    pub async fn cancel_chat(&self, id: &str) -> Result<ChatCompletionCancelResponse, Error> {
+        let url = self.url(&format!("/v1/chat/completions/{id}/cancel"));
+        let response = self.http.post(url).send().await?;
+        json(response).await
+    }
+
+    /// Embed the texts of `request`.
+    pub async fn embeddings(&self, request: &EmbeddingRequest) -> Result<EmbeddingList, Error> {
+        let response = self
+            .http
+            .post(self.url("/v1/embeddings"))
+            .json(request)
+            .send()
+            .await?;
+        json(response).await
+    }
+
+    /// The CPU and GPUs the server can run models on.
+    pub async fn device(&self) -> Result<DeviceReport, Error> {
+        let response = self.http.get(self.url("/admin/device")).send().await?;
+        json(response).await
+    }
+
+    /// Send `request` with `stream` set, whatever the caller left in it.
+    async fn send_chat(
+        &self,
+        request: &ChatCompletionRequest,
+        stream: bool,
+    ) -> Result<Response, Error> {
+        let body = ChatCompletionRequest {
+            stream: Some(stream),
+            ..request.clone()
+        };
+        let url = self.url("/v1/chat/completions");
+        Ok(self.http.post(url).json(&body).send().await?)
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+}
+
+async fn json<T: DeserializeOwned>(response: Response) -> Result<T, Error> {
+    let body = check(response).await?.bytes().await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// The response, or the server's error message when the request failed. The engines
+/// answer with OpenAI's `{"error": {"message"}}`, except for some plain-text errors.
+async fn check(response: Response) -> Result<Response, Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    let message = match serde_json::from_str::<Value>(&body) {
+        Ok(value) => value["error"]["message"].as_str().map(str::to_string),
+        Err(_) => Some(body.trim().to_string()).filter(|body| !body.is_empty()),
+    };
+    Err(Error::Status {
+        status: status.as_u16(),
+        message: message.unwrap_or_else(|| format!("request failed with status {status}")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedding_input() {
+        let single = EmbeddingRequest::new("m", "a");
+        let batch = EmbeddingRequest::new("m", vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(serde_json::to_value(&single).unwrap()["input"], "a");
+        assert_eq!(
+            serde_json::to_value(&batch).unwrap()["input"],
+            serde_json::json!(["a", "b"])
+        );
+    }
+}
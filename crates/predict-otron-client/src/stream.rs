@@ -0,0 +1,167 @@
+//! Decoding of the `text/event-stream` a streaming chat completion is sent as.
+
+use bytes::Bytes;
+use futures_util::stream::{self, BoxStream, Stream, StreamExt};
+use serde_json::Value;
+
+use crate::{ChatCompletionChunk, Error};
+
+/// The chunks of a streaming chat completion, ending after the server's `[DONE]`.
+pub type ChatStream = BoxStream<'static, Result<ChatCompletionChunk, Error>>;
+
+/// Turn the body of a streaming response into its chunks. The stream ends at `[DONE]` or
+/// after the first error.
+pub(crate) fn chat_stream(
+    body: impl Stream<Item = reqwest::Result<Bytes>> + Send + 'static,
+) -> ChatStream {
+    let state = (body.boxed(), SseDecoder::default(), false);
+    stream::unfold(state, |(mut body, mut decoder, done)| async move {
+        if done {
+            return None;
+        }
+        loop {
+            if let Some(data) = decoder.next_data() {
+                let item = match parse_chunk(&data) {
+                    Some(Ok(chunk)) => Ok(chunk),
+                    Some(Err(e)) => Err(e),
+                    None => return None,
+                };
+                let done = item.is_err();
+                return Some((item, (body, decoder, done)));
+            }
+            match body.next().await {
+                Some(Ok(bytes)) => decoder.push(&bytes),
+                Some(Err(e)) => return Some((Err(e.into()), (body, decoder, true))),
+                // A body that ends without `[DONE]` still ends the stream
+                None => return None,
+            }
+        }
+    })
+    .boxed()
+}
+
+/// The chunk in an event's data, an error the server reported in its place, or `None` at
+/// `[DONE]`.
+fn parse_chunk(data: &str) -> Option<Result<ChatCompletionChunk, Error>> {
+    if data == "[DONE]" {
+        return None;
+    }
+    let value: Value = match serde_json::from_str(data) {
+        Ok(value) => value,
+        Err(e) => return Some(Err(e.into())),
+    };
+    if let Some(message) = value["error"]["message"].as_str() {
+        return Some(Err(Error::Stream(message.to_string())));
+    }
+    Some(serde_json::from_value(value).map_err(Error::from))
+}
+
+/// Splits server-sent events out of the bytes received so far. Only unnamed events are
+/// kept: the server's named ones, like `prefill` progress, are not part of the completion.
+#[derive(Default)]
+struct SseDecoder {
+    buffer: Vec<u8>,
+}
+
+impl SseDecoder {
+    fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// The data of the next complete unnamed event, if one has arrived.
+    fn next_data(&mut self) -> Option<String> {
+        while let Some((end, separator)) = find_event_end(&self.buffer) {
+            let event: Vec<u8> = self.buffer.drain(..end + separator).collect();
+            let event = String::from_utf8_lossy(&event[..end]);
+            let mut name = None;
+            let mut data: Vec<&str> = Vec::new();
+            for line in event.lines() {
+                let (field, value) = line.split_once(':').unwrap_or((line, ""));
+                let value = value.strip_prefix(' ').unwrap_or(value);
+                match field {
+                    "event" => name = Some(value),
+                    "data" => data.push(value),
+                    _ => {}
+                }
+            }
+            if name.is_none_or(|name| name == "message") && !data.is_empty() {
+                return Some(data.join("\n"));
+            }
+        }
+        None
+    }
+}
+
+/// Where the first event in `buffer` ends, and the length of the blank line ending it.
+fn find_event_end(buffer: &[u8]) -> Option<(usize, usize)> {
+    (0..buffer.len()).find_map(|i| {
+        if buffer[i..].starts_with(b"\n\n") {
+            Some((i, 2))
+        } else if buffer[i..].starts_with(b"\r\n\r\n") {
+            Some((i, 4))
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(parts: &[&str]) -> impl Stream<Item = reqwest::Result<Bytes>> + use<> {
+        let parts: Vec<_> = parts
+            .iter()
+            .map(|part| Ok(Bytes::from(part.to_string())))
+            .collect();
+        stream::iter(parts)
+    }
+
+    fn chunk(content: &str) -> String {
+        format!(
+            r#"data: {{"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"m","choices":[{{"index":0,"delta":{{"content":"{content}"}},"finish_reason":null}}]}}"#
+        )
+    }
+
+    #[tokio::test]
+    async fn test_chunks_split_across_reads() {
+        let events = format!("{}\n\n{}\n\ndata: [DONE]\n\n", chunk("Hel"), chunk("lo"));
+        let (first, rest) = events.split_at(17);
+        let (second, third) = rest.split_at(rest.len() / 2);
+
+        let chunks: Vec<_> = chat_stream(body(&[first, second, third]))
+            .collect::<Vec<_>>()
+            .await;
+        let contents: Vec<_> = chunks
+            .iter()
+            .map(|chunk| chunk.as_ref().unwrap().content().unwrap().to_string())
+            .collect();
+        assert_eq!(contents, ["Hel", "lo"]);
+    }
+
+    #[tokio::test]
+    async fn test_named_events_skipped() {
+        let events = format!(
+            "event: prefill\ndata: {{\"processed\":4,\"total\":8}}\n\n{}\r\n\r\ndata: [DONE]\n\n",
+            chunk("Hi")
+        );
+
+        let chunks: Vec<_> = chat_stream(body(&[&events])).collect::<Vec<_>>().await;
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].as_ref().unwrap().content(), Some("Hi"));
+    }
+
+    #[tokio::test]
+    async fn test_error_event_ends_stream() {
+        let events = format!(
+            "{}\n\ndata: {{\"error\":{{\"message\":\"model crashed\",\"type\":\"server_error\"}}}}\n\n{}\n\n",
+            chunk("a"),
+            chunk("b")
+        );
+
+        let chunks: Vec<_> = chat_stream(body(&[&events])).collect::<Vec<_>>().await;
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks[0].is_ok());
+        assert!(matches!(&chunks[1], Err(Error::Stream(message)) if message == "model crashed"));
+    }
+}
@@ -0,0 +1,124 @@
+//! Request and response bodies of the API. The chat and model types are the server's own,
+//! from `openai-protocol`; the embeddings and admin ones mirror the schemas in
+//! `embeddings-engine` and `inference-engine` without depending on either.
+
+use serde::{Deserialize, Serialize};
+
+pub use openai_protocol::{
+    ChatCompletionCancelResponse, ChatCompletionChoice, ChatCompletionChunk,
+    ChatCompletionChunkChoice, ChatCompletionRequest, ChatCompletionResponse, Delta, ErrorDetail,
+    ErrorResponse, FunctionCall, FunctionCallDelta, FunctionDefinition, FunctionName, Message,
+    MessageContent, Model, ModelDeleteResponse, ModelDetail, ModelListResponse, ModelReadiness,
+    ModelWarmupResponse, NamedToolChoice, ReadinessResponse, StreamOptions, Tool, ToolCall,
+    ToolCallDelta, ToolChoice, Usage,
+};
+
+/// Body of `POST /v1/embeddings`.
+#[derive(Debug, Clone, Serialize)]
+pub struct EmbeddingRequest {
+    pub model: String,
+    pub input: EmbeddingInput,
+}
+
+impl EmbeddingRequest {
+    pub fn new(model: impl Into<String>, input: impl Into<EmbeddingInput>) -> Self {
+        Self {
+            model: model.into(),
+            input: input.into(),
+        }
+    }
+}
+
+/// A text, or several texts to embed in one request.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum EmbeddingInput {
+    Single(String),
+    Batch(Vec<String>),
+}
+
+impl From<&str> for EmbeddingInput {
+    fn from(text: &str) -> Self {
+        Self::Single(text.to_string())
+    }
+}
+
+impl From<String> for EmbeddingInput {
+    fn from(text: String) -> Self {
+        Self::Single(text)
+    }
+}
+
+impl From<Vec<String>> for EmbeddingInput {
+    fn from(texts: Vec<String>) -> Self {
+        Self::Batch(texts)
+    }
+}
+
+/// Response of `POST /v1/embeddings`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddingList {
+    pub object: String,
+    pub data: Vec<Embedding>,
+    pub model: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Embedding {
+    pub object: String,
+    /// Position of the text in the request's input.
+    pub index: usize,
+    pub embedding: Vec<f32>,
+}
+
+/// Response of `GET /admin/device`: what the server can run models on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceReport {
+    pub cpu: CpuReport,
+    pub cuda: AcceleratorReport,
+    pub metal: AcceleratorReport,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CpuReport {
+    pub physical_cores: usize,
+    pub logical_cores: usize,
+    /// Thread pool size, once a runner has configured it.
+    pub threads: Option<CpuThreads>,
+    pub features: CpuFeatures,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CpuThreads {
+    pub threads: usize,
+    /// "config", "env" or "default"
+    pub source: String,
+}
+
+/// SIMD extensions the server was compiled to use.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CpuFeatures {
+    pub avx: bool,
+    pub neon: bool,
+    pub simd128: bool,
+    pub f16c: bool,
+}
+
+/// Support for one GPU backend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcceleratorReport {
+    /// Whether the server was built with this backend.
+    pub compiled: bool,
+    pub devices: Vec<GpuReport>,
+    /// Why probing failed, if it did.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GpuReport {
+    pub ordinal: usize,
+    pub name: String,
+    /// Device memory in bytes.
+    pub total_memory: u64,
+    pub free_memory: Option<u64>,
+}
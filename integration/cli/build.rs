@@ -7,16 +7,65 @@ use std::thread;
 use std::time::{Duration, SystemTime};
 mod bun_target;
 use bun_target::BunTarget;
+#[path = "src/command.rs"]
+mod command;
 
 fn main() {
     println!("cargo:rerun-if-changed=");
+    println!("cargo:rerun-if-env-changed=PREDICT_OTRON_ASSETS_DIR");
 
+    if let Err(e) = generate_assets() {
+        println!("cargo:warning=build.rs failed to generate completions and man pages: {e}");
+        std::process::exit(1);
+    }
+
+    // Only the embedded client needs Bun; the native one is plain Rust.
+    if env::var_os("CARGO_FEATURE_EMBEDDED_CLIENT").is_none() {
+        return;
+    }
     if let Err(e) = run_build() {
         println!("cargo:warning=build.rs failed: {e}");
         std::process::exit(1);
     }
 }
 
+/// Write bash, zsh and fish completions and the man pages for the clap definition in
+/// `src/command.rs` to `$PREDICT_OTRON_ASSETS_DIR`, or `$OUT_DIR/assets` when it isn't set.
+fn generate_assets() -> io::Result<()> {
+    let assets_dir = match env::var_os("PREDICT_OTRON_ASSETS_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR not set by Cargo")).join("assets")
+        }
+    };
+
+    let completions_dir = assets_dir.join("completions");
+    fs::create_dir_all(&completions_dir)?;
+    for shell in [
+        clap_complete::Shell::Bash,
+        clap_complete::Shell::Zsh,
+        clap_complete::Shell::Fish,
+    ] {
+        clap_complete::generate_to(
+            shell,
+            &mut command::build(),
+            "predict-otron",
+            &completions_dir,
+        )?;
+    }
+
+    // One page for the command and one per subcommand, e.g. `predict-otron-chat.1`
+    let man_dir = assets_dir.join("man");
+    fs::create_dir_all(&man_dir)?;
+    clap_mangen::generate_to(command::build(), &man_dir)?;
+
+    info(&format!(
+        "Generated completions and man pages in {}",
+        assets_dir.display()
+    ));
+    Ok(())
+}
+
 fn run_build() -> io::Result<()> {
     let manifest_dir =
         PathBuf::from(env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR not set"));
@@ -1,6 +1,7 @@
 #!/usr/bin/env bun
 
 import OpenAI from "openai";
+import { createInterface } from "readline/promises";
 import { parseArgs } from "util";
 
 // =====================
@@ -8,6 +9,7 @@ import { parseArgs } from "util";
 // =====================
 const DEFAULT_MODEL = "gemma-3-1b-it";
 const DEFAULT_MAX_TOKENS = 256;
+const SYSTEM_PROMPT = "You are a helpful assistant who responds thoughtfully and concisely.";
 
 // Toggle this to reduce log overhead during timing runs
 const PRINT_CHUNK_DEBUG = false;
@@ -30,21 +32,48 @@ type ChunkStat = {
 
 function printHelp() {
     console.log(`
-./cli [options] [prompt]
+predict-otron [options] [prompt]
+predict-otron chat [--model <model>]
+predict-otron models [--detail] [--json]
+predict-otron models warmup <model>
 
 Simple CLI tool for testing the local OpenAI-compatible API server.
 
+Commands:
+  chat                Start an interactive multi-turn chat; Ctrl+C cancels a reply,
+                      /clear forgets the conversation, /exit (or Ctrl+C at the prompt) quits
+  models              List the server's models with their family, context length and
+                      whether they are loaded; --detail adds the owner, --json prints
+                      the raw response
+  models warmup <id>  Load a model and run a short generation so the first real
+                      request doesn't wait for it
+  complete            Complete the prompt on stdin and print only the completion, for
+                      scripts; --lines takes one prompt per line, --json prints JSON
+                      lines, --raw skips the system prompt (predict-otron binary only)
+  pull <model>        Download a model from the HuggingFace Hub ahead of time; --local-dir
+                      <dir> also copies it into a directory for offline use, --server has
+                      the server fetch and load it instead (predict-otron binary only)
+  tui                 Full-screen chat with a scrollable conversation, a model picker (Tab)
+                      and live tokens/sec (predict-otron binary only)
+  completions <shell> Print a bash, zsh, fish, powershell or elvish completion script
+                      (predict-otron binary only)
+
 Options:
   --model <model>     Model to use (default: gemma-3-1b-it)
   --prompt <prompt>   The prompt to send (can also be provided as positional argument)
   --list-models       List all available models from the server
+  --detail            Show more columns in the models table
+  --json              Print the models response as JSON
   --help              Show this help message
 
 Examples:
-  ./cli "What is the capital of France?"
-  ./cli --model gemma-3-1b-it --prompt "Hello, world!"
-  ./cli --prompt "Who was the 16th president of the United States?"
-  ./cli --list-models
+  predict-otron "What is the capital of France?"
+  predict-otron --model gemma-3-1b-it --prompt "Hello, world!"
+  predict-otron --prompt "Who was the 16th president of the United States?"
+  predict-otron --list-models
+  predict-otron chat --model gemma-3-1b-it
+  predict-otron models --detail
+  predict-otron models warmup gemma-3-1b-it
 
 The server must be running at http://localhost:8080
 `);
@@ -57,6 +86,8 @@ const { values, positionals } = parseArgs({
         prompt: { type: "string" },
         help: { type: "boolean" },
         "list-models": { type: "boolean" },
+        detail: { type: "boolean" },
+        json: { type: "boolean" },
     },
     strict: false,
     allowPositionals: true,
@@ -74,10 +105,7 @@ async function requestLocalOpenAI(model: string, userPrompt: string) {
             max_tokens: DEFAULT_MAX_TOKENS,
             stream: true,
             messages: [
-                {
-                    role: "system",
-                    content: "You are a helpful assistant who responds thoughtfully and concisely.",
-                },
+                { role: "system", content: SYSTEM_PROMPT },
                 { role: "user", content: userPrompt },
             ],
         });
@@ -118,6 +146,145 @@ async function listModels() {
     }
 }
 
+// =====================
+// Models
+// =====================
+type ServerModel = {
+    id: string;
+    owned_by: string;
+    family?: string;
+    context_length?: number;
+    loaded?: boolean;
+};
+
+async function serverRequest(method: "GET" | "POST", path: string) {
+    let response: Response;
+    try {
+        response = await fetch(`http://localhost:8080/v1${path}`, { method });
+    } catch (e: any) {
+        console.error("[HINT] Make sure the server is running at http://localhost:8080");
+        throw e;
+    }
+    const body: any = await response.json().catch(() => null);
+    if (!response.ok) {
+        throw new Error(body?.error?.message ?? `${response.status} ${response.statusText}`);
+    }
+    return body;
+}
+
+function printTable(rows: string[][]) {
+    const widths = rows[0].map((_, column) => Math.max(...rows.map((row) => row[column].length)));
+    for (const row of rows) {
+        console.log(row.map((cell, column) => cell.padEnd(widths[column])).join("  ").trimEnd());
+    }
+}
+
+async function models(detail: boolean, json: boolean) {
+    const list = await serverRequest("GET", "/models");
+    if (json) {
+        console.log(JSON.stringify(list, null, 2));
+        return;
+    }
+
+    // The embeddings engine's models carry no family or load state
+    const rows = (list.data as ServerModel[]).map((model) => {
+        const row = [
+            model.id,
+            model.family ?? "-",
+            model.context_length?.toString() ?? "-",
+            model.loaded === undefined ? "-" : model.loaded ? "yes" : "no",
+        ];
+        return detail ? [...row, model.owned_by] : row;
+    });
+    const header = ["ID", "FAMILY", "CONTEXT", "LOADED"];
+    printTable([detail ? [...header, "OWNED BY"] : header, ...rows]);
+}
+
+async function warmup(model: string) {
+    console.log(`[INFO] Warming up ${model}...`);
+    const result = await serverRequest("POST", `/models/${encodeURIComponent(model)}/warmup`);
+    console.log(
+        `[INFO] ${result.id} is ready: ${result.context_length} token context, ` +
+            `warmed up in ${ms(result.warmup_ms)}`,
+    );
+}
+
+// =====================
+// Interactive chat
+// =====================
+type ChatMessage = { role: "system" | "user" | "assistant"; content: string };
+
+async function chat(model: string) {
+    const openai = new OpenAI({
+        baseURL: "http://localhost:8080/v1",
+        apiKey: "not used",
+    });
+    const rl = createInterface({ input: process.stdin, output: process.stdout });
+    const history: ChatMessage[] = [{ role: "system", content: SYSTEM_PROMPT }];
+
+    // Ctrl+C cancels the reply in progress, or quits when waiting for input
+    let generation: AbortController | null = null;
+    rl.on("SIGINT", () => {
+        if (generation) {
+            generation.abort();
+        } else {
+            rl.close();
+        }
+    });
+    const closed = new Promise<null>((resolve) => rl.once("close", () => resolve(null)));
+
+    console.log(`[INFO] Chatting with ${model} at http://localhost:8080/v1`);
+    console.log("[INFO] /clear forgets the conversation, /exit quits, Ctrl+C cancels a reply");
+
+    while (true) {
+        const input = await Promise.race([rl.question("\n> "), closed]);
+        if (input === null) break;
+        const prompt = input.trim();
+        if (!prompt) continue;
+        if (prompt === "/exit" || prompt === "/quit") break;
+        if (prompt === "/clear") {
+            history.splice(1);
+            console.log("[INFO] Conversation cleared");
+            continue;
+        }
+
+        history.push({ role: "user", content: prompt });
+        generation = new AbortController();
+        let reply = "";
+        try {
+            const stream = await openai.chat.completions.create(
+                { model, max_tokens: DEFAULT_MAX_TOKENS, stream: true, messages: history },
+                { signal: generation.signal },
+            );
+            for await (const chunk of stream) {
+                const content = chunk.choices?.[0]?.delta?.content ?? "";
+                if (content) {
+                    process.stdout.write(content);
+                    reply += content;
+                }
+            }
+            process.stdout.write("\n");
+        } catch (e: any) {
+            if (generation.signal.aborted) {
+                console.log("\n[INFO] Cancelled");
+            } else {
+                console.error("\n[ERROR] Request failed:", e.message);
+                console.error("[HINT] Make sure the server is running at http://localhost:8080");
+            }
+        } finally {
+            generation = null;
+        }
+
+        // Keep what was shown so follow-ups can refer to it; drop turns with no reply
+        if (reply) {
+            history.push({ role: "assistant", content: reply });
+        } else {
+            history.pop();
+        }
+    }
+    rl.close();
+}
+
 // =====================
 // Timing math
 // =====================
@@ -152,6 +319,30 @@ async function main() {
         process.exit(0);
     }
 
+    if (positionals[0] === "chat") {
+        await chat(values.model || DEFAULT_MODEL);
+        process.exit(0);
+    }
+
+    if (positionals[0] === "models") {
+        try {
+            if (positionals[1] === "warmup") {
+                if (!positionals[2]) {
+                    console.error("[ERROR] No model given to warm up!");
+                    printHelp();
+                    process.exit(1);
+                }
+                await warmup(positionals[2]);
+            } else {
+                await models(Boolean(values.detail), Boolean(values.json));
+            }
+            process.exit(0);
+        } catch (error: any) {
+            console.error("[ERROR] Models request failed:", error.message);
+            process.exit(1);
+        }
+    }
+
     if (values["list-models"]) {
         try {
             await listModels();
@@ -0,0 +1,89 @@
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::client::{self, Client, Message, BASE_URL, MAX_TOKENS, SYSTEM_PROMPT};
+
+/// Chat with `model` line by line until `/exit` or end of input. Ctrl+C cancels the reply in
+/// progress, or quits when waiting for input.
+pub fn run(model: &str) -> io::Result<()> {
+    let client = Client::new(BASE_URL)?;
+    let generating = Arc::new(AtomicBool::new(false));
+    let cancel = Arc::new(AtomicBool::new(false));
+    {
+        let generating = generating.clone();
+        let cancel = cancel.clone();
+        ctrlc::set_handler(move || {
+            if generating.load(Ordering::Relaxed) {
+                cancel.store(true, Ordering::Relaxed);
+            } else {
+                println!();
+                process::exit(0);
+            }
+        })
+        .map_err(io::Error::other)?;
+    }
+
+    println!("[INFO] Chatting with {} at {}", model, client.base_url());
+    println!("[INFO] /clear forgets the conversation, /exit quits, Ctrl+C cancels a reply");
+
+    let mut history = vec![Message::system(SYSTEM_PROMPT)];
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout();
+    let mut input = String::new();
+    loop {
+        print!("\n> ");
+        stdout.flush()?;
+        input.clear();
+        if stdin.read_line(&mut input)? == 0 {
+            println!();
+            break;
+        }
+        let prompt = input.trim();
+        if prompt.is_empty() {
+            continue;
+        }
+        if prompt == "/exit" || prompt == "/quit" {
+            break;
+        }
+        if prompt == "/clear" {
+            history.truncate(1);
+            println!("[INFO] Conversation cleared");
+            continue;
+        }
+
+        history.push(Message::user(prompt));
+        cancel.store(false, Ordering::Relaxed);
+        generating.store(true, Ordering::Relaxed);
+        let mut reply = String::new();
+        let result = client.stream_chat(model, MAX_TOKENS, &history, &cancel, |token| {
+            print!("{}", token);
+            let _ = stdout.flush();
+            reply.push_str(token);
+        });
+        generating.store(false, Ordering::Relaxed);
+
+        if cancel.load(Ordering::Relaxed) {
+            println!("\n[INFO] Cancelled");
+        } else {
+            match result {
+                Ok(()) => println!(),
+                Err(e) => {
+                    eprintln!("\n[ERROR] Request failed: {}", e);
+                    if matches!(e, client::Error::Connect(_)) {
+                        eprintln!("[HINT] Make sure the server is running at {}", BASE_URL);
+                    }
+                }
+            }
+        }
+
+        // Keep what was shown so follow-ups can refer to it; drop turns with no reply
+        if reply.is_empty() {
+            history.pop();
+        } else {
+            history.push(Message::assistant(reply));
+        }
+    }
+    Ok(())
+}
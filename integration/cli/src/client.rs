@@ -0,0 +1,120 @@
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures_util::StreamExt;
+use predict_otron_client::ChatCompletionRequest;
+pub use predict_otron_client::{
+    ChatCompletionResponse, Error, Message, MessageContent, ModelDeleteResponse, ModelDetail,
+    ModelListResponse, ModelWarmupResponse,
+};
+use tokio::runtime::Runtime;
+
+pub const BASE_URL: &str = predict_otron_client::DEFAULT_BASE_URL;
+pub const DEFAULT_MODEL: &str = "gemma-3-1b-it";
+pub const MAX_TOKENS: usize = 256;
+pub const SYSTEM_PROMPT: &str =
+    "You are a helpful assistant who responds thoughtfully and concisely.";
+
+/// Blocking wrapper around [`predict_otron_client::Client`] for the commands, which run on
+/// plain threads. Clones share the connection pool and the runtime driving it.
+#[derive(Clone)]
+pub struct Client {
+    inner: predict_otron_client::Client,
+    runtime: Arc<Runtime>,
+}
+
+impl Client {
+    pub fn new(base_url: &str) -> io::Result<Self> {
+        // One worker drives the requests, whichever thread is waiting on them, so the TUI
+        // can stream a reply while listing models.
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            inner: predict_otron_client::Client::new(base_url)?,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    pub fn base_url(&self) -> &str {
+        self.inner.base_url()
+    }
+
+    /// The server's model list, with each model's family, context length and whether it is
+    /// loaded.
+    pub fn models(&self) -> Result<ModelListResponse, Error> {
+        self.block_on(self.inner.models())
+    }
+
+    /// Ids of the models that can chat.
+    pub fn chat_models(&self) -> Result<Vec<String>, Error> {
+        Ok(self
+            .models()?
+            .data
+            .into_iter()
+            .filter(|model| model.is_chat())
+            .map(|model| model.id)
+            .collect())
+    }
+
+    /// Have the server load `model` and run a short generation.
+    pub fn warmup(&self, model: &str) -> Result<ModelWarmupResponse, Error> {
+        self.block_on(self.inner.warmup_model(model))
+    }
+
+    /// Have the server load `model`'s weights.
+    pub fn load(&self, model: &str) -> Result<ModelDetail, Error> {
+        self.block_on(self.inner.load_model(model))
+    }
+
+    /// Have the server drop `model`'s weights.
+    pub fn unload(&self, model: &str) -> Result<ModelDeleteResponse, Error> {
+        self.block_on(self.inner.unload_model(model))
+    }
+
+    /// Complete `messages` in one response.
+    pub fn chat(
+        &self,
+        model: &str,
+        max_tokens: usize,
+        messages: &[Message],
+    ) -> Result<ChatCompletionResponse, Error> {
+        self.block_on(self.inner.chat(&chat_request(model, max_tokens, messages)))
+    }
+
+    /// Stream a completion of `messages`, calling `on_token` with each piece of content as
+    /// it arrives. Returns early, without an error, once `cancel` is set.
+    pub fn stream_chat(
+        &self,
+        model: &str,
+        max_tokens: usize,
+        messages: &[Message],
+        cancel: &Arc<AtomicBool>,
+        mut on_token: impl FnMut(&str),
+    ) -> Result<(), Error> {
+        let request = chat_request(model, max_tokens, messages);
+        let mut stream = self.block_on(self.inner.chat_stream(&request))?;
+        while let Some(chunk) = self.block_on(stream.next()) {
+            if cancel.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            if let Some(content) = chunk?.content() {
+                on_token(content);
+            }
+        }
+        Ok(())
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+}
+
+fn chat_request(model: &str, max_tokens: usize, messages: &[Message]) -> ChatCompletionRequest {
+    let mut request = ChatCompletionRequest::new(model, messages.to_vec());
+    request.max_tokens = Some(max_tokens);
+    request
+}
@@ -0,0 +1,170 @@
+use clap::{Arg, ArgAction, Command};
+use clap_complete::Shell;
+
+/// The command line of `predict-otron`, also used for shell completions and man pages.
+///
+/// `package/cli.ts`, which the `embedded-client` feature bundles, parses its own arguments;
+/// this mirrors them and has to be kept in sync when either gains an option or command.
+pub fn build() -> Command {
+    Command::new("predict-otron")
+        .version(env!("CARGO_PKG_VERSION"))
+        // Only flags the bundled client understands too; the version is for the man pages.
+        .disable_version_flag(true)
+        .disable_help_subcommand(true)
+        .about("Simple CLI tool for testing the local OpenAI-compatible API server")
+        .after_help("The server must be running at http://localhost:8080")
+        .arg(model_arg())
+        .arg(
+            Arg::new("prompt-option")
+                .long("prompt")
+                .value_name("PROMPT")
+                .help("The prompt to send (can also be provided as positional argument)"),
+        )
+        .arg(
+            Arg::new("list-models")
+                .long("list-models")
+                .action(ArgAction::SetTrue)
+                .help("List all available models from the server"),
+        )
+        .arg(
+            Arg::new("prompt")
+                .value_name("PROMPT")
+                .help("The prompt to send"),
+        )
+        .subcommand(
+            Command::new("chat")
+                .about("Start an interactive multi-turn chat")
+                .long_about(
+                    "Start an interactive multi-turn chat. Ctrl+C cancels a reply, /clear \
+                     forgets the conversation, /exit (or Ctrl+C at the prompt) quits.",
+                )
+                .arg(model_arg()),
+        )
+        .subcommand(
+            Command::new("complete")
+                .about("Complete prompts read from stdin, for scripts")
+                .long_about(
+                    "Complete the prompt read from stdin, or with --lines one prompt per line, \
+                     and write each completion to stdout followed by a newline. Exits with 0 \
+                     on success, 1 when a completion fails, 2 when stdin has no prompt, 3 when \
+                     the server can't be reached and 4 when it rejects the request, e.g. for \
+                     an unknown model.",
+                )
+                .arg(model_arg())
+                .arg(
+                    Arg::new("max-tokens")
+                        .long("max-tokens")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Maximum tokens per completion (default: 256)"),
+                )
+                .arg(
+                    Arg::new("raw")
+                        .long("raw")
+                        .action(ArgAction::SetTrue)
+                        .help("Send each prompt as-is, without the system prompt"),
+                )
+                .arg(
+                    Arg::new("lines")
+                        .long("lines")
+                        .action(ArgAction::SetTrue)
+                        .help("Treat every non-empty line of stdin as its own prompt"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Write one JSON object per completion, with finish reason and usage"),
+                ),
+        )
+        .subcommand(
+            Command::new("models")
+                .about("List the server's models")
+                .long_about(
+                    "List the server's models with their family, context length and whether \
+                     they are loaded.",
+                )
+                .arg(
+                    Arg::new("detail")
+                        .long("detail")
+                        .action(ArgAction::SetTrue)
+                        .help("Show more columns in the models table"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Print the models response as JSON"),
+                )
+                .subcommand(
+                    Command::new("warmup")
+                        .about("Load a model ahead of the first request")
+                        .long_about(
+                            "Load a model and run a short generation so the first real \
+                             request doesn't wait for it.",
+                        )
+                        .arg(Arg::new("model").value_name("MODEL").required(true)),
+                )
+                .subcommand(
+                    Command::new("load")
+                        .about("Load a model's weights on the server")
+                        .arg(Arg::new("model").value_name("MODEL").required(true)),
+                )
+                .subcommand(
+                    Command::new("unload")
+                        .about("Drop a model's weights on the server to free their memory")
+                        .arg(Arg::new("model").value_name("MODEL").required(true)),
+                ),
+        )
+        .subcommand(
+            Command::new("pull")
+                .about("Download a model ahead of time")
+                .long_about(
+                    "Download a model's files from the HuggingFace Hub into the local cache, \
+                     with progress bars, so it can later load without network access. With \
+                     --server, the server downloads and loads it instead.",
+                )
+                .arg(Arg::new("model").value_name("MODEL").required(true))
+                .arg(
+                    Arg::new("local-dir")
+                        .long("local-dir")
+                        .value_name("DIR")
+                        .value_parser(clap::value_parser!(std::path::PathBuf))
+                        .help("Also copy the files into DIR, to load with --model-path"),
+                )
+                .arg(
+                    Arg::new("server")
+                        .long("server")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("local-dir")
+                        .help("Download through the server's warmup endpoint instead"),
+                ),
+        )
+        .subcommand(
+            Command::new("tui")
+                .about("Chat in a full-screen terminal interface")
+                .long_about(
+                    "Chat in a full-screen terminal interface with a scrollable conversation, \
+                     a model picker (Tab) and the reply's tokens per second. Esc or Ctrl+C \
+                     cancels a reply, Ctrl+L clears the conversation, Ctrl+C quits.",
+                )
+                .arg(model_arg()),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Print a completion script for a shell")
+                .arg(
+                    Arg::new("shell")
+                        .value_name("SHELL")
+                        .required(true)
+                        .value_parser(clap::value_parser!(Shell)),
+                ),
+        )
+}
+
+fn model_arg() -> Arg {
+    Arg::new("model")
+        .long("model")
+        .value_name("MODEL")
+        .help("Model to use (default: gemma-3-1b-it)")
+}
@@ -0,0 +1,178 @@
+use std::io::{self, IsTerminal, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use serde_json::json;
+
+use crate::client::{self, Client, Message, BASE_URL, SYSTEM_PROMPT};
+
+/// Every prompt was completed, or stdout was closed early (e.g. `| head`).
+pub const EXIT_OK: i32 = 0;
+/// The server failed a completion, or its output could not be written.
+pub const EXIT_FAILED: i32 = 1;
+/// There was no prompt on stdin. Clap exits with the same code for bad arguments.
+pub const EXIT_USAGE: i32 = 2;
+/// The server could not be reached.
+pub const EXIT_UNREACHABLE: i32 = 3;
+/// The server rejected the request, e.g. for an unknown model.
+pub const EXIT_REJECTED: i32 = 4;
+
+pub struct Options<'a> {
+    pub model: &'a str,
+    pub max_tokens: usize,
+    /// Send each prompt as-is, without the system prompt.
+    pub raw: bool,
+    /// Write one JSON object per completion instead of the text.
+    pub json: bool,
+    /// Treat every non-empty line of stdin as its own prompt.
+    pub lines: bool,
+}
+
+enum Failure {
+    Request(client::Error),
+    Output(io::Error),
+}
+
+/// Complete the prompts on stdin, writing the completions to stdout in order. Stops at the
+/// first failure and returns the exit code for it.
+pub fn run(options: &Options) -> i32 {
+    let prompts = match read_prompts(options.lines) {
+        Ok(prompts) if prompts.is_empty() => {
+            eprintln!("[ERROR] No prompt on stdin");
+            return EXIT_USAGE;
+        }
+        Ok(prompts) => prompts,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to read stdin: {}", e);
+            return EXIT_USAGE;
+        }
+    };
+    let client = match Client::new(BASE_URL) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("[ERROR] {}", e);
+            return EXIT_FAILED;
+        }
+    };
+
+    let mut out = io::stdout().lock();
+    for prompt in &prompts {
+        let result = if options.json {
+            complete_json(&client, options, prompt, &mut out)
+        } else {
+            complete_text(&client, options, prompt, &mut out)
+        };
+        match result {
+            Ok(()) => {}
+            Err(Failure::Output(e)) if e.kind() == io::ErrorKind::BrokenPipe => return EXIT_OK,
+            Err(Failure::Output(e)) => {
+                eprintln!("[ERROR] Failed to write the completion: {}", e);
+                return EXIT_FAILED;
+            }
+            Err(Failure::Request(e)) => {
+                eprintln!("[ERROR] {} (server: {})", e, client.base_url());
+                return exit_code(&e);
+            }
+        }
+    }
+    EXIT_OK
+}
+
+/// The whole of stdin as one prompt, or each of its non-empty lines.
+fn read_prompts(lines: bool) -> io::Result<Vec<String>> {
+    let mut stdin = io::stdin();
+    if stdin.is_terminal() {
+        eprintln!("[INFO] Reading the prompt from stdin; end it with Ctrl+D");
+    }
+    let mut input = String::new();
+    stdin.read_to_string(&mut input)?;
+
+    if lines {
+        return Ok(input
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect());
+    }
+    let input = input.trim();
+    Ok(if input.is_empty() {
+        Vec::new()
+    } else {
+        vec![input.to_string()]
+    })
+}
+
+fn messages(prompt: &str, raw: bool) -> Vec<Message> {
+    let mut messages = Vec::with_capacity(2);
+    if !raw {
+        messages.push(Message::system(SYSTEM_PROMPT));
+    }
+    messages.push(Message::user(prompt));
+    messages
+}
+
+/// Stream the completion to `out` as it arrives, followed by a newline.
+fn complete_text(
+    client: &Client,
+    options: &Options,
+    prompt: &str,
+    out: &mut impl Write,
+) -> Result<(), Failure> {
+    // A closed stdout ends the stream; there is no one left to read the rest.
+    let cancel = Arc::new(AtomicBool::new(false));
+    let mut write_error = None;
+    let result = client.stream_chat(
+        options.model,
+        options.max_tokens,
+        &messages(prompt, options.raw),
+        &cancel,
+        |token| {
+            if let Err(e) = out.write_all(token.as_bytes()).and_then(|_| out.flush()) {
+                write_error.get_or_insert(e);
+                cancel.store(true, Ordering::Relaxed);
+            }
+        },
+    );
+    if let Some(e) = write_error {
+        return Err(Failure::Output(e));
+    }
+    result.map_err(Failure::Request)?;
+    writeln!(out)
+        .and_then(|_| out.flush())
+        .map_err(Failure::Output)
+}
+
+/// Write the completion as a single line of JSON, with the prompt it answers.
+fn complete_json(
+    client: &Client,
+    options: &Options,
+    prompt: &str,
+    out: &mut impl Write,
+) -> Result<(), Failure> {
+    let response = client
+        .chat(
+            options.model,
+            options.max_tokens,
+            &messages(prompt, options.raw),
+        )
+        .map_err(Failure::Request)?;
+    let line = json!({
+        "prompt": prompt,
+        "model": &response.model,
+        "completion": response.content(),
+        "finish_reason": response.choices.first().map(|choice| &choice.finish_reason),
+        "usage": &response.usage,
+    });
+    writeln!(out, "{}", line)
+        .and_then(|_| out.flush())
+        .map_err(Failure::Output)
+}
+
+fn exit_code(error: &client::Error) -> i32 {
+    match error {
+        client::Error::Connect(_) => EXIT_UNREACHABLE,
+        client::Error::Status { status, .. } if (400..500).contains(status) => EXIT_REJECTED,
+        _ => EXIT_FAILED,
+    }
+}
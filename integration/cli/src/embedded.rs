@@ -0,0 +1,42 @@
+use std::ffi::{OsStr, OsString};
+use std::{env, fs, io, process::Command};
+
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+
+/// Commands only the native client implements; the rest go to the bundled client.
+const NATIVE: [&str; 4] = ["completions", "complete", "pull", "tui"];
+
+/// Whether the bundled client runs the command starting with `first_arg`.
+pub fn handles(first_arg: Option<&OsStr>) -> bool {
+    !first_arg.is_some_and(|arg| NATIVE.iter().any(|native| arg == *native))
+}
+
+/// Extract the Bun-compiled `package/cli.ts` to the temp dir and run it with `args`,
+/// returning its exit code.
+pub fn run(args: impl Iterator<Item = OsString>) -> io::Result<i32> {
+    // Absolute path provided by build.rs at compile time.
+    // `include_bytes!` accepts string literals; `env!` expands to a literal at compile time.
+    const CLIENT_CLI: &[u8] = include_bytes!(env!("CLIENT_CLI_BIN"));
+
+    // Write to a temp file
+    let mut tmp = env::temp_dir();
+    tmp.push("client-cli-embedded");
+
+    fs::write(&tmp, CLIENT_CLI)?;
+
+    // Ensure it's executable on Unix
+    #[cfg(unix)]
+    {
+        let mut perms = fs::metadata(&tmp)?.permissions();
+        perms.set_mode(0o755);
+        fs::set_permissions(&tmp, perms)?;
+    }
+
+    // The chat REPL keeps the terminal in raw mode, so Ctrl+C reaches it as a keypress
+    // rather than a signal that would also stop this process.
+    let status = Command::new(&tmp).args(args).status()?;
+    status
+        .code()
+        .ok_or_else(|| io::Error::other("client-cli was terminated by a signal"))
+}
@@ -0,0 +1,89 @@
+//! The `predict-otron` client for the server's OpenAI-compatible API. The binary parses its
+//! arguments with [`command::build`] and hands them to [`run`].
+
+use std::io;
+
+use clap::ArgMatches;
+
+pub mod chat;
+pub mod client;
+pub mod command;
+pub mod complete;
+pub mod models;
+pub mod prompt;
+pub mod pull;
+pub mod tui;
+
+/// Run the command `matches` were parsed from, returning the process's exit code.
+pub fn run(matches: &ArgMatches) -> io::Result<i32> {
+    match matches.subcommand() {
+        Some(("chat", chat)) => chat::run(model(chat))?,
+        Some(("models", models)) => match models.subcommand() {
+            Some(("warmup", warmup)) => {
+                models::warmup(warmup.get_one::<String>("model").expect("required"))?
+            }
+            Some(("load", load)) => {
+                models::load(load.get_one::<String>("model").expect("required"))?
+            }
+            Some(("unload", unload)) => {
+                models::unload(unload.get_one::<String>("model").expect("required"))?
+            }
+            _ => models::list(models.get_flag("detail"), models.get_flag("json"))?,
+        },
+        Some(("complete", complete)) => {
+            return Ok(complete::run(&complete::Options {
+                model: model(complete),
+                max_tokens: complete
+                    .get_one::<usize>("max-tokens")
+                    .copied()
+                    .unwrap_or(client::MAX_TOKENS),
+                raw: complete.get_flag("raw"),
+                json: complete.get_flag("json"),
+                lines: complete.get_flag("lines"),
+            }))
+        }
+        Some(("pull", pull)) => {
+            let model = pull.get_one::<String>("model").expect("required");
+            if pull.get_flag("server") {
+                pull::pull_on_server(model)?
+            } else {
+                pull::pull_locally(
+                    model,
+                    pull.get_one::<std::path::PathBuf>("local-dir")
+                        .map(|dir| dir.as_path()),
+                )?
+            }
+        }
+        Some(("tui", tui)) => tui::run(model(tui).to_string())?,
+        Some(("completions", completions)) => {
+            if let Some(&shell) = completions.get_one::<clap_complete::Shell>("shell") {
+                clap_complete::generate(
+                    shell,
+                    &mut command::build(),
+                    "predict-otron",
+                    &mut io::stdout(),
+                );
+            }
+        }
+        _ if matches.get_flag("list-models") => models::list_verbose()?,
+        _ => {
+            let Some(text) = matches
+                .get_one::<String>("prompt-option")
+                .or_else(|| matches.get_one::<String>("prompt"))
+            else {
+                eprintln!("[ERROR] No prompt provided!");
+                command::build().print_help()?;
+                return Ok(1);
+            };
+            prompt::run(model(matches), text)?
+        }
+    }
+    Ok(0)
+}
+
+/// The `--model` of a command, or the default one.
+fn model(matches: &ArgMatches) -> &str {
+    matches
+        .get_one::<String>("model")
+        .map_or(client::DEFAULT_MODEL, String::as_str)
+}
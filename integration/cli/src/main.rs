@@ -1,32 +1,26 @@
-use std::{env, fs, io, path::PathBuf, process::Command};
+use std::process;
 
-#[cfg(unix)]
-use std::os::unix::fs::PermissionsExt;
+#[cfg(feature = "embedded-client")]
+mod embedded;
 
-fn main() -> io::Result<()> {
-    // Absolute path provided by build.rs at compile time.
-    // `include_bytes!` accepts string literals; `env!` expands to a literal at compile time.
-    const CLIENT_CLI: &[u8] = include_bytes!(env!("CLIENT_CLI_BIN"));
-
-    // Write to a temp file
-    let mut tmp = env::temp_dir();
-    tmp.push("client-cli-embedded");
-
-    fs::write(&tmp, CLIENT_CLI)?;
-
-    // Ensure it's executable on Unix
-    #[cfg(unix)]
-    {
-        let mut perms = fs::metadata(&tmp)?.permissions();
-        perms.set_mode(0o755);
-        fs::set_permissions(&tmp, perms)?;
+fn main() {
+    #[cfg(feature = "embedded-client")]
+    if embedded::handles(std::env::args_os().nth(1).as_deref()) {
+        match embedded::run(std::env::args_os().skip(1)) {
+            Ok(code) => process::exit(code),
+            Err(e) => {
+                eprintln!("[ERROR] {}", e);
+                process::exit(1);
+            }
+        }
     }
 
-    // Run it
-    let status = Command::new(&tmp).arg("--version").status()?;
-    if !status.success() {
-        return Err(io::Error::other("client-cli failed"));
+    let matches = cli::command::build().get_matches();
+    match cli::run(&matches) {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("[ERROR] {}", e);
+            process::exit(1);
+        }
     }
-
-    Ok(())
 }
@@ -0,0 +1,128 @@
+use std::io;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::client::{Client, BASE_URL};
+
+/// Print the server's models as a table with their family, context length and whether they
+/// are loaded, or the whole response as JSON with `json`.
+pub fn list(detail: bool, json: bool) -> io::Result<()> {
+    let list = Client::new(BASE_URL)?.models()?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&list)?);
+        return Ok(());
+    }
+
+    let mut header = vec!["ID", "FAMILY", "CONTEXT", "LOADED"];
+    if detail {
+        header.push("OWNED BY");
+    }
+    let mut rows = vec![header.into_iter().map(str::to_string).collect()];
+    // The embeddings engine's models carry no family or load state
+    for model in &list.data {
+        let mut row = vec![
+            model.id.clone(),
+            text(model.family.as_ref()),
+            text(model.context_length),
+            match model.loaded {
+                Some(true) => "yes".to_string(),
+                Some(false) => "no".to_string(),
+                None => "-".to_string(),
+            },
+        ];
+        if detail {
+            row.push(model.owned_by.clone());
+        }
+        rows.push(row);
+    }
+    print_table(&rows);
+    Ok(())
+}
+
+/// Load `model` on the server and run a short generation so the first real request doesn't
+/// wait for it.
+pub fn warmup(model: &str) -> io::Result<()> {
+    println!("[INFO] Warming up {}...", model);
+    let result = Client::new(BASE_URL)?.warmup(model)?;
+    println!(
+        "[INFO] {} is ready: {} token context, warmed up in {} ms",
+        result.id, result.context_length, result.warmup_ms
+    );
+    Ok(())
+}
+
+/// Load `model`'s weights on the server without generating.
+pub fn load(model: &str) -> io::Result<()> {
+    println!("[INFO] Loading {}...", model);
+    let detail = Client::new(BASE_URL)?.load(model)?;
+    println!(
+        "[INFO] {} is loaded: {} token context{}",
+        detail.id,
+        detail.context_length,
+        detail
+            .device
+            .map_or(String::new(), |device| format!(" on {}", device))
+    );
+    Ok(())
+}
+
+/// Drop `model`'s weights on the server to free their memory.
+pub fn unload(model: &str) -> io::Result<()> {
+    let result = Client::new(BASE_URL)?.unload(model)?;
+    if result.deleted {
+        println!("[INFO] Unloaded {}", result.id);
+    } else {
+        println!("[INFO] {} wasn't loaded", result.id);
+    }
+    Ok(())
+}
+
+/// The `--list-models` listing: each model's id, owner and creation time.
+pub fn list_verbose() -> io::Result<()> {
+    let client = Client::new(BASE_URL)?;
+    let list = client.models()?;
+    println!("[INFO] Available models from {}:", client.base_url());
+    println!("---");
+
+    let models = &list.data;
+    if models.is_empty() {
+        println!("No models found.");
+        return Ok(());
+    }
+    for (index, model) in models.iter().enumerate() {
+        println!("{}. {}", index + 1, model.id);
+        println!("   Owner: {}", model.owned_by);
+        println!(
+            "   Created: {}",
+            humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(model.created))
+        );
+        println!();
+    }
+    println!("Total: {} models available", models.len());
+    Ok(())
+}
+
+/// A table cell, with `-` for a missing value.
+fn text(value: Option<impl ToString>) -> String {
+    value.map_or_else(|| "-".to_string(), |value| value.to_string())
+}
+
+/// Print `rows` in columns padded to their widest cell, the first row being the header.
+pub(crate) fn print_table(rows: &[Vec<String>]) {
+    let columns = rows.first().map_or(0, Vec::len);
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    for row in rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
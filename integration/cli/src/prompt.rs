@@ -0,0 +1,169 @@
+use std::cmp::Reverse;
+use std::io::{self, Write};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use indicatif::ProgressBar;
+
+use crate::client::{Client, Message, BASE_URL, MAX_TOKENS, SYSTEM_PROMPT};
+use crate::models::print_table;
+
+// How many rows to show in the timing tables
+const SHOW_FIRST_N: usize = 3;
+const SHOW_SLOWEST_N: usize = 3;
+
+struct ChunkStat {
+    index: usize,
+    since_request: Duration,
+    since_prev: Duration,
+    chars: usize,
+}
+
+/// Stream the reply to a single prompt, then print how long the request and each chunk
+/// took.
+pub fn run(model: &str, prompt: &str) -> io::Result<()> {
+    let program_start = Instant::now();
+    let client = Client::new(BASE_URL)?;
+    println!("[INFO] Using model: {}", model);
+    println!("[INFO] Prompt: {}", prompt);
+    println!("[INFO] Connecting to: {}", client.base_url());
+    println!("---");
+
+    let spinner = ProgressBar::new_spinner().with_message("Thinking");
+    spinner.enable_steady_tick(Duration::from_millis(80));
+
+    let messages = [Message::system(SYSTEM_PROMPT), Message::user(prompt)];
+    let request_start = Instant::now();
+    let mut stdout = io::stdout();
+    let mut response = String::new();
+    let mut stats: Vec<ChunkStat> = Vec::new();
+    let mut prev_chunk: Option<Instant> = None;
+    let result = client.stream_chat(
+        model,
+        MAX_TOKENS,
+        &messages,
+        &Arc::new(AtomicBool::new(false)),
+        |content| {
+            let now = Instant::now();
+            if prev_chunk.is_none() {
+                spinner.finish_and_clear();
+            }
+            print!("{}", content);
+            let _ = stdout.flush();
+            response.push_str(content);
+            stats.push(ChunkStat {
+                index: stats.len() + 1,
+                since_request: now - request_start,
+                since_prev: prev_chunk.map_or(Duration::ZERO, |prev| now - prev),
+                chars: content.chars().count(),
+            });
+            prev_chunk = Some(now);
+        },
+    );
+    spinner.finish_and_clear();
+    result.map_err(|e| io::Error::other(format!("Request failed: {}", e)))?;
+
+    let stream_end = Instant::now();
+    let total_chars = response.chars().count();
+    println!("\n---");
+    println!(
+        "[INFO] Response completed. Total length: {} characters",
+        total_chars
+    );
+    print_summary(&stats, total_chars, request_start, stream_end);
+
+    println!("\n=== Program Overhead ===");
+    println!(
+        "Total program runtime:      {}",
+        ms(program_start.elapsed())
+    );
+    Ok(())
+}
+
+fn print_summary(stats: &[ChunkStat], total_chars: usize, request_start: Instant, end: Instant) {
+    let first_chunk = stats.first().map(|stat| stat.since_request);
+    // The first chunk has no gap before it
+    let gaps: Vec<Duration> = stats.iter().skip(1).map(|stat| stat.since_prev).collect();
+    let average_gap = if gaps.is_empty() {
+        Duration::ZERO
+    } else {
+        gaps.iter().sum::<Duration>() / gaps.len() as u32
+    };
+
+    println!("\n=== Timing Summary ===");
+    println!(
+        "TTFB (to 1st chunk):        {}",
+        ms(first_chunk.unwrap_or(end - request_start))
+    );
+    println!(
+        "Stream duration:            {}",
+        ms(first_chunk.map_or(Duration::ZERO, |first| end - request_start - first))
+    );
+    println!("End-to-end (req→last):      {}", ms(end - request_start));
+    println!("Chunks:                     {}", stats.len());
+    println!("Total content chars:        {}", total_chars);
+    println!(
+        "Avg chars/chunk:            {:.1}",
+        if stats.is_empty() {
+            0.0
+        } else {
+            total_chars as f64 / stats.len() as f64
+        }
+    );
+    println!("Inter-chunk gap (avg):      {}", ms(average_gap));
+    println!("Inter-chunk gap (median):   {}", ms(quantile(&gaps, 0.5)));
+    println!("Inter-chunk gap (p95):      {}", ms(quantile(&gaps, 0.95)));
+    if let Some(largest) = stats.iter().skip(1).max_by_key(|stat| stat.since_prev) {
+        println!(
+            "Largest gap:                {} (before chunk #{})",
+            ms(largest.since_prev),
+            largest.index
+        );
+    }
+
+    if !stats.is_empty() {
+        println!("\n--- First chunk timings ---");
+        print_chunks(stats.iter().take(SHOW_FIRST_N));
+    }
+    let mut slowest: Vec<&ChunkStat> = stats.iter().skip(1).collect();
+    slowest.sort_by_key(|stat| Reverse(stat.since_prev));
+    if !slowest.is_empty() {
+        println!("\n--- Slowest {} gaps ---", SHOW_SLOWEST_N);
+        print_chunks(slowest.into_iter().take(SHOW_SLOWEST_N));
+    }
+}
+
+fn print_chunks<'a>(stats: impl Iterator<Item = &'a ChunkStat>) {
+    let mut rows = vec![["chunk", "t since request", "dt since prev", "chars"]
+        .map(str::to_string)
+        .to_vec()];
+    rows.extend(stats.map(|stat| {
+        vec![
+            stat.index.to_string(),
+            ms(stat.since_request),
+            ms(stat.since_prev),
+            stat.chars.to_string(),
+        ]
+    }));
+    print_table(&rows);
+}
+
+/// The `q` quantile of `durations`, interpolating between the nearest two.
+fn quantile(durations: &[Duration], q: f64) -> Duration {
+    if durations.is_empty() {
+        return Duration::ZERO;
+    }
+    let mut sorted = durations.to_vec();
+    sorted.sort();
+    let position = (sorted.len() - 1) as f64 * q;
+    let base = position.floor() as usize;
+    match sorted.get(base + 1) {
+        Some(&next) => sorted[base] + (next - sorted[base]).mul_f64(position - base as f64),
+        None => sorted[base],
+    }
+}
+
+fn ms(duration: Duration) -> String {
+    format!("{:.1} ms", duration.as_secs_f64() * 1000.0)
+}
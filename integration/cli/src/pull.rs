@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use runner_core::{DownloadEvent, DownloadProgress};
+
+use crate::client::{Client, BASE_URL};
+
+/// A model id of the API, resolved to the runner that serves it.
+enum RunnerModel {
+    Gemma(gemma_runner::WhichModel),
+    Llama(llama_runner::WhichModel),
+    Model(model_runner::WhichModel),
+}
+
+impl RunnerModel {
+    fn parse(id: &str) -> Option<Self> {
+        id.parse().ok().map(Self::Gemma).or_else(|| {
+            llama_runner::WhichModel::from_str(id, true)
+                .ok()
+                .map(Self::Llama)
+                .or_else(|| {
+                    model_runner::WhichModel::from_str(id, true)
+                        .ok()
+                        .map(Self::Model)
+                })
+        })
+    }
+}
+
+/// Have the server download and load `model` through its warmup endpoint, so the files end
+/// up wherever the server keeps its hub cache.
+pub fn pull_on_server(model: &str) -> io::Result<()> {
+    let client = Client::new(BASE_URL)?;
+    let spinner = ProgressBar::new_spinner().with_message(format!(
+        "Downloading and loading {} on {}",
+        model,
+        client.base_url()
+    ));
+    spinner.enable_steady_tick(Duration::from_millis(80));
+    let result = client.warmup(model);
+    spinner.finish_and_clear();
+
+    let warmup = result.map_err(|e| {
+        io::Error::other(format!(
+            "{} (is the server running at {}?)",
+            e,
+            client.base_url()
+        ))
+    })?;
+    println!(
+        "{} is loaded on the server: {} token context, ready in {} ms",
+        model, warmup.context_length, warmup.warmup_ms
+    );
+    Ok(())
+}
+
+/// Download `model` from the hub into the local cache through its runner crate, without
+/// loading it, and copy the files into `local_dir` when given.
+pub fn pull_locally(model: &str, local_dir: Option<&Path>) -> io::Result<()> {
+    let Some(runner_model) = RunnerModel::parse(model) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unknown model: {}", model),
+        ));
+    };
+
+    let start = Instant::now();
+    let progress = progress_bars();
+    let paths = match runner_model {
+        RunnerModel::Gemma(which) => gemma_runner::download_model(which, Some(progress)),
+        RunnerModel::Llama(which) => llama_runner::download_model(which, Some(progress)),
+        RunnerModel::Model(which) => model_runner::download_model(which, Some(progress)),
+    }
+    .map_err(|e| io::Error::other(format!("Failed to download {}: {:#}", model, e)))?;
+    println!(
+        "Pulled {} ({} files) in {:.1?}",
+        model,
+        paths.len(),
+        start.elapsed()
+    );
+
+    if let Some(dir) = local_dir {
+        copy_files(&paths, dir)?;
+        println!(
+            "Copied {} into {}; load it offline with the runners' --model-path {}",
+            model,
+            dir.display(),
+            dir.display()
+        );
+    }
+    Ok(())
+}
+
+/// One progress bar per file being downloaded. Files already in the cache are listed
+/// without a bar.
+fn progress_bars() -> DownloadProgress {
+    let multi = MultiProgress::new();
+    let style = ProgressStyle::with_template(
+        "{msg:32!} [{bar:32}] {bytes}/{total_bytes} {bytes_per_sec} {eta}",
+    )
+    .expect("valid progress template")
+    .progress_chars("=> ");
+    let bars: Mutex<HashMap<String, ProgressBar>> = Mutex::new(HashMap::new());
+
+    DownloadProgress::new(move |event| {
+        let mut bars = bars.lock().unwrap();
+        match event {
+            DownloadEvent::Started { file, total } => {
+                // Sent again when a failed download is retried.
+                let bar = bars.entry(file.clone()).or_insert_with(|| {
+                    multi.add(
+                        ProgressBar::new(total as u64)
+                            .with_style(style.clone())
+                            .with_message(file),
+                    )
+                });
+                bar.set_length(total as u64);
+                bar.set_position(0);
+            }
+            DownloadEvent::Progress {
+                file, downloaded, ..
+            } => {
+                if let Some(bar) = bars.get(&file) {
+                    bar.set_position(downloaded as u64);
+                }
+            }
+            DownloadEvent::Finished { file } => match bars.get(&file) {
+                Some(bar) => bar.finish(),
+                None => {
+                    let _ = multi.println(format!("{} (cached)", file));
+                }
+            },
+        }
+    })
+}
+
+/// Copy files out of the hub cache, where they are symlinks to content-addressed blobs,
+/// into `dir` under their own names.
+fn copy_files(paths: &[PathBuf], dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for path in paths {
+        let Some(name) = path.file_name() else {
+            continue;
+        };
+        fs::copy(path, dir.join(name)).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "Failed to copy {} to {}: {}",
+                    path.display(),
+                    dir.display(),
+                    e
+                ),
+            )
+        })?;
+    }
+    Ok(())
+}
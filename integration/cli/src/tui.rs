@@ -0,0 +1,408 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::client::{Client, Message, MessageContent, BASE_URL, MAX_TOKENS, SYSTEM_PROMPT};
+
+/// How often the screen is redrawn while tokens stream in.
+const TICK: Duration = Duration::from_millis(50);
+
+/// Run the full-screen chat until the user quits.
+pub fn run(model: String) -> io::Result<()> {
+    let client = Client::new(BASE_URL)?;
+    let mut terminal = ratatui::init();
+    let result = App::new(client, model).run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+enum StreamEvent {
+    Token(String),
+    Done,
+    Failed(String),
+}
+
+/// A reply being streamed on a worker thread.
+struct Generation {
+    events: Receiver<StreamEvent>,
+    cancel: Arc<AtomicBool>,
+}
+
+/// Token counts of the reply in progress, or of the last one.
+struct Throughput {
+    tokens: usize,
+    first_token: Option<Instant>,
+    last_token: Option<Instant>,
+}
+
+impl Throughput {
+    fn tokens_per_second(&self) -> Option<f64> {
+        let (first, last) = (self.first_token?, self.last_token?);
+        let seconds = last.duration_since(first).as_secs_f64();
+        // The first token only starts the clock.
+        (self.tokens > 1 && seconds > 0.0).then(|| (self.tokens - 1) as f64 / seconds)
+    }
+}
+
+struct App {
+    client: Client,
+    model: String,
+    /// The system prompt followed by the turns shown in the conversation pane.
+    history: Vec<Message>,
+    input: String,
+    generation: Option<Generation>,
+    throughput: Option<Throughput>,
+    /// Lines scrolled up from the bottom of the conversation; 0 follows new tokens.
+    scroll: u16,
+    /// Chat models and the highlighted one, while the model picker is open.
+    picker: Option<(Vec<String>, ListState)>,
+    status: Option<String>,
+    quit: bool,
+}
+
+impl App {
+    fn new(client: Client, model: String) -> Self {
+        Self {
+            client,
+            model,
+            history: vec![Message::system(SYSTEM_PROMPT)],
+            input: String::new(),
+            generation: None,
+            throughput: None,
+            scroll: 0,
+            picker: None,
+            status: None,
+            quit: false,
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        while !self.quit {
+            self.receive_tokens();
+            terminal.draw(|frame| self.draw(frame))?;
+            if event::poll(TICK)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        self.on_key(key);
+                    }
+                }
+            }
+        }
+        if let Some(generation) = &self.generation {
+            generation.cancel.store(true, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn on_key(&mut self, key: KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        if self.picker.is_some() {
+            self.on_picker_key(key);
+            return;
+        }
+        match key.code {
+            // Ctrl+C cancels the reply in progress, or quits when waiting for input
+            KeyCode::Char('c') if ctrl => match &self.generation {
+                Some(generation) => generation.cancel.store(true, Ordering::Relaxed),
+                None => self.quit = true,
+            },
+            KeyCode::Char('l') if ctrl => self.clear(),
+            KeyCode::Esc => {
+                if let Some(generation) = &self.generation {
+                    generation.cancel.store(true, Ordering::Relaxed);
+                }
+            }
+            KeyCode::Tab => self.open_picker(),
+            KeyCode::Enter => self.submit(),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Char(c) if !ctrl => self.input.push(c),
+            KeyCode::Up => self.scroll = self.scroll.saturating_add(1),
+            KeyCode::Down => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_add(10),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::End => self.scroll = 0,
+            _ => {}
+        }
+    }
+
+    fn on_picker_key(&mut self, key: KeyEvent) {
+        let Some((models, state)) = &mut self.picker else {
+            return;
+        };
+        match key.code {
+            KeyCode::Up => state.select_previous(),
+            KeyCode::Down => state.select_next(),
+            KeyCode::Enter => {
+                if let Some(model) = state.selected().and_then(|i| models.get(i)) {
+                    self.model = model.clone();
+                    self.status = Some(format!("Switched to {}", self.model));
+                }
+                self.picker = None;
+            }
+            KeyCode::Esc | KeyCode::Tab => self.picker = None,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.picker = None
+            }
+            _ => {}
+        }
+    }
+
+    fn open_picker(&mut self) {
+        if self.generation.is_some() {
+            self.status =
+                Some("Wait for the reply, or cancel it with Esc, to switch models".into());
+            return;
+        }
+        match self.client.chat_models() {
+            Ok(models) if models.is_empty() => {
+                self.status = Some("The server lists no chat models".into())
+            }
+            Ok(models) => {
+                let current = models.iter().position(|model| *model == self.model);
+                let state = ListState::default().with_selected(Some(current.unwrap_or(0)));
+                self.picker = Some((models, state));
+            }
+            Err(e) => self.status = Some(format!("Failed to list models: {}", e)),
+        }
+    }
+
+    fn clear(&mut self) {
+        if self.generation.is_none() {
+            self.history.truncate(1);
+            self.scroll = 0;
+            self.status = Some("Conversation cleared".into());
+        }
+    }
+
+    fn submit(&mut self) {
+        if self.generation.is_some() {
+            return;
+        }
+        let prompt = self.input.trim().to_string();
+        self.input.clear();
+        match prompt.as_str() {
+            "" => return,
+            "/exit" | "/quit" => {
+                self.quit = true;
+                return;
+            }
+            "/clear" => {
+                self.clear();
+                return;
+            }
+            _ => {}
+        }
+
+        self.history.push(Message::user(prompt));
+        let messages = self.history.clone();
+        // Tokens are appended to this turn as they arrive.
+        self.history.push(Message::assistant(String::new()));
+
+        let (tx, events) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (client, model, worker_cancel) =
+            (self.client.clone(), self.model.clone(), cancel.clone());
+        thread::spawn(move || {
+            let result =
+                client.stream_chat(&model, MAX_TOKENS, &messages, &worker_cancel, |token| {
+                    let _ = tx.send(StreamEvent::Token(token.to_string()));
+                });
+            let _ = tx.send(match result {
+                Ok(()) => StreamEvent::Done,
+                Err(e) => StreamEvent::Failed(e.to_string()),
+            });
+        });
+
+        self.generation = Some(Generation { events, cancel });
+        self.throughput = Some(Throughput {
+            tokens: 0,
+            first_token: None,
+            last_token: None,
+        });
+        self.scroll = 0;
+        self.status = None;
+    }
+
+    /// Move streamed tokens into the conversation and finish the turn when the stream ends.
+    fn receive_tokens(&mut self) {
+        let Some(generation) = &self.generation else {
+            return;
+        };
+        let finished = loop {
+            match generation.events.try_recv() {
+                Ok(StreamEvent::Token(token)) => {
+                    if let Some(MessageContent::Text(reply)) = self
+                        .history
+                        .last_mut()
+                        .and_then(|reply| reply.content.as_mut())
+                    {
+                        reply.push_str(&token);
+                    }
+                    if let Some(throughput) = &mut self.throughput {
+                        let now = Instant::now();
+                        throughput.tokens += 1;
+                        throughput.first_token.get_or_insert(now);
+                        throughput.last_token = Some(now);
+                    }
+                }
+                Ok(StreamEvent::Done) => break Some(None),
+                Ok(StreamEvent::Failed(e)) => break Some(Some(e)),
+                Err(TryRecvError::Empty) => break None,
+                Err(TryRecvError::Disconnected) => break Some(None),
+            }
+        };
+        let Some(error) = finished else {
+            return;
+        };
+
+        if generation.cancel.load(Ordering::Relaxed) {
+            self.status = Some("Cancelled".into());
+        } else if let Some(e) = error {
+            self.status = Some(format!(
+                "Request failed: {} (is the server running at {}?)",
+                e,
+                self.client.base_url()
+            ));
+        }
+        self.generation = None;
+        // Keep what was shown so follow-ups can refer to it; drop turns with no reply
+        if self
+            .history
+            .last()
+            .is_some_and(|reply| reply.text().unwrap_or_default().is_empty())
+        {
+            self.history.truncate(self.history.len() - 2);
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [conversation, input, footer] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        self.draw_conversation(frame, conversation);
+
+        let title = if self.generation.is_some() {
+            " Generating… (Esc cancels) "
+        } else {
+            " Message (Enter sends, /clear, /exit) "
+        };
+        let input_block = Block::default().borders(Borders::ALL).title(title);
+        let inner = input_block.inner(input);
+        // Keep the end of a long message, where the cursor is, in view.
+        let typed = self.input.chars().count() as u16;
+        let offset = typed.saturating_sub(inner.width.saturating_sub(1));
+        frame.render_widget(
+            Paragraph::new(self.input.as_str())
+                .scroll((0, offset))
+                .block(input_block),
+            input,
+        );
+        if self.picker.is_none() {
+            frame.set_cursor_position((inner.x + typed - offset, inner.y));
+        }
+
+        frame.render_widget(Paragraph::new(self.footer()), footer);
+
+        if let Some((models, state)) = &mut self.picker {
+            let area = centered(frame.area(), 50, models.len() as u16 + 2);
+            let items: Vec<ListItem> = models
+                .iter()
+                .map(|model| ListItem::new(model.as_str()))
+                .collect();
+            let list = List::new(items)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(" Model (Enter selects, Esc closes) "),
+                )
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+            frame.render_widget(Clear, area);
+            frame.render_stateful_widget(list, area, state);
+        }
+    }
+
+    fn draw_conversation(&mut self, frame: &mut Frame, area: Rect) {
+        let mut text = Text::default();
+        for message in &self.history[1..] {
+            let (name, color) = match message.role.as_str() {
+                "user" => ("you", Color::Cyan),
+                _ => (self.model.as_str(), Color::Green),
+            };
+            text.push_line(Line::from(Span::styled(
+                name,
+                Style::default().fg(color).add_modifier(Modifier::BOLD),
+            )));
+            for line in message.text().unwrap_or_default().lines() {
+                text.push_line(Line::raw(line));
+            }
+            text.push_line(Line::default());
+        }
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(format!(" predict-otron · {} ", self.client.base_url()));
+        let paragraph = Paragraph::new(text).wrap(Wrap { trim: false });
+        let inner = block.inner(area);
+        let lines = paragraph.line_count(inner.width) as u16;
+        let bottom = lines.saturating_sub(inner.height);
+        self.scroll = self.scroll.min(bottom);
+        frame.render_widget(
+            paragraph.scroll((bottom - self.scroll, 0)).block(block),
+            area,
+        );
+    }
+
+    fn footer(&self) -> Line<'_> {
+        let mut spans = vec![Span::styled(
+            format!(" {} ", self.model),
+            Style::default().add_modifier(Modifier::REVERSED),
+        )];
+        if let Some(throughput) = &self.throughput {
+            let rate = match throughput.tokens_per_second() {
+                Some(rate) => format!("{:.1} tok/s", rate),
+                None => "- tok/s".to_string(),
+            };
+            spans.push(Span::raw(format!(
+                " {} tokens · {} ",
+                throughput.tokens, rate
+            )));
+        }
+        let hint = match &self.status {
+            Some(status) => status.clone(),
+            None => "Tab models · PgUp/PgDn scroll · Ctrl+L clear · Ctrl+C quit".to_string(),
+        };
+        spans.push(Span::styled(
+            format!(" {}", hint),
+            Style::default().fg(Color::DarkGray),
+        ));
+        Line::from(spans)
+    }
+}
+
+/// A `width` by `height` rectangle in the middle of `area`, shrunk to fit.
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
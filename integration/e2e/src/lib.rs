@@ -0,0 +1,204 @@
+//! Harness for end-to-end tests of the gateway: [`TestServer`] boots the same app as the
+//! `predict-otron-9000` binary in Standalone mode on a free local port, with runners
+//! loaded through a [`RunnerLoader`] instead of the runner crates, and hands out a
+//! [`Client`] for driving it over real HTTP. [`TestServer::proxying`] boots a
+//! HighAvailability gateway in front of another test server.
+//!
+//! The tests in `tests/` only run with the `integration-tests` feature:
+//!
+//! ```text
+//! cargo test -p e2e --features integration-tests
+//! ```
+
+mod mock;
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::Router;
+use inference_engine::runners::RunnerLoader;
+use inference_engine::AppState;
+use predict_otron_9000::config::{ServerConfig, ServerMode, Services};
+use predict_otron_9000::ha_mode::create_ha_router;
+use predict_otron_9000::hooks::{with_chat_hooks, ChatHooks};
+use predict_otron_9000::middleware::{
+    create_metrics_history_router, create_metrics_reset_router, create_usage_router,
+    with_admin_token, with_request_body_limit, AdminToken, MetricsStore,
+    DEFAULT_MAX_REQUEST_BODY_MB,
+};
+use predict_otron_9000::standalone_mode::create_standalone_router_with_state;
+use predict_otron_9000::{create_api_router, not_found, with_layers};
+use predict_otron_client::Client;
+use runner_core::ModelRunner;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+pub use mock::{MockRunner, MOCK_EMBEDDING_DIMENSIONS, MOCK_REPLY};
+
+/// The gateway, serving on a local port until dropped.
+pub struct TestServer {
+    address: SocketAddr,
+    client: Client,
+    server: JoinHandle<()>,
+}
+
+impl TestServer {
+    /// Boot the gateway with a [`MockRunner`] for every model.
+    pub async fn start() -> io::Result<Self> {
+        Self::with_loader(Arc::new(|which, _| {
+            Ok(Box::new(MockRunner::load(which)?) as Box<dyn ModelRunner>)
+        }))
+        .await
+    }
+
+    /// Boot the gateway with runners from `loader`, e.g. one that fails for some models.
+    pub async fn with_loader(loader: RunnerLoader) -> io::Result<Self> {
+        Self::with_state(AppState {
+            runner_loader: Some(loader),
+            ..AppState::default()
+        })
+        .await
+    }
+
+    /// Boot the gateway over `app_state`, e.g. one with a smaller scheduler.
+    pub async fn with_state(app_state: AppState) -> io::Result<Self> {
+        Self::serve(
+            create_standalone_router_with_state(app_state),
+            ChatHooks::new(),
+        )
+        .await
+    }
+
+    /// Boot the gateway with a [`MockRunner`] for every model and `hooks` on its chat
+    /// completions.
+    pub async fn with_chat_hooks(hooks: ChatHooks) -> io::Result<Self> {
+        let app_state = AppState {
+            runner_loader: Some(Arc::new(|which, _| {
+                Ok(Box::new(MockRunner::load(which)?) as Box<dyn ModelRunner>)
+            })),
+            ..AppState::default()
+        };
+        Self::serve(create_standalone_router_with_state(app_state), hooks).await
+    }
+
+    /// Boot a HighAvailability gateway that proxies both services to `backend_url`, e.g.
+    /// another test server's.
+    pub async fn proxying(backend_url: &str) -> io::Result<Self> {
+        Self::proxying_with_chat_hooks(backend_url, ChatHooks::new()).await
+    }
+
+    /// Boot a HighAvailability gateway that proxies to `services`, e.g. with model routes
+    /// to other test servers.
+    pub async fn proxying_to(services: Services) -> io::Result<Self> {
+        Self::serve(
+            create_ha_router(ServerConfig {
+                server_mode: ServerMode::HighAvailability,
+                services: Some(services),
+                ..ServerConfig::default()
+            }),
+            ChatHooks::new(),
+        )
+        .await
+    }
+
+    /// Like [`TestServer::proxying`], with `hooks` on the gateway's chat completions.
+    pub async fn proxying_with_chat_hooks(backend_url: &str, hooks: ChatHooks) -> io::Result<Self> {
+        let backend_url = backend_url.to_string();
+        Self::serve(
+            create_ha_router(ServerConfig {
+                server_mode: ServerMode::HighAvailability,
+                services: Some(Services {
+                    inference_url: Some(backend_url.clone()),
+                    embeddings_url: Some(backend_url),
+                    ..Services::default()
+                }),
+                ..ServerConfig::default()
+            }),
+            hooks,
+        )
+        .await
+    }
+
+    /// Boot the gateway with a [`MockRunner`] for every model and its admin endpoints behind
+    /// `token`.
+    pub async fn with_admin_token(token: &str) -> io::Result<Self> {
+        let app_state = AppState {
+            runner_loader: Some(Arc::new(|which, _| {
+                Ok(Box::new(MockRunner::load(which)?) as Box<dyn ModelRunner>)
+            })),
+            ..AppState::default()
+        };
+        Self::serve_with(
+            create_standalone_router_with_state(app_state),
+            ChatHooks::new(),
+            AdminToken::new(token),
+        )
+        .await
+    }
+
+    /// Serve `service_router` with the routes and layers the binary adds around it.
+    async fn serve(service_router: Router, hooks: ChatHooks) -> io::Result<Self> {
+        Self::serve_with(service_router, hooks, AdminToken::default()).await
+    }
+
+    /// Like [`TestServer::serve`], with the admin endpoints behind `admin_token`.
+    async fn serve_with(
+        service_router: Router,
+        hooks: ChatHooks,
+        admin_token: AdminToken,
+    ) -> io::Result<Self> {
+        let metrics_store = MetricsStore::new();
+        let app = with_layers(
+            with_request_body_limit(
+                with_admin_token(
+                    with_chat_hooks(
+                        create_api_router(service_router)
+                            .merge(create_metrics_history_router(metrics_store.history()))
+                            .merge(create_metrics_reset_router(metrics_store.clone()))
+                            .merge(create_usage_router(metrics_store.usage()))
+                            .fallback(not_found),
+                        hooks,
+                    ),
+                    admin_token,
+                ),
+                DEFAULT_MAX_REQUEST_BODY_MB * 1024 * 1024,
+            ),
+            metrics_store,
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let server = tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .expect("the gateway stopped serving");
+        });
+        let client = Client::new(&format!("http://{address}"))?;
+        Ok(Self {
+            address,
+            client,
+            server,
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The gateway's root URL.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    /// A client for the gateway's root URL.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
@@ -0,0 +1,123 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use inference_engine::Which;
+use runner_core::{
+    token_channel, FinishReason, GenerationRequest, ModelRunner, RunnerError, RunnerMetadata,
+    TokenEvent, TokenReceiver,
+};
+
+/// What the mock runner generates for every prompt, one token per piece.
+pub const MOCK_REPLY: [&str; 6] = ["Hello", " from", " the", " mock", " runner", "."];
+
+/// Length of the mock embeddings.
+pub const MOCK_EMBEDDING_DIMENSIONS: usize = 8;
+
+/// A runner that replies with [`MOCK_REPLY`] and embeds texts by their bytes, without any
+/// weights, so the gateway can be tested end to end in milliseconds.
+pub struct MockRunner {
+    metadata: RunnerMetadata,
+    reply: Vec<String>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl MockRunner {
+    /// A mock of `which` replying with `reply`, one token per piece, in place of
+    /// [`MOCK_REPLY`].
+    pub fn replying(which: Which, reply: &[&str]) -> Result<Self, RunnerError> {
+        Ok(Self {
+            reply: reply.iter().map(|piece| piece.to_string()).collect(),
+            ..Self::load(which)?
+        })
+    }
+}
+
+impl ModelRunner for MockRunner {
+    type Config = Which;
+
+    fn load(which: Which) -> Result<Self, RunnerError> {
+        Ok(Self {
+            metadata: RunnerMetadata {
+                model_id: which.public_id().to_string(),
+                repo_id: which.meta().id.to_string(),
+                family: which.meta().family.as_str().to_string(),
+                owned_by: which.owned_by().to_string(),
+                context_length: 2048,
+                vocab_size: 256,
+                parameter_count: 0,
+                dtype: "f32".to_string(),
+                device: "cpu".to_string(),
+            },
+            reply: MOCK_REPLY.iter().map(|piece| piece.to_string()).collect(),
+            cancelled: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Report the prompt, one token per byte, as prefilled, then generate the reply up to
+    /// `max_tokens`, on a thread of its own like the real runners.
+    fn generate_stream(&self, request: GenerationRequest) -> Result<TokenReceiver, RunnerError> {
+        if request.prompt.is_empty() {
+            return Err(RunnerError::InvalidRequest("empty prompt".to_string()));
+        }
+        let (tx, rx) = token_channel();
+        let cancelled = self.cancelled.clone();
+        let reply = self.reply.clone();
+        std::thread::spawn(move || {
+            let prompt_tokens = request.prompt.len();
+            for byte in request.prompt.bytes() {
+                tx.send(Ok(TokenEvent::prompt(u32::from(byte))));
+            }
+            tx.send(Ok(TokenEvent::prefill(prompt_tokens, prompt_tokens)));
+            for (id, piece) in reply.iter().take(request.max_tokens).enumerate() {
+                if cancelled.load(Ordering::Relaxed) {
+                    tx.send(Ok(TokenEvent::finished(FinishReason::Cancelled, "")));
+                    return;
+                }
+                if !tx.send(Ok(TokenEvent::generated(id as u32, piece.as_str(), None))) {
+                    return;
+                }
+            }
+            let reason = if request.max_tokens < reply.len() {
+                FinishReason::Length
+            } else {
+                FinishReason::Stop
+            };
+            tx.send(Ok(TokenEvent::finished(reason, "")));
+        });
+        Ok(rx)
+    }
+
+    fn metadata(&self) -> &RunnerMetadata {
+        &self.metadata
+    }
+
+    /// One token per byte, as the prompt is reported while prefilling.
+    fn count_tokens(&self, prompt: &str) -> Option<usize> {
+        Some(prompt.len())
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Each text's bytes summed into [`MOCK_EMBEDDING_DIMENSIONS`] buckets, normalized to
+    /// unit length: equal texts embed equally, different ones almost never do.
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, RunnerError> {
+        Ok(texts
+            .iter()
+            .map(|text| {
+                let mut embedding = [0.0f32; MOCK_EMBEDDING_DIMENSIONS];
+                for (i, byte) in text.bytes().enumerate() {
+                    embedding[i % MOCK_EMBEDDING_DIMENSIONS] += f32::from(byte);
+                }
+                let norm = embedding
+                    .iter()
+                    .map(|x| x * x)
+                    .sum::<f32>()
+                    .sqrt()
+                    .max(1e-6);
+                embedding.iter().map(|x| x / norm).collect()
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,1798 @@
+//! The composed gateway over real HTTP: chat with and without streaming, token usage, tool
+//! calls, generations stopped by a client hanging up, by cancelling them or at their
+//! deadline, chats overflowing the context window, requests turned away by a full queue,
+//! embeddings, request body limits and compression, the model list and details, loading
+//! and unloading models, the usage ledger, the admin endpoints and their token, liveness
+//! and readiness, and how failures reach the client, directly and through the
+//! HighAvailability proxy, streams the proxy relays as they are generated, its retries and
+//! circuit breaker, its routes by model, the trace context it passes on, chat hooks in both
+//! modes, and the warm pool's status. Runners are mocks, so nothing is downloaded, but the
+//! tests bind local ports and are ignored unless the crate is built with the
+//! `integration-tests` feature.
+//!
+//! ```text
+//! cargo test -p e2e --features integration-tests
+//! ```
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use e2e::{MockRunner, TestServer, MOCK_EMBEDDING_DIMENSIONS, MOCK_REPLY};
+use futures_util::StreamExt;
+use inference_engine::warm_pool::{run_warm_pool, WarmPoolConfig};
+use inference_engine::{AppState, InferenceScheduler, Which};
+use openai_protocol::ApiError;
+use predict_otron_9000::aliases::{ModelAliases, ModelVariant};
+use predict_otron_9000::config::{ModelRoute, Services};
+use predict_otron_9000::hooks::{ChatHook, ChatHooks, SystemRules};
+use predict_otron_9000::middleware::DEFAULT_MAX_REQUEST_BODY_MB;
+use predict_otron_client::{
+    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, EmbeddingRequest, Error,
+    ErrorResponse, FunctionDefinition, Message, StreamOptions, Tool,
+};
+use runner_core::{
+    token_channel, GenerationRequest, ModelRunner, RunnerError, RunnerMetadata, TokenEvent,
+    TokenReceiver,
+};
+
+const MODEL: &str = "gemma-3-1b-it";
+/// A Gemma 1 model, whose runner can mean-pool hidden states into embeddings.
+const EMBEDDING_MODEL: &str = "gemma-2b-it-embed";
+
+fn request(max_tokens: usize) -> ChatCompletionRequest {
+    let mut request = ChatCompletionRequest::new(
+        MODEL,
+        vec![
+            Message::system("You are a test."),
+            Message::user("Say hello"),
+        ],
+    );
+    request.max_tokens = Some(max_tokens);
+    request
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_health_and_device_report() {
+    let server = TestServer::start().await.unwrap();
+    server.client().health().await.unwrap();
+
+    let device = server.client().device().await.unwrap();
+    assert!(device.cpu.logical_cores >= device.cpu.physical_cores);
+    assert!(device.cpu.logical_cores > 0);
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_readiness_waits_for_the_default_model() {
+    let backend = TestServer::start().await.unwrap();
+    let proxy = TestServer::proxying(&backend.url()).await.unwrap();
+    backend.client().live().await.unwrap();
+    proxy.client().live().await.unwrap();
+
+    // Nothing loads the default model here, as the binary does at startup
+    let model = AppState::default().model_id;
+    let loading = backend.client().readiness().await.unwrap();
+    assert_eq!(loading.status, "loading");
+    assert_eq!(loading.models.len(), 1);
+    assert_eq!(loading.models[0].id, model);
+    assert!(!loading.models[0].loaded);
+    assert_eq!(proxy.client().readiness().await.unwrap().status, "loading");
+    let response = reqwest::get(format!("{}/health/ready", backend.url()))
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 503);
+
+    backend.client().load_model(&model).await.unwrap();
+    let ready = backend.client().readiness().await.unwrap();
+    assert_eq!(ready.status, "ready");
+    assert!(ready.models[0].loaded);
+    assert_eq!(proxy.client().readiness().await.unwrap().status, "ready");
+
+    // Once ready, unloading the model doesn't take the server out of rotation
+    backend.client().unload_model(&model).await.unwrap();
+    let response = reqwest::get(format!("{}/health/ready", proxy.url()))
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    // A proxy whose services don't answer isn't ready either
+    let closed_port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let stranded = TestServer::proxying(&format!("http://127.0.0.1:{closed_port}"))
+        .await
+        .unwrap();
+    stranded.client().live().await.unwrap();
+    let unreachable = stranded.client().readiness().await.unwrap();
+    assert_eq!(unreachable.status, "unreachable");
+    assert!(unreachable.models.is_empty());
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_model_list_covers_chat_and_embedding_models() {
+    let server = TestServer::start().await.unwrap();
+    let models = server.client().models().await.unwrap();
+    assert_eq!(models.object, "list");
+
+    let gemma = models.data.iter().find(|m| m.id == MODEL).unwrap();
+    assert_eq!(gemma.family.as_deref(), Some("gemma3"));
+    assert!(gemma.is_chat());
+    let embedding = models
+        .data
+        .iter()
+        .find(|m| m.id == EMBEDDING_MODEL)
+        .unwrap();
+    assert!(!embedding.is_chat());
+    // The embeddings engine's own models carry no family
+    assert!(models
+        .data
+        .iter()
+        .any(|m| m.family.is_none() && !m.is_chat()));
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_chat_completion() {
+    let server = TestServer::start().await.unwrap();
+    let completion = server.client().chat(&request(64)).await.unwrap();
+
+    assert_eq!(completion.object, "chat.completion");
+    assert_eq!(completion.model, MODEL);
+    assert_eq!(completion.content(), MOCK_REPLY.concat());
+    assert_eq!(completion.choices[0].message.role, "assistant");
+    assert_eq!(completion.choices[0].finish_reason, "stop");
+    assert!(completion.usage.prompt_tokens > 0);
+
+    // `default` names the server's default model
+    let mut default = request(64);
+    default.model = "default".to_string();
+    let completion = server.client().chat(&default).await.unwrap();
+    assert_eq!(
+        completion.model,
+        inference_engine::AppState::default().model_id
+    );
+    assert_eq!(completion.content(), MOCK_REPLY.concat());
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_chat_streaming() {
+    let server = TestServer::start().await.unwrap();
+    let chunks: Vec<_> = server
+        .client()
+        .chat_stream(&request(64))
+        .await
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+        .await;
+
+    // A role chunk, one chunk per token, then the finish reason
+    assert_eq!(chunks.len(), MOCK_REPLY.len() + 2);
+    assert_eq!(
+        chunks[0].choices[0].delta.role.as_deref(),
+        Some("assistant")
+    );
+    let text: String = chunks.iter().filter_map(|chunk| chunk.content()).collect();
+    assert_eq!(text, MOCK_REPLY.concat());
+    let last = chunks.last().unwrap();
+    assert_eq!(last.choices[0].finish_reason.as_deref(), Some("stop"));
+    assert!(chunks.iter().all(|chunk| chunk.id == chunks[0].id));
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_streaming_stops_at_max_tokens() {
+    let server = TestServer::start().await.unwrap();
+    let chunks: Vec<_> = server
+        .client()
+        .chat_stream(&request(3))
+        .await
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+        .await;
+
+    let text: String = chunks.iter().filter_map(|chunk| chunk.content()).collect();
+    assert_eq!(text, MOCK_REPLY[..3].concat());
+    let last = chunks.last().unwrap();
+    assert_eq!(last.choices[0].finish_reason.as_deref(), Some("length"));
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_usage_counts_the_runner_tokens() {
+    let server = TestServer::start().await.unwrap();
+    let completion = server.client().chat(&request(64)).await.unwrap();
+    let usage = completion.usage;
+    assert_eq!(usage.completion_tokens, MOCK_REPLY.len());
+    assert!(usage.prompt_tokens > 0);
+    assert_eq!(
+        usage.total_tokens,
+        usage.prompt_tokens + usage.completion_tokens
+    );
+
+    // A stream reports the same usage in a last chunk without choices, when asked to
+    let mut streamed = request(64);
+    streamed.stream_options = Some(StreamOptions {
+        include_usage: true,
+    });
+    let chunks: Vec<ChatCompletionChunk> = server
+        .client()
+        .chat_stream(&streamed)
+        .await
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    let last = chunks.last().unwrap();
+    assert!(last.choices.is_empty());
+    assert_eq!(last.usage, Some(usage));
+    assert!(chunks[..chunks.len() - 1]
+        .iter()
+        .all(|chunk| chunk.usage.is_none()));
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_tool_calls_in_both_modes() {
+    // The model answers with a call, split over tokens like a real one would
+    let backend = TestServer::with_loader(Arc::new(|which, _| {
+        let call = [
+            "{\"tool_calls\": [{\"name\": ",
+            "\"get_weather\", \"arguments\": ",
+            "{\"city\": \"Paris\"}}]}",
+        ];
+        Ok(Box::new(MockRunner::replying(which, &call)?) as Box<dyn ModelRunner>)
+    }))
+    .await
+    .unwrap();
+    let proxy = TestServer::proxying(&backend.url()).await.unwrap();
+    let mut with_tools = request(16);
+    with_tools.tools = Some(vec![Tool::function(FunctionDefinition {
+        name: "get_weather".to_string(),
+        description: None,
+        parameters: Some(serde_json::json!({"type": "object"})),
+    })]);
+
+    for server in [&backend, &proxy] {
+        let completion = server.client().chat(&with_tools).await.unwrap();
+        assert_eq!(completion.choices[0].finish_reason, "tool_calls");
+        assert_eq!(completion.choices[0].message.content, None);
+        let calls = completion.tool_calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].function.name, "get_weather");
+        assert_eq!(calls[0].function.arguments, r#"{"city":"Paris"}"#);
+
+        let chunks: Vec<ChatCompletionChunk> = server
+            .client()
+            .chat_stream(&with_tools)
+            .await
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert!(chunks.iter().all(|chunk| chunk.content().is_none()));
+        let streamed: Vec<_> = chunks
+            .iter()
+            .filter_map(|chunk| chunk.choices[0].delta.tool_calls.as_ref())
+            .flatten()
+            .collect();
+        assert_eq!(streamed.len(), 1);
+        assert_eq!(streamed[0].index, 0);
+        let function = streamed[0].function.as_ref().unwrap();
+        assert_eq!(function.name.as_deref(), Some("get_weather"));
+        let last = chunks.last().unwrap();
+        assert_eq!(last.choices[0].finish_reason.as_deref(), Some("tool_calls"));
+    }
+
+    // A reply that isn't a call is still text, in both modes
+    let server = TestServer::start().await.unwrap();
+    let completion = server.client().chat(&with_tools).await.unwrap();
+    assert_eq!(completion.content(), MOCK_REPLY.concat());
+    assert!(completion.tool_calls().is_empty());
+    let text: String = server
+        .client()
+        .chat_stream(&with_tools)
+        .await
+        .unwrap()
+        .filter_map(|chunk| async move { chunk.unwrap().content().map(str::to_string) })
+        .collect()
+        .await;
+    assert_eq!(text, MOCK_REPLY.concat());
+}
+
+/// A runner stuck in a prefill that never reports progress, which tells the test when its
+/// stream's reader goes away.
+struct EndlessPrefill {
+    metadata: RunnerMetadata,
+    stopped: tokio::sync::mpsc::UnboundedSender<()>,
+    /// Sent before the runner stalls, if set.
+    first_token: Option<&'static str>,
+}
+
+impl ModelRunner for EndlessPrefill {
+    type Config = Which;
+
+    fn load(_: Which) -> Result<Self, RunnerError> {
+        unreachable!("built by the test's loader")
+    }
+
+    fn generate_stream(&self, _: GenerationRequest) -> Result<TokenReceiver, RunnerError> {
+        let (tx, rx) = token_channel();
+        let stopped = self.stopped.clone();
+        let first_token = self.first_token;
+        std::thread::spawn(move || {
+            if let Some(text) = first_token {
+                tx.send(Ok(TokenEvent::generated(1, text, None)));
+            }
+            let started = Instant::now();
+            while started.elapsed() < Duration::from_secs(30) {
+                if tx.is_closed() {
+                    let _ = stopped.send(());
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        });
+        Ok(rx)
+    }
+
+    fn metadata(&self) -> &RunnerMetadata {
+        &self.metadata
+    }
+
+    fn cancel(&self) {}
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_a_disconnected_stream_stops_its_generation() {
+    let (stopped_tx, mut stopped) = tokio::sync::mpsc::unbounded_channel();
+    let server = TestServer::with_loader(Arc::new(move |which, _| {
+        Ok(Box::new(EndlessPrefill {
+            metadata: MockRunner::load(which)?.metadata().clone(),
+            stopped: stopped_tx.clone(),
+            first_token: None,
+        }) as Box<dyn ModelRunner>)
+    }))
+    .await
+    .unwrap();
+
+    // The role chunk comes first, then nothing at all from the runner
+    let mut chunks = server.client().chat_stream(&request(8)).await.unwrap();
+    chunks.next().await.unwrap().unwrap();
+    drop(chunks);
+
+    // Hanging up reaches the runner without waiting for its next event
+    tokio::time::timeout(Duration::from_secs(5), stopped.recv())
+        .await
+        .expect("the generation outlived its client")
+        .unwrap();
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_the_proxy_relays_streams_as_they_are_generated() {
+    let (stopped_tx, mut stopped) = tokio::sync::mpsc::unbounded_channel();
+    let server = TestServer::with_loader(Arc::new(move |which, _| {
+        Ok(Box::new(EndlessPrefill {
+            metadata: MockRunner::load(which)?.metadata().clone(),
+            stopped: stopped_tx.clone(),
+            first_token: Some("Hello"),
+        }) as Box<dyn ModelRunner>)
+    }))
+    .await
+    .unwrap();
+    let proxy = TestServer::proxying(&server.url()).await.unwrap();
+
+    // The generation never ends, so only events passed on as they come get through
+    let mut chunks = proxy.client().chat_stream(&request(8)).await.unwrap();
+    let role = tokio::time::timeout(Duration::from_secs(5), chunks.next())
+        .await
+        .expect("the proxy held back the role chunk");
+    role.unwrap().unwrap();
+    let first = tokio::time::timeout(Duration::from_secs(5), chunks.next())
+        .await
+        .expect("the proxy held back the first token");
+    assert_eq!(first.unwrap().unwrap().content(), Some("Hello"));
+
+    // Hanging up on the proxy reaches the runner behind it
+    drop(chunks);
+    tokio::time::timeout(Duration::from_secs(5), stopped.recv())
+        .await
+        .expect("the generation outlived its client")
+        .unwrap();
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_a_cancelled_stream_stops_its_generation() {
+    let (stopped_tx, mut stopped) = tokio::sync::mpsc::unbounded_channel();
+    let server = TestServer::with_loader(Arc::new(move |which, _| {
+        Ok(Box::new(EndlessPrefill {
+            metadata: MockRunner::load(which)?.metadata().clone(),
+            stopped: stopped_tx.clone(),
+            first_token: None,
+        }) as Box<dyn ModelRunner>)
+    }))
+    .await
+    .unwrap();
+    let proxy = TestServer::proxying(&server.url()).await.unwrap();
+
+    let mut chunks = server.client().chat_stream(&request(8)).await.unwrap();
+    let id = chunks.next().await.unwrap().unwrap().id;
+
+    // Cancelled through the proxy, while the stream's own connection stays open
+    let cancel = proxy.client().cancel_chat(&id).await.unwrap();
+    assert_eq!(cancel.id, id);
+    assert!(cancel.cancelled);
+    tokio::time::timeout(Duration::from_secs(5), stopped.recv())
+        .await
+        .expect("the cancelled generation kept running")
+        .unwrap();
+
+    // The stream ends as usual, with the reason it stopped
+    let rest: Vec<_> = chunks.map(Result::unwrap).collect().await;
+    let last = rest.last().unwrap();
+    assert_eq!(last.choices[0].finish_reason.as_deref(), Some("cancelled"));
+
+    // A finished or unknown completion can't be cancelled
+    match server.client().cancel_chat(&id).await {
+        Err(Error::Status { status, .. }) => assert_eq!(status, 404),
+        other => panic!("expected a 404, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_generations_stop_at_their_deadline() {
+    let (stopped_tx, mut stopped) = tokio::sync::mpsc::unbounded_channel();
+    let server = TestServer::with_loader(Arc::new(move |which, _| {
+        Ok(Box::new(EndlessPrefill {
+            metadata: MockRunner::load(which)?.metadata().clone(),
+            stopped: stopped_tx.clone(),
+            // Only the Gemma model gets a token out before it stalls
+            first_token: (which == Which::from_public_id(MODEL).unwrap()).then_some("Hel"),
+        }) as Box<dyn ModelRunner>)
+    }))
+    .await
+    .unwrap();
+    let mut timed = request(8);
+    timed.timeout = Some(0.2);
+
+    // A model that never answers is a 504 once the deadline passes
+    let mut stuck = timed.clone();
+    stuck.model = "llama-3.2-1b-instruct".to_string();
+    match server.client().chat(&stuck).await {
+        Err(Error::Status { status, .. }) => assert_eq!(status, 504),
+        other => panic!("expected a 504, got {other:?}"),
+    }
+    tokio::time::timeout(Duration::from_secs(5), stopped.recv())
+        .await
+        .expect("the generation outlived its deadline")
+        .unwrap();
+
+    // One that stalls partway keeps its text, cut short like at max_tokens
+    let response = server.client().chat(&timed).await.unwrap();
+    assert_eq!(response.choices[0].message.text(), Some("Hel"));
+    assert_eq!(response.choices[0].finish_reason, "length");
+    let chunks: Vec<_> = server
+        .client()
+        .chat_stream(&timed)
+        .await
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    let last = chunks.last().unwrap();
+    assert_eq!(last.choices[0].finish_reason.as_deref(), Some("length"));
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_chats_longer_than_the_context_are_rejected_or_truncated() {
+    let server = TestServer::start().await.unwrap();
+    // The mock's context holds 2048 tokens, one per byte; three exchanges overflow it
+    let long = "word ".repeat(160);
+    let mut messages = vec![Message::system("You are a test.")];
+    for _ in 0..3 {
+        messages.push(Message::user(long.as_str()));
+        messages.push(Message::assistant(long.as_str()));
+    }
+    messages.push(Message::user("Say hello"));
+    let mut chat = ChatCompletionRequest::new(MODEL, messages);
+    chat.max_tokens = Some(8);
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/v1/chat/completions", server.url()))
+        .json(&chat)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+    let error: ErrorResponse = response.json().await.unwrap();
+    assert_eq!(error.error.code.as_deref(), Some("context_length_exceeded"));
+    assert_eq!(error.error.param.as_deref(), Some("messages"));
+
+    // Dropping the oldest exchanges makes it fit, keeping the system message
+    chat.truncation_strategy = Some("drop_oldest".to_string());
+    let completion = server.client().chat(&chat).await.unwrap();
+    assert_eq!(
+        completion.choices[0].message.text(),
+        Some(MOCK_REPLY.concat().as_str())
+    );
+    assert!(completion.usage.prompt_tokens + 8 <= 2048);
+    assert!(completion.usage.prompt_tokens > 2 * long.len());
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_a_full_queue_turns_requests_away() {
+    let (stopped_tx, mut stopped) = tokio::sync::mpsc::unbounded_channel();
+    let server = TestServer::with_state(AppState {
+        runner_loader: Some(Arc::new(move |which, _| {
+            Ok(Box::new(EndlessPrefill {
+                metadata: MockRunner::load(which)?.metadata().clone(),
+                stopped: stopped_tx.clone(),
+                first_token: None,
+            }) as Box<dyn ModelRunner>)
+        })),
+        scheduler: InferenceScheduler::new(1, 0),
+        ..AppState::default()
+    })
+    .await
+    .unwrap();
+
+    // One generation takes the only slot and there's no room to wait for it
+    let mut chunks = server.client().chat_stream(&request(8)).await.unwrap();
+    chunks.next().await.unwrap().unwrap();
+    let response = reqwest::Client::new()
+        .post(format!("{}/v1/chat/completions", server.url()))
+        .json(&request(8))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 429);
+    assert_eq!(response.headers()["retry-after"], "1");
+    let error: ErrorResponse = response.json().await.unwrap();
+    assert_eq!(error.error.kind, "rate_limit_exceeded");
+
+    let status: serde_json::Value = reqwest::get(format!("{}/admin/status", server.url()))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(status["scheduler"]["running"], 1);
+    assert_eq!(status["scheduler"]["rejected"], 1);
+    assert_eq!(status["active_streams"], 1);
+
+    // Hanging up gives the slot back
+    drop(chunks);
+    tokio::time::timeout(Duration::from_secs(5), stopped.recv())
+        .await
+        .unwrap();
+    let status: serde_json::Value = reqwest::get(format!("{}/admin/status", server.url()))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(status["scheduler"]["running"], 0);
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_embeddings_batch() {
+    let server = TestServer::start().await.unwrap();
+    let texts = ["the cat sat", "on the mat", "the cat sat"].map(str::to_string);
+    let response = server
+        .client()
+        .embeddings(&EmbeddingRequest::new(EMBEDDING_MODEL, texts.to_vec()))
+        .await
+        .unwrap();
+
+    assert_eq!(response.model, EMBEDDING_MODEL);
+    assert_eq!(response.data.len(), texts.len());
+    for (index, embedding) in response.data.iter().enumerate() {
+        assert_eq!(embedding.index, index);
+        assert_eq!(embedding.embedding.len(), MOCK_EMBEDDING_DIMENSIONS);
+        let norm: f32 = embedding
+            .embedding
+            .iter()
+            .map(|x| x * x)
+            .sum::<f32>()
+            .sqrt();
+        assert!((norm - 1.0).abs() < 1e-4, "norm {norm}");
+    }
+    assert_eq!(response.data[0].embedding, response.data[2].embedding);
+    assert_ne!(response.data[0].embedding, response.data[1].embedding);
+
+    let single = server
+        .client()
+        .embeddings(&EmbeddingRequest::new(EMBEDDING_MODEL, "on the mat"))
+        .await
+        .unwrap();
+    assert_eq!(single.data.len(), 1);
+    assert_eq!(single.data[0].embedding, response.data[1].embedding);
+
+    // Large batches keep one object per text, in order
+    let texts: Vec<String> = (0..128).map(|i| format!("text number {i}")).collect();
+    let response = server
+        .client()
+        .embeddings(&EmbeddingRequest::new(EMBEDDING_MODEL, texts.clone()))
+        .await
+        .unwrap();
+    assert_eq!(response.data.len(), texts.len());
+    assert!(response
+        .data
+        .iter()
+        .enumerate()
+        .all(|(index, embedding)| embedding.index == index));
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_embeddings_encode_as_base64() {
+    use base64::Engine;
+
+    let server = TestServer::start().await.unwrap();
+    let embed = |encoding_format: &str| {
+        reqwest::Client::new()
+            .post(format!("{}/v1/embeddings", server.url()))
+            .json(&serde_json::json!({
+                "model": EMBEDDING_MODEL,
+                "input": ["the cat sat", "on the mat"],
+                "encoding_format": encoding_format,
+            }))
+            .send()
+    };
+    let floats: serde_json::Value = embed("float").await.unwrap().json().await.unwrap();
+    let encoded: serde_json::Value = embed("base64").await.unwrap().json().await.unwrap();
+
+    for index in 0..2 {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded["data"][index]["embedding"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(bytes.len(), MOCK_EMBEDDING_DIMENSIONS * 4);
+        let decoded: Vec<f32> = bytes
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        let expected: Vec<f32> = floats["data"][index]["embedding"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|value| value.as_f64().unwrap() as f32)
+            .collect();
+        assert_eq!(decoded, expected);
+    }
+
+    let invalid = embed("hex").await.unwrap();
+    assert_error_envelope(invalid, 422).await;
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_embedding_dimensions_are_checked() {
+    let server = TestServer::start().await.unwrap();
+    let embed = |dimensions: usize| {
+        reqwest::Client::new()
+            .post(format!("{}/v1/embeddings", server.url()))
+            .json(&serde_json::json!({
+                "model": EMBEDDING_MODEL,
+                "input": "the cat sat",
+                "dimensions": dimensions,
+            }))
+            .send()
+    };
+
+    // Hidden states keep their full size, which may be asked for
+    let whole: serde_json::Value = embed(MOCK_EMBEDDING_DIMENSIONS)
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        whole["data"][0]["embedding"].as_array().unwrap().len(),
+        MOCK_EMBEDDING_DIMENSIONS
+    );
+    for dimensions in [MOCK_EMBEDDING_DIMENSIONS / 2, MOCK_EMBEDDING_DIMENSIONS + 1] {
+        let error = assert_error_envelope(embed(dimensions).await.unwrap(), 400).await;
+        assert_eq!(error.error.param.as_deref(), Some("dimensions"));
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_request_bodies_are_limited_and_responses_compressed() {
+    let server = TestServer::start().await.unwrap();
+    let proxy = TestServer::proxying(&server.url()).await.unwrap();
+    let http = reqwest::Client::new();
+
+    // Bodies over the limit are turned away before they reach a handler or the proxy
+    let oversized = "x".repeat(DEFAULT_MAX_REQUEST_BODY_MB * 1024 * 1024);
+    for gateway in [&server, &proxy] {
+        let response = http
+            .post(format!("{}/v1/embeddings", gateway.url()))
+            .json(&serde_json::json!({"model": EMBEDDING_MODEL, "input": oversized}))
+            .send()
+            .await
+            .unwrap();
+        let error = assert_error_envelope(response, 413).await;
+        assert_eq!(error.error.code.as_deref(), Some("request_too_large"));
+    }
+
+    // Large batches come back compressed to clients that accept it, in both modes
+    let texts: Vec<String> = (0..64).map(|i| format!("text number {i}")).collect();
+    for gateway in [&server, &proxy] {
+        let response = http
+            .post(format!("{}/v1/embeddings", gateway.url()))
+            .header(reqwest::header::ACCEPT_ENCODING, "gzip")
+            .json(&serde_json::json!({"model": EMBEDDING_MODEL, "input": texts}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(
+            response.headers()[reqwest::header::CONTENT_ENCODING],
+            "gzip"
+        );
+    }
+
+    // Streams are sent as they are, so their events aren't held back
+    let response = http
+        .post(format!("{}/v1/chat/completions", proxy.url()))
+        .header(reqwest::header::ACCEPT_ENCODING, "gzip, br")
+        .json(&serde_json::json!({
+            "model": MODEL,
+            "messages": [{"role": "user", "content": "Hi"}],
+            "stream": true
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(!response
+        .headers()
+        .contains_key(reqwest::header::CONTENT_ENCODING));
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_metrics_history_counts_requests_and_tokens() {
+    let server = TestServer::start().await.unwrap();
+    server.client().chat(&request(64)).await.unwrap();
+    let _: Vec<_> = server
+        .client()
+        .chat_stream(&request(64))
+        .await
+        .unwrap()
+        .collect()
+        .await;
+    server
+        .client()
+        .embeddings(&EmbeddingRequest::new(EMBEDDING_MODEL, "the cat sat"))
+        .await
+        .unwrap();
+
+    // Each request is recorded as its response body is dropped, just after it's sent
+    let url = format!("{}/admin/metrics/history?window=1h", server.url());
+    let mut history = serde_json::Value::Null;
+    for _ in 0..50 {
+        history = reqwest::get(&url).await.unwrap().json().await.unwrap();
+        let requests: u64 = history["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|minute| minute["requests"].as_u64().unwrap())
+            .sum();
+        if requests == 3 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(history["window"], "1h");
+
+    // Summed per model, in case the requests straddle a minute
+    let total = |model: &str, field: &str| -> u64 {
+        history["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|minute| minute["model"] == model)
+            .map(|minute| minute[field].as_u64().unwrap())
+            .sum()
+    };
+    assert_eq!(total(MODEL, "requests"), 2);
+    assert_eq!(total(MODEL, "errors"), 0);
+    // The plain completion reports its usage; the stream counts one token per chunk
+    assert_eq!(
+        total(MODEL, "completion_tokens"),
+        2 * MOCK_REPLY.len() as u64
+    );
+    assert!(total(MODEL, "prompt_tokens") > 0);
+    assert_eq!(total(EMBEDDING_MODEL, "requests"), 1);
+
+    let invalid = reqwest::get(format!(
+        "{}/admin/metrics/history?window=soon",
+        server.url()
+    ))
+    .await
+    .unwrap();
+    let error = assert_error_envelope(invalid, 400).await;
+    assert_eq!(error.error.param.as_deref(), Some("window"));
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_admin_endpoints_take_the_admin_token() {
+    let server = TestServer::with_admin_token("admin-secret").await.unwrap();
+    let http = reqwest::Client::new();
+    let status_url = format!("{}/admin/status", server.url());
+
+    // Neither no token, an API key nor a wrong token opens the admin endpoints
+    let missing = http.get(&status_url).send().await.unwrap();
+    let error = assert_error_envelope(missing, 401).await;
+    assert_eq!(error.error.code.as_deref(), Some("invalid_admin_token"));
+    let wrong = http
+        .get(&status_url)
+        .bearer_auth("sk-not-admin")
+        .send()
+        .await
+        .unwrap();
+    assert_error_envelope(wrong, 401).await;
+    // The API stays open to everyone else
+    server.client().chat(&request(8)).await.unwrap();
+
+    let status: serde_json::Value = http
+        .get(&status_url)
+        .bearer_auth("admin-secret")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(status["object"], "admin.status");
+    assert_eq!(status["active_streams"], 0);
+    assert_eq!(status["scheduler"]["queued"], 0);
+    assert!(status["uptime_secs"].is_u64());
+    assert!(status["memory"]["models"].is_object());
+
+    // The chat above and the status calls have response times to clear
+    let reset_url = format!("{}/admin/metrics/reset", server.url());
+    let denied = http.post(&reset_url).send().await.unwrap();
+    assert_error_envelope(denied, 401).await;
+    let reset: serde_json::Value = http
+        .post(&reset_url)
+        .bearer_auth("admin-secret")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(reset["object"], "admin.metrics.reset");
+    assert!(reset["endpoints"].as_u64().unwrap() >= 1);
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_usage_is_recorded_per_api_key() {
+    let server = TestServer::start().await.unwrap();
+    let http = reqwest::Client::new();
+    for _ in 0..2 {
+        http.post(format!("{}/v1/chat/completions", server.url()))
+            .bearer_auth("sk-team-a")
+            .json(&request(64))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+    server
+        .client()
+        .embeddings(&EmbeddingRequest::new(EMBEDDING_MODEL, "the cat sat"))
+        .await
+        .unwrap();
+    // FastEmbed models count their input with their own tokenizer
+    http.post(format!("{}/v1/embeddings", server.url()))
+        .bearer_auth("sk-team-b")
+        .json(&serde_json::json!({"model": "all-minilm-l6-v2", "input": "on the mat"}))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Each request is recorded as its response body is dropped, just after it's sent
+    let url = format!("{}/v1/usage", server.url());
+    let mut usage = serde_json::Value::Null;
+    for _ in 0..50 {
+        usage = reqwest::get(&url).await.unwrap().json().await.unwrap();
+        let requests: u64 = usage["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|day| day["requests"].as_u64().unwrap())
+            .sum();
+        if requests == 4 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+
+    // Summed per key, in case the requests straddle midnight
+    let total = |key: &str, field: &str| -> u64 {
+        usage["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|day| {
+                day["api_key"]
+                    .as_str()
+                    .is_some_and(|id| id.starts_with(key))
+            })
+            .map(|day| day[field].as_u64().unwrap())
+            .sum()
+    };
+    // Keys are recorded by id, never in full
+    assert!(!usage.to_string().contains("sk-team-a"));
+    assert_eq!(total("key-", "requests"), 3);
+    assert_eq!(
+        total("key-", "completion_tokens"),
+        2 * MOCK_REPLY.len() as u64
+    );
+    assert!(total("key-", "prompt_tokens") > 0);
+    assert_eq!(total("anonymous", "requests"), 1);
+    assert_eq!(total("anonymous", "prompt_tokens"), 0);
+    assert!(total("anonymous", "embedding_tokens") > 0);
+    // Of the keyed requests, only the FastEmbed one embedded anything
+    assert!(total("key-", "embedding_tokens") > 0);
+
+    let key = usage["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find_map(|day| day["api_key"].as_str().filter(|id| id.starts_with("key-")))
+        .unwrap()
+        .to_string();
+    let filtered: serde_json::Value = reqwest::get(format!("{url}?api_key={key}"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(filtered["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|day| day["api_key"] == key.as_str()));
+
+    let invalid = reqwest::get(format!("{url}?start_time=200&end_time=100"))
+        .await
+        .unwrap();
+    let error = assert_error_envelope(invalid, 400).await;
+    assert_eq!(error.error.param.as_deref(), Some("start_time"));
+}
+
+const WATERMARK: &str = " [generated]";
+
+/// Rejects prompts mentioning a secret and watermarks the replies.
+struct Policy;
+
+impl ChatHook for Policy {
+    fn name(&self) -> &str {
+        "policy"
+    }
+
+    fn on_request(&self, request: &mut ChatCompletionRequest) -> Result<(), ApiError> {
+        if request
+            .messages
+            .iter()
+            .any(|message| message.text().is_some_and(|text| text.contains("secret")))
+        {
+            return Err(ApiError::invalid_request("Prompts can't mention secrets")
+                .with_param("messages")
+                .with_code("policy_violation"));
+        }
+        Ok(())
+    }
+
+    fn on_response(&self, _request: &ChatCompletionRequest, response: &mut ChatCompletionResponse) {
+        for choice in &mut response.choices {
+            let text = choice.message.text().unwrap_or_default().to_string();
+            choice.message = Message::assistant(text + WATERMARK);
+        }
+    }
+
+    fn on_chunk(&self, _request: &ChatCompletionRequest, chunk: &mut ChatCompletionChunk) {
+        for choice in &mut chunk.choices {
+            if choice.finish_reason.is_some() {
+                choice.delta.content = Some(WATERMARK.to_string());
+            }
+        }
+    }
+}
+
+fn hooks() -> ChatHooks {
+    ChatHooks::new()
+        .with(SystemRules::new("Answer in English."))
+        .with(Policy)
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_chat_hooks_apply_in_both_modes() {
+    let plain = TestServer::start().await.unwrap();
+    let baseline = plain.client().chat(&request(64)).await.unwrap();
+
+    let standalone = TestServer::with_chat_hooks(hooks()).await.unwrap();
+    let proxy = TestServer::proxying_with_chat_hooks(&plain.url(), hooks())
+        .await
+        .unwrap();
+    for server in [&standalone, &proxy] {
+        let completion = server.client().chat(&request(64)).await.unwrap();
+        assert_eq!(completion.content(), MOCK_REPLY.concat() + WATERMARK);
+        // The rules are one more message in the prompt
+        assert!(completion.usage.prompt_tokens > baseline.usage.prompt_tokens);
+
+        let text: String = server
+            .client()
+            .chat_stream(&request(64))
+            .await
+            .unwrap()
+            .map(Result::unwrap)
+            .filter_map(|chunk| async move { chunk.content().map(str::to_string) })
+            .collect()
+            .await;
+        assert_eq!(text, MOCK_REPLY.concat() + WATERMARK);
+
+        let mut secret = request(64);
+        secret.messages.push(Message::user("Tell me the secret"));
+        let response = reqwest::Client::new()
+            .post(format!("{}/v1/chat/completions", server.url()))
+            .json(&secret)
+            .send()
+            .await
+            .unwrap();
+        let error = assert_error_envelope(response, 400).await;
+        assert_eq!(error.error.code.as_deref(), Some("policy_violation"));
+        assert_eq!(error.error.param.as_deref(), Some("messages"));
+    }
+}
+
+fn split_alias() -> ChatHooks {
+    let variants = [MODEL, "llama-3.2-1b-instruct"].map(|model| ModelVariant {
+        model: model.to_string(),
+        weight: 1,
+    });
+    ChatHooks::new().with(ModelAliases::new(
+        [("ab-test".to_string(), variants.to_vec())].into(),
+    ))
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_model_aliases_split_traffic_in_both_modes() {
+    let backend = TestServer::start().await.unwrap();
+    let standalone = TestServer::with_chat_hooks(split_alias()).await.unwrap();
+    let proxy = TestServer::proxying_with_chat_hooks(&backend.url(), split_alias())
+        .await
+        .unwrap();
+    let http = reqwest::Client::new();
+
+    for server in [&standalone, &proxy] {
+        let mut aliased = request(64);
+        aliased.model = "ab-test".to_string();
+        let mut served = Vec::new();
+        for _ in 0..4 {
+            let response = http
+                .post(format!("{}/v1/chat/completions", server.url()))
+                .json(&aliased)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 200);
+            assert_eq!(response.headers()["x-model-alias"], "ab-test");
+            let variant = response.headers()["x-model-variant"]
+                .to_str()
+                .unwrap()
+                .to_string();
+            let completion: ChatCompletionResponse = response.json().await.unwrap();
+            // The response names the model that served it
+            assert_eq!(completion.model, variant);
+            served.push(variant);
+        }
+        served.sort();
+        assert_eq!(
+            served,
+            [
+                "gemma-3-1b-it",
+                "gemma-3-1b-it",
+                "llama-3.2-1b-instruct",
+                "llama-3.2-1b-instruct"
+            ]
+        );
+
+        // Model names that aren't aliases pass through untagged
+        let response = http
+            .post(format!("{}/v1/chat/completions", server.url()))
+            .json(&request(64))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.headers().get("x-model-alias").is_none());
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_warmup_reports_the_runner() {
+    let server = TestServer::start().await.unwrap();
+    let warmup = server.client().warmup_model(MODEL).await.unwrap();
+    assert_eq!(warmup.id, MODEL);
+    assert_eq!(warmup.object, "model.warmup");
+    assert_eq!(warmup.context_length, 2048);
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_model_detail_follows_the_load_state() {
+    let backend = TestServer::start().await.unwrap();
+    let proxy = TestServer::proxying(&backend.url()).await.unwrap();
+
+    let cold = proxy.client().model(MODEL).await.unwrap();
+    assert_eq!(cold.status, "cold");
+    assert_eq!(cold.capabilities, ["chat"]);
+    assert_eq!(cold.context_length, 32 * 1024);
+    assert_eq!(cold.device, None);
+
+    backend.client().warmup_model(MODEL).await.unwrap();
+    let loaded = proxy.client().model(MODEL).await.unwrap();
+    assert!(loaded.is_loaded());
+    assert_eq!(loaded.context_length, 2048);
+    assert_eq!(loaded.device.as_deref(), Some("cpu"));
+
+    match proxy.client().model("no-such-model").await {
+        Err(Error::Status { status, .. }) => assert_eq!(status, 404),
+        other => panic!("expected a 404, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_models_load_and_unload_on_demand() {
+    let backend = TestServer::start().await.unwrap();
+    let proxy = TestServer::proxying(&backend.url()).await.unwrap();
+
+    let loaded = proxy.client().load_model(MODEL).await.unwrap();
+    assert!(loaded.is_loaded());
+    assert_eq!(loaded.context_length, 2048);
+    assert!(backend.client().model(MODEL).await.unwrap().is_loaded());
+
+    let unloaded = proxy.client().unload_model(MODEL).await.unwrap();
+    assert_eq!(unloaded.id, MODEL);
+    assert!(unloaded.deleted);
+    assert_eq!(backend.client().model(MODEL).await.unwrap().status, "cold");
+
+    // Unloading a cold model is fine, but there was nothing to drop
+    let again = backend.client().unload_model(MODEL).await.unwrap();
+    assert!(!again.deleted);
+
+    match proxy.client().load_model("no-such-model").await {
+        Err(Error::Status { status, .. }) => assert_eq!(status, 400),
+        other => panic!("expected a 400, got {other:?}"),
+    }
+    match proxy.client().unload_model("no-such-model").await {
+        Err(Error::Status { status, .. }) => assert_eq!(status, 404),
+        other => panic!("expected a 404, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_unknown_models_are_rejected() {
+    let server = TestServer::start().await.unwrap();
+    let mut unknown = request(8);
+    unknown.model = "no-such-model".to_string();
+
+    for result in [
+        server.client().chat(&unknown).await.map(drop),
+        server.client().chat_stream(&unknown).await.map(drop),
+    ] {
+        match result {
+            Err(Error::Status { status, message }) => {
+                assert_eq!(status, 400);
+                assert!(message.contains("no-such-model"), "{message}");
+            }
+            other => panic!("expected a 400, got {other:?}"),
+        }
+    }
+
+    // The embeddings engine reports it in the same envelope
+    let embeddings = server
+        .client()
+        .embeddings(&EmbeddingRequest::new("no-such-model", "text"))
+        .await;
+    match embeddings {
+        Err(Error::Status { status, message }) => {
+            assert_eq!(status, 400);
+            assert!(message.contains("no-such-model"), "{message}");
+        }
+        other => panic!("expected a 400, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_runner_failures_map_to_http_status() {
+    // Llama models are gated and Gemma ones run out of memory
+    let server = TestServer::with_loader(Arc::new(|which, _| {
+        if which.is_llama_model() {
+            Err(RunnerError::Gated {
+                repo: which.meta().id.to_string(),
+            })
+        } else if which.public_id() == MODEL {
+            Err(RunnerError::OutOfMemory(anyhow::anyhow!("out of memory")))
+        } else {
+            Ok(Box::new(MockRunner::load(which)?) as Box<dyn ModelRunner>)
+        }
+    }))
+    .await
+    .unwrap();
+
+    let mut gated = request(8);
+    gated.model = "llama-3.2-1b-instruct".to_string();
+    match server.client().chat_stream(&gated).await.map(drop) {
+        Err(Error::Status { status, .. }) => assert_eq!(status, 403),
+        other => panic!("expected a 403, got {other:?}"),
+    }
+    match server.client().chat(&request(8)).await {
+        Err(Error::Status { status, message }) => {
+            assert_eq!(status, 503);
+            assert!(message.contains(MODEL), "{message}");
+        }
+        other => panic!("expected a 503, got {other:?}"),
+    }
+    match server.client().warmup_model(MODEL).await {
+        Err(Error::Status { status, .. }) => assert_eq!(status, 503),
+        other => panic!("expected a 503, got {other:?}"),
+    }
+
+    // Other models are unaffected
+    let mut gemma2 = request(64);
+    gemma2.model = "gemma-2b-it".to_string();
+    let completion = server.client().chat(&gemma2).await.unwrap();
+    assert_eq!(completion.content(), MOCK_REPLY.concat());
+}
+
+/// Every failure, whichever service or layer it comes from, is OpenAI's error body
+/// served as JSON.
+async fn assert_error_envelope(response: reqwest::Response, status: u16) -> ErrorResponse {
+    assert_eq!(response.status().as_u16(), status);
+    assert_eq!(
+        response.headers()[reqwest::header::CONTENT_TYPE],
+        "application/json"
+    );
+    let body: serde_json::Value = response.json().await.unwrap();
+    for key in ["message", "type", "param", "code"] {
+        assert!(body["error"].get(key).is_some(), "no error.{key} in {body}");
+    }
+    serde_json::from_value(body).unwrap()
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_errors_share_one_envelope() {
+    let backend = TestServer::start().await.unwrap();
+    let proxy = TestServer::proxying(&backend.url()).await.unwrap();
+    let http = reqwest::Client::new();
+
+    for server in [&backend, &proxy] {
+        let url = |path: &str| format!("{}{path}", server.url());
+
+        let unknown_model = http
+            .post(url("/v1/chat/completions"))
+            .json(&serde_json::json!({
+                "model": "no-such-model",
+                "messages": [{"role": "user", "content": "Hi"}]
+            }))
+            .send()
+            .await
+            .unwrap();
+        let error = assert_error_envelope(unknown_model, 400).await;
+        assert_eq!(error.error.kind, "model_not_supported");
+        assert_eq!(error.error.param.as_deref(), Some("model"));
+
+        let bad_preset = http
+            .post(url("/v1/chat/completions"))
+            .json(&serde_json::json!({
+                "model": MODEL,
+                "messages": [{"role": "user", "content": "Hi"}],
+                "preset": "wild"
+            }))
+            .send()
+            .await
+            .unwrap();
+        let error = assert_error_envelope(bad_preset, 400).await;
+        assert_eq!(error.error.param.as_deref(), Some("preset"));
+
+        // Bodies axum rejects get the envelope rather than its plain text
+        let not_json = http
+            .post(url("/v1/chat/completions"))
+            .header("content-type", "application/json")
+            .body("{")
+            .send()
+            .await
+            .unwrap();
+        assert_error_envelope(not_json, 400).await;
+        let wrong_shape = http
+            .post(url("/v1/embeddings"))
+            .json(&serde_json::json!({"model": 1}))
+            .send()
+            .await
+            .unwrap();
+        assert_error_envelope(wrong_shape, 422).await;
+
+        let token_ids = http
+            .post(url("/v1/embeddings"))
+            .json(&serde_json::json!({"model": "nomic-embed-text-v1.5", "input": [1, 2, 3]}))
+            .send()
+            .await
+            .unwrap();
+        let error = assert_error_envelope(token_ids, 400).await;
+        assert_eq!(error.error.param.as_deref(), Some("input"));
+
+        let no_route = http.get(url("/v1/nothing")).send().await.unwrap();
+        let error = assert_error_envelope(no_route, 404).await;
+        assert_eq!(error.error.kind, "not_found");
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_proxy_relays_streams_and_unreachable_services() {
+    let backend = TestServer::start().await.unwrap();
+    let proxy = TestServer::proxying(&backend.url()).await.unwrap();
+
+    // Streams keep their content type through the proxy
+    let response = reqwest::Client::new()
+        .post(format!("{}/v1/chat/completions", proxy.url()))
+        .json(&serde_json::json!({
+            "model": MODEL,
+            "messages": [{"role": "user", "content": "Hi"}],
+            "stream": true
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()[reqwest::header::CONTENT_TYPE],
+        "text/event-stream"
+    );
+    let chunks: Vec<_> = proxy
+        .client()
+        .chat_stream(&request(64))
+        .await
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    let text: String = chunks.iter().filter_map(|chunk| chunk.content()).collect();
+    assert_eq!(text, MOCK_REPLY.concat());
+
+    // A service that can't be reached is a 502 in the envelope
+    let closed_port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let stranded = TestServer::proxying(&format!("http://127.0.0.1:{closed_port}"))
+        .await
+        .unwrap();
+    let response = reqwest::get(format!("{}/v1/models", stranded.url()))
+        .await
+        .unwrap();
+    let error = assert_error_envelope(response, 502).await;
+    assert_eq!(error.error.kind, "upstream_error");
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_proxy_retries_and_opens_the_circuit_of_a_failing_service() {
+    use axum::response::IntoResponse;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // A service behind a load balancer that answers 502 while it restarts: the model list
+    // comes back on the third call, chat never does
+    let model_calls = Arc::new(AtomicUsize::new(0));
+    let chat_calls = Arc::new(AtomicUsize::new(0));
+    let restarting = axum::Router::new()
+        .route(
+            "/v1/models",
+            axum::routing::get({
+                let model_calls = model_calls.clone();
+                move || async move {
+                    if model_calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                        (axum::http::StatusCode::BAD_GATEWAY, "no healthy upstream").into_response()
+                    } else {
+                        axum::Json(serde_json::json!({"object": "list", "data": []}))
+                            .into_response()
+                    }
+                }
+            }),
+        )
+        .route(
+            "/v1/chat/completions",
+            axum::routing::post({
+                let chat_calls = chat_calls.clone();
+                move || async move {
+                    chat_calls.fetch_add(1, Ordering::SeqCst);
+                    (axum::http::StatusCode::BAD_GATEWAY, "no healthy upstream")
+                }
+            }),
+        );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let service_url = format!("http://{}", listener.local_addr().unwrap());
+    let service = tokio::spawn(async move { axum::serve(listener, restarting).await.unwrap() });
+    let proxy = TestServer::proxying(&service_url).await.unwrap();
+
+    // Requests that are safe to repeat are retried until the service answers
+    let response = reqwest::get(format!("{}/v1/models", proxy.url()))
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(model_calls.load(Ordering::SeqCst), 3);
+
+    // A stream is sent once, a whole response up to three times
+    let chat = |stream: bool| {
+        reqwest::Client::new()
+            .post(format!("{}/v1/chat/completions", proxy.url()))
+            .json(&serde_json::json!({
+                "model": MODEL,
+                "messages": [{"role": "user", "content": "Hi"}],
+                "stream": stream
+            }))
+            .send()
+    };
+    let error = assert_error_envelope(chat(true).await.unwrap(), 502).await;
+    assert_eq!(error.error.kind, "upstream_error");
+    assert_eq!(chat_calls.load(Ordering::SeqCst), 1);
+    assert_error_envelope(chat(false).await.unwrap(), 502).await;
+    assert_eq!(chat_calls.load(Ordering::SeqCst), 4);
+
+    // The fifth failure in a row opens the circuit, which turns requests to the service
+    // away without calling it
+    let response = chat(false).await.unwrap();
+    assert!(response.headers().contains_key("retry-after"));
+    let error = assert_error_envelope(response, 503).await;
+    assert_eq!(error.error.kind, "upstream_error");
+    assert_eq!(chat_calls.load(Ordering::SeqCst), 5);
+    let response = reqwest::get(format!("{}/v1/models", proxy.url()))
+        .await
+        .unwrap();
+    assert_error_envelope(response, 503).await;
+    assert_eq!(model_calls.load(Ordering::SeqCst), 3);
+
+    service.abort();
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_proxy_routes_requests_by_model() {
+    let default_pool = TestServer::start().await.unwrap();
+    let llama_pool = TestServer::start().await.unwrap();
+    let proxy = TestServer::proxying_to(Services {
+        inference_url: Some(default_pool.url()),
+        embeddings_url: Some(default_pool.url()),
+        model_routes: vec![ModelRoute {
+            model: "llama-*".to_string(),
+            url: llama_pool.url(),
+        }],
+    })
+    .await
+    .unwrap();
+    const LLAMA: &str = "llama-3.2-1b-instruct";
+
+    // Each model is served by its pool
+    let mut llama = request(8);
+    llama.model = LLAMA.to_string();
+    proxy.client().chat(&llama).await.unwrap();
+    proxy.client().chat(&request(8)).await.unwrap();
+    assert!(llama_pool.client().model(LLAMA).await.unwrap().is_loaded());
+    assert!(!default_pool
+        .client()
+        .model(LLAMA)
+        .await
+        .unwrap()
+        .is_loaded());
+    assert!(default_pool
+        .client()
+        .model(MODEL)
+        .await
+        .unwrap()
+        .is_loaded());
+    assert!(!llama_pool.client().model(MODEL).await.unwrap().is_loaded());
+
+    // Model endpoints follow the same routes
+    assert!(proxy.client().model(LLAMA).await.unwrap().is_loaded());
+    proxy.client().unload_model(LLAMA).await.unwrap();
+    assert!(!llama_pool.client().model(LLAMA).await.unwrap().is_loaded());
+
+    // A stream on the routed pool is found to cancel it
+    let (stopped_tx, mut stopped) = tokio::sync::mpsc::unbounded_channel();
+    let endless_pool = TestServer::with_loader(Arc::new(move |which, _| {
+        Ok(Box::new(EndlessPrefill {
+            metadata: MockRunner::load(which)?.metadata().clone(),
+            stopped: stopped_tx.clone(),
+            first_token: Some("Hello"),
+        }) as Box<dyn ModelRunner>)
+    }))
+    .await
+    .unwrap();
+    let proxy = TestServer::proxying_to(Services {
+        inference_url: Some(default_pool.url()),
+        embeddings_url: Some(default_pool.url()),
+        model_routes: vec![ModelRoute {
+            model: "llama-*".to_string(),
+            url: endless_pool.url(),
+        }],
+    })
+    .await
+    .unwrap();
+    let mut chunks = proxy.client().chat_stream(&llama).await.unwrap();
+    let id = chunks.next().await.unwrap().unwrap().id;
+    let cancelled = proxy.client().cancel_chat(&id).await.unwrap();
+    assert!(cancelled.cancelled);
+    tokio::time::timeout(Duration::from_secs(5), stopped.recv())
+        .await
+        .expect("the cancelled generation kept running")
+        .unwrap();
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_proxy_propagates_the_trace_context() {
+    // A service that answers with the trace headers the proxy sent it
+    let echo = axum::Router::new().route(
+        "/v1/models",
+        axum::routing::get(|headers: axum::http::HeaderMap| async move {
+            let header = |name: &str| {
+                headers
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string)
+            };
+            axum::Json(serde_json::json!({
+                "traceparent": header("traceparent"),
+                "request_id": header("x-request-id"),
+            }))
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let echo_url = format!("http://{}", listener.local_addr().unwrap());
+    let echo = tokio::spawn(async move { axum::serve(listener, echo).await.unwrap() });
+    let proxy = TestServer::proxying(&echo_url).await.unwrap();
+
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+    let response = reqwest::Client::new()
+        .get(format!("{}/v1/models", proxy.url()))
+        .header("traceparent", format!("00-{trace_id}-00f067aa0ba902b7-01"))
+        .header("x-request-id", "e2e-trace")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["x-request-id"], "e2e-trace");
+    let received: serde_json::Value = response.json().await.unwrap();
+
+    // Same trace and request id, with the gateway's span as the service's parent
+    let traceparent = received["traceparent"].as_str().unwrap();
+    let fields: Vec<_> = traceparent.split('-').collect();
+    assert_eq!(fields.len(), 4, "{traceparent}");
+    assert_eq!(fields[1], trace_id);
+    assert_ne!(fields[2], "00f067aa0ba902b7");
+    assert_eq!(received["request_id"], "e2e-trace");
+
+    // Without a trace to continue, the gateway starts one and names the request
+    let received: serde_json::Value = reqwest::get(format!("{}/v1/models", proxy.url()))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(received["traceparent"].as_str().is_some());
+    assert!(received["request_id"].as_str().is_some());
+
+    // Errors name the request, whether the proxy or the service behind it failed
+    let backend = TestServer::start().await.unwrap();
+    let proxy = TestServer::proxying(&backend.url()).await.unwrap();
+    for uri in ["/v1/models/no-such-model", "/v1/no-such-route"] {
+        let response = reqwest::Client::new()
+            .get(format!("{}{uri}", proxy.url()))
+            .header("x-request-id", "e2e-error")
+            .send()
+            .await
+            .unwrap();
+        let error = assert_error_envelope(response, 404).await;
+        assert_eq!(
+            error.error.request_id.as_deref(),
+            Some("e2e-error"),
+            "{uri}"
+        );
+    }
+
+    echo.abort();
+}
+
+#[tokio::test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "boots the gateway; enable the integration-tests feature"
+)]
+async fn test_warm_pool_reports_through_admin_status() {
+    const PREDICTED: &str = "llama-3.2-3b-instruct";
+    let server = TestServer::start().await.unwrap();
+    let proxy = TestServer::proxying(&server.url()).await.unwrap();
+
+    // Requests for a model make it a candidate for the pool's free slots
+    let mut predicted = request(4);
+    predicted.model = PREDICTED.to_string();
+    for _ in 0..3 {
+        proxy.client().chat(&predicted).await.unwrap();
+    }
+
+    // Other tests share the request mix, so leave slots for their models too
+    let config = WarmPoolConfig {
+        models: vec![Which::InstructV3_1B],
+        size: 5,
+        ..WarmPoolConfig::default()
+    };
+    let state = AppState {
+        runner_loader: Some(Arc::new(|which, _| {
+            Ok(Box::new(MockRunner::load(which)?) as Box<dyn ModelRunner>)
+        })),
+        ..AppState::default()
+    };
+    let pass = tokio::task::spawn_blocking(move || run_warm_pool(&config, &state))
+        .await
+        .unwrap();
+    assert!(pass.request_mix[PREDICTED] >= 3);
+    assert!(pass.predicted.iter().any(|model| model == PREDICTED));
+
+    for server in [&server, &proxy] {
+        let status: serde_json::Value = reqwest::get(format!("{}/admin/status", server.url()))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(status["object"], "admin.status");
+        // The mock runners aren't cached, so nothing counts as loaded
+        assert!(status["loaded_models"].as_array().unwrap().is_empty());
+        // The chat requests above streamed their tokens through bounded buffers
+        assert!(status["streams"]["streams"].as_u64().unwrap() >= 3);
+        assert!(status["streams"]["buffer"].as_u64().unwrap() > 0);
+
+        let warm_pool = &status["warm_pool"];
+        assert_eq!(warm_pool["pinned"], serde_json::json!([MODEL]));
+        let models = warm_pool["models"].as_array().unwrap();
+        assert_eq!(models[0]["id"], MODEL);
+        assert_eq!(models[0]["pinned"], true);
+        let warmed = models
+            .iter()
+            .find(|model| model["id"] == PREDICTED)
+            .unwrap();
+        assert_eq!(warmed["pinned"], false);
+        assert!(warmed["warmed_at"].is_u64());
+        assert!(warmed["error"].is_null());
+    }
+}
@@ -1,6 +1,13 @@
-use crate::gemma_api::{run_gemma_api, GemmaInferenceConfig, WhichModel};
-use clap::Parser;
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use gemma_runner::{run_gemma_api, GemmaInferenceConfig, GemmaRunner, Quantization, WhichModel};
+use runner_core::{
+    bench, run_bench, BenchConfig, BenchReport, ChatMessage, ContextPolicy, DeviceSpec,
+    DownloadProgress, EvalReport, ModelRunner, SamplingPreset, DEFAULT_PARALLEL_DOWNLOADS,
+};
 use std::io::Write;
+use std::path::PathBuf;
+use std::time::Instant;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Fast Gemma inference with Candle", long_about = None)]
@@ -9,6 +16,10 @@ pub struct Args {
     #[arg(short, long, default_value = "The capital of France is")]
     pub(crate) prompt: String,
 
+    /// System prompt, placed ahead of the prompt in the first user turn
+    #[arg(long)]
+    pub(crate) system: Option<String>,
+
     /// The model to use
     #[arg(short, long, default_value = "gemma-2-2b")]
     pub(crate) model: WhichModel,
@@ -17,7 +28,16 @@ pub struct Args {
     #[arg(long)]
     pub(crate) cpu: bool,
 
-    /// The temperature used to generate samples
+    /// Device to run on: auto, cpu, cuda:<n> or metal:<n>. `--cpu` overrides it
+    #[arg(long, default_value = "auto")]
+    pub(crate) device: DeviceSpec,
+
+    /// Named sampling settings: precise, balanced or creative. Sampling flags given
+    /// explicitly override the preset's values
+    #[arg(long)]
+    pub(crate) preset: Option<SamplingPreset>,
+
+    /// The temperature used to generate samples (default: 0.8)
     #[arg(short, long)]
     pub(crate) temperature: Option<f64>,
 
@@ -25,6 +45,10 @@ pub struct Args {
     #[arg(long)]
     pub(crate) top_p: Option<f64>,
 
+    /// Only sample among the top K samples
+    #[arg(long)]
+    pub(crate) top_k: Option<usize>,
+
     /// The seed to use when generating random samples
     #[arg(long, default_value_t = 299792458)]
     pub(crate) seed: u64,
@@ -41,6 +65,10 @@ pub struct Args {
     #[arg(long)]
     pub(crate) model_id: Option<String>,
 
+    /// Load the model from a local directory instead of the HuggingFace Hub
+    #[arg(long)]
+    pub(crate) model_path: Option<PathBuf>,
+
     /// Model revision
     #[arg(long, default_value = "main")]
     pub(crate) revision: String,
@@ -49,42 +77,199 @@ pub struct Args {
     #[arg(long)]
     pub(crate) use_flash_attn: bool,
 
-    /// Penalty to be applied for repeating tokens, 1. means no penalty
-    #[arg(long, default_value_t = 1.1)]
-    pub(crate) repeat_penalty: f32,
+    /// Penalty to be applied for repeating tokens, 1. means no penalty (default: 1.1)
+    #[arg(long)]
+    pub(crate) repeat_penalty: Option<f32>,
 
     /// The context size to consider for the repeat penalty
     #[arg(long, default_value_t = 64)]
     pub(crate) repeat_last_n: usize,
 
+    /// Never repeat an n-gram of this many tokens (0 disables it)
+    #[arg(long, default_value_t = 0)]
+    pub(crate) no_repeat_ngram_size: usize,
+
+    /// Lower a token's logit by this much per time it was already generated (0 disables it)
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub(crate) frequency_penalty: f32,
+
+    /// Load a GGUF quantized checkpoint (Gemma 3 models only)
+    #[arg(long)]
+    pub(crate) quantization: Option<Quantization>,
+
+    /// Stop generating when this string is produced (repeatable)
+    #[arg(long)]
+    pub(crate) stop: Vec<String>,
+
+    /// What to do when the prompt and max tokens exceed the context window:
+    /// error, truncate or sliding-window
+    #[arg(long, default_value = "error")]
+    pub(crate) context_policy: ContextPolicy,
+
+    /// Number of checkpoint shards to download at the same time
+    #[arg(long, default_value_t = DEFAULT_PARALLEL_DOWNLOADS)]
+    pub(crate) parallel_downloads: usize,
+
+    /// CPU threads for tensor operations (default: RUNNER_THREADS, else physical cores)
+    #[arg(long)]
+    pub(crate) threads: Option<usize>,
+
+    /// Load the weights one shard at a time to lower peak memory (slower)
+    #[arg(long)]
+    pub(crate) low_memory: bool,
+
     /// Enable tracing
     #[arg(long)]
     pub(crate) tracing: bool,
+
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Measure time to first token, tokens/sec and peak memory, reported as JSON
+    Bench(BenchArgs),
+    /// Compute the perplexity of the model over a text file, reported as JSON
+    Eval(EvalArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct BenchArgs {
+    /// Prompt length in tokens (approximate: the prompt repeats one word)
+    #[arg(long, default_value_t = 128)]
+    prompt_tokens: usize,
+
+    /// Tokens to generate per run
+    #[arg(long, default_value_t = 128)]
+    gen_tokens: usize,
+
+    /// Number of measured runs
+    #[arg(long, default_value_t = 5)]
+    runs: usize,
+
+    /// Number of runs before measuring
+    #[arg(long, default_value_t = 1)]
+    warmup: usize,
+
+    /// Write the report to this file instead of stdout
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct EvalArgs {
+    /// Text file to score
+    #[arg(long)]
+    file: PathBuf,
+
+    /// Tokens per window; each window is scored from an empty KV cache
+    #[arg(long, default_value_t = 512)]
+    window: usize,
+
+    /// Write the report to this file instead of stdout
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+impl EvalArgs {
+    fn run(self, cfg: GemmaInferenceConfig) -> anyhow::Result<()> {
+        let text = std::fs::read_to_string(&self.file)
+            .with_context(|| format!("failed to read {}", self.file.display()))?;
+        let runner = GemmaRunner::load(cfg)?;
+        let start = Instant::now();
+        let perplexity = runner.perplexity(&text, self.window)?;
+        EvalReport {
+            model_id: runner.metadata().model_id.clone(),
+            window: self.window,
+            elapsed: start.elapsed(),
+            perplexity,
+        }
+        .write_json(self.output.as_deref())
+    }
+}
+
+impl BenchArgs {
+    fn run(self, cfg: GemmaInferenceConfig) -> anyhow::Result<()> {
+        let config = BenchConfig {
+            prompt_tokens: self.prompt_tokens,
+            max_tokens: self.gen_tokens,
+            runs: self.runs,
+            warmup: self.warmup,
+        };
+        let start = Instant::now();
+        let runner = GemmaRunner::load(cfg)?;
+        let load_time = start.elapsed();
+        let runs = run_bench(&runner, &config)?;
+        BenchReport {
+            model_id: runner.metadata().model_id.clone(),
+            config,
+            load_time,
+            runs,
+            peak_memory_bytes: bench::peak_memory_bytes(),
+        }
+        .write_json(self.output.as_deref())
+    }
 }
 
 pub fn run_cli() -> anyhow::Result<()> {
     let args = Args::parse();
+    let command = args.command;
+    let messages = match &args.system {
+        Some(system) => vec![
+            ChatMessage::system(system.clone()),
+            ChatMessage::user(args.prompt.clone()),
+        ],
+        None => Vec::new(),
+    };
+    let preset = args.preset.map(|preset| preset.settings());
     let cfg = GemmaInferenceConfig {
         tracing: args.tracing,
         prompt: args.prompt,
         model: Some(args.model),
         cpu: args.cpu,
+        device: args.device,
         dtype: args.dtype,
         model_id: args.model_id,
         revision: args.revision,
         use_flash_attn: args.use_flash_attn,
         seed: args.seed,
-        temperature: args.temperature.unwrap_or(0.8),
-        top_p: args.top_p,
-        repeat_penalty: args.repeat_penalty,
+        temperature: args
+            .temperature
+            .or(preset.map(|p| p.temperature))
+            .unwrap_or(0.8),
+        top_p: args.top_p.or(preset.and_then(|p| p.top_p)),
+        top_k: args.top_k.or(preset.and_then(|p| p.top_k)),
+        repeat_penalty: args
+            .repeat_penalty
+            .or(preset.map(|p| p.repeat_penalty))
+            .unwrap_or(1.1),
         repeat_last_n: args.repeat_last_n,
+        no_repeat_ngram_size: args.no_repeat_ngram_size,
+        frequency_penalty: args.frequency_penalty,
         max_tokens: args.max_tokens,
+        stop: args.stop,
+        quantization: args.quantization,
+        context_policy: args.context_policy,
+        download_progress: Some(DownloadProgress::stderr()),
+        parallel_downloads: args.parallel_downloads,
+        model_path: args.model_path,
+        messages,
+        threads: args.threads,
+        low_memory: args.low_memory,
+        ..Default::default()
     };
-    let rx = run_gemma_api(cfg)?;
-    for msg in rx {
+    match command {
+        Some(Command::Bench(bench)) => return bench.run(cfg),
+        Some(Command::Eval(eval)) => return eval.run(cfg),
+        None => {}
+    }
+    let mut rx = run_gemma_api(cfg)?;
+    while let Some(msg) = rx.blocking_recv() {
         match msg {
-            Ok(tok) => {
-                print!("{tok}");
+            Ok(event) if event.is_prompt => {}
+            Ok(event) => {
+                print!("{}", event.text);
                 let _ = std::io::stdout().flush(); // <- force it out now
             }
             Err(e) => {
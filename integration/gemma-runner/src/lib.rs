@@ -1,3 +1,7 @@
 pub mod gemma_api;
 
-pub use gemma_api::{run_gemma_api, GemmaInferenceConfig, WhichModel};
+pub use gemma_api::{
+    cached_context_length, cached_models, clear_model_cache, download_model, evict_cached_model,
+    evict_idle_model, evict_model, format_chat_prompt, idle_models, run_gemma_api,
+    GemmaInferenceConfig, GemmaRunner, Quantization, WhichModel,
+};
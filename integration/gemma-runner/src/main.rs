@@ -2,7 +2,6 @@
 extern crate accelerate_src;
 #[cfg(feature = "mkl")]
 extern crate intel_mkl_src;
-mod gemma_api;
 mod gemma_cli;
 
 use anyhow::Error;
@@ -0,0 +1,47 @@
+//! Generation with a real checkpoint from the HuggingFace Hub. These tests download
+//! Gemma 3 1B (about 2 GB), so they are ignored unless the crate is built with the
+//! `integration-tests` feature. Gemma is gated: set `HF_TOKEN` to a token whose account has
+//! accepted the license of `google/gemma-3-1b-it`.
+//!
+//! ```text
+//! HF_TOKEN=... cargo test -p gemma-runner --features integration-tests
+//! ```
+
+use gemma_runner::{GemmaInferenceConfig, GemmaRunner, WhichModel};
+use runner_core::{GenerationRequest, ModelRunner};
+
+fn complete(runner: &GemmaRunner, prompt: &str) -> String {
+    let mut rx = runner
+        .generate_stream(GenerationRequest::new(prompt, 16))
+        .unwrap();
+    let mut text = String::new();
+    while let Some(event) = rx.blocking_recv() {
+        let event = event.unwrap();
+        if !event.is_prompt {
+            text.push_str(&event.text);
+        }
+    }
+    text
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "downloads a checkpoint; enable the integration-tests feature"
+)]
+fn test_gemma3_greedy_generation_is_deterministic() {
+    let runner = GemmaRunner::load(GemmaInferenceConfig {
+        model: Some(WhichModel::InstructV3_1B),
+        cpu: true,
+        dtype: Some("f32".to_string()),
+        temperature: 0.0,
+        ..Default::default()
+    })
+    .unwrap();
+
+    let prompt =
+        "<start_of_turn>user\nWhat is the capital of France?<end_of_turn>\n<start_of_turn>model\n";
+    let first = complete(&runner, prompt);
+    assert!(!first.trim().is_empty(), "empty completion");
+    assert_eq!(complete(&runner, prompt), first);
+}
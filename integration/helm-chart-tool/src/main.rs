@@ -1,8 +1,12 @@
+mod validate;
+
 use anyhow::{Context, Result};
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, Command};
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
+use std::process;
 use walkdir::WalkDir;
 
 #[derive(Debug, Deserialize)]
@@ -22,18 +26,185 @@ struct Metadata {
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 struct KubeMetadata {
     image: String,
+    /// Command the container runs; the image's default when unset.
+    cmd: Option<Vec<String>>,
     replicas: Option<u32>,
     port: u16,
+    /// Number of NVIDIA GPUs each pod requests.
+    gpu: Option<u32>,
+    /// GPU model to schedule on, matched against the `nvidia.com/gpu.product` node label
+    /// set by GPU feature discovery, e.g. `NVIDIA-A10G`.
+    gpu_type: Option<String>,
+    /// RuntimeClass the pods run under, e.g. `nvidia`.
+    runtime_class: Option<String>,
+    /// Size of a PersistentVolumeClaim for the HuggingFace cache, e.g. `20Gi`. Setting it
+    /// mounts the volume and points `HF_HOME` at it, so model weights survive restarts.
+    cache_size: Option<String>,
+    /// Where the cache volume is mounted. Defaults to [`DEFAULT_CACHE_PATH`].
+    cache_path: Option<String>,
+    /// StorageClass of the cache volume; the cluster default when unset.
+    cache_storage_class: Option<String>,
+    /// Environment variables for the service, delivered through a ConfigMap. Values may
+    /// refer to another service's base URL as `${service.<name>}`.
+    env: Option<BTreeMap<String, String>>,
+    /// Names of secret environment variables, e.g. `HF_TOKEN`, delivered through a Secret.
+    /// Their values are left empty in values.yaml, to be set at install time.
+    secret_env: Option<Vec<String>>,
+    autoscaling: Option<AutoscalingMetadata>,
+    /// Path of the liveness probe. Defaults to `/health`.
+    health_path: Option<String>,
+    /// Path of the readiness probe. Defaults to `health_path`.
+    readiness_path: Option<String>,
+    liveness_initial_delay: Option<u32>,
+    liveness_period: Option<u32>,
+    readiness_initial_delay: Option<u32>,
+    readiness_period: Option<u32>,
+    /// Path Prometheus scrapes, e.g. `/metrics`. Setting it enables a ServiceMonitor.
+    metrics_path: Option<String>,
+    /// Port serving metrics, when it is not `port`.
+    metrics_port: Option<u16>,
+    /// Scrape interval, e.g. `30s`.
+    metrics_interval: Option<String>,
+    /// Services allowed to call this one when network policies are enabled, e.g.
+    /// `["predict-otron-9000"]`.
+    allow_from: Option<Vec<String>>,
+    /// Whether traffic from the ingress controller may reach this service.
+    allow_ingress: Option<bool>,
+    /// Path prefixes the chart's ingress routes to this service, e.g. `["/v1/embeddings"]`.
+    routes: Option<Vec<String>>,
 }
 
+/// `[package.metadata.kube.autoscaling]`: enables a HorizontalPodAutoscaler for the service.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct AutoscalingMetadata {
+    min_replicas: Option<u32>,
+    max_replicas: Option<u32>,
+    /// Average CPU utilization to scale at, in percent of the requested CPU.
+    target_cpu: Option<u32>,
+    /// Average memory utilization to scale at, in percent of the requested memory.
+    target_memory: Option<u32>,
+}
+
+/// Mount path of the model cache volume, exported to the container as `HF_HOME`.
+const DEFAULT_CACHE_PATH: &str = "/data/huggingface";
+/// Volume size offered in values.yaml for services that don't enable the cache.
+const DEFAULT_CACHE_SIZE: &str = "10Gi";
+/// Probe path for services that don't set `health-path`.
+const DEFAULT_HEALTH_PATH: &str = "/health";
+
+/// Scrape path offered in values.yaml for services that don't set `metrics-path`.
+const DEFAULT_METRICS_PATH: &str = "/metrics";
+/// Scrape interval for services that don't set `metrics-interval`.
+const DEFAULT_METRICS_INTERVAL: &str = "30s";
+
+/// CPU utilization target when autoscaling sets no target of its own.
+const DEFAULT_TARGET_CPU: u32 = 80;
+/// Replica ceiling offered in values.yaml when none is given.
+const DEFAULT_MAX_REPLICAS: u32 = 3;
+
 #[derive(Debug, Clone)]
 struct ServiceInfo {
     name: String,
     image: String,
+    cmd: Vec<String>,
     port: u16,
     replicas: u32,
+    gpu: Option<u32>,
+    gpu_type: Option<String>,
+    runtime_class: Option<String>,
+    cache: CacheVolume,
+    env: BTreeMap<String, String>,
+    secret_env: Vec<String>,
+    autoscaling: Autoscaling,
+    liveness: Probe,
+    readiness: Probe,
+    metrics: Metrics,
+    allow_from: Vec<String>,
+    allow_ingress: bool,
+    routes: Vec<String>,
+}
+
+impl ServiceInfo {
+    /// Whether the ingress controller sends traffic to this service.
+    fn accepts_ingress(&self) -> bool {
+        self.allow_ingress || !self.routes.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Metrics {
+    enabled: bool,
+    path: String,
+    /// A separate container port for metrics; `None` scrapes the `http` port.
+    port: Option<u16>,
+    interval: String,
+}
+
+impl Metrics {
+    /// Name of the container and Service port Prometheus scrapes.
+    fn port_name(&self) -> &'static str {
+        if self.port.is_some() {
+            "metrics"
+        } else {
+            "http"
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Probe {
+    path: String,
+    initial_delay: u32,
+    period: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Autoscaling {
+    enabled: bool,
+    min_replicas: u32,
+    max_replicas: u32,
+    target_cpu: Option<u32>,
+    target_memory: Option<u32>,
+}
+
+impl Autoscaling {
+    fn from_metadata(metadata: Option<AutoscalingMetadata>, replicas: u32) -> Self {
+        let Some(metadata) = metadata else {
+            return Self {
+                enabled: false,
+                min_replicas: replicas,
+                max_replicas: replicas.max(DEFAULT_MAX_REPLICAS),
+                target_cpu: Some(DEFAULT_TARGET_CPU),
+                target_memory: None,
+            };
+        };
+        let min_replicas = metadata.min_replicas.unwrap_or(replicas);
+        let target_cpu = match (metadata.target_cpu, metadata.target_memory) {
+            (None, None) => Some(DEFAULT_TARGET_CPU),
+            (cpu, _) => cpu,
+        };
+        Self {
+            enabled: true,
+            min_replicas,
+            max_replicas: metadata
+                .max_replicas
+                .unwrap_or(min_replicas.max(DEFAULT_MAX_REPLICAS)),
+            target_cpu,
+            target_memory: metadata.target_memory,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CacheVolume {
+    enabled: bool,
+    size: String,
+    path: String,
+    storage_class: Option<String>,
 }
 
 fn main() -> Result<()> {
@@ -52,8 +223,16 @@ fn main() -> Result<()> {
                 .short('o')
                 .long("output")
                 .value_name("PATH")
-                .help("Output directory for the Helm chart")
-                .default_value("./helm-chart"),
+                .help("Output directory for the Helm chart, or file for the compose file (default: ./helm-chart or ./docker-compose.yml)"),
+        )
+        .arg(
+            Arg::new("format")
+                .short('f')
+                .long("format")
+                .value_name("FORMAT")
+                .help("What to generate")
+                .value_parser(["helm", "compose"])
+                .default_value("helm"),
         )
         .arg(
             Arg::new("chart-name")
@@ -63,16 +242,51 @@ fn main() -> Result<()> {
                 .help("Name of the Helm chart")
                 .default_value("predict-otron-9000"),
         )
+        .arg(
+            Arg::new("chart-version")
+                .long("chart-version")
+                .value_name("VERSION")
+                .help("Chart version (defaults to the workspace version)"),
+        )
+        .arg(
+            Arg::new("validate")
+                .long("validate")
+                .help("Check the generated chart, rendering it with helm when available")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("app-version")
+                .long("app-version")
+                .value_name("VERSION")
+                .help("appVersion and default image tag (defaults to the release tag at HEAD, else the workspace version)"),
+        )
         .get_matches();
 
     let workspace_path = matches.get_one::<String>("workspace").unwrap();
-    let output_path = matches.get_one::<String>("output").unwrap();
+    let format = matches.get_one::<String>("format").unwrap();
+    let output_path = match matches.get_one::<String>("output") {
+        Some(output_path) => output_path.as_str(),
+        None if format == "compose" => "./docker-compose.yml",
+        None => "./helm-chart",
+    };
     let chart_name = matches.get_one::<String>("chart-name").unwrap();
 
     println!("Parsing workspace at: {}", workspace_path);
     println!("Output directory: {}", output_path);
     println!("Chart name: {}", chart_name);
 
+    let mut versions = ChartVersions::from_workspace(Path::new(workspace_path))?;
+    if let Some(version) = matches.get_one::<String>("chart-version") {
+        versions.version = version.clone();
+    }
+    if let Some(app_version) = matches.get_one::<String>("app-version") {
+        versions.app_version = app_version.clone();
+    }
+    println!(
+        "Chart version: {} (appVersion {})",
+        versions.version, versions.app_version
+    );
+
     let services = discover_services(workspace_path)?;
     println!("Found {} services:", services.len());
     for service in &services {
@@ -82,12 +296,132 @@ fn main() -> Result<()> {
         );
     }
 
-    generate_helm_chart(output_path, chart_name, &services)?;
-    println!("Helm chart generated successfully!");
+    if format == "compose" {
+        generate_compose_file(output_path, &versions, &services)?;
+        println!("Compose file generated successfully!");
+    } else {
+        generate_helm_chart(output_path, chart_name, &versions, &services)?;
+        println!("Helm chart generated successfully!");
+        if matches.get_flag("validate") {
+            validate::validate_chart(Path::new(output_path), &services)?;
+            println!("Helm chart validated successfully!");
+        }
+    }
 
     Ok(())
 }
 
+/// Chart.yaml versions, derived from the workspace unless overridden on the command line.
+#[derive(Debug, Clone, PartialEq)]
+struct ChartVersions {
+    version: String,
+    /// Also the image tag of every service that doesn't pin one.
+    app_version: String,
+    /// Commit the chart was generated from, when the workspace is a git checkout.
+    git_commit: Option<String>,
+}
+
+impl ChartVersions {
+    /// The chart version is the workspace version. Images are published per release tag, so
+    /// `appVersion` is the `v*` tag at HEAD when there is one, and the workspace version
+    /// otherwise.
+    fn from_workspace(workspace_root: &Path) -> Result<Self> {
+        let version = workspace_version(&workspace_root.join("Cargo.toml"))?;
+        let app_version = git(
+            workspace_root,
+            &["describe", "--tags", "--exact-match", "HEAD"],
+        )
+        .and_then(|tag| tag.strip_prefix('v').map(str::to_string))
+        .unwrap_or_else(|| version.clone());
+        Ok(Self {
+            version,
+            app_version,
+            git_commit: git(workspace_root, &["rev-parse", "--short", "HEAD"]),
+        })
+    }
+}
+
+/// `[workspace.package] version`, or `[package] version` for a single-crate workspace.
+fn workspace_version(manifest: &Path) -> Result<String> {
+    let content = fs::read_to_string(manifest)
+        .with_context(|| format!("Failed to read Cargo.toml at {:?}", manifest))?;
+    let cargo_toml: toml::Value = toml::from_str(&content)
+        .with_context(|| format!("Failed to parse Cargo.toml at {:?}", manifest))?;
+    cargo_toml
+        .get("workspace")
+        .and_then(|workspace| workspace.get("package"))
+        .or_else(|| cargo_toml.get("package"))
+        .and_then(|package| package.get("version"))
+        .and_then(|version| version.as_str())
+        .map(str::to_string)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "No version found in {:?}; pass --chart-version and --app-version",
+                manifest
+            )
+        })
+}
+
+/// Trimmed stdout of a git command, or `None` if git is missing or the command fails.
+fn git(workspace_root: &Path, args: &[&str]) -> Option<String> {
+    let output = process::Command::new("git")
+        .arg("-C")
+        .arg(workspace_root)
+        .args(args)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    Some(stdout.trim().to_string()).filter(|stdout| !stdout.is_empty())
+}
+
+/// Split an image reference into its repository and tag. The tag is empty when the
+/// reference has none, so the chart falls back to `appVersion`.
+fn split_image(image: &str) -> (&str, &str) {
+    match image.rsplit_once(':') {
+        // A colon followed by a path is a registry port, not a tag
+        Some((repository, tag)) if !tag.contains('/') => (repository, tag),
+        _ => (image, ""),
+    }
+}
+
+/// Replace every `${service.<name>}` in the services' env values with `url(service)` of the
+/// named service.
+fn resolve_service_urls(
+    services: &[ServiceInfo],
+    url: impl Fn(&ServiceInfo) -> String,
+) -> Result<Vec<ServiceInfo>> {
+    let mut resolved = services.to_vec();
+    for service in &mut resolved {
+        for (key, value) in &mut service.env {
+            while let Some(start) = value.find("${service.") {
+                let end = value[start..]
+                    .find('}')
+                    .map(|end| start + end)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Unterminated ${{service.}} in {} of {}", key, service.name)
+                    })?;
+                let name = &value[start + "${service.".len()..end];
+                let target = services
+                    .iter()
+                    .find(|target| target.name == name)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "{} of {} refers to unknown service {}",
+                            key,
+                            service.name,
+                            name
+                        )
+                    })?;
+                value.replace_range(start..=end, &url(target));
+            }
+        }
+    }
+    Ok(resolved)
+}
+
 fn discover_services(workspace_path: &str) -> Result<Vec<ServiceInfo>> {
     let workspace_root = Path::new(workspace_path);
     let mut services = Vec::new();
@@ -128,27 +462,85 @@ fn parse_cargo_toml(path: &Path) -> Result<ServiceInfo> {
         .kube
         .ok_or_else(|| anyhow::anyhow!("No kube metadata found in {:?}", path))?;
 
+    let replicas = kube_metadata.replicas.unwrap_or(1);
+    let health_path = kube_metadata
+        .health_path
+        .unwrap_or_else(|| DEFAULT_HEALTH_PATH.to_string());
+    let liveness = Probe {
+        path: health_path.clone(),
+        initial_delay: kube_metadata.liveness_initial_delay.unwrap_or(30),
+        period: kube_metadata.liveness_period.unwrap_or(10),
+    };
+    let readiness = Probe {
+        path: kube_metadata.readiness_path.unwrap_or(health_path),
+        initial_delay: kube_metadata.readiness_initial_delay.unwrap_or(5),
+        period: kube_metadata.readiness_period.unwrap_or(5),
+    };
+    let metrics = Metrics {
+        enabled: kube_metadata.metrics_path.is_some(),
+        path: kube_metadata
+            .metrics_path
+            .unwrap_or_else(|| DEFAULT_METRICS_PATH.to_string()),
+        port: kube_metadata
+            .metrics_port
+            .filter(|port| *port != kube_metadata.port),
+        interval: kube_metadata
+            .metrics_interval
+            .unwrap_or_else(|| DEFAULT_METRICS_INTERVAL.to_string()),
+    };
     Ok(ServiceInfo {
         name: package.name,
         image: kube_metadata.image,
+        cmd: kube_metadata.cmd.unwrap_or_default(),
         port: kube_metadata.port,
-        replicas: kube_metadata.replicas.unwrap_or(1),
+        replicas,
+        gpu: kube_metadata.gpu,
+        gpu_type: kube_metadata.gpu_type,
+        runtime_class: kube_metadata.runtime_class,
+        cache: CacheVolume {
+            enabled: kube_metadata.cache_size.is_some(),
+            size: kube_metadata
+                .cache_size
+                .unwrap_or_else(|| DEFAULT_CACHE_SIZE.to_string()),
+            path: kube_metadata
+                .cache_path
+                .unwrap_or_else(|| DEFAULT_CACHE_PATH.to_string()),
+            storage_class: kube_metadata.cache_storage_class,
+        },
+        env: kube_metadata.env.unwrap_or_default(),
+        secret_env: kube_metadata.secret_env.unwrap_or_default(),
+        autoscaling: Autoscaling::from_metadata(kube_metadata.autoscaling, replicas),
+        liveness,
+        readiness,
+        metrics,
+        allow_from: kube_metadata.allow_from.unwrap_or_default(),
+        allow_ingress: kube_metadata.allow_ingress.unwrap_or(false),
+        routes: kube_metadata.routes.unwrap_or_default(),
     })
 }
 
 fn generate_helm_chart(
     output_path: &str,
     chart_name: &str,
+    versions: &ChartVersions,
     services: &[ServiceInfo],
 ) -> Result<()> {
     let chart_dir = Path::new(output_path);
     let templates_dir = chart_dir.join("templates");
+    // Service names carry the release prefix, so the ConfigMap resolves them with `tpl`
+    let services = &resolve_service_urls(services, |target| {
+        format!(
+            r#"http://{{{{ include "predict-otron-9000.fullname" . }}}}-{}:{{{{ .Values.{}.service.port }}}}"#,
+            target.name,
+            target.name.replace("-", "_")
+        )
+    })?;
 
     // Create directories
     fs::create_dir_all(&templates_dir)?;
 
     // Generate Chart.yaml
-    generate_chart_yaml(chart_dir, chart_name)?;
+    generate_chart_yaml(chart_dir, chart_name, versions)?;
 
     // Generate values.yaml
     generate_values_yaml(chart_dir, services)?;
@@ -157,8 +549,17 @@ fn generate_helm_chart(
     for service in services {
         generate_deployment_template(&templates_dir, service)?;
         generate_service_template(&templates_dir, service)?;
+        generate_cache_pvc_template(&templates_dir, service)?;
+        generate_configmap_template(&templates_dir, service)?;
+        generate_secret_template(&templates_dir, service)?;
+        generate_hpa_template(&templates_dir, service)?;
+        generate_service_monitor_template(&templates_dir, service)?;
+        generate_network_policy_template(&templates_dir, service)?;
     }
 
+    // Generate the default-deny network policy
+    generate_default_deny_template(&templates_dir)?;
+
     // Generate ingress template
     generate_ingress_template(&templates_dir, services)?;
 
@@ -171,14 +572,134 @@ fn generate_helm_chart(
     Ok(())
 }
 
-fn generate_chart_yaml(chart_dir: &Path, chart_name: &str) -> Result<()> {
+/// A docker-compose file running the same services on one host. Secrets are read from the
+/// host environment, only services that accept ingress publish their port, and each service
+/// starts after the services its metadata says it calls.
+fn generate_compose_file(
+    output_path: &str,
+    versions: &ChartVersions,
+    services: &[ServiceInfo],
+) -> Result<()> {
+    let services = &resolve_service_urls(services, |target| {
+        format!("http://{}:{}", target.name, target.port)
+    })?;
+    let mut compose =
+        String::from("# Generated by helm-chart-tool from Cargo.toml metadata\nservices:\n");
+    for service in services {
+        compose.push_str(&compose_service(service, versions, services));
+    }
+
+    let volumes: String = services
+        .iter()
+        .filter(|service| service.cache.enabled)
+        .map(|service| format!("\n  {}-cache:", service.name))
+        .collect();
+    if !volumes.is_empty() {
+        compose.push_str(&format!("volumes:{}\n", volumes));
+    }
+
+    if let Some(parent) = Path::new(output_path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(output_path, compose)?;
+    Ok(())
+}
+
+fn compose_service(
+    service: &ServiceInfo,
+    versions: &ChartVersions,
+    services: &[ServiceInfo],
+) -> String {
+    let (repository, tag) = split_image(&service.image);
+    let tag = if tag.is_empty() {
+        versions.app_version.as_str()
+    } else {
+        tag
+    };
+    let mut entry = format!(
+        "  {}:\n    image: {}:{}\n    restart: unless-stopped\n",
+        service.name, repository, tag
+    );
+    if !service.cmd.is_empty() {
+        entry.push_str(&format!(
+            "    command: {}\n",
+            serde_json::Value::from(service.cmd.clone())
+        ));
+    }
+    if service.allow_ingress {
+        entry.push_str(&format!(
+            "    ports:\n      - \"{port}:{port}\"\n",
+            port = service.port
+        ));
+    } else {
+        entry.push_str(&format!("    expose:\n      - \"{}\"\n", service.port));
+    }
+
+    let mut environment = service.env.clone();
+    if service.cache.enabled {
+        environment.insert("HF_HOME".to_string(), service.cache.path.clone());
+    }
+    for key in &service.secret_env {
+        environment.insert(key.clone(), format!("${{{}:-}}", key));
+    }
+    if !environment.is_empty() {
+        entry.push_str("    environment:");
+        entry.push_str(&yaml_map(
+            environment.iter().map(|(k, v)| (k, v.as_str())),
+            6,
+        ));
+        entry.push('\n');
+    }
+    if service.cache.enabled {
+        entry.push_str(&format!(
+            "    volumes:\n      - {}-cache:{}\n",
+            service.name, service.cache.path
+        ));
+    }
+
+    let depends_on: String = services
+        .iter()
+        .filter(|other| other.allow_from.contains(&service.name))
+        .map(|other| format!("\n      - {}", other.name))
+        .collect();
+    if !depends_on.is_empty() {
+        entry.push_str(&format!("    depends_on:{}\n", depends_on));
+    }
+    if let Some(gpu) = service.gpu {
+        entry.push_str(&format!(
+            r#"    deploy:
+      resources:
+        reservations:
+          devices:
+            - driver: nvidia
+              count: {}
+              capabilities: [gpu]
+"#,
+            gpu
+        ));
+    }
+    entry.push('\n');
+    entry
+}
+
+fn generate_chart_yaml(chart_dir: &Path, chart_name: &str, versions: &ChartVersions) -> Result<()> {
+    let annotations = versions
+        .git_commit
+        .as_ref()
+        .map(|commit| {
+            format!(
+                "annotations:\n  predict-otron-9000/git-commit: \"{}\"\n",
+                commit
+            )
+        })
+        .unwrap_or_default();
     let chart_yaml = format!(
         r#"apiVersion: v2
 name: {}
 description: A Helm chart for the predict-otron-9000 AI platform
 type: application
-version: 0.1.0
-appVersion: "0.1.0"
+version: {}
+appVersion: "{}"
 keywords:
   - ai
   - llm
@@ -187,8 +708,8 @@ keywords:
   - chat
 maintainers:
   - name: predict-otron-9000-team
-"#,
-        chart_name
+{}"#,
+        chart_name, versions.version, versions.app_version, annotations
     );
 
     fs::write(chart_dir.join("Chart.yaml"), chart_yaml)?;
@@ -196,7 +717,7 @@ maintainers:
 }
 
 fn generate_values_yaml(chart_dir: &Path, services: &[ServiceInfo]) -> Result<()> {
-    let mut values = String::from(
+    let mut values = format!(
         r#"# Default values for predict-otron-9000
 # This is a YAML-formatted file.
 
@@ -208,128 +729,308 @@ global:
 ingress:
   enabled: false
   className: ""
-  annotations: {}
+  annotations: {{}}
   hosts:
     - host: predict-otron-9000.local
-      paths:
-        - path: /
-          pathType: Prefix
-          backend:
-            service:
-              name: predict-otron-9000
-              port:
-                number: 8080
+      paths:{}
   tls: []
 
+# Network policies: deny ingress to every pod of the chart except the traffic each service's
+# metadata allows. Clusters without network policy enforcement ignore them.
+networkPolicy:
+  enabled: true
+  # Peers allowed to reach services that accept ingress traffic. The default admits every
+  # namespace; narrow it to the ingress controller's, e.g.
+  #   - namespaceSelector:
+  #       matchLabels:
+  #         kubernetes.io/metadata.name: ingress-nginx
+  ingressFrom:
+    - namespaceSelector: {{}}
+
 "#,
+        ingress_paths(services)
     );
 
     for service in services {
-        let service_config = format!(
-            r#"{}:
+        values.push_str(&service_values(service));
+    }
+
+    fs::write(chart_dir.join("values.yaml"), values)?;
+    Ok(())
+}
+
+/// Ingress paths for every service's `routes`, longest first so the most specific prefix
+/// reads first.
+fn ingress_paths(services: &[ServiceInfo]) -> String {
+    let mut routes: Vec<(&str, &ServiceInfo)> = services
+        .iter()
+        .flat_map(|service| {
+            service
+                .routes
+                .iter()
+                .map(move |route| (route.as_str(), service))
+        })
+        .collect();
+    routes.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then(a.cmp(b)));
+    if routes.is_empty() {
+        return " []".to_string();
+    }
+    routes
+        .into_iter()
+        .map(|(route, service)| {
+            format!(
+                r#"
+        - path: {}
+          pathType: Prefix
+          backend:
+            service:
+              name: {}
+              port:
+                number: {}"#,
+                route, service.name, service.port
+            )
+        })
+        .collect()
+}
+
+fn service_values(service: &ServiceInfo) -> String {
+    let gpu_limit = service
+        .gpu
+        .map(|gpu| format!("\n      nvidia.com/gpu: {}", gpu))
+        .unwrap_or_default();
+    let node_selector = match &service.gpu_type {
+        Some(gpu_type) => format!("\n    nvidia.com/gpu.product: \"{}\"", gpu_type),
+        None => " {}".to_string(),
+    };
+    let env = yaml_map(service.env.iter().map(|(k, v)| (k, v.as_str())), 4);
+    let secret_data = yaml_map(service.secret_env.iter().map(|k| (k, "")), 6);
+    let autoscaling = &service.autoscaling;
+    // Replicas can land on different nodes, which a ReadWriteOnce claim does not allow.
+    let cache_access_mode = if autoscaling.enabled || service.replicas > 1 {
+        "ReadWriteMany"
+    } else {
+        "ReadWriteOnce"
+    };
+    let utilization_targets: String = [
+        ("targetCPUUtilizationPercentage", autoscaling.target_cpu),
+        (
+            "targetMemoryUtilizationPercentage",
+            autoscaling.target_memory,
+        ),
+    ]
+    .iter()
+    .filter_map(|(key, target)| target.map(|target| format!("\n    {}: {}", key, target)))
+    .collect();
+    let (repository, tag) = split_image(&service.image);
+    format!(
+        r#"{}:
   image:
     repository: {}
-    tag: "latest"
+    # Defaults to the chart's appVersion
+    tag: "{}"
     pullPolicy: IfNotPresent
   replicas: {}
+  autoscaling:
+    enabled: {}
+    minReplicas: {}
+    maxReplicas: {}{}
   service:
     type: ClusterIP
     port: {}
+  probes:
+    liveness:
+      path: "{}"
+      initialDelaySeconds: {}
+      periodSeconds: {}
+    readiness:
+      path: "{}"
+      initialDelaySeconds: {}
+      periodSeconds: {}
+  serviceMonitor:
+    enabled: {}
+    path: "{}"
+    interval: "{}"
+    labels: {{}}
+  runtimeClassName: "{}"
   resources:
     limits:
       memory: "1Gi"
-      cpu: "1000m"
+      cpu: "1000m"{}
     requests:
       memory: "512Mi"
       cpu: "250m"
-  nodeSelector: {{}}
+  cache:
+    enabled: {}
+    size: "{}"
+    storageClass: "{}"
+    accessMode: {}
+    mountPath: "{}"
+  env:{}
+  secret:
+    create: {}
+    existingSecret: ""
+    data:{}
+  nodeSelector:{}
   tolerations: []
   affinity: {{}}
 
 "#,
-            service.name.replace("-", "_"),
-            service.image.split(':').next().unwrap_or(&service.image),
-            service.replicas,
-            service.port
-        );
-        values.push_str(&service_config);
-    }
+        service.name.replace("-", "_"),
+        repository,
+        tag,
+        service.replicas,
+        autoscaling.enabled,
+        autoscaling.min_replicas,
+        autoscaling.max_replicas,
+        utilization_targets,
+        service.port,
+        service.liveness.path,
+        service.liveness.initial_delay,
+        service.liveness.period,
+        service.readiness.path,
+        service.readiness.initial_delay,
+        service.readiness.period,
+        service.metrics.enabled,
+        service.metrics.path,
+        service.metrics.interval,
+        service.runtime_class.as_deref().unwrap_or_default(),
+        gpu_limit,
+        service.cache.enabled,
+        service.cache.size,
+        service.cache.storage_class.as_deref().unwrap_or_default(),
+        cache_access_mode,
+        service.cache.path,
+        env,
+        !service.secret_env.is_empty(),
+        secret_data,
+        node_selector
+    )
+}
 
-    fs::write(chart_dir.join("values.yaml"), values)?;
-    Ok(())
+/// String entries as a YAML block map at `indent` spaces, or ` {}` when empty. Values are
+/// written as JSON strings, which YAML reads as double-quoted scalars, so JSON such as
+/// `SERVER_CONFIG` survives unchanged.
+fn yaml_map<'a>(entries: impl Iterator<Item = (&'a String, &'a str)>, indent: usize) -> String {
+    let map: String = entries
+        .map(|(key, value)| {
+            format!(
+                "\n{:indent$}{}: {}",
+                "",
+                key,
+                serde_json::Value::from(value),
+                indent = indent
+            )
+        })
+        .collect();
+    if map.is_empty() {
+        " {}".to_string()
+    } else {
+        map
+    }
 }
 
 fn generate_deployment_template(templates_dir: &Path, service: &ServiceInfo) -> Result<()> {
-    let service_name_underscore = service.name.replace("-", "_");
     let deployment_template = format!(
         r#"apiVersion: apps/v1
 kind: Deployment
 metadata:
-  name: {{{{ include "predict-otron-9000.fullname" . }}}}-{}
+  name: {{{{ include "predict-otron-9000.fullname" . }}}}-{name}
   labels:
     {{{{- include "predict-otron-9000.labels" . | nindent 4 }}}}
-    app.kubernetes.io/component: {}
+    app.kubernetes.io/component: {name}
 spec:
-  replicas: {{{{ .Values.{}.replicas }}}}
+  {{{{- if not .Values.{values}.autoscaling.enabled }}}}
+  replicas: {{{{ .Values.{values}.replicas }}}}
+  {{{{- end }}}}
   selector:
     matchLabels:
       {{{{- include "predict-otron-9000.selectorLabels" . | nindent 6 }}}}
-      app.kubernetes.io/component: {}
+      app.kubernetes.io/component: {name}
   template:
     metadata:
       labels:
         {{{{- include "predict-otron-9000.selectorLabels" . | nindent 8 }}}}
-        app.kubernetes.io/component: {}
+        app.kubernetes.io/component: {name}
     spec:
+      {{{{- with .Values.{values}.runtimeClassName }}}}
+      runtimeClassName: {{{{ . }}}}
+      {{{{- end }}}}
       containers:
-        - name: {}
-          image: "{{{{ .Values.{}.image.repository }}}}:{{{{ .Values.{}.image.tag }}}}"
-          imagePullPolicy: {{{{ .Values.{}.image.pullPolicy }}}}
+        - name: {name}
+          image: "{{{{ .Values.{values}.image.repository }}}}:{{{{ .Values.{values}.image.tag | default .Chart.AppVersion }}}}"
+          imagePullPolicy: {{{{ .Values.{values}.image.pullPolicy }}}}
+          {{{{- $secret := .Values.{values}.secret }}}}
+          {{{{- if or .Values.{values}.env $secret.create $secret.existingSecret }}}}
+          envFrom:
+            {{{{- if .Values.{values}.env }}}}
+            - configMapRef:
+                name: {{{{ include "predict-otron-9000.fullname" . }}}}-{name}-config
+            {{{{- end }}}}
+            {{{{- if or $secret.create $secret.existingSecret }}}}
+            - secretRef:
+                name: {{{{ $secret.existingSecret | default (printf "%s-{name}-secret" (include "predict-otron-9000.fullname" .)) }}}}
+            {{{{- end }}}}
+          {{{{- end }}}}
+          {{{{- if .Values.{values}.cache.enabled }}}}
+          env:
+            - name: HF_HOME
+              value: {{{{ .Values.{values}.cache.mountPath | quote }}}}
+          volumeMounts:
+            - name: model-cache
+              mountPath: {{{{ .Values.{values}.cache.mountPath }}}}
+          {{{{- end }}}}
           ports:
             - name: http
-              containerPort: {}
-              protocol: TCP
+              containerPort: {port}
+              protocol: TCP{metrics_port}
+          {{{{- with .Values.{values}.probes.liveness }}}}
           livenessProbe:
             httpGet:
-              path: /health
+              path: {{{{ .path }}}}
               port: http
-            initialDelaySeconds: 30
-            periodSeconds: 10
+            initialDelaySeconds: {{{{ .initialDelaySeconds }}}}
+            periodSeconds: {{{{ .periodSeconds }}}}
+          {{{{- end }}}}
+          {{{{- with .Values.{values}.probes.readiness }}}}
           readinessProbe:
             httpGet:
-              path: /health
+              path: {{{{ .path }}}}
               port: http
-            initialDelaySeconds: 5
-            periodSeconds: 5
+            initialDelaySeconds: {{{{ .initialDelaySeconds }}}}
+            periodSeconds: {{{{ .periodSeconds }}}}
+          {{{{- end }}}}
           resources:
-            {{{{- toYaml .Values.{}.resources | nindent 12 }}}}
-      {{{{- with .Values.{}.nodeSelector }}}}
+            {{{{- toYaml .Values.{values}.resources | nindent 12 }}}}
+      {{{{- if .Values.{values}.cache.enabled }}}}
+      volumes:
+        - name: model-cache
+          persistentVolumeClaim:
+            claimName: {{{{ include "predict-otron-9000.fullname" . }}}}-{name}-cache
+      {{{{- end }}}}
+      {{{{- with .Values.{values}.nodeSelector }}}}
       nodeSelector:
         {{{{- toYaml . | nindent 8 }}}}
       {{{{- end }}}}
-      {{{{- with .Values.{}.affinity }}}}
+      {{{{- with .Values.{values}.affinity }}}}
       affinity:
         {{{{- toYaml . | nindent 8 }}}}
       {{{{- end }}}}
-      {{{{- with .Values.{}.tolerations }}}}
+      {{{{- with .Values.{values}.tolerations }}}}
       tolerations:
         {{{{- toYaml . | nindent 8 }}}}
       {{{{- end }}}}
 "#,
-        service.name,
-        service.name,
-        service_name_underscore,
-        service.name,
-        service.name,
-        service.name,
-        service_name_underscore,
-        service_name_underscore,
-        service_name_underscore,
-        service.port,
-        service_name_underscore,
-        service_name_underscore,
-        service_name_underscore,
-        service_name_underscore
+        name = service.name,
+        values = service.name.replace("-", "_"),
+        port = service.port,
+        metrics_port = service
+            .metrics
+            .port
+            .map(|port| format!(
+                "\n            - name: metrics\n              containerPort: {}\n              protocol: TCP",
+                port
+            ))
+            .unwrap_or_default(),
     );
 
     let filename = format!("{}-deployment.yaml", service.name);
@@ -352,7 +1053,7 @@ spec:
     - port: {{{{ .Values.{}.service.port }}}}
       targetPort: http
       protocol: TCP
-      name: http
+      name: http{}
   selector:
     {{{{- include "predict-otron-9000.selectorLabels" . | nindent 4 }}}}
     app.kubernetes.io/component: {}
@@ -361,6 +1062,14 @@ spec:
         service.name,
         service.name.replace("-", "_"),
         service.name.replace("-", "_"),
+        service
+            .metrics
+            .port
+            .map(|port| format!(
+                "\n    - port: {}\n      targetPort: metrics\n      protocol: TCP\n      name: metrics",
+                port
+            ))
+            .unwrap_or_default(),
         service.name
     );
 
@@ -369,6 +1078,267 @@ spec:
     Ok(())
 }
 
+/// A claim for the service's HuggingFace cache, rendered only when `cache.enabled` is set.
+/// values.yaml asks for `ReadWriteMany` when the service may run more than one replica.
+fn generate_cache_pvc_template(templates_dir: &Path, service: &ServiceInfo) -> Result<()> {
+    let pvc_template = format!(
+        r#"{{{{- if .Values.{values}.cache.enabled }}}}
+apiVersion: v1
+kind: PersistentVolumeClaim
+metadata:
+  name: {{{{ include "predict-otron-9000.fullname" . }}}}-{name}-cache
+  labels:
+    {{{{- include "predict-otron-9000.labels" . | nindent 4 }}}}
+    app.kubernetes.io/component: {name}
+spec:
+  accessModes:
+    - {{{{ .Values.{values}.cache.accessMode }}}}
+  {{{{- with .Values.{values}.cache.storageClass }}}}
+  storageClassName: {{{{ . }}}}
+  {{{{- end }}}}
+  resources:
+    requests:
+      storage: {{{{ .Values.{values}.cache.size }}}}
+{{{{- end }}}}
+"#,
+        name = service.name,
+        values = service.name.replace("-", "_"),
+    );
+
+    let filename = format!("{}-cache-pvc.yaml", service.name);
+    fs::write(templates_dir.join(filename), pvc_template)?;
+    Ok(())
+}
+
+fn generate_configmap_template(templates_dir: &Path, service: &ServiceInfo) -> Result<()> {
+    let configmap_template = format!(
+        r#"{{{{- with .Values.{values}.env }}}}
+apiVersion: v1
+kind: ConfigMap
+metadata:
+  name: {{{{ include "predict-otron-9000.fullname" $ }}}}-{name}-config
+  labels:
+    {{{{- include "predict-otron-9000.labels" $ | nindent 4 }}}}
+    app.kubernetes.io/component: {name}
+data:
+  {{{{- range $key, $value := . }}}}
+  {{{{ $key }}}}: {{{{ tpl (toString $value) $ | quote }}}}
+  {{{{- end }}}}
+{{{{- end }}}}
+"#,
+        name = service.name,
+        values = service.name.replace("-", "_"),
+    );
+
+    let filename = format!("{}-configmap.yaml", service.name);
+    fs::write(templates_dir.join(filename), configmap_template)?;
+    Ok(())
+}
+
+/// A Secret built from `secret.data`, skipped when `secret.existingSecret` names one managed
+/// outside the chart.
+fn generate_secret_template(templates_dir: &Path, service: &ServiceInfo) -> Result<()> {
+    let secret_template = format!(
+        r#"{{{{- $secret := .Values.{values}.secret }}}}
+{{{{- if and $secret.create (not $secret.existingSecret) }}}}
+apiVersion: v1
+kind: Secret
+metadata:
+  name: {{{{ include "predict-otron-9000.fullname" . }}}}-{name}-secret
+  labels:
+    {{{{- include "predict-otron-9000.labels" . | nindent 4 }}}}
+    app.kubernetes.io/component: {name}
+type: Opaque
+stringData:
+  {{{{- range $key, $value := $secret.data }}}}
+  {{{{ $key }}}}: {{{{ $value | quote }}}}
+  {{{{- end }}}}
+{{{{- end }}}}
+"#,
+        name = service.name,
+        values = service.name.replace("-", "_"),
+    );
+
+    let filename = format!("{}-secret.yaml", service.name);
+    fs::write(templates_dir.join(filename), secret_template)?;
+    Ok(())
+}
+
+/// A HorizontalPodAutoscaler for the service's deployment, rendered only when
+/// `autoscaling.enabled` is set. The deployment leaves `replicas` to it in that case.
+fn generate_hpa_template(templates_dir: &Path, service: &ServiceInfo) -> Result<()> {
+    let hpa_template = format!(
+        r#"{{{{- with .Values.{values}.autoscaling }}}}
+{{{{- if .enabled }}}}
+apiVersion: autoscaling/v2
+kind: HorizontalPodAutoscaler
+metadata:
+  name: {{{{ include "predict-otron-9000.fullname" $ }}}}-{name}
+  labels:
+    {{{{- include "predict-otron-9000.labels" $ | nindent 4 }}}}
+    app.kubernetes.io/component: {name}
+spec:
+  scaleTargetRef:
+    apiVersion: apps/v1
+    kind: Deployment
+    name: {{{{ include "predict-otron-9000.fullname" $ }}}}-{name}
+  minReplicas: {{{{ .minReplicas }}}}
+  maxReplicas: {{{{ .maxReplicas }}}}
+  metrics:
+    {{{{- if .targetCPUUtilizationPercentage }}}}
+    - type: Resource
+      resource:
+        name: cpu
+        target:
+          type: Utilization
+          averageUtilization: {{{{ .targetCPUUtilizationPercentage }}}}
+    {{{{- end }}}}
+    {{{{- if .targetMemoryUtilizationPercentage }}}}
+    - type: Resource
+      resource:
+        name: memory
+        target:
+          type: Utilization
+          averageUtilization: {{{{ .targetMemoryUtilizationPercentage }}}}
+    {{{{- end }}}}
+{{{{- end }}}}
+{{{{- end }}}}
+"#,
+        name = service.name,
+        values = service.name.replace("-", "_"),
+    );
+
+    let filename = format!("{}-hpa.yaml", service.name);
+    fs::write(templates_dir.join(filename), hpa_template)?;
+    Ok(())
+}
+
+/// A Prometheus Operator ServiceMonitor, rendered only when `serviceMonitor.enabled` is set
+/// and the cluster serves the `monitoring.coreos.com/v1` API, so installs without the
+/// monitoring stack don't fail on the unknown kind.
+fn generate_service_monitor_template(templates_dir: &Path, service: &ServiceInfo) -> Result<()> {
+    let service_monitor_template = format!(
+        r#"{{{{- with .Values.{values}.serviceMonitor }}}}
+{{{{- if and .enabled ($.Capabilities.APIVersions.Has "monitoring.coreos.com/v1") }}}}
+apiVersion: monitoring.coreos.com/v1
+kind: ServiceMonitor
+metadata:
+  name: {{{{ include "predict-otron-9000.fullname" $ }}}}-{name}
+  labels:
+    {{{{- include "predict-otron-9000.labels" $ | nindent 4 }}}}
+    app.kubernetes.io/component: {name}
+    {{{{- with .labels }}}}
+    {{{{- toYaml . | nindent 4 }}}}
+    {{{{- end }}}}
+spec:
+  selector:
+    matchLabels:
+      {{{{- include "predict-otron-9000.selectorLabels" $ | nindent 6 }}}}
+      app.kubernetes.io/component: {name}
+  endpoints:
+    - port: {port}
+      path: {{{{ .path }}}}
+      interval: {{{{ .interval }}}}
+{{{{- end }}}}
+{{{{- end }}}}
+"#,
+        name = service.name,
+        values = service.name.replace("-", "_"),
+        port = service.metrics.port_name(),
+    );
+
+    let filename = format!("{}-servicemonitor.yaml", service.name);
+    fs::write(templates_dir.join(filename), service_monitor_template)?;
+    Ok(())
+}
+
+/// Denies ingress to every pod of the chart; the per-service policies below re-admit the
+/// traffic each service expects.
+fn generate_default_deny_template(templates_dir: &Path) -> Result<()> {
+    let default_deny_template = r#"{{- if .Values.networkPolicy.enabled }}
+apiVersion: networking.k8s.io/v1
+kind: NetworkPolicy
+metadata:
+  name: {{ include "predict-otron-9000.fullname" . }}-default-deny
+  labels:
+    {{- include "predict-otron-9000.labels" . | nindent 4 }}
+spec:
+  podSelector:
+    matchLabels:
+      {{- include "predict-otron-9000.selectorLabels" . | nindent 6 }}
+  policyTypes:
+    - Ingress
+{{- end }}
+"#;
+
+    fs::write(
+        templates_dir.join("networkpolicy-default-deny.yaml"),
+        default_deny_template,
+    )?;
+    Ok(())
+}
+
+/// Admits the service's `allow-from` peers, and the `networkPolicy.ingressFrom` peers when
+/// it sets `allow-ingress` or `routes`, to its `http` port. Services that allow neither get no policy and
+/// stay behind the default deny.
+fn generate_network_policy_template(templates_dir: &Path, service: &ServiceInfo) -> Result<()> {
+    if service.allow_from.is_empty() && !service.accepts_ingress() {
+        return Ok(());
+    }
+    let mut peers: String = service
+        .allow_from
+        .iter()
+        .map(|component| {
+            format!(
+                r#"
+        - podSelector:
+            matchLabels:
+              {{{{- include "predict-otron-9000.selectorLabels" . | nindent 14 }}}}
+              app.kubernetes.io/component: {}"#,
+                component
+            )
+        })
+        .collect();
+    if service.accepts_ingress() {
+        peers.push_str(
+            r#"
+        {{- with .Values.networkPolicy.ingressFrom }}
+        {{- toYaml . | nindent 8 }}
+        {{- end }}"#,
+        );
+    }
+    let network_policy_template = format!(
+        r#"{{{{- if .Values.networkPolicy.enabled }}}}
+apiVersion: networking.k8s.io/v1
+kind: NetworkPolicy
+metadata:
+  name: {{{{ include "predict-otron-9000.fullname" . }}}}-{name}
+  labels:
+    {{{{- include "predict-otron-9000.labels" . | nindent 4 }}}}
+    app.kubernetes.io/component: {name}
+spec:
+  podSelector:
+    matchLabels:
+      {{{{- include "predict-otron-9000.selectorLabels" . | nindent 6 }}}}
+      app.kubernetes.io/component: {name}
+  policyTypes:
+    - Ingress
+  ingress:
+    - from:{peers}
+      ports:
+        - port: http
+          protocol: TCP
+{{{{- end }}}}
+"#,
+        name = service.name,
+        peers = peers,
+    );
+
+    let filename = format!("{}-networkpolicy.yaml", service.name);
+    fs::write(templates_dir.join(filename), network_policy_template)?;
+    Ok(())
+}
+
 fn generate_ingress_template(templates_dir: &Path, _services: &[ServiceInfo]) -> Result<()> {
     let ingress_template = r#"{{- if .Values.ingress.enabled -}}
 apiVersion: networking.k8s.io/v1
@@ -517,3 +1487,406 @@ fn generate_helmignore(chart_dir: &Path) -> Result<()> {
     fs::write(chart_dir.join(".helmignore"), helmignore_content)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gpu_metadata_renders_into_values() {
+        let kube: KubeMetadata = toml::from_str(
+            r#"
+            image = "ghcr.io/geoffsee/predict-otron-9000:latest"
+            port = 8080
+            gpu = 1
+            gpu-type = "NVIDIA-A10G"
+            runtime-class = "nvidia"
+            "#,
+        )
+        .unwrap();
+        let mut service = ServiceInfo {
+            name: "inference-engine".to_string(),
+            image: kube.image,
+            cmd: Vec::new(),
+            port: kube.port,
+            replicas: 1,
+            gpu: kube.gpu,
+            gpu_type: kube.gpu_type,
+            runtime_class: kube.runtime_class,
+            cache: CacheVolume {
+                enabled: false,
+                size: DEFAULT_CACHE_SIZE.to_string(),
+                path: DEFAULT_CACHE_PATH.to_string(),
+                storage_class: None,
+            },
+            env: BTreeMap::new(),
+            secret_env: Vec::new(),
+            autoscaling: Autoscaling::from_metadata(None, 1),
+            liveness: Probe {
+                path: DEFAULT_HEALTH_PATH.to_string(),
+                initial_delay: 30,
+                period: 10,
+            },
+            readiness: Probe {
+                path: DEFAULT_HEALTH_PATH.to_string(),
+                initial_delay: 5,
+                period: 5,
+            },
+            metrics: Metrics {
+                enabled: false,
+                path: DEFAULT_METRICS_PATH.to_string(),
+                port: None,
+                interval: DEFAULT_METRICS_INTERVAL.to_string(),
+            },
+            allow_from: Vec::new(),
+            allow_ingress: false,
+            routes: Vec::new(),
+        };
+
+        let values = service_values(&service);
+        assert!(
+            values.contains("  runtimeClassName: \"nvidia\"\n"),
+            "{values}"
+        );
+        assert!(
+            values.contains("      cpu: \"1000m\"\n      nvidia.com/gpu: 1\n"),
+            "{values}"
+        );
+        assert!(
+            values.contains("  nodeSelector:\n    nvidia.com/gpu.product: \"NVIDIA-A10G\"\n"),
+            "{values}"
+        );
+
+        service.gpu = None;
+        service.gpu_type = None;
+        service.runtime_class = None;
+        let values = service_values(&service);
+        assert!(!values.contains("nvidia"), "{values}");
+        assert!(values.contains("  nodeSelector: {}\n"), "{values}");
+    }
+
+    #[test]
+    fn test_cache_probe_and_metrics_metadata_are_parsed() {
+        let dir = std::env::temp_dir().join(format!("helm-chart-tool-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let manifest = dir.join("Cargo.toml");
+        fs::write(
+            &manifest,
+            r#"
+            [package]
+            name = "inference-engine"
+
+            [package.metadata.kube]
+            image = "ghcr.io/geoffsee/predict-otron-9000:latest"
+            port = 8080
+            cache-size = "20Gi"
+            cache-storage-class = "fast-ssd"
+            health-path = "/v1/models"
+            readiness-initial-delay = 60
+            metrics-path = "/metrics"
+            metrics-port = 9090
+            "#,
+        )
+        .unwrap();
+        let service = parse_cargo_toml(&manifest).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(service.cache.enabled);
+        assert_eq!(service.cache.path, DEFAULT_CACHE_PATH);
+        assert_eq!(
+            service.readiness,
+            Probe {
+                path: "/v1/models".to_string(),
+                initial_delay: 60,
+                period: 5,
+            }
+        );
+        assert_eq!(service.liveness.path, "/v1/models");
+        assert!(service.metrics.enabled);
+        assert_eq!(service.metrics.port, Some(9090));
+        assert_eq!(service.metrics.port_name(), "metrics");
+        assert_eq!(service.metrics.interval, DEFAULT_METRICS_INTERVAL);
+        let values = service_values(&service);
+        assert!(
+            values.contains(
+                "  cache:\n    enabled: true\n    size: \"20Gi\"\n    storageClass: \"fast-ssd\"\n"
+            ),
+            "{values}"
+        );
+    }
+
+    #[test]
+    fn test_env_and_secret_metadata_render_into_values() {
+        let kube: KubeMetadata = toml::from_str(
+            r#"
+            image = "ghcr.io/geoffsee/predict-otron-9000:latest"
+            port = 8080
+            env = { SERVER_CONFIG = '{"serverMode":"Standalone"}', RUST_LOG = "info" }
+            secret-env = ["HF_TOKEN"]
+            "#,
+        )
+        .unwrap();
+        let env = kube.env.unwrap();
+        let secret_env = kube.secret_env.unwrap();
+        assert_eq!(
+            yaml_map(env.iter().map(|(k, v)| (k, v.as_str())), 4),
+            "\n    RUST_LOG: \"info\"\n    SERVER_CONFIG: \"{\\\"serverMode\\\":\\\"Standalone\\\"}\""
+        );
+        assert_eq!(
+            yaml_map(secret_env.iter().map(|k| (k, "")), 6),
+            "\n      HF_TOKEN: \"\""
+        );
+        assert_eq!(yaml_map(std::iter::empty(), 4), " {}");
+    }
+
+    #[test]
+    fn test_autoscaling_metadata_defaults() {
+        let kube: KubeMetadata = toml::from_str(
+            r#"
+            image = "ghcr.io/geoffsee/predict-otron-9000:latest"
+            port = 8080
+            replicas = 2
+
+            [autoscaling]
+            max-replicas = 6
+            target-memory = 70
+            "#,
+        )
+        .unwrap();
+        let autoscaling = Autoscaling::from_metadata(kube.autoscaling, 2);
+        assert!(autoscaling.enabled);
+        assert_eq!((autoscaling.min_replicas, autoscaling.max_replicas), (2, 6));
+        assert_eq!(autoscaling.target_cpu, None);
+        assert_eq!(autoscaling.target_memory, Some(70));
+
+        let disabled = Autoscaling::from_metadata(None, 1);
+        assert!(!disabled.enabled);
+        assert_eq!(disabled.target_cpu, Some(DEFAULT_TARGET_CPU));
+        let cpu_only = Autoscaling::from_metadata(
+            Some(AutoscalingMetadata {
+                min_replicas: None,
+                max_replicas: None,
+                target_cpu: None,
+                target_memory: None,
+            }),
+            1,
+        );
+        assert_eq!(cpu_only.target_cpu, Some(DEFAULT_TARGET_CPU));
+        assert_eq!(cpu_only.max_replicas, DEFAULT_MAX_REPLICAS);
+    }
+
+    #[test]
+    fn test_network_policy_admits_listed_peers() {
+        let dir = std::env::temp_dir().join(format!("helm-chart-netpol-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let manifest = dir.join("Cargo.toml");
+        fs::write(
+            &manifest,
+            r#"
+            [package]
+            name = "inference-engine"
+
+            [package.metadata.kube]
+            image = "ghcr.io/geoffsee/predict-otron-9000:latest"
+            port = 8080
+            allow-from = ["predict-otron-9000"]
+            "#,
+        )
+        .unwrap();
+        let mut service = parse_cargo_toml(&manifest).unwrap();
+        generate_network_policy_template(&dir, &service).unwrap();
+        let policy = fs::read_to_string(dir.join("inference-engine-networkpolicy.yaml")).unwrap();
+        assert!(
+            policy.contains("app.kubernetes.io/component: predict-otron-9000"),
+            "{policy}"
+        );
+        assert!(!policy.contains("ingressFrom"), "{policy}");
+
+        service.name = "isolated".to_string();
+        service.allow_from.clear();
+        generate_network_policy_template(&dir, &service).unwrap();
+        assert!(!dir.join("isolated-networkpolicy.yaml").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_versions_come_from_the_workspace() {
+        let dir = std::env::temp_dir().join(format!("helm-chart-version-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let manifest = dir.join("Cargo.toml");
+        fs::write(
+            &manifest,
+            "[workspace]\n\n[workspace.package]\nversion = \"0.1.6\"\n",
+        )
+        .unwrap();
+        assert_eq!(workspace_version(&manifest).unwrap(), "0.1.6");
+        fs::write(&manifest, "[workspace]\n").unwrap();
+        assert!(workspace_version(&manifest).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            split_image("ghcr.io/geoffsee/predict-otron-9000:0.1.6"),
+            ("ghcr.io/geoffsee/predict-otron-9000", "0.1.6")
+        );
+        assert_eq!(
+            split_image("registry:5000/predict-otron-9000"),
+            ("registry:5000/predict-otron-9000", "")
+        );
+    }
+
+    #[test]
+    fn test_compose_service_wires_dependencies() {
+        let kube: KubeMetadata = toml::from_str(
+            r#"
+            image = "ghcr.io/geoffsee/predict-otron-9000"
+            cmd = ["./bin/predict-otron-9000"]
+            port = 8080
+            secret-env = ["HF_TOKEN"]
+            allow-ingress = true
+            "#,
+        )
+        .unwrap();
+        let dir = std::env::temp_dir().join(format!("helm-chart-compose-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let manifest = dir.join("Cargo.toml");
+        fs::write(
+            &manifest,
+            r#"
+            [package]
+            name = "inference-engine"
+
+            [package.metadata.kube]
+            image = "ghcr.io/geoffsee/predict-otron-9000:0.1.5"
+            port = 8080
+            allow-from = ["predict-otron-9000"]
+            "#,
+        )
+        .unwrap();
+        let engine = parse_cargo_toml(&manifest).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let mut gateway = engine.clone();
+        gateway.name = "predict-otron-9000".to_string();
+        gateway.image = kube.image;
+        gateway.cmd = kube.cmd.unwrap();
+        gateway.secret_env = kube.secret_env.unwrap();
+        gateway.allow_from.clear();
+        gateway.allow_ingress = kube.allow_ingress.unwrap();
+        let versions = ChartVersions {
+            version: "0.1.6".to_string(),
+            app_version: "0.1.6".to_string(),
+            git_commit: None,
+        };
+        let services = [gateway, engine];
+
+        let gateway = compose_service(&services[0], &versions, &services);
+        assert!(
+            gateway.contains("image: ghcr.io/geoffsee/predict-otron-9000:0.1.6\n"),
+            "{gateway}"
+        );
+        assert!(
+            gateway.contains("command: [\"./bin/predict-otron-9000\"]\n"),
+            "{gateway}"
+        );
+        assert!(
+            gateway.contains("ports:\n      - \"8080:8080\"\n"),
+            "{gateway}"
+        );
+        assert!(gateway.contains("HF_TOKEN: \"${HF_TOKEN:-}\""), "{gateway}");
+        assert!(
+            gateway.contains("depends_on:\n      - inference-engine\n"),
+            "{gateway}"
+        );
+
+        let engine = compose_service(&services[1], &versions, &services);
+        assert!(
+            engine.contains("image: ghcr.io/geoffsee/predict-otron-9000:0.1.5\n"),
+            "{engine}"
+        );
+        assert!(engine.contains("expose:\n      - \"8080\"\n"), "{engine}");
+        assert!(!engine.contains("depends_on"), "{engine}");
+    }
+
+    #[test]
+    fn test_service_urls_resolve_per_format() {
+        let kube: KubeMetadata = toml::from_str(
+            r#"
+            image = "ghcr.io/geoffsee/predict-otron-9000"
+            port = 8080
+
+            [env]
+            RUST_LOG = "info"
+            SERVER_CONFIG = '{"services":{"inference_url":"${service.inference-engine}"}}'
+            "#,
+        )
+        .unwrap();
+        let dir = std::env::temp_dir().join(format!("helm-chart-env-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let manifest = dir.join("Cargo.toml");
+        fs::write(
+            &manifest,
+            r#"
+            [package]
+            name = "inference-engine"
+
+            [package.metadata.kube]
+            image = "ghcr.io/geoffsee/predict-otron-9000"
+            port = 9000
+            "#,
+        )
+        .unwrap();
+        let engine = parse_cargo_toml(&manifest).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let mut gateway = engine.clone();
+        gateway.name = "predict-otron-9000".to_string();
+        gateway.env = kube.env.unwrap();
+        let mut services = vec![gateway, engine];
+
+        let resolved = resolve_service_urls(&services, |target| {
+            format!("http://{}:{}", target.name, target.port)
+        })
+        .unwrap();
+        assert_eq!(resolved[0].env["RUST_LOG"], "info");
+        assert_eq!(
+            resolved[0].env["SERVER_CONFIG"],
+            r#"{"services":{"inference_url":"http://inference-engine:9000"}}"#
+        );
+
+        services[0]
+            .env
+            .insert("BROKEN".to_string(), "${service.missing}".to_string());
+        assert!(resolve_service_urls(&services, |target| target.name.clone()).is_err());
+    }
+
+    #[test]
+    fn test_ingress_paths_follow_routes() {
+        let dir = std::env::temp_dir().join(format!("helm-chart-routes-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let manifest = dir.join("Cargo.toml");
+        fs::write(
+            &manifest,
+            r#"
+            [package]
+            name = "embeddings-engine"
+
+            [package.metadata.kube]
+            image = "ghcr.io/geoffsee/predict-otron-9000"
+            port = 8080
+            routes = ["/v1/embeddings"]
+            "#,
+        )
+        .unwrap();
+        let embeddings = parse_cargo_toml(&manifest).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(embeddings.accepts_ingress());
+        let mut gateway = embeddings.clone();
+        gateway.name = "predict-otron-9000".to_string();
+        gateway.routes = vec!["/".to_string()];
+
+        let paths = ingress_paths(&[gateway, embeddings]);
+        let embeddings_at = paths.find("path: /v1/embeddings").unwrap();
+        let gateway_at = paths.find("path: /\n").unwrap();
+        assert!(embeddings_at < gateway_at, "{paths}");
+        assert!(paths.contains("name: embeddings-engine"), "{paths}");
+        assert_eq!(ingress_paths(&[]), " []");
+    }
+}
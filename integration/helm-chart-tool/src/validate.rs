@@ -0,0 +1,310 @@
+use crate::ServiceInfo;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+
+/// Labels every resource of a service must carry for selectors and policies to find it.
+const REQUIRED_LABELS: [&str; 3] = [
+    "app.kubernetes.io/name",
+    "app.kubernetes.io/instance",
+    "app.kubernetes.io/component",
+];
+
+/// Check the metadata, values.yaml and, when `helm` is on the PATH, the rendered templates
+/// of a generated chart, and fail with every problem found.
+pub(crate) fn validate_chart(chart_dir: &Path, services: &[ServiceInfo]) -> Result<()> {
+    let mut problems = check_metadata(services);
+    problems.extend(check_values(&chart_dir.join("values.yaml"), services)?);
+    match render(chart_dir)? {
+        Some(manifests) => problems.extend(check_manifests(&manifests, services)),
+        None => println!("helm not found on PATH; skipping checks on rendered templates"),
+    }
+    if problems.is_empty() {
+        return Ok(());
+    }
+    anyhow::bail!("Chart validation failed:\n  - {}", problems.join("\n  - "))
+}
+
+/// Problems in the metadata that would render fine but misbehave in the cluster.
+fn check_metadata(services: &[ServiceInfo]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let names: Vec<&str> = services
+        .iter()
+        .map(|service| service.name.as_str())
+        .collect();
+    let mut routes: HashMap<&str, &str> = HashMap::new();
+    for service in services {
+        for caller in &service.allow_from {
+            if !names.contains(&caller.as_str()) {
+                problems.push(format!(
+                    "{}: allow-from names unknown service {}; expected one of {}",
+                    service.name,
+                    caller,
+                    names.join(", ")
+                ));
+            }
+        }
+        for route in &service.routes {
+            if !route.starts_with('/') {
+                problems.push(format!(
+                    "{}: route {} must start with /",
+                    service.name, route
+                ));
+            }
+            if let Some(owner) = routes.insert(route, &service.name) {
+                problems.push(format!(
+                    "{}: route {} is also claimed by {}; give it to one service",
+                    service.name, route, owner
+                ));
+            }
+        }
+        let mut paths = vec![
+            ("health-path", &service.liveness.path),
+            ("readiness-path", &service.readiness.path),
+        ];
+        if service.metrics.enabled {
+            paths.push(("metrics-path", &service.metrics.path));
+        }
+        for (field, path) in paths {
+            if !path.starts_with('/') {
+                problems.push(format!(
+                    "{}: {} {} must start with /",
+                    service.name, field, path
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// Problems in values.yaml: invalid YAML, missing sections and ports that disagree with the
+/// metadata.
+fn check_values(values_path: &Path, services: &[ServiceInfo]) -> Result<Vec<String>> {
+    let content = fs::read_to_string(values_path)
+        .with_context(|| format!("Failed to read {:?}", values_path))?;
+    let values: Value = match serde_yaml::from_str(&content) {
+        Ok(values) => values,
+        Err(e) => return Ok(vec![format!("values.yaml is not valid YAML: {}", e)]),
+    };
+
+    let mut problems = Vec::new();
+    for service in services {
+        let key = service.name.replace("-", "_");
+        let Some(section) = values.get(&key) else {
+            problems.push(format!("values.yaml has no {} section", key));
+            continue;
+        };
+        match section["service"]["port"].as_u64() {
+            Some(port) if port == u64::from(service.port) => {}
+            Some(port) => problems.push(format!(
+                "values.yaml sets {}.service.port to {}, but the {} metadata port is {}",
+                key, port, service.name, service.port
+            )),
+            None => problems.push(format!("values.yaml has no {}.service.port", key)),
+        }
+        if section["image"]["repository"]
+            .as_str()
+            .is_none_or(str::is_empty)
+        {
+            problems.push(format!(
+                "values.yaml has no {}.image.repository; set image in the {} metadata",
+                key, service.name
+            ));
+        }
+    }
+    Ok(problems)
+}
+
+/// `helm template` output for the chart, or `None` when helm isn't installed.
+fn render(chart_dir: &Path) -> Result<Option<String>> {
+    let output = match process::Command::new("helm")
+        .arg("template")
+        .arg("validate")
+        .arg(chart_dir)
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context("Failed to run helm template"),
+    };
+    if !output.status.success() {
+        anyhow::bail!(
+            "helm template failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(Some(String::from_utf8(output.stdout)?))
+}
+
+/// Problems in rendered manifests: documents that aren't Kubernetes objects, resources
+/// missing their labels, and workloads that disagree with the metadata.
+fn check_manifests(manifests: &str, services: &[ServiceInfo]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut rendered: Vec<(String, String)> = Vec::new();
+    for (index, document) in serde_yaml::Deserializer::from_str(manifests).enumerate() {
+        let manifest = match Value::deserialize(document) {
+            Ok(Value::Null) => continue,
+            Ok(manifest) => manifest,
+            Err(e) => {
+                problems.push(format!(
+                    "rendered document {} is not valid YAML: {}",
+                    index, e
+                ));
+                continue;
+            }
+        };
+        let (Some(kind), Some(name)) = (
+            manifest["kind"].as_str(),
+            manifest["metadata"]["name"].as_str(),
+        ) else {
+            problems.push(format!(
+                "rendered document {} has no kind or metadata.name",
+                index
+            ));
+            continue;
+        };
+        let labels = &manifest["metadata"]["labels"];
+        let Some(component) = labels["app.kubernetes.io/component"].as_str() else {
+            continue;
+        };
+        for label in REQUIRED_LABELS {
+            if labels.get(label).is_none() {
+                problems.push(format!("{} {} is missing the {} label", kind, name, label));
+            }
+        }
+        rendered.push((kind.to_string(), component.to_string()));
+
+        let Some(service) = services.iter().find(|service| service.name == component) else {
+            continue;
+        };
+        match kind {
+            "Deployment" => {
+                let container_port = manifest["spec"]["template"]["spec"]["containers"][0]["ports"]
+                    .as_sequence()
+                    .and_then(|ports| ports.iter().find(|port| port["name"] == "http"))
+                    .and_then(|port| port["containerPort"].as_u64());
+                if container_port != Some(u64::from(service.port)) {
+                    problems.push(format!(
+                        "Deployment {} exposes http on {:?}, but the {} metadata port is {}",
+                        name, container_port, service.name, service.port
+                    ));
+                }
+            }
+            "Service" => {
+                let targets_http = manifest["spec"]["ports"]
+                    .as_sequence()
+                    .is_some_and(|ports| ports.iter().any(|port| port["targetPort"] == "http"));
+                if !targets_http {
+                    problems.push(format!(
+                        "Service {} has no port targeting the http container port",
+                        name
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for service in services {
+        for kind in ["Deployment", "Service"] {
+            if !rendered.contains(&(kind.to_string(), service.name.clone())) {
+                problems.push(format!("no {} rendered for {}", kind, service.name));
+            }
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_cargo_toml;
+
+    fn service(name: &str, extra: &str) -> ServiceInfo {
+        let dir = std::env::temp_dir().join(format!(
+            "helm-chart-validate-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let manifest = dir.join("Cargo.toml");
+        fs::write(
+            &manifest,
+            format!(
+                "[package]\nname = \"{}\"\n\n[package.metadata.kube]\nimage = \"ghcr.io/geoffsee/predict-otron-9000\"\nport = 8080\n{}",
+                name, extra
+            ),
+        )
+        .unwrap();
+        let service = parse_cargo_toml(&manifest).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        service
+    }
+
+    #[test]
+    fn test_metadata_problems_are_reported() {
+        let services = [
+            service("predict-otron-9000", "routes = [\"/\"]\n"),
+            service(
+                "inference-engine",
+                "allow-from = [\"gateway\"]\nroutes = [\"/\", \"v1/chat\"]\n",
+            ),
+        ];
+        let problems = check_metadata(&services);
+        assert_eq!(problems.len(), 3, "{problems:?}");
+        assert!(problems[0].contains("unknown service gateway"));
+        assert!(problems[1].contains("also claimed by predict-otron-9000"));
+        assert!(problems[2].contains("v1/chat must start with /"));
+    }
+
+    #[test]
+    fn test_rendered_manifests_match_metadata() {
+        let services = [service("inference-engine", "")];
+        let manifests = r#"
+---
+apiVersion: v1
+kind: Service
+metadata:
+  name: validate-predict-otron-9000-inference-engine
+  labels:
+    app.kubernetes.io/name: predict-otron-9000
+    app.kubernetes.io/instance: validate
+    app.kubernetes.io/component: inference-engine
+spec:
+  ports:
+    - port: 8080
+      targetPort: http
+---
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: validate-predict-otron-9000-inference-engine
+  labels:
+    app.kubernetes.io/name: predict-otron-9000
+    app.kubernetes.io/component: inference-engine
+spec:
+  template:
+    spec:
+      containers:
+        - name: inference-engine
+          ports:
+            - name: http
+              containerPort: 9000
+"#;
+        let problems = check_manifests(manifests, &services);
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].contains("missing the app.kubernetes.io/instance label"));
+        assert!(problems[1].contains("exposes http on Some(9000)"));
+
+        let problems = check_manifests("kind: ConfigMap\n", &services);
+        assert!(problems[0].contains("has no kind or metadata.name"));
+        assert!(problems
+            .iter()
+            .any(|p| p == "no Deployment rendered for inference-engine"));
+    }
+}
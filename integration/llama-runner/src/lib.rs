@@ -1,6 +1,10 @@
 pub mod llama_api;
 
-pub use llama_api::{run_llama_inference, LlamaInferenceConfig, WhichModel};
+pub use llama_api::{
+    cached_context_length, cached_models, clear_model_cache, download_model, evict_cached_model,
+    evict_idle_model, evict_model, idle_models, run_llama_inference, ChatTemplate,
+    LlamaInferenceConfig, LlamaRunner, WhichModel,
+};
 
 // Re-export constants and types that might be needed
 pub const EOS_TOKEN: &str = "</s>";
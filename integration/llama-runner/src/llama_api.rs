@@ -1,14 +1,26 @@
 use crate::EOS_TOKEN;
 use anyhow::{bail, Error as E};
-use candle_core::{utils, DType, Device, Tensor};
+use candle_core::quantized::{gguf_file, GgmlDType};
+use candle_core::{DType, Device, DeviceLocation, Tensor};
 use candle_nn::VarBuilder;
 use candle_transformers::generation::{LogitsProcessor, Sampling};
 use candle_transformers::models::llama as model;
 use candle_transformers::models::llama::{Llama, LlamaConfig};
+use candle_transformers::models::quantized_llama::{self, ModelWeights as QLlama};
 use clap::ValueEnum;
 use hf_hub::api::sync::Api;
 use hf_hub::{Repo, RepoType};
-use std::sync::mpsc::{self, Receiver};
+use runner_core::{
+    apply_frequency_penalty, ban_repeated_ngrams, configure_threads, hub_api,
+    safetensors_parameter_count, token_channel, CacheKey, CancelHandle, CancelToken, ChatMessage,
+    ContextPolicy, ConversationCache, DeviceSpec, DownloadProgress, FinishReason,
+    GenerationRequest, HubFiles, JinjaChatTemplate, LocalFiles, ModelCache, ModelFiles,
+    ModelRunner, Perplexity, Role, RunnerError, RunnerMetadata, SamplingPreset, StopCheck,
+    StopSequences, TokenEvent, TokenReceiver, DEFAULT_PARALLEL_DOWNLOADS, TOKENIZER_CONFIG,
+};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
 
 #[derive(Clone, Debug, Copy, PartialEq, Eq, ValueEnum, Default)]
 pub enum WhichModel {
@@ -21,6 +33,12 @@ pub enum WhichModel {
     Llama32_3B,
     #[value(name = "llama-3.2-3b-instruct")]
     Llama32_3BInstruct,
+    #[value(name = "llama-3.1-8b")]
+    Llama31_8B,
+    #[value(name = "llama-3.1-8b-instruct")]
+    Llama31_8BInstruct,
+    #[value(name = "llama-3.3-70b-instruct")]
+    Llama33_70BInstruct,
     #[value(name = "smollm2-135m")]
     SmolLM2_135M,
     #[value(name = "smollm2-135m-instruct")]
@@ -35,6 +53,152 @@ pub enum WhichModel {
     SmolLM2_1_7BInstruct,
     #[value(name = "tinyllama-1.1b-chat")]
     TinyLlama1_1BChat,
+    #[value(name = "llama-3.2-1b-instruct-q4_k_m")]
+    Llama32_1BInstructQ4KM,
+    #[value(name = "llama-3.2-3b-instruct-q4_k_m")]
+    Llama32_3BInstructQ4KM,
+}
+
+impl WhichModel {
+    /// Prompt format the model was tuned on, `None` for base models.
+    pub fn chat_template(&self) -> Option<ChatTemplate> {
+        match self {
+            WhichModel::Llama32_1BInstruct
+            | WhichModel::Llama32_3BInstruct
+            | WhichModel::Llama31_8BInstruct
+            | WhichModel::Llama33_70BInstruct
+            | WhichModel::Llama32_1BInstructQ4KM
+            | WhichModel::Llama32_3BInstructQ4KM => Some(ChatTemplate::Llama3),
+            WhichModel::SmolLM2_135MInstruct
+            | WhichModel::SmolLM2_360MInstruct
+            | WhichModel::SmolLM2_1_7BInstruct => Some(ChatTemplate::ChatMl),
+            WhichModel::TinyLlama1_1BChat => Some(ChatTemplate::Zephyr),
+            WhichModel::Llama32_1B
+            | WhichModel::Llama32_3B
+            | WhichModel::Llama31_8B
+            | WhichModel::SmolLM2_135M
+            | WhichModel::SmolLM2_360M
+            | WhichModel::SmolLM2_1_7B => None,
+        }
+    }
+
+    /// GGUF checkpoint (`owner/repo/file.gguf`) used for the quantized variants.
+    fn default_gguf(&self) -> Option<&'static str> {
+        match self {
+            WhichModel::Llama32_1BInstructQ4KM => {
+                Some("bartowski/Llama-3.2-1B-Instruct-GGUF/Llama-3.2-1B-Instruct-Q4_K_M.gguf")
+            }
+            WhichModel::Llama32_3BInstructQ4KM => {
+                Some("bartowski/Llama-3.2-3B-Instruct-GGUF/Llama-3.2-3B-Instruct-Q4_K_M.gguf")
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the safetensors checkpoint is split into shards listed in an index file, as
+    /// checkpoints above ~2B parameters are.
+    pub fn is_sharded(&self) -> bool {
+        !matches!(
+            self,
+            WhichModel::Llama32_1B
+                | WhichModel::Llama32_1BInstruct
+                | WhichModel::SmolLM2_135M
+                | WhichModel::SmolLM2_135MInstruct
+                | WhichModel::SmolLM2_360M
+                | WhichModel::SmolLM2_360MInstruct
+                | WhichModel::SmolLM2_1_7B
+                | WhichModel::SmolLM2_1_7BInstruct
+                | WhichModel::TinyLlama1_1BChat
+        )
+    }
+
+    /// HuggingFace repository the unquantized weights and tokenizer come from.
+    pub fn repo_id(&self) -> &'static str {
+        match self {
+            Self::Llama32_1B => "meta-llama/Llama-3.2-1B",
+            Self::Llama32_1BInstruct | Self::Llama32_1BInstructQ4KM => {
+                "meta-llama/Llama-3.2-1B-Instruct"
+            }
+            Self::Llama32_3B => "meta-llama/Llama-3.2-3B",
+            Self::Llama32_3BInstruct | Self::Llama32_3BInstructQ4KM => {
+                "meta-llama/Llama-3.2-3B-Instruct"
+            }
+            Self::Llama31_8B => "meta-llama/Llama-3.1-8B",
+            Self::Llama31_8BInstruct => "meta-llama/Llama-3.1-8B-Instruct",
+            Self::Llama33_70BInstruct => "meta-llama/Llama-3.3-70B-Instruct",
+            Self::SmolLM2_135M => "HuggingFaceTB/SmolLM2-135M",
+            Self::SmolLM2_135MInstruct => "HuggingFaceTB/SmolLM2-135M-Instruct",
+            Self::SmolLM2_360M => "HuggingFaceTB/SmolLM2-360M",
+            Self::SmolLM2_360MInstruct => "HuggingFaceTB/SmolLM2-360M-Instruct",
+            Self::SmolLM2_1_7B => "HuggingFaceTB/SmolLM2-1.7B",
+            Self::SmolLM2_1_7BInstruct => "HuggingFaceTB/SmolLM2-1.7B-Instruct",
+            Self::TinyLlama1_1BChat => "TinyLlama/TinyLlama-1.1B-Chat-v1.0",
+        }
+    }
+}
+
+/// Beginning-of-text marker of the Llama 3 tokenizer.
+const LLAMA3_BOS: &str = "<|begin_of_text|>";
+
+/// End-of-sequence and end-of-turn tokens of the supported tokenizers. Those present in the
+/// loaded vocabulary end a generation.
+const STOP_TOKENS: [&str; 5] = [
+    EOS_TOKEN,
+    "<|end_of_text|>",
+    "<|eot_id|>",
+    "<|endoftext|>",
+    "<|im_end|>",
+];
+
+/// Chat prompt formats of the instruct models.
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum ChatTemplate {
+    /// Llama 3.x: `<|start_header_id|>role<|end_header_id|>` headers, turns end with
+    /// `<|eot_id|>`.
+    Llama3,
+    /// SmolLM2: `<|im_start|>role` ... `<|im_end|>`.
+    ChatMl,
+    /// TinyLlama chat: `<|role|>` ... `</s>`.
+    Zephyr,
+}
+
+impl ChatTemplate {
+    /// Render `messages`, ending with an open assistant turn for the model to complete.
+    pub fn render(&self, messages: &[ChatMessage]) -> String {
+        let mut prompt = String::new();
+        if *self == ChatTemplate::Llama3 {
+            prompt.push_str(LLAMA3_BOS);
+        }
+        for message in messages {
+            prompt.push_str(&self.header(message.role));
+            prompt.push_str(&message.content);
+            prompt.push_str(self.end_of_turn());
+            // Llama 3 starts the next header right after `<|eot_id|>`.
+            if *self != ChatTemplate::Llama3 {
+                prompt.push('\n');
+            }
+        }
+        prompt.push_str(&self.header(Role::Assistant));
+        prompt
+    }
+
+    /// Markup opening a turn by `role`.
+    fn header(&self, role: Role) -> String {
+        match self {
+            ChatTemplate::Llama3 => format!("<|start_header_id|>{role}<|end_header_id|>\n\n"),
+            ChatTemplate::ChatMl => format!("<|im_start|>{role}\n"),
+            ChatTemplate::Zephyr => format!("<|{role}|>\n"),
+        }
+    }
+
+    /// Token closing every turn.
+    pub fn end_of_turn(&self) -> &'static str {
+        match self {
+            ChatTemplate::Llama3 => "<|eot_id|>",
+            ChatTemplate::ChatMl => "<|im_end|>",
+            ChatTemplate::Zephyr => EOS_TOKEN,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -43,6 +207,9 @@ pub struct LlamaInferenceConfig {
 
     pub model: WhichModel,
     pub cpu: bool,
+    /// Device to load the model on, e.g. `cuda:1` to place it on the second GPU. `cpu`
+    /// overrides it.
+    pub device: DeviceSpec,
     pub temperature: f64,
     pub top_p: Option<f64>,
     pub top_k: Option<usize>,
@@ -52,9 +219,45 @@ pub struct LlamaInferenceConfig {
     pub dtype: Option<String>,
     pub model_id: Option<String>,
     pub revision: Option<String>,
+    /// Use flash attention in the safetensors models. Needs a CUDA device, an `f16` or
+    /// `bf16` dtype and llama-runner built with the `flash-attn` feature; GGUF checkpoints
+    /// ignore it.
     pub use_flash_attn: bool,
     pub repeat_penalty: f32,
     pub repeat_last_n: usize,
+    /// Never repeat an n-gram of this many tokens, counting the prompt. `0` disables it.
+    pub no_repeat_ngram_size: usize,
+    /// Lower each token's logit by this much per time it was already generated, like
+    /// OpenAI's `frequency_penalty`. `0.0` disables it.
+    pub frequency_penalty: f32,
+    /// Generation stops before any of these strings; the stop text is not emitted.
+    pub stop: Vec<String>,
+    /// Number of conversations whose KV state is retained between turns. Taken from the
+    /// config that first loads a model; `0` disables reuse.
+    pub max_conversations: usize,
+    /// Load a GGUF quantized checkpoint instead of the safetensors weights: a local
+    /// `.gguf` file or `owner/repo/file.gguf` on the HuggingFace Hub. The quantized model
+    /// ids pick one by default; the tokenizer still comes from `model_id`.
+    pub gguf: Option<String>,
+    /// What to do when the prompt plus `max_tokens` exceeds the model's context window.
+    pub context_policy: ContextPolicy,
+    /// Receives per-file progress while model files are downloaded from the Hub.
+    pub download_progress: Option<DownloadProgress>,
+    /// Number of safetensors shards downloaded at the same time, for split checkpoints.
+    pub parallel_downloads: usize,
+    /// Load the model from this directory instead of the Hub. It must hold `tokenizer.json`
+    /// plus `config.json` and the safetensors weights, or a single `.gguf` file for the
+    /// quantized model ids. `model` still selects the architecture.
+    pub model_path: Option<PathBuf>,
+    /// Conversation to complete with [`run_llama_inference`]. When set, it is rendered with
+    /// the model's [`ChatTemplate`] and `prompt` is ignored.
+    pub messages: Vec<ChatMessage>,
+    /// CPU threads for tensor operations. `None` reads `RUNNER_THREADS`, then defaults to
+    /// one per physical core. The thread pool is process-wide: the first load sets it.
+    pub threads: Option<usize>,
+    /// Run a short generation right after the weights are loaded, so the first request does
+    /// not pay for kernel compilation. Skipped when the model comes from the cache.
+    pub warmup: bool,
 }
 
 impl LlamaInferenceConfig {
@@ -63,6 +266,7 @@ impl LlamaInferenceConfig {
             prompt: String::new(),
             model,
             cpu: false,
+            device: DeviceSpec::Auto,
             temperature: 1.0,
             top_p: None,
             top_k: None,
@@ -72,9 +276,33 @@ impl LlamaInferenceConfig {
             dtype: None,
             model_id: None,
             revision: None,
-            use_flash_attn: true,
+            use_flash_attn: false,
             repeat_penalty: 1.1,
             repeat_last_n: 64,
+            stop: Vec::new(),
+            max_conversations: 8,
+            gguf: None,
+            context_policy: ContextPolicy::default(),
+            download_progress: None,
+            parallel_downloads: DEFAULT_PARALLEL_DOWNLOADS,
+            model_path: None,
+            messages: Vec::new(),
+            threads: None,
+            warmup: false,
+            no_repeat_ngram_size: 0,
+            frequency_penalty: 0.0,
+        }
+    }
+
+    /// Replace the sampling settings with those of `preset`.
+    pub fn with_preset(self, preset: SamplingPreset) -> Self {
+        let settings = preset.settings();
+        Self {
+            temperature: settings.temperature,
+            top_p: settings.top_p,
+            top_k: settings.top_k,
+            repeat_penalty: settings.repeat_penalty,
+            ..self
         }
     }
 }
@@ -89,6 +317,7 @@ impl Default for LlamaInferenceConfig {
 
             // Prefer GPU if available.
             cpu: false,
+            device: DeviceSpec::Auto,
 
             // Sampling: balanced + stable
             temperature: 0.7,
@@ -103,7 +332,7 @@ impl Default for LlamaInferenceConfig {
 
             // Performance flags
             no_kv_cache: false,    // keep cache ON for speed
-            use_flash_attn: false, // great speed boost if supported
+            use_flash_attn: false, // off: needs the `flash-attn` feature and CUDA
 
             // Precision: bf16 is a good default on Ampere+; fallback to fp16 if needed.
             dtype: Some("bf16".to_string()),
@@ -115,240 +344,1191 @@ impl Default for LlamaInferenceConfig {
             // Anti-repeat heuristics
             repeat_penalty: 1.15,
             repeat_last_n: 128,
+
+            // No stop sequences beyond EOS unless the caller asks for them.
+            stop: Vec::new(),
+            max_conversations: 8,
+
+            // Full-precision weights unless a quantized model is selected.
+            gguf: None,
+
+            // Fail fast rather than silently dropping part of the prompt.
+            context_policy: ContextPolicy::default(),
+            download_progress: None,
+            parallel_downloads: DEFAULT_PARALLEL_DOWNLOADS,
+            model_path: None,
+            messages: Vec::new(),
+            threads: None,
+            warmup: false,
+            no_repeat_ngram_size: 0,
+            frequency_penalty: 0.0,
         }
     }
 }
 
-fn device(cpu: bool) -> anyhow::Result<Device> {
-    if cpu {
-        Ok(Device::Cpu)
-    } else if utils::cuda_is_available() {
-        Ok(Device::new_cuda(0)?)
-    } else if utils::metal_is_available() {
-        Ok(Device::new_metal(0)?)
-    } else {
-        Ok(Device::Cpu)
+/// Short name of `device` for metadata, e.g. `cuda:0`.
+fn device_name(device: &Device) -> String {
+    match device.location() {
+        DeviceLocation::Cpu => "cpu".to_string(),
+        DeviceLocation::Cuda { gpu_id } => format!("cuda:{gpu_id}"),
+        DeviceLocation::Metal { gpu_id } => format!("metal:{gpu_id}"),
     }
 }
 
-fn hub_load_safetensors(
-    api: &hf_hub::api::sync::ApiRepo,
-    json_file: &str,
-) -> anyhow::Result<Vec<std::path::PathBuf>> {
-    let json_file = api.get(json_file)?;
-    let json_file = std::fs::File::open(json_file)?;
-    let json: serde_json::Value = serde_json::from_reader(&json_file)?;
-    let weight_map = match json.get("weight_map") {
-        None => bail!("no weight map in {json_file:?}"),
-        Some(serde_json::Value::Object(map)) => map,
-        Some(_) => bail!("weight map in {json_file:?} is not a map"),
-    };
-    let mut safetensors_files = std::collections::HashSet::new();
-    for value in weight_map.values() {
-        if let Some(file) = value.as_str() {
-            safetensors_files.insert(file.to_string());
-        }
-    }
-    let safetensors_files = safetensors_files
-        .iter()
-        .map(|v| api.get(v))
-        .collect::<anyhow::Result<Vec<_>, _>>()?;
-    Ok(safetensors_files)
-}
-
-pub fn run_llama_inference(
-    cfg: LlamaInferenceConfig,
-) -> anyhow::Result<Receiver<anyhow::Result<String>>, anyhow::Error> {
-    // ---- Device & dtype -----------------------------------------------------
-    let device = device(cfg.cpu)?;
-    println!("Device: {:?}", device);
-
-    let dtype = match cfg.dtype.as_deref() {
-        Some("f16") => DType::F16,
-        Some("bf16") => DType::BF16,
-        Some("f32") => DType::F32,
-        Some(dtype) => bail!("Unsupported dtype {dtype}"),
-        None => DType::F16,
-    };
-    println!("Using dtype: {:?}", dtype);
-
-    // ---- Load model & tokenizer --------------------------------------------
-    let (llama, tokenizer, mut cache) = {
-        let api = Api::new()?;
-        let model_id = cfg.model_id.clone().unwrap_or_else(|| {
-            match cfg.model {
-                WhichModel::Llama32_1B => "meta-llama/Llama-3.2-1B",
-                WhichModel::Llama32_1BInstruct => "meta-llama/Llama-3.2-1B-Instruct",
-                WhichModel::Llama32_3B => "meta-llama/Llama-3.2-3B",
-                WhichModel::Llama32_3BInstruct => "meta-llama/Llama-3.2-3B-Instruct",
-                WhichModel::SmolLM2_135M => "HuggingFaceTB/SmolLM2-135M",
-                WhichModel::SmolLM2_135MInstruct => "HuggingFaceTB/SmolLM2-135M-Instruct",
-                WhichModel::SmolLM2_360M => "HuggingFaceTB/SmolLM2-360M",
-                WhichModel::SmolLM2_360MInstruct => "HuggingFaceTB/SmolLM2-360M-Instruct",
-                WhichModel::SmolLM2_1_7B => "HuggingFaceTB/SmolLM2-1.7B",
-                WhichModel::SmolLM2_1_7BInstruct => "HuggingFaceTB/SmolLM2-1.7B-Instruct",
-                WhichModel::TinyLlama1_1BChat => "TinyLlama/TinyLlama-1.1B-Chat-v1.0",
+/// Quantization holding most of the weights of a GGUF checkpoint, e.g. `q4k`.
+fn gguf_weight_type(content: &gguf_file::Content) -> String {
+    let mut totals: Vec<(GgmlDType, usize)> = Vec::new();
+    for info in content.tensor_infos.values() {
+        match totals
+            .iter_mut()
+            .find(|(dtype, _)| *dtype == info.ggml_dtype)
+        {
+            Some((_, total)) => *total += info.shape.elem_count(),
+            None => totals.push((info.ggml_dtype, info.shape.elem_count())),
+        }
+    }
+    totals
+        .into_iter()
+        .max_by_key(|(_, total)| *total)
+        .map_or_else(
+            || "gguf".to_string(),
+            |(dtype, _)| format!("{dtype:?}").to_lowercase(),
+        )
+}
+
+/// Log probability of `token` under the distribution described by `logits`.
+fn token_logprob(logits: &Tensor, token: u32) -> anyhow::Result<f32> {
+    let log_probs = candle_nn::ops::log_softmax(logits, candle_core::D::Minus1)?;
+    Ok(log_probs
+        .get(token as usize)?
+        .to_dtype(DType::F32)?
+        .to_scalar::<f32>()?)
+}
+
+/// Resolve a GGUF checkpoint given as a local path or as `owner/repo/file.gguf`.
+fn gguf_path(
+    api: &Api,
+    source: &str,
+    progress: Option<DownloadProgress>,
+) -> anyhow::Result<PathBuf> {
+    let path = Path::new(source);
+    if path.exists() {
+        return Ok(path.to_path_buf());
+    }
+    match source.rsplit_once('/') {
+        Some((repo, file)) if repo.contains('/') => {
+            HubFiles::new(api, Repo::model(repo.to_string()), progress).get(file)
+        }
+        _ => bail!("GGUF checkpoint {source} is neither a local file nor owner/repo/file.gguf"),
+    }
+}
+
+enum Weights {
+    Full { llama: Llama, config: model::Config },
+    Quantized(QLlama),
+}
+
+/// Weights and tokenizer shared by every runner loaded with the same cache key.
+struct LoadedModel {
+    weights: Weights,
+    tokenizer: tokenizers::Tokenizer,
+    device: Device,
+    /// Maximum number of positions the model supports.
+    context_length: usize,
+    /// Number of weights in the checkpoint.
+    parameter_count: u64,
+    /// Dtype of the weights, or the quantization of a GGUF checkpoint.
+    weight_type: String,
+    /// Chat template from the model's `tokenizer_config.json`, if it ships one.
+    chat_template: Option<JinjaChatTemplate>,
+    /// Generators whose KV cache holds a previous turn, keyed by conversation id.
+    conversations: ConversationCache<Generator>,
+}
+
+/// Model state owned by one generation. The full-precision model shares its weights and
+/// gets a fresh KV cache; the quantized model keeps its cache inside its own copy.
+enum Generator {
+    Full {
+        llama: Llama,
+        cache: model::Cache,
+        empty: model::Cache,
+    },
+    Quantized(QLlama),
+}
+
+impl Generator {
+    fn forward(&mut self, input: &Tensor, index_pos: usize) -> candle_core::Result<Tensor> {
+        match self {
+            Self::Full { llama, cache, .. } => llama.forward(input, index_pos, cache),
+            // The quantized model replaces its cache whenever a forward starts at position 0.
+            Self::Quantized(m) => m.forward(input, index_pos),
+        }
+    }
+
+    /// Drop everything in the KV cache so the next forward starts again at position 0.
+    fn clear_kv_cache(&mut self) {
+        match self {
+            Self::Full { cache, empty, .. } => *cache = empty.clone(),
+            Self::Quantized(_) => {}
+        }
+    }
+}
+
+static MODEL_CACHE: LazyLock<ModelCache<LoadedModel>> = LazyLock::new(ModelCache::new);
+
+/// Drop every cached copy of a model repository (e.g. `meta-llama/Llama-3.2-1B-Instruct`).
+/// Returns how many entries were removed.
+pub fn evict_model(model_id: &str) -> usize {
+    MODEL_CACHE.evict_model(model_id)
+}
+
+/// Drop every cached Llama model.
+pub fn clear_model_cache() {
+    MODEL_CACHE.clear()
+}
+
+/// Models currently held in the cache.
+pub fn cached_models() -> Vec<CacheKey> {
+    MODEL_CACHE.keys()
+}
+
+/// Cached models no runner is using, with when each was last loaded.
+pub fn idle_models() -> Vec<(CacheKey, std::time::Instant)> {
+    MODEL_CACHE.idle()
+}
+
+/// Drop a cached model if no runner is using it. Returns whether it was dropped.
+pub fn evict_idle_model(key: &CacheKey) -> bool {
+    MODEL_CACHE.evict_if_idle(key)
+}
+
+/// Download the files [`LlamaRunner::load`] reads for `model` from the hub into the local
+/// cache without loading them, e.g. to prepare a machine that will run offline: the
+/// tokenizer and either the safetensors checkpoint or, for the quantized variants, the GGUF
+/// file. Returns their paths in the cache, named as a local model directory expects them.
+pub fn download_model(
+    model: WhichModel,
+    progress: Option<DownloadProgress>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let api = hub_api()?;
+    let files = HubFiles::new(
+        &api,
+        Repo::model(model.repo_id().to_string()),
+        progress.clone(),
+    );
+    let mut paths = vec![files.get("tokenizer.json")?];
+    // Not every repository ships one; without it the built-in prompt format is used
+    paths.extend(files.get(TOKENIZER_CONFIG).ok());
+    match model.default_gguf() {
+        Some(gguf) => paths.push(gguf_path(&api, gguf, progress)?),
+        None => {
+            paths.push(files.get("config.json")?);
+            paths.extend(files.get_checkpoint(model.is_sharded())?);
+        }
+    }
+    Ok(paths)
+}
+
+/// Context length of `model` if it is in the cache: its hub weights, or its GGUF checkpoint
+/// for the quantized variants.
+pub fn cached_context_length(model: WhichModel) -> Option<usize> {
+    let source = model.default_gguf().unwrap_or(model.repo_id());
+    MODEL_CACHE
+        .get_model(source)
+        .map(|loaded| loaded.context_length)
+}
+
+/// Drop the cached copies of `model`, as [`cached_context_length`] finds them. Returns how
+/// many were dropped.
+pub fn evict_cached_model(model: WhichModel) -> usize {
+    MODEL_CACHE.evict_model(model.default_gguf().unwrap_or(model.repo_id()))
+}
+
+/// candle's flash attention kernels are CUDA-only half-precision kernels, and without the
+/// `flash-attn` feature the attention call is a stub that panics, so refuse up front.
+fn check_flash_attn(device: &Device, dtype: DType) -> Result<(), RunnerError> {
+    if !cfg!(feature = "flash-attn") {
+        return Err(RunnerError::UnsupportedDevice(
+            "flash attention needs llama-runner built with the `flash-attn` feature".to_string(),
+        ));
+    }
+    if !device.is_cuda() {
+        return Err(RunnerError::UnsupportedDevice(
+            "flash attention runs on CUDA devices only".to_string(),
+        ));
+    }
+    if dtype == DType::F32 {
+        return Err(RunnerError::Load(anyhow::anyhow!(
+            "flash attention needs an f16 or bf16 dtype"
+        )));
+    }
+    Ok(())
+}
+
+fn load_model(
+    files: &ModelFiles,
+    cfg: &LlamaInferenceConfig,
+    dtype: DType,
+    device: Device,
+) -> anyhow::Result<LoadedModel> {
+    let tokenizer_filename = files.get("tokenizer.json")?;
+    let config_filename = files.get("config.json")?;
+    let config: LlamaConfig = serde_json::from_slice(&std::fs::read(config_filename)?)?;
+    if cfg.use_flash_attn {
+        check_flash_attn(&device, dtype)?;
+    }
+    let model_config = config.into_config(cfg.use_flash_attn);
+
+    let filenames = files.safetensors(cfg.model.is_sharded())?;
+    let parameter_count = safetensors_parameter_count(&filenames)?;
+
+    let vb = unsafe { VarBuilder::from_mmaped_safetensors(&filenames, dtype, &device)? };
+    let llama = Llama::load(vb, &model_config)?;
+    let tokenizer = tokenizers::Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
+
+    Ok(LoadedModel {
+        context_length: model_config.max_position_embeddings,
+        weights: Weights::Full {
+            llama,
+            config: model_config,
+        },
+        tokenizer,
+        device,
+        parameter_count,
+        weight_type: dtype.as_str().to_string(),
+        // Base models continue their prompt, even where the repository ships a template
+        chat_template: cfg
+            .model
+            .chat_template()
+            .and_then(|_| JinjaChatTemplate::from_model_files(files)),
+        conversations: ConversationCache::new(cfg.max_conversations),
+    })
+}
+
+/// Build a GGUF quantized Llama model. The tokenizer comes from the unquantized repository.
+fn load_quantized_model(
+    files: &ModelFiles,
+    model_path: &Path,
+    device: Device,
+    max_conversations: usize,
+) -> anyhow::Result<LoadedModel> {
+    let tokenizer_filename = files.get("tokenizer.json")?;
+    let tokenizer = tokenizers::Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
+
+    let mut file = std::fs::File::open(model_path)?;
+    let content = gguf_file::Content::read(&mut file).map_err(|e| e.with_path(model_path))?;
+    let parameter_count = content
+        .tensor_infos
+        .values()
+        .map(|info| info.shape.elem_count() as u64)
+        .sum();
+    let weight_type = gguf_weight_type(&content);
+    let model = QLlama::from_gguf(content, &mut file, &device)?;
+
+    Ok(LoadedModel {
+        weights: Weights::Quantized(model),
+        tokenizer,
+        device,
+        context_length: quantized_llama::MAX_SEQ_LEN,
+        parameter_count,
+        weight_type,
+        chat_template: JinjaChatTemplate::from_model_files(files),
+        conversations: ConversationCache::new(max_conversations),
+    })
+}
+
+/// A loaded Llama-architecture model. Weights are read once per model id, dtype and device
+/// and shared through the model cache; every generation gets its own KV cache.
+pub struct LlamaRunner {
+    loaded: Arc<LoadedModel>,
+    dtype: DType,
+    config: LlamaInferenceConfig,
+    metadata: RunnerMetadata,
+    cancel: CancelHandle,
+}
+
+/// Delivers one sequence's events; returns `false` once nobody is listening.
+type EventSink = Box<dyn Fn(Result<TokenEvent, RunnerError>) -> bool + Send>;
+
+/// Settings shared by every sequence of a batch.
+#[derive(Clone)]
+struct BatchParams {
+    stop_token_ids: Vec<u32>,
+    repeat_penalty: f32,
+    repeat_last_n: usize,
+    no_repeat_ngram_size: usize,
+    frequency_penalty: f32,
+    use_kv_cache: bool,
+    context_length: usize,
+}
+
+/// One sequence of a batch, with its own sampler and stop sequences.
+struct Row {
+    tokens: Vec<u32>,
+    max_tokens: usize,
+    generated: usize,
+    logits_processor: LogitsProcessor,
+    stop_sequences: StopSequences,
+    /// Set once the row has finished; its slot keeps being fed so the batch stays
+    /// rectangular.
+    done: bool,
+    send: EventSink,
+}
+
+impl Row {
+    /// Close the stream, releasing text held back as a possible stop sequence that never
+    /// completed.
+    fn finish(&mut self, reason: FinishReason) {
+        let text = self.stop_sequences.flush();
+        let _ = (self.send)(Ok(TokenEvent::finished(reason, text)));
+        self.done = true;
+    }
+
+    /// Forward an error. No final event is sent after it.
+    fn fail(&mut self, error: anyhow::Error) {
+        let _ = (self.send)(Err(RunnerError::generation(error)));
+        self.done = true;
+    }
+
+    /// Sample the next token from this row's logits and stream its text.
+    fn step(
+        &mut self,
+        logits: &Tensor,
+        tokenizer: &tokenizers::Tokenizer,
+        params: &BatchParams,
+    ) -> anyhow::Result<()> {
+        let logits = if params.repeat_penalty == 1. {
+            logits.clone()
+        } else {
+            let start_at = self.tokens.len().saturating_sub(params.repeat_last_n);
+            candle_transformers::utils::apply_repeat_penalty(
+                logits,
+                params.repeat_penalty,
+                &self.tokens[start_at..],
+            )?
+        };
+        let logits = ban_repeated_ngrams(&logits, &self.tokens, params.no_repeat_ngram_size)?;
+        let generated = &self.tokens[self.tokens.len() - self.generated..];
+        let logits = apply_frequency_penalty(&logits, generated, params.frequency_penalty)?;
+
+        let next_token = self.logits_processor.sample(&logits)?;
+        let logprob = token_logprob(&logits, next_token).ok();
+        self.tokens.push(next_token);
+        self.generated += 1;
+
+        // Early stop on EOS or end of turn.
+        if params.stop_token_ids.contains(&next_token) {
+            self.finish(FinishReason::Stop);
+            return Ok(());
+        }
+
+        // Decode this token's text and stream it out, holding back anything that may be
+        // the start of a stop sequence.
+        let text = tokenizer.decode(&[next_token], false).map_err(E::msg)?;
+        match self.stop_sequences.push(&text) {
+            StopCheck::Continue(text) => {
+                // Best-effort send; if receiver is gone, just stop.
+                if !(self.send)(Ok(TokenEvent::generated(next_token, text, logprob))) {
+                    self.done = true;
+                    return Ok(());
+                }
             }
-            .to_string()
-        });
-        println!("Loading model: {}", model_id);
-        let revision = cfg.revision.clone().unwrap_or("main".to_string());
-        let api = api.repo(Repo::with_revision(model_id, RepoType::Model, revision));
-
-        let tokenizer_filename = api.get("tokenizer.json")?;
-        let config_filename = api.get("config.json")?;
-        let config: LlamaConfig = serde_json::from_slice(&std::fs::read(config_filename)?)?;
-        let config = config.into_config(cfg.use_flash_attn);
-
-        let filenames = match cfg.model {
-            WhichModel::Llama32_3B | WhichModel::Llama32_3BInstruct => {
-                hub_load_safetensors(&api, "model.safetensors.index.json")?
+            StopCheck::Stop(text) => {
+                let _ = (self.send)(Ok(TokenEvent::generated(next_token, text, logprob)));
+                self.finish(FinishReason::Stop);
+                return Ok(());
             }
-            _ => vec![api.get("model.safetensors")?],
-        };
+        }
 
-        let cache = model::Cache::new(!cfg.no_kv_cache, dtype, &config, &device)?;
-        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&filenames, dtype, &device)? };
-        let llama = Llama::load(vb, &config)?;
-        let tokenizer = tokenizers::Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
-        (llama, tokenizer, cache)
-    };
+        if self.generated >= self.max_tokens {
+            self.finish(FinishReason::Length);
+        }
+        Ok(())
+    }
+}
 
-    // ---- Prepare prompt & sampler ------------------------------------------
-    let eos_token_id = tokenizer
-        .token_to_id(EOS_TOKEN)
-        .map(model::LlamaEosToks::Single);
+/// Generate for rows whose prompts have the same length, sharing one forward pass per
+/// step, until every row has finished.
+///
+/// The generator's KV cache must already hold the first `cached_len` prompt tokens, which
+/// only a single row can resume. Returns the generator and the tokens its KV cache holds
+/// once a single row finishes, or `None` if it failed or the window slid and the cache no
+/// longer starts at the beginning of the conversation.
+fn run_batch(
+    mut generator: Generator,
+    rows: &mut [Row],
+    cached_len: usize,
+    tokenizer: &tokenizers::Tokenizer,
+    device: &Device,
+    params: &BatchParams,
+    cancel: &CancelToken,
+) -> Option<(Generator, Vec<u32>)> {
+    let start_gen = std::time::Instant::now();
+    // Number of tokens in the KV cache, and the first of them once the window slid.
+    let mut index_pos = cached_len;
+    let mut window_start = 0usize;
+    let mut token_generated = 0usize;
+    let mut failed = false;
 
-    let mut tokens = tokenizer
-        .encode(cfg.prompt.as_str(), true)
-        .map_err(E::msg)?
-        .get_ids()
-        .to_vec();
+    for row in rows.iter_mut() {
+        for &token in row.tokens.iter() {
+            let _ = (row.send)(Ok(TokenEvent::prompt(token)));
+        }
+        if row.max_tokens == 0 {
+            row.finish(FinishReason::Length);
+        }
+    }
+    // candle's Llama masks only line up for a forward that starts at position 0 or adds one
+    // token, so the prompt is prefilled in one pass with progress reported around it.
+    let prompt_len = rows.first().map_or(0, |row| row.tokens.len()) - cached_len;
+    let mut prefilled = false;
+    for row in rows.iter_mut().filter(|row| !row.done) {
+        // A row whose reader already went away isn't worth prefilling for
+        if !(row.send)(Ok(TokenEvent::prefill(0, prompt_len))) {
+            row.done = true;
+        }
+    }
+    // A resumed cache can't take the new tokens in one pass, so all but the last are fed
+    // one at a time; the loop below runs the last one.
+    if cached_len > 0 && !rows[0].done {
+        let pending = rows[0].tokens[cached_len..rows[0].tokens.len() - 1].to_vec();
+        for token in pending {
+            if cancel.is_cancelled() {
+                break;
+            }
+            let fed = Tensor::new(&[token], device)
+                .and_then(|input| input.unsqueeze(0))
+                .and_then(|input| generator.forward(&input, index_pos));
+            if let Err(e) = fed {
+                rows[0].fail(anyhow::anyhow!("{}", e));
+                return None;
+            }
+            index_pos += 1;
+        }
+    }
+
+    while rows.iter().any(|row| !row.done) {
+        if cancel.is_cancelled() {
+            for row in rows.iter_mut().filter(|row| !row.done) {
+                row.finish(FinishReason::Cancelled);
+            }
+            break;
+        }
 
-    println!("Starting inference...");
+        // Every row starts at the same length and grows by one token per step.
+        let len = rows[0].tokens.len();
+        if len - window_start > params.context_length {
+            window_start = len - ContextPolicy::slide_len(params.context_length);
+            index_pos = 0;
+            generator.clear_kv_cache();
+        }
 
-    let mut logits_processor = {
-        let temperature = cfg.temperature;
-        let sampling = if temperature <= 0. {
-            Sampling::ArgMax
+        // Use KV-cache for single-token step after the first pass.
+        let (context_size, context_index) = if params.use_kv_cache && index_pos > 0 {
+            (1, index_pos)
         } else {
-            match (cfg.top_k, cfg.top_p) {
-                (None, None) => Sampling::All { temperature },
-                (Some(k), None) => Sampling::TopK { k, temperature },
-                (None, Some(p)) => Sampling::TopP { p, temperature },
-                (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
+            (len - window_start, 0)
+        };
+
+        let ctxt: Vec<u32> = rows
+            .iter()
+            .flat_map(|row| row.tokens[len - context_size..].iter().copied())
+            .collect();
+        let logits = Tensor::from_vec(ctxt, (rows.len(), context_size), device)
+            .and_then(|input| generator.forward(&input, context_index));
+        let logits = match logits {
+            Ok(logits) => logits,
+            Err(e) => {
+                for row in rows.iter_mut().filter(|row| !row.done) {
+                    row.fail(anyhow::anyhow!("{}", e));
+                }
+                failed = true;
+                break;
             }
         };
-        LogitsProcessor::from_sampling(cfg.seed, sampling)
-    };
+        index_pos += context_size;
+        if !prefilled {
+            prefilled = true;
+            for row in rows.iter_mut().filter(|row| !row.done) {
+                let _ = (row.send)(Ok(TokenEvent::prefill(prompt_len, prompt_len)));
+            }
+        }
 
-    // Channel for streaming decoded fragments to the caller.
-    let (tx, rx) = mpsc::channel::<anyhow::Result<String>>();
+        for (i, row) in rows.iter_mut().enumerate() {
+            if row.done {
+                let filler = row.tokens.last().copied().unwrap_or_default();
+                row.tokens.push(filler);
+                continue;
+            }
+            let stepped = logits
+                .get(i)
+                .map_err(E::from)
+                .and_then(|logits| row.step(&logits, tokenizer, params));
+            match stepped {
+                Ok(()) => token_generated += 1,
+                Err(e) => row.fail(e),
+            }
+        }
+    }
 
-    // ---- Spawn generation thread -------------------------------------------
-    std::thread::spawn(move || {
-        let start_gen = std::time::Instant::now();
-        let mut index_pos = 0usize;
-        let mut token_generated = 0usize;
+    // Optional: final stats as a debug line (not sent through the stream).
+    let dt = start_gen.elapsed();
+    eprintln!(
+        "[llama-runner] {} tokens generated ({:.2} tokens/s)",
+        token_generated,
+        token_generated as f64 / dt.as_secs_f64(),
+    );
 
-        for index in 0..cfg.max_tokens {
-            // Use KV-cache for single-token step after the first pass.
-            let (context_size, context_index) = if cache.use_kv_cache && index > 0 {
-                (1, index_pos)
-            } else {
-                (tokens.len(), 0)
-            };
+    match rows {
+        [row] if !failed && params.use_kv_cache && window_start == 0 => {
+            Some((generator, row.tokens[..index_pos].to_vec()))
+        }
+        _ => None,
+    }
+}
 
-            let ctxt = &tokens[tokens.len().saturating_sub(context_size)..];
-            let input = match Tensor::new(ctxt, &device).and_then(|t| t.unsqueeze(0)) {
-                Ok(t) => t,
-                Err(e) => {
-                    let _ = tx.send(Err(e.into()));
-                    break;
-                }
-            };
+impl LlamaRunner {
+    /// Tokenize a request's prompt and fit it to the context window, returning the prompt
+    /// tokens and how many tokens may be generated.
+    fn prepare_prompt(&self, request: &GenerationRequest) -> anyhow::Result<(Vec<u32>, usize)> {
+        let mut tokens = self
+            .loaded
+            .tokenizer
+            .encode(request.prompt.as_str(), true)
+            .map_err(E::msg)?
+            .get_ids()
+            .to_vec();
+        // A rendered Llama 3 chat already starts with `<|begin_of_text|>`; drop the second
+        // one the tokenizer adds.
+        if let Some(bos) = self.loaded.tokenizer.token_to_id(LLAMA3_BOS) {
+            if tokens.starts_with(&[bos, bos]) {
+                tokens.remove(0);
+            }
+        }
+        let max_tokens = self.config.context_policy.fit(
+            &mut tokens,
+            request.max_tokens,
+            self.loaded.context_length,
+        )?;
+        Ok((tokens, max_tokens))
+    }
 
-            let logits = match llama.forward(&input, context_index, &mut cache) {
-                Ok(l) => l,
-                Err(e) => {
-                    let _ = tx.send(Err(e.into()));
-                    break;
-                }
-            };
-            let logits = match logits.squeeze(0) {
-                Ok(l) => l,
-                Err(e) => {
-                    let _ = tx.send(Err(e.into()));
-                    break;
+    fn new_generator(&self, use_kv_cache: bool) -> anyhow::Result<Generator> {
+        Ok(match &self.loaded.weights {
+            Weights::Full { llama, config } => {
+                let cache =
+                    model::Cache::new(use_kv_cache, self.dtype, config, &self.loaded.device)?;
+                Generator::Full {
+                    llama: llama.clone(),
+                    empty: cache.clone(),
+                    cache,
                 }
-            };
+            }
+            Weights::Quantized(model) => Generator::Quantized(model.clone()),
+        })
+    }
+
+    /// Score `text` with teacher forcing: the model sees each token in turn and is charged
+    /// the log probability it gave to the next one. The text is split into windows of
+    /// `window` tokens (at most the context length), each scored from an empty KV cache.
+    pub fn perplexity(&self, text: &str, window: usize) -> anyhow::Result<Perplexity> {
+        let tokens = self
+            .loaded
+            .tokenizer
+            .encode(text, true)
+            .map_err(E::msg)?
+            .get_ids()
+            .to_vec();
+        let window = window.clamp(2, self.loaded.context_length.max(2));
 
-            let logits = if cfg.repeat_penalty == 1. {
-                logits
+        // Feeding one token per step needs the KV cache whatever `no_kv_cache` says.
+        let mut generator = self.new_generator(true)?;
+        let mut stats = Perplexity::default();
+        for chunk in tokens.chunks(window) {
+            generator.clear_kv_cache();
+            for (pos, pair) in chunk.windows(2).enumerate() {
+                let input = Tensor::new(&pair[..1], &self.loaded.device)?.unsqueeze(0)?;
+                let logits = generator.forward(&input, pos)?.squeeze(0)?;
+                stats.record(token_logprob(&logits, pair[1])?);
+            }
+        }
+        Ok(stats)
+    }
+
+    fn new_row(
+        &self,
+        tokens: Vec<u32>,
+        max_tokens: usize,
+        stop: &[String],
+        send: EventSink,
+    ) -> Row {
+        let cfg = &self.config;
+        let logits_processor = {
+            let temperature = cfg.temperature;
+            let sampling = if temperature <= 0. {
+                Sampling::ArgMax
             } else {
-                let start_at = tokens.len().saturating_sub(cfg.repeat_last_n);
-                match candle_transformers::utils::apply_repeat_penalty(
-                    &logits,
-                    cfg.repeat_penalty,
-                    &tokens[start_at..],
-                ) {
-                    Ok(l) => l,
-                    Err(e) => {
-                        let _ = tx.send(Err(e.into()));
-                        break;
-                    }
+                match (cfg.top_k, cfg.top_p) {
+                    (None, None) => Sampling::All { temperature },
+                    (Some(k), None) => Sampling::TopK { k, temperature },
+                    (None, Some(p)) => Sampling::TopP { p, temperature },
+                    (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
                 }
             };
+            LogitsProcessor::from_sampling(cfg.seed, sampling)
+        };
+        Row {
+            tokens,
+            max_tokens,
+            generated: 0,
+            logits_processor,
+            stop_sequences: StopSequences::new(&[cfg.stop.as_slice(), stop].concat()),
+            done: false,
+            send,
+        }
+    }
+
+    /// Run a batch on its own thread. Dropping the rows' senders closes their streams.
+    ///
+    /// A single row may resume a generator holding `cached_len` of its tokens; with a
+    /// `conversation_id`, its KV state is kept for the next turn afterwards.
+    fn spawn_batch(
+        &self,
+        generator: Generator,
+        rows: Vec<Row>,
+        cached_len: usize,
+        conversation_id: Option<String>,
+    ) {
+        let tokenizer = self.loaded.tokenizer.clone();
+        let device = self.loaded.device.clone();
+        let cancel = self.cancel.token();
+        let params = BatchParams {
+            stop_token_ids: STOP_TOKENS
+                .iter()
+                .filter_map(|token| tokenizer.token_to_id(token))
+                .collect(),
+            repeat_penalty: self.config.repeat_penalty,
+            repeat_last_n: self.config.repeat_last_n,
+            no_repeat_ngram_size: self.config.no_repeat_ngram_size,
+            frequency_penalty: self.config.frequency_penalty,
+            use_kv_cache: !self.config.no_kv_cache,
+            context_length: self.loaded.context_length,
+        };
+        let loaded = Arc::clone(&self.loaded);
+        std::thread::spawn(move || {
+            let mut rows = rows;
+            let kept = run_batch(
+                generator, &mut rows, cached_len, &tokenizer, &device, &params, &cancel,
+            );
+            // Keep the KV state so the next turn only prefills its new tokens. The rows are
+            // dropped after, so their streams close once it's stored.
+            if let (Some(id), Some((generator, kv_tokens))) = (conversation_id, kept) {
+                loaded.conversations.store(id, generator, kv_tokens);
+            }
+        });
+    }
+
+    fn try_load(cfg: LlamaInferenceConfig) -> anyhow::Result<Self> {
+        // ---- Device & dtype -------------------------------------------------
+        let threads = configure_threads(cfg.threads)?;
+        println!("CPU threads: {threads}");
 
-            index_pos += ctxt.len();
+        let device = if cfg.cpu { DeviceSpec::Cpu } else { cfg.device }.open()?;
+        println!("Device: {:?}", device);
+
+        let dtype = match cfg.dtype.as_deref() {
+            Some("f16") => DType::F16,
+            Some("bf16") => DType::BF16,
+            Some("f32") => DType::F32,
+            Some(dtype) => bail!("Unsupported dtype {dtype}"),
+            None => DType::F16,
+        };
+        println!("Using dtype: {:?}", dtype);
+
+        // ---- Load model & tokenizer ----------------------------------------
+        let api = hub_api()?;
+        let model_id = cfg
+            .model_id
+            .clone()
+            .unwrap_or_else(|| cfg.model.repo_id().to_string());
+        let (files, source) = match &cfg.model_path {
+            Some(dir) => {
+                let local = LocalFiles::new(dir)?;
+                let source = local.dir().display().to_string();
+                (ModelFiles::Local(local), source)
+            }
+            None => {
+                let revision = cfg.revision.clone().unwrap_or("main".to_string());
+                let repo = HubFiles::new(
+                    &api,
+                    Repo::with_revision(model_id.clone(), RepoType::Model, revision),
+                    cfg.download_progress.clone(),
+                )
+                .with_parallel_downloads(cfg.parallel_downloads);
+                (ModelFiles::Hub(Box::new(repo)), model_id.clone())
+            }
+        };
+        println!("Loading model: {}", source);
 
-            let next_token = match logits_processor.sample(&logits) {
-                Ok(t) => t,
-                Err(e) => {
-                    let _ = tx.send(Err(e.into()));
-                    break;
+        let device_key = format!("{:?}", device.location());
+        // In a local directory the quantized model ids load the `.gguf` file found there.
+        let gguf = match (&cfg.gguf, &files) {
+            (Some(gguf), _) => Some(gguf.clone()),
+            (None, ModelFiles::Local(local)) if cfg.model.default_gguf().is_some() => {
+                Some(local.gguf()?.display().to_string())
+            }
+            (None, _) => cfg.model.default_gguf().map(str::to_string),
+        };
+        // Set when the weights are read rather than taken from the cache.
+        let mut fresh = false;
+        let (loaded, repo_id) = match gguf.as_deref() {
+            Some(gguf) => {
+                println!("Loading quantized checkpoint: {}", gguf);
+                let mut key = CacheKey::new(gguf, "gguf", device_key);
+                if cfg.model_path.is_none() {
+                    // The tokenizer is downloaded at the requested revision
+                    let revision = cfg.revision.as_deref().unwrap_or("main");
+                    key = key.with_revision(revision);
                 }
+                let loaded = MODEL_CACHE.get_or_load(&key, || {
+                    fresh = true;
+                    let model_path = gguf_path(&api, gguf, cfg.download_progress.clone())?;
+                    load_quantized_model(&files, &model_path, device, cfg.max_conversations)
+                })?;
+                let repo_id = gguf.rsplit_once('/').map_or(gguf, |(repo, _)| repo);
+                (loaded, repo_id.to_string())
+            }
+            None => {
+                // Flash attention is baked into the loaded model, so cache it separately.
+                let variant = if cfg.use_flash_attn {
+                    format!("{}+flash-attn", dtype.as_str())
+                } else {
+                    dtype.as_str().to_string()
+                };
+                let mut key = CacheKey::new(source.clone(), variant, device_key);
+                if cfg.model_path.is_none() {
+                    let revision = cfg.revision.as_deref().unwrap_or("main");
+                    key = key.with_revision(revision);
+                }
+                let loaded = MODEL_CACHE.get_or_load(&key, || {
+                    fresh = true;
+                    load_model(&files, &cfg, dtype, device)
+                })?;
+                (loaded, source)
+            }
+        };
+
+        let owned_by = model_id.split('/').next().unwrap_or("unknown");
+        let owned_by = match owned_by {
+            "meta-llama" => "meta",
+            other => other,
+        }
+        .to_string();
+        let metadata = RunnerMetadata {
+            model_id: cfg
+                .model
+                .to_possible_value()
+                .map(|value| value.get_name().to_string())
+                .unwrap_or_else(|| model_id.clone()),
+            repo_id,
+            family: "llama".to_string(),
+            owned_by,
+            context_length: loaded.context_length,
+            vocab_size: loaded.tokenizer.get_vocab_size(true),
+            parameter_count: loaded.parameter_count,
+            dtype: loaded.weight_type.clone(),
+            device: device_name(&loaded.device),
+        };
+
+        let runner = Self {
+            loaded,
+            dtype,
+            config: cfg,
+            metadata,
+            cancel: CancelHandle::new(),
+        };
+        if fresh && runner.config.warmup {
+            println!("Warmup finished in {:?}", runner.warmup()?);
+        }
+        Ok(runner)
+    }
+
+    fn try_generate_streams(
+        &self,
+        requests: Vec<GenerationRequest>,
+    ) -> anyhow::Result<Vec<TokenReceiver>> {
+        let prepared = requests
+            .iter()
+            .map(|request| self.prepare_prompt(request))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // Candle's Llama has no padding mask, so only prompts of the same length can share
+        // forward passes. Each length gets its own batch.
+        let mut receivers = Vec::with_capacity(requests.len());
+        let mut groups: BTreeMap<usize, Vec<(Row, Option<String>)>> = BTreeMap::new();
+        for (request, (tokens, max_tokens)) in requests.into_iter().zip(prepared) {
+            // Resume the conversation's previous turn if this prompt extends it. Only a
+            // single row can resume, so it runs on its own.
+            let resumed = match request.conversation_id.as_deref() {
+                Some(id) if !self.config.no_kv_cache => {
+                    self.loaded.conversations.take_prefix(id, &tokens)
+                }
+                _ => None,
             };
 
-            token_generated += 1;
-            tokens.push(next_token);
+            // Channel for streaming token events to the caller. It's bounded, so a slow
+            // reader holds the generation back; one that goes away or stalls ends the row.
+            let (tx, rx) = token_channel();
+            receivers.push(rx);
+            let row = self.new_row(
+                tokens,
+                max_tokens,
+                &request.stop,
+                Box::new(move |event| tx.send(event)),
+            );
+            match resumed {
+                Some((generator, cached_len)) => {
+                    self.spawn_batch(generator, vec![row], cached_len, request.conversation_id)
+                }
+                None => groups
+                    .entry(row.tokens.len())
+                    .or_default()
+                    .push((row, request.conversation_id)),
+            }
+        }
 
-            // Early stop on EOS.
-            let stop = match eos_token_id {
-                Some(model::LlamaEosToks::Single(eos_tok_id)) => next_token == eos_tok_id,
-                Some(model::LlamaEosToks::Multiple(ref eos_ids)) => eos_ids.contains(&next_token),
-                None => false,
+        println!("Starting inference...");
+        if groups.len() > 1 {
+            println!("Batched by prompt length into {} batches", groups.len());
+        }
+        for mut group in groups.into_values() {
+            // A row alone keeps its KV state for the conversation's next turn
+            let conversation_id = match group.as_mut_slice() {
+                [(_, id)] => id.take(),
+                _ => None,
             };
-            if stop {
-                break;
+            let rows = group.into_iter().map(|(row, _)| row).collect();
+            let generator = self.new_generator(!self.config.no_kv_cache)?;
+            self.spawn_batch(generator, rows, 0, conversation_id);
+        }
+        Ok(receivers)
+    }
+}
+
+impl ModelRunner for LlamaRunner {
+    type Config = LlamaInferenceConfig;
+
+    fn load(cfg: LlamaInferenceConfig) -> Result<Self, RunnerError> {
+        Self::try_load(cfg).map_err(RunnerError::load)
+    }
+
+    fn generate_stream(&self, request: GenerationRequest) -> Result<TokenReceiver, RunnerError> {
+        let mut streams = self.generate_streams(vec![request])?;
+        Ok(streams.remove(0))
+    }
+
+    fn generate_streams(
+        &self,
+        requests: Vec<GenerationRequest>,
+    ) -> Result<Vec<TokenReceiver>, RunnerError> {
+        self.try_generate_streams(requests)
+            .map_err(RunnerError::generation)
+    }
+
+    fn batches_generations(&self) -> bool {
+        true
+    }
+
+    fn metadata(&self) -> &RunnerMetadata {
+        &self.metadata
+    }
+
+    fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    fn chat_template(&self) -> Option<&JinjaChatTemplate> {
+        self.loaded.chat_template.as_ref()
+    }
+
+    fn count_tokens(&self, prompt: &str) -> Option<usize> {
+        let encoding = self.loaded.tokenizer.encode(prompt, true).ok()?;
+        Some(encoding.len())
+    }
+
+    fn forget_conversation(&self, conversation_id: &str) -> bool {
+        self.loaded.conversations.remove(conversation_id)
+    }
+
+    fn touch(&self) {
+        MODEL_CACHE.touch(&self.loaded);
+    }
+}
+
+/// Loads the model and streams generated text for `cfg.messages`, or for `cfg.prompt`.
+///
+/// Instruct models get their prompt in a user turn of their [`ChatTemplate`]; base models
+/// get it unchanged, and `messages` as plain text, one per line. Use [`LlamaRunner`]
+/// directly to keep a model loaded across calls or to pass an already formatted prompt.
+pub fn run_llama_inference(cfg: LlamaInferenceConfig) -> Result<TokenReceiver, RunnerError> {
+    let prompt = match (cfg.model.chat_template(), cfg.messages.is_empty()) {
+        (Some(template), true) => template.render(&[ChatMessage::user(cfg.prompt.clone())]),
+        (Some(template), false) => template.render(&cfg.messages),
+        (None, true) => cfg.prompt.clone(),
+        (None, false) => cfg
+            .messages
+            .iter()
+            .map(|message| message.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+    let request = GenerationRequest::new(prompt, cfg.max_tokens);
+    let runner = LlamaRunner::load(cfg)?;
+    runner.generate_stream(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runner_core::test_utils::{seeded_var_builder, word_level_tokenizer_json};
+
+    const VOCAB: [&str; 32] = [
+        "<unk>", EOS_TOKEN, "the", "a", "cat", "dog", "sat", "on", "mat", "ran", "to", "park",
+        "and", "then", "slept", "is", "was", "big", "small", "red", "blue", "sun", "moon", "sky",
+        "day", "night", "over", "under", "by", "it", "he", "she",
+    ];
+
+    fn tiny_runner(config: LlamaInferenceConfig) -> LlamaRunner {
+        let device = Device::Cpu;
+        let llama_config: LlamaConfig = serde_json::from_value(serde_json::json!({
+            "hidden_size": 16,
+            "intermediate_size": 32,
+            "vocab_size": VOCAB.len(),
+            "num_hidden_layers": 2,
+            "num_attention_heads": 2,
+            "num_key_value_heads": 1,
+            "rms_norm_eps": 1e-5,
+            "max_position_embeddings": 64,
+            "tie_word_embeddings": false,
+        }))
+        .unwrap();
+        let model_config = llama_config.into_config(false);
+        let vb = seeded_var_builder(&device);
+        let llama = Llama::load(vb, &model_config).unwrap();
+
+        let tokenizer: tokenizers::Tokenizer = word_level_tokenizer_json(&VOCAB).parse().unwrap();
+
+        let context_length = model_config.max_position_embeddings;
+        let conversations = ConversationCache::new(config.max_conversations);
+        LlamaRunner {
+            loaded: Arc::new(LoadedModel {
+                context_length,
+                weights: Weights::Full {
+                    llama,
+                    config: model_config,
+                },
+                tokenizer,
+                device,
+                parameter_count: 0,
+                weight_type: "f32".to_string(),
+                chat_template: None,
+                conversations,
+            }),
+            dtype: DType::F32,
+            config,
+            metadata: RunnerMetadata {
+                model_id: "tiny-llama".to_string(),
+                repo_id: "tiny-llama".to_string(),
+                family: "llama".to_string(),
+                owned_by: "test".to_string(),
+                context_length,
+                vocab_size: VOCAB.len(),
+                parameter_count: 0,
+                dtype: "f32".to_string(),
+                device: "cpu".to_string(),
+            },
+            cancel: CancelHandle::new(),
+        }
+    }
+
+    #[test]
+    fn test_flash_attn_is_refused_where_it_cannot_run() {
+        let err = check_flash_attn(&Device::Cpu, DType::F16).unwrap_err();
+        assert_eq!(err.kind(), "unsupported_device");
+        if !cfg!(feature = "flash-attn") {
+            assert!(err.to_string().contains("`flash-attn` feature"), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_chat_templates() {
+        let messages = [
+            ChatMessage::system("Be brief."),
+            ChatMessage::user("Hi"),
+            ChatMessage::assistant("Hello."),
+            ChatMessage::user("Bye"),
+        ];
+        assert_eq!(
+            ChatTemplate::Llama3.render(&messages),
+            "<|begin_of_text|>\
+             <|start_header_id|>system<|end_header_id|>\n\nBe brief.<|eot_id|>\
+             <|start_header_id|>user<|end_header_id|>\n\nHi<|eot_id|>\
+             <|start_header_id|>assistant<|end_header_id|>\n\nHello.<|eot_id|>\
+             <|start_header_id|>user<|end_header_id|>\n\nBye<|eot_id|>\
+             <|start_header_id|>assistant<|end_header_id|>\n\n"
+        );
+        assert_eq!(
+            ChatTemplate::ChatMl.render(&messages[1..2]),
+            "<|im_start|>user\nHi<|im_end|>\n<|im_start|>assistant\n"
+        );
+        assert_eq!(
+            ChatTemplate::Zephyr.render(&messages[..2]),
+            "<|system|>\nBe brief.</s>\n<|user|>\nHi</s>\n<|assistant|>\n"
+        );
+        assert_eq!(WhichModel::Llama32_1B.chat_template(), None);
+    }
+
+    #[test]
+    fn test_perplexity_scores_each_window() {
+        let runner = tiny_runner(LlamaInferenceConfig::new(WhichModel::SmolLM2_135M));
+        let text = "the cat sat on the mat and then slept";
+
+        let whole = runner.perplexity(text, 64).unwrap();
+        assert_eq!(whole.tokens, 8);
+        assert!(whole.perplexity() > 1.0 && whole.perplexity().is_finite());
+        assert_eq!(runner.perplexity(text, 64).unwrap(), whole);
+
+        // Windows of 5 and 4 tokens: the first token of each is not predicted.
+        assert_eq!(runner.perplexity(text, 5).unwrap().tokens, 7);
+    }
+
+    fn request() -> GenerationRequest {
+        GenerationRequest::new("the cat sat on the mat", 8)
+    }
+
+    fn generated_ids(runner: &LlamaRunner) -> Vec<u32> {
+        generate(runner, request()).0
+    }
+
+    /// The ids generated for `request`, and how many prompt tokens were prefilled.
+    fn generate(runner: &LlamaRunner, request: GenerationRequest) -> (Vec<u32>, usize) {
+        let mut rx = runner.generate_stream(request).unwrap();
+        let mut ids = Vec::new();
+        let mut prefilled = 0;
+        while let Some(event) = rx.blocking_recv() {
+            let event = event.unwrap();
+            if let Some(progress) = event.prefill {
+                prefilled = progress.total;
+            } else if !event.is_prompt {
+                ids.extend(event.token_id);
             }
+        }
+        (ids, prefilled)
+    }
 
-            // Decode this token's text and stream it out.
-            match tokenizer.decode(&[next_token], false) {
-                Ok(text) => {
-                    if !text.is_empty() {
-                        // Best-effort send; if receiver is gone, just stop.
-                        if tx.send(Ok(text)).is_err() {
-                            break;
-                        }
-                    }
-                }
-                Err(e) => {
-                    let _ = tx.send(Err(anyhow::anyhow!("{}", e)));
-                    break;
+    fn words(ids: &[u32]) -> String {
+        let words: Vec<&str> = ids.iter().map(|&id| VOCAB[id as usize]).collect();
+        words.join(" ")
+    }
+
+    #[test]
+    fn test_greedy_generation_is_stable() {
+        let runner = tiny_runner(LlamaInferenceConfig {
+            temperature: 0.0,
+            ..LlamaInferenceConfig::new(WhichModel::SmolLM2_135M)
+        });
+        // What this fixture generated on CPU when the test was written: "by over the she is
+        // over the he" (ids into VOCAB, see `words`). A deliberate change to greedy decoding
+        // or the fixture changes it; print `generated_ids(&runner)` and paste it here.
+        assert_eq!(generated_ids(&runner), [28, 26, 2, 31, 15, 26, 2, 30]);
+    }
+
+    #[test]
+    fn test_seeded_sampling_is_reproducible() {
+        let config = LlamaInferenceConfig {
+            seed: 1234,
+            temperature: 1.0,
+            top_k: Some(8),
+            top_p: None,
+            ..LlamaInferenceConfig::new(WhichModel::SmolLM2_135M)
+        };
+        let first = generated_ids(&tiny_runner(config.clone()));
+        // What seed 1234 drew on CPU when the test was written: "by she over a over it sky
+        // over". A deliberate change to sampling, the RNG or the fixture changes it; print
+        // `first` and paste it here.
+        assert_eq!(first, [28, 31, 26, 3, 26, 29, 23, 26]);
+        assert_eq!(generated_ids(&tiny_runner(config.clone())), first);
+
+        // Batching does not change what a seeded request generates.
+        let mut rx = tiny_runner(config)
+            .generate_batch(vec![request(), request()])
+            .unwrap();
+        let mut batched = [Vec::new(), Vec::new()];
+        while let Some((index, event)) = rx.blocking_recv() {
+            let event = event.unwrap();
+            if !event.is_prompt {
+                batched[index].extend(event.token_id);
+            }
+        }
+        assert_eq!(batched, [first.clone(), first]);
+    }
+
+    #[test]
+    fn test_batched_streams_match_single_generations() {
+        let runner = tiny_runner(LlamaInferenceConfig {
+            temperature: 0.0,
+            ..LlamaInferenceConfig::new(WhichModel::SmolLM2_135M)
+        });
+        let shorter = GenerationRequest::new("the cat sat", 8).with_conversation("chat");
+        let expected = [
+            generated_ids(&runner),
+            generated_ids(&runner),
+            generate(&runner, GenerationRequest::new("the cat sat", 8)).0,
+        ];
+
+        // The two prompts of one length share a batch; the other runs alone and keeps its
+        // KV state for the conversation
+        let streams = runner
+            .generate_streams(vec![request(), request(), shorter])
+            .unwrap();
+        for (mut rx, expected) in streams.into_iter().zip(expected) {
+            let mut ids = Vec::new();
+            while let Some(event) = rx.blocking_recv() {
+                let event = event.unwrap();
+                if event.prefill.is_none() && !event.is_prompt {
+                    ids.extend(event.token_id);
                 }
             }
+            assert_eq!(ids, expected);
         }
+        assert_eq!(runner.loaded.conversations.len(), 1);
+    }
 
-        // Optional: final stats as a debug line (not sent through the stream).
-        let dt = start_gen.elapsed();
-        eprintln!(
-            "[llama-runner] {} tokens generated ({:.2} tokens/s)",
-            token_generated,
-            token_generated as f64 / dt.as_secs_f64(),
+    #[test]
+    fn test_resumed_conversation_matches_a_full_prefill() {
+        let runner = tiny_runner(LlamaInferenceConfig {
+            temperature: 0.0,
+            ..LlamaInferenceConfig::new(WhichModel::SmolLM2_135M)
+        });
+        let opening = "the cat sat on the mat";
+        let (reply, prefilled) = generate(
+            &runner,
+            GenerationRequest::new(opening, 4).with_conversation("chat"),
+        );
+        assert_eq!(prefilled, 6);
+        assert_eq!(runner.loaded.conversations.len(), 1);
+
+        // The next turn extends the first, so only the tokens past the cache are prefilled
+        let next = format!("{opening} {} and then", words(&reply));
+        let (resumed, prefilled) = generate(
+            &runner,
+            GenerationRequest::new(next.as_str(), 4).with_conversation("chat"),
         );
-        // Dropping tx closes the stream.
-    });
+        assert_eq!(prefilled, 3);
+        let (full, _) = generate(&runner, GenerationRequest::new(next.as_str(), 4));
+        assert_eq!(resumed, full);
 
-    Ok(rx)
+        assert!(runner.forget_conversation("chat"));
+        assert!(!runner.forget_conversation("chat"));
+    }
 }
@@ -1,6 +1,13 @@
-use crate::llama_api::{run_llama_inference, LlamaInferenceConfig, WhichModel};
-use clap::Parser;
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use llama_runner::{run_llama_inference, LlamaInferenceConfig, LlamaRunner, WhichModel};
+use runner_core::{
+    bench, run_bench, BenchConfig, BenchReport, ChatMessage, ContextPolicy, DeviceSpec,
+    DownloadProgress, EvalReport, ModelRunner, SamplingPreset, DEFAULT_PARALLEL_DOWNLOADS,
+};
 use std::io::Write;
+use std::path::PathBuf;
+use std::time::Instant;
 
 #[derive(Parser, Debug, Default)]
 #[command(author, version, about = "Fast Llama inference with Candle", long_about = None)]
@@ -9,6 +16,10 @@ struct Args {
     #[arg(short, long, default_value = "The capital of France is")]
     prompt: String,
 
+    /// System prompt, sent as its own turn ahead of the prompt
+    #[arg(long)]
+    system: Option<String>,
+
     /// The model to use
     #[arg(short, long, default_value = "llama-3.2-1b-instruct")]
     model: WhichModel,
@@ -17,9 +28,18 @@ struct Args {
     #[arg(long)]
     cpu: bool,
 
-    /// The temperature used to generate samples
-    #[arg(short, long, default_value_t = 0.8)]
-    temperature: f64,
+    /// Device to run on: auto, cpu, cuda:<n> or metal:<n>. `--cpu` overrides it
+    #[arg(long, default_value = "auto")]
+    device: DeviceSpec,
+
+    /// Named sampling settings: precise, balanced or creative. Sampling flags given
+    /// explicitly override the preset's values
+    #[arg(long)]
+    preset: Option<SamplingPreset>,
+
+    /// The temperature used to generate samples (default: 0.8)
+    #[arg(short, long)]
+    temperature: Option<f64>,
 
     /// Nucleus sampling probability cutoff
     #[arg(long)]
@@ -49,32 +69,167 @@ struct Args {
     #[arg(long)]
     model_id: Option<String>,
 
+    /// Load the model from a local directory instead of the HuggingFace Hub
+    #[arg(long)]
+    model_path: Option<PathBuf>,
+
     /// Model revision
     #[arg(long)]
     revision: Option<String>,
 
-    /// Use flash attention
+    /// Use flash attention (CUDA builds with the flash-attn feature, f16 or bf16)
     #[arg(long)]
     use_flash_attn: bool,
 
-    /// Penalty to be applied for repeating tokens, 1. means no penalty
-    #[arg(long, default_value_t = 1.1)]
-    repeat_penalty: f32,
+    /// Penalty to be applied for repeating tokens, 1. means no penalty (default: 1.1)
+    #[arg(long)]
+    repeat_penalty: Option<f32>,
 
     /// The context size to consider for the repeat penalty
     #[arg(long, default_value_t = 128)]
     repeat_last_n: usize,
+
+    /// Never repeat an n-gram of this many tokens (0 disables it)
+    #[arg(long, default_value_t = 0)]
+    no_repeat_ngram_size: usize,
+
+    /// Lower a token's logit by this much per time it was already generated (0 disables it)
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    frequency_penalty: f32,
+
+    /// Stop generating when this string is produced (repeatable)
+    #[arg(long)]
+    stop: Vec<String>,
+
+    /// GGUF checkpoint to load instead of the safetensors weights: a local file or
+    /// owner/repo/file.gguf on the HuggingFace Hub
+    #[arg(long)]
+    gguf: Option<String>,
+
+    /// What to do when the prompt and max tokens exceed the context window:
+    /// error, truncate or sliding-window
+    #[arg(long, default_value = "error")]
+    context_policy: ContextPolicy,
+
+    /// Number of checkpoint shards to download at the same time
+    #[arg(long, default_value_t = DEFAULT_PARALLEL_DOWNLOADS)]
+    parallel_downloads: usize,
+
+    /// CPU threads for tensor operations (default: RUNNER_THREADS, else physical cores)
+    #[arg(long)]
+    threads: Option<usize>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Measure time to first token, tokens/sec and peak memory, reported as JSON
+    Bench(BenchArgs),
+    /// Compute the perplexity of the model over a text file, reported as JSON
+    Eval(EvalArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct BenchArgs {
+    /// Prompt length in tokens (approximate: the prompt repeats one word)
+    #[arg(long, default_value_t = 128)]
+    prompt_tokens: usize,
+
+    /// Tokens to generate per run
+    #[arg(long, default_value_t = 128)]
+    gen_tokens: usize,
+
+    /// Number of measured runs
+    #[arg(long, default_value_t = 5)]
+    runs: usize,
+
+    /// Number of runs before measuring
+    #[arg(long, default_value_t = 1)]
+    warmup: usize,
+
+    /// Write the report to this file instead of stdout
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct EvalArgs {
+    /// Text file to score
+    #[arg(long)]
+    file: PathBuf,
+
+    /// Tokens per window; each window is scored from an empty KV cache
+    #[arg(long, default_value_t = 512)]
+    window: usize,
+
+    /// Write the report to this file instead of stdout
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+impl EvalArgs {
+    fn run(self, cfg: LlamaInferenceConfig) -> anyhow::Result<()> {
+        let text = std::fs::read_to_string(&self.file)
+            .with_context(|| format!("failed to read {}", self.file.display()))?;
+        let runner = LlamaRunner::load(cfg)?;
+        let start = Instant::now();
+        let perplexity = runner.perplexity(&text, self.window)?;
+        EvalReport {
+            model_id: runner.metadata().model_id.clone(),
+            window: self.window,
+            elapsed: start.elapsed(),
+            perplexity,
+        }
+        .write_json(self.output.as_deref())
+    }
+}
+
+impl BenchArgs {
+    fn run(self, cfg: LlamaInferenceConfig) -> anyhow::Result<()> {
+        let config = BenchConfig {
+            prompt_tokens: self.prompt_tokens,
+            max_tokens: self.gen_tokens,
+            runs: self.runs,
+            warmup: self.warmup,
+        };
+        let start = Instant::now();
+        let runner = LlamaRunner::load(cfg)?;
+        let load_time = start.elapsed();
+        let runs = run_bench(&runner, &config)?;
+        BenchReport {
+            model_id: runner.metadata().model_id.clone(),
+            config,
+            load_time,
+            runs,
+            peak_memory_bytes: bench::peak_memory_bytes(),
+        }
+        .write_json(self.output.as_deref())
+    }
 }
 
 impl Into<LlamaInferenceConfig> for Args {
     fn into(self) -> LlamaInferenceConfig {
+        let messages = match &self.system {
+            Some(system) => vec![
+                ChatMessage::system(system.clone()),
+                ChatMessage::user(self.prompt.clone()),
+            ],
+            None => Vec::new(),
+        };
+        let preset = self.preset.map(|preset| preset.settings());
         LlamaInferenceConfig {
             prompt: self.prompt,
             model: self.model,
             cpu: self.cpu,
-            temperature: self.temperature,
-            top_p: self.top_p,
-            top_k: self.top_k,
+            device: self.device,
+            temperature: self
+                .temperature
+                .or(preset.map(|p| p.temperature))
+                .unwrap_or(0.8),
+            top_p: self.top_p.or(preset.and_then(|p| p.top_p)),
+            top_k: self.top_k.or(preset.and_then(|p| p.top_k)),
             seed: self.seed,
             max_tokens: self.max_tokens,
             no_kv_cache: self.no_kv_cache,
@@ -82,20 +237,43 @@ impl Into<LlamaInferenceConfig> for Args {
             model_id: self.model_id,
             revision: self.revision,
             use_flash_attn: self.use_flash_attn,
-            repeat_penalty: self.repeat_penalty,
+            repeat_penalty: self
+                .repeat_penalty
+                .or(preset.map(|p| p.repeat_penalty))
+                .unwrap_or(1.1),
             repeat_last_n: self.repeat_last_n,
+            no_repeat_ngram_size: self.no_repeat_ngram_size,
+            frequency_penalty: self.frequency_penalty,
+            stop: self.stop,
+            // A run completes one prompt, so there is no next turn to resume
+            max_conversations: 0,
+            gguf: self.gguf,
+            context_policy: self.context_policy,
+            download_progress: Some(DownloadProgress::stderr()),
+            parallel_downloads: self.parallel_downloads,
+            model_path: self.model_path,
+            messages,
+            threads: self.threads,
+            warmup: false,
         }
     }
 }
 
 pub fn run_cli() -> anyhow::Result<()> {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    let command = args.command.take();
     let cfg = args.into();
-    let rx = run_llama_inference(cfg)?;
-    for msg in rx {
+    match command {
+        Some(Command::Bench(bench)) => return bench.run(cfg),
+        Some(Command::Eval(eval)) => return eval.run(cfg),
+        None => {}
+    }
+    let mut rx = run_llama_inference(cfg)?;
+    while let Some(msg) = rx.blocking_recv() {
         match msg {
-            Ok(tok) => {
-                print!("{tok}");
+            Ok(event) if event.is_prompt => {}
+            Ok(event) => {
+                print!("{}", event.text);
                 let _ = std::io::stdout().flush(); // <- force it out now
             }
             Err(e) => {
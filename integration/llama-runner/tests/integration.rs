@@ -0,0 +1,44 @@
+//! Generation with a real checkpoint from the HuggingFace Hub. These tests download
+//! SmolLM2-135M-Instruct (about 270 MB), so they are ignored unless the crate is built with
+//! the `integration-tests` feature:
+//!
+//! ```text
+//! cargo test -p llama-runner --features integration-tests
+//! ```
+
+use llama_runner::{LlamaInferenceConfig, LlamaRunner, WhichModel};
+use runner_core::{GenerationRequest, ModelRunner};
+
+fn complete(runner: &LlamaRunner, prompt: &str) -> String {
+    let mut rx = runner
+        .generate_stream(GenerationRequest::new(prompt, 16))
+        .unwrap();
+    let mut text = String::new();
+    while let Some(event) = rx.blocking_recv() {
+        let event = event.unwrap();
+        if !event.is_prompt {
+            text.push_str(&event.text);
+        }
+    }
+    text
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "integration-tests"),
+    ignore = "downloads a checkpoint; enable the integration-tests feature"
+)]
+fn test_smollm2_greedy_generation_is_deterministic() {
+    let runner = LlamaRunner::load(LlamaInferenceConfig {
+        cpu: true,
+        dtype: Some("f32".to_string()),
+        temperature: 0.0,
+        ..LlamaInferenceConfig::new(WhichModel::SmolLM2_135MInstruct)
+    })
+    .unwrap();
+
+    let prompt = "The capital of France is";
+    let first = complete(&runner, prompt);
+    assert!(!first.trim().is_empty(), "empty completion");
+    assert_eq!(complete(&runner, prompt), first);
+}
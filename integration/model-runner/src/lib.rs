@@ -0,0 +1,7 @@
+pub mod model_api;
+
+pub use model_api::{
+    cached_context_length, cached_models, clear_model_cache, download_model, evict_cached_model,
+    evict_idle_model, evict_model, idle_models, ChatTemplate, ModelInferenceConfig,
+    TextModelRunner, WhichModel,
+};
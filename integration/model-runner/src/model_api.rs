@@ -0,0 +1,1033 @@
+use anyhow::{Error as E, Result};
+use candle_core::{DType, Device, DeviceLocation, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::generation::{LogitsProcessor, Sampling};
+use candle_transformers::models::mistral::{Config as MistralConfig, Model as Mistral};
+use candle_transformers::models::phi3::{Config as Phi3Config, Model as Phi3};
+use candle_transformers::models::qwen2::{Config as Qwen2Config, ModelForCausalLM as Qwen2};
+use clap::ValueEnum;
+use hf_hub::{Repo, RepoType};
+use runner_core::{
+    apply_frequency_penalty, ban_repeated_ngrams, chunked_prefill, configure_threads, hub_api,
+    safetensors_parameter_count, token_channel, CacheKey, CancelHandle, CancelToken, ChatMessage,
+    ContextPolicy, ConversationCache, CpuFeatures, DeviceSpec, DownloadProgress, FinishReason,
+    GenerationRequest, HubFiles, JinjaChatTemplate, LocalFiles, ModelCache, ModelFiles,
+    ModelRunner, Role, RunnerError, RunnerMetadata, SamplingPreset, StopCheck, StopSequences,
+    TokenEvent, TokenReceiver, TokenSender, DEFAULT_PARALLEL_DOWNLOADS, DEFAULT_PREFILL_CHUNK,
+    TOKENIZER_CONFIG,
+};
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock};
+use std::thread;
+use tokenizers::Tokenizer;
+use utils::token_output_stream::TokenOutputStream;
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq, ValueEnum, Default)]
+pub enum WhichModel {
+    #[value(name = "mistral-7b-instruct-v0.3")]
+    Mistral7BInstructV03,
+    #[value(name = "qwen2.5-0.5b-instruct")]
+    #[default]
+    Qwen25_0_5BInstruct,
+    #[value(name = "qwen2.5-1.5b-instruct")]
+    Qwen25_1_5BInstruct,
+    #[value(name = "qwen2.5-3b-instruct")]
+    Qwen25_3BInstruct,
+    #[value(name = "qwen2.5-7b-instruct")]
+    Qwen25_7BInstruct,
+    #[value(name = "phi-3-mini-4k-instruct")]
+    Phi3Mini4KInstruct,
+}
+
+impl WhichModel {
+    /// Architecture of the model, as reported in the runner metadata.
+    pub fn family(&self) -> &'static str {
+        match self {
+            Self::Mistral7BInstructV03 => "mistral",
+            Self::Qwen25_0_5BInstruct
+            | Self::Qwen25_1_5BInstruct
+            | Self::Qwen25_3BInstruct
+            | Self::Qwen25_7BInstruct => "qwen2",
+            Self::Phi3Mini4KInstruct => "phi3",
+        }
+    }
+
+    /// Organization that published the weights.
+    pub fn owned_by(&self) -> &'static str {
+        match self {
+            Self::Mistral7BInstructV03 => "mistralai",
+            Self::Qwen25_0_5BInstruct
+            | Self::Qwen25_1_5BInstruct
+            | Self::Qwen25_3BInstruct
+            | Self::Qwen25_7BInstruct => "qwen",
+            Self::Phi3Mini4KInstruct => "microsoft",
+        }
+    }
+
+    /// Prompt format the model was tuned on.
+    pub fn chat_template(&self) -> ChatTemplate {
+        match self {
+            Self::Mistral7BInstructV03 => ChatTemplate::Mistral,
+            Self::Qwen25_0_5BInstruct
+            | Self::Qwen25_1_5BInstruct
+            | Self::Qwen25_3BInstruct
+            | Self::Qwen25_7BInstruct => ChatTemplate::ChatMl,
+            Self::Phi3Mini4KInstruct => ChatTemplate::Phi3,
+        }
+    }
+
+    /// Whether the checkpoint is split into shards listed in an index file.
+    pub fn is_sharded(&self) -> bool {
+        !matches!(self, Self::Qwen25_0_5BInstruct | Self::Qwen25_1_5BInstruct)
+    }
+
+    /// HuggingFace repository the weights and tokenizer come from.
+    pub fn repo_id(&self) -> &'static str {
+        match self {
+            Self::Mistral7BInstructV03 => "mistralai/Mistral-7B-Instruct-v0.3",
+            Self::Qwen25_0_5BInstruct => "Qwen/Qwen2.5-0.5B-Instruct",
+            Self::Qwen25_1_5BInstruct => "Qwen/Qwen2.5-1.5B-Instruct",
+            Self::Qwen25_3BInstruct => "Qwen/Qwen2.5-3B-Instruct",
+            Self::Qwen25_7BInstruct => "Qwen/Qwen2.5-7B-Instruct",
+            Self::Phi3Mini4KInstruct => "microsoft/Phi-3-mini-4k-instruct",
+        }
+    }
+}
+
+/// End-of-sequence and end-of-turn tokens of the supported tokenizers. Those present in the
+/// loaded vocabulary end a generation.
+const STOP_TOKENS: [&str; 4] = ["</s>", "<|endoftext|>", "<|im_end|>", "<|end|>"];
+
+/// Chat prompt formats of the supported models.
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum ChatTemplate {
+    /// Mistral v0.3: user turns wrapped in `[INST]` ... `[/INST]`, replies end with `</s>`.
+    /// The system message is prepended to the last user turn, as the official template
+    /// does.
+    Mistral,
+    /// Qwen2.5: `<|im_start|>role` ... `<|im_end|>`.
+    ChatMl,
+    /// Phi-3: `<|role|>` ... `<|end|>`.
+    Phi3,
+}
+
+impl ChatTemplate {
+    /// Render `messages`, ending with an open assistant turn for the model to complete.
+    /// The tokenizer adds the beginning-of-sequence token where the model expects one.
+    pub fn render(&self, messages: &[ChatMessage]) -> String {
+        if *self == ChatTemplate::Mistral {
+            return render_mistral(messages);
+        }
+        let mut prompt = String::new();
+        for message in messages {
+            prompt.push_str(&self.header(message.role));
+            prompt.push_str(&message.content);
+            prompt.push_str(self.end_of_turn());
+            prompt.push('\n');
+        }
+        prompt.push_str(&self.header(Role::Assistant));
+        prompt
+    }
+
+    /// Markup opening a turn by `role`.
+    fn header(&self, role: Role) -> String {
+        match self {
+            ChatTemplate::Mistral => String::new(),
+            ChatTemplate::ChatMl => format!("<|im_start|>{role}\n"),
+            ChatTemplate::Phi3 => format!("<|{role}|>\n"),
+        }
+    }
+
+    /// Token closing every turn.
+    pub fn end_of_turn(&self) -> &'static str {
+        match self {
+            ChatTemplate::Mistral => "</s>",
+            ChatTemplate::ChatMl => "<|im_end|>",
+            ChatTemplate::Phi3 => "<|end|>",
+        }
+    }
+}
+
+fn render_mistral(messages: &[ChatMessage]) -> String {
+    let system: Vec<&str> = messages
+        .iter()
+        .filter(|message| message.role == Role::System)
+        .map(|message| message.content.as_str())
+        .collect();
+    let last_user = messages
+        .iter()
+        .rposition(|message| message.role == Role::User);
+    let mut prompt = String::new();
+    for (index, message) in messages.iter().enumerate() {
+        match message.role {
+            Role::System => {}
+            Role::User if Some(index) == last_user && !system.is_empty() => {
+                prompt.push_str(&format!(
+                    "[INST] {}\n\n{}[/INST]",
+                    system.join("\n\n"),
+                    message.content
+                ));
+            }
+            Role::User => prompt.push_str(&format!("[INST] {}[/INST]", message.content)),
+            Role::Assistant => prompt.push_str(&format!(" {}</s>", message.content)),
+        }
+    }
+    // A system message that no user turn followed still reaches the model.
+    if last_user.is_none() && !system.is_empty() {
+        prompt.push_str(&format!("[INST] {}[/INST]", system.join("\n\n")));
+    }
+    prompt
+}
+
+#[derive(Clone)]
+enum Model {
+    Mistral(Mistral),
+    Qwen2(Qwen2),
+    Phi3(Phi3),
+}
+
+impl Model {
+    fn forward(&mut self, input_ids: &Tensor, pos: usize) -> candle_core::Result<Tensor> {
+        match self {
+            Self::Mistral(m) => m.forward(input_ids, pos),
+            Self::Qwen2(m) => m.forward(input_ids, pos),
+            Self::Phi3(m) => m.forward(input_ids, pos),
+        }
+    }
+
+    fn clear_kv_cache(&mut self) {
+        match self {
+            Self::Mistral(m) => m.clear_kv_cache(),
+            Self::Qwen2(m) => m.clear_kv_cache(),
+            Self::Phi3(m) => m.clear_kv_cache(),
+        }
+    }
+}
+
+struct TextGeneration {
+    model: Model,
+    device: Device,
+    tokenizer: TokenOutputStream,
+    logits_processor: LogitsProcessor,
+    repeat_penalty: f32,
+    repeat_last_n: usize,
+    no_repeat_ngram_size: usize,
+    frequency_penalty: f32,
+    stop: Vec<String>,
+    cancel: CancelToken,
+    /// Prompt tokens per forward pass while prefilling; `0` for a single pass.
+    prefill_chunk_size: usize,
+}
+
+/// Log probability of `token` under the distribution described by `logits`.
+fn token_logprob(logits: &Tensor, token: u32) -> Result<f32> {
+    let log_probs = candle_nn::ops::log_softmax(logits, candle_core::D::Minus1)?;
+    Ok(log_probs.get(token as usize)?.to_scalar::<f32>()?)
+}
+
+/// Short name of `device` for metadata, e.g. `cuda:0`.
+fn device_name(device: &Device) -> String {
+    match device.location() {
+        DeviceLocation::Cpu => "cpu".to_string(),
+        DeviceLocation::Cuda { gpu_id } => format!("cuda:{gpu_id}"),
+        DeviceLocation::Metal { gpu_id } => format!("metal:{gpu_id}"),
+    }
+}
+
+impl TextGeneration {
+    fn new(
+        model: Model,
+        tokenizer: Tokenizer,
+        cfg: &ModelInferenceConfig,
+        device: &Device,
+        stop: Vec<String>,
+        cancel: CancelToken,
+    ) -> Self {
+        let sampling = if cfg.temperature < 1e-7 {
+            Sampling::ArgMax
+        } else {
+            let temperature = cfg.temperature;
+            match (cfg.top_k, cfg.top_p) {
+                (None, None) => Sampling::All { temperature },
+                (Some(k), None) => Sampling::TopK { k, temperature },
+                (None, Some(p)) => Sampling::TopP { p, temperature },
+                (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
+            }
+        };
+        Self {
+            model,
+            device: device.clone(),
+            tokenizer: TokenOutputStream::new(tokenizer),
+            logits_processor: LogitsProcessor::from_sampling(cfg.seed, sampling),
+            repeat_penalty: cfg.repeat_penalty,
+            repeat_last_n: cfg.repeat_last_n,
+            no_repeat_ngram_size: cfg.no_repeat_ngram_size,
+            frequency_penalty: cfg.frequency_penalty,
+            stop,
+            cancel,
+            prefill_chunk_size: cfg.prefill_chunk_size,
+        }
+    }
+
+    /// Stream-only generation: sends a [`TokenEvent`] per prompt token, prefill progress
+    /// events, and an event per generated token over `tx`, followed by a final event carrying
+    /// the finish reason.
+    ///
+    /// The model's KV cache must already hold the first `cached_len` prompt tokens; only the
+    /// rest are prefilled. Once more than `context_length` tokens are in play the cache is
+    /// rebuilt from the most recent tokens (see [`ContextPolicy::SlidingWindow`]).
+    ///
+    /// Returns the tokens held in the KV cache once generation ends, or `None` if the window
+    /// slid and the cache no longer starts at the beginning of the conversation.
+    fn run_stream(
+        &mut self,
+        mut tokens: Vec<u32>,
+        cached_len: usize,
+        sample_len: usize,
+        context_length: usize,
+        tx: TokenSender,
+    ) -> Result<Option<Vec<u32>>> {
+        self.tokenizer.clear();
+        // Tokens from here on are generated; the frequency penalty counts only those.
+        let prompt_len = tokens.len();
+
+        // Warm the tokenizer's internal state with prompt tokens (so merges are correct).
+        // Prompt tokens are reported to the receiver without any text.
+        for &t in tokens.iter() {
+            let _ = self.tokenizer.next_token(t)?;
+            let _ = tx.send(Ok(TokenEvent::prompt(t)));
+        }
+
+        let stop_tokens: Vec<u32> = STOP_TOKENS
+            .iter()
+            .filter_map(|token| self.tokenizer.get_token(token))
+            .collect();
+        if stop_tokens.is_empty() {
+            anyhow::bail!("cannot find an end-of-sequence token in the vocabulary");
+        }
+
+        let mut stop = StopSequences::new(&self.stop);
+        let mut finish_reason = FinishReason::Length;
+        // First token covered by the KV cache; moves forward when the window slides.
+        let mut window_start = 0;
+
+        // Prefill the part of the prompt that is not cached yet, reporting progress so the
+        // caller can keep its connection alive through a long prompt.
+        let mut prefilled = if sample_len == 0 {
+            None
+        } else {
+            chunked_prefill(
+                &tokens[cached_len..],
+                self.prefill_chunk_size,
+                &self.cancel,
+                |chunk, offset| {
+                    let input = Tensor::new(chunk, &self.device)?.unsqueeze(0)?;
+                    Ok(self.model.forward(&input, cached_len + offset)?)
+                },
+                // A reader that went away stops the prefill before its next chunk
+                |progress| tx.send(Ok(TokenEvent::prefill(progress.processed, progress.total))),
+            )?
+        };
+        if sample_len > 0 && prefilled.is_none() {
+            // Stopped partway through the prompt, so the KV cache covers no prefix of
+            // `tokens` worth keeping for the conversation
+            let _ = tx.send(Ok(TokenEvent::finished(FinishReason::Cancelled, "")));
+            return Ok(None);
+        }
+        // Number of tokens whose keys and values are in the model's KV cache.
+        let mut processed = if prefilled.is_some() {
+            tokens.len()
+        } else {
+            cached_len
+        };
+
+        for _ in 0..sample_len {
+            // Nobody reads the tokens of a stream whose reader went away or stalled
+            if self.cancel.is_cancelled() || tx.is_closed() {
+                finish_reason = FinishReason::Cancelled;
+                break;
+            }
+
+            let logits = match prefilled.take() {
+                Some(logits) => logits,
+                None => {
+                    if tokens.len() - window_start > context_length {
+                        window_start = tokens.len() - ContextPolicy::slide_len(context_length);
+                        processed = window_start;
+                        self.model.clear_kv_cache();
+                    }
+
+                    let ctxt = &tokens[processed..];
+                    let input = Tensor::new(ctxt, &self.device)?.unsqueeze(0)?;
+                    let logits = self.model.forward(&input, processed - window_start)?;
+                    processed = tokens.len();
+                    logits
+                }
+            };
+            let logits = logits.squeeze(0)?.squeeze(0)?.to_dtype(DType::F32)?;
+
+            let logits = if self.repeat_penalty == 1. {
+                logits
+            } else {
+                let start_at = tokens.len().saturating_sub(self.repeat_last_n);
+                candle_transformers::utils::apply_repeat_penalty(
+                    &logits,
+                    self.repeat_penalty,
+                    &tokens[start_at..],
+                )?
+            };
+            let logits = ban_repeated_ngrams(&logits, &tokens, self.no_repeat_ngram_size)?;
+            let logits =
+                apply_frequency_penalty(&logits, &tokens[prompt_len..], self.frequency_penalty)?;
+
+            let next_token = self.logits_processor.sample(&logits)?;
+            let logprob = token_logprob(&logits, next_token)?;
+            tokens.push(next_token);
+
+            if stop_tokens.contains(&next_token) {
+                finish_reason = FinishReason::Stop;
+                break;
+            }
+
+            // Text that may start a stop sequence is held back until it is resolved.
+            let text = self.tokenizer.next_token(next_token)?.unwrap_or_default();
+            match stop.push(&text) {
+                StopCheck::Continue(text) => {
+                    // Waits while the reader is behind; a dropped reader ends the loop above.
+                    let _ = tx.send(Ok(TokenEvent::generated(next_token, text, Some(logprob))));
+                }
+                StopCheck::Stop(text) => {
+                    let _ = tx.send(Ok(TokenEvent::generated(next_token, text, Some(logprob))));
+                    let _ = tx.send(Ok(TokenEvent::finished(FinishReason::Stop, "")));
+                    tokens.truncate(processed);
+                    return Ok((window_start == 0).then_some(tokens));
+                }
+            }
+        }
+
+        // Flush any remaining buffered bytes with the final event.
+        let rest = self
+            .tokenizer
+            .decode_rest()
+            .map_err(E::msg)?
+            .unwrap_or_default();
+        let (rest, finish_reason) = match stop.push(&rest) {
+            StopCheck::Continue(text) => (text + &stop.flush(), finish_reason),
+            StopCheck::Stop(text) => (text, FinishReason::Stop),
+        };
+        let _ = tx.send(Ok(TokenEvent::finished(finish_reason, rest)));
+
+        tokens.truncate(processed);
+        Ok((window_start == 0).then_some(tokens))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ModelInferenceConfig {
+    pub model: WhichModel,
+    pub cpu: bool,
+    /// Device to load the model on, e.g. `cuda:1` to place it on the second GPU. `cpu`
+    /// overrides it.
+    pub device: DeviceSpec,
+    /// `f16`, `bf16` or `f32`. Defaults to `bf16` on CUDA and `f32` elsewhere.
+    pub dtype: Option<String>,
+    /// Repository to load instead of the one `model` names. `model` still selects the
+    /// architecture and chat template.
+    pub model_id: Option<String>,
+    pub revision: String,
+    pub seed: u64,
+    pub temperature: f64,
+    pub top_p: Option<f64>,
+    /// Only sample among the `top_k` most likely tokens (applied before `top_p`).
+    pub top_k: Option<usize>,
+    pub repeat_penalty: f32,
+    pub repeat_last_n: usize,
+    /// Never repeat an n-gram of this many tokens, counting the prompt. `0` disables it.
+    pub no_repeat_ngram_size: usize,
+    /// Lower each token's logit by this much per time it was already generated, like
+    /// OpenAI's `frequency_penalty`. `0.0` disables it.
+    pub frequency_penalty: f32,
+    pub max_tokens: usize,
+    /// Generation stops before any of these strings; the stop text is not emitted.
+    pub stop: Vec<String>,
+    /// Number of conversations whose KV state is retained between turns. Taken from the
+    /// config that first loads a model; `0` disables reuse.
+    pub max_conversations: usize,
+    /// What to do when the prompt plus `max_tokens` exceeds the model's context window.
+    pub context_policy: ContextPolicy,
+    /// Receives per-file progress while model files are downloaded from the Hub.
+    pub download_progress: Option<DownloadProgress>,
+    /// Number of safetensors shards downloaded at the same time, for split checkpoints.
+    pub parallel_downloads: usize,
+    /// Load the model from this directory instead of the Hub. It must hold `tokenizer.json`,
+    /// `config.json` and the safetensors weights. `model` still selects the architecture.
+    pub model_path: Option<PathBuf>,
+    /// CPU threads for tensor operations. `None` reads `RUNNER_THREADS`, then defaults to
+    /// one per physical core. The thread pool is process-wide: the first load sets it.
+    pub threads: Option<usize>,
+    /// Run a short generation right after the weights are loaded, so the first request does
+    /// not pay for kernel compilation. Skipped when the model comes from the cache.
+    pub warmup: bool,
+    /// Prompt tokens run through the model per forward pass while prefilling, with a
+    /// progress event after each pass. `0` prefills in one pass.
+    pub prefill_chunk_size: usize,
+}
+
+impl ModelInferenceConfig {
+    pub fn new(model: WhichModel) -> Self {
+        Self {
+            model,
+            cpu: false,
+            device: DeviceSpec::Auto,
+            dtype: None,
+            model_id: None,
+            revision: "main".to_string(),
+            seed: 299792458,
+            temperature: 0.7,
+            top_p: Some(0.9),
+            top_k: None,
+            repeat_penalty: 1.1,
+            repeat_last_n: 64,
+            no_repeat_ngram_size: 0,
+            frequency_penalty: 0.0,
+            max_tokens: 512,
+            stop: Vec::new(),
+            max_conversations: 8,
+            context_policy: ContextPolicy::default(),
+            download_progress: None,
+            parallel_downloads: DEFAULT_PARALLEL_DOWNLOADS,
+            model_path: None,
+            threads: None,
+            warmup: false,
+            prefill_chunk_size: DEFAULT_PREFILL_CHUNK,
+        }
+    }
+
+    /// Replace the sampling settings with those of `preset`.
+    pub fn with_preset(self, preset: SamplingPreset) -> Self {
+        let settings = preset.settings();
+        Self {
+            temperature: settings.temperature,
+            top_p: settings.top_p,
+            top_k: settings.top_k,
+            repeat_penalty: settings.repeat_penalty,
+            ..self
+        }
+    }
+}
+
+impl Default for ModelInferenceConfig {
+    fn default() -> Self {
+        Self::new(WhichModel::default())
+    }
+}
+
+/// Weights and tokenizer shared by every runner loaded with the same cache key.
+struct LoadedModel {
+    model: Model,
+    tokenizer: Tokenizer,
+    device: Device,
+    /// Maximum number of positions the model supports.
+    context_length: usize,
+    /// Number of weights in the checkpoint.
+    parameter_count: u64,
+    dtype: DType,
+    /// Chat template from the model's `tokenizer_config.json`, if it ships one.
+    chat_template: Option<JinjaChatTemplate>,
+    /// Model clones whose KV cache holds a previous turn, keyed by conversation id.
+    conversations: ConversationCache<Model>,
+}
+
+static MODEL_CACHE: LazyLock<ModelCache<LoadedModel>> = LazyLock::new(ModelCache::new);
+
+/// Drop every cached copy of a model repository (e.g. `Qwen/Qwen2.5-0.5B-Instruct`).
+/// Returns how many entries were removed.
+pub fn evict_model(model_id: &str) -> usize {
+    MODEL_CACHE.evict_model(model_id)
+}
+
+/// Drop every cached model.
+pub fn clear_model_cache() {
+    MODEL_CACHE.clear()
+}
+
+/// Models currently held in the cache.
+pub fn cached_models() -> Vec<CacheKey> {
+    MODEL_CACHE.keys()
+}
+
+/// Cached models no runner is using, with when each was last loaded.
+pub fn idle_models() -> Vec<(CacheKey, std::time::Instant)> {
+    MODEL_CACHE.idle()
+}
+
+/// Drop a cached model if no runner is using it. Returns whether it was dropped.
+pub fn evict_idle_model(key: &CacheKey) -> bool {
+    MODEL_CACHE.evict_if_idle(key)
+}
+
+/// Download the files [`TextModelRunner::load`] reads for `model` from the hub into the
+/// local cache without loading them, e.g. to prepare a machine that will run offline.
+/// Returns their paths in the cache, named as a local model directory expects them.
+pub fn download_model(
+    model: WhichModel,
+    progress: Option<DownloadProgress>,
+) -> Result<Vec<PathBuf>> {
+    let api = hub_api()?;
+    let files = HubFiles::new(&api, Repo::model(model.repo_id().to_string()), progress);
+    let mut paths = vec![files.get("tokenizer.json")?, files.get("config.json")?];
+    paths.extend(files.get_checkpoint(model.is_sharded())?);
+    // Not every repository ships one; without it the built-in prompt format is used
+    paths.extend(files.get(TOKENIZER_CONFIG).ok());
+    Ok(paths)
+}
+
+/// Context length of `model` if a copy loaded from the hub is in the cache.
+pub fn cached_context_length(model: WhichModel) -> Option<usize> {
+    MODEL_CACHE
+        .get_model(model.repo_id())
+        .map(|loaded| loaded.context_length)
+}
+
+/// Drop the cached copies of `model`. Returns how many were dropped.
+pub fn evict_cached_model(model: WhichModel) -> usize {
+    MODEL_CACHE.evict_model(model.repo_id())
+}
+
+/// Fetch and build the model in `files`. Called by the model cache on a miss.
+fn load_model(
+    files: &ModelFiles,
+    cfg: &ModelInferenceConfig,
+    dtype: DType,
+    device: Device,
+) -> Result<LoadedModel> {
+    let start = std::time::Instant::now();
+
+    let tokenizer_filename = files.get("tokenizer.json")?;
+    let config_filename = files.get("config.json")?;
+    let filenames = files.safetensors(cfg.model.is_sharded())?;
+    println!("Retrieved files in {:?}", start.elapsed());
+    let parameter_count = safetensors_parameter_count(&filenames)?;
+
+    let tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
+
+    let start = std::time::Instant::now();
+    let vb = unsafe { VarBuilder::from_mmaped_safetensors(&filenames, dtype, &device)? };
+    let config = std::fs::File::open(config_filename)?;
+    let (model, context_length) = match cfg.model.chat_template() {
+        ChatTemplate::Mistral => {
+            let config: MistralConfig = serde_json::from_reader(config)?;
+            let model = Mistral::new(&config, vb)?;
+            (Model::Mistral(model), config.max_position_embeddings)
+        }
+        ChatTemplate::ChatMl => {
+            let config: Qwen2Config = serde_json::from_reader(config)?;
+            let model = Qwen2::new(&config, vb)?;
+            (Model::Qwen2(model), config.max_position_embeddings)
+        }
+        ChatTemplate::Phi3 => {
+            let config: Phi3Config = serde_json::from_reader(config)?;
+            let model = Phi3::new(&config, vb)?;
+            (Model::Phi3(model), config.max_position_embeddings)
+        }
+    };
+    println!("Loaded model in {:?}", start.elapsed());
+
+    Ok(LoadedModel {
+        model,
+        tokenizer,
+        device,
+        context_length,
+        parameter_count,
+        dtype,
+        chat_template: JinjaChatTemplate::from_model_files(files),
+        conversations: ConversationCache::new(cfg.max_conversations),
+    })
+}
+
+/// A loaded Mistral, Qwen2.5 or Phi-3 model. Weights are read once per model id, dtype and
+/// device and shared through the model cache; every generation works on a cheap clone of
+/// the model with a fresh KV cache.
+pub struct TextModelRunner {
+    loaded: Arc<LoadedModel>,
+    config: ModelInferenceConfig,
+    metadata: RunnerMetadata,
+    cancel: CancelHandle,
+}
+
+impl TextModelRunner {
+    fn try_load(cfg: ModelInferenceConfig) -> Result<Self> {
+        println!("CPU features: {}", CpuFeatures::detect());
+
+        let threads = configure_threads(cfg.threads)?;
+        println!("CPU threads: {threads}");
+
+        let device = if cfg.cpu { DeviceSpec::Cpu } else { cfg.device }.open()?;
+        println!("Device: {:?}", device);
+
+        let dtype = match cfg.dtype.as_deref() {
+            Some("f16") => DType::F16,
+            Some("bf16") => DType::BF16,
+            Some("f32") => DType::F32,
+            Some(dtype) => anyhow::bail!("Unsupported dtype {dtype}"),
+            None if device.is_cuda() => DType::BF16,
+            None => DType::F32,
+        };
+        println!("Using dtype: {:?}", dtype);
+
+        let start = std::time::Instant::now();
+        let model_id = cfg
+            .model_id
+            .clone()
+            .unwrap_or_else(|| cfg.model.repo_id().to_string());
+        let local = cfg.model_path.clone().map(LocalFiles::new).transpose()?;
+        let source = match &local {
+            Some(local) => local.dir().display().to_string(),
+            None => model_id.clone(),
+        };
+        println!("Loading model: {}", source);
+
+        let mut key = CacheKey::new(
+            source.clone(),
+            dtype.as_str(),
+            format!("{:?}", device.location()),
+        );
+        if local.is_none() {
+            key = key.with_revision(cfg.revision.clone());
+        }
+        // Set when the weights are read rather than taken from the cache.
+        let mut fresh = false;
+        let loaded = MODEL_CACHE.get_or_load(&key, || {
+            fresh = true;
+            let files = match &local {
+                Some(local) => ModelFiles::Local(local.clone()),
+                None => ModelFiles::Hub(Box::new(
+                    HubFiles::new(
+                        &hub_api()?,
+                        Repo::with_revision(
+                            model_id.clone(),
+                            RepoType::Model,
+                            cfg.revision.clone(),
+                        ),
+                        cfg.download_progress.clone(),
+                    )
+                    .with_parallel_downloads(cfg.parallel_downloads),
+                )),
+            };
+            load_model(&files, &cfg, dtype, device)
+        })?;
+        println!("Model ready in {:?}", start.elapsed());
+
+        let metadata = RunnerMetadata {
+            model_id: cfg
+                .model
+                .to_possible_value()
+                .map_or_else(|| model_id.clone(), |value| value.get_name().to_string()),
+            repo_id: source,
+            family: cfg.model.family().to_string(),
+            owned_by: cfg.model.owned_by().to_string(),
+            context_length: loaded.context_length,
+            vocab_size: loaded.tokenizer.get_vocab_size(true),
+            parameter_count: loaded.parameter_count,
+            dtype: loaded.dtype.as_str().to_string(),
+            device: device_name(&loaded.device),
+        };
+
+        let runner = Self {
+            loaded,
+            config: cfg,
+            metadata,
+            cancel: CancelHandle::new(),
+        };
+        if fresh && runner.config.warmup {
+            println!("Warmup finished in {:?}", runner.warmup()?);
+        }
+        Ok(runner)
+    }
+
+    fn try_generate_stream(&self, request: GenerationRequest) -> Result<TokenReceiver> {
+        // Encode prompt (context only; prompt tokens are reported but carry no text).
+        let mut tokens = self
+            .loaded
+            .tokenizer
+            .encode(request.prompt.as_str(), true)
+            .map_err(E::msg)?
+            .get_ids()
+            .to_vec();
+        let max_tokens = self.config.context_policy.fit(
+            &mut tokens,
+            request.max_tokens,
+            self.loaded.context_length,
+        )?;
+
+        // Resume the conversation's previous turn if this prompt extends it.
+        let resumed = request
+            .conversation_id
+            .as_deref()
+            .and_then(|id| self.loaded.conversations.take_prefix(id, &tokens));
+        let (model, cached_len) = match resumed {
+            Some(resumed) => resumed,
+            None => {
+                let mut model = self.loaded.model.clone();
+                model.clear_kv_cache();
+                (model, 0)
+            }
+        };
+
+        let mut pipeline = TextGeneration::new(
+            model,
+            self.loaded.tokenizer.clone(),
+            &self.config,
+            &self.loaded.device,
+            [self.config.stop.as_slice(), request.stop.as_slice()].concat(),
+            self.cancel.token(),
+        );
+
+        // Bounded, so a slow reader holds the generation back rather than letting tokens
+        // pile up.
+        let (tx, rx) = token_channel();
+
+        let loaded = Arc::clone(&self.loaded);
+        thread::spawn(move || {
+            let context_length = loaded.context_length;
+            match pipeline.run_stream(tokens, cached_len, max_tokens, context_length, tx.clone()) {
+                Ok(kv_tokens) => {
+                    // Keep the KV state so the next turn only prefills its new tokens.
+                    if let (Some(id), Some(kv_tokens)) = (request.conversation_id, kv_tokens) {
+                        loaded.conversations.store(id, pipeline.model, kv_tokens);
+                    }
+                }
+                // If generation fails, forward the error once.
+                Err(e) => {
+                    let _ = tx.send(Err(RunnerError::generation(e)));
+                }
+            }
+        });
+
+        Ok(rx)
+    }
+}
+
+impl ModelRunner for TextModelRunner {
+    type Config = ModelInferenceConfig;
+
+    fn load(cfg: ModelInferenceConfig) -> Result<Self, RunnerError> {
+        Self::try_load(cfg).map_err(RunnerError::load)
+    }
+
+    fn generate_stream(&self, request: GenerationRequest) -> Result<TokenReceiver, RunnerError> {
+        self.try_generate_stream(request)
+            .map_err(RunnerError::generation)
+    }
+
+    fn metadata(&self) -> &RunnerMetadata {
+        &self.metadata
+    }
+
+    fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    fn chat_template(&self) -> Option<&JinjaChatTemplate> {
+        self.loaded.chat_template.as_ref()
+    }
+
+    fn count_tokens(&self, prompt: &str) -> Option<usize> {
+        let encoding = self.loaded.tokenizer.encode(prompt, true).ok()?;
+        Some(encoding.len())
+    }
+
+    fn forget_conversation(&self, conversation_id: &str) -> bool {
+        self.loaded.conversations.remove(conversation_id)
+    }
+
+    fn touch(&self) {
+        MODEL_CACHE.touch(&self.loaded);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runner_core::test_utils::{seeded_var_builder, word_level_tokenizer_json};
+
+    const VOCAB: [&str; 16] = [
+        "<unk>",
+        "<|endoftext|>",
+        "<|im_end|>",
+        "the",
+        "a",
+        "cat",
+        "dog",
+        "sat",
+        "on",
+        "mat",
+        "ran",
+        "to",
+        "park",
+        "and",
+        "then",
+        "slept",
+    ];
+
+    /// A two-layer model of `template`'s architecture with a word-level tokenizer.
+    fn tiny_runner(template: ChatTemplate, config: ModelInferenceConfig) -> TextModelRunner {
+        let device = Device::Cpu;
+        let model_config = serde_json::json!({
+            "vocab_size": VOCAB.len(),
+            "hidden_size": 16,
+            "intermediate_size": 32,
+            "num_hidden_layers": 2,
+            "num_attention_heads": 2,
+            "num_key_value_heads": 1,
+            "max_position_embeddings": 64,
+            "sliding_window": 64,
+            "max_window_layers": 2,
+            "tie_word_embeddings": false,
+            "rope_theta": 10000.0,
+            "rms_norm_eps": 1e-6,
+            "use_sliding_window": false,
+            "hidden_act": "silu",
+            "bos_token_id": null,
+            "eos_token_id": null,
+            "rope_scaling": null,
+        });
+        let vb = seeded_var_builder(&device);
+        let model = match template {
+            ChatTemplate::Mistral => Model::Mistral(
+                Mistral::new(&serde_json::from_value(model_config).unwrap(), vb).unwrap(),
+            ),
+            ChatTemplate::ChatMl => Model::Qwen2(
+                Qwen2::new(&serde_json::from_value(model_config).unwrap(), vb).unwrap(),
+            ),
+            ChatTemplate::Phi3 => {
+                Model::Phi3(Phi3::new(&serde_json::from_value(model_config).unwrap(), vb).unwrap())
+            }
+        };
+
+        let tokenizer: Tokenizer = word_level_tokenizer_json(&VOCAB).parse().unwrap();
+
+        TextModelRunner {
+            loaded: Arc::new(LoadedModel {
+                model,
+                tokenizer,
+                device,
+                context_length: 64,
+                parameter_count: 0,
+                dtype: DType::F32,
+                chat_template: None,
+                conversations: ConversationCache::new(0),
+            }),
+            config,
+            metadata: RunnerMetadata {
+                model_id: "tiny".to_string(),
+                repo_id: "tiny".to_string(),
+                family: "tiny".to_string(),
+                owned_by: "test".to_string(),
+                context_length: 64,
+                vocab_size: VOCAB.len(),
+                parameter_count: 0,
+                dtype: "f32".to_string(),
+                device: "cpu".to_string(),
+            },
+            cancel: CancelHandle::new(),
+        }
+    }
+
+    fn generated_ids(runner: &TextModelRunner) -> Vec<u32> {
+        let request = GenerationRequest::new("the cat sat on the mat", 8);
+        let mut rx = runner.generate_stream(request).unwrap();
+        let mut ids = Vec::new();
+        while let Some(event) = rx.blocking_recv() {
+            let event = event.unwrap();
+            if !event.is_prompt {
+                ids.extend(event.token_id);
+            }
+        }
+        ids
+    }
+
+    #[test]
+    fn test_templates_render_each_format() {
+        let messages = [
+            ChatMessage::system("Be brief."),
+            ChatMessage::user("Knock knock."),
+            ChatMessage::assistant("Who's there?"),
+            ChatMessage::user("Qwen."),
+        ];
+        assert_eq!(
+            ChatTemplate::ChatMl.render(&messages),
+            "<|im_start|>system\nBe brief.<|im_end|>\n\
+             <|im_start|>user\nKnock knock.<|im_end|>\n\
+             <|im_start|>assistant\nWho's there?<|im_end|>\n\
+             <|im_start|>user\nQwen.<|im_end|>\n\
+             <|im_start|>assistant\n"
+        );
+        assert_eq!(
+            ChatTemplate::Phi3.render(&messages[1..]),
+            "<|user|>\nKnock knock.<|end|>\n\
+             <|assistant|>\nWho's there?<|end|>\n\
+             <|user|>\nQwen.<|end|>\n\
+             <|assistant|>\n"
+        );
+        assert_eq!(
+            ChatTemplate::Mistral.render(&messages),
+            "[INST] Knock knock.[/INST] Who's there?</s>[INST] Be brief.\n\nQwen.[/INST]"
+        );
+        assert_eq!(
+            ChatTemplate::Mistral.render(&messages[..1]),
+            "[INST] Be brief.[/INST]"
+        );
+    }
+
+    #[test]
+    fn test_every_model_has_a_template_and_family() {
+        for model in WhichModel::value_variants() {
+            let family = model.family();
+            let expected = match model.chat_template() {
+                ChatTemplate::Mistral => "mistral",
+                ChatTemplate::ChatMl => "qwen2",
+                ChatTemplate::Phi3 => "phi3",
+            };
+            assert_eq!(family, expected, "{model:?}");
+        }
+    }
+
+    #[test]
+    fn test_each_architecture_generates_greedily() {
+        let config = ModelInferenceConfig {
+            temperature: 0.0,
+            ..Default::default()
+        };
+        for template in [
+            ChatTemplate::Mistral,
+            ChatTemplate::ChatMl,
+            ChatTemplate::Phi3,
+        ] {
+            let runner = tiny_runner(template, config.clone());
+            let first = generated_ids(&runner);
+            assert!(!first.is_empty(), "{template:?}");
+            assert_eq!(generated_ids(&runner), first, "{template:?}");
+        }
+    }
+
+    #[test]
+    fn test_chunked_prefill_matches_a_single_pass() {
+        let config = |prefill_chunk_size| ModelInferenceConfig {
+            temperature: 0.0,
+            prefill_chunk_size,
+            ..Default::default()
+        };
+        for template in [
+            ChatTemplate::Mistral,
+            ChatTemplate::ChatMl,
+            ChatTemplate::Phi3,
+        ] {
+            assert_eq!(
+                generated_ids(&tiny_runner(template, config(2))),
+                generated_ids(&tiny_runner(template, config(0))),
+                "{template:?}"
+            );
+        }
+    }
+}
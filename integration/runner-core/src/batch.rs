@@ -0,0 +1,55 @@
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+use crate::{RunnerError, TokenEvent, TokenReceiver};
+
+/// Events of a batched generation, tagged with the index of the request they belong to.
+///
+/// Streams are interleaved, but the events of any one request arrive in order and end with
+/// that request's own final event (or an error).
+pub type BatchReceiver = UnboundedReceiver<(usize, Result<TokenEvent, RunnerError>)>;
+
+/// Interleave independent token streams into one [`BatchReceiver`], tagging every event
+/// with the position of its stream in `streams`.
+pub fn merge_streams(streams: Vec<TokenReceiver>) -> BatchReceiver {
+    let (tx, rx) = mpsc::unbounded_channel();
+    for (index, mut stream) in streams.into_iter().enumerate() {
+        let tx = tx.clone();
+        std::thread::spawn(move || {
+            while let Some(event) = stream.blocking_recv() {
+                if tx.send((index, event)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{token_channel, FinishReason};
+
+    #[test]
+    fn test_merge_streams_tags_events() {
+        let mut streams = Vec::new();
+        for text in ["a", "b"] {
+            let (tx, rx) = token_channel();
+            assert!(tx.send(Ok(TokenEvent::generated(1, text, None))));
+            assert!(tx.send(Ok(TokenEvent::finished(FinishReason::Stop, ""))));
+            streams.push(rx);
+        }
+
+        let mut rx = merge_streams(streams);
+        let mut per_stream = [Vec::new(), Vec::new()];
+        while let Some((index, event)) = rx.blocking_recv() {
+            per_stream[index].push(event.unwrap());
+        }
+
+        for (events, text) in per_stream.iter().zip(["a", "b"]) {
+            assert_eq!(events.len(), 2);
+            assert_eq!(events[0].text, text);
+            assert_eq!(events[1].finish_reason, Some(FinishReason::Stop));
+        }
+    }
+}
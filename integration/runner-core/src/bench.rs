@@ -0,0 +1,187 @@
+use anyhow::{bail, Result};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::{GenerationRequest, ModelRunner};
+
+/// Shape of a benchmark: every run sends the same synthetic prompt.
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    /// Approximate prompt length in tokens; the prompt repeats one common word.
+    pub prompt_tokens: usize,
+    /// Tokens to generate per run. A run may stop earlier at an end-of-sequence token.
+    pub max_tokens: usize,
+    /// Measured runs.
+    pub runs: usize,
+    /// Runs made before measuring, to warm up kernels and allocators.
+    pub warmup: usize,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            prompt_tokens: 128,
+            max_tokens: 128,
+            runs: 5,
+            warmup: 1,
+        }
+    }
+}
+
+/// Timings of one generation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunStats {
+    pub prompt_tokens: usize,
+    pub generated_tokens: usize,
+    /// From the request until the first generated token arrived.
+    pub time_to_first_token: Duration,
+    /// From the request until the stream ended.
+    pub total: Duration,
+}
+
+impl RunStats {
+    /// Decode throughput: tokens after the first one over the time spent producing them.
+    pub fn tokens_per_sec(&self) -> f64 {
+        let decode = self.total.saturating_sub(self.time_to_first_token);
+        if self.generated_tokens < 2 || decode.is_zero() {
+            return 0.0;
+        }
+        (self.generated_tokens - 1) as f64 / decode.as_secs_f64()
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "prompt_tokens": self.prompt_tokens,
+            "generated_tokens": self.generated_tokens,
+            "ttft_ms": millis(self.time_to_first_token),
+            "total_ms": millis(self.total),
+            "tokens_per_sec": self.tokens_per_sec(),
+        })
+    }
+}
+
+/// Result of a benchmark, printed as JSON by the runner CLIs.
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    pub model_id: String,
+    pub config: BenchConfig,
+    pub load_time: Duration,
+    pub runs: Vec<RunStats>,
+    /// Peak resident memory of the process, where the platform reports it.
+    pub peak_memory_bytes: Option<u64>,
+}
+
+impl BenchReport {
+    pub fn to_json(&self) -> serde_json::Value {
+        let mean = |value: fn(&RunStats) -> f64| {
+            self.runs.iter().map(value).sum::<f64>() / self.runs.len().max(1) as f64
+        };
+        serde_json::json!({
+            "model": self.model_id,
+            "prompt_tokens": self.config.prompt_tokens,
+            "max_tokens": self.config.max_tokens,
+            "warmup": self.config.warmup,
+            "load_ms": millis(self.load_time),
+            "runs": self.runs.iter().map(RunStats::to_json).collect::<Vec<_>>(),
+            "mean": {
+                "ttft_ms": mean(|run| millis(run.time_to_first_token)),
+                "total_ms": mean(|run| millis(run.total)),
+                "tokens_per_sec": mean(RunStats::tokens_per_sec),
+            },
+            "peak_memory_bytes": self.peak_memory_bytes,
+        })
+    }
+
+    /// Write the report as pretty-printed JSON to `output`, or to stdout.
+    pub fn write_json(&self, output: Option<&Path>) -> Result<()> {
+        write_json(&self.to_json(), output)
+    }
+}
+
+pub(crate) fn write_json(json: &serde_json::Value, output: Option<&Path>) -> Result<()> {
+    let json = serde_json::to_string_pretty(json)?;
+    match output {
+        Some(path) => std::fs::write(path, json + "\n")?,
+        None => println!("{json}"),
+    }
+    Ok(())
+}
+
+pub(crate) fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Run the warmup and measured generations of `config` on a loaded runner.
+pub fn run_bench<R: ModelRunner + ?Sized>(
+    runner: &R,
+    config: &BenchConfig,
+) -> Result<Vec<RunStats>> {
+    let prompt = vec!["the"; config.prompt_tokens].join(" ");
+    for _ in 0..config.warmup {
+        bench_once(runner, &prompt, config.max_tokens)?;
+    }
+    (0..config.runs)
+        .map(|_| bench_once(runner, &prompt, config.max_tokens))
+        .collect()
+}
+
+fn bench_once<R: ModelRunner + ?Sized>(
+    runner: &R,
+    prompt: &str,
+    max_tokens: usize,
+) -> Result<RunStats> {
+    let start = Instant::now();
+    let mut rx = runner.generate_stream(GenerationRequest::new(prompt, max_tokens))?;
+    let mut prompt_tokens = 0;
+    let mut generated_tokens = 0;
+    let mut time_to_first_token = None;
+    while let Some(event) = rx.blocking_recv() {
+        let event = event?;
+        if event.prefill.is_some() {
+            continue;
+        }
+        if event.is_prompt {
+            prompt_tokens += 1;
+        } else if event.token_id.is_some() {
+            generated_tokens += 1;
+            time_to_first_token.get_or_insert_with(|| start.elapsed());
+        }
+    }
+    let total = start.elapsed();
+    if generated_tokens == 0 {
+        bail!("the model generated no tokens");
+    }
+    Ok(RunStats {
+        prompt_tokens,
+        generated_tokens,
+        time_to_first_token: time_to_first_token.unwrap_or(total),
+        total,
+    })
+}
+
+/// Peak resident set size of this process (`VmHWM`). Only available on Linux.
+pub fn peak_memory_bytes() -> Option<u64> {
+    crate::memory::proc_status_bytes("VmHWM:")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_per_sec_excludes_first_token() {
+        let run = RunStats {
+            prompt_tokens: 8,
+            generated_tokens: 11,
+            time_to_first_token: Duration::from_millis(500),
+            total: Duration::from_millis(1500),
+        };
+        assert_eq!(run.tokens_per_sec(), 10.0);
+
+        let single = RunStats {
+            generated_tokens: 1,
+            ..run
+        };
+        assert_eq!(single.tokens_per_sec(), 0.0);
+    }
+}
@@ -0,0 +1,329 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+/// Identifies one set of loaded weights. Two loads with the same key share a model.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    /// HuggingFace repository the weights come from, e.g. `google/gemma-3-1b-it`.
+    pub model_id: String,
+    /// Tensor dtype, e.g. `bf16`.
+    pub dtype: String,
+    /// Device the weights live on, e.g. `Cpu` or `Cuda { gpu_id: 0 }`.
+    pub device: String,
+    /// Hub revision the weights were downloaded at, `None` for local files.
+    pub revision: Option<String>,
+}
+
+impl CacheKey {
+    pub fn new(
+        model_id: impl Into<String>,
+        dtype: impl Into<String>,
+        device: impl Into<String>,
+    ) -> Self {
+        Self {
+            model_id: model_id.into(),
+            dtype: dtype.into(),
+            device: device.into(),
+            revision: None,
+        }
+    }
+
+    /// The key for the weights of hub revision `revision`, so two revisions of one
+    /// repository are cached apart.
+    pub fn with_revision(mut self, revision: impl Into<String>) -> Self {
+        self.revision = Some(revision.into());
+        self
+    }
+}
+
+impl fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.model_id)?;
+        if let Some(revision) = &self.revision {
+            write!(f, "@{}", revision)?;
+        }
+        write!(f, " ({}, {})", self.dtype, self.device)
+    }
+}
+
+/// Process-wide cache of loaded models, shared by every runner of one family.
+///
+/// Entries stay loaded until they are evicted explicitly; generations already holding
+/// an `Arc` to an evicted model keep it alive until they finish.
+pub struct ModelCache<T> {
+    entries: RwLock<HashMap<CacheKey, Entry<T>>>,
+}
+
+struct Entry<T> {
+    model: Arc<T>,
+    /// When [`ModelCache::get_or_load`] last handed the model out, or
+    /// [`ModelCache::touch`] marked it used.
+    last_used: Mutex<Instant>,
+}
+
+impl<T> Entry<T> {
+    fn new(model: Arc<T>) -> Self {
+        Self {
+            model,
+            last_used: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn touch(&self) -> Arc<T> {
+        if let Ok(mut last_used) = self.last_used.lock() {
+            *last_used = Instant::now();
+        }
+        Arc::clone(&self.model)
+    }
+
+    fn last_used(&self) -> Instant {
+        self.last_used
+            .lock()
+            .map(|last_used| *last_used)
+            .unwrap_or_else(|e| *e.into_inner())
+    }
+
+    /// Whether only the cache holds the model, i.e. no runner or generation is using it.
+    fn is_idle(&self) -> bool {
+        Arc::strong_count(&self.model) == 1
+    }
+}
+
+impl<T> Default for ModelCache<T> {
+    fn default() -> Self {
+        Self {
+            entries: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T> ModelCache<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the cached model for `key`, calling `load` to build it on a miss.
+    pub fn get_or_load(&self, key: &CacheKey, load: impl FnOnce() -> Result<T>) -> Result<Arc<T>> {
+        // First try to get from cache (read lock)
+        {
+            let entries = self
+                .entries
+                .read()
+                .map_err(|e| anyhow!("Failed to acquire read lock: {}", e))?;
+            if let Some(entry) = entries.get(key) {
+                return Ok(entry.touch());
+            }
+        }
+
+        // Hold the write lock while loading so concurrent callers don't load the same
+        // weights twice.
+        let mut entries = self
+            .entries
+            .write()
+            .map_err(|e| anyhow!("Failed to acquire write lock: {}", e))?;
+        if let Some(entry) = entries.get(key) {
+            return Ok(entry.touch());
+        }
+
+        let model = Arc::new(load()?);
+        entries.insert(key.clone(), Entry::new(Arc::clone(&model)));
+        Ok(model)
+    }
+
+    /// Any cached copy of `model_id`, whatever its dtype or device.
+    pub fn get_model(&self, model_id: &str) -> Option<Arc<T>> {
+        let entries = self.entries.read().ok()?;
+        entries
+            .iter()
+            .find(|(key, _)| key.model_id == model_id)
+            .map(|(_, entry)| Arc::clone(&entry.model))
+    }
+
+    /// Mark `model` as used now, for a runner that keeps serving requests after its load.
+    /// Returns whether it is still cached.
+    pub fn touch(&self, model: &Arc<T>) -> bool {
+        let Ok(entries) = self.entries.read() else {
+            return false;
+        };
+        entries
+            .values()
+            .find(|entry| Arc::ptr_eq(&entry.model, model))
+            .map(Entry::touch)
+            .is_some()
+    }
+
+    /// Drop a single entry. Returns whether it was cached.
+    pub fn evict(&self, key: &CacheKey) -> bool {
+        self.entries
+            .write()
+            .map(|mut entries| entries.remove(key).is_some())
+            .unwrap_or(false)
+    }
+
+    /// Drop every entry for `model_id`, whatever its dtype or device. Returns how many
+    /// entries were removed.
+    pub fn evict_model(&self, model_id: &str) -> usize {
+        let Ok(mut entries) = self.entries.write() else {
+            return 0;
+        };
+        let before = entries.len();
+        entries.retain(|key, _| key.model_id != model_id);
+        before - entries.len()
+    }
+
+    /// Models that nothing but the cache holds, with when each was last loaded through
+    /// [`ModelCache::get_or_load`] or touched. These can be evicted without waiting for a generation.
+    pub fn idle(&self) -> Vec<(CacheKey, Instant)> {
+        self.entries
+            .read()
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter(|(_, entry)| entry.is_idle())
+                    .map(|(key, entry)| (key.clone(), entry.last_used()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Drop `key` if nothing but the cache holds it. Returns whether it was dropped.
+    pub fn evict_if_idle(&self, key: &CacheKey) -> bool {
+        let Ok(mut entries) = self.entries.write() else {
+            return false;
+        };
+        if !entries.get(key).is_some_and(Entry::is_idle) {
+            return false;
+        }
+        entries.remove(key).is_some()
+    }
+
+    /// Drop every entry.
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.entries.write() {
+            entries.clear();
+        }
+    }
+
+    /// Keys of the models currently loaded.
+    pub fn keys(&self) -> Vec<CacheKey> {
+        self.entries
+            .read()
+            .map(|entries| entries.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_or_load_reuses_entries() {
+        let cache = ModelCache::new();
+        let key = CacheKey::new("org/model", "f16", "Cpu");
+
+        let first = cache.get_or_load(&key, || Ok(1)).unwrap();
+        let second = cache
+            .get_or_load(&key, || panic!("model should be cached"))
+            .unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        let other = CacheKey::new("org/model", "f32", "Cpu");
+        assert_eq!(*cache.get_or_load(&other, || Ok(2)).unwrap(), 2);
+        assert_eq!(cache.keys().len(), 2);
+        assert!(cache.get_model("org/model").is_some());
+        assert!(cache.get_model("org/other").is_none());
+    }
+
+    #[test]
+    fn test_revisions_of_one_model_are_cached_apart() {
+        let cache = ModelCache::new();
+        let main = CacheKey::new("org/model", "f16", "Cpu").with_revision("main");
+        let pinned = CacheKey::new("org/model", "f16", "Cpu").with_revision("a1b2c3");
+
+        assert_eq!(*cache.get_or_load(&main, || Ok(1)).unwrap(), 1);
+        assert_eq!(*cache.get_or_load(&pinned, || Ok(2)).unwrap(), 2);
+        assert_eq!(cache.keys().len(), 2);
+        assert_eq!(pinned.to_string(), "org/model@a1b2c3 (f16, Cpu)");
+    }
+
+    #[test]
+    fn test_eviction() {
+        let cache = ModelCache::new();
+        let f16 = CacheKey::new("org/model", "f16", "Cpu");
+        let f32 = CacheKey::new("org/model", "f32", "Cpu");
+        let other = CacheKey::new("org/other", "f16", "Cpu");
+        for key in [&f16, &f32, &other] {
+            cache.get_or_load(key, || Ok(())).unwrap();
+        }
+
+        assert!(cache.evict(&f16));
+        assert!(!cache.evict(&f16));
+        assert_eq!(cache.evict_model("org/model"), 1);
+        assert_eq!(cache.keys(), vec![other]);
+
+        cache.clear();
+        assert!(cache.keys().is_empty());
+    }
+
+    #[test]
+    fn test_only_idle_entries_are_evicted() {
+        let cache = ModelCache::new();
+        let busy = CacheKey::new("org/busy", "f16", "Cpu");
+        let idle = CacheKey::new("org/idle", "f16", "Cpu");
+
+        let in_use = cache.get_or_load(&busy, || Ok(1)).unwrap();
+        cache.get_or_load(&idle, || Ok(2)).unwrap();
+        let idle_models: Vec<_> = cache.idle().into_iter().map(|(key, _)| key).collect();
+        assert_eq!(idle_models, vec![idle.clone()]);
+
+        assert!(!cache.evict_if_idle(&busy));
+        assert!(cache.evict_if_idle(&idle));
+        assert_eq!(cache.keys(), vec![busy.clone()]);
+
+        // Once the generation is done with it, the model is idle too
+        drop(in_use);
+        assert!(cache.evict_if_idle(&busy));
+        assert!(cache.keys().is_empty());
+    }
+
+    #[test]
+    fn test_idle_entries_report_their_last_use() {
+        let cache = ModelCache::new();
+        let first = CacheKey::new("org/first", "f16", "Cpu");
+        let second = CacheKey::new("org/second", "f16", "Cpu");
+        let tick = || std::thread::sleep(std::time::Duration::from_millis(2));
+        cache.get_or_load(&first, || Ok(())).unwrap();
+        tick();
+        cache.get_or_load(&second, || Ok(())).unwrap();
+        tick();
+        cache.get_or_load(&first, || Ok(())).unwrap();
+
+        let mut idle = cache.idle();
+        idle.sort_by_key(|(_, last_used)| *last_used);
+        let order: Vec<_> = idle.into_iter().map(|(key, _)| key).collect();
+        assert_eq!(order, vec![second.clone(), first.clone()]);
+
+        // A runner kept across requests marks its model used without reloading it
+        let kept = cache.get_model("org/second").unwrap();
+        tick();
+        assert!(cache.touch(&kept));
+        drop(kept);
+        let mut idle = cache.idle();
+        idle.sort_by_key(|(_, last_used)| *last_used);
+        let order: Vec<_> = idle.into_iter().map(|(key, _)| key).collect();
+        assert_eq!(order, vec![first, second]);
+    }
+
+    #[test]
+    fn test_failed_load_is_not_cached() {
+        let cache: ModelCache<u32> = ModelCache::new();
+        let key = CacheKey::new("org/model", "f16", "Cpu");
+
+        assert!(cache.get_or_load(&key, || Err(anyhow!("boom"))).is_err());
+        assert!(cache.keys().is_empty());
+    }
+}
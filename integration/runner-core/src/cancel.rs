@@ -0,0 +1,59 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Owned by a runner. Cancelling bumps an epoch, which invalidates every token issued
+/// before the call while leaving generations started afterwards unaffected.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle {
+    epoch: Arc<AtomicU64>,
+}
+
+impl CancelHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Issue a token for a generation that is about to start.
+    pub fn token(&self) -> CancelToken {
+        CancelToken {
+            epoch: self.epoch.clone(),
+            issued_at: self.epoch.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Cancel every generation holding a token issued before this call.
+    pub fn cancel(&self) {
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Checked by a generation loop between tokens.
+#[derive(Debug, Clone)]
+pub struct CancelToken {
+    epoch: Arc<AtomicU64>,
+    issued_at: u64,
+}
+
+impl CancelToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.epoch.load(Ordering::SeqCst) != self.issued_at
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_only_affects_existing_tokens() {
+        let handle = CancelHandle::new();
+        let before = handle.token();
+        assert!(!before.is_cancelled());
+
+        handle.cancel();
+        let after = handle.token();
+
+        assert!(before.is_cancelled());
+        assert!(!after.is_cancelled());
+    }
+}
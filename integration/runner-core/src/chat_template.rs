@@ -0,0 +1,248 @@
+use anyhow::{anyhow, Result};
+use minijinja::{context, Environment, ErrorKind};
+use serde_json::Value;
+use std::path::Path;
+
+use crate::{ChatMessage, ModelFiles, RunnerError};
+
+/// File of a HuggingFace repository holding the tokenizer's special tokens and, for chat
+/// models, the Jinja template their conversations are rendered with.
+pub const TOKENIZER_CONFIG: &str = "tokenizer_config.json";
+
+/// A model's own chat template, the Jinja source `transformers`' `apply_chat_template`
+/// renders, taken from its `tokenizer_config.json`.
+///
+/// Templates are rendered the way `transformers` does: with `trim_blocks` and
+/// `lstrip_blocks`, Python string methods such as `.strip()`, a `raise_exception` function,
+/// and `add_generation_prompt` set so the prompt ends with an open assistant turn.
+/// `strftime_now` is left undefined, so templates that would stamp today's date fall back
+/// to their fixed one and a conversation's prompts keep extending each other.
+#[derive(Debug, Clone)]
+pub struct JinjaChatTemplate {
+    source: String,
+    bos_token: Option<String>,
+    eos_token: Option<String>,
+}
+
+impl JinjaChatTemplate {
+    /// A template from its Jinja `source`, checking that it parses.
+    pub fn new(
+        source: impl Into<String>,
+        bos_token: Option<String>,
+        eos_token: Option<String>,
+    ) -> Result<Self> {
+        let template = Self {
+            source: source.into(),
+            bos_token,
+            eos_token,
+        };
+        environment()
+            .template_from_str(&template.source)
+            .map_err(|e| anyhow!("invalid chat template: {e}"))?;
+        Ok(template)
+    }
+
+    /// The template of a parsed `tokenizer_config.json`, or `None` when it has none, as
+    /// base models don't. Of several named templates the `default` one is used.
+    pub fn from_tokenizer_config(config: &Value) -> Result<Option<Self>> {
+        let source = match config.get("chat_template") {
+            Some(Value::String(source)) => source.as_str(),
+            Some(Value::Array(templates)) => {
+                let default = templates
+                    .iter()
+                    .find(|template| template["name"] == "default")
+                    .and_then(|template| template["template"].as_str());
+                match default {
+                    Some(source) => source,
+                    None => return Ok(None),
+                }
+            }
+            _ => return Ok(None),
+        };
+        // Special tokens are a string or an added-token object with its text in `content`
+        let token = |name: &str| match config.get(name) {
+            Some(Value::String(token)) => Some(token.clone()),
+            Some(Value::Object(token)) => token
+                .get("content")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            _ => None,
+        };
+        Self::new(source, token("bos_token"), token("eos_token")).map(Some)
+    }
+
+    /// The template in the `tokenizer_config.json` at `path`.
+    pub fn from_file(path: &Path) -> Result<Option<Self>> {
+        let config: Value = serde_json::from_reader(std::fs::File::open(path)?)?;
+        Self::from_tokenizer_config(&config)
+    }
+
+    /// The template of the model in `files`. A missing or unusable `tokenizer_config.json`
+    /// gives `None` with a warning, so the caller falls back to its built-in format.
+    pub fn from_model_files(files: &ModelFiles) -> Option<Self> {
+        let template = files
+            .get(TOKENIZER_CONFIG)
+            .and_then(|path| Self::from_file(&path));
+        match template {
+            Ok(template) => template,
+            Err(e) => {
+                eprintln!("Warning: no chat template from {TOKENIZER_CONFIG}: {e:#}");
+                None
+            }
+        }
+    }
+
+    /// Render `messages`, ending with an open assistant turn for the model to complete.
+    ///
+    /// Runners encode prompts with the tokenizer's special tokens, which already start with
+    /// the beginning-of-sequence token, so a leading `bos_token` rendered by the template is
+    /// dropped. A template that rejects the conversation, e.g. because its roles don't
+    /// alternate, fails with [`RunnerError::InvalidRequest`].
+    pub fn render(&self, messages: &[ChatMessage]) -> Result<String, RunnerError> {
+        let messages: Vec<Value> = messages
+            .iter()
+            .map(|message| {
+                serde_json::json!({
+                    "role": message.role.as_str(),
+                    "content": message.content,
+                })
+            })
+            .collect();
+        let env = environment();
+        let rendered = env
+            .template_from_str(&self.source)
+            .and_then(|template| {
+                template.render(context! {
+                    messages => messages,
+                    add_generation_prompt => true,
+                    bos_token => self.bos_token.as_deref().unwrap_or_default(),
+                    eos_token => self.eos_token.as_deref().unwrap_or_default(),
+                })
+            })
+            .map_err(|e| match e.kind() {
+                ErrorKind::InvalidOperation => {
+                    RunnerError::InvalidRequest(e.detail().unwrap_or("invalid chat").to_string())
+                }
+                _ => RunnerError::InvalidRequest(format!("rendering the chat template: {e}")),
+            })?;
+        Ok(
+            match self.bos_token.as_deref().filter(|bos| !bos.is_empty()) {
+                Some(bos) => rendered
+                    .strip_prefix(bos)
+                    .map(str::to_string)
+                    .unwrap_or(rendered),
+                None => rendered,
+            },
+        )
+    }
+}
+
+fn environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+    env.add_function("raise_exception", |message: String| -> Result<(), _> {
+        Err(minijinja::Error::new(ErrorKind::InvalidOperation, message))
+    });
+    env
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Role;
+
+    /// The chat template of `meta-llama/Llama-3.2-1B-Instruct`, without its tool calling.
+    const LLAMA3: &str = r#"{{- bos_token }}
+{%- if not date_string is defined %}
+    {%- if strftime_now is defined %}
+        {%- set date_string = strftime_now("%d %b %Y") %}
+    {%- else %}
+        {%- set date_string = "26 Jul 2024" %}
+    {%- endif %}
+{%- endif %}
+{%- if messages[0]['role'] == 'system' %}
+    {%- set system_message = messages[0]['content']|trim %}
+    {%- set messages = messages[1:] %}
+{%- else %}
+    {%- set system_message = "" %}
+{%- endif %}
+{{- "<|start_header_id|>system<|end_header_id|>\n\n" }}
+{{- "Cutting Knowledge Date: December 2023\n" }}
+{{- "Today Date: " + date_string + "\n\n" }}
+{{- system_message }}
+{{- "<|eot_id|>" }}
+{%- for message in messages %}
+    {{- '<|start_header_id|>' + message['role'] + '<|end_header_id|>\n\n'+ message['content'] | trim + '<|eot_id|>' }}
+{%- endfor %}
+{%- if add_generation_prompt %}
+    {{- '<|start_header_id|>assistant<|end_header_id|>\n\n' }}
+{%- endif %}
+"#;
+
+    /// The chat template of `mistralai/Mistral-7B-Instruct-v0.1`.
+    const MISTRAL: &str = "{{ bos_token }}{% for message in messages %}{% if (message['role'] == 'user') != (loop.index0 % 2 == 0) %}{{ raise_exception('Conversation roles must alternate user/assistant/user/assistant/...') }}{% endif %}{% if message['role'] == 'user' %}{{ '[INST] ' + message['content'] + ' [/INST]' }}{% elif message['role'] == 'assistant' %}{{ message['content'] + eos_token + ' ' }}{% else %}{{ raise_exception('Only user and assistant roles are supported!') }}{% endif %}{% endfor %}";
+
+    #[test]
+    fn test_tokenizer_config_template_renders_system_prompts() {
+        let config = serde_json::json!({
+            "bos_token": "<|begin_of_text|>",
+            "eos_token": "<|eot_id|>",
+            "chat_template": LLAMA3,
+        });
+        let template = JinjaChatTemplate::from_tokenizer_config(&config)
+            .unwrap()
+            .unwrap();
+        let prompt = template
+            .render(&[ChatMessage::system(" Be brief. "), ChatMessage::user("Hi")])
+            .unwrap();
+        assert_eq!(
+            prompt,
+            "<|start_header_id|>system<|end_header_id|>\n\n\
+             Cutting Knowledge Date: December 2023\nToday Date: 26 Jul 2024\n\n\
+             Be brief.<|eot_id|>\
+             <|start_header_id|>user<|end_header_id|>\n\nHi<|eot_id|>\
+             <|start_header_id|>assistant<|end_header_id|>\n\n"
+        );
+    }
+
+    #[test]
+    fn test_templates_may_reject_a_conversation() {
+        let config = serde_json::json!({
+            "bos_token": {"content": "<s>", "special": true},
+            "eos_token": "</s>",
+            "chat_template": [
+                {"name": "tool_use", "template": "unused"},
+                {"name": "default", "template": MISTRAL},
+            ],
+        });
+        let template = JinjaChatTemplate::from_tokenizer_config(&config)
+            .unwrap()
+            .unwrap();
+        let turns = [
+            ChatMessage::user("Hi"),
+            ChatMessage::assistant("Hello."),
+            ChatMessage::user("Bye"),
+        ];
+        assert_eq!(
+            template.render(&turns).unwrap(),
+            "[INST] Hi [/INST]Hello.</s> [INST] Bye [/INST]"
+        );
+
+        let error = template
+            .render(&[ChatMessage::new(Role::System, "Be brief.")])
+            .unwrap_err();
+        assert_eq!(error.kind(), "invalid_request");
+        assert!(error.to_string().contains("must alternate"), "{error}");
+    }
+
+    #[test]
+    fn test_configs_without_a_template_have_none() {
+        let config = serde_json::json!({"bos_token": "<s>"});
+        assert!(JinjaChatTemplate::from_tokenizer_config(&config)
+            .unwrap()
+            .is_none());
+        assert!(JinjaChatTemplate::new("{% if %}", None, None).is_err());
+    }
+}
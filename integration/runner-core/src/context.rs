@@ -0,0 +1,152 @@
+use anyhow::{bail, Result};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::RunnerError;
+
+/// What a runner does when a prompt plus its token budget exceeds the model's context
+/// window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContextPolicy {
+    /// Reject the request before generating.
+    #[default]
+    Error,
+    /// Drop the oldest prompt tokens (keeping the first, usually BOS) and cap `max_tokens`
+    /// so that the whole generation fits. At most half the window is reserved for output.
+    Truncate,
+    /// Keep generating past the limit: whenever the window fills up, the KV cache is
+    /// rebuilt from the most recent half of the tokens. A prompt that does not fit on its
+    /// own is truncated to half the window first.
+    SlidingWindow,
+}
+
+impl ContextPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContextPolicy::Error => "error",
+            ContextPolicy::Truncate => "truncate",
+            ContextPolicy::SlidingWindow => "sliding-window",
+        }
+    }
+
+    /// Fit `tokens` into a window of `limit` tokens, returning how many tokens may be
+    /// generated. `tokens` is truncated in place when the policy allows it.
+    pub fn fit(&self, tokens: &mut Vec<u32>, max_tokens: usize, limit: usize) -> Result<usize> {
+        if limit == 0 {
+            bail!("model has an empty context window");
+        }
+        match self {
+            ContextPolicy::Error => {
+                if tokens.len() + max_tokens > limit {
+                    return Err(RunnerError::InvalidRequest(format!(
+                        "prompt of {} tokens plus max_tokens {} exceeds the context window of {} tokens",
+                        tokens.len(),
+                        max_tokens,
+                        limit
+                    ))
+                    .into());
+                }
+                Ok(max_tokens)
+            }
+            ContextPolicy::Truncate => {
+                let reserved = max_tokens.min(limit / 2);
+                truncate_front(tokens, limit - reserved);
+                Ok(max_tokens.min(limit - tokens.len()))
+            }
+            ContextPolicy::SlidingWindow => {
+                if tokens.len() >= limit {
+                    truncate_front(tokens, Self::slide_len(limit));
+                }
+                Ok(max_tokens)
+            }
+        }
+    }
+
+    /// Number of most recent tokens re-encoded when a sliding window fills up.
+    pub fn slide_len(limit: usize) -> usize {
+        (limit / 2).max(1)
+    }
+}
+
+/// Keep the first token and the most recent `keep - 1` tokens.
+fn truncate_front(tokens: &mut Vec<u32>, keep: usize) {
+    if tokens.len() <= keep {
+        return;
+    }
+    let drop = tokens.len() - keep;
+    if keep == 0 {
+        tokens.clear();
+    } else {
+        tokens.drain(1..=drop);
+    }
+}
+
+impl fmt::Display for ContextPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ContextPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(ContextPolicy::Error),
+            "truncate" => Ok(ContextPolicy::Truncate),
+            "sliding-window" => Ok(ContextPolicy::SlidingWindow),
+            _ => Err(format!(
+                "unknown context policy {s}; expected error, truncate or sliding-window"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_policy() {
+        let mut tokens = vec![1, 2, 3, 4];
+        assert_eq!(ContextPolicy::Error.fit(&mut tokens, 4, 8).unwrap(), 4);
+        assert!(ContextPolicy::Error.fit(&mut tokens, 5, 8).is_err());
+        assert_eq!(tokens, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_truncate_keeps_first_and_latest_tokens() {
+        let mut tokens = vec![1, 2, 3, 4, 5, 6];
+        assert_eq!(ContextPolicy::Truncate.fit(&mut tokens, 4, 9).unwrap(), 4);
+        assert_eq!(tokens, vec![1, 3, 4, 5, 6]);
+
+        // Output never takes more than half the window from the prompt.
+        let mut tokens = vec![1, 2, 3, 4, 5, 6];
+        assert_eq!(ContextPolicy::Truncate.fit(&mut tokens, 100, 8).unwrap(), 4);
+        assert_eq!(tokens, vec![1, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_sliding_window_only_truncates_oversized_prompts() {
+        let mut tokens = vec![1, 2, 3];
+        let policy = ContextPolicy::SlidingWindow;
+        assert_eq!(policy.fit(&mut tokens, 100, 8).unwrap(), 100);
+        assert_eq!(tokens, vec![1, 2, 3]);
+
+        let mut tokens: Vec<u32> = (1..=10).collect();
+        policy.fit(&mut tokens, 100, 8).unwrap();
+        assert_eq!(tokens, vec![1, 8, 9, 10]);
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        for policy in [
+            ContextPolicy::Error,
+            ContextPolicy::Truncate,
+            ContextPolicy::SlidingWindow,
+        ] {
+            assert_eq!(policy.as_str().parse::<ContextPolicy>(), Ok(policy));
+        }
+        assert!("drop".parse::<ContextPolicy>().is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+struct Entry<S> {
+    state: S,
+    tokens: Vec<u32>,
+    last_used: u64,
+}
+
+/// Model state (typically a KV cache) retained between the turns of a conversation.
+///
+/// Entries are keyed by a caller-chosen id such as a conversation id or a hash of the
+/// conversation prefix. A stored state can be resumed when the next prompt starts with the
+/// tokens it already holds, so only the new tokens need to be prefilled. The least
+/// recently used entry is dropped once `capacity` is exceeded.
+pub struct ConversationCache<S> {
+    entries: Mutex<HashMap<String, Entry<S>>>,
+    capacity: usize,
+    clock: AtomicU64,
+}
+
+impl<S> ConversationCache<S> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            capacity,
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    /// Take the state stored for `key` if its tokens are a strict prefix of `tokens`,
+    /// returning it with the number of tokens it already covers.
+    ///
+    /// The entry is removed either way: a resumed state is handed to exactly one
+    /// generation, and a state that no longer matches the conversation is stale.
+    pub fn take_prefix(&self, key: &str, tokens: &[u32]) -> Option<(S, usize)> {
+        let mut entries = self.entries.lock().ok()?;
+        let entry = entries.remove(key)?;
+        let cached = entry.tokens.len();
+        (cached < tokens.len() && tokens.starts_with(&entry.tokens))
+            .then_some((entry.state, cached))
+    }
+
+    /// Store the state reached after a turn. `tokens` are the tokens the state covers.
+    pub fn store(&self, key: impl Into<String>, state: S, tokens: Vec<u32>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let last_used = self.clock.fetch_add(1, Ordering::Relaxed);
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        entries.insert(
+            key.into(),
+            Entry {
+                state,
+                tokens,
+                last_used,
+            },
+        );
+        while entries.len() > self.capacity {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(key) => entries.remove(&key),
+                None => break,
+            };
+        }
+    }
+
+    /// Forget a conversation. Returns whether any state was stored for it.
+    pub fn remove(&self, key: &str) -> bool {
+        self.entries
+            .lock()
+            .map(|mut entries| entries.remove(key).is_some())
+            .unwrap_or(false)
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.clear();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().map(|e| e.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resume_on_prefix() {
+        let cache = ConversationCache::new(4);
+        cache.store("chat", "kv", vec![1, 2, 3]);
+
+        assert_eq!(cache.take_prefix("chat", &[1, 2, 3, 4, 5]), Some(("kv", 3)));
+        // Taken states are handed out once.
+        assert_eq!(cache.take_prefix("chat", &[1, 2, 3, 4, 5]), None);
+    }
+
+    #[test]
+    fn test_mismatch_drops_stale_state() {
+        let cache = ConversationCache::new(4);
+        cache.store("chat", "kv", vec![1, 2, 3]);
+        assert_eq!(cache.take_prefix("chat", &[1, 9, 3, 4]), None);
+        assert!(cache.is_empty());
+
+        // A prompt that adds no new tokens cannot be resumed either.
+        cache.store("chat", "kv", vec![1, 2, 3]);
+        assert_eq!(cache.take_prefix("chat", &[1, 2, 3]), None);
+    }
+
+    #[test]
+    fn test_capacity_evicts_least_recently_used() {
+        let cache = ConversationCache::new(2);
+        cache.store("a", 1, vec![1]);
+        cache.store("b", 2, vec![1]);
+        cache.store("c", 3, vec![1]);
+
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.remove("a"));
+        assert!(cache.remove("b"));
+        assert!(cache.remove("c"));
+
+        let disabled = ConversationCache::new(0);
+        disabled.store("a", 1, vec![1]);
+        assert!(disabled.is_empty());
+    }
+}
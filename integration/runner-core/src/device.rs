@@ -0,0 +1,280 @@
+use crate::threads::{current_threads, CpuThreads};
+use crate::RunnerError;
+use candle_core::{utils, Device};
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// Device a runner loads its model on, written `auto`, `cpu`, `cuda:<n>` or `metal:<n>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DeviceSpec {
+    /// The first CUDA device, else the first Metal device, else the CPU.
+    #[default]
+    Auto,
+    Cpu,
+    Cuda(usize),
+    Metal(usize),
+}
+
+impl DeviceSpec {
+    /// Open the device. A GPU this build has no support for, or that can't be opened, fails
+    /// with [`RunnerError::UnsupportedDevice`].
+    pub fn open(&self) -> Result<Device, RunnerError> {
+        match *self {
+            DeviceSpec::Auto if utils::cuda_is_available() => DeviceSpec::Cuda(0).open(),
+            DeviceSpec::Auto if utils::metal_is_available() => DeviceSpec::Metal(0).open(),
+            DeviceSpec::Auto | DeviceSpec::Cpu => Ok(Device::Cpu),
+            DeviceSpec::Cuda(ordinal) => {
+                if !utils::cuda_is_available() {
+                    return Err(RunnerError::UnsupportedDevice(format!(
+                        "{self} was requested, but this build has no CUDA support"
+                    )));
+                }
+                Device::new_cuda(ordinal).map_err(|e| {
+                    RunnerError::UnsupportedDevice(format!(
+                        "CUDA device {ordinal} is not available: {e}"
+                    ))
+                })
+            }
+            DeviceSpec::Metal(ordinal) => {
+                if !utils::metal_is_available() {
+                    return Err(RunnerError::UnsupportedDevice(format!(
+                        "{self} was requested, but this build has no Metal support"
+                    )));
+                }
+                Device::new_metal(ordinal).map_err(|e| {
+                    RunnerError::UnsupportedDevice(format!(
+                        "Metal device {ordinal} is not available: {e}"
+                    ))
+                })
+            }
+        }
+    }
+}
+
+impl fmt::Display for DeviceSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceSpec::Auto => f.write_str("auto"),
+            DeviceSpec::Cpu => f.write_str("cpu"),
+            DeviceSpec::Cuda(ordinal) => write!(f, "cuda:{ordinal}"),
+            DeviceSpec::Metal(ordinal) => write!(f, "metal:{ordinal}"),
+        }
+    }
+}
+
+impl FromStr for DeviceSpec {
+    type Err = String;
+
+    /// `cuda` and `metal` without an ordinal mean device 0.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        let (backend, ordinal) = match s.split_once(':') {
+            Some((backend, ordinal)) => match ordinal.parse() {
+                Ok(ordinal) => (backend, ordinal),
+                Err(_) => return Err(format!("invalid device ordinal in {s}")),
+            },
+            None => (s.as_str(), 0),
+        };
+        match (backend, s.contains(':')) {
+            ("auto", false) => Ok(DeviceSpec::Auto),
+            ("cpu", false) => Ok(DeviceSpec::Cpu),
+            ("cuda", _) => Ok(DeviceSpec::Cuda(ordinal)),
+            ("metal", _) => Ok(DeviceSpec::Metal(ordinal)),
+            _ => Err(format!(
+                "unknown device {s}; expected auto, cpu, cuda, cuda:<n>, metal or metal:<n>"
+            )),
+        }
+    }
+}
+
+/// What the host and this build can run models on.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceReport {
+    pub cpu: CpuReport,
+    pub cuda: AcceleratorReport,
+    pub metal: AcceleratorReport,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CpuReport {
+    pub physical_cores: usize,
+    pub logical_cores: usize,
+    /// Thread pool size, once a runner has configured it.
+    pub threads: Option<CpuThreads>,
+    pub features: CpuFeatures,
+}
+
+/// SIMD extensions candle was compiled to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CpuFeatures {
+    pub avx: bool,
+    pub neon: bool,
+    pub simd128: bool,
+    pub f16c: bool,
+}
+
+impl CpuFeatures {
+    pub fn detect() -> Self {
+        Self {
+            avx: utils::with_avx(),
+            neon: utils::with_neon(),
+            simd128: utils::with_simd128(),
+            f16c: utils::with_f16c(),
+        }
+    }
+}
+
+impl fmt::Display for CpuFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "avx: {}, neon: {}, simd128: {}, f16c: {}",
+            self.avx, self.neon, self.simd128, self.f16c
+        )
+    }
+}
+
+/// Support for one GPU backend.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AcceleratorReport {
+    /// Whether candle was built with this backend.
+    pub compiled: bool,
+    /// Devices that could be opened.
+    pub devices: Vec<GpuReport>,
+    /// Why probing failed, if it did.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GpuReport {
+    pub ordinal: usize,
+    pub name: String,
+    /// Device memory in bytes; for Metal, the working set the system recommends.
+    pub total_memory: u64,
+    /// Memory not yet allocated, where the driver reports it.
+    pub free_memory: Option<u64>,
+}
+
+/// Probe the CPU and any GPUs this build supports.
+///
+/// Opening a GPU creates a driver context, which takes a moment and some device memory, so
+/// call this on demand rather than per request.
+pub fn device_report() -> DeviceReport {
+    DeviceReport {
+        cpu: CpuReport {
+            physical_cores: num_cpus::get_physical(),
+            logical_cores: num_cpus::get(),
+            threads: current_threads(),
+            features: CpuFeatures::detect(),
+        },
+        cuda: probe(utils::cuda_is_available(), probe_cuda),
+        metal: probe(utils::metal_is_available(), probe_metal),
+    }
+}
+
+/// CUDA devices with their free memory; empty in builds without CUDA or if probing fails.
+pub(crate) fn cuda_devices() -> Vec<GpuReport> {
+    probe(utils::cuda_is_available(), probe_cuda).devices
+}
+
+fn probe(compiled: bool, devices: fn() -> anyhow::Result<Vec<GpuReport>>) -> AcceleratorReport {
+    if !compiled {
+        return AcceleratorReport::default();
+    }
+    match devices() {
+        Ok(devices) => AcceleratorReport {
+            compiled,
+            devices,
+            error: None,
+        },
+        Err(error) => AcceleratorReport {
+            compiled,
+            devices: Vec::new(),
+            error: Some(format!("{error:#}")),
+        },
+    }
+}
+
+#[cfg(feature = "cuda")]
+fn probe_cuda() -> anyhow::Result<Vec<GpuReport>> {
+    use candle_core::cuda::cudarc::driver::CudaContext;
+
+    let count = CudaContext::device_count()? as usize;
+    (0..count)
+        .map(|ordinal| {
+            let context = CudaContext::new(ordinal)?;
+            let (free, total) = context.mem_get_info()?;
+            Ok(GpuReport {
+                ordinal,
+                name: context.name()?,
+                total_memory: total as u64,
+                free_memory: Some(free as u64),
+            })
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "cuda"))]
+fn probe_cuda() -> anyhow::Result<Vec<GpuReport>> {
+    anyhow::bail!("enable the `cuda` feature of runner-core to list CUDA devices")
+}
+
+#[cfg(feature = "metal")]
+fn probe_metal() -> anyhow::Result<Vec<GpuReport>> {
+    let device = candle_core::Device::new_metal(0)?;
+    let candle_core::Device::Metal(metal) = &device else {
+        unreachable!("new_metal returns a Metal device");
+    };
+    let device = metal.device();
+    Ok(vec![GpuReport {
+        ordinal: 0,
+        name: device.name().to_string(),
+        total_memory: device.recommended_max_working_set_size(),
+        free_memory: None,
+    }])
+}
+
+#[cfg(not(feature = "metal"))]
+fn probe_metal() -> anyhow::Result<Vec<GpuReport>> {
+    anyhow::bail!("enable the `metal` feature of runner-core to list Metal devices")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_specs_parse_and_print() {
+        for (text, spec) in [
+            ("auto", DeviceSpec::Auto),
+            ("cpu", DeviceSpec::Cpu),
+            ("cuda:1", DeviceSpec::Cuda(1)),
+            ("metal:0", DeviceSpec::Metal(0)),
+        ] {
+            assert_eq!(text.parse::<DeviceSpec>(), Ok(spec));
+            assert_eq!(spec.to_string(), text);
+        }
+        assert_eq!(" CUDA ".parse::<DeviceSpec>(), Ok(DeviceSpec::Cuda(0)));
+        assert!("cuda:x".parse::<DeviceSpec>().is_err());
+        assert!("cpu:1".parse::<DeviceSpec>().is_err());
+        assert!("tpu".parse::<DeviceSpec>().is_err());
+
+        assert!(matches!(DeviceSpec::Cpu.open(), Ok(Device::Cpu)));
+        if !utils::cuda_is_available() {
+            let error = DeviceSpec::Cuda(1).open().unwrap_err();
+            assert_eq!(error.kind(), "unsupported_device");
+        }
+    }
+
+    #[test]
+    fn test_device_report_serializes() {
+        let report = device_report();
+        assert!(report.cpu.logical_cores >= report.cpu.physical_cores);
+        assert_eq!(report.cuda.compiled, utils::cuda_is_available());
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert!(json["cpu"]["features"]["avx"].is_boolean());
+        assert!(json["metal"]["devices"].is_array());
+    }
+}
@@ -0,0 +1,418 @@
+use anyhow::Result;
+use hf_hub::api::sync::{Api, ApiBuilder, ApiRepo};
+use hf_hub::api::Progress;
+use hf_hub::{Cache, CacheRepo, Repo};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+use crate::files::{shard_names, SAFETENSORS, SAFETENSORS_INDEX};
+use crate::RunnerError;
+
+/// Default number of shards of a split checkpoint downloaded at the same time.
+pub const DEFAULT_PARALLEL_DOWNLOADS: usize = 4;
+/// How many times a failed download is retried before giving up. Each attempt resumes from
+/// the bytes already on disk.
+pub const DOWNLOAD_RETRIES: usize = 3;
+
+/// Progress of one file a runner fetches from the HuggingFace Hub.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadEvent {
+    /// The file is not cached locally and started downloading. `total` is its size in bytes.
+    /// Sent again if the download is retried after a failure.
+    Started { file: String, total: usize },
+    /// `downloaded` of `total` bytes are on disk. Reported at most once per percent.
+    Progress {
+        file: String,
+        downloaded: usize,
+        total: usize,
+    },
+    /// The file is available locally, either downloaded now or found in the cache.
+    Finished { file: String },
+}
+
+/// Callback receiving [`DownloadEvent`]s while a runner loads its model. Set it on a
+/// runner config to surface download status, e.g. as a progress bar.
+#[derive(Clone)]
+pub struct DownloadProgress(Arc<dyn Fn(DownloadEvent) + Send + Sync>);
+
+impl DownloadProgress {
+    pub fn new(callback: impl Fn(DownloadEvent) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(callback))
+    }
+
+    /// A callback that forwards every event to the returned receiver.
+    pub fn channel() -> (Self, UnboundedReceiver<DownloadEvent>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let progress = Self::new(move |event| {
+            let _ = tx.send(event);
+        });
+        (progress, rx)
+    }
+
+    /// A callback that renders downloads as a progress line on stderr, for command-line
+    /// tools. Shards downloaded in parallel share the line. Cached files print nothing.
+    pub fn stderr() -> Self {
+        let downloading = AtomicUsize::new(0);
+        Self::new(move |event| match event {
+            DownloadEvent::Started { file, .. } => {
+                downloading.fetch_add(1, Ordering::Relaxed);
+                eprint!("\rDownloading {file}:   0%");
+            }
+            DownloadEvent::Progress {
+                file,
+                downloaded,
+                total,
+            } => {
+                let percent = (downloaded * 100).checked_div(total).unwrap_or(100);
+                eprint!("\rDownloading {file}: {percent:>3}%");
+            }
+            DownloadEvent::Finished { .. } => {
+                let finished =
+                    downloading
+                        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+                if finished == Ok(1) {
+                    eprintln!();
+                }
+            }
+        })
+    }
+
+    pub fn report(&self, event: DownloadEvent) {
+        (self.0)(event)
+    }
+}
+
+impl fmt::Debug for DownloadProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DownloadProgress")
+    }
+}
+
+/// Adapts hf-hub's [`Progress`] to [`DownloadEvent`]s.
+struct Reporter {
+    file: String,
+    total: usize,
+    downloaded: usize,
+    percent: usize,
+    started: bool,
+    progress: DownloadProgress,
+}
+
+impl Progress for Reporter {
+    fn init(&mut self, size: usize, _filename: &str) {
+        // hf-hub calls `init` again before every attempt, followed by an `update` with the
+        // bytes already on disk. Count from zero each time but only report the start once.
+        self.total = size;
+        self.downloaded = 0;
+        if !self.started {
+            self.started = true;
+            self.progress.report(DownloadEvent::Started {
+                file: self.file.clone(),
+                total: size,
+            });
+        }
+    }
+
+    fn update(&mut self, size: usize) {
+        self.downloaded += size;
+        let percent = (self.downloaded * 100)
+            .checked_div(self.total)
+            .unwrap_or(100);
+        if percent != self.percent {
+            self.percent = percent;
+            self.progress.report(DownloadEvent::Progress {
+                file: self.file.clone(),
+                downloaded: self.downloaded,
+                total: self.total,
+            });
+        }
+    }
+
+    fn finish(&mut self) {
+        self.progress.report(DownloadEvent::Finished {
+            file: self.file.clone(),
+        });
+    }
+}
+
+/// A Hub client configured from the environment: `HF_HOME` moves the cache (e.g. onto a
+/// persistent volume), `HF_ENDPOINT` points at a mirror and `HF_TOKEN`, when set, takes
+/// precedence over the token saved by `huggingface-cli login`. [`HubFiles`] expects its
+/// `api` to come from here, so both agree on where cached files live.
+pub fn hub_api() -> Result<Api> {
+    let builder = ApiBuilder::from_env();
+    let builder = match std::env::var("HF_TOKEN") {
+        Ok(token) if !token.trim().is_empty() => builder.with_token(Some(token.trim().to_string())),
+        _ => builder,
+    };
+    Ok(builder.build()?)
+}
+
+/// Fetches files from one HuggingFace Hub repository, reporting downloads to an optional
+/// [`DownloadProgress`]. Failed downloads are retried [`DOWNLOAD_RETRIES`] times, resuming
+/// from the partial file hf-hub keeps in its cache, and the shards of a split checkpoint
+/// download in parallel.
+pub struct HubFiles {
+    repo_id: String,
+    api_repo: ApiRepo,
+    cache: CacheRepo,
+    progress: Option<DownloadProgress>,
+    parallel_downloads: usize,
+}
+
+impl HubFiles {
+    pub fn new(api: &Api, repo: Repo, progress: Option<DownloadProgress>) -> Self {
+        Self {
+            repo_id: repo.url(),
+            api_repo: api.repo(repo.clone()),
+            // `hub_api` reads and writes the cache `HF_HOME` selects, so look files up there.
+            cache: Cache::from_env().repo(repo),
+            progress,
+            parallel_downloads: DEFAULT_PARALLEL_DOWNLOADS,
+        }
+    }
+
+    /// Download at most `parallel_downloads` shards at a time in [`get_sharded`](Self::get_sharded).
+    /// `1` fetches them one after another.
+    pub fn with_parallel_downloads(mut self, parallel_downloads: usize) -> Self {
+        self.parallel_downloads = parallel_downloads.max(1);
+        self
+    }
+
+    /// Local path of `filename`, downloading it first if it isn't cached. Failures are
+    /// [`RunnerError`]s, so callers can tell missing or gated models from network errors.
+    pub fn get(&self, filename: &str) -> Result<PathBuf> {
+        Ok(retry(DOWNLOAD_RETRIES, Duration::from_secs(1), || {
+            self.fetch(filename)
+        })?)
+    }
+
+    fn fetch(&self, filename: &str) -> std::result::Result<PathBuf, RunnerError> {
+        let hub_error = |error| RunnerError::from_hub(&self.repo_id, filename, error);
+        let Some(progress) = &self.progress else {
+            return self.api_repo.get(filename).map_err(hub_error);
+        };
+        if let Some(path) = self.cache.get(filename) {
+            progress.report(DownloadEvent::Finished {
+                file: filename.to_string(),
+            });
+            return Ok(path);
+        }
+        let reporter = Reporter {
+            file: filename.to_string(),
+            total: 0,
+            downloaded: 0,
+            percent: 0,
+            started: false,
+            progress: progress.clone(),
+        };
+        self.api_repo
+            .download_with_progress(filename, reporter)
+            .map_err(hub_error)
+    }
+
+    /// Every file of the repository's safetensors checkpoint: the index and its shards when
+    /// `sharded`, otherwise the single weights file. Prefetching a model for a local model
+    /// directory needs the index as well as the shards.
+    pub fn get_checkpoint(&self, sharded: bool) -> Result<Vec<PathBuf>> {
+        if !sharded {
+            return Ok(vec![self.get(SAFETENSORS)?]);
+        }
+        let mut paths = vec![self.get(SAFETENSORS_INDEX)?];
+        paths.extend(self.get_sharded(SAFETENSORS_INDEX)?);
+        Ok(paths)
+    }
+
+    /// Fetch every safetensors shard listed in the `weight_map` of `index_file`, up to the
+    /// configured number at a time.
+    pub fn get_sharded(&self, index_file: &str) -> Result<Vec<PathBuf>> {
+        let index_path = self.get(index_file)?;
+        let shards: Vec<String> = shard_names(&index_path)?.into_iter().collect();
+        parallel_map(&shards, self.parallel_downloads, |shard| self.get(shard))
+    }
+}
+
+/// Run `attempt` until it succeeds, retrying up to `retries` times after a
+/// [`RunnerError::DownloadFailed`] and waiting `backoff` times the attempt number in between.
+/// Any other error, such as a missing file or a rejected token, is returned right away.
+fn retry<T>(
+    retries: usize,
+    backoff: Duration,
+    mut attempt: impl FnMut() -> std::result::Result<T, RunnerError>,
+) -> std::result::Result<T, RunnerError> {
+    let mut failures = 0;
+    loop {
+        match attempt() {
+            Err(RunnerError::DownloadFailed { .. }) if failures < retries => {
+                failures += 1;
+                thread::sleep(backoff * failures as u32);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Apply `f` to every item on up to `parallelism` threads, returning the results in order.
+/// Once an item fails no new ones are started, and the first error in order is returned.
+fn parallel_map<T: Sync, R: Send>(
+    items: &[T],
+    parallelism: usize,
+    f: impl Fn(&T) -> Result<R> + Sync,
+) -> Result<Vec<R>> {
+    let next = Mutex::new(items.iter().enumerate());
+    let failed = AtomicBool::new(false);
+    let results: Vec<Mutex<Option<Result<R>>>> = items.iter().map(|_| Mutex::new(None)).collect();
+    thread::scope(|scope| {
+        for _ in 0..parallelism.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                if failed.load(Ordering::Relaxed) {
+                    return;
+                }
+                let Some((index, item)) = next.lock().unwrap().next() else {
+                    return;
+                };
+                let result = f(item);
+                if result.is_err() {
+                    failed.store(true, Ordering::Relaxed);
+                }
+                *results[index].lock().unwrap() = Some(result);
+            });
+        }
+    });
+    let mut outputs = Vec::with_capacity(items.len());
+    let mut error = None;
+    for result in results {
+        match result.into_inner().unwrap() {
+            Some(Ok(output)) => outputs.push(output),
+            Some(Err(e)) => {
+                error.get_or_insert(e);
+            }
+            None => {}
+        }
+    }
+    match error {
+        Some(error) => Err(error),
+        None => Ok(outputs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reporter_throttles_to_percent_steps() {
+        let (progress, mut rx) = DownloadProgress::channel();
+        let mut reporter = Reporter {
+            file: "model.safetensors".to_string(),
+            total: 0,
+            downloaded: 0,
+            percent: 0,
+            started: false,
+            progress,
+        };
+
+        reporter.init(1000, "model.safetensors");
+        reporter.init(1000, "model.safetensors");
+        reporter.update(0);
+        for _ in 0..1000 {
+            reporter.update(1);
+        }
+        reporter.finish();
+        drop(reporter);
+
+        let mut events = Vec::new();
+        while let Some(event) = rx.blocking_recv() {
+            events.push(event);
+        }
+        assert_eq!(events.len(), 102);
+        assert_eq!(
+            events[0],
+            DownloadEvent::Started {
+                file: "model.safetensors".to_string(),
+                total: 1000
+            }
+        );
+        assert_eq!(
+            events[100],
+            DownloadEvent::Progress {
+                file: "model.safetensors".to_string(),
+                downloaded: 1000,
+                total: 1000
+            }
+        );
+        assert!(matches!(events[101], DownloadEvent::Finished { .. }));
+    }
+
+    #[test]
+    fn test_retry_only_repeats_failed_downloads() {
+        let download_failed = || RunnerError::DownloadFailed {
+            repo: "repo".to_string(),
+            file: "model.safetensors".to_string(),
+            source: anyhow::anyhow!("connection reset"),
+        };
+
+        let mut attempts = 0;
+        let result = retry(3, Duration::ZERO, || {
+            attempts += 1;
+            if attempts < 3 {
+                Err(download_failed())
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        attempts = 0;
+        let result: std::result::Result<(), _> = retry(2, Duration::ZERO, || {
+            attempts += 1;
+            Err(download_failed())
+        });
+        assert!(matches!(result, Err(RunnerError::DownloadFailed { .. })));
+        assert_eq!(attempts, 3);
+
+        attempts = 0;
+        let result: std::result::Result<(), _> = retry(2, Duration::ZERO, || {
+            attempts += 1;
+            Err(RunnerError::Gated {
+                repo: "repo".to_string(),
+            })
+        });
+        assert!(matches!(result, Err(RunnerError::Gated { .. })));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_parallel_map_keeps_order_and_limits_threads() {
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let items: Vec<usize> = (0..12).collect();
+        let doubled = parallel_map(&items, 3, |item| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(5));
+            running.fetch_sub(1, Ordering::SeqCst);
+            Ok(item * 2)
+        })
+        .unwrap();
+        assert_eq!(doubled, (0..12).map(|item| item * 2).collect::<Vec<_>>());
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+
+        let err = parallel_map(&items, 2, |item| {
+            if *item == 4 {
+                anyhow::bail!("shard {item} failed");
+            }
+            Ok(*item)
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "shard 4 failed");
+        assert!(parallel_map(&[] as &[usize], 4, |item| Ok(*item))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,36 @@
+use candle_core::{DType, Result, Tensor};
+
+/// Mean-pool the hidden states of one sequence into a unit-length embedding.
+///
+/// `hidden_states` has shape `(1, seq_len, hidden_size)`, as returned by a decoder's final
+/// layer. Every position gets equal weight; decoders attend causally, so later positions
+/// have seen more of the text, but the mean is what query/document retrieval expects.
+pub fn mean_pool(hidden_states: &Tensor) -> Result<Vec<f32>> {
+    let pooled = hidden_states
+        .to_dtype(DType::F32)?
+        .mean(1)?
+        .squeeze(0)?
+        .to_vec1::<f32>()?;
+    let norm = pooled.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        return Ok(pooled);
+    }
+    Ok(pooled.into_iter().map(|x| x / norm).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::Device;
+
+    #[test]
+    fn test_mean_pool_averages_positions_and_normalizes() {
+        let hidden = Tensor::new(&[[[3f32, 0.0], [0.0, 4.0], [0.0, 0.0]]], &Device::Cpu).unwrap();
+        let embedding = mean_pool(&hidden).unwrap();
+        assert!((embedding[0] - 0.6).abs() < 1e-6);
+        assert!((embedding[1] - 0.8).abs() < 1e-6);
+
+        let zeros = Tensor::zeros((1, 2, 3), DType::BF16, &Device::Cpu).unwrap();
+        assert_eq!(mean_pool(&zeros).unwrap(), vec![0.0; 3]);
+    }
+}
@@ -0,0 +1,183 @@
+use hf_hub::api::sync::ApiError;
+use std::fmt;
+
+/// Why a runner could not load a model or serve a request.
+///
+/// Runners work with `anyhow` internally and convert at the [`ModelRunner`](crate::ModelRunner)
+/// boundary with [`RunnerError::load`] or [`RunnerError::generation`]. Errors raised as a
+/// `RunnerError` somewhere inside keep their variant through that conversion.
+#[derive(Debug)]
+pub enum RunnerError {
+    /// A model file could not be downloaded from the HuggingFace Hub.
+    DownloadFailed {
+        repo: String,
+        file: String,
+        source: anyhow::Error,
+    },
+    /// The Hub rejected the request for lack of a valid token (HTTP 401).
+    Unauthorized { repo: String },
+    /// The repository is gated and the token's account has not been granted access (HTTP 403).
+    Gated { repo: String },
+    /// The repository, revision or file does not exist on the Hub (HTTP 404).
+    ModelNotFound { repo: String, file: String },
+    /// The device ran out of memory while loading the weights or running the model.
+    OutOfMemory(anyhow::Error),
+    /// The requested device is not available in this build or on this host.
+    UnsupportedDevice(String),
+    /// The request cannot be served as given, e.g. its prompt exceeds the context window.
+    InvalidRequest(String),
+    /// Loading the model failed for any other reason.
+    Load(anyhow::Error),
+    /// Generation failed for any other reason.
+    Generation(anyhow::Error),
+}
+
+impl RunnerError {
+    /// Classify an error raised while loading a model.
+    pub fn load(error: anyhow::Error) -> Self {
+        Self::classify(error, Self::Load)
+    }
+
+    /// Classify an error raised while preparing or running a generation.
+    pub fn generation(error: anyhow::Error) -> Self {
+        Self::classify(error, Self::Generation)
+    }
+
+    fn classify(error: anyhow::Error, other: fn(anyhow::Error) -> Self) -> Self {
+        let error = match error.downcast::<RunnerError>() {
+            Ok(runner_error) => return runner_error,
+            Err(error) => error,
+        };
+        if is_out_of_memory(&error) {
+            return Self::OutOfMemory(error);
+        }
+        other(error)
+    }
+
+    /// Map a failed download of `file` from `repo` to the matching variant.
+    pub fn from_hub(repo: &str, file: &str, error: ApiError) -> Self {
+        let status = match &error {
+            ApiError::RequestError(request) => match request.as_ref() {
+                ureq::Error::Status(status, _) => Some(*status),
+                ureq::Error::Transport(_) => None,
+            },
+            _ => None,
+        };
+        let repo = repo.to_string();
+        match status {
+            Some(401) => Self::Unauthorized { repo },
+            Some(403) => Self::Gated { repo },
+            Some(404) => Self::ModelNotFound {
+                repo,
+                file: file.to_string(),
+            },
+            _ => Self::DownloadFailed {
+                repo,
+                file: file.to_string(),
+                source: error.into(),
+            },
+        }
+    }
+
+    /// Stable identifier of the variant, e.g. for the `type` of an API error.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::DownloadFailed { .. } => "download_failed",
+            Self::Unauthorized { .. } => "unauthorized",
+            Self::Gated { .. } => "gated_model",
+            Self::ModelNotFound { .. } => "model_not_found",
+            Self::OutOfMemory(_) => "out_of_memory",
+            Self::UnsupportedDevice(_) => "unsupported_device",
+            Self::InvalidRequest(_) => "invalid_request",
+            Self::Load(_) => "load_failed",
+            Self::Generation(_) => "generation_failed",
+        }
+    }
+}
+
+/// candle reports allocation failures as driver errors without a dedicated variant, so the
+/// messages in the chain are the only signal (e.g. CUDA's `CUDA_ERROR_OUT_OF_MEMORY`).
+fn is_out_of_memory(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        let message = cause.to_string().to_lowercase();
+        message.contains("out of memory") || message.contains("out_of_memory")
+    })
+}
+
+impl fmt::Display for RunnerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DownloadFailed { repo, file, source } => {
+                write!(f, "failed to download {file} from {repo}: {source}")
+            }
+            Self::Unauthorized { repo } => write!(
+                f,
+                "the HuggingFace Hub requires a valid token to download {repo}"
+            ),
+            Self::Gated { repo } => write!(
+                f,
+                "{repo} is gated: accept its terms on the HuggingFace Hub with the account of the configured token"
+            ),
+            Self::ModelNotFound { repo, file } => write!(f, "{file} not found in {repo}"),
+            Self::OutOfMemory(error) => write!(f, "out of memory: {error}"),
+            Self::UnsupportedDevice(message) | Self::InvalidRequest(message) => {
+                f.write_str(message)
+            }
+            Self::Load(error) | Self::Generation(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for RunnerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            // Display already includes the wrapped error's message.
+            Self::DownloadFailed { source: error, .. }
+            | Self::OutOfMemory(error)
+            | Self::Load(error)
+            | Self::Generation(error) => error.source(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_classify_keeps_typed_errors() {
+        let typed = anyhow::Error::new(RunnerError::InvalidRequest("too long".to_string()));
+        assert_eq!(RunnerError::generation(typed).kind(), "invalid_request");
+
+        let oom = anyhow!("DriverError(CUDA_ERROR_OUT_OF_MEMORY, \"out of memory\")")
+            .context("loading model.safetensors");
+        assert_eq!(RunnerError::load(oom).kind(), "out_of_memory");
+
+        assert_eq!(
+            RunnerError::load(anyhow!("bad config")).kind(),
+            "load_failed"
+        );
+        assert_eq!(
+            RunnerError::generation(anyhow!("bad token")).kind(),
+            "generation_failed"
+        );
+    }
+
+    #[test]
+    fn test_from_hub_maps_http_status() {
+        let status = |code: u16| {
+            let response = ureq::Response::new(code, "", "").unwrap();
+            ApiError::RequestError(Box::new(ureq::Error::Status(code, response)))
+        };
+        let kind =
+            |error| RunnerError::from_hub("google/gemma-2-9b-it", "config.json", error).kind();
+
+        assert_eq!(kind(status(401)), "unauthorized");
+        assert_eq!(kind(status(403)), "gated_model");
+        assert_eq!(kind(status(404)), "model_not_found");
+        assert_eq!(kind(status(500)), "download_failed");
+        assert_eq!(kind(ApiError::InvalidResume), "download_failed");
+    }
+}
@@ -0,0 +1,78 @@
+use anyhow::Result;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::bench::{millis, write_json};
+
+/// Negative log-likelihood accumulated over a teacher-forced pass through a text.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Perplexity {
+    /// Tokens that were predicted, i.e. every token except the first of each window.
+    pub tokens: usize,
+    pub total_nll: f64,
+}
+
+impl Perplexity {
+    /// Account for one predicted token given the log probability the model assigned to it.
+    pub fn record(&mut self, logprob: f32) {
+        self.tokens += 1;
+        self.total_nll -= logprob as f64;
+    }
+
+    pub fn mean_nll(&self) -> f64 {
+        if self.tokens == 0 {
+            return 0.0;
+        }
+        self.total_nll / self.tokens as f64
+    }
+
+    pub fn perplexity(&self) -> f64 {
+        self.mean_nll().exp()
+    }
+}
+
+/// Result of evaluating a model over a text, printed as JSON by the runner CLIs.
+#[derive(Debug, Clone)]
+pub struct EvalReport {
+    pub model_id: String,
+    /// Tokens per window; each window is scored from an empty KV cache.
+    pub window: usize,
+    pub elapsed: Duration,
+    pub perplexity: Perplexity,
+}
+
+impl EvalReport {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "model": self.model_id,
+            "window": self.window,
+            "tokens": self.perplexity.tokens,
+            "mean_nll": self.perplexity.mean_nll(),
+            "perplexity": self.perplexity.perplexity(),
+            "elapsed_ms": millis(self.elapsed),
+        })
+    }
+
+    /// Write the report as pretty-printed JSON to `output`, or to stdout.
+    pub fn write_json(&self, output: Option<&Path>) -> Result<()> {
+        write_json(&self.to_json(), output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_perplexity_of_uniform_predictions() {
+        let mut stats = Perplexity::default();
+        assert_eq!(stats.perplexity(), 1.0);
+
+        // Always giving the right token a probability of 1/4 means choosing among 4.
+        for _ in 0..10 {
+            stats.record(0.25f32.ln());
+        }
+        assert_eq!(stats.tokens, 10);
+        assert!((stats.perplexity() - 4.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,162 @@
+use serde::Serialize;
+use std::fmt;
+
+/// Why a generation ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinishReason {
+    /// The model produced an end-of-sequence token or a configured stop sequence.
+    Stop,
+    /// The `max_tokens` budget was used up.
+    Length,
+    /// The runner was cancelled while generating.
+    Cancelled,
+}
+
+impl FinishReason {
+    /// Value used for `finish_reason` in OpenAI-compatible responses.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FinishReason::Stop => "stop",
+            FinishReason::Length => "length",
+            FinishReason::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl fmt::Display for FinishReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How much of the prompt the model has processed so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PrefillProgress {
+    /// Prompt tokens run through the model.
+    pub processed: usize,
+    /// Prompt tokens to run through the model, not counting any already in a retained
+    /// KV cache.
+    pub total: usize,
+}
+
+/// One step of a generation, sent over a runner's [`crate::TokenReceiver`].
+///
+/// A stream carries one event per prompt token (`is_prompt`), prefill progress events
+/// (also `is_prompt`) while the prompt is processed, one event per sampled token, and ends
+/// with a single event whose `finish_reason` is set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenEvent {
+    /// Decoded text to append to the output. May be empty while the decoder is waiting
+    /// for more bytes or holding back a possible stop sequence.
+    pub text: String,
+    /// Vocabulary id of the token, `None` for the final event.
+    pub token_id: Option<u32>,
+    /// Log probability of a sampled token under the (penalised) model distribution.
+    pub logprob: Option<f32>,
+    /// Whether the token belongs to the prompt rather than the completion.
+    pub is_prompt: bool,
+    /// Set on the last event of the stream only.
+    pub finish_reason: Option<FinishReason>,
+    /// Set on prefill progress events, which carry no token.
+    pub prefill: Option<PrefillProgress>,
+}
+
+impl TokenEvent {
+    pub fn prompt(token_id: u32) -> Self {
+        Self {
+            text: String::new(),
+            token_id: Some(token_id),
+            logprob: None,
+            is_prompt: true,
+            finish_reason: None,
+            prefill: None,
+        }
+    }
+
+    pub fn prefill(processed: usize, total: usize) -> Self {
+        Self {
+            text: String::new(),
+            token_id: None,
+            logprob: None,
+            is_prompt: true,
+            finish_reason: None,
+            prefill: Some(PrefillProgress { processed, total }),
+        }
+    }
+
+    pub fn generated(token_id: u32, text: impl Into<String>, logprob: Option<f32>) -> Self {
+        Self {
+            text: text.into(),
+            token_id: Some(token_id),
+            logprob,
+            is_prompt: false,
+            finish_reason: None,
+            prefill: None,
+        }
+    }
+
+    /// Closing event carrying any text that was still buffered.
+    pub fn finished(reason: FinishReason, text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            token_id: None,
+            logprob: None,
+            is_prompt: false,
+            finish_reason: Some(reason),
+            prefill: None,
+        }
+    }
+}
+
+/// Tokens a generation has used, counted from the events of its stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TokenUsage {
+    /// Tokens of the prompt, including any resumed from a retained KV cache.
+    pub prompt: usize,
+    /// Tokens sampled, including those held back or cut off as a stop sequence.
+    pub completion: usize,
+}
+
+impl TokenUsage {
+    /// Count `event` if it carries a token.
+    pub fn record(&mut self, event: &TokenEvent) {
+        match event.token_id {
+            Some(_) if event.is_prompt => self.prompt += 1,
+            Some(_) => self.completion += 1,
+            None => {}
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.prompt + self.completion
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_counts_tokens_not_progress() {
+        let mut usage = TokenUsage::default();
+        for event in [
+            TokenEvent::prompt(1),
+            TokenEvent::prompt(2),
+            TokenEvent::prefill(0, 2),
+            TokenEvent::prefill(2, 2),
+            TokenEvent::generated(3, "a", None),
+            TokenEvent::generated(4, "", None),
+            TokenEvent::finished(FinishReason::Stop, "b"),
+        ] {
+            usage.record(&event);
+        }
+        assert_eq!(
+            usage,
+            TokenUsage {
+                prompt: 2,
+                completion: 2
+            }
+        );
+        assert_eq!(usage.total(), 4);
+    }
+}
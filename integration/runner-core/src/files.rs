@@ -0,0 +1,230 @@
+use anyhow::{anyhow, bail, Result};
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::HubFiles;
+
+/// Index listing the shards of a split safetensors checkpoint.
+pub const SAFETENSORS_INDEX: &str = "model.safetensors.index.json";
+/// Weights of a checkpoint that is not split.
+pub const SAFETENSORS: &str = "model.safetensors";
+
+/// Where a runner reads its model files from.
+pub enum ModelFiles {
+    Hub(Box<HubFiles>),
+    Local(LocalFiles),
+}
+
+impl ModelFiles {
+    pub fn get(&self, filename: &str) -> Result<PathBuf> {
+        match self {
+            ModelFiles::Hub(hub) => hub.get(filename),
+            ModelFiles::Local(local) => local.get(filename),
+        }
+    }
+
+    /// The safetensors weights of the model. Hub repositories are expected to be `sharded`
+    /// or not as published; a local directory may hold either layout.
+    pub fn safetensors(&self, sharded: bool) -> Result<Vec<PathBuf>> {
+        match self {
+            ModelFiles::Hub(hub) if sharded => hub.get_sharded(SAFETENSORS_INDEX),
+            ModelFiles::Hub(hub) => Ok(vec![hub.get(SAFETENSORS)?]),
+            ModelFiles::Local(local) => local.safetensors(),
+        }
+    }
+}
+
+/// Model files in a local directory, e.g. a fine-tune or a snapshot copied onto an
+/// air-gapped machine. Nothing is downloaded; missing files are reported by name.
+#[derive(Debug, Clone)]
+pub struct LocalFiles {
+    dir: PathBuf,
+}
+
+impl LocalFiles {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        if !dir.is_dir() {
+            bail!("model directory {dir:?} does not exist or is not a directory");
+        }
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn get(&self, filename: &str) -> Result<PathBuf> {
+        let path = self.dir.join(filename);
+        if !path.is_file() {
+            bail!("{filename} not found in model directory {:?}", self.dir);
+        }
+        Ok(path)
+    }
+
+    /// The shards listed in [`SAFETENSORS_INDEX`] if present, otherwise [`SAFETENSORS`].
+    pub fn safetensors(&self) -> Result<Vec<PathBuf>> {
+        let index_path = self.dir.join(SAFETENSORS_INDEX);
+        if !index_path.is_file() {
+            return match self.get(SAFETENSORS) {
+                Ok(path) => Ok(vec![path]),
+                Err(_) => bail!(
+                    "model directory {:?} has neither {SAFETENSORS} nor {SAFETENSORS_INDEX}",
+                    self.dir
+                ),
+            };
+        }
+        let shards = shard_names(&index_path)?;
+        let missing: Vec<&str> = shards
+            .iter()
+            .map(String::as_str)
+            .filter(|shard| !self.dir.join(shard).is_file())
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "model directory {:?} is missing {} of the {} shards listed in {SAFETENSORS_INDEX}: {}",
+                self.dir,
+                missing.len(),
+                shards.len(),
+                missing.join(", ")
+            );
+        }
+        Ok(shards.iter().map(|shard| self.dir.join(shard)).collect())
+    }
+
+    /// The single `.gguf` checkpoint in the directory.
+    pub fn gguf(&self) -> Result<PathBuf> {
+        let mut found = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "gguf") {
+                found.push(path);
+            }
+        }
+        match found.len() {
+            0 => bail!(
+                "no .gguf checkpoint found in model directory {:?}",
+                self.dir
+            ),
+            1 => Ok(found.remove(0)),
+            n => bail!(
+                "model directory {:?} holds {n} .gguf checkpoints; pass the one to load explicitly",
+                self.dir
+            ),
+        }
+    }
+}
+
+/// Distinct shard file names in the `weight_map` of a safetensors index.
+pub(crate) fn shard_names(index_path: &Path) -> Result<BTreeSet<String>> {
+    let json: serde_json::Value = serde_json::from_reader(std::fs::File::open(index_path)?)?;
+    let weight_map = match json.get("weight_map") {
+        None => bail!("no weight map in {index_path:?}"),
+        Some(serde_json::Value::Object(map)) => map,
+        Some(_) => bail!("weight map in {index_path:?} is not a map"),
+    };
+    Ok(weight_map
+        .values()
+        .filter_map(|v| v.as_str())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Number of parameters in safetensors files, counted from their headers without reading
+/// any weights.
+pub fn safetensors_parameter_count(paths: &[PathBuf]) -> Result<u64> {
+    let mut count = 0;
+    for path in paths {
+        let mut file = std::fs::File::open(path)?;
+        let mut len = [0u8; 8];
+        file.read_exact(&mut len)?;
+        let mut header = vec![0u8; u64::from_le_bytes(len) as usize];
+        file.read_exact(&mut header)?;
+        let header: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(&header)
+            .map_err(|e| anyhow!("invalid safetensors header in {path:?}: {e}"))?;
+        for (name, tensor) in header.iter().filter(|(name, _)| *name != "__metadata__") {
+            let Some(shape) = tensor.get("shape").and_then(|shape| shape.as_array()) else {
+                bail!("tensor {name} in {path:?} has no shape");
+            };
+            count += shape.iter().filter_map(|dim| dim.as_u64()).product::<u64>();
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("runner-core-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            std::fs::write(dir.join(file), contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_local_files_report_missing_files() {
+        let dir = model_dir("missing", &[("config.json", "{}")]);
+        let local = LocalFiles::new(&dir).unwrap();
+
+        assert_eq!(local.get("config.json").unwrap(), dir.join("config.json"));
+        let err = local.get("tokenizer.json").unwrap_err().to_string();
+        assert!(err.starts_with("tokenizer.json not found"), "{err}");
+        assert!(local.safetensors().is_err());
+        assert!(local.gguf().is_err());
+        assert!(LocalFiles::new(dir.join("config.json")).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_local_files_resolve_shards() {
+        let index = r#"{"weight_map": {"a": "model-1.safetensors", "b": "model-2.safetensors", "c": "model-1.safetensors"}}"#;
+        let dir = model_dir(
+            "shards",
+            &[(SAFETENSORS_INDEX, index), ("model-1.safetensors", "")],
+        );
+        let local = LocalFiles::new(&dir).unwrap();
+
+        let err = local.safetensors().unwrap_err().to_string();
+        assert!(err.ends_with("missing 1 of the 2 shards listed in model.safetensors.index.json: model-2.safetensors"), "{err}");
+
+        std::fs::write(dir.join("model-2.safetensors"), "").unwrap();
+        assert_eq!(
+            local.safetensors().unwrap(),
+            vec![
+                dir.join("model-1.safetensors"),
+                dir.join("model-2.safetensors")
+            ]
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_safetensors_parameter_count_reads_headers() {
+        let header = r#"{"__metadata__":{"format":"pt"},"a":{"dtype":"F32","shape":[2,3],"data_offsets":[0,24]},"b":{"dtype":"F32","shape":[4],"data_offsets":[24,40]}}"#;
+        let mut contents = (header.len() as u64).to_le_bytes().to_vec();
+        contents.extend_from_slice(header.as_bytes());
+        contents.resize(contents.len() + 40, 0);
+        let dir = model_dir("params", &[]);
+        std::fs::write(dir.join(SAFETENSORS), contents).unwrap();
+
+        let shard = dir.join(SAFETENSORS);
+        assert_eq!(
+            safetensors_parameter_count(std::slice::from_ref(&shard)).unwrap(),
+            10
+        );
+        assert_eq!(
+            safetensors_parameter_count(&[shard.clone(), shard]).unwrap(),
+            20
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+pub mod batch;
+pub mod bench;
+pub mod cache;
+pub mod cancel;
+pub mod chat;
+pub mod chat_template;
+pub mod context;
+pub mod conversation;
+pub mod device;
+pub mod download;
+pub mod embed;
+pub mod error;
+pub mod eval;
+pub mod event;
+pub mod files;
+pub mod memory;
+pub mod prefill;
+pub mod sampling;
+pub mod stop;
+pub mod stream;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
+pub mod threads;
+
+pub use batch::{merge_streams, BatchReceiver};
+pub use bench::{run_bench, BenchConfig, BenchReport, RunStats};
+pub use cache::{CacheKey, ModelCache};
+pub use cancel::{CancelHandle, CancelToken};
+pub use chat::{ChatMessage, Role};
+pub use chat_template::{JinjaChatTemplate, TOKENIZER_CONFIG};
+pub use context::ContextPolicy;
+pub use conversation::ConversationCache;
+pub use device::{
+    device_report, AcceleratorReport, CpuFeatures, CpuReport, DeviceReport, DeviceSpec, GpuReport,
+};
+pub use download::{
+    hub_api, DownloadEvent, DownloadProgress, HubFiles, DEFAULT_PARALLEL_DOWNLOADS,
+    DOWNLOAD_RETRIES,
+};
+pub use embed::mean_pool;
+pub use error::RunnerError;
+pub use eval::{EvalReport, Perplexity};
+pub use event::{FinishReason, PrefillProgress, TokenEvent, TokenUsage};
+pub use files::{safetensors_parameter_count, LocalFiles, ModelFiles};
+pub use memory::{resident_memory_bytes, MemorySample};
+pub use prefill::{chunked_prefill, DEFAULT_PREFILL_CHUNK};
+pub use sampling::{
+    apply_frequency_penalty, ban_repeated_ngrams, SamplingPreset, SamplingSettings,
+};
+pub use stop::{StopCheck, StopSequences};
+pub use stream::{
+    record_stalled_stream, stream_config, stream_stats, token_channel, token_channel_with,
+    StreamConfig, StreamStats, TokenReceiver, TokenSender,
+};
+pub use threads::{configure_threads, current_threads, CpuThreads, ThreadSource};
+
+use anyhow::anyhow;
+use std::time::{Duration, Instant};
+
+/// A single generation request handed to a loaded runner.
+#[derive(Debug, Clone)]
+pub struct GenerationRequest {
+    /// Fully formatted prompt, including any chat template markup.
+    pub prompt: String,
+    /// Maximum number of new tokens to generate.
+    pub max_tokens: usize,
+    /// Conversation (or conversation prefix hash) this turn belongs to. Runners that retain
+    /// KV state use it to resume the previous turn instead of prefilling the whole prompt.
+    pub conversation_id: Option<String>,
+    /// Stop sequences for this request, checked along with the runner config's.
+    pub stop: Vec<String>,
+}
+
+impl GenerationRequest {
+    pub fn new(prompt: impl Into<String>, max_tokens: usize) -> Self {
+        Self {
+            prompt: prompt.into(),
+            max_tokens,
+            conversation_id: None,
+            stop: Vec::new(),
+        }
+    }
+
+    pub fn with_conversation(mut self, conversation_id: impl Into<String>) -> Self {
+        self.conversation_id = Some(conversation_id.into());
+        self
+    }
+
+    pub fn with_stop(mut self, stop: Vec<String>) -> Self {
+        self.stop = stop;
+        self
+    }
+}
+
+/// Describes the model a runner has loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunnerMetadata {
+    /// Public model id, e.g. `gemma-3-1b-it`.
+    pub model_id: String,
+    /// HuggingFace repository the weights were loaded from.
+    pub repo_id: String,
+    /// Model family, e.g. `gemma` or `llama`.
+    pub family: String,
+    /// Organisation that publishes the model, as reported by `/v1/models`.
+    pub owned_by: String,
+    /// Maximum number of tokens, prompt plus generated, the model can attend to.
+    pub context_length: usize,
+    /// Size of the tokenizer's vocabulary, including added tokens.
+    pub vocab_size: usize,
+    /// Number of weights in the checkpoint.
+    pub parameter_count: u64,
+    /// Weight type: the dtype name (e.g. `bf16`), or the quantization of a GGUF checkpoint.
+    pub dtype: String,
+    /// Device the model runs on: `cpu`, `cuda:<n>` or `metal:<n>`.
+    pub device: String,
+}
+
+/// Common interface implemented by every model runner.
+///
+/// `load` is only callable on concrete runners; everything else is object safe so the
+/// inference engine can hold runners of different families as `Box<dyn ModelRunner>`.
+pub trait ModelRunner: Send + Sync {
+    /// Runner-specific configuration used to load the model.
+    type Config
+    where
+        Self: Sized;
+
+    /// Build the model, tokenizer and device described by `config`.
+    fn load(config: Self::Config) -> Result<Self, RunnerError>
+    where
+        Self: Sized;
+
+    /// Start generating on a background thread and stream token events. The last event
+    /// carries the finish reason; if generation fails the error is forwarded instead.
+    fn generate_stream(&self, request: GenerationRequest) -> Result<TokenReceiver, RunnerError>;
+
+    /// Start generations for several requests at once, each streamed on its own channel
+    /// in the order of `requests`, so every stream has its own backpressure and a reader
+    /// that goes away ends only its own generation.
+    ///
+    /// The default runs each request as an independent stream; runners that can share
+    /// forward passes between sequences override it, along with
+    /// [`ModelRunner::batches_generations`].
+    fn generate_streams(
+        &self,
+        requests: Vec<GenerationRequest>,
+    ) -> Result<Vec<TokenReceiver>, RunnerError> {
+        requests
+            .into_iter()
+            .map(|request| self.generate_stream(request))
+            .collect()
+    }
+
+    /// Generate completions for several requests at once, with the events of every request
+    /// interleaved on one channel and tagged with its index in `requests`.
+    ///
+    /// The default merges the streams of [`ModelRunner::generate_streams`].
+    fn generate_batch(
+        &self,
+        requests: Vec<GenerationRequest>,
+    ) -> Result<BatchReceiver, RunnerError> {
+        Ok(merge_streams(self.generate_streams(requests)?))
+    }
+
+    /// Whether [`ModelRunner::generate_streams`] shares forward passes between requests,
+    /// making it worth holding a request back briefly for others to join it.
+    fn batches_generations(&self) -> bool {
+        false
+    }
+
+    /// Describe the loaded model.
+    fn metadata(&self) -> &RunnerMetadata;
+
+    /// Stop every generation currently running on this runner.
+    fn cancel(&self);
+
+    /// The chat template shipped with the loaded model, when its `tokenizer_config.json`
+    /// has one. Callers render conversations with it in preference to a built-in format.
+    fn chat_template(&self) -> Option<&JinjaChatTemplate> {
+        None
+    }
+
+    /// Number of tokens `prompt` encodes to, as a generation would count it, so callers can
+    /// check it fits the context window before generating. `None` when the runner can't
+    /// tell.
+    fn count_tokens(&self, _prompt: &str) -> Option<usize> {
+        None
+    }
+
+    /// Mark the runner's cached model as used now. Callers that keep a runner across
+    /// requests call it on each one, so idle eviction sees when the model was last used
+    /// rather than when the runner was loaded.
+    fn touch(&self) {}
+
+    /// Drop KV state retained for a conversation. Returns whether any was held; runners
+    /// that don't retain state always return `false`.
+    fn forget_conversation(&self, _conversation_id: &str) -> bool {
+        false
+    }
+
+    /// Embed each text as the mean of the model's final hidden states, normalized to unit
+    /// length. Runners whose model does not expose its hidden states reject the request.
+    fn embed(&self, _texts: &[String]) -> Result<Vec<Vec<f32>>, RunnerError> {
+        Err(RunnerError::InvalidRequest(format!(
+            "{} does not support embeddings",
+            self.metadata().model_id
+        )))
+    }
+
+    /// Run a short throwaway generation so kernels are compiled and buffers allocated
+    /// before the first real request. Returns how long it took.
+    fn warmup(&self) -> Result<Duration, RunnerError> {
+        let start = Instant::now();
+        let mut rx = self.generate_stream(GenerationRequest::new("Hello", 4))?;
+        // Drain on a plain thread: `blocking_recv` panics on a tokio runtime thread, and the
+        // inference engine loads runners from its request handlers.
+        std::thread::spawn(move || {
+            while let Some(event) = rx.blocking_recv() {
+                event?;
+            }
+            Ok(())
+        })
+        .join()
+        .map_err(|_| RunnerError::Generation(anyhow!("warmup generation panicked")))??;
+        Ok(start.elapsed())
+    }
+}
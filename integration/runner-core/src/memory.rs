@@ -0,0 +1,113 @@
+use serde::Serialize;
+use std::fmt;
+
+use crate::device::{cuda_devices, GpuReport};
+
+const GIB: f64 = (1u64 << 30) as f64;
+
+/// Memory in use by this process and on the GPUs it can reach, taken by
+/// [`MemorySample::take`] to watch for pressure while models are loaded.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MemorySample {
+    /// Resident set size of this process (`VmRSS`). Only available on Linux.
+    pub resident_bytes: Option<u64>,
+    /// CUDA devices, with the memory their driver reports free.
+    pub devices: Vec<GpuReport>,
+}
+
+impl MemorySample {
+    /// Sample the process and, in builds with CUDA, every CUDA device. Probing a device
+    /// opens a driver context, so sample every few seconds rather than per request.
+    pub fn take() -> Self {
+        Self {
+            resident_bytes: resident_memory_bytes(),
+            devices: cuda_devices(),
+        }
+    }
+
+    /// Share of device memory in use on the fullest device that reports its free memory.
+    pub fn device_used_fraction(&self) -> Option<f64> {
+        self.devices
+            .iter()
+            .filter(|device| device.total_memory > 0)
+            .filter_map(|device| {
+                let free = device.free_memory?;
+                Some(1.0 - free as f64 / device.total_memory as f64)
+            })
+            .reduce(f64::max)
+    }
+}
+
+impl fmt::Display for MemorySample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.resident_bytes {
+            Some(bytes) => write!(f, "rss {:.2} GiB", bytes as f64 / GIB)?,
+            None => write!(f, "rss unknown")?,
+        }
+        for device in &self.devices {
+            let total = device.total_memory as f64 / GIB;
+            match device.free_memory {
+                Some(free) => write!(
+                    f,
+                    ", cuda:{} {:.2}/{:.2} GiB",
+                    device.ordinal,
+                    total - free as f64 / GIB,
+                    total
+                )?,
+                None => write!(f, ", cuda:{} ?/{:.2} GiB", device.ordinal, total)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Resident set size of this process (`VmRSS`). Only available on Linux.
+pub fn resident_memory_bytes() -> Option<u64> {
+    proc_status_bytes("VmRSS:")
+}
+
+/// A size field of `/proc/self/status`, which the kernel reports in KiB.
+pub(crate) fn proc_status_bytes(field: &str) -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with(field))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gpu(ordinal: usize, total_memory: u64, free_memory: Option<u64>) -> GpuReport {
+        GpuReport {
+            ordinal,
+            name: "test".to_string(),
+            total_memory,
+            free_memory,
+        }
+    }
+
+    #[test]
+    fn test_device_used_fraction_takes_the_fullest_device() {
+        let sample = MemorySample {
+            resident_bytes: Some(1 << 30),
+            devices: vec![
+                gpu(0, 8 << 30, Some(6 << 30)),
+                gpu(1, 8 << 30, Some(2 << 30)),
+                gpu(2, 8 << 30, None),
+            ],
+        };
+        assert_eq!(sample.device_used_fraction(), Some(0.75));
+        assert_eq!(
+            sample.to_string(),
+            "rss 1.00 GiB, cuda:0 2.00/8.00 GiB, cuda:1 6.00/8.00 GiB, cuda:2 ?/8.00 GiB"
+        );
+        assert_eq!(MemorySample::default().device_used_fraction(), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_resident_memory_is_reported_on_linux() {
+        assert!(resident_memory_bytes().is_some_and(|bytes| bytes > 0));
+    }
+}
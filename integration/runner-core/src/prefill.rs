@@ -0,0 +1,124 @@
+use crate::cancel::CancelToken;
+use crate::event::PrefillProgress;
+use anyhow::Result;
+
+/// Default number of prompt tokens run through the model per forward pass while prefilling.
+pub const DEFAULT_PREFILL_CHUNK: usize = 512;
+
+/// Run `tokens` through `forward` in chunks of at most `chunk_size` tokens, reporting
+/// progress before the first chunk and after each one so callers can keep connections alive
+/// while a long prompt is processed.
+///
+/// `forward` receives each chunk and its offset within `tokens`, and must append to the same
+/// KV cache on every call. `chunk_size == 0` processes the whole prompt in one pass, for
+/// models that cannot extend their cache by more than one token at a time.
+///
+/// `progress` returns whether to carry on: `false`, e.g. when nobody reads the stream any
+/// more, stops before the next chunk just as cancelling does.
+///
+/// Returns the output of the last chunk, or `None` if stopped between chunks.
+pub fn chunked_prefill<T>(
+    tokens: &[u32],
+    chunk_size: usize,
+    cancel: &CancelToken,
+    mut forward: impl FnMut(&[u32], usize) -> Result<T>,
+    mut progress: impl FnMut(PrefillProgress) -> bool,
+) -> Result<Option<T>> {
+    let total = tokens.len();
+    let chunk_size = if chunk_size == 0 {
+        total.max(1)
+    } else {
+        chunk_size
+    };
+    let mut carry_on = progress(PrefillProgress {
+        processed: 0,
+        total,
+    });
+    let mut output = None;
+    for (index, chunk) in tokens.chunks(chunk_size).enumerate() {
+        if !carry_on || cancel.is_cancelled() {
+            return Ok(None);
+        }
+        let offset = index * chunk_size;
+        output = Some(forward(chunk, offset)?);
+        carry_on = progress(PrefillProgress {
+            processed: offset + chunk.len(),
+            total,
+        });
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::CancelHandle;
+
+    #[test]
+    fn test_chunked_prefill_reports_each_chunk() {
+        let tokens: Vec<u32> = (0..10).collect();
+        let cancel = CancelHandle::new();
+        let mut chunks = Vec::new();
+        let mut reports = Vec::new();
+        let last = chunked_prefill(
+            &tokens,
+            4,
+            &cancel.token(),
+            |chunk, offset| {
+                chunks.push((chunk.to_vec(), offset));
+                Ok(offset)
+            },
+            |progress| {
+                reports.push(progress.processed);
+                true
+            },
+        )
+        .unwrap();
+
+        assert_eq!(last, Some(8));
+        assert_eq!(
+            chunks,
+            [
+                (vec![0, 1, 2, 3], 0),
+                (vec![4, 5, 6, 7], 4),
+                (vec![8, 9], 8)
+            ]
+        );
+        assert_eq!(reports, [0, 4, 8, 10]);
+
+        let mut passes = 0;
+        chunked_prefill(
+            &tokens,
+            0,
+            &cancel.token(),
+            |_, _| {
+                passes += 1;
+                Ok(())
+            },
+            |_| true,
+        )
+        .unwrap();
+        assert_eq!(passes, 1);
+
+        // A reader that goes away after the first chunk stops the rest
+        let mut chunks = 0;
+        let stopped = chunked_prefill(
+            &tokens,
+            4,
+            &cancel.token(),
+            |_, _| {
+                chunks += 1;
+                Ok(())
+            },
+            |progress| progress.processed < 4,
+        )
+        .unwrap();
+        assert!(stopped.is_none());
+        assert_eq!(chunks, 1);
+
+        let token = cancel.token();
+        cancel.cancel();
+        let cancelled = chunked_prefill(&tokens, 4, &token, |_, _| Ok(()), |_| true);
+        assert!(cancelled.unwrap().is_none());
+    }
+}
@@ -0,0 +1,190 @@
+use candle_core::{Result, Tensor};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// Named sampling settings, so callers can pick a style without tuning each knob.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SamplingPreset {
+    /// Low temperature and a narrow candidate pool: factual answers, code, extraction.
+    Precise,
+    /// A moderate temperature for general chat.
+    Balanced,
+    /// A high temperature and a wide pool for brainstorming and fiction.
+    Creative,
+}
+
+/// The values a [`SamplingPreset`] stands for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplingSettings {
+    pub temperature: f64,
+    pub top_p: Option<f64>,
+    pub top_k: Option<usize>,
+    pub repeat_penalty: f32,
+}
+
+impl SamplingPreset {
+    pub fn settings(&self) -> SamplingSettings {
+        match self {
+            SamplingPreset::Precise => SamplingSettings {
+                temperature: 0.2,
+                top_p: Some(0.8),
+                top_k: Some(20),
+                repeat_penalty: 1.1,
+            },
+            SamplingPreset::Balanced => SamplingSettings {
+                temperature: 0.7,
+                top_p: Some(0.9),
+                top_k: Some(40),
+                repeat_penalty: 1.1,
+            },
+            SamplingPreset::Creative => SamplingSettings {
+                temperature: 1.0,
+                top_p: Some(0.95),
+                top_k: None,
+                repeat_penalty: 1.05,
+            },
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SamplingPreset::Precise => "precise",
+            SamplingPreset::Balanced => "balanced",
+            SamplingPreset::Creative => "creative",
+        }
+    }
+}
+
+impl fmt::Display for SamplingPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SamplingPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "precise" => Ok(SamplingPreset::Precise),
+            "balanced" => Ok(SamplingPreset::Balanced),
+            "creative" => Ok(SamplingPreset::Creative),
+            _ => Err(format!(
+                "unknown sampling preset {s}; expected precise, balanced or creative"
+            )),
+        }
+    }
+}
+
+/// Rule out every token that would complete an n-gram of `ngram_size` tokens already
+/// present in `tokens`, by setting its logit to negative infinity. `ngram_size == 0`
+/// leaves the logits unchanged.
+///
+/// `logits` is the 1-D distribution for the token following `tokens`.
+pub fn ban_repeated_ngrams(logits: &Tensor, tokens: &[u32], ngram_size: usize) -> Result<Tensor> {
+    let banned = repeated_ngram_completions(tokens, ngram_size);
+    if banned.is_empty() {
+        return Ok(logits.clone());
+    }
+    let mut values = logits.to_vec1::<f32>()?;
+    for token in banned {
+        if let Some(value) = values.get_mut(token as usize) {
+            *value = f32::NEG_INFINITY;
+        }
+    }
+    Tensor::new(values, logits.device())
+}
+
+/// Lower the logit of every token by `penalty` for each time it occurs in `generated`, the
+/// tokens generated so far, like OpenAI's `frequency_penalty`. A negative penalty makes
+/// repeats more likely; `0.0` leaves the logits unchanged.
+///
+/// `logits` is the 1-D distribution for the next token.
+pub fn apply_frequency_penalty(logits: &Tensor, generated: &[u32], penalty: f32) -> Result<Tensor> {
+    if penalty == 0.0 || generated.is_empty() {
+        return Ok(logits.clone());
+    }
+    let mut counts: HashMap<u32, usize> = HashMap::new();
+    for &token in generated {
+        *counts.entry(token).or_default() += 1;
+    }
+    let mut values = logits.to_vec1::<f32>()?;
+    for (token, count) in counts {
+        if let Some(value) = values.get_mut(token as usize) {
+            *value -= penalty * count as f32;
+        }
+    }
+    Tensor::new(values, logits.device())
+}
+
+/// Tokens that follow an earlier occurrence of the last `ngram_size - 1` tokens.
+fn repeated_ngram_completions(tokens: &[u32], ngram_size: usize) -> HashSet<u32> {
+    if ngram_size == 0 || tokens.len() < ngram_size {
+        return HashSet::new();
+    }
+    let prefix = &tokens[tokens.len() - (ngram_size - 1)..];
+    tokens
+        .windows(ngram_size)
+        .filter(|ngram| &ngram[..ngram_size - 1] == prefix)
+        .map(|ngram| ngram[ngram_size - 1])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::Device;
+
+    #[test]
+    fn test_ban_repeated_ngrams() {
+        // "1 2 3 ... 1 2" must not continue with 3; unigrams ban every token seen so far.
+        let tokens = [1, 2, 3, 4, 1, 2];
+        assert_eq!(repeated_ngram_completions(&tokens, 3), HashSet::from([3]));
+        assert_eq!(
+            repeated_ngram_completions(&tokens, 1),
+            HashSet::from([1, 2, 3, 4])
+        );
+        assert!(repeated_ngram_completions(&tokens, 0).is_empty());
+        assert!(repeated_ngram_completions(&tokens, 7).is_empty());
+
+        let logits = Tensor::new(&[0.5f32, 1.0, 2.0, 3.0, 4.0], &Device::Cpu).unwrap();
+        let banned = ban_repeated_ngrams(&logits, &tokens, 3)
+            .unwrap()
+            .to_vec1::<f32>()
+            .unwrap();
+        assert_eq!(banned, [0.5, 1.0, 2.0, f32::NEG_INFINITY, 4.0]);
+    }
+
+    #[test]
+    fn test_frequency_penalty() {
+        let logits = Tensor::new(&[1.0f32, 1.0, 1.0], &Device::Cpu).unwrap();
+        let penalized = |generated: &[u32], penalty| {
+            apply_frequency_penalty(&logits, generated, penalty)
+                .unwrap()
+                .to_vec1::<f32>()
+                .unwrap()
+        };
+        assert_eq!(penalized(&[0, 2, 2], 0.5), [0.5, 1.0, 0.0]);
+        assert_eq!(penalized(&[1], -1.0), [1.0, 2.0, 1.0]);
+        assert_eq!(penalized(&[0, 1], 0.0), [1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_presets_parse_by_name() {
+        for preset in [
+            SamplingPreset::Precise,
+            SamplingPreset::Balanced,
+            SamplingPreset::Creative,
+        ] {
+            assert_eq!(preset.as_str().parse::<SamplingPreset>(), Ok(preset));
+        }
+        assert!("wild".parse::<SamplingPreset>().is_err());
+        assert!(
+            SamplingPreset::Precise.settings().temperature
+                < SamplingPreset::Creative.settings().temperature
+        );
+    }
+}
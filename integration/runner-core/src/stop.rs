@@ -0,0 +1,126 @@
+/// Outcome of feeding a decoded fragment to [`StopSequences`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopCheck {
+    /// No stop sequence yet; the text is safe to emit (it may be empty while a possible
+    /// stop sequence is being held back).
+    Continue(String),
+    /// A stop sequence was found. Emit the text that preceded it and stop generating.
+    Stop(String),
+}
+
+/// Detects stop strings in streamed text, including ones split across tokens.
+///
+/// Text that could be the beginning of a stop string is held back until the next fragment
+/// either completes the match or rules it out, so the stop text itself is never emitted.
+#[derive(Debug, Clone, Default)]
+pub struct StopSequences {
+    stops: Vec<String>,
+    held: String,
+}
+
+impl StopSequences {
+    pub fn new(stops: &[String]) -> Self {
+        Self {
+            stops: stops.iter().filter(|s| !s.is_empty()).cloned().collect(),
+            held: String::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stops.is_empty()
+    }
+
+    /// Feed the next decoded fragment.
+    pub fn push(&mut self, text: &str) -> StopCheck {
+        if self.stops.is_empty() {
+            return StopCheck::Continue(text.to_string());
+        }
+        self.held.push_str(text);
+
+        let first_match = self
+            .stops
+            .iter()
+            .filter_map(|stop| self.held.find(stop.as_str()))
+            .min();
+        if let Some(index) = first_match {
+            let before = self.held[..index].to_string();
+            self.held.clear();
+            return StopCheck::Stop(before);
+        }
+
+        let keep = self.partial_match_len();
+        let emit = self.held[..self.held.len() - keep].to_string();
+        self.held.drain(..emit.len());
+        StopCheck::Continue(emit)
+    }
+
+    /// Release held-back text once generation ends without hitting a stop sequence.
+    pub fn flush(&mut self) -> String {
+        std::mem::take(&mut self.held)
+    }
+
+    /// Length of the longest suffix of the held text that is a prefix of a stop string.
+    fn partial_match_len(&self) -> usize {
+        self.held
+            .char_indices()
+            .map(|(index, _)| &self.held[index..])
+            .find(|suffix| self.stops.iter().any(|stop| stop.starts_with(suffix)))
+            .map_or(0, str::len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stops(values: &[&str]) -> StopSequences {
+        let values: Vec<String> = values.iter().map(|s| s.to_string()).collect();
+        StopSequences::new(&values)
+    }
+
+    #[test]
+    fn test_stop_within_fragment() {
+        let mut matcher = stops(&["\n\n"]);
+        assert_eq!(
+            matcher.push("Hello\n\nWorld"),
+            StopCheck::Stop("Hello".to_string())
+        );
+    }
+
+    #[test]
+    fn test_stop_across_fragments() {
+        let mut matcher = stops(&["</answer>"]);
+        assert_eq!(
+            matcher.push("42 </"),
+            StopCheck::Continue("42 ".to_string())
+        );
+        assert_eq!(matcher.push("ans"), StopCheck::Continue(String::new()));
+        assert_eq!(matcher.push("wer> ignored"), StopCheck::Stop(String::new()));
+    }
+
+    #[test]
+    fn test_partial_match_released() {
+        let mut matcher = stops(&["STOP"]);
+        assert_eq!(matcher.push("ST"), StopCheck::Continue(String::new()));
+        assert_eq!(matcher.push("AR"), StopCheck::Continue("STAR".to_string()));
+        assert_eq!(matcher.push("S"), StopCheck::Continue(String::new()));
+        assert_eq!(matcher.flush(), "S");
+    }
+
+    #[test]
+    fn test_earliest_stop_wins() {
+        let mut matcher = stops(&["b", "a"]);
+        assert_eq!(matcher.push("xaby"), StopCheck::Stop("x".to_string()));
+    }
+
+    #[test]
+    fn test_no_stops_passes_through() {
+        let mut matcher = stops(&[""]);
+        assert!(matcher.is_empty());
+        assert_eq!(
+            matcher.push("anything"),
+            StopCheck::Continue("anything".to_string())
+        );
+        assert_eq!(matcher.flush(), "");
+    }
+}
@@ -0,0 +1,267 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::{RunnerError, TokenEvent};
+
+/// Environment variable setting how many events a stream buffers before its generation waits.
+pub const STREAM_BUFFER_ENV: &str = "STREAM_BUFFER_TOKENS";
+
+/// Environment variable setting how long a stream may sit full before it's dropped, in seconds.
+pub const STREAM_STALL_TIMEOUT_ENV: &str = "STREAM_STALL_TIMEOUT_SECS";
+
+/// Longest pause between attempts to send into a full buffer.
+const MAX_RETRY_PAUSE: Duration = Duration::from_millis(10);
+
+/// Stream of [`TokenEvent`]s produced by a generation.
+///
+/// Consume it with `recv().await` from async code or `blocking_recv()` from a plain thread.
+/// It holds at most [`StreamConfig::buffer`] events: a generation whose events aren't read
+/// waits for room rather than piling them up.
+pub type TokenReceiver = mpsc::Receiver<Result<TokenEvent, RunnerError>>;
+
+/// How much a token stream buffers, and how long it waits for a reader that stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamConfig {
+    /// Events a stream holds before the generation waits for the reader to catch up.
+    pub buffer: usize,
+    /// How long a generation waits on a full buffer before it gives up on the reader.
+    pub stall_timeout: Duration,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            buffer: 64,
+            stall_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl StreamConfig {
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let parse = |name: &str| {
+            let value = var(name)?;
+            match value.trim().parse::<u64>() {
+                Ok(number) if number > 0 => Some(number),
+                _ => {
+                    eprintln!("Warning: ignoring {name}={value}: expected a positive number");
+                    None
+                }
+            }
+        };
+        let defaults = Self::default();
+        Self {
+            buffer: parse(STREAM_BUFFER_ENV).map_or(defaults.buffer, |buffer| buffer as usize),
+            stall_timeout: parse(STREAM_STALL_TIMEOUT_ENV)
+                .map_or(defaults.stall_timeout, Duration::from_secs),
+        }
+    }
+}
+
+static CONFIG: OnceLock<StreamConfig> = OnceLock::new();
+
+/// The stream settings of this process, read from [`STREAM_BUFFER_ENV`] and
+/// [`STREAM_STALL_TIMEOUT_ENV`] on first use.
+pub fn stream_config() -> StreamConfig {
+    *CONFIG.get_or_init(|| StreamConfig::from_vars(|name| std::env::var(name).ok()))
+}
+
+static STREAMS: AtomicU64 = AtomicU64::new(0);
+static MAX_QUEUED: AtomicU64 = AtomicU64::new(0);
+static FULL_WAITS: AtomicU64 = AtomicU64::new(0);
+static STALLED: AtomicU64 = AtomicU64::new(0);
+static ABANDONED: AtomicU64 = AtomicU64::new(0);
+
+/// How the token streams of this process have kept up with their readers since it started.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamStats {
+    /// Events each stream buffers, see [`STREAM_BUFFER_ENV`]
+    pub buffer: usize,
+    /// Token streams opened
+    pub streams: u64,
+    /// Most events any one stream has held at once
+    pub max_queued: u64,
+    /// Sends that found a buffer full and had to wait for the reader
+    pub full_waits: u64,
+    /// Streams dropped because their reader stopped reading for longer than the stall timeout
+    pub stalled: u64,
+    /// Streams whose reader went away before the generation ended, e.g. a disconnected client
+    pub abandoned: u64,
+}
+
+/// A snapshot of the stream counters.
+pub fn stream_stats() -> StreamStats {
+    StreamStats {
+        buffer: stream_config().buffer,
+        streams: STREAMS.load(Ordering::Relaxed),
+        max_queued: MAX_QUEUED.load(Ordering::Relaxed),
+        full_waits: FULL_WAITS.load(Ordering::Relaxed),
+        stalled: STALLED.load(Ordering::Relaxed),
+        abandoned: ABANDONED.load(Ordering::Relaxed),
+    }
+}
+
+/// Count a stream dropped for a stalled reader further downstream, e.g. a client that
+/// stopped reading its server-sent events.
+pub fn record_stalled_stream() {
+    STALLED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The generation's end of a [`TokenReceiver`]. Sending waits while the buffer is full, so a
+/// slow reader slows the generation down instead of growing memory, and reports when the
+/// generation should stop because nobody is reading.
+#[derive(Debug, Clone)]
+pub struct TokenSender {
+    tx: mpsc::Sender<Result<TokenEvent, RunnerError>>,
+    stall_timeout: Duration,
+    /// Set once the reader is gone, so the stream is counted as dropped only once.
+    dropped: Arc<AtomicBool>,
+}
+
+/// A bounded token stream with the settings of [`stream_config`].
+pub fn token_channel() -> (TokenSender, TokenReceiver) {
+    token_channel_with(stream_config())
+}
+
+/// A bounded token stream with the given settings.
+pub fn token_channel_with(config: StreamConfig) -> (TokenSender, TokenReceiver) {
+    let (tx, rx) = mpsc::channel(config.buffer.max(1));
+    STREAMS.fetch_add(1, Ordering::Relaxed);
+    let sender = TokenSender {
+        tx,
+        stall_timeout: config.stall_timeout,
+        dropped: Arc::new(AtomicBool::new(false)),
+    };
+    (sender, rx)
+}
+
+impl TokenSender {
+    /// Send `event`, waiting up to the stall timeout while the buffer is full. Returns
+    /// `false` when the reader has gone or stalled; the generation should stop then.
+    ///
+    /// Blocks the calling thread, so call it from the generation thread, not async code.
+    pub fn send(&self, event: Result<TokenEvent, RunnerError>) -> bool {
+        if self.dropped.load(Ordering::Relaxed) {
+            return false;
+        }
+        let mut event = match self.tx.try_send(event) {
+            Ok(()) => return self.sent(),
+            Err(TrySendError::Closed(_)) => return self.drop_stream(&ABANDONED),
+            Err(TrySendError::Full(event)) => event,
+        };
+
+        FULL_WAITS.fetch_add(1, Ordering::Relaxed);
+        let deadline = Instant::now() + self.stall_timeout;
+        let mut pause = Duration::from_micros(500);
+        loop {
+            std::thread::sleep(pause);
+            pause = (pause * 2).min(MAX_RETRY_PAUSE);
+            event = match self.tx.try_send(event) {
+                Ok(()) => return self.sent(),
+                Err(TrySendError::Closed(_)) => return self.drop_stream(&ABANDONED),
+                Err(TrySendError::Full(event)) => event,
+            };
+            if Instant::now() >= deadline {
+                eprintln!(
+                    "Warning: token stream reader stalled for {:?}, stopping the generation",
+                    self.stall_timeout
+                );
+                return self.drop_stream(&STALLED);
+            }
+        }
+    }
+
+    /// Whether the reader has gone, e.g. to skip work nobody will see.
+    pub fn is_closed(&self) -> bool {
+        self.dropped.load(Ordering::Relaxed) || self.tx.is_closed()
+    }
+
+    fn sent(&self) -> bool {
+        let queued = (self.tx.max_capacity() - self.tx.capacity()) as u64;
+        MAX_QUEUED.fetch_max(queued, Ordering::Relaxed);
+        true
+    }
+
+    fn drop_stream(&self, counter: &AtomicU64) -> bool {
+        if !self.dropped.swap(true, Ordering::Relaxed) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FinishReason;
+
+    fn token(text: &str) -> Result<TokenEvent, RunnerError> {
+        Ok(TokenEvent::generated(1, text, None))
+    }
+
+    #[test]
+    fn test_config_from_vars() {
+        let config = StreamConfig::from_vars(|name| match name {
+            STREAM_BUFFER_ENV => Some("8".to_string()),
+            STREAM_STALL_TIMEOUT_ENV => Some("0".to_string()),
+            _ => None,
+        });
+        assert_eq!(
+            config,
+            StreamConfig {
+                buffer: 8,
+                stall_timeout: StreamConfig::default().stall_timeout,
+            }
+        );
+        assert_eq!(StreamConfig::from_vars(|_| None), StreamConfig::default());
+    }
+
+    #[test]
+    fn test_a_slow_reader_holds_the_sender_back() {
+        let (tx, mut rx) = token_channel_with(StreamConfig {
+            buffer: 2,
+            stall_timeout: Duration::from_secs(5),
+        });
+        let writer = std::thread::spawn(move || {
+            for text in ["a", "b", "c", "d"] {
+                assert!(tx.send(token(text)));
+            }
+            tx.send(Ok(TokenEvent::finished(FinishReason::Stop, "")))
+        });
+
+        // The writer fills the buffer, then waits for each event read
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(rx.len(), 2);
+        let mut texts = String::new();
+        while let Some(event) = rx.blocking_recv() {
+            texts.push_str(&event.unwrap().text);
+        }
+        assert!(writer.join().unwrap());
+        assert_eq!(texts, "abcd");
+        assert!(stream_stats().full_waits > 0);
+        assert!(stream_stats().max_queued >= 2);
+    }
+
+    #[test]
+    fn test_a_stalled_or_missing_reader_stops_the_sender() {
+        let config = StreamConfig {
+            buffer: 1,
+            stall_timeout: Duration::from_millis(20),
+        };
+        let (tx, _rx) = token_channel_with(config);
+        assert!(tx.send(token("a")));
+        let stalled = stream_stats().stalled;
+        assert!(!tx.send(token("b")));
+        assert!(stream_stats().stalled > stalled);
+        assert!(tx.is_closed());
+
+        let (tx, rx) = token_channel_with(config);
+        drop(rx);
+        assert!(!tx.send(token("a")));
+        assert!(!tx.send(token("b")));
+        assert!(tx.is_closed());
+    }
+}
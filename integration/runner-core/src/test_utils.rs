@@ -0,0 +1,64 @@
+//! Fixtures for the runners' tests: a model built from seeded weights and a word-level
+//! tokenizer, so tests run a real forward pass without downloading a checkpoint.
+//! Behind the `test-utils` feature, which the runners enable as a dev-dependency.
+
+use candle_core::{DType, Device, Shape, Tensor};
+use candle_nn::var_builder::SimpleBackend;
+use candle_nn::{Init, VarBuilder};
+
+/// Weights drawn from a fixed pseudo-random sequence per tensor name, so the tests
+/// build the same model every time without downloading a checkpoint.
+///
+/// Each tensor's values come from an xorshift64 generator seeded with the FNV-1a hash of
+/// its name, mapped to `[-0.5, 0.5)`. Tests that assert generated token ids depend on
+/// these exact values, so changing them means regenerating those ids.
+pub struct SeededWeights;
+
+impl SimpleBackend for SeededWeights {
+    fn get(
+        &self,
+        shape: Shape,
+        name: &str,
+        _init: Init,
+        dtype: DType,
+        device: &Device,
+    ) -> candle_core::Result<Tensor> {
+        let mut state = name.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3)
+        });
+        let values: Vec<f32> = (0..shape.elem_count())
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+            })
+            .collect();
+        Tensor::from_vec(values, shape, device)?.to_dtype(dtype)
+    }
+
+    fn contains_tensor(&self, _name: &str) -> bool {
+        true
+    }
+}
+
+/// A `VarBuilder` over [`SeededWeights`] in f32.
+pub fn seeded_var_builder(device: &Device) -> VarBuilder<'static> {
+    VarBuilder::from_backend(Box::new(SeededWeights), DType::F32, device.clone())
+}
+
+/// `tokenizer.json` for a word-level tokenizer that splits on whitespace and gives each
+/// word of `vocab` its index as id, unknown words `<unk>`. Returned as JSON so each runner
+/// parses it with its own `tokenizers` version.
+pub fn word_level_tokenizer_json(vocab: &[&str]) -> String {
+    let vocab: serde_json::Map<String, serde_json::Value> = vocab
+        .iter()
+        .enumerate()
+        .map(|(id, word)| (word.to_string(), id.into()))
+        .collect();
+    serde_json::json!({
+        "model": { "type": "WordLevel", "vocab": vocab, "unk_token": "<unk>" },
+        "pre_tokenizer": { "type": "Whitespace" },
+    })
+    .to_string()
+}
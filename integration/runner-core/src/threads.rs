@@ -0,0 +1,166 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// Environment variable read when the runner config does not set a CPU thread count.
+pub const THREADS_ENV: &str = "RUNNER_THREADS";
+
+/// Read by rayon and by candle's CPU matmul when sizing their thread pools.
+const RAYON_THREADS_ENV: &str = "RAYON_NUM_THREADS";
+
+/// Where the thread count in effect came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThreadSource {
+    Config,
+    /// [`THREADS_ENV`], or `RAYON_NUM_THREADS` if only that one is set.
+    Env,
+    /// One thread per physical core the process may run on.
+    Default,
+}
+
+/// Size of the CPU thread pool used for tensor operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CpuThreads {
+    pub threads: usize,
+    pub source: ThreadSource,
+}
+
+impl fmt::Display for CpuThreads {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = match self.source {
+            ThreadSource::Config => "runner config",
+            ThreadSource::Env => "environment",
+            ThreadSource::Default => "physical cores",
+        };
+        write!(f, "{} ({source})", self.threads)
+    }
+}
+
+static CONFIGURED: OnceLock<CpuThreads> = OnceLock::new();
+
+/// Size the thread pools candle uses on the CPU: rayon's global pool and the matmul kernels.
+///
+/// `requested` wins over [`THREADS_ENV`] and `RAYON_NUM_THREADS`. Without either, one thread
+/// per physical core is used rather than one per logical CPU, so several services on a
+/// hyperthreaded host do not oversubscribe it; the count is also capped by the CPUs the
+/// process's affinity mask and cgroup quota allow.
+///
+/// The pools are process-wide, so only the first call takes effect. Later calls return the
+/// setting already in place and warn if they asked for a different one.
+pub fn configure_threads(requested: Option<usize>) -> Result<CpuThreads> {
+    let wanted = resolve(requested, |name| std::env::var(name).ok())?;
+    let mut applied = false;
+    let effective = *CONFIGURED.get_or_init(|| {
+        applied = true;
+        apply(wanted)
+    });
+    if !applied && requested.is_some() && effective.threads != wanted.threads {
+        eprintln!(
+            "Warning: CPU threads already set to {effective}, ignoring request for {}",
+            wanted.threads
+        );
+    }
+    Ok(effective)
+}
+
+/// The setting applied by [`configure_threads`], if it has been called.
+pub fn current_threads() -> Option<CpuThreads> {
+    CONFIGURED.get().copied()
+}
+
+fn resolve(requested: Option<usize>, env: impl Fn(&str) -> Option<String>) -> Result<CpuThreads> {
+    if let Some(threads) = requested {
+        if threads == 0 {
+            bail!("the CPU thread count must be at least 1");
+        }
+        return Ok(CpuThreads {
+            threads,
+            source: ThreadSource::Config,
+        });
+    }
+    if let Some(value) = env(THREADS_ENV) {
+        return match value.trim().parse::<usize>() {
+            Ok(threads) if threads > 0 => Ok(CpuThreads {
+                threads,
+                source: ThreadSource::Env,
+            }),
+            _ => bail!("{THREADS_ENV} must be a positive integer, got {value:?}"),
+        };
+    }
+    // Same rule as rayon and candle: unparsable or zero means "use the default".
+    if let Some(threads) = env(RAYON_THREADS_ENV).and_then(|value| value.trim().parse().ok()) {
+        if threads > 0 {
+            return Ok(CpuThreads {
+                threads,
+                source: ThreadSource::Env,
+            });
+        }
+    }
+    let available = std::thread::available_parallelism().map_or(1, |n| n.get());
+    Ok(CpuThreads {
+        threads: num_cpus::get_physical().clamp(1, available),
+        source: ThreadSource::Default,
+    })
+}
+
+fn apply(wanted: CpuThreads) -> CpuThreads {
+    // candle's matmul reads this variable on every call instead of asking rayon.
+    std::env::set_var(RAYON_THREADS_ENV, wanted.threads.to_string());
+    match rayon::ThreadPoolBuilder::new()
+        .num_threads(wanted.threads)
+        .build_global()
+    {
+        Ok(()) => wanted,
+        Err(_) => {
+            // Something already started rayon's global pool; it keeps its size.
+            let threads = rayon::current_num_threads();
+            if threads != wanted.threads {
+                eprintln!(
+                    "Warning: rayon thread pool already running with {threads} threads, \
+                     cannot resize it to {}",
+                    wanted.threads
+                );
+            }
+            CpuThreads { threads, ..wanted }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_prefers_config_then_env() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        let both = env(&[(THREADS_ENV, "6"), (RAYON_THREADS_ENV, "2")]);
+
+        let config = resolve(Some(3), both).unwrap();
+        assert_eq!((config.threads, config.source), (3, ThreadSource::Config));
+        let runner_env = resolve(None, both).unwrap();
+        assert_eq!(
+            (runner_env.threads, runner_env.source),
+            (6, ThreadSource::Env)
+        );
+        let rayon_env = resolve(None, env(&[(RAYON_THREADS_ENV, "2")])).unwrap();
+        assert_eq!(
+            (rayon_env.threads, rayon_env.source),
+            (2, ThreadSource::Env)
+        );
+
+        let default = resolve(None, env(&[(RAYON_THREADS_ENV, "0")])).unwrap();
+        assert_eq!(default.source, ThreadSource::Default);
+        assert!(default.threads >= 1);
+
+        assert!(resolve(Some(0), env(&[])).is_err());
+        assert!(resolve(None, env(&[(THREADS_ENV, "many")])).is_err());
+    }
+}